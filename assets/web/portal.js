@@ -0,0 +1,1279 @@
+document.addEventListener('DOMContentLoaded', function () {
+    const uploadArea = document.getElementById('uploadArea');
+    const fileInput = document.getElementById('fileInput');
+    const selectFileBtn = document.getElementById('selectFileBtn');
+    const uploadPassword = document.getElementById('uploadPassword');
+    const fileList = document.getElementById('fileList');
+    const fileSearch = document.getElementById('fileSearch');
+    const archiveBtn = document.getElementById('archiveBtn');
+    const statusEl = document.getElementById('status');
+    const speedTestBtn = document.getElementById('speedTestBtn');
+    const speedTestResult = document.getElementById('speedTestResult');
+    const SPEED_TEST_BYTES = 8 * 1024 * 1024;
+    const textShare = document.getElementById('textShare');
+    const textForm = document.getElementById('textForm');
+    const textInput = document.getElementById('textInput');
+    const textFormat = document.getElementById('textFormat');
+    const shareTextBtn = document.getElementById('shareTextBtn');
+    const snippetList = document.getElementById('snippetList');
+    const qrOverlay = document.getElementById('qrOverlay');
+    const qrImage = document.getElementById('qrImage');
+    const qrCaption = document.getElementById('qrCaption');
+    const userBar = document.getElementById('userBar');
+    const eventLog = document.getElementById('eventLog');
+    const eventList = document.getElementById('eventList');
+    const languageSelect = document.getElementById('language');
+    const hostControls = document.getElementById('hostControls');
+    const readOnlyToggle = document.getElementById('readOnlyToggle');
+    let lastFileCount = 0;
+    let lastSnippetId = null;
+    let pollingInterval;
+    // Socket pushing changes of the file list, while connected
+    let liveSocket = null;
+    let chunkSize = 5 * 1024 * 1024; // Default 5MB, will be updated from config
+    let configLoaded = false;
+    // Whether files sent by mistake can be deleted from here
+    let canDelete = true;
+    // IDs of the files ticked for downloading together as a ZIP
+    const selectedFiles = new Set();
+
+    // Catalog of the page's language, embedded by the server; the English
+    // text in the script is its own key
+    let i18n = { lang: 'en', languages: [], messages: {} };
+    try {
+        i18n = JSON.parse(document.getElementById('i18n').textContent);
+    } catch (error) {
+        console.warn('No translations, showing English:', error);
+    }
+
+    // Translate `text`, filling in its {placeholders} from `values`
+    function t(text, values = {}) {
+        const message = i18n.messages[text] || text;
+        return message.replace(/\{(\w+)\}/g, (match, name) => name in values ? values[name] : match);
+    }
+
+    // Languages the portal is available in; the server reads the pick from
+    // the cookie and serves the page in it
+    if (i18n.languages.length > 1) {
+        i18n.languages.forEach(([code, name]) => {
+            languageSelect.add(new Option(name, code, false, code === i18n.lang));
+        });
+        languageSelect.addEventListener('change', function () {
+            document.cookie = `justrans_lang=${languageSelect.value}; path=/; max-age=31536000; SameSite=Lax`;
+            window.location.reload();
+        });
+        languageSelect.classList.remove('hidden');
+    }
+
+    // Pages under /r/<room> talk to that room's API
+    const roomMatch = window.location.pathname.match(/^\/r\/([^/]+)/);
+    const roomName = roomMatch ? decodeURIComponent(roomMatch[1]) : null;
+    const apiBase = roomName ? `/api/v1/rooms/${encodeURIComponent(roomName)}` : '/api/v1';
+    // The main portal can require a PIN too, when a network overlay sets one
+    const pinKey = roomName ? `room-pin-${roomName}` : 'portal-pin';
+    let accessPin = sessionStorage.getItem(pinKey) || '';
+    // A room link carries its PIN after `#pin=`, which isn't sent to the server
+    const linkPin = roomName && new URLSearchParams(window.location.hash.slice(1)).get('pin');
+    if (linkPin) {
+        accessPin = linkPin;
+        sessionStorage.setItem(pinKey, linkPin);
+        history.replaceState(null, '', window.location.pathname + window.location.search);
+    }
+    storeRoomPin();
+
+    // Keep the page and its assets for opening from the home screen.
+    // Browsers only allow this over HTTPS or on localhost.
+    if ('serviceWorker' in navigator && window.isSecureContext) {
+        navigator.serviceWorker.register('/sw.js').catch((error) => {
+            console.warn('Offline shell not available:', error);
+        });
+    }
+
+    if (roomName) {
+        document.querySelector('h1').textContent = t('JusTrans Room: {room}', { room: roomName });
+        // Text sharing is only available on the main portal
+        textShare.classList.add('hidden');
+    }
+
+    // Build an API URL, adding the PIN of the main portal when one is set.
+    // A room's PIN goes in its cookie instead, kept out of URLs and logs.
+    function apiUrl(path) {
+        const url = apiBase + path;
+        return accessPin && !roomName ? `${url}?pin=${encodeURIComponent(accessPin)}` : url;
+    }
+
+    // Keep the room's PIN in the cookie the server reads it from, so
+    // requests, downloads and the socket all carry it
+    function storeRoomPin() {
+        if (roomName && accessPin) {
+            document.cookie = `justrans_room_${roomName}=${encodeURIComponent(accessPin)}; path=/; SameSite=Strict`;
+        }
+    }
+
+    // URL of the file list, narrowed to the names matching the search
+    function filesUrl() {
+        const url = apiUrl('/files');
+        const query = fileSearch.value.trim();
+        if (!query) {
+            return url;
+        }
+        return `${url}${url.includes('?') ? '&' : '?'}q=${encodeURIComponent(query)}`;
+    }
+
+    // Ask for the PIN and remember it for this browser session
+    function requestPin() {
+        const pin = prompt(roomName ? t('Room "{room}" requires a PIN', { room: roomName }) : t('This share requires a PIN'));
+        if (pin === null) {
+            return false;
+        }
+        accessPin = pin;
+        sessionStorage.setItem(pinKey, pin);
+        storeRoomPin();
+        return true;
+    }
+
+    // Load configuration and files on page load
+    loadConfig().then(() => {
+        configLoaded = true;
+        loadFiles();
+        loadSnippets();
+        // Set up automatic polling to check for file changes every 2 seconds
+        startPolling();
+        followFileList();
+    });
+
+    // Handle file selection button
+    selectFileBtn.addEventListener('click', function () {
+        if (!configLoaded) {
+            showStatus(t('Please wait, loading configuration...'), 'error');
+            return;
+        }
+        fileInput.click();
+    });
+
+    // Handle file selection
+    fileInput.addEventListener('change', function () {
+        if (!configLoaded) {
+            showStatus(t('Please wait, loading configuration...'), 'error');
+            return;
+        }
+        if (fileInput.files.length > 0) {
+            uploadFiles(fileInput.files);
+        }
+    });
+
+    speedTestBtn.addEventListener('click', runSpeedTest);
+    shareTextBtn.addEventListener('click', shareText);
+    qrOverlay.addEventListener('click', function () {
+        qrOverlay.classList.add('hidden');
+    });
+
+    // Handle drag and drop
+    uploadArea.addEventListener('dragover', function (e) {
+        e.preventDefault();
+        uploadArea.classList.add('dragover');
+    });
+
+    uploadArea.addEventListener('dragleave', function () {
+        uploadArea.classList.remove('dragover');
+    });
+
+    uploadArea.addEventListener('drop', function (e) {
+        e.preventDefault();
+        uploadArea.classList.remove('dragover');
+
+        if (!configLoaded) {
+            showStatus(t('Please wait, loading configuration...'), 'error');
+            return;
+        }
+
+        if (e.dataTransfer.files.length > 0) {
+            uploadFiles(e.dataTransfer.files);
+        }
+    });
+
+    // Function to load configuration from server
+    function loadConfig() {
+        return fetch('/api/v1/config')
+            .then(response => response.json())
+            .then(data => {
+                // Update chunk size from server configuration
+                chunkSize = data.upload_chunk_size_mb * 1024 * 1024;
+                console.log(`Loaded chunk size from config: ${data.upload_chunk_size_mb}MB (${chunkSize} bytes)`);
+
+                // Send the browser to the sign-in page when the portal requires it
+                if (data.login_required && !data.user) {
+                    const returnTo = window.location.pathname + window.location.search;
+                    window.location.href = `/auth/login?return_to=${encodeURIComponent(returnTo)}`;
+                    return new Promise(() => {});
+                }
+                if (data.user) {
+                    userBar.textContent = `${t('Signed in as {name}', { name: data.user.name })} · `;
+                    const logout = document.createElement('a');
+                    logout.href = '/auth/logout';
+                    logout.textContent = t('Sign out');
+                    userBar.appendChild(logout);
+                    userBar.classList.remove('hidden');
+                }
+
+                // Hosts watch the server's events and manage it on the main portal
+                if (data.user && data.user.role === 'host' && !roomName) {
+                    followEvents();
+                    showHostControls(data.read_only);
+                }
+
+                // Read-only profiles and viewers only get to download files
+                if (data.read_only || (data.user && data.user.role === 'viewer')) {
+                    uploadArea.classList.add('hidden');
+                    textForm.classList.add('hidden');
+                    canDelete = false;
+                }
+            })
+            .catch(error => {
+                console.error('Error loading configuration, using default chunk size:', error);
+                // Keep default chunk size if config loading fails
+            });
+    }
+
+    // Function to start polling for file changes
+    function startPolling() {
+        // Clear any existing polling
+        if (pollingInterval) {
+            clearInterval(pollingInterval);
+        }
+
+        // Poll every 2 seconds
+        pollingInterval = setInterval(checkForChanges, 2000);
+
+        // Stop polling if the page is hidden to save resources
+        document.addEventListener('visibilitychange', function () {
+            if (document.visibilityState === 'hidden') {
+                clearInterval(pollingInterval);
+            } else {
+                pollingInterval = setInterval(checkForChanges, 2000);
+            }
+        });
+    }
+
+    function checkForChanges() {
+        // Changes to the file list are pushed while the socket is up
+        if (!liveSocket) {
+            checkForFileChanges();
+        }
+        if (!roomName) {
+            loadSnippets();
+        }
+    }
+
+    // Function to check for file changes
+    function checkForFileChanges() {
+        fetch(filesUrl())
+            .then(response => {
+                if (!response.ok) {
+                    throw new Error(`Server returned ${response.status}`);
+                }
+                return response.json();
+            })
+            .then(data => {
+                const newFileCount = data.files ? data.files.length : 0;
+
+                // Update the file list if the number of files has changed
+                if (newFileCount !== lastFileCount) {
+                    updateFileList(data);
+                    lastFileCount = newFileCount;
+
+                    // Show a notification if files were added (and not just on first load)
+                    if (lastFileCount > 0 && newFileCount > lastFileCount) {
+                        showStatus(t('New files available!'), 'success');
+                    }
+                }
+            })
+            .catch(error => {
+                console.error('Error checking for file changes:', error);
+            });
+    }
+
+    // Wait before connecting the socket again, e.g. until the server is back
+    const LIVE_RETRY_MS = 5000;
+
+    // Follow the file list over a WebSocket; it is polled while the socket
+    // is down, e.g. behind a proxy that doesn't pass WebSockets on
+    function followFileList() {
+        if (!('WebSocket' in window)) {
+            return;
+        }
+        const scheme = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
+        const path = roomName ? `/r/${encodeURIComponent(roomName)}/ws` : '/ws';
+        const query = accessPin && !roomName ? `?pin=${encodeURIComponent(accessPin)}` : '';
+        const socket = new WebSocket(`${scheme}//${window.location.host}${path}${query}`);
+        socket.addEventListener('open', function () {
+            liveSocket = socket;
+            // Catch up on changes made while not connected
+            loadFiles();
+        });
+        socket.addEventListener('message', function (message) {
+            const update = JSON.parse(message.data);
+            if (update.event === 'file.added' || update.event === 'file.removed') {
+                loadFiles();
+            }
+            if (update.event === 'file.added') {
+                showStatus(t('New files available!'), 'success');
+            } else if (update.event === 'server.stopping') {
+                showStatus(t('The server is stopping'), 'info');
+            }
+        });
+        socket.addEventListener('close', function () {
+            if (liveSocket === socket) {
+                liveSocket = null;
+            }
+            setTimeout(followFileList, LIVE_RETRY_MS);
+        });
+    }
+
+    // Uploads expected to take longer than this get a heads-up first
+    const LONG_UPLOAD_SECS = 60;
+
+    // Problems the preflight reports, as shown to the user
+    const preflightProblems = {
+        read_only: t('this share is read-only'),
+        kind_not_accepted: t("this share doesn't accept this kind of file"),
+        too_large: t('the file is larger than this share accepts'),
+        over_quota: t('this device has reached its storage limit'),
+        no_space: t('the host is running out of disk space')
+    };
+
+    // Function to upload files
+    async function uploadFiles(files) {
+        const checks = await preflight(Array.from(files));
+        let sent = 0;
+        let rejected = false;
+        for (let i = 0; i < files.length; i++) {
+            const file = files[i];
+            const problem = checks && checks.files[i] && checks.files[i].problem;
+            if (problem) {
+                showStatus(t('{name} was not sent: {problem}', { name: file.name, problem: preflightProblems[problem] || problem }), 'error');
+                rejected = true;
+                continue;
+            }
+            uploadFile(file);
+            sent++;
+        }
+        // A rejection is worth more than the estimate
+        if (!rejected && checks && checks.eta_secs > LONG_UPLOAD_SECS) {
+            const values = {
+                count: sent,
+                size: formatFileSize(checks.total_size),
+                duration: formatDuration(checks.eta_secs)
+            };
+            const message = sent === 1 ? t('This {size} file will take ~{duration} to upload', values) :
+                t('These {count} files ({size}) will take ~{duration} to upload', values);
+            showStatus(message, 'info');
+        }
+    }
+
+    // Ask the host whether it takes the files and how long they will take.
+    // Rooms and hosts without a preflight just upload.
+    async function preflight(files) {
+        if (roomName) {
+            return null;
+        }
+        try {
+            const response = await fetch(apiUrl('/preflight'), {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify({ files: files.map(file => ({ name: file.name, size: file.size })) })
+            });
+            return response.ok ? await response.json() : null;
+        } catch (error) {
+            console.warn('Upload preflight failed:', error);
+            return null;
+        }
+    }
+
+    // Round a number of seconds to what a person would say, e.g. "11 minutes"
+    function formatDuration(secs) {
+        const minutes = Math.round(secs / 60);
+        if (minutes < 60) {
+            return minutes === 1 ? t('1 minute') : t('{count} minutes', { count: minutes });
+        }
+        const hours = Math.round(minutes / 6) / 10;
+        return hours === 1 ? t('1 hour') : t('{count} hours', { count: hours });
+    }
+
+    // CRC32 of a chunk as 8 hex digits, sent so the server can turn away a
+    // chunk damaged on the way rather than the whole file at the end
+    const crcTable = new Uint32Array(256).map((_, n) => {
+        let c = n;
+        for (let k = 0; k < 8; k++) {
+            c = c & 1 ? 0xedb88320 ^ (c >>> 1) : c >>> 1;
+        }
+        return c;
+    });
+    function crc32(bytes) {
+        let crc = 0xffffffff;
+        for (let i = 0; i < bytes.length; i++) {
+            crc = crcTable[(crc ^ bytes[i]) & 0xff] ^ (crc >>> 8);
+        }
+        return ((crc ^ 0xffffffff) >>> 0).toString(16).padStart(8, '0');
+    }
+
+    // Times a chunk the server found damaged is sent again
+    const CHUNK_RETRIES = 3;
+
+    // Function to upload a single file
+    function uploadFile(file) {
+        let fileId; // Issued by the server before the first chunk
+        const password = uploadPassword.value;
+        const totalChunks = Math.ceil(file.size / chunkSize);
+
+        // Create progress bar immediately
+        updateProgressBar(0, totalChunks);
+
+        // The bytes the server has, as it reports them; the chunks sent
+        // count when it can't
+        let progressSource = null;
+        let serverProgress = false;
+        function followProgress() {
+            progressSource = new EventSource(apiUrl(`/upload/${fileId}/progress`));
+            progressSource.addEventListener('message', function (message) {
+                const progress = JSON.parse(message.data);
+                if (progress.stage === 'completed' || progress.stage === 'failed') {
+                    progressSource.close();
+                    return;
+                }
+                serverProgress = true;
+                if (progress.size) {
+                    updateProgressBar(progress.received, progress.size);
+                } else if (progress.total_segments) {
+                    updateProgressBar(progress.segments_received, progress.total_segments);
+                }
+            });
+            progressSource.addEventListener('error', function () {
+                // Left to the chunks sent rather than reconnecting
+                progressSource.close();
+                serverProgress = false;
+            });
+        }
+
+        // Function to upload a single chunk
+        async function uploadChunk(index, attempt = 0) {
+            if (index >= totalChunks) {
+                // All chunks uploaded
+                return Promise.resolve();
+            }
+
+            const start = index * chunkSize;
+            const end = Math.min(file.size, start + chunkSize);
+            const chunk = file.slice(start, end);
+            const checksum = crc32(new Uint8Array(await chunk.arrayBuffer()));
+
+            // Log chunk details
+            console.log(`Uploading chunk ${index + 1}/${totalChunks} of file ${file.name} (${start}-${end}, size: ${chunk.size} bytes)`);
+
+            // Create FormData for this chunk
+            const formData = new FormData();
+
+            // Add metadata - before the chunk, so the server can report
+            // its progress while it arrives
+            formData.append('segment_index', index.toString());
+            formData.append('total_segments', totalChunks.toString());
+            formData.append('file_id', fileId);
+            formData.append('file_size', file.size.toString());
+            formData.append('segment_crc32', checksum);
+            if (password) {
+                formData.append('password', password);
+            }
+
+            // Add file chunk
+            formData.append('file', new Blob([chunk]), file.name);
+
+            // Debug log form data
+            console.log(`FormData for chunk ${index + 1}:`, {
+                fileName: file.name,
+                fileId: fileId,
+                segmentIndex: index,
+                totalSegments: totalChunks,
+                chunkSize: chunk.size
+            });
+
+            try {
+                // Upload this chunk
+                const response = await fetch(apiUrl('/upload'), {
+                    method: 'POST',
+                    body: formData
+                });
+
+                // Damaged on the way; only this chunk needs to go again
+                if (response.status === 422 && attempt < CHUNK_RETRIES) {
+                    console.warn(`Chunk ${index + 1} arrived damaged, sending it again`);
+                    return await uploadChunk(index, attempt + 1);
+                }
+
+                // The desktop user chose to discard a duplicate
+                if (response.status === 409) {
+                    showStatus(t('{name} already exists and was discarded', { name: file.name }), 'error');
+                    return;
+                }
+
+                // This device has used up its storage quota
+                if (response.status === 507) {
+                    showStatus(t('{name} was rejected: {problem}', { name: file.name, problem: preflightProblems.over_quota }), 'error');
+                    return;
+                }
+
+                // Too many chunks for the host's limit
+                if (response.status === 413) {
+                    showStatus(t('{name} was rejected: {problem}', { name: file.name, problem: preflightProblems.too_large }), 'error');
+                    return;
+                }
+
+                // The host doesn't accept this kind of file
+                if (response.status === 415) {
+                    showStatus(t('{name} was rejected: {problem}', { name: file.name, problem: preflightProblems.kind_not_accepted }), 'error');
+                    return;
+                }
+
+                if (!response.ok) {
+                    const errorText = await response.text();
+                    throw new Error(`Server returned ${response.status}: ${errorText}`);
+                }
+
+                const data = await response.json();
+                console.log(`Successfully uploaded chunk ${index + 1}/${totalChunks}`);
+
+                // Update progress bar, unless the server reports it
+                if (!serverProgress) {
+                    updateProgressBar(index + 1, totalChunks);
+                }
+
+                // If this was the last chunk, show completion
+                if (index === totalChunks - 1) {
+                    showUploadComplete(data);
+                    return;
+                }
+
+                // Upload next chunk
+                return await uploadChunk(index + 1);
+            } catch (error) {
+                console.error(`Error uploading chunk ${index + 1}:`, error);
+                showStatus(t('Upload failed: {error}', { error: error.message }), 'error');
+                throw error;
+            }
+        }
+
+        // Get an upload ID, then start uploading from the first chunk
+        fetch(apiUrl('/upload/init'), { method: 'POST' })
+            .then(response => {
+                if (!response.ok) {
+                    showStatus(t('Upload failed: server returned {status}', { status: response.status }), 'error');
+                    throw new Error(`Server returned ${response.status}`);
+                }
+                return response.json();
+            })
+            .then(data => {
+                fileId = data.file_id;
+                followProgress();
+                return uploadChunk(0);
+            })
+            .catch(err => {
+                console.error('Upload chain failed:', err);
+            })
+            .finally(() => {
+                if (progressSource) {
+                    progressSource.close();
+                }
+            });
+    }
+
+    // Function to load files
+    function loadFiles() {
+        fetch(filesUrl())
+            .then(response => {
+                if (response.status === 401) {
+                    if (requestPin()) {
+                        loadFiles();
+                    }
+                    return null;
+                }
+                if (response.status === 429) {
+                    throw new Error(t('Too many wrong PINs, try again in a minute'));
+                }
+                if (!response.ok) {
+                    throw new Error(`Server returned ${response.status}`);
+                }
+                return response.json();
+            })
+            .then(data => {
+                if (!data) {
+                    return;
+                }
+                updateFileList(data);
+                lastFileCount = data.files ? data.files.length : 0;
+            })
+            .catch(error => {
+                showStatus(t('Error loading files: {error}', { error: error.message }), 'error');
+            });
+    }
+
+    // Search once the user stops typing
+    let searchTimer;
+    fileSearch.addEventListener('input', function () {
+        clearTimeout(searchTimer);
+        searchTimer = setTimeout(loadFiles, 250);
+    });
+
+    // Groups the user opened or closed, by group ID, kept across refreshes
+    const groupOpen = new Map();
+
+    // Download the ticked files in one ZIP
+    archiveBtn.addEventListener('click', function () {
+        const url = apiUrl('/files/archive');
+        const ids = [...selectedFiles].map(encodeURIComponent).join(',');
+        window.location.href = `${url}${url.includes('?') ? '&' : '?'}ids=${ids}`;
+    });
+
+    function updateArchiveButton() {
+        archiveBtn.textContent = t('⬇️ Download {count} files as ZIP', { count: selectedFiles.size });
+        archiveBtn.classList.toggle('hidden', selectedFiles.size < 2);
+    }
+
+    // Function to update the file list UI
+    function updateFileList(data) {
+        fileList.innerHTML = '';
+        // Files deleted since they were ticked are no longer selected; a
+        // search only hides the others
+        if (!fileSearch.value.trim()) {
+            const listed = new Set((data.files || []).map(file => file.id));
+            [...selectedFiles].filter(id => !listed.has(id)).forEach(id => selectedFiles.delete(id));
+        }
+        updateArchiveButton();
+
+        if (!data.files || data.files.length === 0) {
+            const empty = document.createElement('p');
+            empty.textContent = fileSearch.value.trim() ? t('No files match the search') : t('No files available');
+            fileList.appendChild(empty);
+            return;
+        }
+        if (!data.groups) {
+            data.files.forEach(file => fileList.appendChild(createFileItem(file)));
+            return;
+        }
+
+        const filesById = new Map(data.files.map(file => [file.id, file]));
+        data.groups.forEach((group, index) => {
+            const details = document.createElement('details');
+            details.className = 'file-group';
+            // Only the newest group starts open
+            details.open = groupOpen.has(group.id) ? groupOpen.get(group.id) : index === 0;
+            details.addEventListener('toggle', function () {
+                groupOpen.set(group.id, details.open);
+            });
+
+            const summary = document.createElement('summary');
+            summary.textContent = describeGroup(group);
+            details.appendChild(summary);
+
+            group.files.forEach(id => {
+                const file = filesById.get(id);
+                if (file) {
+                    details.appendChild(createFileItem(file));
+                }
+            });
+            fileList.appendChild(details);
+        });
+    }
+
+    // e.g. "Tue, 14 May, 10:02 · 12 files · 48.3 MB"
+    const groupTimeFormat = new Intl.DateTimeFormat(undefined, {
+        weekday: 'short',
+        day: 'numeric',
+        month: 'short',
+        hour: '2-digit',
+        minute: '2-digit'
+    });
+    function describeGroup(group) {
+        const parts = [groupTimeFormat.format(parseTime(group.started_at_utc, group.started_at))];
+        if (group.shared) {
+            parts.push(t('shared from the host'));
+        }
+        parts.push(group.files.length === 1 ? t('1 file') : t('{count} files', { count: group.files.length }));
+        parts.push(formatFileSize(group.size));
+        return parts.join(' · ');
+    }
+
+    // Row of the file list for one file
+    function createFileItem(file) {
+        const fileItem = document.createElement('div');
+        fileItem.className = 'file-item';
+
+        const fileInfo = document.createElement('div');
+        fileInfo.className = 'file-info';
+
+        const fileName = document.createElement('div');
+        fileName.className = 'file-name';
+        fileName.textContent = file.name;
+
+        // Protected files are only sent with their password, one at a time
+        if (!roomName && !file.protected) {
+            const select = document.createElement('input');
+            select.type = 'checkbox';
+            select.className = 'file-select';
+            select.checked = selectedFiles.has(file.id);
+            select.setAttribute('aria-label', t('Select {name}', { name: file.name }));
+            select.addEventListener('change', function () {
+                if (select.checked) {
+                    selectedFiles.add(file.id);
+                } else {
+                    selectedFiles.delete(file.id);
+                }
+                updateArchiveButton();
+            });
+            fileName.prepend(select);
+        }
+
+        const fileSize = document.createElement('div');
+        fileSize.className = 'file-size';
+        const details = [formatFileSize(file.size)];
+        if (file.added_at) {
+            const added = parseTime(file.added_at_utc, file.added_at);
+            details.push(formatRelativeTime(added));
+            fileSize.title = t('Added {time}', { time: dateTimeFormat.format(added) });
+        }
+        if (file.folder) {
+            details.push(file.folder);
+        }
+        if (file.corrupted) {
+            details.push(t('⚠️ damaged on disk, ask the sender to send it again'));
+            fileItem.classList.add('corrupted');
+        }
+        if (file.quarantined) {
+            details.push(t('🔒 program or script, quarantined on the host'));
+        }
+        if (file.protected) {
+            details.push(t('🔑 password protected'));
+        }
+        fileSize.textContent = details.join(' · ');
+
+        fileInfo.appendChild(fileName);
+        fileInfo.appendChild(fileSize);
+
+        const fileActions = document.createElement('div');
+        fileActions.className = 'file-actions';
+
+        const downloadBtn = document.createElement('button');
+        downloadBtn.className = 'download-btn';
+        downloadBtn.textContent = t('⬇️ Download');
+        downloadBtn.addEventListener('click', function () {
+            if (file.protected) {
+                downloadProtectedFile(file);
+            } else {
+                window.location.href = apiUrl(`/files/${file.id}`);
+            }
+        });
+
+        fileActions.appendChild(downloadBtn);
+
+        // Single-use links and QR codes hand a file to another device,
+        // which protected files only go to with their password
+        if (!roomName && !file.protected) {
+            const linkBtn = document.createElement('button');
+            linkBtn.className = 'download-btn';
+            linkBtn.textContent = t('🔗 One-time link');
+            linkBtn.addEventListener('click', function () {
+                createOneTimeLink(file);
+            });
+            fileActions.appendChild(linkBtn);
+
+            const qrBtn = document.createElement('button');
+            qrBtn.className = 'download-btn';
+            qrBtn.textContent = t('📱 QR');
+            qrBtn.addEventListener('click', function () {
+                qrImage.src = apiUrl(`/files/${file.id}/qrcode.png`);
+                qrCaption.textContent = file.name;
+                qrOverlay.classList.remove('hidden');
+            });
+            fileActions.appendChild(qrBtn);
+        }
+
+        // Received files can be deleted by the device that sent them
+        if (!roomName && !file.shared && canDelete) {
+            const deleteBtn = document.createElement('button');
+            deleteBtn.className = 'download-btn';
+            deleteBtn.textContent = t('🗑️ Delete');
+            deleteBtn.addEventListener('click', function () {
+                deleteFile(file);
+            });
+            fileActions.appendChild(deleteBtn);
+        }
+
+        fileItem.appendChild(fileInfo);
+        fileItem.appendChild(fileActions);
+
+        return fileItem;
+    }
+
+    // Post the password in a form, so it stays out of the URL and history
+    function downloadProtectedFile(file) {
+        const password = window.prompt(t('Password for {name}', { name: file.name }));
+        if (!password) {
+            return;
+        }
+        const form = document.createElement('form');
+        form.method = 'post';
+        form.action = apiUrl(`/files/${file.id}/unlock`);
+        form.style.display = 'none';
+        const input = document.createElement('input');
+        input.type = 'password';
+        input.name = 'password';
+        input.value = password;
+        form.appendChild(input);
+        document.body.appendChild(form);
+        form.submit();
+        form.remove();
+    }
+
+    // Format a byte count in the browser's locale, e.g. "1.5 MB" or "1,5 MB"
+    const sizeNumberFormat = new Intl.NumberFormat(undefined, {
+        minimumFractionDigits: 1,
+        maximumFractionDigits: 1
+    });
+    function formatFileSize(bytes) {
+        const units = ['B', 'KB', 'MB', 'GB', 'TB'];
+        let size = bytes;
+        let unit = 0;
+        while (size >= 1024 && unit < units.length - 1) {
+            size /= 1024;
+            unit++;
+        }
+        return unit === 0 ? `${bytes} B` : `${sizeNumberFormat.format(size)} ${units[unit]}`;
+    }
+
+    // A time from the API: its RFC 3339 UTC twin, or the Unix seconds
+    // alone from servers that don't send twins
+    function parseTime(utc, unix) {
+        return utc ? new Date(utc) : new Date(unix * 1000);
+    }
+
+    // Times in the device's locale and time zone, e.g. "16 Oct 2025, 11:30"
+    const dateTimeFormat = new Intl.DateTimeFormat(undefined, {
+        dateStyle: 'medium',
+        timeStyle: 'short'
+    });
+    const timeFormat = new Intl.DateTimeFormat(undefined, { timeStyle: 'medium' });
+
+    // Describe a time relative to now, e.g. "3 minutes ago"
+    const relativeTimeFormat = new Intl.RelativeTimeFormat(undefined, { numeric: 'auto' });
+    function formatRelativeTime(date) {
+        const secondsAgo = Math.max(0, Math.floor((Date.now() - date.getTime()) / 1000));
+        if (secondsAgo < 60) {
+            return relativeTimeFormat.format(0, 'second');
+        } else if (secondsAgo < 60 * 60) {
+            return relativeTimeFormat.format(-Math.floor(secondsAgo / 60), 'minute');
+        } else if (secondsAgo < 24 * 60 * 60) {
+            return relativeTimeFormat.format(-Math.floor(secondsAgo / 3600), 'hour');
+        }
+        return relativeTimeFormat.format(-Math.floor(secondsAgo / 86400), 'day');
+    }
+
+    // Function to show status message
+    function showStatus(message, type) {
+        statusEl.textContent = message;
+        statusEl.className = 'status ' + type;
+        statusEl.classList.remove('hidden');
+
+        setTimeout(() => {
+            statusEl.classList.add('hidden');
+        }, 5000);
+    }
+
+    // Delete a file sent by mistake, once the user confirmed it
+    async function deleteFile(file) {
+        if (!confirm(t('Delete {name}?', { name: file.name }))) {
+            return;
+        }
+        try {
+            const response = await fetch(apiUrl(`/files/${file.id}`), { method: 'DELETE' });
+            if (response.status === 403) {
+                throw new Error(t('only the device that sent it can delete it'));
+            }
+            if (!response.ok) {
+                throw new Error(`Server returned ${response.status}`);
+            }
+            showStatus(t('Deleted {name}', { name: file.name }), 'success');
+            loadFiles();
+        } catch (error) {
+            showStatus(t('Deleting failed: {error}', { error: error.message }), 'error');
+        }
+    }
+
+    // Create a link that downloads the file once and copy it
+    async function createOneTimeLink(file) {
+        try {
+            const response = await fetch(apiUrl(`/files/${file.id}/onetime`), { method: 'POST' });
+            if (!response.ok) {
+                throw new Error(`Server returned ${response.status}`);
+            }
+            const link = await response.json();
+            await copyText(link.url);
+            showStatus(t('One-time link for {name} copied: {url}', { name: file.name, url: link.url }), 'success');
+        } catch (error) {
+            showStatus(t('Creating link failed: {error}', { error: error.message }), 'error');
+        }
+    }
+
+    // Share the pasted text; the server renders it for everyone
+    async function shareText() {
+        const content = textInput.value;
+        if (!content.trim()) {
+            return;
+        }
+        try {
+            const response = await fetch(apiUrl('/texts'), {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify({ content, format: textFormat.value })
+            });
+            if (!response.ok) {
+                throw new Error(`Server returned ${response.status}`);
+            }
+            textInput.value = '';
+            loadSnippets();
+        } catch (error) {
+            showStatus(t('Sharing text failed: {error}', { error: error.message }), 'error');
+        }
+    }
+
+    // Load shared text, redrawing only when a new snippet arrived
+    function loadSnippets() {
+        fetch(apiUrl('/texts'))
+            .then(response => response.json())
+            .then(snippets => {
+                const newest = snippets.length > 0 ? snippets[snippets.length - 1].id : null;
+                if (newest !== lastSnippetId) {
+                    lastSnippetId = newest;
+                    updateSnippetList(snippets);
+                }
+            })
+            .catch(error => {
+                console.error('Error loading shared text:', error);
+            });
+    }
+
+    // Stream the server's events into the event log, newest first.
+    // EventSource reconnects by itself, e.g. after a server restart, and
+    // the server sends the recent events again then.
+    function followEvents() {
+        eventLog.classList.remove('hidden');
+        const source = new EventSource(apiUrl('/events'));
+        source.addEventListener('open', function () {
+            eventList.innerHTML = '';
+        });
+        source.addEventListener('message', function (message) {
+            const event = JSON.parse(message.data);
+            const item = document.createElement('li');
+            if (event.event === 'server.error' || event.outcome === 'failed') {
+                item.className = 'error';
+            }
+            const time = document.createElement('time');
+            const at = parseTime(event.at_utc, event.at);
+            time.dateTime = at.toISOString();
+            time.textContent = timeFormat.format(at);
+            time.title = dateTimeFormat.format(at);
+            item.appendChild(time);
+            item.appendChild(document.createTextNode(describeEvent(event)));
+            eventList.prepend(item);
+        });
+    }
+
+    // Ask the app on the computer to carry out a host's command; it
+    // answers before the command runs
+    async function sendControl(method, path, body) {
+        const init = { method };
+        if (body) {
+            init.headers = { 'Content-Type': 'application/json' };
+            init.body = JSON.stringify(body);
+        }
+        const response = await fetch(apiUrl(`/control/${path}`), init);
+        if (response.status !== 202) {
+            throw new Error(`Server returned ${response.status}`);
+        }
+    }
+
+    // Controls for managing the share from the portal, hosts only
+    function showHostControls(readOnly) {
+        hostControls.classList.remove('hidden');
+        readOnlyToggle.checked = readOnly;
+        readOnlyToggle.addEventListener('change', async function () {
+            const on = readOnlyToggle.checked;
+            try {
+                await sendControl('PUT', 'read-only', { read_only: on });
+                uploadArea.classList.toggle('hidden', on);
+                textForm.classList.toggle('hidden', on);
+                showStatus(on ? 'Guests can now only download' : 'Guests can upload again', 'success');
+            } catch (error) {
+                readOnlyToggle.checked = !on;
+                showStatus(`Changing read-only mode failed: ${error.message}`, 'error');
+            }
+        });
+
+        document.getElementById('clearFilesBtn').addEventListener('click', async function () {
+            if (!confirm('Remove all received files? Files shared from the computer are kept.')) {
+                return;
+            }
+            try {
+                await sendControl('POST', 'clear');
+                showStatus('Clearing the received files...', 'info');
+                setTimeout(loadFiles, 1000);
+            } catch (error) {
+                showStatus(`Clearing files failed: ${error.message}`, 'error');
+            }
+        });
+
+        // Either takes the portal down; only the computer can start it again
+        document.getElementById('pauseServerBtn').addEventListener('click', async function () {
+            if (!confirm('Pause the server? Received files are kept, but the portal is offline until the server is started again on the computer.')) {
+                return;
+            }
+            try {
+                await sendControl('POST', 'pause');
+                clearInterval(pollingInterval);
+                showStatus('The server is pausing; start it again on the computer', 'info');
+            } catch (error) {
+                showStatus(`Pausing the server failed: ${error.message}`, 'error');
+            }
+        });
+        document.getElementById('stopServerBtn').addEventListener('click', async function () {
+            if (!confirm('Stop the server and remove the received files? The portal is offline until the server is started again on the computer.')) {
+                return;
+            }
+            try {
+                await sendControl('POST', 'stop');
+                clearInterval(pollingInterval);
+                showStatus('The server is stopping; start it again on the computer', 'info');
+            } catch (error) {
+                showStatus(`Stopping the server failed: ${error.message}`, 'error');
+            }
+        });
+    }
+
+    // One line about a server event
+    function describeEvent(event) {
+        const where = event.room ? ` in room "${event.room}"` : '';
+        switch (event.event) {
+            case 'client.connected':
+                return `Device connected from ${event.address}`;
+            case 'file.received':
+                return `Received ${event.name} (${formatFileSize(event.size)})${where}`;
+            case 'file.deleted':
+                return `Removed ${event.name}${where}`;
+            case 'file.conflict':
+                return `${event.name} conflicts with a stored file${where}`;
+            case 'file.corrupted':
+                return `${event.name} no longer matches its checksum${where}`;
+            case 'file.quarantined':
+                return `${event.name} was quarantined${where}`;
+            case 'server.started':
+                return `Server started at ${event.url}`;
+            case 'server.stopped':
+                return `Server at ${event.url} stopped`;
+            case 'server.error':
+                return event.message;
+            case 'transfer.started':
+                return `${event.kind[0].toUpperCase()}${event.kind.slice(1)} of ${event.name || 'a file'} started` +
+                    (event.peer ? ` by ${event.peer}` : '');
+            case 'transfer.paused':
+                return `Transfer ${event.id} paused`;
+            case 'transfer.resumed':
+                return `Transfer ${event.id} resumed`;
+            case 'transfer.finished':
+                return `Transfer ${event.outcome} after ${formatFileSize(event.bytes)}`;
+            default:
+                return event.event;
+        }
+    }
+
+    // Render snippets newest first; the HTML comes pre-rendered and escaped from the server
+    function updateSnippetList(snippets) {
+        snippetList.innerHTML = '';
+        snippets.slice().reverse().forEach(snippet => {
+            const item = document.createElement('div');
+            item.className = 'snippet';
+
+            const header = document.createElement('div');
+            header.className = 'snippet-header';
+            const label = document.createElement('span');
+            label.textContent = `${snippet.format} · ${formatRelativeTime(new Date(snippet.created_at * 1000))}`;
+            const copyBtn = document.createElement('button');
+            copyBtn.className = 'download-btn';
+            copyBtn.textContent = t('📋 Copy');
+            copyBtn.addEventListener('click', function () {
+                copyText(snippet.content).then(() => showStatus(t('Copied to clipboard'), 'success'));
+            });
+            header.appendChild(label);
+            header.appendChild(copyBtn);
+
+            const body = document.createElement('div');
+            body.className = 'snippet-body';
+            body.innerHTML = snippet.html;
+
+            item.appendChild(header);
+            item.appendChild(body);
+            if (snippet.preview) {
+                item.appendChild(linkPreview(snippet.content.trim(), snippet.preview));
+            }
+            snippetList.appendChild(item);
+        });
+    }
+
+    // Card for a shared link, with the page's title and icon fetched by the
+    // server; the icon comes embedded, so nothing is loaded from the site
+    function linkPreview(url, preview) {
+        const card = document.createElement('a');
+        card.className = 'link-preview';
+        card.href = url;
+        card.target = '_blank';
+        card.rel = 'noopener noreferrer';
+        if (preview.icon) {
+            const icon = document.createElement('img');
+            icon.src = preview.icon;
+            icon.alt = '';
+            card.appendChild(icon);
+        }
+        const text = document.createElement('span');
+        const title = document.createElement('strong');
+        title.textContent = preview.title;
+        const host = document.createElement('small');
+        host.textContent = new URL(url).host;
+        text.appendChild(title);
+        text.appendChild(host);
+        card.appendChild(text);
+        return card;
+    }
+
+    // The async clipboard API needs HTTPS, so fall back to a hidden textarea
+    function copyText(text) {
+        if (navigator.clipboard && window.isSecureContext) {
+            return navigator.clipboard.writeText(text);
+        }
+        const area = document.createElement('textarea');
+        area.value = text;
+        area.style.position = 'fixed';
+        area.style.opacity = '0';
+        document.body.appendChild(area);
+        area.select();
+        document.execCommand('copy');
+        document.body.removeChild(area);
+        return Promise.resolve();
+    }
+
+    // Convert a transfer into megabits per second
+    function toMbps(bytes, ms) {
+        return (bytes * 8) / (ms / 1000) / 1e6;
+    }
+
+    // Measure latency and throughput between this device and the server
+    async function runSpeedTest() {
+        speedTestBtn.disabled = true;
+        try {
+            speedTestResult.textContent = t('Measuring latency...');
+            const pingStart = performance.now();
+            await fetch('/api/v1/ping', { cache: 'no-store' });
+            const latency = performance.now() - pingStart;
+
+            speedTestResult.textContent = t('Measuring download...');
+            let start = performance.now();
+            const download = await fetch(`/api/v1/speedtest/download?bytes=${SPEED_TEST_BYTES}`, { cache: 'no-store' });
+            if (!download.ok) {
+                throw new Error(`Server returned ${download.status}`);
+            }
+            const received = (await download.arrayBuffer()).byteLength;
+            const downMbps = toMbps(received, performance.now() - start);
+
+            speedTestResult.textContent = t('Measuring upload...');
+            const payload = new Uint8Array(SPEED_TEST_BYTES);
+            start = performance.now();
+            const upload = await fetch('/api/v1/speedtest/upload', {
+                method: 'POST',
+                headers: { 'Content-Type': 'application/octet-stream' },
+                body: payload
+            });
+            if (!upload.ok) {
+                throw new Error(`Server returned ${upload.status}`);
+            }
+            const sent = (await upload.json()).bytes;
+            const upMbps = toMbps(sent, performance.now() - start);
+
+            speedTestResult.textContent = t('↓ {down} Mbps · ↑ {up} Mbps · {latency} ms latency', {
+                down: downMbps.toFixed(1),
+                up: upMbps.toFixed(1),
+                latency: Math.round(latency)
+            });
+        } catch (error) {
+            console.error('Speed test failed:', error);
+            speedTestResult.textContent = t('Speed test failed: {error}', { error: error.message });
+        } finally {
+            speedTestBtn.disabled = false;
+        }
+    }
+
+    // Function to update progress bar for chunked uploads
+    function updateProgressBar(currentChunk, totalChunks) {
+        // Calculate percentage
+        const percent = Math.round((currentChunk / totalChunks) * 100);
+
+        // Create progress element if it doesn't exist
+        if (!document.getElementById('uploadProgress')) {
+            const progressContainer = document.createElement('div');
+            progressContainer.id = 'progressContainer';
+            progressContainer.style.marginTop = '15px';
+            progressContainer.style.marginBottom = '15px';
+
+            const progressText = document.createElement('div');
+            progressText.id = 'progressText';
+            progressText.textContent = t('Uploading: {percent}%', { percent: 0 });
+
+            const progressBar = document.createElement('div');
+            progressBar.style.height = '10px';
+            progressBar.style.backgroundColor = '#e0e0e0';
+            progressBar.style.borderRadius = '5px';
+            progressBar.style.marginTop = '5px';
+
+            const progress = document.createElement('div');
+            progress.id = 'uploadProgress';
+            progress.style.width = '0%';
+            progress.style.height = '100%';
+            progress.style.backgroundColor = 'var(--primary-color)';
+            progress.style.borderRadius = '5px';
+            progress.style.transition = 'width 0.3s';
+
+            progressBar.appendChild(progress);
+            progressContainer.appendChild(progressText);
+            progressContainer.appendChild(progressBar);
+
+            // Insert after status element
+            statusEl.parentNode.insertBefore(progressContainer, statusEl.nextSibling);
+        }
+
+        // Update progress
+        const progressBar = document.getElementById('uploadProgress');
+        const progressText = document.getElementById('progressText');
+
+        progressBar.style.width = `${percent}%`;
+        progressText.textContent = t('Uploading: {percent}%', { percent });
+    }
+
+    // Function to show upload complete message
+    function showUploadComplete(fileData) {
+        // Remove progress bar
+        const progressContainer = document.getElementById('progressContainer');
+        if (progressContainer) {
+            progressContainer.remove();
+        }
+
+        // Show success message
+        showStatus(t('File "{name}" uploaded successfully!', { name: fileData.name }), 'success');
+
+        // Refresh the file list
+        loadFiles();
+    }
+});
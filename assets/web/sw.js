@@ -0,0 +1,47 @@
+// Offline shell of the portal: the page and its assets are kept so the
+// portal opens at once from the home screen, while file lists, uploads and
+// downloads always go to the network. The server fills in the cache
+// version and the hashed asset names.
+const CACHE = 'justrans-shell-SHELL_VERSION';
+const SHELL = [
+    '/',
+    '/static/portal.css',
+    '/static/portal.js',
+    '/static/icon-192.png',
+];
+
+self.addEventListener('install', (event) => {
+    event.waitUntil(
+        caches.open(CACHE)
+            .then((cache) => cache.addAll(SHELL))
+            .then(() => self.skipWaiting())
+    );
+});
+
+self.addEventListener('activate', (event) => {
+    // Drop the shells of earlier versions
+    event.waitUntil(
+        caches.keys()
+            .then((keys) => Promise.all(
+                keys.filter((key) => key !== CACHE).map((key) => caches.delete(key))
+            ))
+            .then(() => self.clients.claim())
+    );
+});
+
+self.addEventListener('fetch', (event) => {
+    const request = event.request;
+    const url = new URL(request.url);
+    if (request.method !== 'GET' || url.origin !== self.location.origin) {
+        return;
+    }
+
+    if (request.mode === 'navigate') {
+        // The current page when the computer is reachable, the shell when not
+        event.respondWith(fetch(request).catch(() => caches.match('/')));
+    } else if (url.pathname.startsWith('/static/')) {
+        event.respondWith(
+            caches.match(request).then((cached) => cached || fetch(request))
+        );
+    }
+});
@@ -1,3 +1,9 @@
 fn main() {
     slint_build::compile("assets/ui/app-window.slint").unwrap();
+
+    #[cfg(feature = "grpc")]
+    {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().unwrap());
+        tonic_build::compile_protos("proto/control.proto").unwrap();
+    }
 }
@@ -0,0 +1,109 @@
+//! What the desktop app does with a file once its upload completed, by
+//! kind: open it in its default app, only report it in the status bar, or
+//! never open it. Rules come from `display.auto_open`, e.g. "images: open",
+//! "pdf: notify", "apk: never"; the first rule matching the file's name
+//! decides and files no rule matches are left alone.
+
+use crate::config::{AutoOpenAction, DisplayConfig};
+use crate::models::FileInfo;
+use crate::server::file_types::Rule;
+
+/// The `display.auto_open` rules, parsed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AutoOpen {
+    rules: Vec<(Vec<Rule>, AutoOpenAction)>,
+}
+
+impl AutoOpen {
+    pub fn from_config(config: &DisplayConfig) -> Self {
+        Self {
+            rules: config
+                .auto_open
+                .iter()
+                .map(|rule| {
+                    let kinds = rule.files.iter().filter_map(|entry| Rule::parse(entry));
+                    (kinds.collect(), rule.action)
+                })
+                .collect(),
+        }
+    }
+
+    /// What to do with a received file, if anything. Quarantined programs
+    /// are never opened, whatever the rules say.
+    pub fn action(&self, file: &FileInfo) -> Option<AutoOpenAction> {
+        let action = self
+            .rules
+            .iter()
+            .find(|(kinds, _)| kinds.iter().any(|kind| kind.matches(&file.name)))
+            .map(|(_, action)| *action)?;
+        if file.quarantined && action == AutoOpenAction::Open {
+            return Some(AutoOpenAction::Notify);
+        }
+        Some(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AutoOpenRuleConfig;
+    use std::path::PathBuf;
+
+    fn rule(files: &[&str], action: AutoOpenAction) -> AutoOpenRuleConfig {
+        AutoOpenRuleConfig {
+            files: files.iter().map(|entry| entry.to_string()).collect(),
+            action,
+        }
+    }
+
+    fn received(name: &str) -> FileInfo {
+        FileInfo {
+            id: "1".to_string(),
+            added_at: 0,
+            ..FileInfo::new(name.to_string(), PathBuf::from("1_file"), 1)
+        }
+    }
+
+    #[test]
+    fn test_first_matching_rule_decides() {
+        let auto_open = AutoOpen::from_config(&DisplayConfig {
+            auto_open: vec![
+                rule(&["apk", "image/svg+xml"], AutoOpenAction::Never),
+                rule(&["image/*"], AutoOpenAction::Open),
+                rule(&["PDF"], AutoOpenAction::Notify),
+            ],
+            ..Default::default()
+        });
+        assert_eq!(
+            auto_open.action(&received("photo.jpg")),
+            Some(AutoOpenAction::Open)
+        );
+        assert_eq!(
+            auto_open.action(&received("drawing.svg")),
+            Some(AutoOpenAction::Never)
+        );
+        assert_eq!(
+            auto_open.action(&received("invoice.pdf")),
+            Some(AutoOpenAction::Notify)
+        );
+        assert_eq!(
+            auto_open.action(&received("app.apk")),
+            Some(AutoOpenAction::Never)
+        );
+        assert_eq!(auto_open.action(&received("notes.txt")), None);
+        assert_eq!(AutoOpen::default().action(&received("photo.jpg")), None);
+    }
+
+    #[test]
+    fn test_quarantined_files_stay_closed() {
+        let auto_open = AutoOpen::from_config(&DisplayConfig {
+            auto_open: vec![rule(&["sh"], AutoOpenAction::Open)],
+            ..Default::default()
+        });
+        let script = FileInfo {
+            quarantined: true,
+            ..received("install.sh")
+        };
+        assert_eq!(auto_open.action(&script), Some(AutoOpenAction::Notify));
+    }
+}
@@ -0,0 +1,394 @@
+//! Export and import of the application state, for moving JusTrans to
+//! another machine. The archive is a tar file holding `manifest.json`
+//! (settings, rooms and shared paths) followed by the stored files.
+
+pub mod snapshots;
+mod tar;
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigData;
+use crate::models::{unix_now, FileInfo};
+use crate::server::scrub;
+use crate::server::FileServer;
+
+/// Directory state archives are exported to
+pub const BACKUP_DIR: &str = "backups";
+
+const MANIFEST: &str = "manifest.json";
+const FILES_PREFIX: &str = "files/";
+
+/// Bumped when the archive layout changes incompatibly
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RoomDefinition {
+    name: String,
+    pin: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredFile {
+    room: Option<String>,
+    file: FileInfo,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    app_version: String,
+    created_at: u64,
+    settings: ConfigData,
+    rooms: Vec<RoomDefinition>,
+    /// Files shared from the desktop in place, restored by path
+    shared: Vec<PathBuf>,
+    /// Received files included in the archive
+    #[serde(default)]
+    files: Vec<StoredFile>,
+}
+
+impl Manifest {
+    fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let manifest: Manifest = serde_json::from_slice(data)?;
+        if manifest.version > FORMAT_VERSION {
+            return Err(anyhow::anyhow!(
+                "The archive was made by a newer JusTrans ({}) and cannot be imported",
+                manifest.app_version
+            ));
+        }
+        Ok(manifest)
+    }
+}
+
+/// What an export wrote
+#[derive(Debug, Clone)]
+pub struct ExportSummary {
+    pub path: PathBuf,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// What an import restored
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub rooms: usize,
+    pub shared: usize,
+    /// Shared paths that do not exist on this machine
+    pub missing: usize,
+    pub files: usize,
+    /// Stored files whose contents did not match their checksum
+    pub damaged: usize,
+}
+
+impl ImportSummary {
+    pub fn message(&self) -> String {
+        let mut message = format!(
+            "Imported settings, {} room(s), {} shared file(s) and {} stored file(s)",
+            self.rooms, self.shared, self.files
+        );
+        if self.missing > 0 {
+            message.push_str(&format!(
+                "; {} shared path(s) not found on this machine",
+                self.missing
+            ));
+        }
+        if self.damaged > 0 {
+            message.push_str(&format!("; skipped {} damaged file(s)", self.damaged));
+        }
+        message
+    }
+}
+
+/// Write the settings, rooms and shares of `file_server`, and its received
+/// files if `include_files` is set, to a new archive in `dir`
+pub fn export(
+    file_server: &Mutex<FileServer>,
+    config: &ConfigData,
+    include_files: bool,
+    dir: &Path,
+) -> anyhow::Result<ExportSummary> {
+    // Take a snapshot so the archive is written without holding the lock
+    let (rooms, stored) = {
+        let file_server = file_server.lock().unwrap();
+        (file_server.rooms(), file_server.stored_files())
+    };
+
+    let shared = stored
+        .iter()
+        .filter(|(_, file)| file.shared)
+        .map(|(_, file)| file.path.clone())
+        .collect();
+    let files: Vec<StoredFile> = if include_files {
+        stored
+            .into_iter()
+            // Protected files would come back without their password
+            .filter(|(_, file)| !file.shared && !file.corrupted && !file.protected)
+            .map(|(room, file)| StoredFile { room, file })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let manifest = Manifest {
+        version: FORMAT_VERSION,
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: unix_now(),
+        settings: config.clone(),
+        rooms: rooms
+            .into_iter()
+            .map(|room| RoomDefinition {
+                name: room.name,
+                pin: room.pin,
+            })
+            .collect(),
+        shared,
+        files,
+    };
+
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!(
+        "justrans-state-{}.tar",
+        Local::now().format("%Y%m%d_%H%M%S")
+    ));
+    let result = write_archive(&path, &manifest);
+    if result.is_err() {
+        let _ = std::fs::remove_file(&path);
+    }
+    let bytes = result?;
+
+    log::info!(
+        "Exported application state with {} file(s) to {:?}",
+        manifest.files.len(),
+        path
+    );
+    Ok(ExportSummary {
+        path,
+        files: manifest.files.len(),
+        bytes,
+    })
+}
+
+/// Write `manifest` and the files it lists, returning the bytes of file
+/// contents archived
+fn write_archive(path: &Path, manifest: &Manifest) -> anyhow::Result<u64> {
+    let mut builder = tar::Builder::new(BufWriter::new(File::create(path)?), manifest.created_at);
+    builder.append_bytes(MANIFEST, &serde_json::to_vec_pretty(manifest)?)?;
+
+    let mut bytes = 0;
+    for stored in &manifest.files {
+        let file = File::open(&stored.file.path)
+            .map_err(|e| anyhow::anyhow!("Failed to read '{}': {}", stored.file.name, e))?;
+        builder.append(
+            &format!("{}{}", FILES_PREFIX, stored.file.id),
+            stored.file.size,
+            BufReader::new(file),
+        )?;
+        bytes += stored.file.size;
+    }
+    builder.finish()?;
+    Ok(bytes)
+}
+
+/// Restore an archive made by [`export`] into `file_server`, returning the
+/// archived settings for the caller to apply. Rooms that already exist and
+/// paths that are already shared are left alone.
+pub fn import(
+    path: &Path,
+    file_server: &Mutex<FileServer>,
+) -> anyhow::Result<(ConfigData, ImportSummary)> {
+    let mut manifest: Option<Manifest> = None;
+    let mut summary = ImportSummary::default();
+    let mut failure: Option<anyhow::Error> = None;
+
+    tar::read_entries(BufReader::new(File::open(path)?), |name, data| {
+        // The manifest comes first and says where the files that follow go
+        let result = if let Some(manifest) = &manifest {
+            match name.strip_prefix(FILES_PREFIX) {
+                Some(id) => restore_file(manifest, id, data, file_server, &mut summary),
+                None => Ok(()),
+            }
+        } else if name == MANIFEST {
+            let mut contents = Vec::new();
+            data.read_to_end(&mut contents)?;
+            Manifest::parse(&contents).map(|parsed| {
+                restore_definitions(&parsed, file_server, &mut summary);
+                manifest = Some(parsed);
+            })
+        } else {
+            Err(anyhow::anyhow!("Not a JusTrans state archive"))
+        };
+        result.map_err(|e| {
+            failure = Some(e);
+            io::Error::other("import failed")
+        })
+    })
+    .map_err(|e| failure.take().unwrap_or_else(|| e.into()))?;
+
+    let manifest = manifest.ok_or_else(|| anyhow::anyhow!("Not a JusTrans state archive"))?;
+    log::info!("Imported application state from {:?}: {:?}", path, summary);
+    Ok((manifest.settings, summary))
+}
+
+/// Recreate the rooms and re-share the desktop files of `manifest`
+fn restore_definitions(
+    manifest: &Manifest,
+    file_server: &Mutex<FileServer>,
+    summary: &mut ImportSummary,
+) {
+    let file_server = file_server.lock().unwrap();
+    let existing: Vec<String> = file_server
+        .rooms()
+        .into_iter()
+        .map(|room| room.name)
+        .collect();
+    for room in &manifest.rooms {
+        if existing.contains(&room.name) {
+            continue;
+        }
+        match file_server.create_room(&room.name, room.pin.clone()) {
+            Ok(()) => summary.rooms += 1,
+            Err(e) => log::warn!("Skipped room '{}': {}", room.name, e),
+        }
+    }
+
+    let shared: Vec<PathBuf> = file_server
+        .stored_files()
+        .into_iter()
+        .filter(|(_, file)| file.shared)
+        .map(|(_, file)| file.path)
+        .collect();
+    for path in &manifest.shared {
+        if shared.contains(path) {
+            continue;
+        }
+        if !path.is_file() {
+            log::warn!("Shared file {:?} does not exist on this machine", path);
+            summary.missing += 1;
+            continue;
+        }
+        match file_server.share_local_file(path) {
+            Ok(_) => summary.shared += 1,
+            Err(e) => log::warn!("Failed to re-share {:?}: {}", path, e),
+        }
+    }
+}
+
+/// `folder` from an archive, if it is a plain relative path
+fn safe_folder(folder: &str) -> Option<&Path> {
+    let path = Path::new(folder);
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        .then_some(path)
+}
+
+/// Copy one archived file into the storage directory of its room
+fn restore_file(
+    manifest: &Manifest,
+    id: &str,
+    data: &mut dyn Read,
+    file_server: &Mutex<FileServer>,
+    summary: &mut ImportSummary,
+) -> anyhow::Result<()> {
+    let Some(stored) = manifest.files.iter().find(|stored| stored.file.id == id) else {
+        log::warn!("Skipped archive entry for unknown file {}", id);
+        return Ok(());
+    };
+    let storage_dir = file_server
+        .lock()
+        .unwrap()
+        .storage_dir(stored.room.as_deref());
+
+    // Organized files go back into their date folder unless the name is taken
+    let mut file_info = stored.file.clone();
+    file_info.id = uuid::Uuid::new_v4().to_string();
+    let organized = file_info
+        .folder
+        .as_deref()
+        .and_then(safe_folder)
+        .zip(Path::new(&file_info.name).file_name())
+        .map(|(folder, name)| storage_dir.join(folder).join(name))
+        .filter(|path| !path.exists());
+    file_info.path = match organized {
+        Some(path) => path,
+        None => {
+            file_info.folder = None;
+            storage_dir.join(format!("{}_file", file_info.id))
+        }
+    };
+
+    if let Some(dir) = file_info.path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut writer = BufWriter::new(File::create(&file_info.path)?);
+    io::copy(data, &mut writer)?;
+    writer.flush()?;
+
+    let hash = scrub::hash_file(&file_info.path)?;
+    if file_info
+        .sha256
+        .as_ref()
+        .is_some_and(|sha256| *sha256 != hash)
+    {
+        log::warn!(
+            "Archived copy of '{}' is damaged, skipping it",
+            file_info.name
+        );
+        std::fs::remove_file(&file_info.path)?;
+        summary.damaged += 1;
+        return Ok(());
+    }
+    file_info.sha256 = Some(hash);
+
+    file_server
+        .lock()
+        .unwrap()
+        .add_restored_file(stored.room.as_deref(), file_info)?;
+    summary.files += 1;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(version: u32) -> Manifest {
+        let mut settings = ConfigData::default();
+        settings.server.port = 9090;
+        Manifest {
+            version,
+            app_version: "9.9.9".to_string(),
+            created_at: 1_700_000_000,
+            settings,
+            rooms: vec![RoomDefinition {
+                name: "family".to_string(),
+                pin: Some("1234".to_string()),
+            }],
+            shared: vec![PathBuf::from("/home/me/report.pdf")],
+            files: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let data = serde_json::to_vec(&manifest(FORMAT_VERSION)).unwrap();
+        let parsed = Manifest::parse(&data).unwrap();
+        assert_eq!(parsed.settings.server.port, 9090);
+        assert_eq!(parsed.rooms, manifest(FORMAT_VERSION).rooms);
+        assert_eq!(parsed.shared, vec![PathBuf::from("/home/me/report.pdf")]);
+
+        let newer = serde_json::to_vec(&manifest(FORMAT_VERSION + 1)).unwrap();
+        assert!(Manifest::parse(&newer).is_err());
+    }
+
+    #[test]
+    fn test_safe_folder() {
+        assert!(safe_folder("2024/05").is_some());
+        assert!(safe_folder("../2024").is_none());
+        assert!(safe_folder("/etc").is_none());
+    }
+}
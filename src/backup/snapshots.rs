@@ -0,0 +1,254 @@
+//! Snapshots of the metadata records and the settings file, so pairing,
+//! device and history records survive a damaged database or a deleted
+//! settings file. While the default server runs, a snapshot folder is
+//! written to `backups/snapshots` every `storage.snapshots.interval_hours`
+//! and the oldest beyond `keep` are removed. A snapshot is restored from
+//! the diagnostics panel, after taking one of the current state.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use chrono::Local;
+use settings::Settings;
+use tokio::sync::watch;
+
+use crate::config::{ConfigData, SnapshotConfig};
+use crate::metadata::MetadataStore;
+
+/// Directory snapshot folders are written to
+pub const SNAPSHOT_DIR: &str = "backups/snapshots";
+
+const DATABASE_FILE: &str = "metadata.db";
+const SETTINGS_FILE: &str = "settings.yaml";
+
+/// What a restore brought back
+#[derive(Debug, Clone, Default)]
+pub struct Restored {
+    /// The settings of the snapshot, for the caller to apply
+    pub settings: Option<ConfigData>,
+    /// Whether the metadata records were replaced
+    pub records: bool,
+}
+
+impl Restored {
+    pub fn message(&self) -> String {
+        match (self.settings.is_some(), self.records) {
+            (true, true) => "Restored the settings and the records - restart JusTrans to list the restored files".to_string(),
+            (true, false) => "Restored the settings".to_string(),
+            (false, true) => "Restored the records - restart JusTrans to list the restored files".to_string(),
+            (false, false) => "The snapshot was empty".to_string(),
+        }
+    }
+}
+
+/// A new snapshot folder in `dir`, named after the current time
+pub fn new_folder(dir: &Path) -> PathBuf {
+    dir.join(Local::now().format("%Y%m%d_%H%M%S").to_string())
+}
+
+/// Write a snapshot of `store` and the settings file at `settings` to the
+/// new folder `folder`
+pub fn take(store: &dyn MetadataStore, settings: &Path, folder: &Path) -> anyhow::Result<()> {
+    if let Some(dir) = folder.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::create_dir(folder)?;
+    let result = (|| {
+        if settings.is_file() {
+            std::fs::copy(settings, folder.join(SETTINGS_FILE))?;
+        }
+        store.snapshot(&folder.join(DATABASE_FILE))?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_dir_all(folder);
+    }
+    result
+}
+
+/// Snapshot folders in `dir`, the newest first
+pub fn list(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut folders: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    // Names are timestamps, so they sort by age
+    folders.sort_by(|a, b| b.file_name().cmp(&a.file_name()));
+    folders
+}
+
+/// Remove all but the newest `keep` snapshots in `dir`, returning how many
+/// were removed
+pub fn prune(dir: &Path, keep: usize) -> usize {
+    let mut removed = 0;
+    for folder in list(dir).into_iter().skip(keep) {
+        match std::fs::remove_dir_all(&folder) {
+            Ok(()) => removed += 1,
+            Err(e) => log::warn!("Failed to remove old snapshot {:?}: {}", folder, e),
+        }
+    }
+    removed
+}
+
+/// How long until the next snapshot in `dir` is due at `now`, one
+/// `interval` after the newest
+fn due_in(dir: &Path, interval: Duration, now: SystemTime) -> Duration {
+    let newest = list(dir)
+        .first()
+        .and_then(|folder| std::fs::metadata(folder).ok()?.modified().ok());
+    match newest {
+        Some(newest) => (newest + interval)
+            .duration_since(now)
+            .unwrap_or(Duration::ZERO),
+        None => Duration::ZERO,
+    }
+}
+
+/// Restore the snapshot in `folder`: its records replace those of `store`
+/// and its settings are returned. The current state is first saved as a
+/// snapshot in `dir`, so a restore can be undone.
+pub fn restore(
+    folder: &Path,
+    store: &dyn MetadataStore,
+    settings: &Path,
+    dir: &Path,
+) -> anyhow::Result<Restored> {
+    let database = folder.join(DATABASE_FILE);
+    let settings_file = folder.join(SETTINGS_FILE);
+    if !database.is_file() && !settings_file.is_file() {
+        anyhow::bail!("{:?} is not a JusTrans snapshot", folder);
+    }
+    let snapshot = if settings_file.is_file() {
+        Some(ConfigData::load(&settings_file)?)
+    } else {
+        None
+    };
+
+    let backup = new_folder(dir);
+    take(store, settings, &backup)?;
+    log::info!("Saved the current state to {:?} before restoring", backup);
+    let records = database.is_file();
+    if records {
+        store.restore(&database)?;
+    }
+    log::info!("Restored snapshot {:?}", folder);
+    Ok(Restored {
+        settings: snapshot,
+        records,
+    })
+}
+
+/// Take snapshots of `store` as `config` says until `shutdown` changes
+pub async fn run(
+    store: Arc<dyn MetadataStore>,
+    config: SnapshotConfig,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let interval = Duration::from_secs(config.interval_hours * 3600);
+    let dir = PathBuf::from(SNAPSHOT_DIR);
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(due_in(&dir, interval, SystemTime::now())) => {}
+            _ = shutdown.changed() => return,
+        }
+        let store = store.clone();
+        let dir = dir.clone();
+        let keep = config.keep;
+        let taken = tokio::task::spawn_blocking(move || {
+            let folder = new_folder(&dir);
+            take(store.as_ref(), &settings::path(), &folder)?;
+            let removed = prune(&dir, keep.max(1));
+            anyhow::Ok((folder, removed))
+        })
+        .await;
+        match taken {
+            Ok(Ok((folder, removed))) => log::info!(
+                "Saved a snapshot to {:?}, removed {} old one(s)",
+                folder,
+                removed
+            ),
+            Ok(Err(e)) => {
+                log::error!("Failed to save a snapshot: {}", e);
+                // Try again later rather than at once
+                tokio::select! {
+                    _ = tokio::time::sleep(interval.min(Duration::from_secs(3600))) => {}
+                    _ = shutdown.changed() => return,
+                }
+            }
+            Err(e) => log::error!("Snapshot task failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::MemoryStore;
+
+    #[test]
+    fn test_take_and_prune() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = dir.path().join("settings.yaml");
+        std::fs::write(&settings, "server:\n  port: 9000\n").unwrap();
+        let snapshots = dir.path().join("snapshots");
+        let store = MemoryStore::default();
+
+        for name in ["20261001_120000", "20261003_120000", "20261002_120000"] {
+            take(&store, &settings, &snapshots.join(name)).unwrap();
+        }
+        // A store kept in memory only has nothing to write
+        let newest = &list(&snapshots)[0];
+        assert!(newest.ends_with("20261003_120000"));
+        assert!(newest.join(SETTINGS_FILE).is_file());
+        assert!(!newest.join(DATABASE_FILE).exists());
+        assert!(take(&store, &settings, newest).is_err());
+
+        assert_eq!(prune(&snapshots, 2), 1);
+        let left: Vec<_> = list(&snapshots)
+            .iter()
+            .map(|folder| folder.file_name().unwrap().to_owned())
+            .collect();
+        assert_eq!(left, ["20261003_120000", "20261002_120000"]);
+    }
+
+    #[test]
+    fn test_due_in() {
+        let dir = tempfile::tempdir().unwrap();
+        let day = Duration::from_secs(24 * 3600);
+        let now = SystemTime::now();
+        assert_eq!(due_in(dir.path(), day, now), Duration::ZERO);
+
+        std::fs::create_dir(dir.path().join("20261001_120000")).unwrap();
+        let due = due_in(dir.path(), day, now);
+        assert!(due > day - Duration::from_secs(60) && due <= day);
+        assert_eq!(due_in(dir.path(), day, now + day * 2), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = dir.path().join("settings.yaml");
+        std::fs::write(&settings, "server:\n  port: 9000\n").unwrap();
+        let snapshots = dir.path().join("snapshots");
+        let store = MemoryStore::default();
+        let folder = snapshots.join("20261001_120000");
+        take(&store, &settings, &folder).unwrap();
+        std::fs::write(&settings, "server:\n  port: 9100\n").unwrap();
+
+        let restored = restore(&folder, &store, &settings, &snapshots).unwrap();
+        assert_eq!(restored.settings.unwrap().server.port, 9000);
+        assert!(!restored.records);
+        // The state before the restore was saved first
+        assert_eq!(list(&snapshots).len(), 2);
+
+        // A snapshot with records can't go into a store kept in memory
+        std::fs::write(folder.join(DATABASE_FILE), "").unwrap();
+        assert!(restore(&folder, &store, &settings, &snapshots).is_err());
+        assert!(restore(dir.path(), &store, &settings, &snapshots).is_err());
+    }
+}
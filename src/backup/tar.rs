@@ -0,0 +1,194 @@
+//! Minimal ustar reader and writer, enough for regular files with short
+//! names. Archives open with any tar tool.
+
+use std::io::{self, Read, Write};
+
+const BLOCK: usize = 512;
+const NAME_LEN: usize = 100;
+
+fn octal(field: &mut [u8], value: u64) {
+    let len = field.len() - 1;
+    let digits = format!("{:0width$o}", value, width = len);
+    field[..len].copy_from_slice(digits.as_bytes());
+    field[len] = 0;
+}
+
+fn parse_octal(field: &[u8]) -> io::Result<u64> {
+    let text = std::str::from_utf8(field)
+        .map_err(|_| invalid("bad number in tar header"))?
+        .trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| invalid("bad number in tar header"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn padding(size: u64) -> usize {
+    (BLOCK - (size % BLOCK as u64) as usize) % BLOCK
+}
+
+fn header(name: &str, size: u64, mtime: u64) -> io::Result<[u8; BLOCK]> {
+    if name.len() > NAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("name too long for tar: {}", name),
+        ));
+    }
+    let mut header = [0u8; BLOCK];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    octal(&mut header[100..108], 0o644);
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    octal(&mut header[124..136], size);
+    octal(&mut header[136..148], mtime);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    // The checksum is computed with its own field filled with spaces
+    header[148..156].fill(b' ');
+    let checksum: u64 = header.iter().map(|&b| b as u64).sum();
+    octal(&mut header[148..155], checksum);
+    header[155] = b' ';
+    Ok(header)
+}
+
+/// Writes entries to a tar stream
+pub struct Builder<W: Write> {
+    writer: W,
+    mtime: u64,
+}
+
+impl<W: Write> Builder<W> {
+    /// Entries get `mtime` (seconds since the Unix epoch) as modification time
+    pub fn new(writer: W, mtime: u64) -> Self {
+        Self { writer, mtime }
+    }
+
+    /// Add an entry of exactly `size` bytes read from `data`
+    pub fn append<R: Read>(&mut self, name: &str, size: u64, data: R) -> io::Result<()> {
+        self.writer.write_all(&header(name, size, self.mtime)?)?;
+        let copied = io::copy(&mut data.take(size), &mut self.writer)?;
+        if copied != size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{} changed while it was archived", name),
+            ));
+        }
+        self.writer.write_all(&[0; BLOCK][..padding(size)])
+    }
+
+    pub fn append_bytes(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.append(name, data.len() as u64, data)
+    }
+
+    /// Write the end-of-archive marker and return the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.write_all(&[0; 2 * BLOCK])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Call `f` with the name and contents of each entry of a tar stream.
+/// Whatever `f` leaves unread is skipped.
+pub fn read_entries<R: Read>(
+    mut reader: R,
+    mut f: impl FnMut(&str, &mut dyn Read) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut header = [0u8; BLOCK];
+    loop {
+        reader.read_exact(&mut header)?;
+        if header.iter().all(|&b| b == 0) {
+            return Ok(());
+        }
+        let stored: u64 = parse_octal(&header[148..156])?;
+        let mut check = header;
+        check[148..156].fill(b' ');
+        if check.iter().map(|&b| b as u64).sum::<u64>() != stored {
+            return Err(invalid("tar header checksum mismatch"));
+        }
+
+        let name_end = header[..NAME_LEN]
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(NAME_LEN);
+        let name = std::str::from_utf8(&header[..name_end])
+            .map_err(|_| invalid("entry name is not UTF-8"))?
+            .to_string();
+        let size = parse_octal(&header[124..136])?;
+
+        let mut entry = (&mut reader).take(size);
+        if matches!(header[156], b'0' | 0) {
+            f(&name, &mut entry)?;
+        }
+        io::copy(&mut entry, &mut io::sink())?;
+        if entry.limit() > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "archive ends inside an entry",
+            ));
+        }
+        io::copy(
+            &mut (&mut reader).take(padding(size) as u64),
+            &mut io::sink(),
+        )?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let big = vec![7u8; 1500];
+        let mut builder = Builder::new(Vec::new(), 1_700_000_000);
+        builder.append_bytes("manifest.json", b"{}").unwrap();
+        builder
+            .append("files/abc", big.len() as u64, &big[..])
+            .unwrap();
+        builder.append_bytes("empty", b"").unwrap();
+        let archive = builder.finish().unwrap();
+        assert_eq!(archive.len() % BLOCK, 0);
+
+        let mut entries = Vec::new();
+        read_entries(&archive[..], |name, data| {
+            // Leave the big entry unread to check that it is skipped
+            let mut contents = Vec::new();
+            if name != "files/abc" {
+                data.read_to_end(&mut contents)?;
+            }
+            entries.push((name.to_string(), contents));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                ("manifest.json".to_string(), b"{}".to_vec()),
+                ("files/abc".to_string(), Vec::new()),
+                ("empty".to_string(), Vec::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rejects_damaged_archive() {
+        let mut builder = Builder::new(Vec::new(), 0);
+        builder
+            .append_bytes("settings.yaml", b"server: {}")
+            .unwrap();
+        let mut archive = builder.finish().unwrap();
+
+        archive[3] ^= 1;
+        let result = read_entries(&archive[..], |_, _| Ok(()));
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(read_entries(&archive[..100], |_, _| Ok(())).is_err());
+        assert!(header(&"x".repeat(101), 0, 0).is_err());
+    }
+}
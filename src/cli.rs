@@ -0,0 +1,676 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use settings::Settings;
+
+use crate::config::ConfigData;
+use crate::ipc::{self, IpcRequest};
+use crate::metadata;
+use crate::models::unix_now;
+use crate::server::auth::basic;
+use crate::server::{chunks, delta, diagnostics, inhibit, migrate, power, ranges};
+use crate::service;
+use crate::shell;
+use crate::shell::bundle;
+use crate::shell::link::Link;
+
+const USAGE: &str =
+    "Usage: justrans [--portable] [--dir <dir>] [--config <file>] [--storage <dir>] [--service] [--present] [--chaos] [<command>]
+  justrans                   Start the desktop app
+  justrans share <path>...   Add files to the running instance's share
+  justrans register-shell    Install the Send To / Services menu entries
+  justrans install-service   Run the server as a Windows service, with the options given
+  justrans uninstall-service Remove the Windows service
+  justrans diagnose          Run the self-check and export a diagnostics bundle
+  justrans migrate           Recover uploads left in the old storage layout; run it
+                             while JusTrans is closed
+  justrans hash-password     Read a password from stdin and print its hash for security.basic_auth
+  justrans sync <url> <file> Send a file, skipping chunks the receiver already has
+  justrans update <url> <file>
+                             Send a new version of a file as a delta against the earlier one
+  justrans get [--parallel <n>] <file-url> [<dir>]
+                             Download a file over several connections at once
+  justrans --print-effective-config
+                             Print the settings in use, with the environment and
+                             options applied and secrets removed
+  justrans justrans://...    Open a pairing or share link
+  justrans <bundle>.justrans Download the files listed in a bundle
+
+Options:
+  --portable                 Keep settings, logs and uploads next to the executable,
+                             as does a portable.flag file there
+  --dir <dir>                Keep settings, logs and uploads in <dir> instead of the
+                             directory JusTrans was started in
+  --config <file>            Settings file to use instead of config/settings.yaml;
+                             other state is kept next to it
+  --storage <dir>            Store uploads in <dir> instead of the configured directory
+  --service                  Run the server without the desktop app, e.g. as a systemd
+                             or Windows service; SIGHUP reloads the settings
+  --present                  Start showing only the QR code and URL, full-screen,
+                             e.g. on a projector; Ctrl+P switches back
+  --chaos                    Fail uploads at random to test clients' resume and retry;
+                             only in builds with the chaos feature";
+
+/// Environment variables starting with this override the settings file,
+/// e.g. `JUSTRANS_SERVER__PORT=9000`
+const ENV_PREFIX: &str = "JUSTRANS_";
+
+/// File next to the executable that turns on portable mode
+const PORTABLE_FLAG: &str = "portable.flag";
+
+/// Storage directory given with `--storage`, which still applies after the
+/// settings file is read again
+static STORAGE: OnceLock<String> = OnceLock::new();
+
+/// Options given before the command, which take precedence over the
+/// settings file and the environment
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    /// Keep all data next to the executable
+    pub portable: bool,
+    /// Directory to keep all data in
+    pub dir: Option<PathBuf>,
+    /// Settings file to use instead of the default one
+    pub config: Option<PathBuf>,
+    /// Directory to store uploads in
+    pub storage: Option<PathBuf>,
+    /// Run headless as a service
+    pub service: bool,
+    /// Start the desktop app in present mode
+    pub present: bool,
+    /// Inject upload failures
+    pub chaos: bool,
+}
+
+impl Options {
+    /// Point the settings at the chosen file and apply the overrides. Must
+    /// run before the settings are first used.
+    ///
+    /// Settings, logs and uploads live in relative paths, so portable mode
+    /// switches to the executable's directory; paths given on the command
+    /// line still count from where JusTrans was started. Returns that
+    /// directory when running portable.
+    pub fn apply(self) -> anyhow::Result<Option<PathBuf>> {
+        let cwd = std::env::current_dir()?;
+        let exe_dir = std::env::current_exe()?
+            .parent()
+            .map(Path::to_path_buf)
+            .ok_or_else(|| anyhow::anyhow!("The executable has no parent directory"))?;
+        if self.chaos {
+            #[cfg(feature = "chaos")]
+            crate::server::chaos::enable();
+            #[cfg(not(feature = "chaos"))]
+            return Err(anyhow::anyhow!(
+                "--chaos needs a build with the chaos feature\n\n{}",
+                USAGE
+            ));
+        }
+        let portable = self.portable || exe_dir.join(PORTABLE_FLAG).exists();
+        if portable {
+            std::env::set_current_dir(&exe_dir)?;
+        }
+        if let Some(dir) = self.dir {
+            let dir = cwd.join(dir);
+            std::fs::create_dir_all(&dir)?;
+            std::env::set_current_dir(dir)?;
+        }
+
+        if let Some(config) = self.config {
+            settings::set_path(cwd.join(config))?;
+        }
+        if let Some(storage) = self.storage {
+            let _ = STORAGE.set(cwd.join(storage).display().to_string());
+        }
+        override_settings(&mut ConfigData::instance()?.lock().unwrap())?;
+        Ok(portable.then_some(exe_dir))
+    }
+}
+
+/// Storage directory given with `--storage`, as an absolute path
+#[cfg(windows)]
+pub fn storage_override() -> Option<&'static str> {
+    STORAGE.get().map(String::as_str)
+}
+
+/// Apply the overrides from the environment and then those given on the
+/// command line to `config`, e.g. after reading the settings file again
+pub fn override_settings(config: &mut ConfigData) -> anyhow::Result<()> {
+    // `env::vars` would panic on a variable that isn't valid UTF-8
+    let vars = std::env::vars_os().filter_map(|(name, value)| {
+        match (name.into_string(), value.into_string()) {
+            (Ok(name), Ok(value)) => Some((name, value)),
+            (Ok(name), Err(_)) if name.starts_with(ENV_PREFIX) => {
+                log::warn!("Ignoring {}: its value is not valid UTF-8", name);
+                None
+            }
+            _ => None,
+        }
+    });
+    settings::schema::apply_env(config, ENV_PREFIX, vars)?;
+    if let Some(storage) = STORAGE.get() {
+        config.storage.storage_dir = storage.clone();
+    }
+    Ok(())
+}
+
+/// What the process was asked to do
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Gui,
+    Share(Vec<PathBuf>),
+    RegisterShell,
+    InstallService,
+    UninstallService,
+    Diagnose,
+    Migrate,
+    HashPassword,
+    PrintConfig,
+    Sync {
+        url: String,
+        path: PathBuf,
+    },
+    Update {
+        url: String,
+        path: PathBuf,
+    },
+    Get {
+        url: String,
+        parallel: u32,
+        dir: PathBuf,
+    },
+    OpenLink(String),
+    OpenBundle(PathBuf),
+}
+
+impl Command {
+    /// The command with its relative paths resolved against `dir`, the
+    /// directory JusTrans was started in
+    pub fn relative_to(self, dir: &Path) -> Self {
+        match self {
+            Command::Share(paths) => {
+                Command::Share(paths.into_iter().map(|path| dir.join(path)).collect())
+            }
+            Command::Sync { url, path } => Command::Sync {
+                url,
+                path: dir.join(path),
+            },
+            Command::Update { url, path } => Command::Update {
+                url,
+                path: dir.join(path),
+            },
+            Command::Get {
+                url,
+                parallel,
+                dir: target,
+            } => Command::Get {
+                url,
+                parallel,
+                dir: dir.join(target),
+            },
+            Command::OpenBundle(path) => Command::OpenBundle(dir.join(path)),
+            command => command,
+        }
+    }
+}
+
+/// Split the options off the front of the command line (without the
+/// program name), leaving the command and its arguments
+pub fn parse_options(args: impl Iterator<Item = String>) -> anyhow::Result<(Options, Vec<String>)> {
+    let mut options = Options::default();
+    let mut args = args.peekable();
+    while let Some(option) = args.next_if(|arg| {
+        [
+            "--portable",
+            "--dir",
+            "--config",
+            "--storage",
+            "--service",
+            "--present",
+            "--chaos",
+        ]
+        .contains(&arg.as_str())
+    }) {
+        if option == "--portable" {
+            options.portable = true;
+            continue;
+        }
+        if option == "--service" {
+            options.service = true;
+            continue;
+        }
+        if option == "--present" {
+            options.present = true;
+            continue;
+        }
+        if option == "--chaos" {
+            options.chaos = true;
+            continue;
+        }
+        let value = args
+            .next()
+            .map(PathBuf::from)
+            .ok_or_else(|| anyhow::anyhow!("{} needs a path\n\n{}", option, USAGE))?;
+        match option.as_str() {
+            "--dir" => options.dir = Some(value),
+            "--config" => options.config = Some(value),
+            _ => options.storage = Some(value),
+        }
+    }
+    Ok((options, args.collect()))
+}
+
+/// Parse the command line (without the program name)
+pub fn parse(mut args: impl Iterator<Item = String>) -> anyhow::Result<Command> {
+    match args.next().as_deref() {
+        None => Ok(Command::Gui),
+        Some("share") => {
+            let paths: Vec<PathBuf> = args.map(PathBuf::from).collect();
+            if paths.is_empty() {
+                return Err(anyhow::anyhow!("No files given\n\n{}", USAGE));
+            }
+            Ok(Command::Share(paths))
+        }
+        Some("get") => parse_get(args),
+        Some("register-shell") => Ok(Command::RegisterShell),
+        Some("install-service") => Ok(Command::InstallService),
+        Some("uninstall-service") => Ok(Command::UninstallService),
+        Some("diagnose") => Ok(Command::Diagnose),
+        Some("migrate") => Ok(Command::Migrate),
+        Some("hash-password") => Ok(Command::HashPassword),
+        Some("--print-effective-config") => Ok(Command::PrintConfig),
+        Some("sync") => match (args.next(), args.next()) {
+            (Some(url), Some(path)) => Ok(Command::Sync {
+                url,
+                path: PathBuf::from(path),
+            }),
+            _ => Err(anyhow::anyhow!("Expected a URL and a file\n\n{}", USAGE)),
+        },
+        Some("update") => match (args.next(), args.next()) {
+            (Some(url), Some(path)) => Ok(Command::Update {
+                url,
+                path: PathBuf::from(path),
+            }),
+            _ => Err(anyhow::anyhow!("Expected a URL and a file\n\n{}", USAGE)),
+        },
+        Some(link) if Link::is_link(link) => Ok(Command::OpenLink(link.to_string())),
+        Some(other) => match bundle::bundle_path(other) {
+            Some(path) => Ok(Command::OpenBundle(path)),
+            None => Err(anyhow::anyhow!("Unknown command '{}'\n\n{}", other, USAGE)),
+        },
+    }
+}
+
+fn parse_get(mut args: impl Iterator<Item = String>) -> anyhow::Result<Command> {
+    let mut parallel = 1;
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--parallel" {
+            parallel = args
+                .next()
+                .and_then(|n| n.parse().ok())
+                .filter(|n| *n > 0)
+                .ok_or_else(|| anyhow::anyhow!("--parallel needs a positive number"))?;
+        } else {
+            positional.push(arg);
+        }
+    }
+    let mut positional = positional.into_iter();
+    match (positional.next(), positional.next(), positional.next()) {
+        (Some(url), dir, None) => Ok(Command::Get {
+            url,
+            parallel,
+            dir: dir.map(PathBuf::from).unwrap_or_else(|| PathBuf::from(".")),
+        }),
+        _ => Err(anyhow::anyhow!("Expected a file URL\n\n{}", USAGE)),
+    }
+}
+
+/// Run a non-GUI command to completion
+pub fn run(command: Command) -> anyhow::Result<()> {
+    match command {
+        Command::Gui | Command::OpenLink(_) | Command::OpenBundle(_) => Ok(()),
+        Command::Share(paths) => {
+            // The running instance has its own working directory
+            let paths = paths
+                .iter()
+                .map(|p| {
+                    p.canonicalize()
+                        .map_err(|e| anyhow::anyhow!("{}: {}", p.display(), e))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            let runtime = tokio::runtime::Runtime::new()?;
+            let response = runtime.block_on(ipc::send(&IpcRequest::Share { paths }))?;
+            if response.ok {
+                println!("{}", response.message);
+                Ok(())
+            } else {
+                Err(anyhow::anyhow!(response.message))
+            }
+        }
+        Command::RegisterShell => {
+            for path in shell::register::register()? {
+                println!("Installed {}", path.display());
+            }
+            Ok(())
+        }
+        Command::InstallService => {
+            service::install()?;
+            println!("Installed the {} service", service::SERVICE_NAME);
+            Ok(())
+        }
+        Command::UninstallService => {
+            service::uninstall()?;
+            println!("Removed the {} service", service::SERVICE_NAME);
+            Ok(())
+        }
+        Command::Diagnose => {
+            let instance = ConfigData::instance()?;
+            let config = instance.lock().unwrap();
+            let report = diagnostics::run_self_check(&config, false);
+            println!("{}", report.summary());
+
+            let path = diagnostics::export_bundle(
+                &report,
+                &config,
+                Path::new(diagnostics::LOG_DIR),
+                Path::new(diagnostics::BUNDLE_DIR),
+            )?;
+            println!("\nDiagnostics bundle written to {}", path.display());
+            Ok(())
+        }
+        Command::Migrate => {
+            // A running instance may be writing to the blobs being moved
+            let runtime = tokio::runtime::Runtime::new()?;
+            if runtime.block_on(ipc::is_running()) {
+                return Err(anyhow::anyhow!(
+                    "JusTrans is running; close it first, it migrates its storage when it starts"
+                ));
+            }
+            let (storage_dir, backend) = {
+                let instance = ConfigData::instance()?;
+                let config = instance.lock().unwrap();
+                (
+                    PathBuf::from(&config.storage.storage_dir),
+                    config.storage.metadata,
+                )
+            };
+            let store = metadata::open(backend, None);
+            let migration = migrate::migrate(&storage_dir, store.as_ref(), unix_now())?;
+            println!("{}", migration);
+            if !migration.recovered.is_empty() {
+                println!(
+                    "\nRecovered files are quarantined in {} until released",
+                    storage_dir.join(migrate::RECOVERED_DIR).display()
+                );
+            }
+            Ok(())
+        }
+        Command::HashPassword => {
+            eprintln!("Password:");
+            let mut password = String::new();
+            std::io::stdin().read_line(&mut password)?;
+            let password = password.trim_end_matches(['\r', '\n']);
+            if password.is_empty() {
+                return Err(anyhow::anyhow!("No password given"));
+            }
+            println!("{}", basic::hash_password(password)?);
+            Ok(())
+        }
+        Command::PrintConfig => {
+            let instance = ConfigData::instance()?;
+            let config = instance.lock().unwrap();
+            let redacted = settings::schema::redacted(&*config, diagnostics::SECRET_NAMES)?;
+            print!("{}", serde_yaml::to_string(&redacted)?);
+            Ok(())
+        }
+        Command::Sync { url, path } => {
+            warn_on_battery(&path);
+            let _awake = inhibit::keep_awake("Sending a file");
+            let runtime = tokio::runtime::Runtime::new()?;
+            let report = runtime.block_on(chunks::push(&url, &path))?;
+            println!(
+                "Sent {} of {} chunks ({} of {})",
+                report.sent_chunks,
+                report.chunks,
+                humanize::size(report.sent_bytes),
+                humanize::size(report.size)
+            );
+            Ok(())
+        }
+        Command::Update { url, path } => {
+            warn_on_battery(&path);
+            let _awake = inhibit::keep_awake("Sending a file");
+            let runtime = tokio::runtime::Runtime::new()?;
+            match runtime.block_on(delta::update(&url, &path))? {
+                Some(report) => println!(
+                    "Sent {} for {} ({} unchanged blocks, {} changed)",
+                    humanize::size(report.sent_bytes),
+                    humanize::size(report.size),
+                    report.stats.copied_blocks,
+                    humanize::size(report.stats.literal_bytes)
+                ),
+                // Nothing to diff against, send the whole file
+                None => {
+                    let report = runtime.block_on(chunks::push(&url, &path))?;
+                    println!("Sent {}", humanize::size(report.sent_bytes));
+                }
+            }
+            Ok(())
+        }
+        Command::Get { url, parallel, dir } => {
+            let _awake = inhibit::keep_awake("Downloading a file");
+            let runtime = tokio::runtime::Runtime::new()?;
+            let (path, parts) = runtime.block_on(ranges::download(&url, parallel, &dir))?;
+            println!("Downloaded {} in {} parts", path.display(), parts);
+            Ok(())
+        }
+    }
+}
+
+/// Warn before sending a large file while running on battery
+fn warn_on_battery(path: &Path) {
+    let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if let Some(warning) = power::large_transfer_warning(size) {
+        eprintln!("{}", warning);
+    }
+}
+
+/// Ask an already running instance to show its window.
+///
+/// Returns `true` when one answered, in which case this process should exit.
+pub fn activate_running_instance() -> anyhow::Result<bool> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    match runtime.block_on(ipc::send(&IpcRequest::Activate)) {
+        Ok(response) => {
+            println!("{}", response.message);
+            Ok(true)
+        }
+        Err(_) => Ok(false),
+    }
+}
+
+/// Hand a link to the running instance.
+///
+/// Returns `false` when no instance is running, in which case the caller
+/// starts the GUI and opens the link itself.
+pub fn forward_link(link: &str) -> anyhow::Result<bool> {
+    Link::parse(link)?;
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let request = IpcRequest::OpenLink {
+        url: link.to_string(),
+    };
+    match runtime.block_on(ipc::send(&request)) {
+        Ok(response) if response.ok => {
+            println!("{}", response.message);
+            Ok(true)
+        }
+        Ok(response) => Err(anyhow::anyhow!(response.message)),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Hand a `.justrans` bundle to the running instance.
+///
+/// Returns `false` when no instance is running, in which case the caller
+/// starts the GUI and opens the bundle itself.
+pub fn forward_bundle(path: &Path) -> anyhow::Result<bool> {
+    // The running instance has its own working directory
+    let path = path
+        .canonicalize()
+        .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    match runtime.block_on(ipc::send(&IpcRequest::OpenBundle { path })) {
+        Ok(response) if response.ok => {
+            println!("{}", response.message);
+            Ok(true)
+        }
+        Ok(response) => Err(anyhow::anyhow!(response.message)),
+        Err(_) => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> impl Iterator<Item = String> {
+        list.iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(args(&[])).unwrap(), Command::Gui);
+        assert_eq!(
+            parse(args(&["share", "a.txt", "b.txt"])).unwrap(),
+            Command::Share(vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")])
+        );
+        assert_eq!(
+            parse(args(&["register-shell"])).unwrap(),
+            Command::RegisterShell
+        );
+        assert_eq!(
+            parse(args(&["install-service"])).unwrap(),
+            Command::InstallService
+        );
+        assert_eq!(parse(args(&["diagnose"])).unwrap(), Command::Diagnose);
+        assert_eq!(parse(args(&["migrate"])).unwrap(), Command::Migrate);
+        assert_eq!(
+            parse(args(&["hash-password"])).unwrap(),
+            Command::HashPassword
+        );
+        assert_eq!(
+            parse(args(&["--print-effective-config"])).unwrap(),
+            Command::PrintConfig
+        );
+        assert_eq!(
+            parse(args(&["sync", "http://10.0.0.2:8080", "disk.img"])).unwrap(),
+            Command::Sync {
+                url: "http://10.0.0.2:8080".to_string(),
+                path: PathBuf::from("disk.img"),
+            }
+        );
+        assert!(parse(args(&["sync", "http://10.0.0.2:8080"])).is_err());
+        assert_eq!(
+            parse(args(&["update", "http://10.0.0.2:8080", "backup.tar"])).unwrap(),
+            Command::Update {
+                url: "http://10.0.0.2:8080".to_string(),
+                path: PathBuf::from("backup.tar"),
+            }
+        );
+        assert_eq!(
+            parse(args(&["justrans://pair?host=10.0.0.2"])).unwrap(),
+            Command::OpenLink("justrans://pair?host=10.0.0.2".to_string())
+        );
+        assert_eq!(
+            parse(args(&["photos.justrans"])).unwrap(),
+            Command::OpenBundle(PathBuf::from("photos.justrans"))
+        );
+        assert_eq!(
+            parse(args(&[
+                "get",
+                "--parallel",
+                "4",
+                "http://10.0.0.2:8080/api/v1/files/abc"
+            ]))
+            .unwrap(),
+            Command::Get {
+                url: "http://10.0.0.2:8080/api/v1/files/abc".to_string(),
+                parallel: 4,
+                dir: PathBuf::from("."),
+            }
+        );
+        assert!(parse(args(&["get", "--parallel", "0", "http://10.0.0.2:8080"])).is_err());
+        assert!(parse(args(&["share"])).is_err());
+        assert!(parse(args(&["bogus"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let (options, rest) = parse_options(args(&[
+            "--portable",
+            "--dir",
+            "E:\\justrans",
+            "--config",
+            "E:\\justrans\\settings.yaml",
+            "--storage",
+            "E:\\uploads",
+            "--service",
+            "--present",
+            "--chaos",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                portable: true,
+                dir: Some(PathBuf::from("E:\\justrans")),
+                config: Some(PathBuf::from("E:\\justrans\\settings.yaml")),
+                storage: Some(PathBuf::from("E:\\uploads")),
+                service: true,
+                present: true,
+                chaos: true,
+            }
+        );
+        assert!(rest.is_empty());
+
+        // Options only count before the command
+        let (options, rest) =
+            parse_options(args(&["--storage", "/tmp/a", "share", "--config"])).unwrap();
+        assert_eq!(options.storage, Some(PathBuf::from("/tmp/a")));
+        assert_eq!(options.config, None);
+        assert_eq!(rest, vec!["share", "--config"]);
+
+        assert!(parse_options(args(&["--config"])).is_err());
+    }
+
+    #[test]
+    fn test_relative_to() {
+        let dir = Path::new("/home/me");
+        assert_eq!(
+            parse(args(&["share", "a.txt", "/tmp/b.txt"]))
+                .unwrap()
+                .relative_to(dir),
+            Command::Share(vec![
+                PathBuf::from("/home/me/a.txt"),
+                PathBuf::from("/tmp/b.txt")
+            ])
+        );
+        assert_eq!(
+            parse(args(&["get", "http://10.0.0.2:8080/api/v1/files/abc"]))
+                .unwrap()
+                .relative_to(dir),
+            Command::Get {
+                url: "http://10.0.0.2:8080/api/v1/files/abc".to_string(),
+                parallel: 1,
+                dir: PathBuf::from("/home/me/."),
+            }
+        );
+        assert_eq!(Command::Gui.relative_to(dir), Command::Gui);
+    }
+}
@@ -1,49 +1,783 @@
 use serde::{Deserialize, Serialize};
-use settings::Settings;
+use settings::{Settings, SettingsSchema};
 
 /// Application configuration data
-/// This struct will be serialized/deserialized to/from YAML
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Settings)]
+/// This struct will be serialized/deserialized to/from YAML.
+/// Fields marked with `#[setting(...)]` appear in the settings panel.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Settings, SettingsSchema)]
 pub struct ConfigData {
     /// Server configuration
     #[serde(default)]
+    #[setting(section = "Server Configuration")]
     pub server: ServerConfig,
 
     /// Display configuration
     #[serde(default)]
+    #[setting(section = "Display Configuration")]
     pub display: DisplayConfig,
 
     /// File storage configuration
     #[serde(default)]
+    #[setting(section = "Storage Configuration")]
     pub storage: StorageConfig,
+
+    /// Times the default server runs on its own
+    #[serde(default)]
+    #[setting(section = "Schedule")]
+    pub schedule: ScheduleConfig,
+
+    /// Behavior while running on battery
+    #[serde(default)]
+    #[setting(section = "Power")]
+    pub power: PowerConfig,
+
+    /// Downloads from other JusTrans instances
+    #[serde(default)]
+    #[setting(section = "Downloads")]
+    pub downloads: DownloadsConfig,
+
+    /// Additional named server profiles that can run alongside the default server
+    #[serde(default)]
+    pub profiles: Vec<ProfileConfig>,
+
+    /// Overrides for the default server, applied at start when connected to
+    /// a matching Wi-Fi network
+    #[serde(default)]
+    pub networks: Vec<NetworkOverlayConfig>,
+
+    /// Quotas and retention for uploads from matching devices, on top of the
+    /// storage settings
+    #[serde(default)]
+    pub devices: Vec<DeviceRuleConfig>,
+
+    /// Other instances paired through `justrans://pair` links, whose files
+    /// can be browsed from the Transfers panel
+    #[serde(default)]
+    pub peers: Vec<PeerConfig>,
+
+    /// External integrations notified about server events
+    #[serde(default)]
+    pub integrations: IntegrationsConfig,
+
+    /// Portal sign-in for shared office machines
+    #[serde(default)]
+    pub security: SecurityConfig,
+
+    /// Opt-in anonymous usage statistics (requires the `telemetry` build feature)
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
 }
 
 /// Server configuration options
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, SettingsSchema)]
 pub struct ServerConfig {
     /// Port for the server to listen on
     #[serde(default = "default_port")]
+    #[setting(
+        label = "Server Port",
+        min = 1024,
+        max = 65535,
+        help = "Choose a port between 1024-65535. Default is 8080."
+    )]
     pub port: u16,
 
     /// Upload chunk size in megabytes
     #[serde(default = "default_upload_chunk_size_mb")]
+    #[setting(
+        label = "Upload Chunk Size (MB)",
+        min = 1,
+        max = 1024,
+        help = "Size of file chunks during upload. Larger values may improve performance for big files."
+    )]
     pub upload_chunk_size_mb: u64,
+
+    /// Most chunks one uploaded file may be split into; with the chunk size
+    /// this caps the size of files uploaded from the portal
+    #[serde(default = "default_max_upload_segments")]
+    #[setting(
+        label = "Maximum Upload Chunks",
+        min = 1,
+        max = 1000000,
+        help = "Uploads split into more chunks than this are refused. With 5 MB chunks, 10000 allows files up to about 50 GB."
+    )]
+    pub max_upload_segments: u32,
+
+    /// Seconds to hold an upload whose name is already taken while waiting
+    /// for the user to review it; both files are kept afterwards
+    #[serde(default = "default_conflict_timeout_secs")]
+    #[setting(label = "Duplicate Review Timeout (s)", min = 5, max = 3600)]
+    pub conflict_timeout_secs: u64,
+
+    /// Addresses to listen on, e.g. the LAN, Tailscale and loopback
+    /// addresses; empty listens on all interfaces
+    #[serde(default)]
+    pub bind_addresses: Vec<String>,
+
+    /// Accept HTTP/2 connections, which send many requests over one connection
+    #[serde(default = "default_http2")]
+    #[setting(
+        label = "HTTP/2",
+        help = "Let clients send many requests over one connection. Browsers only use HTTP/2 over HTTPS."
+    )]
+    pub http2: bool,
+
+    /// Seconds an idle connection is kept open for further requests; 0
+    /// closes connections after each request
+    #[serde(default = "default_keep_alive_secs")]
+    #[setting(label = "Keep-Alive Timeout (s)", min = 0, max = 3600)]
+    pub keep_alive_secs: u64,
+
+    /// Requests a client may have in flight on one HTTP/2 connection
+    #[serde(default = "default_max_concurrent_streams")]
+    #[setting(label = "HTTP/2 Concurrent Streams", min = 1, max = 1000)]
+    pub max_concurrent_streams: u32,
+
+    /// Offer block checksums of shared files so clients can upload a new
+    /// version as a delta against the old one
+    #[serde(default = "default_delta_uploads")]
+    #[setting(
+        label = "Delta Uploads",
+        help = "Let clients re-send a changed file by uploading only the blocks that differ."
+    )]
+    pub delta_uploads: bool,
+
+    /// Most ranged connections a client is told to open for one download
+    #[serde(default = "default_max_download_connections")]
+    #[setting(
+        label = "Parallel Download Connections",
+        min = 1,
+        max = 32,
+        help = "Connections a client may use for one file. More connections help over high-latency Wi-Fi."
+    )]
+    pub max_download_connections: u32,
+
+    /// Memory transfers may hold in buffers at once, in megabytes; more
+    /// uploads wait until some finish
+    #[serde(default = "default_transfer_memory_mb")]
+    #[setting(
+        label = "Transfer Memory (MB)",
+        min = 16,
+        max = 8192,
+        help = "Memory uploads and downloads may buffer at once. Transfers beyond it wait for others to finish."
+    )]
+    pub transfer_memory_mb: u64,
+
+    /// Reverse proxies, as addresses or networks, whose `Forwarded` and
+    /// `X-Forwarded-For` headers name the real client
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+
+    /// Fetch the title and icon of links shared as text, for a preview
+    #[serde(default)]
+    #[setting(
+        label = "Link Previews",
+        help = "Show the title and icon of links shared as text. The server fetches them from the linked site."
+    )]
+    pub link_previews: bool,
+
+    /// Serve the portal over HTTPS, e.g. with a self-signed certificate
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+
+    /// Whether the first-start firewall check has already been performed
+    #[serde(default)]
+    pub firewall_checked: bool,
 }
 
-/// Display configuration options
+/// HTTPS options of the portal
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TlsConfig {
+    /// Whether the portal is served over HTTPS
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// PEM file with the certificate, followed by any intermediate ones
+    pub cert_path: String,
+
+    /// PEM file with the certificate's private key
+    pub key_path: String,
+
+    /// Port of a plain-HTTP page that explains the browsers' warning about a
+    /// self-signed certificate and offers it for trusting; 0 turns it off
+    #[serde(default = "default_trust_page_port")]
+    pub trust_page_port: u16,
+}
+
+/// Display configuration options
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, SettingsSchema)]
 pub struct DisplayConfig {
     /// Default theme (light or dark)
     #[serde(default = "default_theme")]
+    #[setting(
+        label = "Theme",
+        choices("light", "dark"),
+        help = "Choose between light and dark theme for the application."
+    )]
     pub theme: String,
+
+    /// What the desktop app does with received files, by kind; the first
+    /// rule matching a file decides and files no rule matches are left alone
+    #[serde(default)]
+    pub auto_open: Vec<AutoOpenRuleConfig>,
 }
 
-/// File storage configuration
+/// What to do with received files of some kinds
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AutoOpenRuleConfig {
+    /// Extensions or MIME classes of the files, e.g. `image/*` or `pdf`
+    pub files: Vec<String>,
+
+    pub action: AutoOpenAction,
+}
+
+/// What is done with a received file
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoOpenAction {
+    /// Open it in its default app and report it in the status bar
+    Open,
+    /// Only report it in the status bar
+    Notify,
+    /// Leave it alone, e.g. for installers that should never be opened
+    Never,
+}
+
+/// File storage configuration
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, SettingsSchema)]
 pub struct StorageConfig {
     /// Directory to store uploaded files
     #[serde(default = "default_storage_dir")]
+    #[setting(
+        label = "Storage Directory",
+        required,
+        help = "Directory where uploaded files will be stored."
+    )]
+    pub storage_dir: String,
+
+    /// Directory uploads are written to until complete, then moved into
+    /// the storage directory; `.incoming` in the storage directory when
+    /// empty
+    #[serde(default)]
+    #[setting(
+        label = "Temporary Directory",
+        help = "Where files are written while they are received, e.g. on a faster disk. They are moved into the storage directory once complete. Empty uses a hidden folder in the storage directory."
+    )]
+    pub temp_dir: String,
+
+    /// Keep received photos and videos in `YYYY/MM` folders by capture date
+    #[serde(default)]
+    #[setting(label = "Organize Photos and Videos by Date")]
+    pub organize_media_by_date: bool,
+
+    /// Hours between re-hashing received files to detect damage on disk;
+    /// 0 turns scrubbing off
+    #[serde(default = "default_scrub_interval_hours")]
+    #[setting(
+        label = "Integrity Check Interval (h)",
+        min = 0,
+        max = 720,
+        help = "How often received files are re-read and compared with their checksum. 0 turns the check off."
+    )]
+    pub scrub_interval_hours: u64,
+
+    /// Treat received names differing only in case as the same file when
+    /// looking for name conflicts
+    #[serde(default)]
+    #[setting(
+        label = "Match Names Ignoring Case",
+        help = "Review an upload named Photo.JPG as a conflict with photo.jpg, as Windows and macOS disks would."
+    )]
+    pub case_insensitive_names: bool,
+
+    /// Permission bits received files are stored with
+    #[serde(default)]
+    pub permissions: PermissionsConfig,
+
+    /// Where records of files, devices, events and one-time links are kept
+    #[serde(default)]
+    pub metadata: MetadataBackend,
+
+    /// Scheduled copies of those records and of the settings file
+    #[serde(default)]
+    pub snapshots: SnapshotConfig,
+}
+
+/// Permissions of received files on Unix; ignored elsewhere
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct PermissionsConfig {
+    /// Bits every stored file gets, in octal, e.g. "640"; the umask of the
+    /// app decides when empty. Executable bits here are ignored.
+    #[serde(default)]
+    pub mode: String,
+
+    /// What happens to the executable bits a sender declares
+    #[serde(default)]
+    pub executable: ExecutableBits,
+}
+
+/// Executable bits of received files
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecutableBits {
+    /// Files are never stored executable
+    #[default]
+    Strip,
+    /// Files the sender declared executable stay so, for whoever may read
+    /// them; quarantined files get them once released
+    Keep,
+}
+
+/// Where the records of a server are kept
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataBackend {
+    /// In memory, gone when the app quits
+    #[default]
+    Memory,
+    /// In an SQLite database next to the settings file, kept between runs;
+    /// needs a build with the `sqlite` feature
+    Sqlite,
+}
+
+/// Snapshots of the metadata records and the settings, taken while the
+/// default server runs
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SnapshotConfig {
+    /// Hours between snapshots; 0 turns them off
+    #[serde(default = "default_snapshot_interval_hours")]
+    pub interval_hours: u64,
+
+    /// Snapshots kept, the oldest removed first
+    #[serde(default = "default_snapshots_kept")]
+    pub keep: usize,
+}
+
+/// Availability window of the default server, e.g. weekdays 9 to 18 on a
+/// shared office machine
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, SettingsSchema)]
+pub struct ScheduleConfig {
+    /// Start and stop the default server at the times below
+    #[serde(default)]
+    #[setting(
+        label = "Run on a Schedule",
+        help = "Start the server at the start time and stop it at the stop time on the chosen days."
+    )]
+    pub enabled: bool,
+
+    /// Days the window opens, e.g. "mon-fri" or "mon,wed,sat"; every day
+    /// if empty
+    #[serde(default = "default_schedule_days")]
+    #[setting(
+        label = "Days",
+        help = "Ranges or lists of mon, tue, wed, thu, fri, sat, sun, e.g. mon-fri. Empty means every day."
+    )]
+    pub days: String,
+
+    /// Local time the server starts, as HH:MM
+    #[serde(default = "default_schedule_start")]
+    #[setting(label = "Start Time", help = "24-hour time, e.g. 09:00.")]
+    pub start: String,
+
+    /// Local time the server stops, as HH:MM; before `start` for windows
+    /// that run past midnight
+    #[serde(default = "default_schedule_stop")]
+    #[setting(
+        label = "Stop Time",
+        help = "24-hour time, e.g. 18:00. A time before the start runs the server overnight."
+    )]
+    pub stop: String,
+}
+
+/// Battery-aware behavior on laptops
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, SettingsSchema)]
+pub struct PowerConfig {
+    /// Postpone integrity checks and retention cleanup until mains power is
+    /// back
+    #[serde(default = "default_defer_background_tasks")]
+    #[setting(
+        label = "Postpone Background Tasks on Battery",
+        help = "Integrity checks and retention cleanup wait until the laptop is plugged in."
+    )]
+    pub defer_background_tasks: bool,
+
+    /// Warn before sending or downloading files of at least this many
+    /// megabytes on battery; 0 turns the warning off
+    #[serde(default = "default_large_transfer_warning_mb")]
+    #[setting(
+        label = "Large Transfer Warning on Battery (MB)",
+        min = 0,
+        max = 1048576,
+        help = "Command-line transfers of files at least this large warn when running on battery. 0 turns the warning off."
+    )]
+    pub large_transfer_warning_mb: u64,
+
+    /// Keep the computer from sleeping while uploads or downloads are in
+    /// flight
+    #[serde(default = "default_prevent_sleep_during_transfers")]
+    #[setting(
+        label = "Stay Awake During Transfers",
+        help = "Keep the computer from going to sleep while files are being sent or received."
+    )]
+    pub prevent_sleep_during_transfers: bool,
+}
+
+/// Where and how files from other instances are downloaded
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, SettingsSchema)]
+pub struct DownloadsConfig {
+    /// Folder downloads are saved to
+    #[serde(default = "default_download_dir")]
+    #[setting(
+        label = "Download Folder",
+        help = "Folder files from other JusTrans instances and .justrans bundles are saved to."
+    )]
+    pub directory: String,
+
+    /// How often a failed download is attempted again before giving up
+    #[serde(default = "default_download_retries")]
+    #[setting(
+        label = "Automatic Retries",
+        min = 0,
+        max = 20,
+        help = "How often an interrupted download is resumed before it is marked as failed."
+    )]
+    pub retries: u32,
+}
+
+/// Another JusTrans instance files can be downloaded from
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PeerConfig {
+    /// Display name, `host:port` for paired instances
+    pub name: String,
+
+    /// Address of its portal, e.g. "http://192.168.1.5:8080"
+    pub url: String,
+
+    /// PIN of its portal, if it has one
+    #[serde(default)]
+    pub pin: Option<String>,
+
+    /// Identity fingerprint pinned when pairing, which follows the instance
+    /// to a new address
+    #[serde(default)]
+    pub identity: Option<String>,
+}
+
+/// A named server profile with its own port, storage and access settings
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProfileConfig {
+    /// Display name, also used to identify the profile
+    pub name: String,
+
+    /// Port for this profile's server
+    pub port: u16,
+
+    /// Directory to store files uploaded to this profile
+    #[serde(default = "default_storage_dir")]
     pub storage_dir: String,
+
+    /// Reject uploads and only serve existing files
+    #[serde(default)]
+    pub read_only: bool,
+
+    /// PIN required to use this profile's portal; open access if empty
+    #[serde(default)]
+    pub pin: Option<String>,
+}
+
+/// Settings that replace the defaults while connected to a given Wi-Fi network
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NetworkOverlayConfig {
+    /// Display name, shown while the overlay is active
+    pub name: String,
+
+    /// SSID of the Wi-Fi network this overlay applies to
+    pub ssid: String,
+
+    /// Port for the server to listen on
+    #[serde(default)]
+    pub port: Option<u16>,
+
+    /// Directory to store uploaded files
+    #[serde(default)]
+    pub storage_dir: Option<String>,
+
+    /// Reject uploads and only serve existing files
+    #[serde(default)]
+    pub read_only: Option<bool>,
+
+    /// PIN required to use the main portal; open access if empty
+    #[serde(default)]
+    pub pin: Option<String>,
+}
+
+/// Limits for uploads from a group of devices, matched by address
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DeviceRuleConfig {
+    /// Display name of the group, e.g. "guests"
+    pub name: String,
+
+    /// Addresses or networks of the devices, e.g. "192.168.50.0/24"
+    pub addresses: Vec<String>,
+
+    /// Megabytes each device may have stored at once
+    #[serde(default)]
+    pub quota_mb: Option<u64>,
+
+    /// Hours after which files from these devices are deleted
+    #[serde(default)]
+    pub retention_hours: Option<u64>,
+}
+
+/// External integrations configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct IntegrationsConfig {
+    /// Webhooks that receive a signed JSON POST for server events
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+
+    /// MQTT broker receiving status and event messages
+    #[serde(default)]
+    pub mqtt: Option<MqttConfig>,
+
+    /// Local gRPC control API (requires the `grpc` build feature)
+    #[serde(default)]
+    pub grpc: Option<GrpcConfig>,
+}
+
+/// Access control for the portal
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SecurityConfig {
+    /// Require sign-in through an OpenID Connect provider (company SSO)
+    #[serde(default)]
+    pub oidc: Option<OidcConfig>,
+
+    /// Ask browsers for a username and password before serving anything
+    #[serde(default)]
+    pub basic_auth: Option<BasicAuthConfig>,
+
+    /// Only admit browsers that scanned the QR code recently
+    #[serde(default)]
+    pub qr_token: Option<QrTokenConfig>,
+
+    /// Security headers sent with every response
+    #[serde(default)]
+    pub headers: SecurityHeadersConfig,
+
+    /// Only accept uploads of these extensions or MIME classes
+    /// (e.g. `jpg`, `image/*`); empty accepts every kind
+    #[serde(default)]
+    pub allowed_extensions: Vec<String>,
+
+    /// Never accept uploads of these extensions or MIME classes
+    #[serde(default)]
+    pub blocked_extensions: Vec<String>,
+
+    /// Which links among files shared in place are served
+    #[serde(default)]
+    pub shared_links: LinkPolicy,
+}
+
+/// How links are treated when sharing files in place from the desktop
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkPolicy {
+    /// Serve whatever a link points to
+    Follow,
+    /// Serve links that point into their own folder, refuse the others
+    #[default]
+    Within,
+    /// Never serve links
+    Deny,
+}
+
+/// Security headers options; an empty value leaves that header out
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SecurityHeadersConfig {
+    /// Content-Security-Policy of the portal; loosen it when customizing
+    /// the portal with scripts or images from elsewhere
+    #[serde(default = "default_content_security_policy")]
+    pub content_security_policy: String,
+
+    /// Sources allowed to embed the portal in a frame, added to the
+    /// Content-Security-Policy unless it has its own `frame-ancestors`
+    #[serde(default = "default_frame_ancestors")]
+    pub frame_ancestors: String,
+
+    #[serde(default = "default_referrer_policy")]
+    pub referrer_policy: String,
+}
+
+/// HTTP Basic Auth options
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BasicAuthConfig {
+    /// Whether the portal requires the credentials
+    #[serde(default)]
+    pub enabled: bool,
+
+    pub username: String,
+
+    /// Argon2 hash of the password in PHC format, as printed by
+    /// `justrans hash-password`
+    pub password_hash: String,
+}
+
+/// Options of the access token in the QR code
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QrTokenConfig {
+    /// Whether the portal requires a token from the QR code
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Minutes until the QR code shows a new token
+    #[serde(default = "default_qr_token_rotate_minutes")]
+    pub rotate_minutes: u64,
+
+    /// Hours a browser stays admitted after scanning
+    #[serde(default = "default_session_hours")]
+    pub session_hours: u64,
+}
+
+/// OpenID Connect sign-in options
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OidcConfig {
+    /// Whether the portal requires sign-in
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Issuer URL; the provider is discovered from
+    /// `<issuer>/.well-known/openid-configuration`
+    pub issuer: String,
+
+    /// Client identifier registered with the provider
+    pub client_id: String,
+
+    /// Client secret registered with the provider
+    #[serde(default)]
+    pub client_secret: String,
+
+    /// Callback URL registered with the provider; derived from the address
+    /// the browser used if empty
+    #[serde(default)]
+    pub redirect_url: String,
+
+    /// Scopes requested at sign-in
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+
+    /// Emails or groups allowed to upload and share; everyone else who signs
+    /// in can only download. Everyone may upload if empty.
+    #[serde(default)]
+    pub uploaders: Vec<String>,
+
+    /// Emails or groups managing the share, who may also upload and watch
+    /// the server's event log on the portal
+    #[serde(default)]
+    pub hosts: Vec<String>,
+
+    /// Hours a sign-in stays valid
+    #[serde(default = "default_session_hours")]
+    pub session_hours: u64,
+}
+
+/// Anonymous usage statistics options
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct TelemetryConfig {
+    /// Whether counters are collected and sent; off unless the user opts in
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// URL receiving the weekly report as a JSON POST; nothing is sent if empty
+    #[serde(default)]
+    pub endpoint: String,
+}
+
+/// Local gRPC control API options
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct GrpcConfig {
+    /// Whether to start the control API
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Port on 127.0.0.1
+    #[serde(default = "default_grpc_port")]
+    pub port: u16,
+}
+
+/// MQTT publishing options
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MqttConfig {
+    /// Whether to connect to the broker
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Broker host name or IP address
+    pub host: String,
+
+    /// Broker port
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+
+    /// Prefix for the `status` and `events` topics
+    #[serde(default = "default_mqtt_topic_prefix")]
+    pub topic_prefix: String,
+
+    /// Client identifier presented to the broker
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+
+    /// Optional username; no authentication if empty
+    #[serde(default)]
+    pub username: String,
+
+    /// Password used together with `username`
+    #[serde(default)]
+    pub password: String,
+}
+
+/// A single webhook endpoint
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WebhookConfig {
+    /// URL receiving the POST request
+    pub url: String,
+
+    /// Secret used to sign the request body (HMAC-SHA256); unsigned if empty
+    #[serde(default)]
+    pub secret: String,
+
+    /// Event names to deliver (e.g. "file.received"); all events if empty
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+impl WebhookConfig {
+    /// Whether this webhook is subscribed to the given event
+    pub fn accepts(&self, event: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|e| e == event)
+    }
+}
+
+impl ServerConfig {
+    /// HTTPS options, if HTTPS is turned on
+    pub fn tls(&self) -> Option<&TlsConfig> {
+        self.tls.as_ref().filter(|tls| tls.enabled)
+    }
+}
+
+impl ConfigData {
+    /// Look up a server profile by name
+    pub fn profile(&self, name: &str) -> Option<&ProfileConfig> {
+        self.profiles.iter().find(|p| p.name == name)
+    }
+
+    /// Look up a paired instance by name
+    pub fn peer(&self, name: &str) -> Option<&PeerConfig> {
+        self.peers.iter().find(|p| p.name == name)
+    }
+
+    /// Overlay configured for the given Wi-Fi network, if any
+    pub fn network_overlay(&self, ssid: &str) -> Option<&NetworkOverlayConfig> {
+        self.networks.iter().find(|n| n.ssid == ssid)
+    }
 }
 
 // Default function implementations
@@ -51,10 +785,138 @@ fn default_port() -> u16 {
     8080
 }
 
+fn default_trust_page_port() -> u16 {
+    8088
+}
+
 fn default_upload_chunk_size_mb() -> u64 {
     5
 }
 
+fn default_max_upload_segments() -> u32 {
+    10_000
+}
+
+fn default_conflict_timeout_secs() -> u64 {
+    120
+}
+
+fn default_http2() -> bool {
+    true
+}
+
+fn default_scrub_interval_hours() -> u64 {
+    24
+}
+
+fn default_snapshot_interval_hours() -> u64 {
+    24
+}
+
+fn default_snapshots_kept() -> usize {
+    7
+}
+
+fn default_delta_uploads() -> bool {
+    true
+}
+
+fn default_max_download_connections() -> u32 {
+    8
+}
+
+fn default_transfer_memory_mb() -> u64 {
+    256
+}
+
+fn default_keep_alive_secs() -> u64 {
+    75
+}
+
+fn default_max_concurrent_streams() -> u32 {
+    250
+}
+
+fn default_grpc_port() -> u16 {
+    50051
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_topic_prefix() -> String {
+    "justrans".to_string()
+}
+
+fn default_mqtt_client_id() -> String {
+    "justrans".to_string()
+}
+
+fn default_oidc_scopes() -> Vec<String> {
+    vec![
+        "openid".to_string(),
+        "email".to_string(),
+        "profile".to_string(),
+    ]
+}
+
+fn default_session_hours() -> u64 {
+    8
+}
+
+fn default_qr_token_rotate_minutes() -> u64 {
+    5
+}
+
+fn default_content_security_policy() -> String {
+    // Highlighted code snippets carry inline styles
+    "default-src 'self'; script-src 'self'; style-src 'self' 'unsafe-inline'; \
+     img-src 'self' data: blob:; object-src 'none'; base-uri 'self'; form-action 'self'"
+        .to_string()
+}
+
+fn default_frame_ancestors() -> String {
+    "'none'".to_string()
+}
+
+fn default_referrer_policy() -> String {
+    // Portal URLs may carry a PIN
+    "no-referrer".to_string()
+}
+
+fn default_schedule_days() -> String {
+    "mon-fri".to_string()
+}
+
+fn default_schedule_start() -> String {
+    "09:00".to_string()
+}
+
+fn default_schedule_stop() -> String {
+    "18:00".to_string()
+}
+
+fn default_defer_background_tasks() -> bool {
+    true
+}
+
+fn default_large_transfer_warning_mb() -> u64 {
+    1024
+}
+
+fn default_prevent_sleep_during_transfers() -> bool {
+    true
+}
+
+fn default_download_dir() -> String {
+    "downloads".to_string()
+}
+
+fn default_download_retries() -> u32 {
+    3
+}
+
 fn default_theme() -> String {
     "light".to_string()
 }
@@ -64,21 +926,24 @@ fn default_storage_dir() -> String {
 }
 
 // Default implementations
-impl Default for ConfigData {
-    fn default() -> Self {
-        ConfigData {
-            server: ServerConfig::default(),
-            display: DisplayConfig::default(),
-            storage: StorageConfig::default(),
-        }
-    }
-}
-
 impl Default for ServerConfig {
     fn default() -> Self {
         ServerConfig {
             port: default_port(),
             upload_chunk_size_mb: default_upload_chunk_size_mb(),
+            max_upload_segments: default_max_upload_segments(),
+            conflict_timeout_secs: default_conflict_timeout_secs(),
+            bind_addresses: Vec::new(),
+            http2: default_http2(),
+            keep_alive_secs: default_keep_alive_secs(),
+            max_concurrent_streams: default_max_concurrent_streams(),
+            delta_uploads: default_delta_uploads(),
+            max_download_connections: default_max_download_connections(),
+            transfer_memory_mb: default_transfer_memory_mb(),
+            trusted_proxies: Vec::new(),
+            link_previews: false,
+            tls: None,
+            firewall_checked: false,
         }
     }
 }
@@ -87,6 +952,7 @@ impl Default for DisplayConfig {
     fn default() -> Self {
         DisplayConfig {
             theme: default_theme(),
+            auto_open: Vec::new(),
         }
     }
 }
@@ -95,6 +961,62 @@ impl Default for StorageConfig {
     fn default() -> Self {
         StorageConfig {
             storage_dir: default_storage_dir(),
+            temp_dir: String::new(),
+            organize_media_by_date: false,
+            scrub_interval_hours: default_scrub_interval_hours(),
+            case_insensitive_names: false,
+            permissions: PermissionsConfig::default(),
+            metadata: MetadataBackend::default(),
+            snapshots: SnapshotConfig::default(),
+        }
+    }
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        SnapshotConfig {
+            interval_hours: default_snapshot_interval_hours(),
+            keep: default_snapshots_kept(),
+        }
+    }
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        ScheduleConfig {
+            enabled: false,
+            days: default_schedule_days(),
+            start: default_schedule_start(),
+            stop: default_schedule_stop(),
+        }
+    }
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        PowerConfig {
+            defer_background_tasks: default_defer_background_tasks(),
+            large_transfer_warning_mb: default_large_transfer_warning_mb(),
+            prevent_sleep_during_transfers: default_prevent_sleep_during_transfers(),
+        }
+    }
+}
+
+impl Default for DownloadsConfig {
+    fn default() -> Self {
+        DownloadsConfig {
+            directory: default_download_dir(),
+            retries: default_download_retries(),
+        }
+    }
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        SecurityHeadersConfig {
+            content_security_policy: default_content_security_policy(),
+            frame_ancestors: default_frame_ancestors(),
+            referrer_policy: default_referrer_policy(),
         }
     }
 }
@@ -102,8 +1024,50 @@ impl Default for StorageConfig {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+    use settings::{SettingField, SettingKind};
     use tempfile::tempdir;
 
+    /// Input the settings panel could be given for `field`
+    fn setting_input(field: &SettingField) -> BoxedStrategy<String> {
+        match &field.kind {
+            SettingKind::Integer { min, max } => (min.unwrap_or(0)
+                ..=max.unwrap_or(u16::MAX.into()))
+                .prop_map(|number| number.to_string())
+                .boxed(),
+            SettingKind::Text => "\\PC{0,16}".boxed(),
+            SettingKind::Choice(choices) => prop::sample::select(choices.clone()).boxed(),
+            SettingKind::Toggle => any::<bool>().prop_map(|on| on.to_string()).boxed(),
+        }
+    }
+
+    /// Top-level keys of the settings file
+    fn sections() -> Vec<String> {
+        serde_yaml::to_value(ConfigData::default())
+            .unwrap()
+            .as_mapping()
+            .unwrap()
+            .keys()
+            .filter_map(|key| key.as_str().map(str::to_string))
+            .collect()
+    }
+
+    /// Settings edited in the panel, as they are before saving
+    fn edited_config() -> impl Strategy<Value = ConfigData> {
+        let schema = ConfigData::schema();
+        let inputs: Vec<_> = schema.iter().map(setting_input).collect();
+        inputs.prop_map(move |inputs| {
+            let mut config = ConfigData::default();
+            for (field, input) in schema.iter().zip(&inputs) {
+                // Refused input leaves the setting as it was, like in the panel
+                if let Ok(value) = field.parse(input) {
+                    let _ = settings::schema::set_value(&mut config, &field.key, value);
+                }
+            }
+            config
+        })
+    }
+
     #[test]
     fn test_default_config() {
         let config = ServerConfig::default();
@@ -130,6 +1094,89 @@ mod tests {
         assert_eq!(reloaded_config.server.port, 9000);
     }
 
+    #[test]
+    fn test_profiles_default_and_lookup() {
+        let yaml = "profiles:\n  - name: drop-box\n    port: 8080\n  - name: share\n    port: 8081\n    storage_dir: shared\n    read_only: true\n    pin: \"2468\"\n";
+        let temp_dir = tempdir().unwrap();
+        let config_path = temp_dir.path().join("profiles.yaml");
+        std::fs::write(&config_path, yaml).unwrap();
+
+        let config = ConfigData::load(&config_path).unwrap();
+
+        assert_eq!(config.profiles.len(), 2);
+        assert_eq!(config.profile("drop-box").unwrap().storage_dir, "uploads");
+        assert!(!config.profile("drop-box").unwrap().read_only);
+        assert!(config.profile("share").unwrap().read_only);
+        assert_eq!(config.profile("drop-box").unwrap().pin, None);
+        assert_eq!(config.profile("share").unwrap().pin.as_deref(), Some("2468"));
+        assert!(config.profile("missing").is_none());
+    }
+
+    #[test]
+    fn test_network_overlay_lookup() {
+        let yaml = "networks:\n  - name: Office\n    ssid: ACME Guest\n    read_only: true\n    pin: \"4711\"\n";
+        let temp_dir = tempdir().unwrap();
+        let config_path = temp_dir.path().join("networks.yaml");
+        std::fs::write(&config_path, yaml).unwrap();
+
+        let config = ConfigData::load(&config_path).unwrap();
+
+        let office = config.network_overlay("ACME Guest").unwrap();
+        assert_eq!(office.name, "Office");
+        assert_eq!(office.read_only, Some(true));
+        assert_eq!(office.pin.as_deref(), Some("4711"));
+        assert!(office.port.is_none());
+        assert!(config.network_overlay("Home").is_none());
+    }
+
+    #[test]
+    fn test_settings_schema() {
+        let schema = ConfigData::schema();
+        let port = schema.iter().find(|f| f.key == "server.port").unwrap();
+        assert_eq!(port.section, "Server Configuration");
+        assert!(port.parse("80").is_err());
+        assert!(schema.iter().any(|f| f.key == "display.theme"));
+        assert!(!schema.iter().any(|f| f.key == "server.firewall_checked"));
+    }
+
+    proptest! {
+        /// Every setting the panel can edit survives saving and loading
+        #[test]
+        fn test_edited_settings_round_trip(config in edited_config()) {
+            let temp_dir = tempdir().unwrap();
+            let config_path = temp_dir.path().join("settings.yaml");
+            config.save(&config_path).unwrap();
+            prop_assert_eq!(ConfigData::load(&config_path).unwrap(), config);
+        }
+
+        /// A settings file written before a section existed, or with the
+        /// section removed by hand, gets that section's defaults and keeps
+        /// the rest
+        #[test]
+        fn test_missing_section_defaults(
+            config in edited_config(),
+            section in prop::sample::select(sections()),
+        ) {
+            let mut yaml = serde_yaml::to_value(&config).unwrap();
+            yaml.as_mapping_mut().unwrap().remove(section.as_str());
+            let temp_dir = tempdir().unwrap();
+            let config_path = temp_dir.path().join("settings.yaml");
+            std::fs::write(&config_path, serde_yaml::to_string(&yaml).unwrap()).unwrap();
+
+            let loaded = serde_yaml::to_value(ConfigData::load(&config_path).unwrap()).unwrap();
+            let defaults = serde_yaml::to_value(ConfigData::default()).unwrap();
+            let saved = serde_yaml::to_value(&config).unwrap();
+            for (key, value) in loaded.as_mapping().unwrap() {
+                let expected = if key.as_str() == Some(section.as_str()) {
+                    &defaults
+                } else {
+                    &saved
+                };
+                prop_assert_eq!(Some(value), expected.get(key), "{:?}", key);
+            }
+        }
+    }
+
     #[test]
     fn test_settings_instance() {
         // Test that we can get the singleton instance using Settings trait
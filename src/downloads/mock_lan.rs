@@ -0,0 +1,261 @@
+//! A pretend LAN for tests: JusTrans peers served from loopback aliases
+//! (`127.0.0.2`, `127.0.0.3`, ...) as if they were separate machines, so
+//! pairing with, browsing and downloading from peers can be tested without
+//! a network. Peers are found through the pairing links they hand out, as
+//! in the app; there is no multicast. Where the aliases aren't routed, as on
+//! macOS, every peer listens on `127.0.0.1` instead.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use futures_util::StreamExt;
+use serde_json::json;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use crate::config::PeerConfig;
+use crate::server::ranges;
+use crate::shell::link::Link;
+
+/// A file a mock peer shares
+#[derive(Debug, Clone)]
+pub struct MockFile {
+    pub id: String,
+    pub name: String,
+    pub contents: Vec<u8>,
+}
+
+impl MockFile {
+    pub fn new(id: &str, name: &str, contents: &[u8]) -> Self {
+        Self {
+            id: id.to_string(),
+            name: name.to_string(),
+            contents: contents.to_vec(),
+        }
+    }
+}
+
+/// What a mock peer shares and how it behaves
+#[derive(Debug, Clone, Default)]
+pub struct PeerSetup {
+    /// Identity fingerprint put in its pairing links
+    pub identity: Option<String>,
+    pub pin: Option<String>,
+    pub files: Vec<MockFile>,
+    /// Drop the connection halfway through the first download of each
+    /// file, like a phone walking out of Wi-Fi range
+    pub flaky: bool,
+}
+
+#[derive(Default)]
+struct PeerState {
+    setup: PeerSetup,
+    /// Files whose first download was already cut off
+    interrupted: Mutex<Vec<String>>,
+    /// Requests served, by path
+    requests: Mutex<HashMap<String, usize>>,
+}
+
+/// A peer running on the mock LAN
+pub struct MockPeer {
+    pub address: SocketAddr,
+    state: Arc<PeerState>,
+    shutdown: Option<oneshot::Sender<()>>,
+    server: Option<JoinHandle<()>>,
+}
+
+impl MockPeer {
+    /// The pairing link its portal would show
+    pub fn pair_link(&self) -> Link {
+        let mut link = format!(
+            "justrans://pair?host={}&port={}",
+            self.address.ip(),
+            self.address.port()
+        );
+        if let Some(identity) = &self.state.setup.identity {
+            link.push_str(&format!("&id={}", identity));
+        }
+        Link::parse(&link).unwrap()
+    }
+
+    /// The peer as if it had been added in the settings, with its PIN
+    pub fn config(&self) -> PeerConfig {
+        PeerConfig {
+            name: self.address.to_string(),
+            url: format!("http://{}", self.address),
+            pin: self.state.setup.pin.clone(),
+            identity: self.state.setup.identity.clone(),
+        }
+    }
+
+    /// How many requests for `path` it answered
+    pub fn requests(&self, path: &str) -> usize {
+        let requests = self.state.requests.lock().unwrap();
+        requests.get(path).copied().unwrap_or(0)
+    }
+
+    /// Leave the network, closing the address
+    pub async fn stop(mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(server) = self.server.take() {
+            server.abort();
+            let _ = server.await;
+        }
+    }
+}
+
+/// Loopback addresses handed out to peers, one "machine" each
+#[derive(Default)]
+pub struct MockLan {
+    hosts: u8,
+}
+
+impl MockLan {
+    /// Start a peer on the next free loopback alias
+    pub async fn peer(&mut self, setup: PeerSetup) -> MockPeer {
+        self.hosts += 1;
+        let alias = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, self.hosts + 1)), 0);
+        let listener = match TcpListener::bind(alias).await {
+            Ok(listener) => listener,
+            Err(_) => TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        };
+        serve(listener, setup)
+    }
+
+    /// Start a peer at `address`, e.g. one another peer just left
+    pub async fn peer_at(&self, address: SocketAddr, setup: PeerSetup) -> MockPeer {
+        serve(TcpListener::bind(address).await.unwrap(), setup)
+    }
+}
+
+fn serve(listener: TcpListener, setup: PeerSetup) -> MockPeer {
+    let address = listener.local_addr().unwrap();
+    let state = Arc::new(PeerState {
+        setup,
+        ..PeerState::default()
+    });
+    let app = Router::new()
+        .route("/api/v1/files", get(list_files))
+        .route("/api/v1/files/:id", get(download_file))
+        .with_state(state.clone());
+    let (shutdown, stopped) = oneshot::channel();
+    let server = tokio::spawn(async move {
+        let _ = axum::serve(listener, app)
+            .with_graceful_shutdown(async {
+                let _ = stopped.await;
+            })
+            .await;
+    });
+    MockPeer {
+        address,
+        state,
+        shutdown: Some(shutdown),
+        server: Some(server),
+    }
+}
+
+/// Count the request and check the PIN, as the portal does
+fn admit(
+    state: &PeerState,
+    path: String,
+    query: &HashMap<String, String>,
+) -> Result<(), StatusCode> {
+    *state.requests.lock().unwrap().entry(path).or_default() += 1;
+    match &state.setup.pin {
+        Some(pin) if query.get("pin") != Some(pin) => Err(StatusCode::FORBIDDEN),
+        _ => Ok(()),
+    }
+}
+
+async fn list_files(
+    State(state): State<Arc<PeerState>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    admit(&state, "/api/v1/files".to_string(), &query)?;
+    let files: Vec<_> = state
+        .setup
+        .files
+        .iter()
+        .map(|file| json!({ "id": file.id, "name": file.name, "size": file.contents.len() }))
+        .collect();
+    Ok(Json(json!({ "files": files })))
+}
+
+async fn download_file(
+    Path(id): Path<String>,
+    State(state): State<Arc<PeerState>>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    admit(&state, format!("/api/v1/files/{}", id), &query)?;
+    let file = state
+        .setup
+        .files
+        .iter()
+        .find(|file| file.id == id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let len = file.contents.len() as u64;
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(Ok(None), |value| ranges::parse_range(value, len));
+    let range = match range {
+        Ok(range) => range,
+        Err(ranges::Unsatisfiable) => return Ok(ranges::not_satisfiable(len)),
+    };
+    let part = range.clone().unwrap_or(0..len);
+    let contents = file.contents[part.start as usize..part.end as usize].to_vec();
+
+    let first = state.setup.flaky && {
+        let mut interrupted = state.interrupted.lock().unwrap();
+        let first = !interrupted.contains(&id);
+        interrupted.push(id.clone());
+        first
+    };
+    let body = if first {
+        // Half the data, then the connection breaks. The break waits a
+        // moment so the half is sent before the server drops the connection.
+        let half = contents[..contents.len() / 2].to_vec();
+        let reset = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset))
+        };
+        Body::from_stream(
+            futures_util::stream::iter([Ok(half)]).chain(futures_util::stream::once(reset)),
+        )
+    } else {
+        Body::from(contents.clone())
+    };
+
+    let mut response = (
+        [
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", file.name),
+            ),
+            (header::CONTENT_LENGTH, contents.len().to_string()),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+        ],
+        body,
+    )
+        .into_response();
+    if let Some(range) = range {
+        *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+        response.headers_mut().insert(
+            header::CONTENT_RANGE,
+            ranges::content_range(&range, len).parse().unwrap(),
+        );
+    }
+    Ok(response)
+}
@@ -0,0 +1,540 @@
+//! Download manager for files fetched from other JusTrans instances: files
+//! browsed on paired peers and the ones listed in `.justrans` bundles.
+//! Downloads run one after the other and can be paused, resumed and
+//! retried; interrupted downloads continue where they stopped.
+
+#[cfg(test)]
+mod mock_lan;
+pub mod peers;
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::header;
+use reqwest::{StatusCode, Url};
+use settings::Settings;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Notify;
+
+use crate::config::ConfigData;
+use crate::server::{inhibit, organize, rooms};
+
+/// Suffix of a file that is still being downloaded
+const PARTIAL_SUFFIX: &str = ".part";
+
+/// Wait before an interrupted download is attempted again
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Callback invoked with a status message when a download finishes or fails
+pub type DownloadNotifier = Box<dyn Fn(String) + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadState {
+    Queued,
+    Active,
+    Paused,
+    Done(PathBuf),
+    Failed(String),
+}
+
+/// One remote file in the queue
+#[derive(Debug, Clone)]
+pub struct Download {
+    pub id: u64,
+    pub url: Url,
+    /// Name from the server once the download started, the URL until then
+    pub name: String,
+    /// Folder the file is saved to
+    pub dir: PathBuf,
+    pub size: Option<u64>,
+    pub received: u64,
+    pub state: DownloadState,
+    /// PIN of the room the file is in, sent in a header rather than the URL
+    room_pin: Option<String>,
+    /// Where the finished file goes, chosen when the download first starts
+    target: Option<PathBuf>,
+}
+
+impl Download {
+    /// File the contents are written to until the download completes
+    fn partial(&self) -> Option<PathBuf> {
+        self.target
+            .as_ref()
+            .map(|target| PathBuf::from(format!("{}{}", target.display(), PARTIAL_SUFFIX)))
+    }
+
+    /// Progress for the Transfers panel, e.g. "1.2 MB of 4.0 MB"
+    pub fn progress(&self) -> String {
+        match self.size {
+            Some(size) => format!(
+                "{} of {}",
+                humanize::size(self.received),
+                humanize::size(size)
+            ),
+            None => humanize::size(self.received),
+        }
+    }
+
+    /// Completed fraction between 0 and 1, 0 while the size is unknown
+    pub fn fraction(&self) -> f32 {
+        match self.size {
+            Some(0) => 1.0,
+            Some(size) => (self.received as f64 / size as f64).min(1.0) as f32,
+            None => 0.0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    downloads: Vec<Download>,
+    next_id: u64,
+}
+
+/// Remote files waiting to be, being or done being downloaded
+#[derive(Clone, Default)]
+pub struct DownloadQueue {
+    state: Arc<Mutex<QueueState>>,
+    /// Wakes the worker when there is something new to download
+    wake: Arc<Notify>,
+    /// Tells the active download to check whether it was paused or removed
+    interrupt: Arc<Notify>,
+}
+
+/// Folder new downloads are saved to, from the config
+pub fn destination() -> PathBuf {
+    let directory = ConfigData::instance()
+        .map(|instance| instance.lock().unwrap().downloads.directory.clone())
+        .unwrap_or_default();
+    if directory.is_empty() {
+        PathBuf::from("downloads")
+    } else {
+        PathBuf::from(directory)
+    }
+}
+
+impl DownloadQueue {
+    /// Queue the file at `url` to be saved in `dir`, returning its id
+    pub fn add(&self, url: Url, dir: PathBuf) -> u64 {
+        self.add_from_room(url, None, dir)
+    }
+
+    /// Queue a file of a room protected by `room_pin`
+    pub fn add_from_room(&self, url: Url, room_pin: Option<String>, dir: PathBuf) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        let id = state.next_id;
+        state.downloads.push(Download {
+            id,
+            name: name_from_url(&url),
+            url,
+            dir,
+            size: None,
+            received: 0,
+            state: DownloadState::Queued,
+            room_pin,
+            target: None,
+        });
+        drop(state);
+        self.wake.notify_one();
+        id
+    }
+
+    /// Snapshot of the queue, oldest first
+    pub fn list(&self) -> Vec<Download> {
+        self.state.lock().unwrap().downloads.clone()
+    }
+
+    /// Number of downloads that have not started yet
+    pub fn waiting(&self) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .downloads
+            .iter()
+            .filter(|download| download.state == DownloadState::Queued)
+            .count()
+    }
+
+    /// Stop a queued or active download, keeping what has been received
+    pub fn pause(&self, id: u64) {
+        self.update(id, |download| {
+            if matches!(
+                download.state,
+                DownloadState::Queued | DownloadState::Active
+            ) {
+                download.state = DownloadState::Paused;
+            }
+        });
+        self.interrupt.notify_waiters();
+    }
+
+    /// Queue a paused download again
+    pub fn resume(&self, id: u64) {
+        self.update(id, |download| {
+            if download.state == DownloadState::Paused {
+                download.state = DownloadState::Queued;
+            }
+        });
+        self.wake.notify_one();
+    }
+
+    /// Queue a failed download again, continuing from what was received
+    pub fn retry(&self, id: u64) {
+        self.update(id, |download| {
+            if matches!(download.state, DownloadState::Failed(_)) {
+                download.state = DownloadState::Queued;
+            }
+        });
+        self.wake.notify_one();
+    }
+
+    /// Drop a download from the list, deleting its partial file. Finished
+    /// files are kept.
+    pub fn remove(&self, id: u64) {
+        let removed = {
+            let mut state = self.state.lock().unwrap();
+            let index = state
+                .downloads
+                .iter()
+                .position(|download| download.id == id);
+            index.map(|index| state.downloads.remove(index))
+        };
+        let Some(removed) = removed else {
+            return;
+        };
+        if removed.state == DownloadState::Active {
+            // The worker deletes the partial file once it notices
+            self.interrupt.notify_waiters();
+        } else if let Some(partial) = removed.partial() {
+            let _ = std::fs::remove_file(partial);
+        }
+    }
+
+    /// Mark the oldest queued download active and return it
+    fn take_next(&self) -> Option<Download> {
+        let mut state = self.state.lock().unwrap();
+        let download = state
+            .downloads
+            .iter_mut()
+            .find(|download| download.state == DownloadState::Queued)?;
+        download.state = DownloadState::Active;
+        Some(download.clone())
+    }
+
+    fn get(&self, id: u64) -> Option<Download> {
+        self.state
+            .lock()
+            .unwrap()
+            .downloads
+            .iter()
+            .find(|download| download.id == id)
+            .cloned()
+    }
+
+    fn update(&self, id: u64, change: impl FnOnce(&mut Download)) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(download) = state
+            .downloads
+            .iter_mut()
+            .find(|download| download.id == id)
+        {
+            change(download);
+        }
+    }
+
+    /// Whether the download was paused or removed while active
+    fn stopped(&self, id: u64) -> bool {
+        !self
+            .get(id)
+            .is_some_and(|download| download.state == DownloadState::Active)
+    }
+}
+
+/// Last path segment of `url`, used as the name until the server sends one
+fn name_from_url(url: &Url) -> String {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|segment| !segment.is_empty())
+        .unwrap_or("download")
+        .to_string()
+}
+
+/// File name from a `Content-Disposition: attachment; filename="..."` header
+fn parse_content_disposition(value: &str) -> Option<String> {
+    let name = value
+        .split(';')
+        .find_map(|part| part.trim().strip_prefix("filename="))?
+        .trim_matches('"');
+    // Never let the server pick a directory
+    Path::new(name)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+}
+
+/// How a download attempt ended
+enum Outcome {
+    Finished(PathBuf),
+    /// Paused or removed by the user
+    Stopped,
+}
+
+/// Download `id`, continuing from its partial file if the server supports
+/// ranges
+async fn fetch(queue: &DownloadQueue, id: u64) -> anyhow::Result<Outcome> {
+    let Some(download) = queue.get(id) else {
+        return Ok(Outcome::Stopped);
+    };
+    let mut request = reqwest::Client::new().get(download.url.clone());
+    if let Some(pin) = &download.room_pin {
+        request = request.header(rooms::PIN_HEADER, pin);
+    }
+    if download.target.is_some() && download.received > 0 {
+        request = request.header(header::RANGE, format!("bytes={}-", download.received));
+    }
+    let mut response = request.send().await?.error_for_status()?;
+    let resumed = response.status() == StatusCode::PARTIAL_CONTENT;
+
+    let (target, offset, size) = match &download.target {
+        Some(target) if resumed => {
+            let size = response
+                .content_length()
+                .map(|remaining| download.received + remaining);
+            (target.clone(), download.received, size)
+        }
+        _ => {
+            let name = response
+                .headers()
+                .get(header::CONTENT_DISPOSITION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_content_disposition)
+                .unwrap_or_else(|| download.name.clone());
+            tokio::fs::create_dir_all(&download.dir).await?;
+            let target = download
+                .target
+                .clone()
+                .unwrap_or_else(|| organize::free_path(&download.dir, &name));
+            (target, 0, response.content_length())
+        }
+    };
+    queue.update(id, |download| {
+        if let Some(name) = target.file_name() {
+            download.name = name.to_string_lossy().into_owned();
+        }
+        download.target = Some(target.clone());
+        download.size = size;
+        download.received = offset;
+    });
+
+    let partial = PathBuf::from(format!("{}{}", target.display(), PARTIAL_SUFFIX));
+    let mut file = if resumed {
+        tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&partial)
+            .await?
+    } else {
+        tokio::fs::File::create(&partial).await?
+    };
+    let mut received = offset;
+    loop {
+        if queue.stopped(id) {
+            file.flush().await?;
+            if queue.get(id).is_none() {
+                let _ = tokio::fs::remove_file(&partial).await;
+            }
+            return Ok(Outcome::Stopped);
+        }
+        let chunk = tokio::select! {
+            chunk = response.chunk() => chunk?,
+            _ = queue.interrupt.notified() => continue,
+        };
+        let Some(chunk) = chunk else {
+            break;
+        };
+        file.write_all(&chunk).await?;
+        received += chunk.len() as u64;
+        queue.update(id, |download| download.received = received);
+    }
+    file.flush().await?;
+    if size.is_some_and(|size| size != received) {
+        anyhow::bail!("The connection closed before the download finished");
+    }
+
+    // The name may have been taken while the download was paused
+    let target = if target.exists() {
+        organize::free_path(&download.dir, &download.name)
+    } else {
+        target
+    };
+    tokio::fs::rename(&partial, &target).await?;
+    Ok(Outcome::Finished(target))
+}
+
+/// Download queued files one after the other, for as long as the app runs
+pub async fn run(queue: DownloadQueue, notify: DownloadNotifier) {
+    loop {
+        let Some(download) = queue.take_next() else {
+            queue.wake.notified().await;
+            continue;
+        };
+
+        let _awake = inhibit::keep_awake("Downloading files");
+        let retries = ConfigData::instance()
+            .map(|instance| instance.lock().unwrap().downloads.retries)
+            .unwrap_or(0);
+        let mut attempt = 0;
+        let result = loop {
+            match fetch(&queue, download.id).await {
+                Err(e) if attempt < retries && !queue.stopped(download.id) => {
+                    attempt += 1;
+                    log::info!(
+                        "Download of {} interrupted ({}), retrying ({}/{})",
+                        download.url,
+                        e,
+                        attempt,
+                        retries
+                    );
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+                result => break result,
+            }
+        };
+        if queue.stopped(download.id) {
+            // Paused or removed by the user
+            continue;
+        }
+
+        let name = queue
+            .get(download.id)
+            .map_or_else(|| download.name.clone(), |download| download.name);
+        let (state, mut message) = match result {
+            Ok(Outcome::Stopped) => continue,
+            Ok(Outcome::Finished(path)) => {
+                log::info!("Downloaded {} to {:?}", download.url, path);
+                let message = format!("Downloaded {}", path.display());
+                (DownloadState::Done(path), message)
+            }
+            Err(e) => {
+                log::warn!("Failed to download {}: {}", download.url, e);
+                (
+                    DownloadState::Failed(e.to_string()),
+                    format!("Failed to download {}: {}", name, e),
+                )
+            }
+        };
+        queue.update(download.id, |download| download.state = state);
+        let waiting = queue.waiting();
+        if waiting > 0 {
+            message.push_str(&format!(", {} more queued", waiting));
+        }
+        notify(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock_lan::{MockFile, MockLan, PeerSetup};
+    use super::*;
+
+    fn queue_with(urls: &[&str]) -> (DownloadQueue, Vec<u64>) {
+        let queue = DownloadQueue::default();
+        let ids = urls
+            .iter()
+            .map(|url| queue.add(Url::parse(url).unwrap(), PathBuf::from("downloads")))
+            .collect();
+        (queue, ids)
+    }
+
+    #[test]
+    fn test_queue_order() {
+        let (queue, ids) = queue_with(&[
+            "http://10.0.0.2:8080/api/v1/files/abc",
+            "http://10.0.0.2:8080/d/",
+        ]);
+        assert_eq!(queue.waiting(), 2);
+
+        let next = queue.take_next().unwrap();
+        assert_eq!((next.id, next.name.as_str()), (ids[0], "abc"));
+        assert_eq!(queue.take_next().unwrap().id, ids[1]);
+        assert!(queue.take_next().is_none());
+        assert_eq!(queue.waiting(), 0);
+        assert_eq!(queue.list()[1].name, "download");
+    }
+
+    #[test]
+    fn test_pause_resume_retry() {
+        let (queue, ids) = queue_with(&["http://a/1", "http://a/2", "http://a/3"]);
+        queue.pause(ids[0]);
+        assert_eq!(queue.take_next().unwrap().id, ids[1]);
+        assert!(!queue.stopped(ids[1]));
+        queue.pause(ids[1]);
+        assert!(queue.stopped(ids[1]));
+
+        queue.resume(ids[0]);
+        assert_eq!(queue.take_next().unwrap().id, ids[0]);
+        queue.update(ids[0], |d| {
+            d.state = DownloadState::Failed("timed out".to_string())
+        });
+        // Only paused downloads resume and only failed ones are retried
+        queue.resume(ids[0]);
+        assert_eq!(queue.take_next().unwrap().id, ids[2]);
+        queue.retry(ids[0]);
+        assert_eq!(queue.take_next().unwrap().id, ids[0]);
+
+        queue.remove(ids[0]);
+        assert!(queue.stopped(ids[0]));
+        assert_eq!(queue.list().len(), 2);
+    }
+
+    #[test]
+    fn test_progress() {
+        let (queue, ids) = queue_with(&["http://a/1"]);
+        assert_eq!(queue.list()[0].fraction(), 0.0);
+        queue.update(ids[0], |d| {
+            d.received = 512 * 1024;
+            d.size = Some(2 * 1024 * 1024);
+        });
+        assert_eq!(queue.list()[0].fraction(), 0.25);
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_peer() {
+        let phone = MockLan::default()
+            .peer(PeerSetup {
+                files: vec![MockFile::new("b2", "photo.jpg", &[0xA5; 4096])],
+                flaky: true,
+                ..PeerSetup::default()
+            })
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let queue = DownloadQueue::default();
+        let url = peers::file_url(&phone.config(), "b2").unwrap();
+        let id = queue.add(url, dir.path().to_path_buf());
+        assert_eq!(queue.take_next().unwrap().id, id);
+
+        // The connection breaks halfway, and the retry asks for the rest
+        assert!(fetch(&queue, id).await.is_err());
+        let received = queue.get(id).unwrap().received;
+        assert!(received > 0 && received < 4096, "{} received", received);
+        let Outcome::Finished(path) = fetch(&queue, id).await.unwrap() else {
+            panic!("The download stopped");
+        };
+        assert_eq!(path, dir.path().join("photo.jpg"));
+        assert_eq!(std::fs::read(&path).unwrap(), [0xA5; 4096]);
+        assert_eq!(phone.requests("/api/v1/files/b2"), 2);
+    }
+
+    #[test]
+    fn test_parse_content_disposition() {
+        assert_eq!(
+            parse_content_disposition("attachment; filename=\"report.pdf\""),
+            Some("report.pdf".to_string())
+        );
+        assert_eq!(
+            parse_content_disposition("attachment; filename=\"../../.bashrc\""),
+            Some(".bashrc".to_string())
+        );
+        assert_eq!(parse_content_disposition("inline"), None);
+    }
+}
@@ -0,0 +1,248 @@
+use reqwest::Url;
+use serde::Deserialize;
+use settings::Settings;
+
+use crate::config::{ConfigData, PeerConfig};
+use crate::shell::link::Link;
+
+/// A file shared by a paired instance
+#[derive(Debug, Clone, Deserialize)]
+pub struct RemoteFile {
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+}
+
+#[derive(Deserialize)]
+struct RemoteFileList {
+    files: Vec<RemoteFile>,
+}
+
+/// Address of `path` in the API of `peer`, with its PIN if it has one
+fn api_url(peer: &PeerConfig, path: &str) -> anyhow::Result<Url> {
+    let mut url = Url::parse(&peer.url)
+        .map_err(|e| anyhow::anyhow!("Invalid address '{}': {}", peer.url, e))?
+        .join(&format!("/api/v1/{}", path))?;
+    if let Some(pin) = &peer.pin {
+        url.query_pairs_mut().append_pair("pin", pin);
+    }
+    Ok(url)
+}
+
+/// Download address of a file shared by `peer`
+pub fn file_url(peer: &PeerConfig, id: &str) -> anyhow::Result<Url> {
+    api_url(peer, &format!("files/{}", id))
+}
+
+/// Files `peer` currently shares
+pub async fn list_files(peer: &PeerConfig) -> anyhow::Result<Vec<RemoteFile>> {
+    let list: RemoteFileList = reqwest::get(api_url(peer, "files")?)
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(list.files)
+}
+
+/// The peer a `justrans://pair` link points at
+fn paired_peer(link: &Link) -> anyhow::Result<Option<PeerConfig>> {
+    let Link::Pair { host, port, id, .. } = link else {
+        return Ok(None);
+    };
+    let mut url = link.web_url()?;
+    url.set_query(None);
+    Ok(Some(PeerConfig {
+        name: format!("{}:{}", host, port),
+        url: url.as_str().trim_end_matches('/').to_string(),
+        pin: None,
+        identity: id.clone(),
+    }))
+}
+
+/// How a paired peer changed the list of peers
+#[derive(Debug, PartialEq)]
+enum Paired {
+    New,
+    /// An instance with the same identity, now at another address
+    Moved,
+    Known,
+}
+
+/// Add a paired peer to `peers`. An instance whose identity is pinned
+/// keeps its entry when it shows up at a new address.
+fn add_peer(peers: &mut Vec<PeerConfig>, peer: PeerConfig) -> Paired {
+    let pinned = peer.identity.as_ref().and_then(|identity| {
+        peers
+            .iter_mut()
+            .find(|known| known.identity.as_ref() == Some(identity))
+    });
+    if let Some(known) = pinned {
+        if known.url == peer.url {
+            return Paired::Known;
+        }
+        log::info!("Paired instance {} moved to {}", known.name, peer.url);
+        known.name = peer.name;
+        known.url = peer.url;
+        return Paired::Moved;
+    }
+    if peers.iter().any(|known| known.name == peer.name) {
+        return Paired::Known;
+    }
+    log::info!("Paired with {} at {}", peer.name, peer.url);
+    peers.push(peer);
+    Paired::New
+}
+
+/// Remember the instance of a pairing link so its files can be browsed
+/// later, returning whether it was new
+pub fn remember(link: &Link) -> anyhow::Result<bool> {
+    let Some(peer) = paired_peer(link)? else {
+        return Ok(false);
+    };
+    let instance = ConfigData::instance()?;
+    let mut config = instance.lock().unwrap();
+    let paired = add_peer(&mut config.peers, peer);
+    if paired != Paired::Known {
+        config.save(&settings::path())?;
+    }
+    Ok(paired == Paired::New)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock_lan::{MockFile, MockLan, MockPeer, PeerSetup};
+    use super::*;
+
+    #[test]
+    fn test_paired_peer() {
+        let link = Link::parse("justrans://pair?host=fe80::1&port=9000&token=abc").unwrap();
+        let peer = paired_peer(&link).unwrap().unwrap();
+        assert_eq!(peer.name, "fe80::1:9000");
+        assert_eq!(peer.url, "http://[fe80::1]:9000");
+
+        let share = Link::parse("justrans://share?host=10.0.0.2").unwrap();
+        assert!(paired_peer(&share).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_pinned_peer_moves() {
+        let mut peers = Vec::new();
+        let pair = |link: &str| paired_peer(&Link::parse(link).unwrap()).unwrap().unwrap();
+
+        let first = pair("justrans://pair?host=10.0.0.2&port=8080&id=abc");
+        assert_eq!(first.identity.as_deref(), Some("abc"));
+        assert_eq!(add_peer(&mut peers, first.clone()), Paired::New);
+        assert_eq!(add_peer(&mut peers, first), Paired::Known);
+
+        let moved = pair("justrans://pair?host=10.0.0.9&port=9000&id=abc");
+        assert_eq!(add_peer(&mut peers, moved), Paired::Moved);
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].url, "http://10.0.0.9:9000");
+
+        // Without a pinned identity a new address is a new instance
+        let unpinned = pair("justrans://pair?host=10.0.0.3&port=8080");
+        assert_eq!(add_peer(&mut peers, unpinned), Paired::New);
+        assert_eq!(peers.len(), 2);
+    }
+
+    #[test]
+    fn test_file_url() {
+        let mut peer = PeerConfig {
+            name: "office".to_string(),
+            url: "http://10.0.0.2:8080/".to_string(),
+            pin: None,
+            identity: None,
+        };
+        assert_eq!(
+            file_url(&peer, "abc").unwrap().as_str(),
+            "http://10.0.0.2:8080/api/v1/files/abc"
+        );
+        peer.pin = Some("12 34".to_string());
+        assert_eq!(
+            api_url(&peer, "files").unwrap().as_str(),
+            "http://10.0.0.2:8080/api/v1/files?pin=12+34"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_browse_peers() {
+        let mut lan = MockLan::default();
+        let office = lan
+            .peer(PeerSetup {
+                files: vec![
+                    MockFile::new("a1", "notes.txt", b"hello from the office"),
+                    MockFile::new("b2", "photo.jpg", &[0xA5; 4096]),
+                ],
+                ..PeerSetup::default()
+            })
+            .await;
+        let locked = lan
+            .peer(PeerSetup {
+                pin: Some("4711".to_string()),
+                files: vec![MockFile::new("c3", "report.pdf", b"%PDF")],
+                ..PeerSetup::default()
+            })
+            .await;
+        assert_ne!(office.address, locked.address);
+
+        let listed = list_files(&office.config()).await.unwrap();
+        let names: Vec<_> = listed.iter().map(|file| file.name.as_str()).collect();
+        assert_eq!(names, ["notes.txt", "photo.jpg"]);
+        assert_eq!(listed[1].size, 4096);
+
+        // The PIN from the settings goes along; without it the peer refuses
+        assert_eq!(list_files(&locked.config()).await.unwrap().len(), 1);
+        let mut forgotten = locked.config();
+        forgotten.pin = None;
+        assert!(list_files(&forgotten).await.is_err());
+        assert_eq!(locked.requests("/api/v1/files"), 2);
+
+        // A peer that left can't be browsed, the others still can
+        let config = locked.config();
+        locked.stop().await;
+        assert!(list_files(&config).await.is_err());
+        assert_eq!(list_files(&office.config()).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_pairing_on_lan() {
+        let mut lan = MockLan::default();
+        let pinned = || PeerSetup {
+            identity: Some("laptop".to_string()),
+            ..PeerSetup::default()
+        };
+        let laptop = lan.peer(pinned()).await;
+        let desktop = lan.peer(PeerSetup::default()).await;
+        let pair = |peer: &MockPeer| paired_peer(&peer.pair_link()).unwrap().unwrap();
+
+        let mut peers = Vec::new();
+        assert_eq!(add_peer(&mut peers, pair(&laptop)), Paired::New);
+        assert_eq!(add_peer(&mut peers, pair(&desktop)), Paired::New);
+        assert_eq!(add_peer(&mut peers, pair(&laptop)), Paired::Known);
+
+        // The laptop comes back on another address with the same identity
+        laptop.stop().await;
+        let laptop = lan.peer(pinned()).await;
+        assert_eq!(add_peer(&mut peers, pair(&laptop)), Paired::Moved);
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].url, format!("http://{}", laptop.address));
+
+        // Another instance on the address the desktop left has its name,
+        // and neither gets a second entry nor pins its identity on it
+        let address = desktop.address;
+        desktop.stop().await;
+        let stranger = lan
+            .peer_at(
+                address,
+                PeerSetup {
+                    identity: Some("stranger".to_string()),
+                    ..PeerSetup::default()
+                },
+            )
+            .await;
+        assert_eq!(pair(&stranger).name, peers[1].name);
+        assert_eq!(add_peer(&mut peers, pair(&stranger)), Paired::Known);
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[1].identity, None);
+    }
+}
@@ -0,0 +1,153 @@
+//! Stable Ed25519 identity of this instance, created at first run. Clients
+//! pin its fingerprint when pairing, so they still recognise the instance
+//! after its address or port changes, and check signatures made with it on
+//! pairing bundles and webhook payloads.
+
+use std::io::Write;
+use std::path::Path;
+
+use ed25519_dalek::{Signer, SigningKey};
+use once_cell::sync::Lazy;
+use rand_core::OsRng;
+use sha2::{Digest, Sha256};
+
+/// File the private key is kept in, hex encoded, next to the settings file
+const KEY_FILE: &str = "identity.key";
+
+static IDENTITY: Lazy<Identity> =
+    Lazy::new(|| Identity::load_or_create(&settings::path().with_file_name(KEY_FILE)));
+
+/// The instance's signing key
+pub struct Identity {
+    key: SigningKey,
+}
+
+/// The identity of this instance, loaded or created on first use
+pub fn get() -> &'static Identity {
+    &IDENTITY
+}
+
+impl Identity {
+    /// A new random identity
+    pub fn generate() -> Self {
+        Self {
+            key: SigningKey::generate(&mut OsRng),
+        }
+    }
+
+    fn load(path: &Path) -> Option<Self> {
+        let bytes = hex::decode(std::fs::read_to_string(path).ok()?.trim()).ok()?;
+        Some(Self {
+            key: SigningKey::from_bytes(&bytes.try_into().ok()?),
+        })
+    }
+
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options
+            .open(path)?
+            .write_all(hex::encode(self.key.to_bytes()).as_bytes())
+    }
+
+    /// The identity stored at `path`, or a new one saved there. A key that
+    /// cannot be saved is still used for this run.
+    fn load_or_create(path: &Path) -> Self {
+        if let Some(identity) = Self::load(path) {
+            return identity;
+        }
+        if path.exists() {
+            log::error!(
+                "Unreadable server identity in {:?}, creating a new one",
+                path
+            );
+        }
+        let identity = Self::generate();
+        match identity.save(path) {
+            Ok(()) => log::info!(
+                "Created server identity {} in {:?}",
+                identity.fingerprint(),
+                path
+            ),
+            Err(e) => log::error!("Failed to save server identity to {:?}: {}", path, e),
+        }
+        identity
+    }
+
+    /// Public key, hex encoded
+    pub fn public_key(&self) -> String {
+        hex::encode(self.key.verifying_key().as_bytes())
+    }
+
+    /// SHA-256 of the public key, hex encoded, which clients pin
+    pub fn fingerprint(&self) -> String {
+        hex::encode(Sha256::digest(self.key.verifying_key().as_bytes()))
+    }
+
+    /// Ed25519 signature of `message`, hex encoded
+    pub fn sign(&self, message: &[u8]) -> String {
+        hex::encode(self.key.sign(message).to_bytes())
+    }
+}
+
+/// `url` with the identity fingerprint in its fragment, for QR codes.
+/// Browsers ignore the fragment, companion clients pin the fingerprint.
+pub fn pinned_url(url: &str, identity: &Identity) -> String {
+    format!("{}#id={}", url, identity.fingerprint())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    #[test]
+    fn test_identity_is_stable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config").join("identity.key");
+
+        let created = Identity::load_or_create(&path);
+        let loaded = Identity::load_or_create(&path);
+        assert_eq!(created.fingerprint(), loaded.fingerprint());
+        assert_eq!(created.fingerprint().len(), 64);
+
+        std::fs::write(&path, "not a key").unwrap();
+        assert_ne!(
+            Identity::load_or_create(&path).fingerprint(),
+            created.fingerprint()
+        );
+    }
+
+    #[test]
+    fn test_signature_verifies() {
+        let identity = Identity::generate();
+        let signature = identity.sign(b"payload");
+
+        let key: [u8; 32] = hex::decode(identity.public_key())
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let key = VerifyingKey::from_bytes(&key).unwrap();
+        let signature: [u8; 64] = hex::decode(signature).unwrap().try_into().unwrap();
+        let signature = Signature::from_bytes(&signature);
+        assert!(key.verify(b"payload", &signature).is_ok());
+        assert!(key.verify(b"tampered", &signature).is_err());
+    }
+
+    #[test]
+    fn test_pinned_url() {
+        let identity = Identity::generate();
+        assert_eq!(
+            pinned_url("http://192.168.1.5:8080", &identity),
+            format!("http://192.168.1.5:8080#id={}", identity.fingerprint())
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use tokio::runtime::Handle;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+use crate::config::GrpcConfig;
+use crate::models::FileInfo;
+use crate::server::FileServer;
+
+pub mod proto {
+    tonic::include_proto!("justrans.control.v1");
+}
+
+use proto::control_server::{Control, ControlServer};
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<proto::Event, Status>> + Send>>;
+
+impl From<FileInfo> for proto::File {
+    fn from(file: FileInfo) -> Self {
+        Self {
+            id: file.id,
+            name: file.name,
+            size: file.size,
+            mime_type: file.mime_type,
+        }
+    }
+}
+
+/// gRPC control service driving the default file server
+struct ControlService {
+    file_server: Arc<Mutex<FileServer>>,
+}
+
+impl ControlService {
+    fn status(&self) -> proto::ServerStatus {
+        let info = self.file_server.lock().unwrap().get_server_info();
+        proto::ServerStatus {
+            running: info.running,
+            url: info.url,
+            port: info.port as u32,
+        }
+    }
+
+    async fn set_running(&self, running: bool) -> Result<proto::ServerStatus, Status> {
+        FileServer::set_running(&self.file_server, running)
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        Ok(self.status())
+    }
+}
+
+#[tonic::async_trait]
+impl Control for ControlService {
+    async fn get_status(
+        &self,
+        _request: Request<proto::Empty>,
+    ) -> Result<Response<proto::ServerStatus>, Status> {
+        Ok(Response::new(self.status()))
+    }
+
+    async fn start_server(
+        &self,
+        _request: Request<proto::Empty>,
+    ) -> Result<Response<proto::ServerStatus>, Status> {
+        self.set_running(true).await.map(Response::new)
+    }
+
+    async fn stop_server(
+        &self,
+        _request: Request<proto::Empty>,
+    ) -> Result<Response<proto::ServerStatus>, Status> {
+        self.set_running(false).await.map(Response::new)
+    }
+
+    async fn list_files(
+        &self,
+        _request: Request<proto::Empty>,
+    ) -> Result<Response<proto::FileList>, Status> {
+        let files = self.file_server.lock().unwrap().files();
+        Ok(Response::new(proto::FileList {
+            files: files.into_iter().map(proto::File::from).collect(),
+        }))
+    }
+
+    async fn push_file(
+        &self,
+        request: Request<proto::PushFileRequest>,
+    ) -> Result<Response<proto::File>, Status> {
+        let path = request.into_inner().path;
+        let path = Path::new(&path);
+        if !path.is_absolute() {
+            return Err(Status::invalid_argument("path must be absolute"));
+        }
+        self.file_server
+            .lock()
+            .unwrap()
+            .share_local_file(path)
+            .map(|file| Response::new(file.into()))
+            .map_err(|e| Status::not_found(e.to_string()))
+    }
+
+    type SubscribeEventsStream = EventStream;
+
+    async fn subscribe_events(
+        &self,
+        _request: Request<proto::Empty>,
+    ) -> Result<Response<Self::SubscribeEventsStream>, Status> {
+        let stream = BroadcastStream::new(super::subscribe()).filter_map(|event| {
+            // Lagging subscribers skip missed events instead of failing
+            let event = event.ok()?;
+            Some(Ok(proto::Event {
+                name: event.name().to_string(),
+                json: serde_json::to_string(&event).unwrap_or_default(),
+            }))
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// Start the control API on localhost
+pub fn serve(handle: &Handle, config: &GrpcConfig, file_server: Arc<Mutex<FileServer>>) {
+    let addr = SocketAddr::from(([127, 0, 0, 1], config.port));
+    let service = ControlServer::new(ControlService { file_server });
+
+    log::info!("Starting gRPC control API on {}", addr);
+    handle.spawn(async move {
+        if let Err(e) = tonic::transport::Server::builder()
+            .add_service(service)
+            .serve(addr)
+            .await
+        {
+            log::error!("gRPC control API error: {}", e);
+        }
+    });
+}
@@ -0,0 +1,212 @@
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod mqtt;
+#[cfg(feature = "telemetry")]
+pub mod telemetry;
+pub mod webhooks;
+
+use std::sync::{Arc, Mutex};
+
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use settings::Settings;
+use tokio::runtime::Handle;
+use tokio::sync::broadcast;
+
+use crate::config::ConfigData;
+use crate::server::transfers::{TransferKind, TransferOutcome};
+use crate::server::FileServer;
+
+/// Runtime used to deliver integration events from any thread
+static RUNTIME: OnceCell<Handle> = OnceCell::new();
+
+/// In-process subscribers such as the UI and the gRPC event stream; roomy
+/// enough for the progress events of many transfers at once
+static EVENTS: Lazy<broadcast::Sender<ServerEvent>> = Lazy::new(|| broadcast::channel(256).0);
+
+/// Events published to external integrations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event")]
+pub enum ServerEvent {
+    #[serde(rename = "file.received")]
+    FileReceived {
+        id: String,
+        name: String,
+        size: u64,
+        room: Option<String>,
+    },
+    #[serde(rename = "file.deleted")]
+    FileDeleted {
+        id: String,
+        name: String,
+        room: Option<String>,
+    },
+    #[serde(rename = "file.conflict")]
+    FileConflict {
+        id: String,
+        name: String,
+        room: Option<String>,
+    },
+    #[serde(rename = "file.corrupted")]
+    FileCorrupted {
+        id: String,
+        name: String,
+        room: Option<String>,
+    },
+    #[serde(rename = "file.quarantined")]
+    FileQuarantined {
+        id: String,
+        name: String,
+        room: Option<String>,
+    },
+    #[serde(rename = "server.started")]
+    ServerStarted { url: String },
+    #[serde(rename = "server.stopped")]
+    ServerStopped { url: String },
+    #[serde(rename = "server.error")]
+    ServerError { message: String },
+    #[serde(rename = "client.connected")]
+    ClientConnected { address: String },
+    #[serde(rename = "transfer.started")]
+    TransferStarted {
+        id: u64,
+        kind: TransferKind,
+        name: Option<String>,
+        peer: Option<String>,
+        size: Option<u64>,
+    },
+    #[serde(rename = "transfer.progress")]
+    TransferProgress { id: u64, bytes: u64 },
+    #[serde(rename = "transfer.paused")]
+    TransferPaused { id: u64 },
+    #[serde(rename = "transfer.resumed")]
+    TransferResumed { id: u64 },
+    #[serde(rename = "transfer.finished")]
+    TransferFinished {
+        id: u64,
+        bytes: u64,
+        outcome: TransferOutcome,
+    },
+}
+
+impl ServerEvent {
+    /// Event name as used in the payload and in config filters
+    pub fn name(&self) -> &'static str {
+        match self {
+            ServerEvent::FileReceived { .. } => "file.received",
+            ServerEvent::FileDeleted { .. } => "file.deleted",
+            ServerEvent::FileConflict { .. } => "file.conflict",
+            ServerEvent::FileCorrupted { .. } => "file.corrupted",
+            ServerEvent::FileQuarantined { .. } => "file.quarantined",
+            ServerEvent::ServerStarted { .. } => "server.started",
+            ServerEvent::ServerStopped { .. } => "server.stopped",
+            ServerEvent::ServerError { .. } => "server.error",
+            ServerEvent::ClientConnected { .. } => "client.connected",
+            ServerEvent::TransferStarted { .. } => "transfer.started",
+            ServerEvent::TransferProgress { .. } => "transfer.progress",
+            ServerEvent::TransferPaused { .. } => "transfer.paused",
+            ServerEvent::TransferResumed { .. } => "transfer.resumed",
+            ServerEvent::TransferFinished { .. } => "transfer.finished",
+        }
+    }
+
+    /// Whether the event only goes to in-process subscribers. Transfer events
+    /// are too frequent for webhooks and MQTT, and errors and connections
+    /// are notices for the status bar.
+    pub fn is_local(&self) -> bool {
+        matches!(
+            self,
+            ServerEvent::TransferStarted { .. }
+                | ServerEvent::TransferProgress { .. }
+                | ServerEvent::TransferPaused { .. }
+                | ServerEvent::TransferResumed { .. }
+                | ServerEvent::TransferFinished { .. }
+                | ServerEvent::ServerError { .. }
+                | ServerEvent::ClientConnected { .. }
+        )
+    }
+}
+
+/// Register the runtime used for delivering events and connect to
+/// long-lived integrations such as MQTT
+pub fn init(handle: Handle) {
+    match ConfigData::instance() {
+        Ok(instance) => {
+            let mqtt = instance.lock().unwrap().integrations.mqtt.clone();
+            if let Some(mqtt) = mqtt.filter(|m| m.enabled) {
+                mqtt::init(&handle, &mqtt);
+            }
+        }
+        Err(e) => log::error!("Failed to read integrations config: {}", e),
+    }
+    let _ = RUNTIME.set(handle);
+}
+
+/// Serve the local gRPC control API for `file_server`, if enabled
+pub fn serve_control_api(
+    handle: &Handle,
+    file_server: Arc<Mutex<FileServer>>,
+) -> anyhow::Result<()> {
+    let config = ConfigData::instance()?
+        .lock()
+        .unwrap()
+        .integrations
+        .grpc
+        .clone();
+    if let Some(config) = config.filter(|g| g.enabled) {
+        #[cfg(feature = "grpc")]
+        grpc::serve(handle, &config, file_server);
+        #[cfg(not(feature = "grpc"))]
+        {
+            let _ = (handle, file_server);
+            log::warn!(
+                "gRPC control API on port {} is enabled but this build lacks the `grpc` feature",
+                config.port
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Receive every event emitted from now on
+pub fn subscribe() -> broadcast::Receiver<ServerEvent> {
+    EVENTS.subscribe()
+}
+
+/// Publish an event to all configured integrations without blocking the caller
+pub fn emit(event: ServerEvent) {
+    // No receivers is not an error
+    let _ = EVENTS.send(event.clone());
+    if event.is_local() {
+        return;
+    }
+
+    let Some(handle) = RUNTIME.get() else {
+        log::debug!(
+            "Integrations not initialized, dropping {} event",
+            event.name()
+        );
+        return;
+    };
+
+    let integrations = match ConfigData::instance() {
+        Ok(instance) => instance.lock().unwrap().integrations.clone(),
+        Err(e) => {
+            log::error!("Failed to read integrations config: {}", e);
+            return;
+        }
+    };
+
+    if let Some(publisher) = mqtt::publisher() {
+        let event = event.clone();
+        handle.spawn(async move {
+            publisher.publish_event(&event).await;
+        });
+    }
+
+    if !integrations.webhooks.is_empty() {
+        handle.spawn(async move {
+            webhooks::deliver(&integrations.webhooks, &event).await;
+        });
+    }
+}
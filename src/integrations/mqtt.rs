@@ -0,0 +1,155 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use once_cell::sync::OnceCell;
+use rumqttc::{AsyncClient, LastWill, MqttOptions, QoS};
+use serde::Serialize;
+use tokio::runtime::Handle;
+
+use super::ServerEvent;
+use crate::config::MqttConfig;
+
+static PUBLISHER: OnceCell<MqttPublisher> = OnceCell::new();
+
+/// Retained status message for dashboards such as Home Assistant
+#[derive(Debug, Serialize)]
+struct Status<'a> {
+    state: &'a str,
+    files_waiting: u64,
+    url: Option<&'a str>,
+}
+
+/// Publishes server status and events to an MQTT broker
+pub struct MqttPublisher {
+    client: AsyncClient,
+    topic_prefix: String,
+    running: AtomicBool,
+    files_waiting: AtomicU64,
+}
+
+impl MqttPublisher {
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{}", self.topic_prefix, name)
+    }
+
+    /// Update the tracked status from an event and publish it
+    pub async fn publish_event(&self, event: &ServerEvent) {
+        let url = match event {
+            ServerEvent::FileReceived { .. } => {
+                self.files_waiting.fetch_add(1, Ordering::SeqCst);
+                None
+            }
+            ServerEvent::FileDeleted { .. } => {
+                let _ = self
+                    .files_waiting
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                        Some(n.saturating_sub(1))
+                    });
+                None
+            }
+            ServerEvent::FileConflict { .. }
+            | ServerEvent::FileCorrupted { .. }
+            | ServerEvent::FileQuarantined { .. }
+            | ServerEvent::ServerError { .. }
+            | ServerEvent::ClientConnected { .. }
+            | ServerEvent::TransferStarted { .. }
+            | ServerEvent::TransferProgress { .. }
+            | ServerEvent::TransferPaused { .. }
+            | ServerEvent::TransferResumed { .. }
+            | ServerEvent::TransferFinished { .. } => None,
+            ServerEvent::ServerStarted { url } => {
+                self.running.store(true, Ordering::SeqCst);
+                Some(url.as_str())
+            }
+            ServerEvent::ServerStopped { url } => {
+                self.running.store(false, Ordering::SeqCst);
+                self.files_waiting.store(0, Ordering::SeqCst);
+                Some(url.as_str())
+            }
+        };
+
+        match serde_json::to_vec(event) {
+            Ok(payload) => {
+                if let Err(e) = self
+                    .client
+                    .publish(self.topic("events"), QoS::AtLeastOnce, false, payload)
+                    .await
+                {
+                    log::warn!("Failed to publish MQTT event: {}", e);
+                }
+            }
+            Err(e) => log::error!("Failed to serialize MQTT event: {}", e),
+        }
+
+        let status = Status {
+            state: if self.running.load(Ordering::SeqCst) {
+                "running"
+            } else {
+                "stopped"
+            },
+            files_waiting: self.files_waiting.load(Ordering::SeqCst),
+            url,
+        };
+        if let Ok(payload) = serde_json::to_vec(&status) {
+            if let Err(e) = self
+                .client
+                .publish(self.topic("status"), QoS::AtLeastOnce, true, payload)
+                .await
+            {
+                log::warn!("Failed to publish MQTT status: {}", e);
+            }
+        }
+    }
+}
+
+/// Connect to the broker and keep the connection alive in the background
+pub fn init(handle: &Handle, config: &MqttConfig) {
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if !config.username.is_empty() {
+        options.set_credentials(&config.username, &config.password);
+    }
+
+    let topic_prefix = config.topic_prefix.trim_end_matches('/').to_string();
+    let offline = serde_json::to_vec(&Status {
+        state: "offline",
+        files_waiting: 0,
+        url: None,
+    })
+    .unwrap_or_default();
+    options.set_last_will(LastWill::new(
+        format!("{}/status", topic_prefix),
+        offline,
+        QoS::AtLeastOnce,
+        true,
+    ));
+
+    let _guard = handle.enter();
+    let (client, mut eventloop) = AsyncClient::new(options, 32);
+    handle.spawn(async move {
+        loop {
+            if let Err(e) = eventloop.poll().await {
+                log::warn!("MQTT connection error: {}, retrying in 5s", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    });
+
+    log::info!(
+        "Publishing MQTT status to {}:{} under '{}'",
+        config.host,
+        config.port,
+        topic_prefix
+    );
+    let _ = PUBLISHER.set(MqttPublisher {
+        client,
+        topic_prefix,
+        running: AtomicBool::new(false),
+        files_waiting: AtomicU64::new(0),
+    });
+}
+
+/// The publisher, if MQTT is enabled
+pub fn publisher() -> Option<&'static MqttPublisher> {
+    PUBLISHER.get()
+}
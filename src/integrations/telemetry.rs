@@ -0,0 +1,312 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use settings::Settings;
+use tokio::runtime::Handle;
+use tokio::sync::broadcast::error::RecvError;
+
+use super::ServerEvent;
+use crate::config::ConfigData;
+use crate::models::unix_now;
+
+/// File the counters are kept in between runs, next to the settings file
+const STATE_FILE: &str = "telemetry.json";
+
+/// Length of one reporting period
+const PERIOD_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// How often to check whether a report is due
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+static STARTED: OnceCell<()> = OnceCell::new();
+
+static COUNTERS: Lazy<Mutex<Counters>> = Lazy::new(|| Mutex::new(Counters::load(&state_path())));
+
+fn state_path() -> PathBuf {
+    settings::path().with_file_name(STATE_FILE)
+}
+
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_default()
+});
+
+/// Coarse counters for the current reporting period
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Counters {
+    pub period_start: u64,
+    pub transfers: u64,
+    pub transferred_bytes: u64,
+    pub conflicts: u64,
+    pub server_starts: u64,
+}
+
+impl Counters {
+    fn load(path: &Path) -> Self {
+        std::fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Count an event; file names, rooms and URLs are never kept
+    pub fn record(&mut self, event: &ServerEvent, now: u64) {
+        if self.period_start == 0 {
+            self.period_start = now;
+        }
+        match event {
+            ServerEvent::FileReceived { size, .. } => {
+                self.transfers += 1;
+                self.transferred_bytes += size;
+            }
+            ServerEvent::FileConflict { .. } => self.conflicts += 1,
+            ServerEvent::ServerStarted { .. } => self.server_starts += 1,
+            ServerEvent::FileDeleted { .. }
+            | ServerEvent::FileCorrupted { .. }
+            | ServerEvent::FileQuarantined { .. }
+            | ServerEvent::ServerStopped { .. }
+            | ServerEvent::ServerError { .. }
+            | ServerEvent::ClientConnected { .. }
+            | ServerEvent::TransferStarted { .. }
+            | ServerEvent::TransferProgress { .. }
+            | ServerEvent::TransferPaused { .. }
+            | ServerEvent::TransferResumed { .. }
+            | ServerEvent::TransferFinished { .. } => {}
+        }
+    }
+}
+
+/// Everything that is sent for one reporting period
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub version: &'static str,
+    pub os: &'static str,
+    pub transfers_per_week: u64,
+    pub average_file_size: &'static str,
+    pub conflicts: u64,
+    pub server_starts: u64,
+    pub features: Vec<&'static str>,
+}
+
+/// Size range of the average transfer, so that no exact size is reported
+pub fn size_class(bytes: u64) -> &'static str {
+    const MB: u64 = 1024 * 1024;
+    match bytes {
+        0 => "none",
+        b if b < MB => "<1MB",
+        b if b < 10 * MB => "1-10MB",
+        b if b < 100 * MB => "10-100MB",
+        b if b < 1024 * MB => "100MB-1GB",
+        _ => ">1GB",
+    }
+}
+
+/// Optional features switched on in the config
+pub fn features(config: &ConfigData) -> Vec<&'static str> {
+    let integrations = &config.integrations;
+    [
+        (
+            config.storage.organize_media_by_date,
+            "organize_media_by_date",
+        ),
+        (!config.profiles.is_empty(), "profiles"),
+        (!config.networks.is_empty(), "network_overlays"),
+        (!integrations.webhooks.is_empty(), "webhooks"),
+        (
+            integrations.mqtt.as_ref().is_some_and(|m| m.enabled),
+            "mqtt",
+        ),
+        (
+            integrations.grpc.as_ref().is_some_and(|g| g.enabled),
+            "grpc",
+        ),
+        (config.display.theme == "dark", "dark_theme"),
+    ]
+    .into_iter()
+    .filter_map(|(enabled, name)| enabled.then_some(name))
+    .collect()
+}
+
+/// Build the report for the counters collected up to `now`
+pub fn build_report(counters: &Counters, config: &ConfigData, now: u64) -> Report {
+    // Periods can run long when the app was not running at the due date
+    let weeks = (now.saturating_sub(counters.period_start) / PERIOD_SECS).max(1);
+    let average = counters
+        .transferred_bytes
+        .checked_div(counters.transfers)
+        .unwrap_or(0);
+    Report {
+        version: env!("CARGO_PKG_VERSION"),
+        os: std::env::consts::OS,
+        transfers_per_week: counters.transfers / weeks,
+        average_file_size: size_class(average),
+        conflicts: counters.conflicts,
+        server_starts: counters.server_starts,
+        features: features(config),
+    }
+}
+
+/// The report for the current period exactly as it would be sent
+pub fn preview(config: &ConfigData) -> String {
+    let report = build_report(&COUNTERS.lock().unwrap(), config, unix_now());
+    serde_json::to_string_pretty(&report).unwrap_or_default()
+}
+
+fn current_config() -> Option<ConfigData> {
+    match ConfigData::instance() {
+        Ok(instance) => Some(instance.lock().unwrap().clone()),
+        Err(e) => {
+            log::error!("Failed to read telemetry config: {}", e);
+            None
+        }
+    }
+}
+
+/// Send the report once a full period has passed, then start a new one
+async fn send_if_due() {
+    let Some(config) = current_config() else {
+        return;
+    };
+    if !config.telemetry.enabled || config.telemetry.endpoint.is_empty() {
+        return;
+    }
+
+    let now = unix_now();
+    let report = {
+        let counters = COUNTERS.lock().unwrap();
+        if counters.period_start == 0 || now < counters.period_start + PERIOD_SECS {
+            return;
+        }
+        build_report(&counters, &config, now)
+    };
+
+    match CLIENT
+        .post(&config.telemetry.endpoint)
+        .json(&report)
+        .send()
+        .await
+    {
+        Ok(response) if response.status().is_success() => {
+            log::info!("Sent weekly usage statistics");
+            let mut counters = COUNTERS.lock().unwrap();
+            *counters = Counters {
+                period_start: now,
+                ..Counters::default()
+            };
+            if let Err(e) = counters.save(&state_path()) {
+                log::warn!("Failed to save usage statistics: {}", e);
+            }
+        }
+        Ok(response) => log::warn!(
+            "Usage statistics endpoint answered with status {}",
+            response.status()
+        ),
+        Err(e) => log::warn!("Failed to send usage statistics: {}", e),
+    }
+}
+
+/// Start counting events and sending weekly reports. Does nothing if it was
+/// already started; counting stops while the config flag is off.
+pub fn init(handle: &Handle) {
+    if STARTED.set(()).is_err() {
+        return;
+    }
+    log::info!("Anonymous usage statistics enabled");
+
+    let mut events = super::subscribe();
+    handle.spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    let enabled = current_config()
+                        .map(|c| c.telemetry.enabled)
+                        .unwrap_or(false);
+                    if enabled {
+                        let mut counters = COUNTERS.lock().unwrap();
+                        counters.record(&event, unix_now());
+                        if let Err(e) = counters.save(&state_path()) {
+                            log::warn!("Failed to save usage statistics: {}", e);
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+
+    handle.spawn(async {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            send_if_due().await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_counts_without_names() {
+        let mut counters = Counters::default();
+        counters.record(
+            &ServerEvent::FileReceived {
+                id: "1".to_string(),
+                name: "secret-plans.pdf".to_string(),
+                size: 3 * 1024 * 1024,
+                room: None,
+            },
+            1000,
+        );
+        counters.record(
+            &ServerEvent::ServerStarted {
+                url: "http://192.168.1.2:8080".to_string(),
+            },
+            2000,
+        );
+
+        assert_eq!(counters.period_start, 1000);
+        assert_eq!(counters.transfers, 1);
+        assert_eq!(counters.server_starts, 1);
+
+        let json = serde_json::to_string(&counters).unwrap();
+        assert!(!json.contains("secret-plans") && !json.contains("192.168"));
+    }
+
+    #[test]
+    fn test_build_report() {
+        let counters = Counters {
+            period_start: 0,
+            transfers: 10,
+            transferred_bytes: 50 * 1024 * 1024,
+            conflicts: 1,
+            server_starts: 2,
+        };
+        let mut config = ConfigData::default();
+        config.storage.organize_media_by_date = true;
+
+        let report = build_report(&counters, &config, 2 * PERIOD_SECS);
+        assert_eq!(report.transfers_per_week, 5);
+        assert_eq!(report.average_file_size, "1-10MB");
+        assert_eq!(report.features, vec!["organize_media_by_date"]);
+    }
+
+    #[test]
+    fn test_size_class() {
+        assert_eq!(size_class(0), "none");
+        assert_eq!(size_class(512), "<1MB");
+        assert_eq!(size_class(2 * 1024 * 1024 * 1024), ">1GB");
+    }
+}
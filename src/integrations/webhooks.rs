@@ -0,0 +1,110 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use sha2::Sha256;
+
+use super::ServerEvent;
+use crate::config::WebhookConfig;
+use crate::identity;
+
+/// Header carrying the HMAC-SHA256 signature of the request body
+pub const SIGNATURE_HEADER: &str = "X-JusTrans-Signature";
+
+/// Header carrying the Ed25519 signature of the request body by the
+/// instance's identity, hex encoded
+pub const IDENTITY_SIGNATURE_HEADER: &str = "X-JusTrans-Identity-Signature";
+
+/// Header carrying the event name
+pub const EVENT_HEADER: &str = "X-JusTrans-Event";
+
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap_or_default()
+});
+
+/// Build the JSON body sent for an event
+pub fn payload(event: &ServerEvent) -> serde_json::Result<String> {
+    let mut value = serde_json::to_value(event)?;
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    value["timestamp"] = timestamp.into();
+    serde_json::to_string(&value)
+}
+
+/// Sign a body with the webhook secret, formatted as `sha256=<hex>`
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Send an event to every webhook subscribed to it
+pub async fn deliver(webhooks: &[WebhookConfig], event: &ServerEvent) {
+    let body = match payload(event) {
+        Ok(body) => body,
+        Err(e) => {
+            log::error!("Failed to serialize {} event: {}", event.name(), e);
+            return;
+        }
+    };
+
+    for webhook in webhooks.iter().filter(|w| w.accepts(event.name())) {
+        let mut request = CLIENT
+            .post(&webhook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, event.name())
+            .header(
+                IDENTITY_SIGNATURE_HEADER,
+                identity::get().sign(body.as_bytes()),
+            )
+            .body(body.clone());
+        if !webhook.secret.is_empty() {
+            request = request.header(SIGNATURE_HEADER, sign(&webhook.secret, &body));
+        }
+
+        match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                log::debug!("Delivered {} event to {}", event.name(), webhook.url);
+            }
+            Ok(response) => log::warn!(
+                "Webhook {} rejected {} event with status {}",
+                webhook.url,
+                event.name(),
+                response.status()
+            ),
+            Err(e) => log::warn!("Failed to deliver webhook to {}: {}", webhook.url, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        // Reference value from RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_payload() {
+        let body = payload(&ServerEvent::ServerStarted {
+            url: "http://127.0.0.1:8080".to_string(),
+        })
+        .unwrap();
+        let value: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(value["event"], "server.started");
+        assert_eq!(value["url"], "http://127.0.0.1:8080");
+        assert!(value["timestamp"].is_u64());
+    }
+}
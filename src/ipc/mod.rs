@@ -0,0 +1,315 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Largest frame accepted from a peer
+const MAX_FRAME_LEN: u32 = 1024 * 1024;
+
+/// Requests accepted by the running instance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum IpcRequest {
+    /// Bring the running window to the front (single-instance activation)
+    Activate,
+    /// Add local files to the share
+    Share { paths: Vec<PathBuf> },
+    /// Handle a `justrans://` link
+    OpenLink { url: String },
+    /// Queue the downloads listed in a `.justrans` bundle
+    OpenBundle { path: PathBuf },
+}
+
+/// Reply sent back for every request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IpcResponse {
+    pub ok: bool,
+    pub message: String,
+}
+
+impl IpcResponse {
+    pub fn ok(message: impl Into<String>) -> Self {
+        Self {
+            ok: true,
+            message: message.into(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            message: message.into(),
+        }
+    }
+}
+
+/// Handler invoked for each request received by the running instance
+pub type IpcHandler = Arc<dyn Fn(IpcRequest) -> IpcResponse + Send + Sync>;
+
+/// Write one message as a big-endian u32 length followed by its JSON body
+async fn write_frame<W, T>(writer: &mut W, message: &T) -> anyhow::Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let payload = serde_json::to_vec(message)?;
+    let len = u32::try_from(payload.len())
+        .ok()
+        .filter(|len| *len <= MAX_FRAME_LEN)
+        .ok_or_else(|| anyhow::anyhow!("Message too large ({} bytes)", payload.len()))?;
+    writer.write_u32(len).await?;
+    writer.write_all(&payload).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Read one length-prefixed JSON message
+async fn read_frame<R, T>(reader: &mut R) -> anyhow::Result<T>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    let len = reader.read_u32().await?;
+    if len > MAX_FRAME_LEN {
+        return Err(anyhow::anyhow!("Frame too large ({} bytes)", len));
+    }
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+/// Read one request frame and answer it
+async fn handle_connection<S>(mut stream: S, handler: IpcHandler)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let response = match read_frame::<_, IpcRequest>(&mut stream).await {
+        Ok(request) => {
+            log::debug!("IPC request: {:?}", request);
+            handler(request)
+        }
+        Err(e) => {
+            log::warn!("Invalid IPC request: {}", e);
+            IpcResponse::error(format!("Invalid request: {}", e))
+        }
+    };
+
+    if let Err(e) = write_frame(&mut stream, &response).await {
+        log::warn!("Failed to write IPC response: {}", e);
+    }
+}
+
+/// Write a request and wait for the response on an open connection
+async fn exchange<S>(mut stream: S, request: &IpcRequest) -> anyhow::Result<IpcResponse>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    write_frame(&mut stream, request).await?;
+    read_frame(&mut stream).await
+}
+
+/// Name of the endpoint for `user`. Instances started with their own
+/// settings file get their own endpoint, so they can run side by side.
+fn endpoint_name(user: &str, settings_path: &Path) -> String {
+    if settings_path == Path::new(settings::DEFAULT_PATH) {
+        return format!("justrans-{}", user);
+    }
+    let path = settings_path
+        .canonicalize()
+        .unwrap_or_else(|_| settings_path.to_path_buf());
+    let hash = hex::encode(Sha256::digest(path.to_string_lossy().as_bytes()));
+    format!("justrans-{}-{}", user, &hash[..8])
+}
+
+#[cfg(unix)]
+mod endpoint {
+    use super::*;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use tokio::net::{UnixListener, UnixStream};
+
+    /// Socket path, private to the current user
+    pub fn path() -> PathBuf {
+        let dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        let user = std::env::var("USER").unwrap_or_else(|_| "default".to_string());
+        dir.join(format!("{}.sock", endpoint_name(&user, &settings::path())))
+    }
+
+    pub async fn serve(handler: IpcHandler) -> anyhow::Result<()> {
+        let path = path();
+        if path.exists() {
+            if UnixStream::connect(&path).await.is_ok() {
+                return Err(anyhow::anyhow!(
+                    "Another instance is listening on {:?}",
+                    path
+                ));
+            }
+            // Stale socket left behind by a crashed instance
+            std::fs::remove_file(&path)?;
+        }
+
+        // Access is limited by the socket's permissions and checked again
+        // against the peer's uid, since the fallback dir may be shared
+        let listener = UnixListener::bind(&path)?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        let owner = std::fs::metadata(&path)?.uid();
+        log::info!("Listening for local commands on {:?}", path);
+
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => match stream.peer_cred() {
+                        Ok(cred) if cred.uid() == owner => {
+                            tokio::spawn(handle_connection(stream, handler.clone()));
+                        }
+                        Ok(cred) => {
+                            log::warn!("Rejected IPC connection from uid {}", cred.uid())
+                        }
+                        Err(e) => log::warn!("Failed to read IPC peer credentials: {}", e),
+                    },
+                    Err(e) => log::warn!("Failed to accept IPC connection: {}", e),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    pub async fn send(request: &IpcRequest) -> anyhow::Result<IpcResponse> {
+        let stream = UnixStream::connect(path()).await?;
+        exchange(stream, request).await
+    }
+
+    pub async fn is_listening() -> bool {
+        UnixStream::connect(path()).await.is_ok()
+    }
+}
+
+#[cfg(windows)]
+mod endpoint {
+    use super::*;
+    use tokio::net::windows::named_pipe::{ClientOptions, NamedPipeServer, ServerOptions};
+
+    /// Pipe name for the current user
+    pub fn path() -> PathBuf {
+        let user = std::env::var("USERNAME").unwrap_or_else(|_| "default".to_string());
+        PathBuf::from(format!(
+            r"\\.\pipe\{}",
+            endpoint_name(&user, &settings::path())
+        ))
+    }
+
+    /// The default pipe security only grants write access to the creating
+    /// user, administrators and SYSTEM; remote clients are rejected
+    fn create(name: &PathBuf, first: bool) -> std::io::Result<NamedPipeServer> {
+        ServerOptions::new()
+            .first_pipe_instance(first)
+            .reject_remote_clients(true)
+            .create(name)
+    }
+
+    pub async fn serve(handler: IpcHandler) -> anyhow::Result<()> {
+        let name = path();
+        let mut server = create(&name, true)?;
+        log::info!("Listening for local commands on {:?}", name);
+
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = server.connect().await {
+                    log::warn!("Failed to accept IPC connection: {}", e);
+                    continue;
+                }
+                let connected = server;
+                server = match create(&name, false) {
+                    Ok(server) => server,
+                    Err(e) => {
+                        log::error!("Failed to create IPC pipe: {}", e);
+                        return;
+                    }
+                };
+                tokio::spawn(handle_connection(connected, handler.clone()));
+            }
+        });
+        Ok(())
+    }
+
+    pub async fn send(request: &IpcRequest) -> anyhow::Result<IpcResponse> {
+        let client = ClientOptions::new().open(path())?;
+        exchange(client, request).await
+    }
+
+    pub async fn is_listening() -> bool {
+        ClientOptions::new().open(path()).is_ok()
+    }
+}
+
+/// Start accepting requests from other processes of the same user
+pub async fn serve(handler: IpcHandler) -> anyhow::Result<()> {
+    endpoint::serve(handler).await
+}
+
+/// Send a request to the running instance
+pub async fn send(request: &IpcRequest) -> anyhow::Result<IpcResponse> {
+    endpoint::send(request).await.map_err(|e| {
+        anyhow::anyhow!(
+            "Could not reach a running JusTrans instance at {:?}: {}",
+            endpoint::path(),
+            e
+        )
+    })
+}
+
+/// Whether an instance is accepting requests
+pub async fn is_running() -> bool {
+    endpoint::is_listening().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_request_roundtrip() {
+        let (client, server) = tokio::io::duplex(1024);
+        let handler: IpcHandler = Arc::new(|request| match request {
+            IpcRequest::Share { paths } => IpcResponse::ok(format!("{} file(s)", paths.len())),
+            _ => IpcResponse::error("unexpected"),
+        });
+        tokio::spawn(handle_connection(server, handler));
+
+        let response = exchange(
+            client,
+            &IpcRequest::Share {
+                paths: vec![PathBuf::from("/tmp/a"), PathBuf::from("/tmp/b")],
+            },
+        )
+        .await
+        .unwrap();
+        assert!(response.ok);
+        assert_eq!(response.message, "2 file(s)");
+    }
+
+    #[tokio::test]
+    async fn test_oversized_frame_rejected() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_u32(MAX_FRAME_LEN + 1).await.unwrap();
+        let result = read_frame::<_, IpcRequest>(&mut server).await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_endpoint_name() {
+        let default = Path::new(settings::DEFAULT_PATH);
+        assert_eq!(endpoint_name("me", default), "justrans-me");
+
+        let portable = endpoint_name("me", Path::new("/media/usb/justrans/settings.yaml"));
+        assert!(portable.starts_with("justrans-me-"));
+        assert_ne!(
+            portable,
+            endpoint_name("me", Path::new("/tmp/test-instance/settings.yaml"))
+        );
+    }
+}
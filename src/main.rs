@@ -1,19 +1,44 @@
 #![windows_subsystem = "windows"]
+mod auto_open;
+mod backup;
+mod cli;
 mod config;
+mod downloads;
+mod identity;
+mod integrations;
+mod ipc;
+mod metadata;
 mod models;
 mod server;
+mod service;
+mod shell;
+mod status;
 
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
 use anyhow::Result;
 use log::{error, info};
 use qrcode::generate_qr_code_for_url;
-use settings::Settings;
-use slint::{ComponentHandle, SharedString};
-use tokio::runtime::Runtime;
+use settings::{SettingKind, Settings, SettingsSchema};
+use slint::{ComponentHandle, Model, ModelRc, SharedString, VecModel};
+use tokio::runtime::{Handle, Runtime};
 
-use config::ConfigData;
+use auto_open::AutoOpen;
+use backup::snapshots;
+use config::{AutoOpenAction, ConfigData, PeerConfig};
+use downloads::{peers, DownloadQueue, DownloadState};
+use models::unix_now;
+use server::conflicts::{ConflictResolution, PendingConflict};
+use server::diagnostics;
+use server::file_server::ServerInfo;
+use server::firewall::{self, FirewallStatus};
+use server::reachability::run_connection_test;
+use server::remote;
+use server::schedule;
+use server::transfers::{self, TransferList};
 use server::FileServer;
+use status::{server_stats, Severity, Status, StatusFeed};
 
 // Add this const to get version from Cargo.toml
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -23,24 +48,175 @@ slint::include_modules!();
 #[derive(Clone)]
 struct AppData {
     file_server: Arc<Mutex<FileServer>>,
-    runtime: Arc<Runtime>,
+    profile_servers: Arc<Mutex<HashMap<String, Arc<Mutex<FileServer>>>>>,
+    /// The app's single runtime, owned by `main`. Callbacks hand their work
+    /// to it and report back through the Slint event loop.
+    runtime: Handle,
+    downloads: DownloadQueue,
+    /// Transfers in flight on the servers, followed through their events
+    server_transfers: Arc<Mutex<TransferList>>,
 }
 
 impl AppData {
-    fn new() -> Result<Self> {
-        let runtime = Arc::new(Runtime::new()?);
-
+    fn new(runtime: Handle) -> Result<Self> {
         // Create file server (will get config from singleton instance)
         let file_server = Arc::new(Mutex::new(FileServer::new()?));
 
         Ok(Self {
             file_server,
+            profile_servers: Arc::new(Mutex::new(HashMap::new())),
             runtime,
+            downloads: DownloadQueue::default(),
+            server_transfers: Arc::new(Mutex::new(TransferList::default())),
+        })
+    }
+
+    /// Get the server for a profile, creating it on first use
+    fn profile_server(&self, name: &str) -> Result<Arc<Mutex<FileServer>>> {
+        let mut servers = self.profile_servers.lock().unwrap();
+        if let Some(server) = servers.get(name) {
+            return Ok(server.clone());
+        }
+        let server = Arc::new(Mutex::new(FileServer::for_profile(name)?));
+        servers.insert(name.to_string(), server.clone());
+        Ok(server)
+    }
+
+    /// Build the room list shown in the UI
+    fn room_items(&self) -> Vec<RoomItem> {
+        let file_server = self.file_server.lock().unwrap();
+        let url = file_server.get_server_info().url;
+
+        file_server
+            .rooms()
+            .into_iter()
+            .map(|room| RoomItem {
+                url: SharedString::from(format!("{}/r/{}", url, room.name)),
+                name: SharedString::from(room.name),
+                has_pin: room.pin.is_some(),
+            })
+            .collect()
+    }
+
+    /// All servers, the default one first
+    fn servers(&self) -> Vec<Arc<Mutex<FileServer>>> {
+        let mut servers = vec![self.file_server.clone()];
+        servers.extend(self.profile_servers.lock().unwrap().values().cloned());
+        servers
+    }
+
+    /// A received file of any server, looked up by its id
+    fn received_file(&self, id: &str) -> Option<models::FileInfo> {
+        self.servers().into_iter().find_map(|server| {
+            let files = server.lock().unwrap().stored_files();
+            files
+                .into_iter()
+                .map(|(_, file)| file)
+                .find(|file| file.id == id)
         })
     }
+
+    /// Oldest upload on any server waiting for a conflict review
+    fn next_conflict(&self) -> Option<PendingConflict> {
+        self.servers().iter().find_map(|server| {
+            server
+                .lock()
+                .unwrap()
+                .pending_conflicts()
+                .into_iter()
+                .next()
+        })
+    }
+
+    /// Resolve a conflict on whichever server holds it
+    fn resolve_conflict(&self, id: &str, resolution: ConflictResolution) -> Result<()> {
+        let server = self
+            .servers()
+            .into_iter()
+            .find(|server| {
+                server
+                    .lock()
+                    .unwrap()
+                    .pending_conflicts()
+                    .iter()
+                    .any(|c| c.id == id)
+            })
+            .ok_or_else(|| anyhow::anyhow!("The upload is no longer waiting for review"))?;
+        let server = server.lock().unwrap();
+        server.resolve_conflict(id, resolution)
+    }
+
+    /// Build the profile list shown in the UI
+    fn profile_items(&self) -> Vec<ProfileItem> {
+        let profiles = match ConfigData::instance() {
+            Ok(instance) => instance.lock().unwrap().profiles.clone(),
+            Err(_) => Vec::new(),
+        };
+        let servers = self.profile_servers.lock().unwrap();
+
+        profiles
+            .into_iter()
+            .map(|profile| {
+                let info = servers
+                    .get(&profile.name)
+                    .map(|server| server.lock().unwrap().get_server_info());
+                ProfileItem {
+                    name: SharedString::from(profile.name),
+                    url: SharedString::from(
+                        info.as_ref()
+                            .map(|info| info.url.clone())
+                            .unwrap_or_else(|| format!("port {}", profile.port)),
+                    ),
+                    read_only: profile.read_only,
+                    has_pin: profile.pin.as_deref().is_some_and(|pin| !pin.is_empty()),
+                    running: info.map(|info| info.running).unwrap_or(false),
+                }
+            })
+            .collect()
+    }
 }
 
 fn main() -> Result<()> {
+    // Commands such as `justrans share` talk to the running instance and exit
+    let mut pending_link = None;
+    let mut pending_bundle = None;
+    // Options take effect before the settings are loaded, and may move to
+    // the portable directory once the command's own paths are resolved
+    let (options, args) = cli::parse_options(std::env::args().skip(1))?;
+    let command = cli::parse(args.into_iter())?.relative_to(&std::env::current_dir()?);
+    let service = options.service;
+    let present = options.present;
+    let portable_dir = options.apply()?;
+    if service {
+        return match command {
+            cli::Command::Gui => service::run(portable_dir),
+            _ => Err(anyhow::anyhow!(
+                "--service starts the server and takes no command"
+            )),
+        };
+    }
+    match command {
+        cli::Command::Gui => {
+            // Single instance: a second launch brings the first one forward
+            if cli::activate_running_instance()? {
+                return Ok(());
+            }
+        }
+        cli::Command::OpenLink(link) => {
+            if cli::forward_link(&link)? {
+                return Ok(());
+            }
+            pending_link = Some(link);
+        }
+        cli::Command::OpenBundle(path) => {
+            if cli::forward_bundle(&path)? {
+                return Ok(());
+            }
+            pending_bundle = Some(path);
+        }
+        command => return cli::run(command),
+    }
+
     // Initialize logger with timestamped log file
     let log_path = logger::timestamped_log_path()?;
     logger::init(&log_path, log::Level::Info)?;
@@ -49,9 +225,28 @@ fn main() -> Result<()> {
         "Starting JusTrans v{} with log file at {:?}",
         VERSION, log_path
     );
+    if let Some(dir) = &portable_dir {
+        info!("Portable mode, keeping all data in {:?}", dir);
+    }
+
+    // The one runtime for servers, integrations and background work; the
+    // Slint event loop runs on this thread next to it
+    let runtime = Runtime::new()?;
 
     // Create app data (includes loading settings)
-    let app_data = Arc::new(AppData::new()?);
+    let app_data = Arc::new(AppData::new(runtime.handle().clone())?);
+    integrations::init(app_data.runtime.clone());
+
+    // Local gRPC control API
+    integrations::serve_control_api(&app_data.runtime, app_data.file_server.clone())?;
+
+    // Opt-in anonymous usage statistics
+    #[cfg(feature = "telemetry")]
+    {
+        if ConfigData::instance()?.lock().unwrap().telemetry.enabled {
+            integrations::telemetry::init(&app_data.runtime);
+        }
+    }
 
     // Log some settings info
     info!(
@@ -74,21 +269,336 @@ fn main() -> Result<()> {
     // Set initial UI state
     {
         let server_info = app_data.file_server.lock().unwrap().get_server_info();
-        ui.set_server_url(SharedString::from(server_info.url));
+        show_server_urls(&ui, &server_info);
         ui.set_server_running(server_info.running);
-        ui.set_status_message(SharedString::from("Server not running"));
+        show_status(&ui, Status::info("Server not running"));
 
         // Set config values from singleton instance
         let instance = ConfigData::instance()?;
         let config = instance.lock().unwrap();
-        ui.set_config_server_port(config.server.port as i32);
-        ui.set_config_upload_chunk_size_mb(config.server.upload_chunk_size_mb as i32);
         ui.set_config_theme(SharedString::from(config.display.theme.clone()));
-        ui.set_config_storage_dir(SharedString::from(config.storage.storage_dir.clone()));
+        show_schedule(&ui, &config);
 
         info!("Applied theme: {}", config.display.theme);
     }
 
+    ui.set_profiles(ModelRc::new(VecModel::from(app_data.profile_items())));
+
+    // Accept commands such as `justrans share` from other processes
+    {
+        let file_server = app_data.file_server.clone();
+        let downloads = app_data.downloads.clone();
+        let ui_handle = Mutex::new(ui.as_weak());
+        let handler: ipc::IpcHandler = Arc::new(move |request| {
+            let response = match request {
+                ipc::IpcRequest::Activate => {
+                    let ui_handle = ui_handle.lock().unwrap().clone();
+                    let _ = slint::invoke_from_event_loop(move || {
+                        if let Some(ui) = ui_handle.upgrade() {
+                            if let Err(e) = ui.show() {
+                                error!("Failed to show window: {}", e);
+                            }
+                        }
+                    });
+                    ipc::IpcResponse::ok("JusTrans is already running")
+                }
+                ipc::IpcRequest::Share { paths } => {
+                    let file_server = file_server.lock().unwrap();
+                    let mut errors = Vec::new();
+                    for path in &paths {
+                        if let Err(e) = file_server.share_local_file(path) {
+                            errors.push(format!("{}: {}", path.display(), e));
+                        }
+                    }
+
+                    let message = format!("Shared {} file(s)", paths.len() - errors.len());
+                    if errors.is_empty() {
+                        ipc::IpcResponse::ok(message)
+                    } else {
+                        ipc::IpcResponse::error(format!(
+                            "{}; failed: {}",
+                            message,
+                            errors.join("; ")
+                        ))
+                    }
+                }
+                ipc::IpcRequest::OpenLink { url } => match shell::link::open_link(&url) {
+                    Ok(message) => ipc::IpcResponse::ok(message),
+                    Err(e) => ipc::IpcResponse::error(format!("Failed to open link: {}", e)),
+                },
+                ipc::IpcRequest::OpenBundle { path } => {
+                    match shell::bundle::open_bundle(&path, &downloads) {
+                        Ok(message) => ipc::IpcResponse::ok(message),
+                        Err(e) => ipc::IpcResponse::error(format!("Failed to open bundle: {}", e)),
+                    }
+                }
+            };
+
+            let ui_handle = ui_handle.lock().unwrap().clone();
+            let status = if response.ok {
+                Status::info(response.message.clone())
+            } else {
+                Status::error(response.message.clone())
+            };
+            let _ = slint::invoke_from_event_loop(move || {
+                if let Some(ui) = ui_handle.upgrade() {
+                    show_status(&ui, status);
+                }
+            });
+            response
+        });
+        if let Err(e) = runtime.block_on(ipc::serve(handler)) {
+            error!("Failed to start local command channel: {}", e);
+        }
+    }
+
+    // Keep the main view in sync when the server is started or stopped,
+    // also from outside the UI, e.g. through the gRPC control API, and
+    // report notable server events in the status bar
+    {
+        let file_server = app_data.file_server.clone();
+        let ui_handle = Mutex::new(ui.as_weak());
+        let mut events = integrations::subscribe();
+        let mut feed = StatusFeed::default();
+        app_data.runtime.spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                };
+                let server_info = file_server.lock().unwrap().get_server_info();
+                let Some(status) = feed.update(&event, &server_info) else {
+                    continue;
+                };
+                let started_or_stopped = matches!(
+                    event,
+                    integrations::ServerEvent::ServerStarted { .. }
+                        | integrations::ServerEvent::ServerStopped { .. }
+                );
+                let ui_handle = ui_handle.lock().unwrap().clone();
+                let _ = slint::invoke_from_event_loop(move || {
+                    if let Some(ui) = ui_handle.upgrade() {
+                        if started_or_stopped {
+                            show_server_urls(&ui, &server_info);
+                            ui.set_server_running(server_info.running);
+                        }
+                        show_status(&ui, status);
+                    }
+                });
+            }
+        });
+    }
+
+    // Ask the user to review uploads whose name is already taken
+    {
+        let app_data = app_data.clone();
+        let ui_handle = Mutex::new(ui.as_weak());
+        let mut events = integrations::subscribe();
+        app_data.runtime.clone().spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(integrations::ServerEvent::FileConflict { .. }) => {}
+                    Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+                let conflict = app_data.next_conflict();
+                let ui_handle = ui_handle.lock().unwrap().clone();
+                let _ = slint::invoke_from_event_loop(move || {
+                    if let Some(ui) = ui_handle.upgrade() {
+                        show_conflict(&ui, conflict);
+                    }
+                });
+            }
+        });
+    }
+
+    // Keep the list of quarantined files current
+    {
+        let file_server = app_data.file_server.clone();
+        let ui_handle = Mutex::new(ui.as_weak());
+        let mut events = integrations::subscribe();
+        app_data.runtime.spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(
+                        integrations::ServerEvent::FileQuarantined { .. }
+                        | integrations::ServerEvent::FileDeleted { .. }
+                        | integrations::ServerEvent::ServerStopped { .. },
+                    ) => {}
+                    Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+                let items = quarantine_items(&file_server.lock().unwrap());
+                let ui_handle = ui_handle.lock().unwrap().clone();
+                let _ = slint::invoke_from_event_loop(move || {
+                    if let Some(ui) = ui_handle.upgrade() {
+                        ui.set_quarantined(ModelRc::new(VecModel::from(items)));
+                    }
+                });
+            }
+        });
+    }
+
+    // Open received files or report them, as the `display.auto_open`
+    // rules say
+    {
+        let app_data = app_data.clone();
+        let ui_handle = Mutex::new(ui.as_weak());
+        let mut events = integrations::subscribe();
+        app_data.runtime.clone().spawn(async move {
+            loop {
+                let id = match events.recv().await {
+                    Ok(integrations::ServerEvent::FileReceived { id, .. }) => id,
+                    Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                };
+                let Some(file) = app_data.received_file(&id) else {
+                    continue;
+                };
+                let auto_open = match ConfigData::instance() {
+                    Ok(instance) => AutoOpen::from_config(&instance.lock().unwrap().display),
+                    Err(e) => {
+                        error!("Failed to read the auto-open rules: {}", e);
+                        continue;
+                    }
+                };
+                let status = match auto_open.action(&file) {
+                    Some(AutoOpenAction::Open) => match open::that(&file.path) {
+                        Ok(()) => Status::info(format!("Received and opened '{}'", file.name)),
+                        Err(e) => {
+                            error!("Failed to open {:?}: {}", file.path, e);
+                            Status::warning(format!(
+                                "Received '{}' but failed to open it",
+                                file.name
+                            ))
+                        }
+                    },
+                    Some(AutoOpenAction::Notify) => Status::info(format!(
+                        "Received '{}' ({})",
+                        file.name,
+                        humanize::size(file.size)
+                    )),
+                    Some(AutoOpenAction::Never) | None => continue,
+                };
+                let ui_handle = ui_handle.lock().unwrap().clone();
+                let _ = slint::invoke_from_event_loop(move || {
+                    if let Some(ui) = ui_handle.upgrade() {
+                        show_status(&ui, status);
+                    }
+                });
+            }
+        });
+    }
+
+    // Follow the transfers of the servers for the Transfers panel
+    {
+        let server_transfers = app_data.server_transfers.clone();
+        let mut events = integrations::subscribe();
+        app_data.runtime.spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => server_transfers
+                        .lock()
+                        .unwrap()
+                        .apply(&event, std::time::Instant::now()),
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {
+                        server_transfers.lock().unwrap().prune()
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    // Start and stop the default server in its availability window
+    {
+        let ui_handle = Mutex::new(ui.as_weak());
+        let notify: schedule::ScheduleNotifier = Box::new(move |message| {
+            let ui_handle = ui_handle.lock().unwrap().clone();
+            let _ = slint::invoke_from_event_loop(move || {
+                if let Some(ui) = ui_handle.upgrade() {
+                    show_status(&ui, Status::info(message));
+                }
+            });
+        });
+        app_data
+            .runtime
+            .spawn(schedule::run(app_data.file_server.clone(), notify));
+    }
+
+    // Carry out what hosts ask for from the portal
+    let commands = app_data.file_server.lock().unwrap().take_remote_commands();
+    if let Some(commands) = commands {
+        let ui_handle = Mutex::new(ui.as_weak());
+        let notify: remote::RemoteNotifier = Box::new(move |message| {
+            let ui_handle = ui_handle.lock().unwrap().clone();
+            let _ = slint::invoke_from_event_loop(move || {
+                if let Some(ui) = ui_handle.upgrade() {
+                    show_status(&ui, Status::info(message));
+                }
+            });
+        });
+        app_data
+            .runtime
+            .spawn(remote::run(app_data.file_server.clone(), commands, notify));
+    }
+
+    // Download the files of opened `.justrans` bundles in the background
+    {
+        let ui_handle = Mutex::new(ui.as_weak());
+        let notify: downloads::DownloadNotifier = Box::new(move |message| {
+            let ui_handle = ui_handle.lock().unwrap().clone();
+            let _ = slint::invoke_from_event_loop(move || {
+                if let Some(ui) = ui_handle.upgrade() {
+                    show_status(&ui, Status::info(message));
+                }
+            });
+        });
+        app_data
+            .runtime
+            .spawn(downloads::run(app_data.downloads.clone(), notify));
+    }
+
+    // Launched through a justrans:// link while no instance was running
+    if let Some(link) = pending_link {
+        match shell::link::open_link(&link) {
+            Ok(message) => show_status(&ui, Status::info(message)),
+            Err(e) => {
+                error!("Failed to open link {}: {}", link, e);
+                show_status(&ui, Status::error(format!("Failed to open link: {}", e)));
+            }
+        }
+    }
+
+    // Launched by opening a `.justrans` bundle while no instance was running
+    if let Some(path) = pending_bundle {
+        match shell::bundle::open_bundle(&path, &app_data.downloads) {
+            Ok(message) => show_status(&ui, Status::info(message)),
+            Err(e) => {
+                error!("Failed to open bundle {:?}: {}", path, e);
+                show_status(&ui, Status::error(format!("Failed to open bundle: {}", e)));
+            }
+        }
+    }
+
+    // Accept files from the Linux file manager over D-Bus
+    #[cfg(target_os = "linux")]
+    {
+        let ui_handle = Mutex::new(ui.as_weak());
+        let notify: shell::dbus::ShareNotifier = Box::new(move |message| {
+            let ui_handle = ui_handle.lock().unwrap().clone();
+            let _ = slint::invoke_from_event_loop(move || {
+                if let Some(ui) = ui_handle.upgrade() {
+                    show_status(&ui, Status::info(message));
+                }
+            });
+        });
+        if let Err(e) = runtime.block_on(shell::dbus::serve(app_data.file_server.clone(), notify)) {
+            error!("Failed to register D-Bus service: {}", e);
+        }
+    }
+
     // Set up version information
     ui.set_version(SharedString::from(VERSION));
 
@@ -105,15 +615,10 @@ fn main() -> Result<()> {
             // Clone ui_handle for use in async block
             let ui_handle_clone = ui_handle.clone();
 
-            // Start the server in a separate thread to avoid MutexGuard across await points
-            std::thread::spawn(move || {
+            // Start the server on the runtime
+            app_data.runtime.spawn(async move {
                 // Start the server
-                let server_result = {
-                    let mut file_server = app_data_clone.file_server.lock().unwrap();
-                    app_data_clone
-                        .runtime
-                        .block_on(async { file_server.start().await })
-                };
+                let server_result = FileServer::start(&app_data_clone.file_server).await;
 
                 match server_result {
                     Ok(_) => {
@@ -123,16 +628,43 @@ fn main() -> Result<()> {
                             file_server.get_server_info()
                         };
 
+                        // Check the OS firewall once, on the first successful
+                        // start; the check runs system tools
+                        let port = server_info.port;
+                        let firewall_check = tokio::task::spawn_blocking(move || {
+                            run_first_start_firewall_check(port)
+                        })
+                        .await
+                        .unwrap_or_default();
+                        let room_items = app_data_clone.room_items();
+
                         // Update UI only after QR code is generated
                         slint::invoke_from_event_loop(move || {
                             let ui = ui_handle_clone.unwrap();
-                            ui.set_server_url(SharedString::from(server_info.url.clone()));
+                            show_server_urls(&ui, &server_info);
+                            ui.set_rooms(ModelRc::new(VecModel::from(room_items)));
                             ui.set_server_running(true);
-                            ui.set_status_message(SharedString::from(
-                                "Server running - QR code ready",
-                            ));
                             ui.set_is_loading(false);
                             info!("UI updated with server_running=true and QR code ready");
+
+                            if let Some(check) = firewall_check {
+                                show_status(
+                                    &ui,
+                                    match check.status {
+                                        FirewallStatus::Blocked => {
+                                            Status::warning(check.status_message())
+                                        }
+                                        _ => Status::info(check.status_message()),
+                                    },
+                                );
+                                if check.status == FirewallStatus::Blocked {
+                                    ui.set_firewall_detail(SharedString::from(check.detail));
+                                    ui.set_firewall_command(SharedString::from(
+                                        check.suggested_command.unwrap_or_default(),
+                                    ));
+                                    ui.set_show_firewall_prompt(true);
+                                }
+                            }
                         })
                         .unwrap();
                     }
@@ -143,7 +675,7 @@ fn main() -> Result<()> {
                         slint::invoke_from_event_loop(move || {
                             let ui = ui_handle_clone.unwrap();
                             ui.set_server_running(false);
-                            ui.set_status_message(SharedString::from(error_msg));
+                            show_status(&ui, Status::error(error_msg));
                             ui.set_is_loading(false);
                         })
                         .unwrap();
@@ -166,22 +698,16 @@ fn main() -> Result<()> {
             // Clone ui_handle for use in async block
             let ui_handle_clone = ui_handle.clone();
 
-            // Stop the server in a separate thread to avoid MutexGuard across await points
-            std::thread::spawn(move || {
+            // Stop the server on the runtime
+            app_data.runtime.spawn(async move {
                 // Stop the server
-                let stop_result = {
-                    let mut file_server = app_data_clone.file_server.lock().unwrap();
-                    app_data_clone
-                        .runtime
-                        .block_on(async { file_server.stop().await })
-                };
+                let stop_result = FileServer::stop(&app_data_clone.file_server).await;
 
                 match stop_result {
                     Ok(_) => {
                         slint::invoke_from_event_loop(move || {
                             let ui = ui_handle_clone.unwrap();
                             ui.set_server_running(false);
-                            ui.set_status_message(SharedString::from("Server stopped"));
                             // No need to set QR code path
                             ui.set_is_loading(false);
                         })
@@ -193,7 +719,7 @@ fn main() -> Result<()> {
 
                         slint::invoke_from_event_loop(move || {
                             let ui = ui_handle_clone.unwrap();
-                            ui.set_status_message(SharedString::from(error_msg));
+                            show_status(&ui, Status::error(error_msg));
                             ui.set_is_loading(false);
                         })
                         .unwrap();
@@ -204,111 +730,1267 @@ fn main() -> Result<()> {
     });
 
     ui.on_render_qr({
-        let file_server = app_data.file_server.clone();
-        move || match generate_qr_code_for_url(&file_server.lock().unwrap().get_server_info().url) {
-            Ok(qr_image) => {
-                info!("QR code generated successfully");
-                let rgba = qr_image.to_rgba8();
-                slint::Image::from_rgba8(slint::SharedPixelBuffer::clone_from_slice(
-                    &rgba,
-                    rgba.width(),
-                    rgba.height(),
-                ))
+        let app_data = app_data.clone();
+        move |url, _period| {
+            let url = app_data
+                .file_server
+                .lock()
+                .unwrap()
+                .qr_url(&url, unix_now());
+            match generate_qr_code_for_url(&identity::pinned_url(&url, identity::get())) {
+                Ok(qr_image) => {
+                    info!("QR code generated successfully");
+                    let rgba = qr_image.to_rgba8();
+                    slint::Image::from_rgba8(slint::SharedPixelBuffer::clone_from_slice(
+                        &rgba,
+                        rgba.width(),
+                        rgba.height(),
+                    ))
+                }
+                Err(_) => slint::Image::default(),
             }
-            Err(_) => slint::Image::default(),
         }
     });
 
-    // Handle URL click
-    ui.on_open_url({
-        let file_server = app_data.file_server.clone();
-        move || {
-            let server_url = &file_server.lock().unwrap().get_server_info().url;
+    // Handle room management
+    ui.on_create_room({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move |name, pin| {
+            let ui = ui_handle.unwrap();
+            let result = app_data
+                .file_server
+                .lock()
+                .unwrap()
+                .create_room(name.trim(), Some(pin.to_string()));
 
-            info!("Opening server URL in browser: {}", server_url);
-            if let Err(e) = open::that(server_url) {
-                error!("Failed to open URL: {:?}", e);
+            match result {
+                Ok(_) => show_status(&ui, Status::info(format!("Room '{}' created", name.trim()))),
+                Err(e) => {
+                    error!("Failed to create room: {}", e);
+                    show_status(&ui, Status::error(e.to_string()));
+                }
             }
+            ui.set_rooms(ModelRc::new(VecModel::from(app_data.room_items())));
         }
     });
 
-    // Handle save config
-    ui.on_save_config({
+    ui.on_remove_room({
         let ui_handle = ui.as_weak();
-        let app_data_clone = app_data.clone();
-        move |port, chunk_size, theme, storage_dir| {
+        let app_data = app_data.clone();
+        move |name| {
             let ui = ui_handle.unwrap();
+            if let Err(e) = app_data.file_server.lock().unwrap().remove_room(&name) {
+                error!("Failed to remove room: {}", e);
+                show_status(&ui, Status::error(e.to_string()));
+            }
+            ui.set_rooms(ModelRc::new(VecModel::from(app_data.room_items())));
+        }
+    });
 
-            info!(
-                "Saving config: port={}, chunk_size={}, theme={}, storage_dir={}",
-                port, chunk_size, theme, storage_dir
+    // Handle conflict review
+    ui.on_resolve_conflict({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move |id, action| {
+            let ui = ui_handle.unwrap();
+            let Some(resolution) = ConflictResolution::parse(&action) else {
+                error!("Unknown conflict resolution: {}", action);
+                return;
+            };
+            if let Err(e) = app_data.resolve_conflict(&id, resolution) {
+                error!("Failed to resolve conflict: {}", e);
+                show_status(&ui, Status::error(e.to_string()));
+            }
+            show_conflict(&ui, app_data.next_conflict());
+        }
+    });
+
+    // Handle storage dashboard
+    ui.on_refresh_storage({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move || {
+            let ui = ui_handle.unwrap();
+            show_storage_usage(&ui, &app_data.file_server.lock().unwrap());
+        }
+    });
+
+    ui.on_cleanup_storage({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move |days| {
+            let ui = ui_handle.unwrap();
+            let file_server = app_data.file_server.lock().unwrap();
+            let (count, bytes) = file_server.cleanup_older_than(days.max(0) as u64);
+            show_status(
+                &ui,
+                Status::info(format!(
+                    "Removed {} file(s), freed {}",
+                    count,
+                    humanize::size(bytes)
+                )),
             );
+            show_storage_usage(&ui, &file_server);
+        }
+    });
 
-            match ConfigData::instance() {
-                Ok(instance) => {
-                    // Get current port for comparison
-                    let current_port = {
-                        let config = instance.lock().unwrap();
-                        config.server.port
-                    };
+    // Delete a received file after the user confirmed it
+    ui.on_remove_file({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move |id| {
+            let ui = ui_handle.unwrap();
+            let file_server = app_data.file_server.lock().unwrap();
+            match file_server.remove_file(&id) {
+                Ok(file_info) => {
+                    show_status(&ui, Status::info(format!("Deleted '{}'", file_info.name)))
+                }
+                Err(e) => {
+                    error!("Failed to delete file {}: {}", id, e);
+                    show_status(&ui, Status::error(format!("Failed to delete file: {}", e)));
+                }
+            }
+            show_storage_usage(&ui, &file_server);
+        }
+    });
 
-                    // Update config
-                    {
-                        let mut config = instance.lock().unwrap();
-                        config.server.port = port as u16;
-                        config.server.upload_chunk_size_mb = chunk_size as u64;
-                        config.display.theme = theme.to_string();
-                        config.storage.storage_dir = storage_dir.to_string();
-
-                        // Save the updated config
-                        let default_path = std::path::PathBuf::from("config/settings.yaml");
-                        if let Err(e) = config.save(&default_path) {
-                            error!("Failed to save config: {}", e);
-                            ui.set_status_message(SharedString::from(format!(
-                                "Failed to save config: {}",
-                                e
-                            )));
-                            return;
-                        }
+    // Let a quarantined file out after the user confirmed it
+    ui.on_release_file({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move |id| {
+            let ui = ui_handle.unwrap();
+            let file_server = app_data.file_server.lock().unwrap();
+            match file_server.release_file(&id) {
+                Ok(file_info) => show_status(
+                    &ui,
+                    Status::info(format!("Released '{}' from quarantine", file_info.name)),
+                ),
+                Err(e) => {
+                    error!("Failed to release file {}: {}", id, e);
+                    show_status(&ui, Status::error(format!("Failed to release file: {}", e)));
+                }
+            }
+            ui.set_quarantined(ModelRc::new(VecModel::from(quarantine_items(&file_server))));
+        }
+    });
+
+    // Handle starting/stopping a server profile
+    ui.on_toggle_profile({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move |name| {
+            let app_data_clone = app_data.clone();
+            let ui_handle_clone = ui_handle.clone();
+            let name = name.to_string();
+
+            app_data.runtime.spawn(async move {
+                let result = match app_data_clone.profile_server(&name) {
+                    Ok(server) => {
+                        let running = server.lock().unwrap().get_server_info().running;
+                        FileServer::set_running(&server, !running)
+                            .await
+                            .map(|_| !running)
+                    }
+                    Err(e) => Err(e),
+                };
+
+                let status = match result {
+                    Ok(true) => Status::info(format!("Profile '{}' started", name)),
+                    Ok(false) => Status::info(format!("Profile '{}' stopped", name)),
+                    Err(err) => {
+                        error!("Failed to toggle profile '{}': {}", name, err);
+                        Status::error(format!("Profile '{}' failed: {}", name, err))
                     }
+                };
+                let items = app_data_clone.profile_items();
+
+                slint::invoke_from_event_loop(move || {
+                    let ui = ui_handle_clone.unwrap();
+                    ui.set_profiles(ModelRc::new(VecModel::from(items)));
+                    show_status(&ui, status);
+                })
+                .unwrap();
+            });
+        }
+    });
 
-                    // Update UI config properties to apply theme immediately
-                    ui.set_config_server_port(port);
-                    ui.set_config_upload_chunk_size_mb(chunk_size);
-                    ui.set_config_theme(SharedString::from(theme.to_string()));
-                    ui.set_config_storage_dir(SharedString::from(storage_dir.to_string()));
+    // Handle connection test
+    ui.on_test_connection({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move || {
+            let ui = ui_handle.unwrap();
+            ui.set_connection_test_running(true);
 
-                    info!("Config saved successfully and theme applied");
+            let app_data_clone = app_data.clone();
+            let ui_handle_clone = ui_handle.clone();
 
-                    // Check if server is running and port changed
-                    let server_running = {
-                        let file_server = app_data_clone.file_server.lock().unwrap();
-                        file_server.get_server_info().running
-                    };
+            app_data.runtime.spawn(async move {
+                let server_info = app_data_clone.file_server.lock().unwrap().get_server_info();
+                let result = run_connection_test(
+                    &server_info.ip,
+                    server_info.port,
+                    server_info.url.starts_with("https://"),
+                )
+                .await;
 
-                    if server_running && current_port != port as u16 {
-                        ui.set_status_message(SharedString::from(
-                            "Configuration saved - restart server to apply port changes",
-                        ));
-                    } else {
-                        ui.set_status_message(SharedString::from(
-                            "Configuration saved successfully",
-                        ));
+                let (report, status) = match result {
+                    Ok(report) => {
+                        info!(
+                            "Connection test finished: target reachable={}",
+                            report.target_ok
+                        );
+                        let status = if report.target_ok {
+                            Status::info("Connection test passed")
+                        } else {
+                            Status::warning("Connection test failed - see hints")
+                        };
+                        (report.summary(), status)
                     }
-                }
-                Err(e) => {
-                    error!("Failed to access config instance: {}", e);
-                    ui.set_status_message(SharedString::from(format!(
-                        "Failed to access config: {}",
-                        e
-                    )));
-                }
-            }
+                    Err(err) => {
+                        error!("Connection test failed: {}", err);
+                        (
+                            format!("Connection test could not run: {}", err),
+                            Status::error("Connection test could not run"),
+                        )
+                    }
+                };
+
+                slint::invoke_from_event_loop(move || {
+                    let ui = ui_handle_clone.unwrap();
+                    ui.set_connection_test_report(SharedString::from(report));
+                    ui.set_connection_test_running(false);
+                    show_status(&ui, status);
+                })
+                .unwrap();
+            });
         }
     });
 
-    // Run the UI
-    ui.run()?;
+    // Handle diagnostics self-check
+    ui.on_run_diagnostics({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move || {
+            let ui = ui_handle.unwrap();
+            ui.set_diagnostics_export_message(SharedString::default());
+            ui.set_diagnostics_running(true);
+            run_diagnostics(ui_handle.clone(), app_data.clone(), false);
+        }
+    });
 
-    Ok(())
+    // Handle diagnostics bundle export
+    ui.on_export_diagnostics({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move || {
+            let ui_handle_clone = ui_handle.clone();
+            let app_data_clone = app_data.clone();
+
+            app_data.runtime.spawn_blocking(move || {
+                let result = ConfigData::instance().and_then(|instance| {
+                    let server_running = app_data_clone
+                        .file_server
+                        .lock()
+                        .unwrap()
+                        .get_server_info()
+                        .running;
+                    let config = instance.lock().unwrap();
+                    let report = diagnostics::run_self_check(&config, server_running);
+                    diagnostics::export_bundle(
+                        &report,
+                        &config,
+                        std::path::Path::new(diagnostics::LOG_DIR),
+                        std::path::Path::new(diagnostics::BUNDLE_DIR),
+                    )
+                });
+
+                let message = match result {
+                    Ok(path) => {
+                        if let Err(e) = open::that(&path) {
+                            error!("Failed to open diagnostics bundle: {}", e);
+                        }
+                        format!("Bundle saved to {}", path.display())
+                    }
+                    Err(e) => {
+                        error!("Failed to export diagnostics bundle: {}", e);
+                        format!("Failed to export diagnostics bundle: {}", e)
+                    }
+                };
+
+                slint::invoke_from_event_loop(move || {
+                    let ui = ui_handle_clone.unwrap();
+                    ui.set_diagnostics_export_message(SharedString::from(message));
+                })
+                .unwrap();
+            });
+        }
+    });
+
+    // Restore a snapshot of the records and settings, saving the current ones first
+    ui.on_restore_snapshot({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move || {
+            let Some(folder) = rfd::FileDialog::new()
+                .set_title("Restore JusTrans Snapshot")
+                .set_directory(snapshots::SNAPSHOT_DIR)
+                .pick_folder()
+            else {
+                return;
+            };
+            let ui_handle_clone = ui_handle.clone();
+            let metadata = app_data.file_server.lock().unwrap().metadata();
+            ui_handle
+                .unwrap()
+                .set_diagnostics_export_message(SharedString::from("Restoring..."));
+
+            app_data.runtime.spawn_blocking(move || {
+                let result = snapshots::restore(
+                    &folder,
+                    metadata.as_ref(),
+                    &settings::path(),
+                    std::path::Path::new(snapshots::SNAPSHOT_DIR),
+                )
+                .and_then(|restored| {
+                    let message = restored.message();
+                    let config = match restored.settings {
+                        Some(snapshot) => {
+                            let instance = ConfigData::instance()?;
+                            let mut config = instance.lock().unwrap();
+                            *config = snapshot;
+                            config.save(&settings::path())?;
+                            Some(config.clone())
+                        }
+                        None => None,
+                    };
+                    Ok((config, message))
+                });
+
+                slint::invoke_from_event_loop(move || {
+                    let ui = ui_handle_clone.unwrap();
+                    let message = match result {
+                        Ok((config, message)) => {
+                            if let Some(config) = config {
+                                ui.set_config_theme(SharedString::from(
+                                    config.display.theme.clone(),
+                                ));
+                                show_schedule(&ui, &config);
+                                ui.set_settings_items(ModelRc::new(VecModel::from(setting_items(
+                                    &config,
+                                ))));
+                            }
+                            message
+                        }
+                        Err(e) => {
+                            error!("Failed to restore snapshot: {}", e);
+                            format!("Failed to restore snapshot: {}", e)
+                        }
+                    };
+                    ui.set_diagnostics_export_message(SharedString::from(message));
+                })
+                .unwrap();
+            });
+        }
+    });
+
+    // Usage statistics opt-in with a preview of the report
+    #[cfg(feature = "telemetry")]
+    {
+        ui.set_telemetry_available(true);
+
+        ui.on_open_telemetry({
+            let ui_handle = ui.as_weak();
+            move || {
+                let ui = ui_handle.unwrap();
+                match ConfigData::instance() {
+                    Ok(instance) => {
+                        let config = instance.lock().unwrap();
+                        ui.set_telemetry_enabled(config.telemetry.enabled);
+                        ui.set_telemetry_preview(SharedString::from(
+                            integrations::telemetry::preview(&config),
+                        ));
+                    }
+                    Err(e) => error!("Failed to access config instance: {}", e),
+                }
+            }
+        });
+
+        ui.on_telemetry_toggled({
+            let ui_handle = ui.as_weak();
+            let app_data = app_data.clone();
+            move |enabled| {
+                let ui = ui_handle.unwrap();
+                let instance = match ConfigData::instance() {
+                    Ok(instance) => instance,
+                    Err(e) => {
+                        error!("Failed to access config instance: {}", e);
+                        return;
+                    }
+                };
+
+                let mut config = instance.lock().unwrap();
+                config.telemetry.enabled = enabled;
+                if let Err(e) = config.save(&settings::path()) {
+                    error!("Failed to save config: {}", e);
+                }
+                if enabled {
+                    integrations::telemetry::init(&app_data.runtime);
+                }
+                info!(
+                    "Usage statistics {}",
+                    if enabled { "enabled" } else { "disabled" }
+                );
+                ui.set_telemetry_enabled(enabled);
+            }
+        });
+    }
+
+    // Handle firewall rule creation
+    ui.on_add_firewall_rule({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move || {
+            let port = app_data.file_server.lock().unwrap().get_server_info().port;
+            let ui_handle_clone = ui_handle.clone();
+
+            app_data.runtime.spawn_blocking(move || {
+                let check = firewall::check_port(port);
+                let status = match firewall::add_rule(&check) {
+                    Ok(_) => Status::info("Firewall rule added"),
+                    Err(e) => {
+                        error!("Failed to add firewall rule: {}", e);
+                        Status::error(format!("Failed to add firewall rule: {}", e))
+                    }
+                };
+
+                slint::invoke_from_event_loop(move || {
+                    let ui = ui_handle_clone.unwrap();
+                    ui.set_show_firewall_prompt(false);
+                    show_status(&ui, status);
+                })
+                .unwrap();
+            });
+        }
+    });
+
+    // Handle URL click
+    ui.on_open_url({
+        let ui_handle = ui.as_weak();
+        move || {
+            // The URL whose QR code is currently shown
+            let server_url = ui_handle.unwrap().get_server_url().to_string();
+
+            info!("Opening server URL in browser: {}", server_url);
+            if let Err(e) = open::that(server_url) {
+                error!("Failed to open URL: {:?}", e);
+            }
+        }
+    });
+
+    // Handle Ctrl+L
+    ui.on_show_logs({
+        let ui_handle = ui.as_weak();
+        let log_path = log_path.clone();
+        move || {
+            info!("Opening log file: {:?}", log_path);
+            if let Err(e) = open::that(&log_path) {
+                error!("Failed to open log file: {:?}", e);
+                show_status(
+                    &ui_handle.unwrap(),
+                    Status::error(format!("Failed to open log file: {}", e)),
+                );
+            }
+        }
+    });
+
+    // Handle Ctrl+P: present mode fills the screen with the QR code
+    ui.on_present({
+        let ui_handle = ui.as_weak();
+        move |present| {
+            info!("Present mode {}", if present { "on" } else { "off" });
+            ui_handle.unwrap().window().set_fullscreen(present);
+        }
+    });
+    if present {
+        ui.set_present_mode(true);
+        ui.window().set_fullscreen(true);
+    }
+
+    // Handle Ctrl+Q
+    ui.on_quit(|| {
+        info!("Quitting from keyboard shortcut");
+        if let Err(e) = slint::quit_event_loop() {
+            error!("Failed to quit: {}", e);
+        }
+    });
+
+    // Handle settings panel
+    ui.on_open_settings({
+        let ui_handle = ui.as_weak();
+        move || {
+            let ui = ui_handle.unwrap();
+            match ConfigData::instance() {
+                Ok(instance) => {
+                    let items = setting_items(&instance.lock().unwrap());
+                    ui.set_settings_items(ModelRc::new(VecModel::from(items)));
+                    ui.set_state_message(SharedString::default());
+                }
+                Err(e) => error!("Failed to access config instance: {}", e),
+            }
+        }
+    });
+
+    // Handle state export for moving to another machine
+    ui.on_export_state({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move |include_files| {
+            let ui_handle_clone = ui_handle.clone();
+            let app_data_clone = app_data.clone();
+            ui_handle
+                .unwrap()
+                .set_state_message(SharedString::from("Exporting..."));
+
+            app_data.runtime.spawn_blocking(move || {
+                let result = ConfigData::instance().and_then(|instance| {
+                    let config = instance.lock().unwrap().clone();
+                    backup::export(
+                        &app_data_clone.file_server,
+                        &config,
+                        include_files,
+                        std::path::Path::new(backup::BACKUP_DIR),
+                    )
+                });
+
+                let message = match result {
+                    Ok(summary) => {
+                        if let Some(dir) = summary.path.parent() {
+                            if let Err(e) = open::that(dir) {
+                                error!("Failed to open backup folder: {}", e);
+                            }
+                        }
+                        format!(
+                            "Saved {} with {} stored file(s) ({})",
+                            summary.path.display(),
+                            summary.files,
+                            humanize::size(summary.bytes)
+                        )
+                    }
+                    Err(e) => {
+                        error!("Failed to export application state: {}", e);
+                        format!("Failed to export state: {}", e)
+                    }
+                };
+
+                slint::invoke_from_event_loop(move || {
+                    let ui = ui_handle_clone.unwrap();
+                    ui.set_state_message(SharedString::from(message));
+                })
+                .unwrap();
+            });
+        }
+    });
+
+    // Handle state import, replacing the settings with the archived ones
+    ui.on_import_state({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move || {
+            let Some(path) = rfd::FileDialog::new()
+                .set_title("Import JusTrans State")
+                .add_filter("JusTrans state", &["tar"])
+                .set_directory(backup::BACKUP_DIR)
+                .pick_file()
+            else {
+                return;
+            };
+            let ui_handle_clone = ui_handle.clone();
+            let app_data_clone = app_data.clone();
+            ui_handle
+                .unwrap()
+                .set_state_message(SharedString::from("Importing..."));
+
+            app_data.runtime.spawn_blocking(move || {
+                let result = backup::import(&path, &app_data_clone.file_server).and_then(
+                    |(imported, summary)| {
+                        let instance = ConfigData::instance()?;
+                        let mut config = instance.lock().unwrap();
+                        *config = imported;
+                        config.save(&settings::path())?;
+                        Ok((config.clone(), summary))
+                    },
+                );
+                let room_items = app_data_clone.room_items();
+
+                slint::invoke_from_event_loop(move || {
+                    let ui = ui_handle_clone.unwrap();
+                    match result {
+                        Ok((config, summary)) => {
+                            ui.set_config_theme(SharedString::from(config.display.theme.clone()));
+                            show_schedule(&ui, &config);
+                            ui.set_settings_items(ModelRc::new(VecModel::from(setting_items(
+                                &config,
+                            ))));
+                            ui.set_rooms(ModelRc::new(VecModel::from(room_items)));
+                            ui.set_state_message(SharedString::from(format!(
+                                "{} - restart the server to apply network settings",
+                                summary.message()
+                            )));
+                        }
+                        Err(e) => {
+                            error!("Failed to import application state: {}", e);
+                            ui.set_state_message(SharedString::from(format!(
+                                "Failed to import state: {}",
+                                e
+                            )));
+                        }
+                    }
+                })
+                .unwrap();
+            });
+        }
+    });
+
+    // Validate each edit against the schema as the user types
+    ui.on_setting_edited({
+        let ui_handle = ui.as_weak();
+        move |index, value| {
+            let ui = ui_handle.unwrap();
+            let items = ui.get_settings_items();
+            let Some(mut item) = items.row_data(index as usize) else {
+                return;
+            };
+            let schema = ConfigData::schema();
+            let Some(field) = schema.iter().find(|f| f.key == item.key.as_str()) else {
+                return;
+            };
+
+            item.error = SharedString::from(field.parse(&value).err().unwrap_or_default());
+            item.value = value;
+            items.set_row_data(index as usize, item);
+        }
+    });
+
+    // Handle save settings
+    ui.on_save_settings({
+        let ui_handle = ui.as_weak();
+        let app_data_clone = app_data.clone();
+        move || {
+            let ui = ui_handle.unwrap();
+            let items = ui.get_settings_items();
+            let schema = ConfigData::schema();
+
+            let instance = match ConfigData::instance() {
+                Ok(instance) => instance,
+                Err(e) => {
+                    error!("Failed to access config instance: {}", e);
+                    show_status(
+                        &ui,
+                        Status::error(format!("Failed to access config: {}", e)),
+                    );
+                    return;
+                }
+            };
+
+            // Apply every value to a copy so a bad value leaves the config untouched
+            let mut updated = instance.lock().unwrap().clone();
+            let mut invalid = 0;
+            for (index, mut item) in items.iter().enumerate() {
+                let Some(field) = schema.iter().find(|f| f.key == item.key.as_str()) else {
+                    continue;
+                };
+                let result = field.parse(&item.value).and_then(|value| {
+                    settings::schema::set_value(&mut updated, &field.key, value)
+                        .map_err(|e| e.to_string())
+                });
+                if let Err(e) = result {
+                    invalid += 1;
+                    item.error = SharedString::from(e);
+                    items.set_row_data(index, item);
+                }
+            }
+            if invalid > 0 {
+                show_status(
+                    &ui,
+                    Status::warning(format!("{} setting(s) need attention", invalid)),
+                );
+                return;
+            }
+            if let Err(e) = schedule::configured(&updated) {
+                show_status(&ui, Status::error(e.to_string()));
+                return;
+            }
+
+            info!("Saving config: {:?}", updated);
+
+            // Get current port for comparison
+            let current_port = {
+                let mut config = instance.lock().unwrap();
+                let current_port = config.server.port;
+                *config = updated;
+
+                // Save the updated config
+                if let Err(e) = config.save(&settings::path()) {
+                    error!("Failed to save config: {}", e);
+                    show_status(&ui, Status::error(format!("Failed to save config: {}", e)));
+                    return;
+                }
+
+                // Update UI config properties to apply theme immediately
+                ui.set_config_theme(SharedString::from(config.display.theme.clone()));
+                show_schedule(&ui, &config);
+                current_port
+            };
+
+            info!("Config saved successfully and theme applied");
+            ui.set_show_config(false);
+
+            // Check if server is running and port changed
+            let (server_running, new_port) = {
+                let file_server = app_data_clone.file_server.lock().unwrap();
+                let config = instance.lock().unwrap();
+                (file_server.get_server_info().running, config.server.port)
+            };
+
+            if server_running && current_port != new_port {
+                show_status(
+                    &ui,
+                    Status::info("Configuration saved - restart server to apply port changes"),
+                );
+            } else {
+                show_status(&ui, Status::info("Configuration saved successfully"));
+            }
+        }
+    });
+
+    // Transfers panel: downloads from paired instances and bundles
+    ui.on_open_transfers({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move || {
+            let ui = ui_handle.unwrap();
+            let peers: Vec<SharedString> = ConfigData::instance()
+                .map(|instance| {
+                    instance
+                        .lock()
+                        .unwrap()
+                        .peers
+                        .iter()
+                        .map(|peer| SharedString::from(peer.name.as_str()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            ui.set_transfer_peers(ModelRc::new(VecModel::from(peers)));
+            ui.set_download_folder(SharedString::from(
+                downloads::destination().display().to_string(),
+            ));
+            show_transfers(&ui, &app_data);
+        }
+    });
+
+    ui.on_browse_peer({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move |name| {
+            let Some(peer) = find_peer(&name) else {
+                return;
+            };
+            let ui = ui_handle.unwrap();
+            ui.set_peer_message(SharedString::from(format!(
+                "Loading the files of {}...",
+                peer.name
+            )));
+            ui.set_peer_files(ModelRc::new(VecModel::from(Vec::<PeerFileItem>::new())));
+
+            let ui_handle = ui_handle.clone();
+            app_data.runtime.spawn(async move {
+                let result = peers::list_files(&peer).await;
+                let _ = slint::invoke_from_event_loop(move || {
+                    let Some(ui) = ui_handle.upgrade() else {
+                        return;
+                    };
+                    match result {
+                        Ok(files) => {
+                            ui.set_peer_message(SharedString::from(format!(
+                                "{} shares {} file(s)",
+                                peer.name,
+                                files.len()
+                            )));
+                            let items: Vec<PeerFileItem> = files
+                                .into_iter()
+                                .map(|file| PeerFileItem {
+                                    id: SharedString::from(file.id),
+                                    name: SharedString::from(file.name),
+                                    size: SharedString::from(humanize::size(file.size)),
+                                })
+                                .collect();
+                            ui.set_peer_files(ModelRc::new(VecModel::from(items)));
+                        }
+                        Err(e) => {
+                            error!("Failed to list the files of {}: {}", peer.name, e);
+                            ui.set_peer_message(SharedString::from(format!(
+                                "Failed to reach {}: {}",
+                                peer.name, e
+                            )));
+                        }
+                    }
+                });
+            });
+        }
+    });
+
+    ui.on_download_peer_file({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move |name, id| {
+            let ui = ui_handle.unwrap();
+            let url = find_peer(&name)
+                .ok_or_else(|| anyhow::anyhow!("Unknown instance '{}'", name))
+                .and_then(|peer| peers::file_url(&peer, &id));
+            match url {
+                Ok(url) => {
+                    app_data.downloads.add(url, downloads::destination());
+                    show_transfers(&ui, &app_data);
+                }
+                Err(e) => ui.set_peer_message(SharedString::from(format!(
+                    "Failed to queue the download: {}",
+                    e
+                ))),
+            }
+        }
+    });
+
+    ui.on_pause_transfer({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move |id| {
+            app_data.downloads.pause(id as u64);
+            show_transfers(&ui_handle.unwrap(), &app_data);
+        }
+    });
+
+    ui.on_resume_transfer({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move |id| {
+            app_data.downloads.resume(id as u64);
+            show_transfers(&ui_handle.unwrap(), &app_data);
+        }
+    });
+
+    ui.on_retry_transfer({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move |id| {
+            app_data.downloads.retry(id as u64);
+            show_transfers(&ui_handle.unwrap(), &app_data);
+        }
+    });
+
+    ui.on_remove_transfer({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move |id| {
+            app_data.downloads.remove(id as u64);
+            show_transfers(&ui_handle.unwrap(), &app_data);
+        }
+    });
+
+    ui.on_pause_server_transfer({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move |id| {
+            transfers::pause(id as u64);
+            show_transfers(&ui_handle.unwrap(), &app_data);
+        }
+    });
+
+    ui.on_resume_server_transfer({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move |id| {
+            transfers::resume(id as u64);
+            show_transfers(&ui_handle.unwrap(), &app_data);
+        }
+    });
+
+    ui.on_cancel_transfer({
+        let ui_handle = ui.as_weak();
+        let app_data = app_data.clone();
+        move |id| {
+            transfers::cancel(id as u64);
+            show_transfers(&ui_handle.unwrap(), &app_data);
+        }
+    });
+
+    ui.on_choose_download_folder({
+        let ui_handle = ui.as_weak();
+        move || {
+            let Some(folder) = rfd::FileDialog::new()
+                .set_title("Download Folder")
+                .set_directory(downloads::destination())
+                .pick_folder()
+            else {
+                return;
+            };
+            let ui = ui_handle.unwrap();
+            let result = ConfigData::instance().and_then(|instance| {
+                let mut config = instance.lock().unwrap();
+                config.downloads.directory = folder.display().to_string();
+                config.save(&settings::path())
+            });
+            match result {
+                Ok(()) => ui.set_download_folder(SharedString::from(folder.display().to_string())),
+                Err(e) => {
+                    error!("Failed to save the download folder: {}", e);
+                    show_status(
+                        &ui,
+                        Status::error(format!("Failed to save the download folder: {}", e)),
+                    );
+                }
+            }
+        }
+    });
+
+    // Keep the progress in the Transfers panel current while it is open
+    let transfers_timer = slint::Timer::default();
+    transfers_timer.start(
+        slint::TimerMode::Repeated,
+        std::time::Duration::from_secs(1),
+        {
+            let ui_handle = ui.as_weak();
+            let app_data = app_data.clone();
+            move || {
+                if let Some(ui) = ui_handle.upgrade() {
+                    if ui.get_show_transfers() {
+                        show_transfers(&ui, &app_data);
+                    }
+                }
+            }
+        },
+    );
+
+    // Keep the uptime, devices and traffic in the header current
+    let stats_timer = slint::Timer::default();
+    stats_timer.start(
+        slint::TimerMode::Repeated,
+        std::time::Duration::from_secs(1),
+        {
+            let ui_handle = ui.as_weak();
+            let app_data = app_data.clone();
+            move || {
+                let Some(ui) = ui_handle.upgrade() else {
+                    return;
+                };
+                let server_info = app_data.file_server.lock().unwrap().get_server_info();
+                let stats = if server_info.running {
+                    server_stats(&server_info.stats)
+                } else {
+                    String::new()
+                };
+                if ui.get_server_stats() != stats {
+                    ui.set_server_stats(SharedString::from(stats));
+                }
+            }
+        },
+    );
+
+    // Draw the QR code again whenever the access token in it rotates
+    let qr_timer = slint::Timer::default();
+    qr_timer.start(
+        slint::TimerMode::Repeated,
+        std::time::Duration::from_secs(1),
+        {
+            let ui_handle = ui.as_weak();
+            let app_data = app_data.clone();
+            move || {
+                let Some(ui) = ui_handle.upgrade() else {
+                    return;
+                };
+                let period = app_data
+                    .file_server
+                    .lock()
+                    .unwrap()
+                    .qr_period(unix_now())
+                    .unwrap_or(0) as i32;
+                if ui.get_qr_period() != period {
+                    ui.set_qr_period(period);
+                }
+            }
+        },
+    );
+
+    // Startup self-check; the report only pops up when something is broken
+    run_diagnostics(ui.as_weak(), app_data.clone(), true);
+
+    // Run the UI
+    ui.run()?;
+
+    Ok(())
+}
+
+/// Build the settings panel rows from the config schema
+fn setting_items(config: &ConfigData) -> Vec<SettingItem> {
+    let mut previous_section = String::new();
+    ConfigData::schema()
+        .into_iter()
+        .map(|field| {
+            let first_in_section = field.section != previous_section;
+            previous_section = field.section.clone();
+
+            let (kind, choices) = match &field.kind {
+                SettingKind::Integer { .. } => ("int", Vec::new()),
+                SettingKind::Text => ("text", Vec::new()),
+                SettingKind::Choice(choices) => ("choice", choices.clone()),
+                SettingKind::Toggle => ("bool", Vec::new()),
+            };
+            let choices: Vec<SharedString> = choices.into_iter().map(SharedString::from).collect();
+
+            SettingItem {
+                value: SharedString::from(
+                    settings::schema::get_value(config, &field.key).unwrap_or_default(),
+                ),
+                key: SharedString::from(field.key),
+                label: SharedString::from(field.label),
+                help: SharedString::from(field.help),
+                section: SharedString::from(field.section),
+                first_in_section,
+                kind: SharedString::from(kind),
+                secret: field.secret,
+                choices: ModelRc::new(VecModel::from(choices)),
+                error: SharedString::default(),
+            }
+        })
+        .collect()
+}
+
+/// Show the review prompt for a conflict, or hide it if none is left
+fn show_conflict(ui: &AppWindow, conflict: Option<PendingConflict>) {
+    match conflict {
+        Some(conflict) => {
+            ui.set_conflict_id(SharedString::from(conflict.id));
+            ui.set_conflict_name(SharedString::from(match conflict.room {
+                Some(room) => format!("{} (room '{}')", conflict.name, room),
+                None => conflict.name,
+            }));
+            ui.set_conflict_size(SharedString::from(humanize::size(conflict.size)));
+            ui.set_show_conflict(true);
+        }
+        None => ui.set_show_conflict(false),
+    }
+}
+
+/// Show `status` in the status bar, marked with its severity
+fn show_status(ui: &AppWindow, status: Status) {
+    ui.set_status_severity(match status.severity {
+        Severity::Info => StatusSeverity::Info,
+        Severity::Warning => StatusSeverity::Warning,
+        Severity::Error => StatusSeverity::Error,
+    });
+    ui.set_status_message(SharedString::from(status.message));
+}
+
+/// Show the availability window of the default server, if scheduled
+fn show_schedule(ui: &AppWindow, config: &ConfigData) {
+    let summary = match schedule::configured(config) {
+        Ok(schedule) => schedule.map(|schedule| schedule.describe()),
+        Err(e) => Some(format!("not applied, {}", e)),
+    };
+    ui.set_schedule_summary(SharedString::from(summary.unwrap_or_default()));
+}
+
+/// Show the server's URLs, starting the QR carousel at the first one
+fn show_server_urls(ui: &AppWindow, server_info: &ServerInfo) {
+    let urls: Vec<SharedString> = server_info
+        .urls
+        .iter()
+        .map(|url| SharedString::from(url.as_str()))
+        .collect();
+    ui.set_server_url(SharedString::from(server_info.url.as_str()));
+    ui.set_server_urls(ModelRc::new(VecModel::from(urls)));
+    ui.set_qr_index(0);
+}
+
+/// Fill the storage dashboard from the server's file accounting
+fn show_storage_usage(ui: &AppWindow, file_server: &FileServer) {
+    let usage = file_server.storage_usage();
+    let free = match file_server.free_space() {
+        Ok(bytes) => humanize::size(bytes),
+        Err(e) => {
+            error!("Failed to query free disk space: {}", e);
+            "unknown".to_string()
+        }
+    };
+
+    let mut types: Vec<(String, u64)> = usage.by_type.into_iter().collect();
+    types.sort_by_key(|(_, bytes)| std::cmp::Reverse(*bytes));
+    let types: Vec<StorageTypeItem> = types
+        .into_iter()
+        .map(|(file_type, bytes)| StorageTypeItem {
+            file_type: SharedString::from(file_type),
+            size: SharedString::from(humanize::size(bytes)),
+        })
+        .collect();
+
+    ui.set_storage_used(SharedString::from(humanize::size(usage.total_bytes)));
+    ui.set_storage_free(SharedString::from(free));
+    ui.set_storage_file_count(usage.file_count as i32);
+    ui.set_storage_types(ModelRc::new(VecModel::from(types)));
+
+    let files: Vec<ReceivedFileItem> = file_server
+        .files()
+        .into_iter()
+        .filter(|file| !file.shared)
+        .map(|file| ReceivedFileItem {
+            id: SharedString::from(file.id),
+            name: SharedString::from(file.name),
+            size: SharedString::from(humanize::size(file.size)),
+        })
+        .collect();
+    ui.set_storage_files(ModelRc::new(VecModel::from(files)));
+}
+
+/// Quarantined files of the default server for the Quarantine panel
+fn quarantine_items(file_server: &FileServer) -> Vec<QuarantineItem> {
+    file_server
+        .quarantined_files()
+        .into_iter()
+        .map(|(room, file)| QuarantineItem {
+            id: SharedString::from(file.id),
+            name: SharedString::from(file.name),
+            size: SharedString::from(humanize::size(file.size)),
+            room: SharedString::from(room.unwrap_or_default()),
+        })
+        .collect()
+}
+
+/// Look up a paired instance in the config
+fn find_peer(name: &str) -> Option<PeerConfig> {
+    let instance = ConfigData::instance().ok()?;
+    let config = instance.lock().unwrap();
+    config.peer(name).cloned()
+}
+
+/// Fill the Transfers panel with the transfers in flight on the servers and
+/// the download queue, updating rows in place so the list keeps its scroll
+/// position
+fn show_transfers(ui: &AppWindow, app_data: &AppData) {
+    let mut items: Vec<TransferItem> = app_data
+        .server_transfers
+        .lock()
+        .unwrap()
+        .list()
+        .iter()
+        .map(|transfer| {
+            let mut detail = match transfer.size {
+                Some(size) => format!(
+                    "{} of {}",
+                    humanize::size(transfer.bytes),
+                    humanize::size(size)
+                ),
+                None => humanize::size(transfer.bytes),
+            };
+            if transfer.paused {
+                detail.insert_str(0, "Paused, ");
+            } else if transfer.speed > 0.0 {
+                detail.push_str(&format!(", {}/s", humanize::size(transfer.speed as u64)));
+            }
+            TransferItem {
+                id: transfer.id as i32,
+                name: SharedString::from(transfer.title()),
+                detail: SharedString::from(detail),
+                fraction: transfer.fraction(),
+                state: SharedString::from(if transfer.paused { "paused" } else { "active" }),
+                server: true,
+            }
+        })
+        .collect();
+    items.extend(app_data.downloads.list().into_iter().map(|download| {
+        let (state, detail) = match &download.state {
+            DownloadState::Queued => ("queued", "Waiting".to_string()),
+            DownloadState::Active => ("active", download.progress()),
+            DownloadState::Paused => ("paused", format!("Paused, {}", download.progress())),
+            DownloadState::Done(path) => ("done", format!("Saved to {}", path.display())),
+            DownloadState::Failed(error) => ("failed", error.clone()),
+        };
+        TransferItem {
+            id: download.id as i32,
+            fraction: download.fraction(),
+            name: SharedString::from(download.name),
+            detail: SharedString::from(detail),
+            state: SharedString::from(state),
+            server: false,
+        }
+    }));
+
+    let model = ui.get_transfers();
+    if model.row_count() == items.len() {
+        for (row, item) in items.into_iter().enumerate() {
+            model.set_row_data(row, item);
+        }
+    } else {
+        ui.set_transfers(ModelRc::new(VecModel::from(items)));
+    }
+}
+
+/// Run the self-check in the background and show its report. At startup
+/// the report is only shown if a check failed.
+fn run_diagnostics(ui_handle: slint::Weak<AppWindow>, app_data: Arc<AppData>, startup: bool) {
+    let runtime = app_data.runtime.clone();
+    runtime.spawn_blocking(move || {
+        let server_running = app_data
+            .file_server
+            .lock()
+            .unwrap()
+            .get_server_info()
+            .running;
+        let report = match ConfigData::instance() {
+            Ok(instance) => diagnostics::run_self_check(&instance.lock().unwrap(), server_running),
+            Err(e) => {
+                error!("Failed to access config instance: {}", e);
+                return;
+            }
+        };
+        for check in &report.checks {
+            info!(
+                "Self-check {}: {:?} - {}",
+                check.name, check.status, check.detail
+            );
+        }
+
+        slint::invoke_from_event_loop(move || {
+            let ui = ui_handle.unwrap();
+            ui.set_diagnostics_report(SharedString::from(report.summary()));
+            ui.set_diagnostics_running(false);
+            if startup && report.has_failures() {
+                ui.set_show_diagnostics(true);
+            }
+        })
+        .unwrap();
+    });
+}
+
+/// Run the firewall check if it has not been done yet and remember that it ran
+fn run_first_start_firewall_check(port: u16) -> Option<firewall::FirewallCheck> {
+    let instance = ConfigData::instance().ok()?;
+    let mut config = instance.lock().unwrap();
+    if config.server.firewall_checked {
+        return None;
+    }
+
+    let check = firewall::check_port(port);
+    info!(
+        "First-start firewall check ({}): {:?}",
+        check.backend, check.status
+    );
+
+    config.server.firewall_checked = true;
+    if let Err(e) = config.save(&settings::path()) {
+        error!("Failed to save config after firewall check: {}", e);
+    }
+
+    Some(check)
 }
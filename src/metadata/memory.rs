@@ -0,0 +1,140 @@
+//! Records kept in memory only, for the default setup and for tests
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+
+use super::{Change, DeviceRecord, MetadataStore, ShareRecord, StoredFile, HISTORY_SIZE};
+use crate::server::event_log::LoggedEvent;
+
+#[derive(Default)]
+struct Records {
+    /// Files by ID, with the order they were added in
+    files: HashMap<String, (u64, StoredFile)>,
+    next_place: u64,
+    devices: HashMap<IpAddr, DeviceRecord>,
+    history: VecDeque<LoggedEvent>,
+    shares: Vec<ShareRecord>,
+}
+
+impl Records {
+    fn device(&mut self, address: IpAddr, at: u64) -> &mut DeviceRecord {
+        let device = self.devices.entry(address).or_insert(DeviceRecord {
+            address,
+            first_seen: at,
+            last_seen: at,
+            files: 0,
+            bytes: 0,
+        });
+        device.last_seen = device.last_seen.max(at);
+        device
+    }
+
+    fn apply(&mut self, change: &Change) {
+        match change {
+            Change::PutFile(stored) => {
+                let place = match self.files.get(&stored.file.id) {
+                    Some((place, _)) => *place,
+                    None => {
+                        self.next_place += 1;
+                        self.next_place
+                    }
+                };
+                self.files
+                    .insert(stored.file.id.clone(), (place, stored.clone()));
+            }
+            Change::RemoveFile(id) => {
+                self.files.remove(id);
+            }
+            Change::DeviceSeen { address, at } => {
+                self.device(*address, *at);
+            }
+            Change::DeviceSent { address, at, bytes } => {
+                let device = self.device(*address, *at);
+                device.files += 1;
+                device.bytes += bytes;
+            }
+            Change::Event(event) => {
+                if self.history.len() == HISTORY_SIZE {
+                    self.history.pop_front();
+                }
+                self.history.push_back(event.clone());
+            }
+            Change::PutShare(share) => {
+                self.shares.retain(|s| s.token != share.token);
+                self.shares.push(share.clone());
+            }
+            Change::RemoveShare(token) => self.shares.retain(|s| s.token != *token),
+        }
+    }
+}
+
+/// Records that last as long as the app runs
+#[derive(Default)]
+pub struct MemoryStore {
+    records: Mutex<Records>,
+}
+
+impl MetadataStore for MemoryStore {
+    fn apply(&self, changes: &[Change]) -> anyhow::Result<()> {
+        let mut records = self.records.lock().unwrap();
+        for change in changes {
+            records.apply(change);
+        }
+        Ok(())
+    }
+
+    fn files(&self) -> anyhow::Result<Vec<StoredFile>> {
+        let records = self.records.lock().unwrap();
+        let mut files: Vec<_> = records.files.values().collect();
+        files.sort_by_key(|(place, _)| *place);
+        Ok(files.into_iter().map(|(_, file)| file.clone()).collect())
+    }
+
+    fn devices(&self) -> anyhow::Result<Vec<DeviceRecord>> {
+        let mut devices: Vec<_> = self
+            .records
+            .lock()
+            .unwrap()
+            .devices
+            .values()
+            .cloned()
+            .collect();
+        devices.sort_by_key(|device| std::cmp::Reverse(device.last_seen));
+        Ok(devices)
+    }
+
+    fn history(&self, limit: usize) -> anyhow::Result<Vec<LoggedEvent>> {
+        let records = self.records.lock().unwrap();
+        let skip = records.history.len().saturating_sub(limit);
+        Ok(records.history.iter().skip(skip).cloned().collect())
+    }
+
+    fn shares(&self) -> anyhow::Result<Vec<ShareRecord>> {
+        Ok(self.records.lock().unwrap().shares.clone())
+    }
+
+    fn snapshot(&self, _path: &Path) -> anyhow::Result<bool> {
+        Ok(false)
+    }
+
+    fn restore(&self, _path: &Path) -> anyhow::Result<()> {
+        anyhow::bail!(
+            "metadata is only kept in memory; set storage.metadata to sqlite to restore it"
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::tests::check_store;
+
+    #[test]
+    fn test_memory_store() {
+        let store = MemoryStore::default();
+        check_store(&store);
+        assert!(!store.snapshot(Path::new("metadata.db")).unwrap());
+    }
+}
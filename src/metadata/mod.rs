@@ -0,0 +1,253 @@
+//! Records kept about a server: the files it lists, the devices that
+//! connected to it, its past events and its one-time links. They go through
+//! `MetadataStore`, kept in memory by default; with `storage.metadata` set
+//! to `sqlite` in a build with the `sqlite` feature they are kept in a
+//! database next to the settings file, updated in transactions, and survive
+//! restarts and crashes.
+
+mod memory;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::config::MetadataBackend;
+use crate::models::FileInfo;
+use crate::server::event_log::LoggedEvent;
+
+pub use memory::MemoryStore;
+
+/// Events kept in the history, the oldest dropped first
+pub const HISTORY_SIZE: usize = 10_000;
+
+/// A file listed on the main share or in a room
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredFile {
+    pub room: Option<String>,
+    pub file: FileInfo,
+}
+
+/// A device that connected to the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeviceRecord {
+    pub address: IpAddr,
+    /// Unix times it first and last connected or sent a file
+    #[serde(flatten, with = "crate::models::time::first_seen")]
+    pub first_seen: u64,
+    #[serde(flatten, with = "crate::models::time::last_seen")]
+    pub last_seen: u64,
+    /// Files it sent and their bytes, over all runs
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// A one-time download link not used yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShareRecord {
+    pub token: String,
+    pub file_id: String,
+    pub expires_at: u64,
+}
+
+/// A change to the records
+#[derive(Debug, Clone)]
+pub enum Change {
+    /// Add a file, or update the one with its ID
+    PutFile(StoredFile),
+    /// Forget the file with this ID
+    RemoveFile(String),
+    /// A device connected
+    DeviceSeen {
+        address: IpAddr,
+        at: u64,
+    },
+    /// A device sent a file of `bytes`
+    DeviceSent {
+        address: IpAddr,
+        at: u64,
+        bytes: u64,
+    },
+    Event(LoggedEvent),
+    PutShare(ShareRecord),
+    /// Forget the link with this token
+    RemoveShare(String),
+}
+
+/// Where records are kept
+pub trait MetadataStore: Send + Sync {
+    /// Make all of `changes`, or none of them if one fails
+    fn apply(&self, changes: &[Change]) -> anyhow::Result<()>;
+
+    /// Files, in the order they were first added
+    fn files(&self) -> anyhow::Result<Vec<StoredFile>>;
+
+    /// Devices, the most recently seen first
+    fn devices(&self) -> anyhow::Result<Vec<DeviceRecord>>;
+
+    /// The last `limit` events, oldest first
+    fn history(&self, limit: usize) -> anyhow::Result<Vec<LoggedEvent>>;
+
+    /// One-time links, expired or not
+    fn shares(&self) -> anyhow::Result<Vec<ShareRecord>>;
+
+    /// Write a consistent copy of the records to a new file at `path`;
+    /// `false` when the records are only kept in memory and nothing was
+    /// written
+    fn snapshot(&self, path: &Path) -> anyhow::Result<bool>;
+
+    /// Replace every record with those of a copy written by `snapshot`
+    fn restore(&self, path: &Path) -> anyhow::Result<()>;
+}
+
+/// Apply `changes` to `store`, logging rather than returning a failure;
+/// the server keeps running on what it has in memory
+pub fn save(store: &dyn MetadataStore, changes: &[Change]) {
+    if changes.is_empty() {
+        return;
+    }
+    if let Err(e) = store.apply(changes) {
+        log::error!("Failed to save {} metadata change(s): {}", changes.len(), e);
+    }
+}
+
+/// Open the store `backend` names for the server of `profile`, falling back
+/// to memory when it can't be opened
+pub fn open(backend: MetadataBackend, profile: Option<&str>) -> Arc<dyn MetadataStore> {
+    match backend {
+        MetadataBackend::Memory => {}
+        #[cfg(feature = "sqlite")]
+        MetadataBackend::Sqlite => {
+            let path = sqlite::database_path(profile);
+            match sqlite::SqliteStore::open(&path) {
+                Ok(store) => {
+                    log::info!("Keeping metadata in {:?}", path);
+                    return Arc::new(store);
+                }
+                Err(e) => log::error!(
+                    "Failed to open the metadata database {:?}, keeping metadata in memory: {}",
+                    path,
+                    e
+                ),
+            }
+        }
+        #[cfg(not(feature = "sqlite"))]
+        MetadataBackend::Sqlite => {
+            let _ = profile;
+            log::warn!(
+                "storage.metadata is sqlite, but this build has no SQLite support; keeping metadata in memory"
+            );
+        }
+    }
+    Arc::new(MemoryStore::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::integrations::ServerEvent;
+
+    pub(super) fn file(id: &str, room: Option<&str>) -> StoredFile {
+        StoredFile {
+            room: room.map(str::to_string),
+            file: FileInfo {
+                id: id.to_string(),
+                mime_type: "text/plain".to_string(),
+                added_at: 1000,
+                sha256: Some("ab".repeat(32)),
+                protected: true,
+                password_hash: Some("$argon2id$hash".to_string()),
+                device: Some("192.168.1.20".parse().unwrap()),
+                mode: Some(0o644),
+                ..FileInfo::new(format!("{}.txt", id), PathBuf::from(id), 10)
+            },
+        }
+    }
+
+    pub(super) fn event(at: u64) -> Change {
+        Change::Event(LoggedEvent {
+            at,
+            event: ServerEvent::ClientConnected {
+                address: "192.168.1.20".to_string(),
+            },
+        })
+    }
+
+    /// The behavior every store shares
+    pub(super) fn check_store(store: &dyn MetadataStore) {
+        let phone: IpAddr = "192.168.1.20".parse().unwrap();
+        let laptop: IpAddr = "192.168.1.30".parse().unwrap();
+        store
+            .apply(&[
+                Change::PutFile(file("a", None)),
+                Change::PutFile(file("b", Some("party"))),
+                Change::PutFile(file("c", None)),
+                Change::DeviceSeen {
+                    address: phone,
+                    at: 1000,
+                },
+                Change::DeviceSent {
+                    address: phone,
+                    at: 1010,
+                    bytes: 10,
+                },
+                Change::DeviceSeen {
+                    address: laptop,
+                    at: 1005,
+                },
+                Change::PutShare(ShareRecord {
+                    token: "t1".to_string(),
+                    file_id: "a".to_string(),
+                    expires_at: 5000,
+                }),
+                event(1000),
+                event(1001),
+            ])
+            .unwrap();
+
+        // Updating a file keeps its place
+        let mut corrupted = file("a", None);
+        corrupted.file.corrupted = true;
+        store
+            .apply(&[
+                Change::PutFile(corrupted.clone()),
+                Change::RemoveFile("c".to_string()),
+                Change::RemoveShare("t1".to_string()),
+                Change::DeviceSent {
+                    address: phone,
+                    at: 1020,
+                    bytes: 5,
+                },
+            ])
+            .unwrap();
+        assert_eq!(
+            store.files().unwrap(),
+            vec![corrupted, file("b", Some("party"))]
+        );
+        assert!(store.shares().unwrap().is_empty());
+
+        let devices = store.devices().unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(
+            devices[0],
+            DeviceRecord {
+                address: phone,
+                first_seen: 1000,
+                last_seen: 1020,
+                files: 2,
+                bytes: 15,
+            }
+        );
+        assert_eq!(devices[1].address, laptop);
+
+        let history = store.history(1).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].at, 1001);
+        assert_eq!(store.history(10).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,427 @@
+//! Records kept in an SQLite database, one per server, next to the settings
+//! file. Each batch of changes is one transaction.
+
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection, Row, Transaction};
+
+use super::{Change, DeviceRecord, MetadataStore, ShareRecord, StoredFile, HISTORY_SIZE};
+use crate::models::FileInfo;
+use crate::server::event_log::LoggedEvent;
+
+/// Database of the default server, next to the settings file
+const DATABASE_FILE: &str = "metadata.db";
+
+/// Bumped when the tables change, so an older JusTrans refuses the database
+const SCHEMA_VERSION: i64 = 1;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS files (
+        place INTEGER PRIMARY KEY AUTOINCREMENT,
+        id TEXT NOT NULL UNIQUE,
+        room TEXT,
+        name TEXT NOT NULL,
+        path TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        mime_type TEXT NOT NULL,
+        shared INTEGER NOT NULL,
+        added_at INTEGER NOT NULL,
+        folder TEXT,
+        sha256 TEXT,
+        corrupted INTEGER NOT NULL,
+        quarantined INTEGER NOT NULL,
+        protected INTEGER NOT NULL,
+        password_hash TEXT,
+        device TEXT,
+        mode INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS files_device ON files (device);
+    CREATE TABLE IF NOT EXISTS devices (
+        address TEXT PRIMARY KEY,
+        first_seen INTEGER NOT NULL,
+        last_seen INTEGER NOT NULL,
+        files INTEGER NOT NULL DEFAULT 0,
+        bytes INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE TABLE IF NOT EXISTS history (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        at INTEGER NOT NULL,
+        event TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS shares (
+        token TEXT PRIMARY KEY,
+        file_id TEXT NOT NULL,
+        expires_at INTEGER NOT NULL
+    );
+";
+
+/// Database file of the server of `profile`
+pub fn database_path(profile: Option<&str>) -> PathBuf {
+    let name = match profile {
+        Some(profile) => {
+            let profile: String = profile
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            format!("metadata-{}.db", profile)
+        }
+        None => DATABASE_FILE.to_string(),
+    };
+    settings::path().with_file_name(name)
+}
+
+/// Records in an SQLite database
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Open the database at `path`, creating it if needed
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    fn with_connection(connection: Connection) -> anyhow::Result<Self> {
+        // Another instance of the app may hold the database for a moment
+        connection.busy_timeout(std::time::Duration::from_secs(5))?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        let version: i64 = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            anyhow::bail!(
+                "the database was made by a newer JusTrans (schema {})",
+                version
+            );
+        }
+        connection.execute_batch(SCHEMA)?;
+        connection.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    #[cfg(test)]
+    fn in_memory() -> anyhow::Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+}
+
+fn apply_change(tx: &Transaction, change: &Change) -> rusqlite::Result<()> {
+    match change {
+        Change::PutFile(StoredFile { room, file }) => {
+            tx.execute(
+                "INSERT INTO files (id, room, name, path, size, mime_type, shared, added_at,
+                     folder, sha256, corrupted, quarantined, protected, password_hash, device, mode)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)
+                 ON CONFLICT (id) DO UPDATE SET room = excluded.room, name = excluded.name,
+                     path = excluded.path, size = excluded.size, mime_type = excluded.mime_type,
+                     shared = excluded.shared, added_at = excluded.added_at,
+                     folder = excluded.folder, sha256 = excluded.sha256,
+                     corrupted = excluded.corrupted, quarantined = excluded.quarantined,
+                     protected = excluded.protected, password_hash = excluded.password_hash,
+                     device = excluded.device, mode = excluded.mode",
+                params![
+                    file.id,
+                    room,
+                    file.name,
+                    file.path.to_string_lossy(),
+                    file.size,
+                    file.mime_type,
+                    file.shared,
+                    file.added_at,
+                    file.folder,
+                    file.sha256,
+                    file.corrupted,
+                    file.quarantined,
+                    file.protected,
+                    file.password_hash,
+                    file.device.map(|device| device.to_string()),
+                    file.mode,
+                ],
+            )?;
+        }
+        Change::RemoveFile(id) => {
+            tx.execute("DELETE FROM files WHERE id = ?1", params![id])?;
+        }
+        Change::DeviceSeen { address, at } => {
+            seen(tx, *address, *at)?;
+        }
+        Change::DeviceSent { address, at, bytes } => {
+            seen(tx, *address, *at)?;
+            tx.execute(
+                "UPDATE devices SET files = files + 1, bytes = bytes + ?2 WHERE address = ?1",
+                params![address.to_string(), bytes],
+            )?;
+        }
+        Change::Event(event) => {
+            let json = serde_json::to_string(event)
+                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+            tx.execute(
+                "INSERT INTO history (at, event) VALUES (?1, ?2)",
+                params![event.at, json],
+            )?;
+        }
+        Change::PutShare(share) => {
+            tx.execute(
+                "INSERT OR REPLACE INTO shares (token, file_id, expires_at) VALUES (?1, ?2, ?3)",
+                params![share.token, share.file_id, share.expires_at],
+            )?;
+        }
+        Change::RemoveShare(token) => {
+            tx.execute("DELETE FROM shares WHERE token = ?1", params![token])?;
+        }
+    }
+    Ok(())
+}
+
+fn seen(tx: &Transaction, address: IpAddr, at: u64) -> rusqlite::Result<usize> {
+    tx.execute(
+        "INSERT INTO devices (address, first_seen, last_seen) VALUES (?1, ?2, ?2)
+         ON CONFLICT (address) DO UPDATE SET last_seen = max(last_seen, excluded.last_seen)",
+        params![address.to_string(), at],
+    )
+}
+
+fn file_from_row(row: &Row) -> rusqlite::Result<StoredFile> {
+    let device: Option<String> = row.get("device")?;
+    Ok(StoredFile {
+        room: row.get("room")?,
+        file: FileInfo {
+            id: row.get("id")?,
+            name: row.get("name")?,
+            path: PathBuf::from(row.get::<_, String>("path")?),
+            size: row.get("size")?,
+            mime_type: row.get("mime_type")?,
+            shared: row.get("shared")?,
+            added_at: row.get("added_at")?,
+            folder: row.get("folder")?,
+            sha256: row.get("sha256")?,
+            corrupted: row.get("corrupted")?,
+            quarantined: row.get("quarantined")?,
+            protected: row.get("protected")?,
+            password_hash: row.get("password_hash")?,
+            device: device.and_then(|device| device.parse().ok()),
+            mode: row.get("mode")?,
+        },
+    })
+}
+
+fn device_from_row(row: &Row) -> rusqlite::Result<DeviceRecord> {
+    let address: String = row.get("address")?;
+    Ok(DeviceRecord {
+        address: address.parse().map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
+        })?,
+        first_seen: row.get("first_seen")?,
+        last_seen: row.get("last_seen")?,
+        files: row.get("files")?,
+        bytes: row.get("bytes")?,
+    })
+}
+
+/// Replace the records with those of the attached `snapshot` database
+fn replace_records(connection: &mut Connection) -> anyhow::Result<()> {
+    let version: i64 =
+        connection.query_row("PRAGMA snapshot.user_version", [], |row| row.get(0))?;
+    if version != SCHEMA_VERSION {
+        anyhow::bail!(
+            "the snapshot has schema {}, this JusTrans uses {}",
+            version,
+            SCHEMA_VERSION
+        );
+    }
+    let tx = connection.transaction()?;
+    for table in ["files", "devices", "history", "shares"] {
+        tx.execute(&format!("DELETE FROM main.{}", table), [])?;
+        tx.execute(
+            &format!("INSERT INTO main.{0} SELECT * FROM snapshot.{0}", table),
+            [],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+impl MetadataStore for SqliteStore {
+    fn apply(&self, changes: &[Change]) -> anyhow::Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let tx = connection.transaction()?;
+        for change in changes {
+            apply_change(&tx, change)?;
+        }
+        if changes
+            .iter()
+            .any(|change| matches!(change, Change::Event(_)))
+        {
+            tx.execute(
+                "DELETE FROM history WHERE id <= (SELECT max(id) FROM history) - ?1",
+                params![HISTORY_SIZE as i64],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn files(&self) -> anyhow::Result<Vec<StoredFile>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare("SELECT * FROM files ORDER BY place")?;
+        let files = statement
+            .query_map([], file_from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(files)
+    }
+
+    fn devices(&self) -> anyhow::Result<Vec<DeviceRecord>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare("SELECT * FROM devices ORDER BY last_seen DESC")?;
+        let devices = statement
+            .query_map([], device_from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(devices)
+    }
+
+    fn history(&self, limit: usize) -> anyhow::Result<Vec<LoggedEvent>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT event FROM (SELECT id, event FROM history ORDER BY id DESC LIMIT ?1)
+             ORDER BY id",
+        )?;
+        let events = statement
+            .query_map(params![limit as i64], |row| row.get::<_, String>(0))?
+            .filter_map(|json| match json {
+                // Events of a newer JusTrans are skipped
+                Ok(json) => serde_json::from_str(&json).ok().map(Ok),
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(events)
+    }
+
+    fn snapshot(&self, path: &Path) -> anyhow::Result<bool> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute("VACUUM INTO ?1", params![path.to_string_lossy()])?;
+        Ok(true)
+    }
+
+    fn restore(&self, path: &Path) -> anyhow::Result<()> {
+        // Attaching a missing file would create an empty database
+        if !path.is_file() {
+            anyhow::bail!("no metadata snapshot at {:?}", path);
+        }
+        let mut connection = self.connection.lock().unwrap();
+        connection.execute(
+            "ATTACH DATABASE ?1 AS snapshot",
+            params![path.to_string_lossy()],
+        )?;
+        let restored = replace_records(&mut connection);
+        connection.execute("DETACH DATABASE snapshot", [])?;
+        restored
+    }
+
+    fn shares(&self) -> anyhow::Result<Vec<ShareRecord>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare("SELECT * FROM shares ORDER BY expires_at")?;
+        let shares = statement
+            .query_map([], |row| {
+                Ok(ShareRecord {
+                    token: row.get("token")?,
+                    file_id: row.get("file_id")?,
+                    expires_at: row.get("expires_at")?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(shares)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::tests::{check_store, event, file};
+
+    #[test]
+    fn test_sqlite_store() {
+        check_store(&SqliteStore::in_memory().unwrap());
+    }
+
+    #[test]
+    fn test_kept_between_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DATABASE_FILE);
+        SqliteStore::open(&path)
+            .unwrap()
+            .apply(&[Change::PutFile(file("a", None)), event(1000)])
+            .unwrap();
+
+        let store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.files().unwrap(), vec![file("a", None)]);
+        assert_eq!(store.history(10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_changes_are_all_or_nothing() {
+        let store = SqliteStore::in_memory().unwrap();
+        store
+            .connection
+            .lock()
+            .unwrap()
+            .execute_batch("CREATE TRIGGER no_shares BEFORE INSERT ON shares BEGIN SELECT RAISE(ABORT, 'full'); END")
+            .unwrap();
+
+        let failed = store.apply(&[
+            Change::PutFile(file("a", None)),
+            Change::PutShare(ShareRecord {
+                token: "t1".to_string(),
+                file_id: "a".to_string(),
+                expires_at: 5000,
+            }),
+        ]);
+        assert!(failed.is_err());
+        assert!(store.files().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot = dir.path().join("snapshot.db");
+        let store = SqliteStore::in_memory().unwrap();
+        store
+            .apply(&[Change::PutFile(file("a", None)), event(1000)])
+            .unwrap();
+        assert!(store.snapshot(&snapshot).unwrap());
+
+        store
+            .apply(&[
+                Change::RemoveFile("a".to_string()),
+                Change::PutFile(file("b", None)),
+            ])
+            .unwrap();
+        store.restore(&snapshot).unwrap();
+        assert_eq!(store.files().unwrap(), vec![file("a", None)]);
+        assert_eq!(store.history(10).unwrap().len(), 1);
+
+        // Still usable after the restore
+        store.apply(&[event(1001)]).unwrap();
+        assert_eq!(store.history(10).unwrap().len(), 2);
+        assert!(store.restore(&dir.path().join("missing.db")).is_err());
+        assert_eq!(store.files().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let store = SqliteStore::in_memory().unwrap();
+        let events: Vec<_> = (0..HISTORY_SIZE as u64 + 5).map(event).collect();
+        store.apply(&events).unwrap();
+
+        let history = store.history(HISTORY_SIZE * 2).unwrap();
+        assert_eq!(history.len(), HISTORY_SIZE);
+        assert_eq!(history[0].at, 5);
+    }
+}
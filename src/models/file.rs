@@ -1,40 +1,579 @@
+use chrono::{Local, TimeZone};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use super::names::{self, NameMatch};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct FileInfo {
     pub id: String,
     pub name: String,
     pub path: PathBuf,
     pub size: u64,
     pub mime_type: String,
+    /// Shared from the desktop in place; never deleted by the server
+    #[serde(default)]
+    pub shared: bool,
+    /// When the file was added, in seconds since the Unix epoch
+    #[serde(flatten, with = "super::time::added_at")]
+    pub added_at: u64,
+    /// `YYYY/MM` folder the file was sorted into; organized files are kept
+    /// when the server stops
+    #[serde(default)]
+    pub folder: Option<String>,
+    /// SHA-256 of a received file, recorded so scrubbing can detect damage
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// The stored copy no longer matches `sha256`
+    #[serde(default)]
+    pub corrupted: bool,
+    /// A received program or script, stored under a `.blocked` path until
+    /// the user releases it
+    #[serde(default)]
+    pub quarantined: bool,
+    /// Downloads need the password the sender set
+    #[serde(default)]
+    pub protected: bool,
+    /// Argon2 hash of that password; never sent to clients
+    #[serde(default, skip_serializing)]
+    pub password_hash: Option<String>,
+    /// Address of the device that uploaded the file, for its quota and
+    /// retention rules; not shown to portal users
+    #[serde(default, skip_serializing)]
+    pub device: Option<IpAddr>,
+    /// Unix permission bits: those the sender declared while the file is
+    /// received, then those it was stored with. Peers read them to keep
+    /// scripts executable; `None` off Unix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FileList {
+impl FileInfo {
+    /// A new, unshared file added now under a fresh id, typed as
+    /// `application/octet-stream`
+    pub fn new(name: String, path: PathBuf, size: u64) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            name,
+            path,
+            size,
+            mime_type: "application/octet-stream".to_string(),
+            added_at: unix_now(),
+            ..Default::default()
+        }
+    }
+
+    /// Lowercase file extension used to group files by type
+    pub fn file_type(&self) -> String {
+        std::path::Path::new(&self.name)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_else(|| "other".to_string())
+    }
+}
+
+/// Local calendar day of a Unix timestamp, `YYYY-MM-DD`
+fn local_day(timestamp: u64) -> String {
+    Local
+        .timestamp_opt(timestamp as i64, 0)
+        .single()
+        .map(|time| time.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+/// Current time in seconds since the Unix epoch
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Bytes used by files received from clients
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StorageUsage {
+    pub total_bytes: u64,
+    pub file_count: usize,
+    /// Bytes per file type, keyed by extension
+    pub by_type: BTreeMap<String, u64>,
+}
+
+impl StorageUsage {
+    /// Add the usage of another file list to this one
+    pub fn merge(&mut self, other: StorageUsage) {
+        self.total_bytes += other.total_bytes;
+        self.file_count += other.file_count;
+        for (file_type, bytes) in other.by_type {
+            *self.by_type.entry(file_type).or_default() += bytes;
+        }
+    }
+}
+
+/// Uploads from one device at most this far apart belong to one session
+pub const SESSION_GAP_SECS: u64 = 10 * 60;
+
+/// Files added together: one upload session of a device, or the files
+/// shared from the desktop, on one day
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileGroup {
+    /// ID of the group's first file, stable while that file is listed
+    pub id: String,
+    /// Local day the group started, `YYYY-MM-DD`
+    pub day: String,
+    #[serde(flatten, with = "super::time::started_at")]
+    pub started_at: u64,
+    #[serde(flatten, with = "super::time::ended_at")]
+    pub ended_at: u64,
+    /// Shared from the desktop rather than uploaded
+    pub shared: bool,
+    pub size: u64,
+    /// IDs of the files, oldest first
+    pub files: Vec<String>,
+}
+
+/// A file list with its groups, as served to the portal
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupedFileList {
     pub files: Vec<FileInfo>,
+    /// Newest first
+    pub groups: Vec<FileGroup>,
+}
+
+impl From<&FileList> for GroupedFileList {
+    fn from(list: &FileList) -> Self {
+        Self {
+            files: list.iter().cloned().collect(),
+            groups: list.groups().to_vec(),
+        }
+    }
+}
+
+/// A file list as sent over the API
+#[derive(Serialize, Deserialize)]
+struct ListedFiles {
+    files: Vec<FileInfo>,
+}
+
+/// Files offered for download, in the order they were added. Files are
+/// indexed by ID and by name, so shares that run for months with tens of
+/// thousands of files answer lookups without scanning them all.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "ListedFiles", into = "ListedFiles")]
+pub struct FileList {
+    /// Files by ID, with their place in `order`
+    files: HashMap<String, (u64, FileInfo)>,
+    /// IDs by place, oldest first
+    order: BTreeMap<u64, String>,
+    /// Place of the next file added
+    next_place: u64,
+    /// IDs by folded name
+    names: HashMap<String, Vec<String>>,
+    /// Groups of the files, worked out when first asked for after a change
+    groups: OnceLock<Vec<FileGroup>>,
+}
+
+impl From<ListedFiles> for FileList {
+    fn from(listed: ListedFiles) -> Self {
+        let mut list = FileList::new();
+        for file in listed.files {
+            list.add_file(file);
+        }
+        list
+    }
+}
+
+impl From<FileList> for ListedFiles {
+    fn from(list: FileList) -> Self {
+        Self {
+            files: list.iter().cloned().collect(),
+        }
+    }
 }
 
 impl FileList {
     pub fn new() -> Self {
-        Self { files: Vec::new() }
+        Self::default()
     }
 
+    /// Add a file after the others, replacing a file with the same ID
     pub fn add_file(&mut self, file: FileInfo) {
-        self.files.push(file);
+        self.remove(&file.id);
+        let place = self.next_place;
+        self.next_place += 1;
+        self.order.insert(place, file.id.clone());
+        self.index_name(&file.name, &file.id);
+        self.files.insert(file.id.clone(), (place, file));
+        self.changed();
     }
 
     pub fn get_file_by_id(&self, id: &str) -> Option<&FileInfo> {
-        self.files.iter().find(|f| f.id == id)
+        self.files.get(id).map(|(_, file)| file)
+    }
+
+    /// Change the file with the given ID and return what `change` returns
+    pub fn update<R>(&mut self, id: &str, change: impl FnOnce(&mut FileInfo) -> R) -> Option<R> {
+        let (_, file) = self.files.get_mut(id)?;
+        let name = file.name.clone();
+        let result = change(file);
+        let renamed = (file.name != name).then(|| file.name.clone());
+        if let Some(new_name) = renamed {
+            self.unindex_name(&name, id);
+            self.index_name(&new_name, id);
+        }
+        self.changed();
+        Some(result)
+    }
+
+    /// Remove the file with the given ID and return it
+    pub fn remove(&mut self, id: &str) -> Option<FileInfo> {
+        let (place, file) = self.files.remove(id)?;
+        self.order.remove(&place);
+        self.unindex_name(&file.name, id);
+        self.changed();
+        Some(file)
+    }
+
+    pub fn get_file_by_name(&self, name: &str, matching: NameMatch) -> Option<&FileInfo> {
+        self.names
+            .get(&names::fold(name))?
+            .iter()
+            .filter_map(|id| self.get_file_by_id(id))
+            .find(|f| matching.same(&f.name, name))
+    }
+
+    /// Remove the file with the given name and return it
+    pub fn remove_by_name(&mut self, name: &str, matching: NameMatch) -> Option<FileInfo> {
+        let id = self.get_file_by_name(name, matching)?.id.clone();
+        self.remove(&id)
+    }
+
+    /// Remove the files `expired` is true for and return them
+    pub fn remove_where(&mut self, expired: impl Fn(&FileInfo) -> bool) -> Vec<FileInfo> {
+        let ids: Vec<String> = self
+            .iter()
+            .filter(|f| expired(f))
+            .map(|f| f.id.clone())
+            .collect();
+        ids.iter().filter_map(|id| self.remove(id)).collect()
+    }
+
+    /// The files whose names contain `query`, in any case or Unicode form
+    pub fn search(&self, query: &str) -> FileList {
+        let query = names::fold(query.trim());
+        let mut found: Vec<(u64, &FileInfo)> = self
+            .names
+            .iter()
+            .filter(|(name, _)| name.contains(&query))
+            .flat_map(|(_, ids)| ids)
+            .filter_map(|id| self.files.get(id))
+            .map(|(place, file)| (*place, file))
+            .collect();
+        found.sort_by_key(|(place, _)| *place);
+        let mut list = FileList::new();
+        for (_, file) in found {
+            list.add_file(file.clone());
+        }
+        list
+    }
+
+    /// The files, oldest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &FileInfo> {
+        self.order.values().map(|id| &self.files[id].1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
     }
 
     pub fn clear(&mut self) {
-        self.files.clear();
+        *self = Self::new();
+    }
+
+    fn index_name(&mut self, name: &str, id: &str) {
+        self.names
+            .entry(names::fold(name))
+            .or_default()
+            .push(id.to_string());
+    }
+
+    fn unindex_name(&mut self, name: &str, id: &str) {
+        let key = names::fold(name);
+        if let Some(ids) = self.names.get_mut(&key) {
+            ids.retain(|indexed| indexed != id);
+            if ids.is_empty() {
+                self.names.remove(&key);
+            }
+        }
+    }
+
+    /// Forget what was worked out from the files before they changed
+    fn changed(&mut self) {
+        self.groups = OnceLock::new();
+    }
+
+    /// Storage used by received files; files shared in place from the
+    /// desktop don't take up space in the storage directory
+    pub fn usage(&self) -> StorageUsage {
+        let mut usage = StorageUsage::default();
+        for file in self.iter().filter(|f| !f.shared) {
+            usage.total_bytes += file.size;
+            usage.file_count += 1;
+            *usage.by_type.entry(file.file_type()).or_default() += file.size;
+        }
+        usage
+    }
+
+    /// The files grouped by upload session, device and day, newest group
+    /// first. A session is a run of uploads from one device with no more
+    /// than `SESSION_GAP_SECS` between them.
+    pub fn groups(&self) -> &[FileGroup] {
+        self.groups.get_or_init(|| self.group_files())
+    }
+
+    fn group_files(&self) -> Vec<FileGroup> {
+        let mut files: Vec<&FileInfo> = self.iter().collect();
+        files.sort_by_key(|f| f.added_at);
+
+        let mut groups: Vec<FileGroup> = Vec::new();
+        // Open group of each device, and of the desktop's shared files
+        let mut open: HashMap<(bool, Option<IpAddr>), usize> = HashMap::new();
+        for file in files {
+            let day = local_day(file.added_at);
+            let key = (file.shared, file.device);
+            let current = open.get(&key).copied().filter(|&index| {
+                let group = &groups[index];
+                group.day == day && file.added_at - group.ended_at <= SESSION_GAP_SECS
+            });
+            match current {
+                Some(index) => {
+                    let group = &mut groups[index];
+                    group.ended_at = file.added_at;
+                    group.size += file.size;
+                    group.files.push(file.id.clone());
+                }
+                None => {
+                    open.insert(key, groups.len());
+                    groups.push(FileGroup {
+                        id: file.id.clone(),
+                        day,
+                        started_at: file.added_at,
+                        ended_at: file.added_at,
+                        shared: file.shared,
+                        size: file.size,
+                        files: vec![file.id.clone()],
+                    });
+                }
+            }
+        }
+        groups.reverse();
+        groups
+    }
+
+    /// Remove received files added before `cutoff` (seconds since the Unix
+    /// epoch) from the list and return them
+    pub fn remove_older_than(&mut self, cutoff: u64) -> Vec<FileInfo> {
+        self.remove_where(|f| !f.shared && f.added_at < cutoff)
     }
 }
 
-impl Default for FileList {
-    fn default() -> Self {
-        Self::new()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, size: u64, added_at: u64, shared: bool) -> FileInfo {
+        FileInfo {
+            id: name.to_string(),
+            shared,
+            added_at,
+            ..FileInfo::new(name.to_string(), PathBuf::from(name), size)
+        }
+    }
+
+    #[test]
+    fn test_usage_by_type() {
+        let mut list = FileList::new();
+        list.add_file(file("a.JPG", 100, 0, false));
+        list.add_file(file("b.jpg", 50, 0, false));
+        list.add_file(file("notes", 10, 0, false));
+        list.add_file(file("desktop.pdf", 1000, 0, true));
+
+        let usage = list.usage();
+        assert_eq!(usage.total_bytes, 160);
+        assert_eq!(usage.file_count, 3);
+        assert_eq!(usage.by_type.get("jpg"), Some(&150));
+        assert_eq!(usage.by_type.get("other"), Some(&10));
+        assert!(!usage.by_type.contains_key("pdf"));
+    }
+
+    #[test]
+    fn test_groups() {
+        let phone = Some("192.168.1.20".parse().unwrap());
+        let laptop = Some("192.168.1.30".parse().unwrap());
+        let noon = Local
+            .with_ymd_and_hms(2024, 5, 1, 12, 0, 0)
+            .unwrap()
+            .timestamp() as u64;
+        let mut list = FileList::new();
+        for (name, added_at, device) in [
+            ("a.jpg", noon, phone),
+            ("b.jpg", noon + 60, phone),
+            ("report.pdf", noon + 90, laptop),
+            // Still the same session, the gap counts from the last file
+            ("c.jpg", noon + 60 + SESSION_GAP_SECS, phone),
+            ("d.jpg", noon + 120 + 2 * SESSION_GAP_SECS, phone),
+            ("e.jpg", noon + 24 * 60 * 60, phone),
+        ] {
+            let mut file = file(name, 10, added_at, false);
+            file.device = device;
+            list.add_file(file);
+        }
+        list.add_file(file("shared.txt", 5, noon + 30, true));
+
+        let groups = list.groups();
+        let files: Vec<Vec<&str>> = groups
+            .iter()
+            .map(|g| g.files.iter().map(String::as_str).collect())
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                vec!["e.jpg"],
+                vec!["d.jpg"],
+                vec!["report.pdf"],
+                vec!["shared.txt"],
+                vec!["a.jpg", "b.jpg", "c.jpg"],
+            ]
+        );
+        assert_eq!(groups[0].day, "2024-05-02");
+        assert!(groups[3].shared);
+        let session = &groups[4];
+        assert_eq!(session.id, "a.jpg");
+        assert_eq!(session.day, "2024-05-01");
+        assert_eq!(session.size, 30);
+        assert_eq!(session.ended_at - session.started_at, 60 + SESSION_GAP_SECS);
+    }
+
+    #[test]
+    fn test_names() {
+        let mut list = FileList::new();
+        list.add_file(file("Cafe\u{301}.txt", 1, 0, false));
+        list.add_file(file("notes.txt", 1, 0, false));
+
+        assert!(list
+            .get_file_by_name("Café.txt", NameMatch::Exact)
+            .is_some());
+        assert!(list
+            .get_file_by_name("CAFÉ.txt", NameMatch::Exact)
+            .is_none());
+        assert!(list
+            .get_file_by_name("CAFÉ.txt", NameMatch::IgnoreCase)
+            .is_some());
+        let found = list.search("café");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found.iter().next().unwrap().name, "Cafe\u{301}.txt");
+
+        let removed = list.remove_by_name("NOTES.TXT", NameMatch::IgnoreCase);
+        assert_eq!(removed.unwrap().name, "notes.txt");
+        assert_eq!(list.len(), 1);
+    }
+
+    #[test]
+    fn test_remove_older_than() {
+        let mut list = FileList::new();
+        list.add_file(file("old.txt", 1, 100, false));
+        list.add_file(file("new.txt", 1, 300, false));
+        list.add_file(file("shared.txt", 1, 100, true));
+
+        let removed = list.remove_older_than(200);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].name, "old.txt");
+        assert_eq!(list.len(), 2);
+    }
+
+    #[test]
+    fn test_index() {
+        let mut list = FileList::new();
+        list.add_file(file("a.txt", 1, 0, false));
+        list.add_file(file("b.txt", 2, 0, false));
+        list.add_file(file("c.txt", 3, 0, false));
+        assert_eq!(list.groups()[0].size, 6);
+
+        list.update("b.txt", |file| file.name = "renamed.txt".to_string());
+        assert!(list.get_file_by_name("b.txt", NameMatch::Exact).is_none());
+        assert_eq!(
+            list.get_file_by_name("renamed.txt", NameMatch::Exact)
+                .unwrap()
+                .id,
+            "b.txt"
+        );
+        assert_eq!(list.remove("a.txt").unwrap().size, 1);
+        assert!(list.get_file_by_name("a.txt", NameMatch::Exact).is_none());
+        // Changes show in the groups worked out before them
+        assert_eq!(list.groups()[0].size, 5);
+
+        list.add_file(file("a.txt", 1, 0, false));
+        let ids: Vec<&str> = list.iter().map(|f| f.id.as_str()).collect();
+        assert_eq!(ids, vec!["b.txt", "c.txt", "a.txt"]);
+
+        let json = serde_json::to_string(&list).unwrap();
+        let parsed: FileList = serde_json::from_str(&json).unwrap();
+        let names: Vec<&str> = parsed.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["renamed.txt", "c.txt", "a.txt"]);
+    }
+
+    /// Lookups, search and listing on a long-running share. Run with
+    /// `cargo test --release -- --ignored bench_`.
+    #[test]
+    #[ignore]
+    fn bench_large_list() {
+        const FILES: usize = 50_000;
+        let mut list = FileList::new();
+        let start = std::time::Instant::now();
+        for i in 0..FILES {
+            list.add_file(file(&format!("IMG_{:05}.jpg", i), 1000, i as u64, false));
+        }
+        let added = start.elapsed();
+
+        let start = std::time::Instant::now();
+        for i in (0..FILES).step_by(10) {
+            let name = format!("img_{:05}.JPG", i);
+            assert!(list
+                .get_file_by_name(&name, NameMatch::IgnoreCase)
+                .is_some());
+            assert!(list.get_file_by_id(&format!("IMG_{:05}.jpg", i)).is_some());
+        }
+        let lookups = start.elapsed();
+
+        let start = std::time::Instant::now();
+        assert_eq!(list.search("img_4999").len(), 10);
+        let search = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let body = serde_json::to_vec(&GroupedFileList::from(&list)).unwrap();
+        let listing = start.elapsed();
+        let start = std::time::Instant::now();
+        let _ = serde_json::to_vec(&GroupedFileList::from(&list)).unwrap();
+        let relisting = start.elapsed();
+
+        println!(
+            "{} files: added in {:?}, {} lookups {:?}, search {:?}, listing of {} KB {:?}, again {:?}",
+            FILES,
+            added,
+            FILES / 10 * 2,
+            lookups,
+            search,
+            body.len() / 1024,
+            listing,
+            relisting
+        );
+        assert!(relisting < listing);
     }
 }
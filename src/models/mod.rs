@@ -1,3 +1,10 @@
 pub mod file;
+pub mod names;
+pub mod room;
+pub mod snippet;
+pub mod time;
 
-pub use file::{FileInfo, FileList};
+pub use file::{unix_now, FileInfo, FileList, GroupedFileList, StorageUsage};
+pub use names::NameMatch;
+pub use room::Room;
+pub use snippet::{LinkPreview, Snippet};
@@ -0,0 +1,71 @@
+//! Comparing file names from different devices. iOS and macOS send names
+//! decomposed (NFD), so an "é" may be two code points from an iPhone and
+//! one from a Windows laptop; names are stored composed (NFC) and compared
+//! that way, ignoring case when `storage.case_insensitive_names` is on.
+
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+
+/// How names of files in a list are told apart
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NameMatch {
+    /// Names differing in case are different files
+    #[default]
+    Exact,
+    /// Names differing only in case are the same file, as on Windows and
+    /// macOS disks
+    IgnoreCase,
+}
+
+impl NameMatch {
+    pub fn from_config(case_insensitive: bool) -> Self {
+        if case_insensitive {
+            NameMatch::IgnoreCase
+        } else {
+            NameMatch::Exact
+        }
+    }
+
+    /// Whether `a` and `b` name the same file
+    pub fn same(self, a: &str, b: &str) -> bool {
+        match self {
+            NameMatch::Exact => a == b || normalize(a) == normalize(b),
+            NameMatch::IgnoreCase => fold(a) == fold(b),
+        }
+    }
+}
+
+/// `name` in composed form (NFC)
+pub fn normalize(name: &str) -> String {
+    match is_nfc_quick(name.chars()) {
+        IsNormalized::Yes => name.to_string(),
+        _ => name.nfc().collect(),
+    }
+}
+
+/// `name` composed and in lower case, for comparing without case
+pub fn fold(name: &str) -> String {
+    normalize(name).to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// "Café.txt" as iOS sends it, with a combining acute accent
+    const DECOMPOSED: &str = "Cafe\u{301}.txt";
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(DECOMPOSED), "Café.txt");
+        assert_eq!(normalize("Café.txt"), "Café.txt");
+        assert_eq!(fold("ÉTÉ.JPG"), "été.jpg");
+    }
+
+    #[test]
+    fn test_same_name() {
+        assert!(NameMatch::Exact.same(DECOMPOSED, "Café.txt"));
+        assert!(!NameMatch::Exact.same("café.txt", "Café.txt"));
+        assert!(NameMatch::IgnoreCase.same(DECOMPOSED, "CAFÉ.TXT"));
+        assert!(!NameMatch::IgnoreCase.same("cafe.txt", "café.txt"));
+    }
+}
@@ -0,0 +1,79 @@
+use std::sync::{Arc, Mutex};
+
+use subtle::ConstantTimeEq;
+
+use super::FileList;
+
+/// Maximum length of a room name
+const MAX_ROOM_NAME_LEN: usize = 32;
+
+/// An isolated sharing namespace with its own file list and optional PIN
+#[derive(Debug, Clone)]
+pub struct Room {
+    pub name: String,
+    pub pin: Option<String>,
+    pub file_list: Arc<Mutex<FileList>>,
+}
+
+impl Room {
+    pub fn new(name: &str, pin: Option<String>) -> anyhow::Result<Self> {
+        if !is_valid_room_name(name) {
+            return Err(anyhow::anyhow!(
+                "Invalid room name '{}': use 1-{} lowercase letters, digits, '-' or '_'",
+                name,
+                MAX_ROOM_NAME_LEN
+            ));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            pin: pin.filter(|p| !p.is_empty()),
+            file_list: Arc::new(Mutex::new(FileList::new())),
+        })
+    }
+
+    /// Check a PIN supplied by a client against the room's PIN, taking the
+    /// same time however much of it matches
+    pub fn check_pin(&self, pin: Option<&str>) -> bool {
+        match (&self.pin, pin) {
+            (Some(expected), Some(pin)) => expected.as_bytes().ct_eq(pin.as_bytes()).into(),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+}
+
+/// Room names are used in URLs, so only allow a safe subset of characters
+pub fn is_valid_room_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_ROOM_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_room_names() {
+        assert!(is_valid_room_name("team-standup"));
+        assert!(is_valid_room_name("room_1"));
+        assert!(!is_valid_room_name(""));
+        assert!(!is_valid_room_name("Team"));
+        assert!(!is_valid_room_name("../etc"));
+        assert!(!is_valid_room_name(&"a".repeat(33)));
+    }
+
+    #[test]
+    fn test_room_pin() {
+        let open = Room::new("open", Some(String::new())).unwrap();
+        assert!(open.check_pin(None));
+
+        let locked = Room::new("locked", Some("1234".to_string())).unwrap();
+        assert!(!locked.check_pin(None));
+        assert!(!locked.check_pin(Some("0000")));
+        assert!(locked.check_pin(Some("1234")));
+    }
+}
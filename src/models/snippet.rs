@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+
+/// A piece of text pasted into the portal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snippet {
+    pub id: String,
+    /// Raw text as pasted, used by the copy button
+    pub content: String,
+    /// `text`, `markdown` or the language of a code snippet, e.g. `rust`
+    pub format: String,
+    /// HTML rendered on the server when the snippet was shared
+    pub html: String,
+    /// When the snippet was shared, in seconds since the Unix epoch
+    pub created_at: u64,
+    /// Title and icon of the page, when the snippet is a link and
+    /// `server.link_previews` is on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview: Option<LinkPreview>,
+}
+
+/// What a link snippet points to, as shown on its card
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LinkPreview {
+    pub title: String,
+    /// The page's icon as a `data:` URL, so the portal needn't load anything
+    /// from the site
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+}
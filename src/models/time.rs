@@ -0,0 +1,163 @@
+//! Times in API responses. Fields hold Unix seconds, as they always have;
+//! the times of history events, file metadata and upload sessions also go
+//! out as an RFC 3339 UTC twin named after the field with `_utc` added:
+//!
+//! ```json
+//! "added_at": 1760607000, "added_at_utc": "2025-10-16T09:30:00Z"
+//! ```
+//!
+//! Clients should read the twins, which carry no time zone ambiguity, and
+//! render them in the device's locale. A field gets its twin with
+//! `#[serde(flatten, with = "crate::models::time::<field>")]`; the twin is
+//! ignored when the JSON is read back.
+
+use chrono::{SecondsFormat, TimeZone, Utc};
+use serde::ser::SerializeMap;
+use serde::Serializer;
+
+/// A Unix time as RFC 3339 in UTC, e.g. `2025-10-16T09:30:00Z`
+pub fn rfc3339(unix: u64) -> String {
+    Utc.timestamp_opt(unix as i64, 0)
+        .single()
+        .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_default()
+}
+
+/// Write `unix` under `key` and as RFC 3339 under `twin`
+fn serialize_twin<S: Serializer>(
+    key: &'static str,
+    twin: &'static str,
+    unix: u64,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(2))?;
+    map.serialize_entry(key, &unix)?;
+    map.serialize_entry(twin, &rfc3339(unix))?;
+    map.end()
+}
+
+pub mod at {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(at: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        super::serialize_twin("at", "at_utc", *at, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        #[derive(Deserialize)]
+        struct Field {
+            at: u64,
+        }
+        Field::deserialize(deserializer).map(|field| field.at)
+    }
+}
+
+pub mod added_at {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(added_at: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        super::serialize_twin("added_at", "added_at_utc", *added_at, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        // Lists from older instances may lack it
+        #[derive(Deserialize)]
+        struct Field {
+            #[serde(default)]
+            added_at: u64,
+        }
+        Field::deserialize(deserializer).map(|field| field.added_at)
+    }
+}
+
+pub mod expires_at {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(expires_at: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        super::serialize_twin("expires_at", "expires_at_utc", *expires_at, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        #[derive(Deserialize)]
+        struct Field {
+            expires_at: u64,
+        }
+        Field::deserialize(deserializer).map(|field| field.expires_at)
+    }
+}
+
+/// Serialized only, so there is nothing to read back
+pub mod started_at {
+    use serde::Serializer;
+
+    pub fn serialize<S: Serializer>(started_at: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        super::serialize_twin("started_at", "started_at_utc", *started_at, serializer)
+    }
+}
+
+pub mod ended_at {
+    use serde::Serializer;
+
+    pub fn serialize<S: Serializer>(ended_at: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        super::serialize_twin("ended_at", "ended_at_utc", *ended_at, serializer)
+    }
+}
+
+pub mod first_seen {
+    use serde::Serializer;
+
+    pub fn serialize<S: Serializer>(first_seen: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        super::serialize_twin("first_seen", "first_seen_utc", *first_seen, serializer)
+    }
+}
+
+pub mod last_seen {
+    use serde::Serializer;
+
+    pub fn serialize<S: Serializer>(last_seen: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        super::serialize_twin("last_seen", "last_seen_utc", *last_seen, serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Stamped {
+        name: String,
+        #[serde(flatten, with = "added_at")]
+        added_at: u64,
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(1760607000), "2025-10-16T09:30:00Z");
+    }
+
+    #[test]
+    fn test_twin() {
+        let stamped = Stamped {
+            name: "a.txt".to_string(),
+            added_at: 1760607000,
+        };
+        let value = serde_json::to_value(&stamped).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "name": "a.txt",
+                "added_at": 1760607000,
+                "added_at_utc": "2025-10-16T09:30:00Z",
+            })
+        );
+        assert_eq!(serde_json::from_value::<Stamped>(value).unwrap(), stamped);
+
+        // Written before the twins, or by hand
+        let older: Stamped = serde_json::from_value(json!({ "name": "a.txt" })).unwrap();
+        assert_eq!(older.added_at, 0);
+    }
+}
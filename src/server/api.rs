@@ -0,0 +1,133 @@
+use axum::extract::{OriginalUri, Request, State};
+use axum::http::{HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::json;
+
+use super::file_server::AppState;
+use super::upload_sessions::{EXPIRY_WARNING_SECS, SESSION_TTL_SECS};
+
+/// API version served under `/api/v1`
+pub const CURRENT_VERSION: u32 = 1;
+
+/// Version of the upload protocol served under `/api/v2/upload`
+pub const UPLOAD_VERSION: u32 = 2;
+
+/// Versions this server can answer
+pub const SUPPORTED_VERSIONS: &[u32] = &[1, 2];
+
+/// Header a client may send to pin the API version it expects.
+/// Every API response carries it with the version that was served.
+pub const VERSION_HEADER: &str = "X-JusTrans-API-Version";
+
+/// Successor path for a request to the unversioned `/api/...` routes
+pub fn successor_path(path: &str) -> Option<String> {
+    let rest = path.strip_prefix("/api/")?;
+    if let Some(version) = rest.strip_prefix('v') {
+        if version.split('/').next()?.parse::<u32>().is_ok() {
+            return None;
+        }
+    }
+    Some(format!("/api/v{}/{}", CURRENT_VERSION, rest))
+}
+
+/// Reject requests pinned to a version we don't serve and stamp the
+/// `served` version on the response
+async fn negotiate(State(served): State<u32>, request: Request, next: Next) -> Response {
+    if let Some(requested) = request.headers().get(VERSION_HEADER) {
+        let requested = requested.to_str().unwrap_or_default().trim();
+        let supported = requested
+            .parse::<u32>()
+            .map(|v| SUPPORTED_VERSIONS.contains(&v))
+            .unwrap_or(false);
+        if !supported {
+            log::warn!(
+                "Rejected request for unsupported API version '{}'",
+                requested
+            );
+            return (
+                StatusCode::NOT_ACCEPTABLE,
+                Json(json!({
+                    "error": format!("Unsupported API version '{}'", requested),
+                    "supported": SUPPORTED_VERSIONS,
+                })),
+            )
+                .into_response();
+        }
+    }
+
+    let mut response = next.run(request).await;
+    response
+        .headers_mut()
+        .insert(VERSION_HEADER, HeaderValue::from(served));
+    response
+}
+
+/// Mark unversioned routes as deprecated (RFC 9745) and point at the
+/// versioned successor
+async fn deprecated(request: Request, next: Next) -> Response {
+    // Nesting strips the `/api` prefix from the request URI
+    let path = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.path().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    let successor = successor_path(&path);
+    let mut response = next.run(request).await;
+
+    let headers = response.headers_mut();
+    headers.insert("Deprecation", HeaderValue::from_static("true"));
+    if let Some(link) = successor
+        .map(|path| format!("<{}>; rel=\"successor-version\"", path))
+        .and_then(|link| HeaderValue::from_str(&link).ok())
+    {
+        headers.insert("Link", link);
+    }
+    response
+}
+
+async fn get_versions() -> Json<serde_json::Value> {
+    Json(json!({
+        "current": CURRENT_VERSION,
+        "supported": SUPPORTED_VERSIONS,
+        "upload": UPLOAD_VERSION,
+        "upload_session_ttl_secs": SESSION_TTL_SECS,
+        "upload_expiry_warning_secs": EXPIRY_WARNING_SECS,
+    }))
+}
+
+/// Serve `routes` under `/api/v1` and, deprecated, under the legacy `/api`
+/// prefix, and `upload_routes` under `/api/v2`, with version negotiation on
+/// all of them.
+pub fn versioned(routes: Router<AppState>, upload_routes: Router<AppState>) -> Router<AppState> {
+    let v1 = Router::new()
+        .route("/api/versions", get(get_versions))
+        .nest(&format!("/api/v{}", CURRENT_VERSION), routes.clone())
+        .nest("/api", routes.layer(middleware::from_fn(deprecated)))
+        .layer(middleware::from_fn_with_state(CURRENT_VERSION, negotiate));
+    let v2 = Router::new()
+        .nest(&format!("/api/v{}", UPLOAD_VERSION), upload_routes)
+        .layer(middleware::from_fn_with_state(UPLOAD_VERSION, negotiate));
+    v1.merge(v2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_successor_path() {
+        assert_eq!(
+            successor_path("/api/files"),
+            Some("/api/v1/files".to_string())
+        );
+        assert_eq!(
+            successor_path("/api/rooms/team/files/abc"),
+            Some("/api/v1/rooms/team/files/abc".to_string())
+        );
+        assert_eq!(successor_path("/api/v1/files"), None);
+        assert_eq!(successor_path("/static/app.js"), None);
+    }
+}
@@ -0,0 +1,507 @@
+//! `GET /api/v1/files/archive?ids=<id>,<id>,...`: several files of the main
+//! share in one ZIP, for grabbing a batch of photos at once. The archive is
+//! written while it is sent, never held whole: each file is read in 64 KiB
+//! pieces, its CRC-32 worked out on the way and written in a data
+//! descriptor after it. Files are stored as they are, since photos and
+//! videos don't compress, so the length of the archive is known before it
+//! starts and the download shows its progress. Files and archives over
+//! 4 GiB use the ZIP64 extensions. Password-protected files are refused;
+//! they are only sent with their password, one at a time.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use axum::body::Body;
+use axum::extract::{Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{AppendHeaders, IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use chrono::{DateTime, Datelike, Local, Timelike};
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::file_server::{shared_path, AppState, DOWNLOAD_CHUNK_LEN};
+use super::memory;
+use super::sanitize::content_disposition;
+use super::transfers::{Tracked, Transfer, TransferKind};
+
+/// Most files one archive may hold
+const MAX_ARCHIVE_FILES: usize = 1000;
+
+/// Name the archive is saved under
+const ARCHIVE_NAME: &str = "justrans-files.zip";
+
+/// Sizes and offsets from here on only fit in ZIP64 fields
+const ZIP64_LIMIT: u64 = 0xFFFF_FFFF;
+
+/// Entries are stored, with sizes and CRC-32 after the data (bit 3), and
+/// their names in UTF-8 (bit 11)
+const FLAGS: u16 = 0x0808;
+
+/// A file as it goes into the archive
+#[derive(Debug, Clone)]
+struct Entry {
+    /// Path inside the archive
+    name: String,
+    path: PathBuf,
+    size: u64,
+    /// Last modification as MS-DOS time and date
+    time: u16,
+    date: u16,
+}
+
+impl Entry {
+    fn zip64(&self) -> bool {
+        self.size >= ZIP64_LIMIT
+    }
+
+    fn version(&self) -> u16 {
+        if self.zip64() {
+            45
+        } else {
+            20
+        }
+    }
+}
+
+/// `time` as MS-DOS time and date in local time, which can't go before 1980
+fn dos_time(time: SystemTime) -> (u16, u16) {
+    let time = DateTime::<Local>::from(time);
+    if time.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    (
+        ((time.hour() << 11) | (time.minute() << 5) | (time.second() / 2)) as u16,
+        ((((time.year() - 1980) as u32) << 9) | (time.month() << 5) | time.day()) as u16,
+    )
+}
+
+/// Header written before an entry's data; its CRC-32 and sizes follow the
+/// data
+fn local_header(entry: &Entry) -> Vec<u8> {
+    // Sizes are in the data descriptor; an empty ZIP64 field says they take
+    // 8 bytes there
+    let mut extra = Vec::new();
+    if entry.zip64() {
+        extra.extend(0x0001u16.to_le_bytes());
+        extra.extend(16u16.to_le_bytes());
+        extra.extend([0; 16]);
+    }
+    let size = if entry.zip64() { u32::MAX } else { 0 };
+    let mut header = Vec::with_capacity(30 + entry.name.len() + extra.len());
+    header.extend(0x0403_4b50u32.to_le_bytes());
+    header.extend(entry.version().to_le_bytes());
+    header.extend(FLAGS.to_le_bytes());
+    header.extend(0u16.to_le_bytes());
+    header.extend(entry.time.to_le_bytes());
+    header.extend(entry.date.to_le_bytes());
+    header.extend(0u32.to_le_bytes());
+    header.extend(size.to_le_bytes());
+    header.extend(size.to_le_bytes());
+    header.extend((entry.name.len() as u16).to_le_bytes());
+    header.extend((extra.len() as u16).to_le_bytes());
+    header.extend(entry.name.as_bytes());
+    header.extend(extra);
+    header
+}
+
+/// CRC-32 and sizes of an entry, written after its data
+fn data_descriptor(entry: &Entry, crc: u32) -> Vec<u8> {
+    let mut descriptor = Vec::with_capacity(24);
+    descriptor.extend(0x0807_4b50u32.to_le_bytes());
+    descriptor.extend(crc.to_le_bytes());
+    if entry.zip64() {
+        descriptor.extend(entry.size.to_le_bytes());
+        descriptor.extend(entry.size.to_le_bytes());
+    } else {
+        descriptor.extend((entry.size as u32).to_le_bytes());
+        descriptor.extend((entry.size as u32).to_le_bytes());
+    }
+    descriptor
+}
+
+/// Central directory record of an entry whose local header is at `offset`
+fn central_header(entry: &Entry, crc: u32, offset: u64) -> Vec<u8> {
+    let mut zip64 = Vec::new();
+    if entry.zip64() {
+        zip64.extend(entry.size.to_le_bytes());
+        zip64.extend(entry.size.to_le_bytes());
+    }
+    if offset >= ZIP64_LIMIT {
+        zip64.extend(offset.to_le_bytes());
+    }
+    let mut extra = Vec::new();
+    if !zip64.is_empty() {
+        extra.extend(0x0001u16.to_le_bytes());
+        extra.extend((zip64.len() as u16).to_le_bytes());
+        extra.extend(zip64);
+    }
+    let version = if extra.is_empty() { 20u16 } else { 45 };
+    let size = entry.size.min(ZIP64_LIMIT) as u32;
+
+    let mut header = Vec::with_capacity(46 + entry.name.len() + extra.len());
+    header.extend(0x0201_4b50u32.to_le_bytes());
+    header.extend(version.to_le_bytes());
+    header.extend(version.to_le_bytes());
+    header.extend(FLAGS.to_le_bytes());
+    header.extend(0u16.to_le_bytes());
+    header.extend(entry.time.to_le_bytes());
+    header.extend(entry.date.to_le_bytes());
+    header.extend(crc.to_le_bytes());
+    header.extend(size.to_le_bytes());
+    header.extend(size.to_le_bytes());
+    header.extend((entry.name.len() as u16).to_le_bytes());
+    header.extend((extra.len() as u16).to_le_bytes());
+    // Comment, disk, internal and external attributes
+    header.extend([0; 10]);
+    header.extend((offset.min(ZIP64_LIMIT) as u32).to_le_bytes());
+    header.extend(entry.name.as_bytes());
+    header.extend(extra);
+    header
+}
+
+/// End of the archive, after a central directory of `size` bytes at
+/// `offset` listing `entries`
+fn end_of_archive(entries: usize, offset: u64, size: u64) -> Vec<u8> {
+    let mut end = Vec::with_capacity(98);
+    let zip64 = entries >= 0xFFFF || offset >= ZIP64_LIMIT || size >= ZIP64_LIMIT;
+    if zip64 {
+        // ZIP64 end of central directory record, then its locator
+        end.extend(0x0606_4b50u32.to_le_bytes());
+        end.extend(44u64.to_le_bytes());
+        end.extend(45u16.to_le_bytes());
+        end.extend(45u16.to_le_bytes());
+        end.extend([0; 8]);
+        end.extend((entries as u64).to_le_bytes());
+        end.extend((entries as u64).to_le_bytes());
+        end.extend(size.to_le_bytes());
+        end.extend(offset.to_le_bytes());
+
+        end.extend(0x0706_4b50u32.to_le_bytes());
+        end.extend(0u32.to_le_bytes());
+        end.extend((offset + size).to_le_bytes());
+        end.extend(1u32.to_le_bytes());
+    }
+    let count = entries.min(0xFFFF) as u16;
+    end.extend(0x0605_4b50u32.to_le_bytes());
+    end.extend([0; 4]);
+    end.extend(count.to_le_bytes());
+    end.extend(count.to_le_bytes());
+    end.extend((size.min(ZIP64_LIMIT) as u32).to_le_bytes());
+    end.extend((offset.min(ZIP64_LIMIT) as u32).to_le_bytes());
+    end.extend(0u16.to_le_bytes());
+    end
+}
+
+/// Bytes in the archive of `entries`; headers don't depend on the CRC-32s,
+/// so it is known before any file is read
+fn archive_len(entries: &[Entry]) -> u64 {
+    let mut offset = 0;
+    let mut central = 0;
+    for entry in entries {
+        central += central_header(entry, 0, offset).len() as u64;
+        offset +=
+            local_header(entry).len() as u64 + entry.size + data_descriptor(entry, 0).len() as u64;
+    }
+    offset + central + end_of_archive(entries.len(), offset, central).len() as u64
+}
+
+/// Copy the `size` bytes of `file` into `out`, returning their CRC-32
+async fn copy_entry(
+    file: impl AsyncRead + Unpin,
+    size: u64,
+    out: &mut (impl AsyncWrite + Unpin),
+) -> std::io::Result<u32> {
+    let mut file = file.take(size);
+    let mut buffer = vec![0; DOWNLOAD_CHUNK_LEN];
+    let mut hasher = crc32fast::Hasher::new();
+    let mut copied = 0;
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        out.write_all(&buffer[..read]).await?;
+        copied += read as u64;
+    }
+    if copied < size {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "file got shorter while it was sent",
+        ));
+    }
+    Ok(hasher.finalize())
+}
+
+/// Write the archive of `entries` to `out`, one file at a time
+async fn write_archive(entries: &[Entry], mut out: impl AsyncWrite + Unpin) -> std::io::Result<()> {
+    let mut offset = 0;
+    let mut central = Vec::new();
+    for entry in entries {
+        let header = local_header(entry);
+        out.write_all(&header).await?;
+        let file = tokio::fs::File::open(&entry.path).await?;
+        let crc = copy_entry(file, entry.size, &mut out).await?;
+        let descriptor = data_descriptor(entry, crc);
+        out.write_all(&descriptor).await?;
+
+        central.extend(central_header(entry, crc, offset));
+        offset += header.len() as u64 + entry.size + descriptor.len() as u64;
+    }
+    out.write_all(&central).await?;
+    out.write_all(&end_of_archive(entries.len(), offset, central.len() as u64))
+        .await?;
+    out.shutdown().await
+}
+
+/// `name` numbered as `name (n).ext` until it is not in `taken`
+fn unique_name(taken: &mut HashSet<String>, name: String) -> String {
+    if taken.insert(name.to_lowercase()) {
+        return name;
+    }
+    let path = Path::new(&name);
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let parent = match name.rsplit_once('/') {
+        Some((parent, _)) => format!("{}/", parent),
+        None => String::new(),
+    };
+    (1..)
+        .map(|n| format!("{}{} ({}){}", parent, stem, n, extension))
+        .find(|candidate| taken.insert(candidate.to_lowercase()))
+        .unwrap()
+}
+
+#[derive(Debug, Deserialize)]
+struct ArchiveQuery {
+    /// IDs of the files, separated by commas
+    ids: String,
+}
+
+/// The files `ids` of the main share as archive entries, in the order
+/// asked for
+async fn entries(state: &AppState, ids: &str) -> Result<Vec<Entry>, StatusCode> {
+    let mut seen = HashSet::new();
+    let ids: Vec<&str> = ids
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty() && seen.insert(*id))
+        .collect();
+    if ids.is_empty() || ids.len() > MAX_ARCHIVE_FILES {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let files = {
+        let file_list = state.file_list.lock().unwrap();
+        ids.iter()
+            .map(|id| file_list.get_file_by_id(id).cloned())
+            .collect::<Option<Vec<_>>>()
+            .ok_or(StatusCode::NOT_FOUND)?
+    };
+    let mut taken = HashSet::new();
+    let mut entries = Vec::with_capacity(files.len());
+    for file_info in files {
+        if file_info.protected {
+            log::warn!(
+                "Refused to add protected '{}' to an archive",
+                file_info.name
+            );
+            return Err(StatusCode::FORBIDDEN);
+        }
+        let path = if file_info.shared {
+            shared_path(state, &file_info)?
+        } else {
+            file_info.path.clone()
+        };
+        let metadata = tokio::fs::metadata(&path)
+            .await
+            .map_err(|_| StatusCode::NOT_FOUND)?;
+        let (time, date) = dos_time(metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH));
+        let name = match &file_info.folder {
+            Some(folder) => format!("{}/{}", folder, file_info.name),
+            None => file_info.name,
+        };
+        entries.push(Entry {
+            name: unique_name(&mut taken, name),
+            path,
+            size: metadata.len(),
+            time,
+            date,
+        });
+    }
+    Ok(entries)
+}
+
+#[axum::debug_handler]
+async fn download_archive(
+    State(state): State<AppState>,
+    Query(query): Query<ArchiveQuery>,
+) -> Result<Response, StatusCode> {
+    let entries = entries(&state, &query.ids).await?;
+    let len = archive_len(&entries);
+    log::info!(
+        "Sending {} file(s) as a ZIP archive of {} bytes",
+        entries.len(),
+        len
+    );
+
+    // The archive is written into a pipe of one read buffer as the
+    // download takes it; a failed write cuts the download short
+    let (writer, reader) = tokio::io::duplex(DOWNLOAD_CHUNK_LEN);
+    tokio::spawn(async move {
+        if let Err(e) = write_archive(&entries, writer).await {
+            log::warn!("ZIP archive download ended early: {}", e);
+        }
+    });
+
+    let transfer = state.transfers.start_if_large(len).map(|guard| {
+        Transfer::start_for_file(
+            TransferKind::Download,
+            None,
+            Some(ARCHIVE_NAME.to_string()),
+            None,
+            Some(len),
+            Some(guard),
+        )
+    });
+    let body = Tracked::new(reader, transfer).counting(state.stats.bytes_out());
+    // The pipe, the buffer files are read into and the one the download
+    // is read from
+    let buffers = memory::reserve(3 * DOWNLOAD_CHUNK_LEN as u64).await;
+    let body = Body::from_stream(buffers.holding(tokio_util::io::ReaderStream::with_capacity(
+        body,
+        DOWNLOAD_CHUNK_LEN,
+    )));
+    let headers = AppendHeaders([
+        (header::CONTENT_TYPE, "application/zip".to_string()),
+        (
+            header::CONTENT_DISPOSITION,
+            content_disposition(ARCHIVE_NAME),
+        ),
+        (header::CONTENT_LENGTH, len.to_string()),
+    ]);
+    Ok((headers, body).into_response())
+}
+
+/// Archive route on the main share
+pub fn api_router() -> Router<AppState> {
+    Router::new().route("/files/archive", get(download_archive))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn entry(name: &str, path: PathBuf, size: u64) -> Entry {
+        Entry {
+            name: name.to_string(),
+            path,
+            size,
+            time: 0,
+            date: (1 << 5) | 1,
+        }
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_write_archive() {
+        let dir = tempdir().unwrap();
+        let photo = dir.path().join("a_file");
+        let notes = dir.path().join("b_file");
+        std::fs::write(&photo, vec![7u8; 200_000]).unwrap();
+        std::fs::write(&notes, b"hello").unwrap();
+        let entries = vec![
+            entry("2024/05/IMG_0001.jpg", photo, 200_000),
+            entry("notes.txt", notes, 5),
+        ];
+
+        let mut archive = Vec::new();
+        write_archive(&entries, &mut archive).await.unwrap();
+        assert_eq!(archive.len() as u64, archive_len(&entries));
+
+        // Each file is stored as it is, right after its local header
+        assert_eq!(u32_at(&archive, 0), 0x0403_4b50);
+        let data = 30 + "2024/05/IMG_0001.jpg".len();
+        assert_eq!(archive[data..data + 200_000], [7u8; 200_000]);
+        let descriptor = data + 200_000;
+        assert_eq!(u32_at(&archive, descriptor), 0x0807_4b50);
+        assert_eq!(
+            u32_at(&archive, descriptor + 4),
+            crc32fast::hash(&[7u8; 200_000])
+        );
+        assert_eq!(u32_at(&archive, descriptor + 8), 200_000);
+
+        // The end lists both entries and where the central directory is
+        let end = archive.len() - 22;
+        assert_eq!(u32_at(&archive, end), 0x0605_4b50);
+        assert_eq!(archive[end + 10], 2);
+        let central = u32_at(&archive, end + 16) as usize;
+        assert_eq!(u32_at(&archive, central), 0x0201_4b50);
+        assert_eq!(
+            u32_at(&archive, central + 16),
+            crc32fast::hash(&[7u8; 200_000])
+        );
+        assert_eq!(u32_at(&archive, central + 42), 0);
+    }
+
+    #[tokio::test]
+    async fn test_shortened_file() {
+        // A file cut short while it is sent fails the archive
+        let mut out = Vec::new();
+        let error = copy_entry(&b"abc"[..], 10, &mut out).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_zip64() {
+        let large = entry("video.mkv", PathBuf::new(), 5 << 30);
+        assert_eq!(u32_at(&local_header(&large), 18), u32::MAX);
+        assert_eq!(data_descriptor(&large, 0).len(), 24);
+        // Sizes and the offset past 4 GiB move into the ZIP64 field
+        let central = central_header(&large, 0, 6 << 30);
+        assert_eq!(u32_at(&central, 20), u32::MAX);
+        assert_eq!(u32_at(&central, 42), u32::MAX);
+        assert_eq!(central.len(), 46 + "video.mkv".len() + 4 + 24);
+
+        let end = end_of_archive(2, 11 << 30, 200);
+        assert_eq!(end.len(), 56 + 20 + 22);
+        assert_eq!(u32_at(&end, 0), 0x0606_4b50);
+        assert_eq!(u32_at(&end, 56), 0x0706_4b50);
+        assert_eq!(end_of_archive(2, 1000, 200).len(), 22);
+    }
+
+    #[test]
+    fn test_unique_name() {
+        let mut taken = HashSet::new();
+        assert_eq!(
+            unique_name(&mut taken, "photo.jpg".to_string()),
+            "photo.jpg"
+        );
+        assert_eq!(
+            unique_name(&mut taken, "Photo.JPG".to_string()),
+            "Photo (1).JPG"
+        );
+        assert_eq!(
+            unique_name(&mut taken, "photo.jpg".to_string()),
+            "photo (2).jpg"
+        );
+        assert_eq!(
+            unique_name(&mut taken, "2024/05/photo.jpg".to_string()),
+            "2024/05/photo.jpg"
+        );
+    }
+}
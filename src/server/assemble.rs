@@ -0,0 +1,212 @@
+//! Checksums taken while received files are written rather than in a second
+//! pass over them. Segments are joined by a small pipeline, one thread
+//! reading the segments ahead while the file is written and another hashing
+//! the same blocks. Parts of an upload session, written at their offsets,
+//! are hashed as soon as the bytes before them are in.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::Arc;
+
+use sha2::{Digest, Sha256};
+
+/// Bytes read, written and hashed at a time
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Blocks read ahead of the writer and handed to the hasher, bounding the
+/// memory a join takes
+const PIPELINE_DEPTH: usize = 4;
+
+/// A file joined from its segments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembled {
+    pub size: u64,
+    /// Hex SHA-256 of the joined file
+    pub sha256: String,
+}
+
+/// Write the files at `parts` one after the other into `writer`, hashing
+/// them on the way
+pub fn join<W: Write>(parts: &[PathBuf], mut writer: W) -> io::Result<Assembled> {
+    std::thread::scope(|scope| {
+        let (read, blocks) = mpsc::sync_channel(PIPELINE_DEPTH);
+        scope.spawn(move || read_parts(parts, read));
+        let (hash, to_hash) = mpsc::sync_channel::<Arc<[u8]>>(PIPELINE_DEPTH);
+        let hasher = scope.spawn(move || {
+            let mut hasher = Sha256::new();
+            for block in to_hash {
+                hasher.update(&block);
+            }
+            hasher.finalize()
+        });
+
+        let mut size = 0;
+        // Dropping the blocks, done or failed, stops the reader
+        let written = blocks.into_iter().try_for_each(|block| {
+            let block = block?;
+            // The hasher outlives the writer, so sending never fails
+            let _ = hash.send(block.clone());
+            writer.write_all(&block)?;
+            size += block.len() as u64;
+            Ok(())
+        });
+        drop(hash);
+        let digest = hasher.join().expect("hasher panicked");
+        written.and_then(|()| writer.flush())?;
+        Ok(Assembled {
+            size,
+            sha256: hex::encode(digest),
+        })
+    })
+}
+
+/// Send the contents of `parts` in blocks until done, failed or no longer
+/// wanted
+fn read_parts(parts: &[PathBuf], blocks: mpsc::SyncSender<io::Result<Arc<[u8]>>>) {
+    for path in parts {
+        let mut part = match File::open(path) {
+            Ok(part) => part,
+            Err(e) => {
+                let _ = blocks.send(Err(e));
+                return;
+            }
+        };
+        loop {
+            let mut block = vec![0; BLOCK_SIZE];
+            let block = match part.read(&mut block) {
+                Ok(0) => break,
+                Ok(read) => {
+                    block.truncate(read);
+                    Ok(block.into())
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            let failed = block.is_err();
+            if blocks.send(block).is_err() || failed {
+                return;
+            }
+        }
+    }
+}
+
+/// SHA-256 of the leading bytes of a file that is written in parts at
+/// offsets, in any order
+#[derive(Debug, Clone, Default)]
+pub struct RunningHash {
+    hasher: Sha256,
+    /// Bytes from the start of the file hashed so far
+    hashed: u64,
+}
+
+impl RunningHash {
+    /// Take `data`, just written at `offset` into the file at `path`, into
+    /// account. `contiguous` is how many bytes from the start of the file
+    /// are written by now, which may include earlier parts that arrived
+    /// ahead of their turn; those are read back and hashed.
+    pub fn update(&mut self, path: &Path, offset: u64, data: &[u8], contiguous: u64) {
+        if offset < self.hashed {
+            // Hashed bytes were sent again, maybe different ones, so the
+            // file is hashed from the start once complete
+            *self = Self::default();
+            return;
+        }
+        if offset == self.hashed {
+            self.hasher.update(data);
+            self.hashed += data.len() as u64;
+        }
+        if contiguous > self.hashed {
+            if let Err(e) = self.hash_file(path, contiguous) {
+                log::warn!("Failed to hash {:?} while receiving: {}", path, e);
+                *self = Self::default();
+            }
+        }
+    }
+
+    /// Hex SHA-256 of the whole file at `path`, hashing what is left of it
+    pub fn finish(mut self, path: &Path) -> io::Result<String> {
+        self.hash_file(path, u64::MAX)?;
+        Ok(hex::encode(self.hasher.finalize()))
+    }
+
+    /// Hash the file from where the hash got to, up to `end`
+    fn hash_file(&mut self, path: &Path, end: u64) -> io::Result<()> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(self.hashed))?;
+        let mut rest = file.take(end - self.hashed);
+        let mut block = vec![0; BLOCK_SIZE];
+        loop {
+            match rest.read(&mut block) {
+                Ok(0) => return Ok(()),
+                Ok(read) => {
+                    self.hasher.update(&block[..read]);
+                    self.hashed += read as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha256(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    #[test]
+    fn test_join_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let contents: Vec<u8> = (0..BLOCK_SIZE * 2 + 10).map(|i| i as u8).collect();
+        let mut parts = Vec::new();
+        for (i, segment) in contents.chunks(BLOCK_SIZE + 3).chain([&[][..]]).enumerate() {
+            let path = dir.path().join(format!("segment_{}", i));
+            std::fs::write(&path, segment).unwrap();
+            parts.push(path);
+        }
+
+        let mut joined = Vec::new();
+        let assembled = join(&parts, &mut joined).unwrap();
+        assert_eq!(joined, contents);
+        assert_eq!(
+            assembled,
+            Assembled {
+                size: contents.len() as u64,
+                sha256: sha256(&contents),
+            }
+        );
+
+        parts.insert(1, dir.path().join("missing"));
+        let error = join(&parts, io::sink()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_running_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("upload");
+        let contents = b"hello wonderful world";
+        std::fs::write(&path, contents).unwrap();
+
+        // In order, then a part that arrived early and is read back
+        let mut hash = RunningHash::default();
+        hash.update(&path, 0, &contents[..6], 6);
+        hash.update(&path, 16, &contents[16..], 6);
+        assert_eq!(hash.hashed, 6);
+        hash.update(&path, 6, &contents[6..16], 21);
+        assert_eq!(hash.hashed, 21);
+        assert_eq!(hash.finish(&path).unwrap(), sha256(contents));
+
+        // A part sent again starts over, from the file once complete
+        let mut hash = RunningHash::default();
+        hash.update(&path, 0, &contents[..6], 6);
+        hash.update(&path, 0, b"HELLO ", 6);
+        assert_eq!(hash.hashed, 0);
+        assert_eq!(hash.finish(&path).unwrap(), sha256(contents));
+    }
+}
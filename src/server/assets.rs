@@ -0,0 +1,377 @@
+use std::io::Write;
+
+use axum::extract::Path;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+
+use super::i18n;
+
+/// URL prefix the portal's assets are served under
+const STATIC_PREFIX: &str = "/static/";
+
+/// Hashed asset names never change content, so browsers may keep them
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// Pages and unhashed names must be checked again so upgrades show up
+const REVALIDATE: &str = "no-cache";
+
+/// Web assets compiled into the binary, by name under `/static/`
+const EMBEDDED: &[(&str, &str, &[u8])] = &[
+    (
+        "portal.css",
+        "text/css; charset=utf-8",
+        include_bytes!("../../assets/web/portal.css"),
+    ),
+    (
+        "portal.js",
+        "text/javascript; charset=utf-8",
+        include_bytes!("../../assets/web/portal.js"),
+    ),
+    (
+        "icon-192.png",
+        "image/png",
+        include_bytes!("../../assets/web/icon-192.png"),
+    ),
+    (
+        "icon-512.png",
+        "image/png",
+        include_bytes!("../../assets/web/icon-512.png"),
+    ),
+];
+
+static ASSETS: Lazy<Vec<Asset>> = Lazy::new(|| {
+    EMBEDDED
+        .iter()
+        .map(|&(name, content_type, body)| Asset::new(name, content_type, body))
+        .collect()
+});
+
+/// The portal page in each language, linking the current hashed asset
+/// names
+static INDEXES: Lazy<Vec<(&'static str, Asset)>> = Lazy::new(|| {
+    let html = link_assets(include_str!("../../assets/web/index.html"));
+    i18n::languages()
+        .map(|language| {
+            let page = i18n::localize(&html, language);
+            let asset = Asset::new("index.html", "text/html; charset=utf-8", page.as_bytes());
+            (language, asset)
+        })
+        .collect()
+});
+
+/// Web app manifest, for adding the portal to a phone's home screen
+static MANIFEST: Lazy<Asset> = Lazy::new(|| {
+    let manifest = link_assets(include_str!("../../assets/web/manifest.webmanifest"));
+    Asset::new(
+        "manifest.webmanifest",
+        "application/manifest+json",
+        manifest.as_bytes(),
+    )
+});
+
+/// Service worker keeping the portal's shell for offline starts. Its cache
+/// is named after the pages, so a new release replaces the cached shell.
+static SERVICE_WORKER: Lazy<Asset> = Lazy::new(|| {
+    let script = link_assets(include_str!("../../assets/web/sw.js"))
+        .replace("SHELL_VERSION", &short_hash(&pages()));
+    Asset::new("sw.js", "text/javascript; charset=utf-8", script.as_bytes())
+});
+
+/// The portal pages in all languages, one after the other
+fn pages() -> Vec<u8> {
+    INDEXES
+        .iter()
+        .flat_map(|(_, index)| index.identity.iter().copied())
+        .collect()
+}
+
+/// `text` with the plain asset names it links replaced by the hashed ones
+fn link_assets(text: &str) -> String {
+    let mut text = text.to_string();
+    for asset in ASSETS.iter() {
+        text = text.replace(
+            &format!("{}{}", STATIC_PREFIX, asset.name),
+            &format!("{}{}", STATIC_PREFIX, asset.hashed_name),
+        );
+    }
+    text
+}
+
+/// Content encodings an asset is stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Identity,
+    Brotli,
+    Zstd,
+}
+
+impl Encoding {
+    fn header_value(self) -> Option<&'static str> {
+        match self {
+            Encoding::Identity => None,
+            Encoding::Brotli => Some("br"),
+            Encoding::Zstd => Some("zstd"),
+        }
+    }
+}
+
+/// An embedded asset with its compressed variants, made once on first use
+struct Asset {
+    name: &'static str,
+    hashed_name: String,
+    content_type: &'static str,
+    identity: Vec<u8>,
+    brotli: Vec<u8>,
+    zstd: Vec<u8>,
+}
+
+impl Asset {
+    fn new(name: &'static str, content_type: &'static str, body: &[u8]) -> Self {
+        Self {
+            name,
+            hashed_name: hashed_name(name, body),
+            content_type,
+            identity: body.to_vec(),
+            brotli: smaller(compress_brotli(body), body),
+            zstd: smaller(zstd::encode_all(body, 19).unwrap_or_default(), body),
+        }
+    }
+
+    fn body(&self, encoding: Encoding) -> &[u8] {
+        match encoding {
+            Encoding::Identity => &self.identity,
+            Encoding::Brotli => &self.brotli,
+            Encoding::Zstd => &self.zstd,
+        }
+    }
+
+    fn response(&self, headers: &HeaderMap, cache_control: &'static str) -> Response {
+        let mut encoding = preferred_encoding(
+            headers
+                .get(header::ACCEPT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default(),
+        );
+        // A failed or useless compression, as of images, leaves the
+        // variant empty
+        if self.body(encoding).is_empty() {
+            encoding = Encoding::Identity;
+        }
+
+        let mut response = self.body(encoding).to_vec().into_response();
+        let response_headers = response.headers_mut();
+        response_headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(self.content_type),
+        );
+        response_headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static(cache_control),
+        );
+        response_headers.insert(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+        if let Some(value) = encoding.header_value() {
+            response_headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(value));
+        }
+        response
+    }
+}
+
+/// First 8 hex digits of the SHA-256 of `body`
+fn short_hash(body: &[u8]) -> String {
+    hex::encode(&Sha256::digest(body)[..4])
+}
+
+/// `portal.js` -> `portal.<first 8 hex digits of the SHA-256>.js`
+pub fn hashed_name(name: &str, body: &[u8]) -> String {
+    let hash = short_hash(body);
+    match name.rsplit_once('.') {
+        Some((stem, extension)) => format!("{}.{}.{}", stem, hash, extension),
+        None => format!("{}.{}", name, hash),
+    }
+}
+
+/// `compressed`, or nothing if it is no smaller than `body`
+fn smaller(compressed: Vec<u8>, body: &[u8]) -> Vec<u8> {
+    if compressed.len() < body.len() {
+        compressed
+    } else {
+        Vec::new()
+    }
+}
+
+fn compress_brotli(body: &[u8]) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    {
+        let mut writer = brotli::CompressorWriter::new(&mut compressed, 4096, 11, 22);
+        if writer.write_all(body).is_err() {
+            return Vec::new();
+        }
+    }
+    compressed
+}
+
+/// Best stored encoding the client accepts according to `Accept-Encoding`
+pub fn preferred_encoding(accept_encoding: &str) -> Encoding {
+    let mut brotli = false;
+    let mut zstd = false;
+    for entry in accept_encoding.split(',') {
+        let mut parts = entry.split(';');
+        let coding = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let refused = parts.any(|param| {
+            param
+                .trim()
+                .strip_prefix("q=")
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .is_some_and(|q| q <= 0.0)
+        });
+        if refused {
+            continue;
+        }
+        match coding.as_str() {
+            "br" => brotli = true,
+            "zstd" => zstd = true,
+            _ => {}
+        }
+    }
+
+    // Brotli compresses text assets a little better than zstd
+    if brotli {
+        Encoding::Brotli
+    } else if zstd {
+        Encoding::Zstd
+    } else {
+        Encoding::Identity
+    }
+}
+
+/// The portal page, also served for rooms, in the language negotiated for
+/// the request
+pub async fn serve_index(headers: HeaderMap) -> Response {
+    let language = i18n::negotiate(&headers);
+    let (language, index) = INDEXES
+        .iter()
+        .find(|(code, _)| *code == language)
+        .unwrap_or(&INDEXES[0]);
+    let mut response = index.response(&headers, REVALIDATE);
+    let response_headers = response.headers_mut();
+    response_headers.insert(
+        header::VARY,
+        HeaderValue::from_static("Accept-Encoding, Accept-Language, Cookie"),
+    );
+    response_headers.insert(header::CONTENT_LANGUAGE, HeaderValue::from_static(language));
+    response
+}
+
+/// The web app manifest
+pub async fn manifest(headers: HeaderMap) -> Response {
+    MANIFEST.response(&headers, REVALIDATE)
+}
+
+/// The service worker, served from the root so that it controls the whole
+/// portal
+pub async fn service_worker(headers: HeaderMap) -> Response {
+    SERVICE_WORKER.response(&headers, REVALIDATE)
+}
+
+/// An embedded asset by hashed or plain name
+#[axum::debug_handler]
+pub async fn static_asset(Path(name): Path<String>, headers: HeaderMap) -> Response {
+    if let Some(asset) = ASSETS.iter().find(|asset| asset.hashed_name == name) {
+        return asset.response(&headers, IMMUTABLE);
+    }
+    match ASSETS.iter().find(|asset| asset.name == name) {
+        Some(asset) => asset.response(&headers, REVALIDATE),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preferred_encoding() {
+        assert_eq!(
+            preferred_encoding("gzip, deflate, br, zstd"),
+            Encoding::Brotli
+        );
+        assert_eq!(preferred_encoding("gzip, zstd"), Encoding::Zstd);
+        assert_eq!(preferred_encoding("br;q=0, zstd;q=0.5"), Encoding::Zstd);
+        assert_eq!(preferred_encoding("gzip"), Encoding::Identity);
+        assert_eq!(preferred_encoding(""), Encoding::Identity);
+    }
+
+    #[test]
+    fn test_index_links_hashed_assets() {
+        let index = String::from_utf8(INDEXES[0].1.identity.clone()).unwrap();
+        for asset in ASSETS.iter() {
+            assert!(asset
+                .hashed_name
+                .starts_with(asset.name.split('.').next().unwrap()));
+            assert!(index.contains(&format!("/static/{}", asset.hashed_name)));
+        }
+        assert_eq!(hashed_name("portal.js", b"a"), "portal.ca978112.js");
+    }
+
+    #[test]
+    fn test_app_shell() {
+        let manifest: serde_json::Value = serde_json::from_slice(&MANIFEST.identity).unwrap();
+        for icon in manifest["icons"].as_array().unwrap() {
+            let src = icon["src"].as_str().unwrap();
+            let name = src.strip_prefix(STATIC_PREFIX).unwrap();
+            assert!(ASSETS.iter().any(|asset| asset.hashed_name == name));
+        }
+
+        let script = String::from_utf8(SERVICE_WORKER.identity.clone()).unwrap();
+        assert!(script.contains(&format!("justrans-shell-{}", short_hash(&pages()))));
+        assert!(!script.contains("/static/portal.js'"));
+    }
+
+    #[tokio::test]
+    async fn test_static_asset_variants() {
+        let asset = &ASSETS[0];
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT_ENCODING,
+            HeaderValue::from_static("gzip, br"),
+        );
+
+        let response = static_asset(Path(asset.hashed_name.clone()), headers).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "br");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], IMMUTABLE);
+        assert_eq!(response.headers()[header::VARY], "Accept-Encoding");
+        assert!(asset.brotli.len() < asset.identity.len());
+
+        let response = static_asset(Path(asset.name.to_string()), HeaderMap::new()).await;
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(response.headers()[header::CACHE_CONTROL], REVALIDATE);
+
+        let response = static_asset(Path("missing.js".to_string()), HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_index_languages() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT_LANGUAGE,
+            HeaderValue::from_static("de-CH, en;q=0.5"),
+        );
+        let response = serve_index(headers).await;
+        assert_eq!(response.headers()[header::CONTENT_LANGUAGE], "de");
+        assert_eq!(
+            response.headers()[header::VARY],
+            "Accept-Encoding, Accept-Language, Cookie"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<html lang=\"de\">"));
+
+        let response = serve_index(HeaderMap::new()).await;
+        assert_eq!(response.headers()[header::CONTENT_LANGUAGE], "en");
+    }
+}
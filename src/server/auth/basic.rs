@@ -0,0 +1,172 @@
+//! HTTP Basic Auth in front of the whole portal, for instances exposed
+//! through a reverse proxy without a sign-in provider. The password is only
+//! stored as an argon2 hash.
+
+use std::sync::{Arc, Mutex};
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+use crate::config::BasicAuthConfig;
+use crate::server::file_server::AppState;
+
+/// Checks the credentials browsers send with every request
+#[derive(Clone)]
+pub struct BasicAuth {
+    username: String,
+    password_hash: String,
+    /// Digest of the last accepted `Authorization` header, so the slow
+    /// password hash is not computed again for every request
+    accepted: Arc<Mutex<Option<[u8; 32]>>>,
+}
+
+impl BasicAuth {
+    /// Basic Auth as configured, if enabled
+    pub fn from_config(config: Option<&BasicAuthConfig>) -> Option<Self> {
+        let config = config.filter(|config| config.enabled)?;
+        if let Err(e) = PasswordHash::new(&config.password_hash) {
+            log::error!(
+                "Invalid security.basic_auth.password_hash, every request will be refused: {}",
+                e
+            );
+        }
+        Some(Self {
+            username: config.username.clone(),
+            password_hash: config.password_hash.clone(),
+            accepted: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Whether the request carries the configured username and password
+    fn check(&self, headers: &HeaderMap) -> bool {
+        let Some(value) = headers.get(header::AUTHORIZATION) else {
+            return false;
+        };
+        let digest: [u8; 32] = Sha256::digest(value.as_bytes()).into();
+        if *self.accepted.lock().unwrap() == Some(digest) {
+            return true;
+        }
+
+        let Some((username, password)) = credentials(value) else {
+            return false;
+        };
+        let valid = username == self.username && verify_password(&password, &self.password_hash);
+        if valid {
+            *self.accepted.lock().unwrap() = Some(digest);
+        }
+        valid
+    }
+}
+
+/// Username and password of a `Basic` authorization header
+fn credentials(value: &HeaderValue) -> Option<(String, String)> {
+    let (scheme, encoded) = value.to_str().ok()?.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+/// Hash a password for `security.basic_auth.password_hash`
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow::anyhow!("Failed to hash the password: {}", e))
+}
+
+/// Whether `password` matches an argon2 `hash` made by `hash_password`
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+/// Ask for the Basic Auth credentials before serving anything, if enabled
+pub async fn require_basic_auth(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(basic_auth) = state.basic_auth.clone() else {
+        return next.run(request).await;
+    };
+
+    let headers = request.headers().clone();
+    let valid = tokio::task::spawn_blocking(move || basic_auth.check(&headers))
+        .await
+        .unwrap_or(false);
+    if valid {
+        return next.run(request).await;
+    }
+    if request.headers().contains_key(header::AUTHORIZATION) {
+        log::warn!("Rejected request with wrong Basic Auth credentials");
+    }
+    (
+        StatusCode::UNAUTHORIZED,
+        [(
+            header::WWW_AUTHENTICATE,
+            "Basic realm=\"JusTrans\", charset=\"UTF-8\"",
+        )],
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic(credentials: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let value = format!("Basic {}", STANDARD.encode(credentials));
+        headers.insert(header::AUTHORIZATION, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_check_credentials() {
+        let config = BasicAuthConfig {
+            enabled: true,
+            username: "family".to_string(),
+            password_hash: hash_password("correct horse").unwrap(),
+        };
+        let auth = BasicAuth::from_config(Some(&config)).unwrap();
+
+        assert!(!auth.check(&HeaderMap::new()));
+        assert!(!auth.check(&basic("family:wrong")));
+        assert!(!auth.check(&basic("other:correct horse")));
+        assert!(auth.check(&basic("family:correct horse")));
+        // Served from the remembered digest the second time
+        assert!(auth.check(&basic("family:correct horse")));
+        assert!(!auth.check(&basic("family:wrong")));
+
+        let disabled = BasicAuthConfig {
+            enabled: false,
+            ..config
+        };
+        assert!(BasicAuth::from_config(Some(&disabled)).is_none());
+    }
+
+    #[test]
+    fn test_credentials() {
+        let value = HeaderValue::from_static("basic dXNlcjpwYTpzcw==");
+        assert_eq!(
+            credentials(&value),
+            Some(("user".to_string(), "pa:ss".to_string()))
+        );
+        assert_eq!(credentials(&HeaderValue::from_static("Bearer abc")), None);
+    }
+}
@@ -0,0 +1,445 @@
+pub mod basic;
+pub mod oidc;
+pub mod qr_token;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use axum::extract::{Query, Request, State};
+use axum::http::{header, HeaderMap, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::get;
+use axum::Router;
+use serde::{Deserialize, Serialize};
+
+use super::file_server::{request_base_url, AppState};
+use crate::config::SecurityConfig;
+use crate::models::unix_now;
+
+/// Cookie holding the session token
+pub const SESSION_COOKIE: &str = "justrans_session";
+
+/// Time allowed between starting a sign-in and returning from the provider
+const LOGIN_TIMEOUT_SECS: u64 = 10 * 60;
+
+/// What a signed-in user may do on the portal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Browse and download only
+    Viewer,
+    /// Also upload files and share text
+    Uploader,
+    /// Also watch the server's event log
+    Host,
+}
+
+/// A user as reported by an auth provider
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub subject: String,
+    pub name: String,
+    pub email: Option<String>,
+    pub groups: Vec<String>,
+}
+
+impl Identity {
+    /// Role of this user, given the emails and groups allowed to upload
+    /// and those managing the share
+    pub fn role(&self, uploaders: &[String], hosts: &[String]) -> Role {
+        if self.listed(hosts) {
+            Role::Host
+        } else if uploaders.is_empty() || self.listed(uploaders) {
+            Role::Uploader
+        } else {
+            Role::Viewer
+        }
+    }
+
+    /// Whether the user's email or one of their groups is in `entries`
+    fn listed(&self, entries: &[String]) -> bool {
+        entries.iter().any(|entry| {
+            self.email.as_deref() == Some(entry.as_str()) || self.groups.contains(entry)
+        })
+    }
+}
+
+/// A source of user identities that signs users in through a browser
+/// redirect, such as an OpenID Connect provider
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    /// URL to send the browser to for signing in
+    async fn login_url(&self, redirect_url: &str, state: &str) -> anyhow::Result<String>;
+
+    /// Look up the user once the provider redirected back with `code`
+    async fn complete(&self, redirect_url: &str, code: &str) -> anyhow::Result<Identity>;
+}
+
+/// A signed-in browser
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub identity: Identity,
+    pub role: Role,
+    pub expires_at: u64,
+}
+
+/// A sign-in waiting for the provider's redirect
+struct PendingLogin {
+    return_to: String,
+    started_at: u64,
+}
+
+/// Sign-in state shared by the portal routes
+#[derive(Clone)]
+pub struct Auth {
+    provider: Arc<dyn AuthProvider>,
+    redirect_url: String,
+    uploaders: Vec<String>,
+    hosts: Vec<String>,
+    session_secs: u64,
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+    pending: Arc<Mutex<HashMap<String, PendingLogin>>>,
+}
+
+impl Auth {
+    pub fn new(
+        provider: Arc<dyn AuthProvider>,
+        redirect_url: String,
+        uploaders: Vec<String>,
+        hosts: Vec<String>,
+        session_secs: u64,
+    ) -> Self {
+        Self {
+            provider,
+            redirect_url,
+            uploaders,
+            hosts,
+            session_secs,
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Sign-in through the configured provider, if one is enabled
+    pub fn from_config(config: &SecurityConfig) -> Option<Self> {
+        let oidc = config.oidc.as_ref().filter(|oidc| oidc.enabled)?;
+        Some(Self::new(
+            Arc::new(oidc::OidcProvider::new(oidc.clone())),
+            oidc.redirect_url.clone(),
+            oidc.uploaders.clone(),
+            oidc.hosts.clone(),
+            oidc.session_hours * 60 * 60,
+        ))
+    }
+
+    /// Session for the cookie sent with a request, if it is still valid
+    pub fn session(&self, headers: &HeaderMap, now: u64) -> Option<Session> {
+        let token = session_token(headers)?;
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.get(&token).cloned()
+    }
+
+    /// Remember where to return after signing in and return the state token
+    fn start_login(&self, return_to: String, now: u64) -> String {
+        let state = uuid::Uuid::new_v4().simple().to_string();
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, login| login.started_at + LOGIN_TIMEOUT_SECS > now);
+        pending.insert(
+            state.clone(),
+            PendingLogin {
+                return_to,
+                started_at: now,
+            },
+        );
+        state
+    }
+
+    /// Check the state token from the provider's redirect; each one can
+    /// only be used once
+    fn finish_login(&self, state: &str, now: u64) -> Option<String> {
+        self.pending
+            .lock()
+            .unwrap()
+            .remove(state)
+            .filter(|login| login.started_at + LOGIN_TIMEOUT_SECS > now)
+            .map(|login| login.return_to)
+    }
+
+    fn create_session(&self, identity: Identity, now: u64) -> (String, Session) {
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        let session = Session {
+            role: identity.role(&self.uploaders, &self.hosts),
+            identity,
+            expires_at: now + self.session_secs,
+        };
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(token.clone(), session.clone());
+        (token, session)
+    }
+
+    fn end_session(&self, headers: &HeaderMap) {
+        if let Some(token) = session_token(headers) {
+            self.sessions.lock().unwrap().remove(&token);
+        }
+    }
+
+    /// Callback URL registered with the provider
+    fn callback_url(&self, headers: &HeaderMap, scheme: &str) -> Option<String> {
+        if !self.redirect_url.is_empty() {
+            return Some(self.redirect_url.clone());
+        }
+        request_base_url(headers, scheme).map(|base_url| format!("{}/auth/callback", base_url))
+    }
+}
+
+/// Value of the cookie `name` from the request's cookies
+pub fn cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|cookie| {
+            cookie
+                .trim()
+                .strip_prefix(name)?
+                .strip_prefix('=')
+                .map(str::to_string)
+        })
+}
+
+/// Session token from the request's cookies
+fn session_token(headers: &HeaderMap) -> Option<String> {
+    cookie(headers, SESSION_COOKIE)
+}
+
+/// Who is signed in, as reported to the portal
+#[derive(Debug, Serialize)]
+pub struct SignedInUser {
+    pub name: String,
+    pub role: Role,
+}
+
+/// The signed-in user for a request, if sign-in is enabled
+pub fn signed_in_user(state: &AppState, headers: &HeaderMap) -> Option<SignedInUser> {
+    let session = state.auth.as_ref()?.session(headers, unix_now())?;
+    Some(SignedInUser {
+        name: session.identity.name,
+        role: session.role,
+    })
+}
+
+/// Whether a request only reads, which viewers may make. Unlocking a
+/// protected file is a download, posted to keep its password out of the URL.
+fn is_read(request: &Request) -> bool {
+    matches!(*request.method(), Method::GET | Method::HEAD)
+        || request.uri().path().ends_with("/unlock")
+}
+
+/// Reject portal requests without a valid sign-in, and changes by viewers
+pub async fn require_login(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let Some(auth) = &state.auth else {
+        return Ok(next.run(request).await);
+    };
+
+    let session = auth
+        .session(request.headers(), unix_now())
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    if session.role == Role::Viewer && !is_read(&request) {
+        log::warn!(
+            "Rejected {} {} by viewer {}",
+            request.method(),
+            request.uri().path(),
+            session.identity.name
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(next.run(request).await)
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginParams {
+    return_to: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+#[axum::debug_handler]
+async fn login(
+    Query(params): Query<LoginParams>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Redirect, StatusCode> {
+    let auth = state.auth.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    let callback_url = auth
+        .callback_url(&headers, state.scheme())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+
+    // Only local paths, so the sign-in can't be used as an open redirect
+    let return_to = params
+        .return_to
+        .filter(|path| path.starts_with('/') && !path.starts_with("//"))
+        .unwrap_or_else(|| "/".to_string());
+    let login_state = auth.start_login(return_to, unix_now());
+
+    let url = auth
+        .provider
+        .login_url(&callback_url, &login_state)
+        .await
+        .map_err(|e| {
+            log::error!("Failed to start sign-in: {}", e);
+            StatusCode::BAD_GATEWAY
+        })?;
+    Ok(Redirect::to(&url))
+}
+
+#[axum::debug_handler]
+async fn callback(
+    Query(params): Query<CallbackParams>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Response, StatusCode> {
+    let auth = state.auth.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    if let Some(error) = params.error {
+        log::warn!("Sign-in was refused by the provider: {}", error);
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let now = unix_now();
+    let return_to = params
+        .state
+        .and_then(|login_state| auth.finish_login(&login_state, now))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let code = params.code.ok_or(StatusCode::BAD_REQUEST)?;
+    let callback_url = auth
+        .callback_url(&headers, state.scheme())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+
+    let identity = auth
+        .provider
+        .complete(&callback_url, &code)
+        .await
+        .map_err(|e| {
+            log::warn!("Sign-in failed: {}", e);
+            StatusCode::UNAUTHORIZED
+        })?;
+    let (token, session) = auth.create_session(identity, now);
+    log::info!("{} signed in as {:?}", session.identity.name, session.role);
+
+    let cookie = format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}",
+        SESSION_COOKIE, token, auth.session_secs
+    );
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::to(&return_to)).into_response())
+}
+
+#[axum::debug_handler]
+async fn logout(headers: HeaderMap, State(state): State<AppState>) -> Response {
+    if let Some(auth) = &state.auth {
+        auth.end_session(&headers);
+    }
+    let cookie = format!(
+        "{}=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0",
+        SESSION_COOKIE
+    );
+    ([(header::SET_COOKIE, cookie)], Redirect::to("/")).into_response()
+}
+
+/// Sign-in routes, outside the versioned API
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/auth/login", get(login))
+        .route("/auth/callback", get(callback))
+        .route("/auth/logout", get(logout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    struct FixedProvider;
+
+    #[async_trait]
+    impl AuthProvider for FixedProvider {
+        async fn login_url(&self, _redirect_url: &str, state: &str) -> anyhow::Result<String> {
+            Ok(format!("https://sso.example.com/?state={}", state))
+        }
+
+        async fn complete(&self, _redirect_url: &str, _code: &str) -> anyhow::Result<Identity> {
+            Ok(identity(Some("bob@example.com"), &[]))
+        }
+    }
+
+    fn identity(email: Option<&str>, groups: &[&str]) -> Identity {
+        Identity {
+            subject: "1".to_string(),
+            name: "Bob".to_string(),
+            email: email.map(str::to_string),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_role_mapping() {
+        let uploaders = vec!["alice@example.com".to_string(), "office".to_string()];
+        let hosts = vec!["it".to_string()];
+        assert_eq!(identity(None, &[]).role(&[], &[]), Role::Uploader);
+        assert_eq!(
+            identity(Some("alice@example.com"), &[]).role(&uploaders, &hosts),
+            Role::Uploader
+        );
+        assert_eq!(
+            identity(None, &["office"]).role(&uploaders, &hosts),
+            Role::Uploader
+        );
+        assert_eq!(
+            identity(Some("bob@example.com"), &["guests"]).role(&uploaders, &hosts),
+            Role::Viewer
+        );
+        // Hosts may do everything, whether or not they are uploaders
+        assert_eq!(
+            identity(Some("bob@example.com"), &["it"]).role(&uploaders, &hosts),
+            Role::Host
+        );
+        assert_eq!(identity(None, &["it"]).role(&[], &hosts), Role::Host);
+    }
+
+    #[test]
+    fn test_sessions_and_login_state() {
+        let auth = Auth::new(
+            Arc::new(FixedProvider),
+            String::new(),
+            Vec::new(),
+            Vec::new(),
+            3600,
+        );
+
+        let state = auth.start_login("/r/team".to_string(), 1000);
+        assert_eq!(auth.finish_login(&state, 1001).as_deref(), Some("/r/team"));
+        assert!(auth.finish_login(&state, 1002).is_none());
+
+        let (token, _) = auth.create_session(identity(None, &[]), 1000);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_str(&format!("theme=dark; {}={}", SESSION_COOKIE, token)).unwrap(),
+        );
+        assert_eq!(auth.session(&headers, 2000).unwrap().role, Role::Uploader);
+        assert!(auth.session(&headers, 1000 + 3600).is_none());
+    }
+}
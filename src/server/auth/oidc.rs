@@ -0,0 +1,187 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use tokio::sync::OnceCell;
+
+use super::{AuthProvider, Identity};
+use crate::config::OidcConfig;
+
+/// Endpoints announced by the provider's discovery document
+#[derive(Debug, Clone, Deserialize)]
+struct Discovery {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// Claims returned by the userinfo endpoint
+#[derive(Debug, Deserialize)]
+struct UserInfo {
+    sub: String,
+    name: Option<String>,
+    preferred_username: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    groups: Vec<String>,
+}
+
+impl From<UserInfo> for Identity {
+    fn from(info: UserInfo) -> Self {
+        let name = info
+            .name
+            .or(info.preferred_username)
+            .or_else(|| info.email.clone())
+            .unwrap_or_else(|| info.sub.clone());
+        Identity {
+            subject: info.sub,
+            name,
+            email: info.email,
+            groups: info.groups,
+        }
+    }
+}
+
+/// Authorization URL starting the code flow
+pub fn authorization_url(
+    endpoint: &str,
+    client_id: &str,
+    redirect_url: &str,
+    scopes: &[String],
+    state: &str,
+) -> anyhow::Result<String> {
+    let mut url = reqwest::Url::parse(endpoint)?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", client_id)
+        .append_pair("redirect_uri", redirect_url)
+        .append_pair("scope", &scopes.join(" "))
+        .append_pair("state", state);
+    Ok(url.into())
+}
+
+/// Sign-in through an OpenID Connect provider using the authorization code
+/// flow. The user is looked up at the userinfo endpoint with the access
+/// token received directly from the provider.
+pub struct OidcProvider {
+    config: OidcConfig,
+    client: reqwest::Client,
+    discovery: OnceCell<Discovery>,
+}
+
+impl OidcProvider {
+    pub fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            discovery: OnceCell::new(),
+        }
+    }
+
+    async fn discovery(&self) -> anyhow::Result<&Discovery> {
+        self.discovery
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.config.issuer.trim_end_matches('/')
+                );
+                let discovery = self
+                    .client
+                    .get(&url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<Discovery>()
+                    .await?;
+                Ok(discovery)
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl AuthProvider for OidcProvider {
+    async fn login_url(&self, redirect_url: &str, state: &str) -> anyhow::Result<String> {
+        let discovery = self.discovery().await?;
+        authorization_url(
+            &discovery.authorization_endpoint,
+            &self.config.client_id,
+            redirect_url,
+            &self.config.scopes,
+            state,
+        )
+    }
+
+    async fn complete(&self, redirect_url: &str, code: &str) -> anyhow::Result<Identity> {
+        let discovery = self.discovery().await?;
+        let token = self
+            .client
+            .post(&discovery.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", redirect_url),
+                ("client_id", self.config.client_id.as_str()),
+                ("client_secret", self.config.client_secret.as_str()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<TokenResponse>()
+            .await?;
+
+        let info = self
+            .client
+            .get(&discovery.userinfo_endpoint)
+            .bearer_auth(&token.access_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<UserInfo>()
+            .await?;
+        Ok(info.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authorization_url() {
+        let url = authorization_url(
+            "https://sso.example.com/auth",
+            "justrans",
+            "http://192.168.1.5:8080/auth/callback",
+            &["openid".to_string(), "email".to_string()],
+            "abc",
+        )
+        .unwrap();
+        assert_eq!(
+            url,
+            "https://sso.example.com/auth?response_type=code&client_id=justrans\
+             &redirect_uri=http%3A%2F%2F192.168.1.5%3A8080%2Fauth%2Fcallback\
+             &scope=openid+email&state=abc"
+        );
+    }
+
+    #[test]
+    fn test_identity_from_userinfo() {
+        let info: UserInfo = serde_json::from_str(
+            r#"{"sub": "42", "preferred_username": "alice", "email": "alice@example.com", "groups": ["office"]}"#,
+        )
+        .unwrap();
+        let identity = Identity::from(info);
+        assert_eq!(identity.name, "alice");
+        assert_eq!(identity.email.as_deref(), Some("alice@example.com"));
+        assert_eq!(identity.groups, vec!["office".to_string()]);
+    }
+}
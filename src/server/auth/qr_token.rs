@@ -0,0 +1,201 @@
+//! Access tokens in the QR code. The token is derived from the clock and
+//! replaced every few minutes, so only someone who scanned the QR code
+//! recently gets in; a photo of yesterday's QR code is useless. Browsers
+//! that arrived with a current token are remembered by a cookie.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::cookie;
+use crate::config::QrTokenConfig;
+use crate::models::unix_now;
+use crate::server::file_server::AppState;
+
+/// Cookie of a browser admitted with a QR token
+pub const QR_SESSION_COOKIE: &str = "justrans_qr";
+
+/// Hex digits of a token
+const TOKEN_LEN: usize = 16;
+
+/// Paths that stay reachable without a token: one-time links carry their
+/// own token, and static assets are the same for everyone
+const OPEN_PREFIXES: &[&str] = &["/d/", "/static/"];
+
+/// Tokens for the QR code and the browsers admitted with them
+#[derive(Clone)]
+pub struct QrTokens {
+    /// Key the tokens are derived with, new for every server start
+    secret: String,
+    period_secs: u64,
+    session_secs: u64,
+    /// Expiry of each admitted browser's session, by session token
+    sessions: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl QrTokens {
+    fn new(period_secs: u64, session_secs: u64) -> Self {
+        Self {
+            secret: uuid::Uuid::new_v4().simple().to_string(),
+            period_secs: period_secs.max(1),
+            session_secs,
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// QR tokens as configured, if enabled
+    pub fn from_config(config: Option<&QrTokenConfig>) -> Option<Self> {
+        let config = config.filter(|config| config.enabled)?;
+        Some(Self::new(
+            config.rotate_minutes * 60,
+            config.session_hours * 60 * 60,
+        ))
+    }
+
+    /// Number of the rotation period `now` falls in, which changes
+    /// whenever a new token is due
+    pub fn period(&self, now: u64) -> u64 {
+        now / self.period_secs
+    }
+
+    fn token_for(&self, period: u64) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC accepts any key length");
+        mac.update(&period.to_be_bytes());
+        let mut token = hex::encode(mac.finalize().into_bytes());
+        token.truncate(TOKEN_LEN);
+        token
+    }
+
+    /// Token to put in the QR code at `now`
+    pub fn token_at(&self, now: u64) -> String {
+        self.token_for(self.period(now))
+    }
+
+    /// Whether `token` is the current one, or the one just before, so a
+    /// code scanned right before it rotated still works
+    fn is_valid(&self, token: &str, now: u64) -> bool {
+        let period = self.period(now);
+        token == self.token_for(period) || (period > 0 && token == self.token_for(period - 1))
+    }
+
+    /// Start a session for a browser that presented a valid token
+    fn admit(&self, now: u64) -> String {
+        let session = uuid::Uuid::new_v4().simple().to_string();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, expires_at| *expires_at > now);
+        sessions.insert(session.clone(), now + self.session_secs);
+        session
+    }
+
+    /// Whether the request comes from a browser admitted before
+    fn is_admitted(&self, headers: &HeaderMap, now: u64) -> bool {
+        cookie(headers, QR_SESSION_COOKIE).is_some_and(|session| {
+            self.sessions
+                .lock()
+                .unwrap()
+                .get(&session)
+                .is_some_and(|expires_at| *expires_at > now)
+        })
+    }
+}
+
+/// The `token` parameter of a query string
+fn query_token(query: &str) -> Option<&str> {
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="))
+        .filter(|token| !token.is_empty())
+}
+
+/// Only let in browsers that scanned a current QR code, if enabled
+pub async fn require_qr_token(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(qr_tokens) = state.qr_tokens.clone() else {
+        return next.run(request).await;
+    };
+    let path = request.uri().path();
+    if OPEN_PREFIXES.iter().any(|prefix| path.starts_with(prefix)) {
+        return next.run(request).await;
+    }
+
+    let now = unix_now();
+    if qr_tokens.is_admitted(request.headers(), now) {
+        return next.run(request).await;
+    }
+    let token = request.uri().query().and_then(query_token);
+    if !token.is_some_and(|token| qr_tokens.is_valid(token, now)) {
+        if token.is_some() {
+            log::warn!("Rejected request with an expired QR token");
+        }
+        return (
+            StatusCode::UNAUTHORIZED,
+            "Scan the QR code shown in JusTrans again to open the portal.",
+        )
+            .into_response();
+    }
+
+    let session = qr_tokens.admit(now);
+    let mut response = next.run(request).await;
+    let cookie = format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}",
+        QR_SESSION_COOKIE, session, qr_tokens.session_secs
+    );
+    if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+        response.headers_mut().append(header::SET_COOKIE, cookie);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_rotate() {
+        let tokens = QrTokens::new(300, 3600);
+        let token = tokens.token_at(1000);
+        assert_eq!(token.len(), TOKEN_LEN);
+        assert_eq!(tokens.token_at(1199), token);
+        assert_ne!(tokens.token_at(1200), token);
+
+        assert!(tokens.is_valid(&token, 1000));
+        // Still accepted for one period after the QR code changed
+        assert!(tokens.is_valid(&token, 1400));
+        assert!(!tokens.is_valid(&token, 1500));
+        assert!(!tokens.is_valid("0123456789abcdef", 1000));
+
+        // Another server start hands out other tokens
+        assert_ne!(QrTokens::new(300, 3600).token_at(1000), token);
+    }
+
+    #[test]
+    fn test_admitted_browsers() {
+        let tokens = QrTokens::new(300, 3600);
+        let session = tokens.admit(1000);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_str(&format!("{}={}", QR_SESSION_COOKIE, session)).unwrap(),
+        );
+        assert!(tokens.is_admitted(&headers, 2000));
+        assert!(!tokens.is_admitted(&headers, 1000 + 3600));
+        assert!(!tokens.is_admitted(&HeaderMap::new(), 2000));
+    }
+
+    #[test]
+    fn test_query_token() {
+        assert_eq!(query_token("pin=1234&token=abc"), Some("abc"));
+        assert_eq!(query_token("token="), None);
+        assert_eq!(query_token("pin=1234"), None);
+    }
+}
@@ -0,0 +1,237 @@
+//! Failure injection for the upload pipeline, in builds with the `chaos`
+//! feature started with `--chaos`. Upload requests (segments, v2 sessions
+//! and their parts, room uploads) are at random turned away with
+//! `503 Service Unavailable` before they reach the server, answered late,
+//! or cut short so only the start of their data arrives. Clients then have
+//! to retry, resume from what the server reports missing and catch damaged
+//! data with their checksums, which is what this is for: never ship a build
+//! with it to anyone.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use axum::extract::Request;
+use axum::http::{Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use rand_core::{OsRng, RngCore};
+
+/// Failures turned on with `--chaos`
+static CHAOS: OnceLock<Chaos> = OnceLock::new();
+
+/// How often each failure happens, and the dice they are rolled with
+#[derive(Debug)]
+pub struct Chaos {
+    /// Share of upload requests turned away
+    drop_rate: f64,
+    /// Share of responses held back, and for up to how long
+    delay_rate: f64,
+    max_delay: Duration,
+    /// Share of uploads whose data is cut short
+    truncate_rate: f64,
+    /// xorshift state, shared by every request
+    state: AtomicU64,
+}
+
+impl Chaos {
+    /// Failures at the default rates, rolled from `seed` so a run can be
+    /// repeated
+    pub fn new(seed: u64) -> Self {
+        Self {
+            drop_rate: 0.1,
+            delay_rate: 0.2,
+            max_delay: Duration::from_secs(2),
+            truncate_rate: 0.1,
+            // xorshift never leaves zero
+            state: AtomicU64::new(seed.max(1)),
+        }
+    }
+
+    /// A number in `0.0..1.0`
+    fn roll(&self) -> f64 {
+        let mut next = 0;
+        let _ = self
+            .state
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |mut x| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                next = x;
+                Some(x)
+            });
+        (next >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// How many of `len` bytes of upload data get through
+    pub fn truncate(&self, len: usize) -> usize {
+        if len == 0 || self.roll() >= self.truncate_rate {
+            return len;
+        }
+        (self.roll() * len as f64) as usize
+    }
+
+    /// How long to hold back a response, if at all
+    fn delay(&self) -> Option<Duration> {
+        (self.roll() < self.delay_rate).then(|| self.max_delay.mul_f64(self.roll()))
+    }
+}
+
+/// Turn on failure injection for the rest of the run
+pub fn enable() {
+    let _ = CHAOS.set(Chaos::new(OsRng.next_u64()));
+}
+
+/// Whether `--chaos` was given
+pub fn enabled() -> bool {
+    CHAOS.get().is_some()
+}
+
+/// How many of `len` bytes of upload data get through, all of them unless
+/// chaos mode is on
+pub fn truncated_len(len: usize) -> usize {
+    match CHAOS.get() {
+        Some(chaos) => {
+            let kept = chaos.truncate(len);
+            if kept < len {
+                log::warn!("Chaos mode: cut upload of {} bytes to {}", len, kept);
+            }
+            kept
+        }
+        None => len,
+    }
+}
+
+/// Whether `request` is part of an upload
+fn is_upload(request: &Request) -> bool {
+    (request.method() == Method::POST || request.method() == Method::PUT)
+        && request
+            .uri()
+            .path()
+            .split('/')
+            .any(|segment| segment == "upload")
+}
+
+/// Middleware dropping and delaying uploads while chaos mode is on
+pub async fn inject(request: Request, next: Next) -> Response {
+    match CHAOS.get() {
+        Some(chaos) if is_upload(&request) => run(chaos, request, next).await,
+        _ => next.run(request).await,
+    }
+}
+
+async fn run(chaos: &Chaos, request: Request, next: Next) -> Response {
+    if chaos.roll() < chaos.drop_rate {
+        log::warn!("Chaos mode: dropped {} {}", request.method(), request.uri());
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    let response = next.run(request).await;
+    if let Some(delay) = chaos.delay() {
+        tokio::time::sleep(delay).await;
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::body::Bytes;
+    use axum::routing::put;
+    use axum::{middleware, Router};
+
+    use super::*;
+
+    #[test]
+    fn test_rates() {
+        let chaos = Chaos::new(42);
+        let rolls: Vec<f64> = (0..10_000).map(|_| chaos.roll()).collect();
+        assert!(rolls.iter().all(|roll| (0.0..1.0).contains(roll)));
+        let mean = rolls.iter().sum::<f64>() / rolls.len() as f64;
+        assert!((0.45..0.55).contains(&mean), "mean {}", mean);
+
+        let lengths: Vec<usize> = (0..1000).map(|_| chaos.truncate(100)).collect();
+        assert!(lengths.iter().all(|len| *len <= 100));
+        let cut = lengths.iter().filter(|len| **len < 100).count();
+        assert!((50..150).contains(&cut), "{} cut", cut);
+        assert_eq!(chaos.truncate(0), 0);
+
+        // The same seed fails the same way
+        let (a, b) = (Chaos::new(7), Chaos::new(7));
+        assert!((0..100).all(|_| a.roll() == b.roll()));
+    }
+
+    #[test]
+    fn test_is_upload() {
+        let request = |method: Method, path: &str| {
+            Request::builder()
+                .method(method)
+                .uri(path)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+        assert!(is_upload(&request(Method::POST, "/api/v1/upload")));
+        assert!(is_upload(&request(
+            Method::PUT,
+            "/api/v2/upload/abc?offset=0"
+        )));
+        assert!(is_upload(&request(
+            Method::POST,
+            "/api/v1/rooms/team/upload"
+        )));
+        assert!(!is_upload(&request(Method::GET, "/api/v2/upload/abc")));
+        assert!(!is_upload(&request(Method::POST, "/api/v1/uploads")));
+        assert!(!is_upload(&request(Method::GET, "/api/v1/files")));
+    }
+
+    /// A client that retries dropped parts still gets every one of them
+    /// through, and delays don't lose any
+    #[tokio::test]
+    async fn test_retry_through_chaos() {
+        let chaos: &'static Chaos = Box::leak(Box::new(Chaos {
+            drop_rate: 0.3,
+            max_delay: Duration::from_millis(20),
+            ..Chaos::new(1234)
+        }));
+        let stored = Arc::new(Mutex::new(Vec::new()));
+        let app = Router::new()
+            .route(
+                "/upload/:part",
+                put({
+                    let stored = stored.clone();
+                    move |body: Bytes| async move { stored.lock().unwrap().push(body) }
+                }),
+            )
+            .layer(middleware::from_fn(move |request, next| {
+                run(chaos, request, next)
+            }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = reqwest::Client::new();
+        let mut dropped = 0;
+        for part in 0..20 {
+            loop {
+                let response = client
+                    .put(format!("http://{}/upload/{}", addr, part))
+                    .body(vec![part as u8; 10])
+                    .send()
+                    .await
+                    .unwrap();
+                if response.status().is_success() {
+                    break;
+                }
+                assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+                dropped += 1;
+            }
+        }
+        assert!(dropped > 0);
+        let stored = stored.lock().unwrap();
+        assert_eq!(stored.len(), 20);
+        assert!(stored
+            .iter()
+            .enumerate()
+            .all(|(part, body)| body[..] == [part as u8; 10]));
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::HashSet;
+use std::path::Path as FsPath;
+
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Path, State};
+use axum::http::StatusCode;
+use axum::middleware;
+use axum::routing::{post, put};
+use axum::{Json, Router};
+use chunkstore::{ChunkHash, ChunkStore, Chunker, Manifest};
+use serde::{Deserialize, Serialize};
+
+use super::client_ip::ClientIp;
+use super::file_server::{add_received_file, AppState};
+use super::memory;
+use super::permissions;
+use super::sanitize::sanitize_name;
+use crate::models::FileInfo;
+
+/// Directory under the storage dir that keeps chunks between transfers
+pub const CHUNK_DIR: &str = ".chunks";
+
+/// Largest chunk accepted, with room above the chunker's maximum
+const MAX_CHUNK_LEN: usize = 8 * 1024 * 1024;
+
+/// Chunk store for a storage directory
+pub fn open_store(storage_dir: &FsPath) -> std::io::Result<ChunkStore> {
+    ChunkStore::open(storage_dir.join(CHUNK_DIR))
+}
+
+fn parse_hashes(hashes: &[String]) -> Result<Vec<ChunkHash>, StatusCode> {
+    hashes
+        .iter()
+        .map(|hash| hash.parse().map_err(|_| StatusCode::BAD_REQUEST))
+        .collect()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkList {
+    pub chunks: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MissingChunks {
+    pub missing: Vec<String>,
+}
+
+/// A file to put together from stored chunks
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AssembleRequest {
+    pub name: String,
+    pub size: u64,
+    pub chunks: Vec<String>,
+    /// Unix permission bits of the sent file, kept as far as
+    /// `storage.permissions` allows
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+}
+
+fn store(state: &AppState) -> Result<ChunkStore, StatusCode> {
+    open_store(&state.temp_dir).map_err(|e| {
+        log::error!("Failed to open chunk store: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[axum::debug_handler]
+async fn missing_chunks(
+    State(state): State<AppState>,
+    Json(list): Json<ChunkList>,
+) -> Result<Json<MissingChunks>, StatusCode> {
+    let hashes = parse_hashes(&list.chunks)?;
+    let missing = store(&state)?.missing(&hashes);
+    log::debug!(
+        "{} of {} offered chunks are missing",
+        missing.len(),
+        hashes.len()
+    );
+    Ok(Json(MissingChunks {
+        missing: missing.iter().map(ChunkHash::to_string).collect(),
+    }))
+}
+
+#[axum::debug_handler]
+async fn put_chunk(
+    Path(hash): Path<String>,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Result<StatusCode, StatusCode> {
+    if state.read_only() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let hash: ChunkHash = hash.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+    store(&state)?
+        .put_verified(&hash, &body)
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::InvalidData => StatusCode::BAD_REQUEST,
+            _ => {
+                log::error!("Failed to store chunk {}: {}", hash, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler]
+async fn assemble(
+    ClientIp(client_ip): ClientIp,
+    State(state): State<AppState>,
+    Json(request): Json<AssembleRequest>,
+) -> Result<Json<FileInfo>, StatusCode> {
+    if state.read_only() {
+        log::warn!("Rejected chunked upload to read-only server");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let name = sanitize_name(&request.name);
+    if !state.file_types.accepts(&name) {
+        log::warn!("Rejected '{}': kind of file not accepted", name);
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+    let manifest = Manifest {
+        chunks: parse_hashes(&request.chunks)?,
+        size: request.size,
+    };
+    let store = store(&state)?;
+    if !store.missing(&manifest.chunks).is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let id = uuid::Uuid::new_v4().to_string();
+    let path = state.scratch_dir.join(format!("{}_file", id));
+    let written = std::fs::File::create(&path)
+        .and_then(|file| store.assemble(&manifest, std::io::BufWriter::new(file)))
+        .map_err(|e| {
+            log::error!("Failed to assemble {:?}: {}", path, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if written != manifest.size {
+        log::warn!(
+            "Assembled '{}' has {} bytes instead of {}",
+            name,
+            written,
+            manifest.size
+        );
+        let _ = std::fs::remove_file(&path);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    log::info!(
+        "Assembled '{}' from {} stored chunks",
+        name,
+        manifest.chunks.len()
+    );
+
+    let file_info = FileInfo {
+        id,
+        device: client_ip,
+        mode: request.mode,
+        ..FileInfo::new(name, path, written)
+    };
+    add_received_file(&state, &state.file_list, &state.temp_dir, None, file_info).await
+}
+
+/// Routes of the chunked upload API on the main share
+pub fn api_router() -> Router<AppState> {
+    Router::new()
+        .route("/chunks/missing", post(missing_chunks))
+        .route(
+            "/chunks/:hash",
+            put(put_chunk)
+                .layer(DefaultBodyLimit::max(MAX_CHUNK_LEN))
+                .layer(middleware::from_fn_with_state(
+                    MAX_CHUNK_LEN,
+                    memory::hold_body,
+                )),
+        )
+        .route("/chunks/assemble", post(assemble))
+}
+
+/// Outcome of sending a file through the chunk API
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushReport {
+    pub chunks: usize,
+    pub sent_chunks: usize,
+    pub size: u64,
+    pub sent_bytes: u64,
+}
+
+/// Send a file to the instance at `base_url`, uploading only the chunks it
+/// does not have from earlier transfers
+pub async fn push(base_url: &str, path: &FsPath) -> anyhow::Result<PushReport> {
+    let chunker = Chunker::default();
+    let manifest = Manifest::from_reader(&chunker, std::fs::File::open(path)?)?;
+    let api = format!("{}/api/v1/chunks", base_url.trim_end_matches('/'));
+    let client = reqwest::Client::new();
+
+    let chunks: Vec<String> = manifest.chunks.iter().map(ChunkHash::to_string).collect();
+    let missing: HashSet<String> = client
+        .post(format!("{}/missing", api))
+        .json(&ChunkList {
+            chunks: chunks.clone(),
+        })
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<MissingChunks>()
+        .await?
+        .missing
+        .into_iter()
+        .collect();
+
+    let mut report = PushReport {
+        chunks: chunks.len(),
+        sent_chunks: 0,
+        size: manifest.size,
+        sent_bytes: 0,
+    };
+    let mut sent = HashSet::new();
+    for chunk in chunker.chunks(std::fs::File::open(path)?) {
+        let chunk = chunk?;
+        let hash = ChunkHash::of(&chunk).to_string();
+        if !missing.contains(&hash) || !sent.insert(hash.clone()) {
+            continue;
+        }
+        report.sent_chunks += 1;
+        report.sent_bytes += chunk.len() as u64;
+        client
+            .put(format!("{}/{}", api, hash))
+            .body(chunk)
+            .send()
+            .await?
+            .error_for_status()?;
+    }
+
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| anyhow::anyhow!("Invalid file path: {:?}", path))?;
+    client
+        .post(format!("{}/assemble", api))
+        .json(&AssembleRequest {
+            name,
+            size: manifest.size,
+            chunks,
+            mode: permissions::mode_of(path),
+        })
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hashes() {
+        let hash = ChunkHash::of(b"chunk").to_string();
+        assert_eq!(parse_hashes(&[hash]).unwrap().len(), 1);
+        assert_eq!(
+            parse_hashes(&["../../etc/passwd".to_string()]),
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! Address of the client behind a request. A reverse proxy such as Caddy or
+//! Traefik connects on the client's behalf and names it in the `Forwarded`
+//! or `X-Forwarded-For` header, which is only believed when the connection
+//! comes from one of `server.trusted_proxies`.
+
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+
+use async_trait::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::HeaderMap;
+
+use super::devices::address_matches;
+use super::file_server::AppState;
+
+/// Address of the client that made a request, `None` when the connection
+/// has no peer address
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(ClientIp(of_parts(
+            &parts.extensions,
+            &parts.headers,
+            &state.trusted_proxies,
+        )))
+    }
+}
+
+/// Client address of a request from its connection and headers
+pub fn of_parts(
+    extensions: &axum::http::Extensions,
+    headers: &HeaderMap,
+    trusted_proxies: &[String],
+) -> Option<IpAddr> {
+    let ConnectInfo(peer) = extensions.get::<ConnectInfo<SocketAddr>>()?;
+    Some(resolve(peer.ip(), headers, trusted_proxies))
+}
+
+fn is_trusted(trusted_proxies: &[String], ip: IpAddr) -> bool {
+    trusted_proxies
+        .iter()
+        .any(|pattern| address_matches(pattern, ip))
+}
+
+/// The client that connected to `peer`, following the forwarding headers
+/// back through trusted proxies. Each proxy appends the address it got the
+/// request from, so the client is the last hop that is not a trusted proxy.
+pub fn resolve(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[String]) -> IpAddr {
+    let mut client = peer;
+    if !is_trusted(trusted_proxies, client) {
+        return client;
+    }
+    for hop in forwarded_hops(headers).into_iter().rev() {
+        // Obfuscated or unknown hops cannot be followed any further
+        let Some(hop) = hop else {
+            break;
+        };
+        client = hop;
+        if !is_trusted(trusted_proxies, client) {
+            break;
+        }
+    }
+    client
+}
+
+/// Hops listed by the `Forwarded` header, or by `X-Forwarded-For` when there
+/// is none, from the original client to the nearest proxy
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+
+    let forwarded = values("forwarded");
+    if !forwarded.is_empty() {
+        return forwarded
+            .iter()
+            .map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (name, value) = pair.split_once('=')?;
+                    name.trim()
+                        .eq_ignore_ascii_case("for")
+                        .then(|| parse_node(value))
+                })?
+            })
+            .collect();
+    }
+    values("x-forwarded-for")
+        .iter()
+        .map(|node| parse_node(node))
+        .collect()
+}
+
+/// Address of a node such as `192.0.2.60`, `192.0.2.60:4711` or
+/// `"[2001:db8::17]:4711"`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_forwarded_for() {
+        let trusted = vec!["10.0.0.0/8".to_string()];
+        let forwarded = headers(&[("x-forwarded-for", "203.0.113.9, 10.0.0.7")]);
+        assert_eq!(
+            resolve(ip("10.0.0.2"), &forwarded, &trusted),
+            ip("203.0.113.9")
+        );
+
+        // Untrusted peers cannot claim to be someone else
+        assert_eq!(
+            resolve(ip("192.168.1.50"), &forwarded, &trusted),
+            ip("192.168.1.50")
+        );
+        // A client cannot hide behind a spoofed entry in front of its own
+        let spoofed = headers(&[("x-forwarded-for", "10.0.0.9, 198.51.100.4")]);
+        assert_eq!(
+            resolve(ip("10.0.0.2"), &spoofed, &trusted),
+            ip("198.51.100.4")
+        );
+        assert_eq!(
+            resolve(ip("10.0.0.2"), &HeaderMap::new(), &trusted),
+            ip("10.0.0.2")
+        );
+    }
+
+    #[test]
+    fn test_forwarded_header() {
+        let trusted = vec!["127.0.0.1".to_string()];
+        let forwarded = headers(&[
+            ("forwarded", "for=192.0.2.60;proto=http;by=203.0.113.43"),
+            ("forwarded", "For=\"[2001:db8:cafe::17]:4711\""),
+            ("x-forwarded-for", "198.51.100.4"),
+        ]);
+        assert_eq!(
+            resolve(ip("127.0.0.1"), &forwarded, &trusted),
+            ip("2001:db8:cafe::17")
+        );
+
+        let hidden = headers(&[("forwarded", "for=192.0.2.60, for=_hidden")]);
+        assert_eq!(resolve(ip("127.0.0.1"), &hidden, &trusted), ip("127.0.0.1"));
+    }
+}
@@ -0,0 +1,197 @@
+use std::time::UNIX_EPOCH;
+
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Format of HTTP dates, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Validators describing one version of a resource
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: String,
+    /// Whole seconds since the Unix epoch, as HTTP dates have no fractions
+    pub last_modified: Option<u64>,
+}
+
+impl Validators {
+    /// Strong ETag from the bytes of a response body
+    pub fn for_content(body: &[u8]) -> Self {
+        Self {
+            etag: format!("\"{}\"", hex::encode(&Sha256::digest(body)[..8])),
+            last_modified: None,
+        }
+    }
+
+    /// ETag and Last-Modified of a file on disk, without reading it
+    pub fn for_file(metadata: &std::fs::Metadata) -> Self {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok());
+        let nanos = modified.map(|d| d.as_nanos()).unwrap_or_default();
+        Self {
+            etag: format!("\"{:x}-{:x}\"", nanos, metadata.len()),
+            last_modified: modified.map(|d| d.as_secs()),
+        }
+    }
+
+    /// Whether the client's cached copy, described by the request's
+    /// conditional headers, is still current. `If-None-Match` takes
+    /// precedence over `If-Modified-Since`.
+    pub fn matches(&self, headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = header_str(headers, header::IF_NONE_MATCH) {
+            return if_none_match.split(',').map(str::trim).any(|tag| {
+                tag == "*" || tag.trim_start_matches("W/") == self.etag.trim_start_matches("W/")
+            });
+        }
+        match (
+            self.last_modified,
+            header_str(headers, header::IF_MODIFIED_SINCE).and_then(parse_http_date),
+        ) {
+            (Some(last_modified), Some(since)) => last_modified <= since,
+            _ => false,
+        }
+    }
+
+    /// Add the validators to a response
+    pub fn apply(&self, response: &mut Response) {
+        let headers = response.headers_mut();
+        if let Ok(etag) = HeaderValue::from_str(&self.etag) {
+            headers.insert(header::ETAG, etag);
+        }
+        if let Some(date) = self.last_modified.and_then(format_http_date) {
+            if let Ok(date) = HeaderValue::from_str(&date) {
+                headers.insert(header::LAST_MODIFIED, date);
+            }
+        }
+    }
+
+    /// Empty `304 Not Modified` carrying the validators
+    pub fn not_modified(&self) -> Response {
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        self.apply(&mut response);
+        response
+    }
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Seconds since the Unix epoch of an HTTP date
+pub fn parse_http_date(value: &str) -> Option<u64> {
+    DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .and_then(|date| u64::try_from(date.timestamp()).ok())
+}
+
+/// HTTP date for seconds since the Unix epoch
+pub fn format_http_date(secs: u64) -> Option<String> {
+    DateTime::<Utc>::from_timestamp(i64::try_from(secs).ok()?, 0)
+        .map(|date| date.format(HTTP_DATE).to_string())
+}
+
+/// JSON response with an ETag; answers `304 Not Modified` when the client
+/// already has this exact content, so polling clients skip the body
+pub fn json<T: Serialize>(headers: &HeaderMap, value: &T) -> Response {
+    let body = match serde_json::to_vec(value) {
+        Ok(body) => body,
+        Err(e) => {
+            log::error!("Failed to serialize response: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let validators = Validators::for_content(&body);
+    if validators.matches(headers) {
+        return validators.not_modified();
+    }
+
+    let mut response = (
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            // Cache, but check back every time
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        body,
+    )
+        .into_response();
+    validators.apply(&mut response);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn secs(time: SystemTime) -> u64 {
+        time.duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    fn request(name: header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_http_date_round_trip() {
+        let date = format_http_date(784111777).unwrap();
+        assert_eq!(date, "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse_http_date(&date), Some(784111777));
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let validators = Validators::for_content(b"[]");
+        assert!(validators.matches(&request(header::IF_NONE_MATCH, &validators.etag)));
+        assert!(validators.matches(&request(
+            header::IF_NONE_MATCH,
+            &format!("\"other\", W/{}", validators.etag)
+        )));
+        assert!(!validators.matches(&request(header::IF_NONE_MATCH, "\"other\"")));
+        assert!(!validators.matches(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn test_if_modified_since() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let metadata = file.as_file().metadata().unwrap();
+        let validators = Validators::for_file(&metadata);
+        let modified = secs(metadata.modified().unwrap());
+
+        let current = request(
+            header::IF_MODIFIED_SINCE,
+            &format_http_date(modified).unwrap(),
+        );
+        let stale = request(
+            header::IF_MODIFIED_SINCE,
+            &format_http_date(modified - 60).unwrap(),
+        );
+        assert!(validators.matches(&current));
+        assert!(!validators.matches(&stale));
+
+        // A non-matching ETag wins over a current date
+        let mut both = current.clone();
+        both.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"old\""));
+        assert!(!validators.matches(&both));
+    }
+
+    #[test]
+    fn test_json_not_modified() {
+        let first = json(&HeaderMap::new(), &["a.txt"]);
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = first.headers()[header::ETAG].to_str().unwrap().to_string();
+
+        let second = json(&request(header::IF_NONE_MATCH, &etag), &["a.txt"]);
+        assert_eq!(second.status(), StatusCode::NOT_MODIFIED);
+
+        let changed = json(&request(header::IF_NONE_MATCH, &etag), &["b.txt"]);
+        assert_eq!(changed.status(), StatusCode::OK);
+    }
+}
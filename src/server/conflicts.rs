@@ -0,0 +1,227 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use crate::models::{FileInfo, FileList, NameMatch};
+
+/// How the desktop user decided to handle an incoming file whose name is
+/// already taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictResolution {
+    /// Store the incoming file under a new name next to the existing one
+    KeepBoth,
+    /// Replace the existing file with the incoming one
+    Replace,
+    /// Delete the incoming file
+    Discard,
+}
+
+impl ConflictResolution {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "keep-both" => Some(Self::KeepBoth),
+            "replace" => Some(Self::Replace),
+            "discard" => Some(Self::Discard),
+            _ => None,
+        }
+    }
+}
+
+/// An incoming file waiting for review
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingConflict {
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+    pub room: Option<String>,
+}
+
+/// A held upload and the channel its resolution is sent on
+type Waiting = (PendingConflict, oneshot::Sender<ConflictResolution>);
+
+/// Uploads held until the desktop user resolves their name conflict
+#[derive(Clone, Default)]
+pub struct ConflictQueue {
+    pending: Arc<Mutex<Vec<Waiting>>>,
+}
+
+impl ConflictQueue {
+    /// Queue a conflict and wait for its resolution, falling back to
+    /// keeping both files once `timeout` elapses
+    pub async fn review(&self, conflict: PendingConflict, timeout: Duration) -> ConflictResolution {
+        let (tx, rx) = oneshot::channel();
+        let id = conflict.id.clone();
+        self.pending.lock().unwrap().push((conflict, tx));
+
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(resolution)) => resolution,
+            _ => {
+                log::info!(
+                    "Conflict for upload {} not resolved in time, keeping both",
+                    id
+                );
+                self.pending.lock().unwrap().retain(|(c, _)| c.id != id);
+                ConflictResolution::KeepBoth
+            }
+        }
+    }
+
+    /// Conflicts still waiting for a decision, oldest first
+    pub fn pending(&self) -> Vec<PendingConflict> {
+        self.pending
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(c, _)| c.clone())
+            .collect()
+    }
+
+    /// Release a held upload with the user's decision
+    pub fn resolve(&self, id: &str, resolution: ConflictResolution) -> anyhow::Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        let index = pending
+            .iter()
+            .position(|(c, _)| c.id == id)
+            .ok_or_else(|| anyhow::anyhow!("No pending conflict with id {}", id))?;
+        let (_, tx) = pending.remove(index);
+        tx.send(resolution)
+            .map_err(|_| anyhow::anyhow!("Upload {} is no longer waiting", id))
+    }
+}
+
+/// First free name of the form `name (n).ext` in the list
+pub fn unique_name(file_list: &FileList, name: &str, matching: NameMatch) -> String {
+    let path = std::path::Path::new(name);
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| name.to_string());
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+
+    (1..)
+        .map(|n| format!("{} ({}){}", stem, n, extension))
+        .find(|candidate| file_list.get_file_by_name(candidate, matching).is_none())
+        .unwrap()
+}
+
+/// Apply a resolution to the list, returning the file that was added, if
+/// any, and the existing file that was replaced
+pub fn apply(
+    file_list: &mut FileList,
+    mut incoming: FileInfo,
+    resolution: ConflictResolution,
+    matching: NameMatch,
+) -> (Option<FileInfo>, Option<FileInfo>) {
+    match resolution {
+        ConflictResolution::KeepBoth => {
+            incoming.name = unique_name(file_list, &incoming.name, matching);
+            file_list.add_file(incoming.clone());
+            (Some(incoming), None)
+        }
+        ConflictResolution::Replace => {
+            let replaced = file_list.remove_by_name(&incoming.name, matching);
+            file_list.add_file(incoming.clone());
+            (Some(incoming), replaced)
+        }
+        ConflictResolution::Discard => (None, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn file(id: &str, name: &str) -> FileInfo {
+        FileInfo {
+            id: id.to_string(),
+            added_at: 0,
+            ..FileInfo::new(name.to_string(), PathBuf::from(id), 1)
+        }
+    }
+
+    #[test]
+    fn test_unique_name() {
+        let mut list = FileList::new();
+        list.add_file(file("a", "report.pdf"));
+        assert_eq!(
+            unique_name(&list, "report.pdf", NameMatch::Exact),
+            "report (1).pdf"
+        );
+
+        list.add_file(file("b", "report (1).pdf"));
+        assert_eq!(
+            unique_name(&list, "report.pdf", NameMatch::Exact),
+            "report (2).pdf"
+        );
+        assert_eq!(unique_name(&list, "notes", NameMatch::Exact), "notes (1)");
+
+        list.add_file(file("c", "Report (2).PDF"));
+        assert_eq!(
+            unique_name(&list, "report.pdf", NameMatch::IgnoreCase),
+            "report (3).pdf"
+        );
+    }
+
+    #[test]
+    fn test_apply_resolutions() {
+        let mut list = FileList::new();
+        list.add_file(file("old", "photo.jpg"));
+
+        let (added, replaced) = apply(
+            &mut list,
+            file("new", "photo.jpg"),
+            ConflictResolution::Replace,
+            NameMatch::Exact,
+        );
+        assert_eq!(added.unwrap().id, "new");
+        assert_eq!(replaced.unwrap().id, "old");
+        assert_eq!(list.len(), 1);
+
+        let (added, _) = apply(
+            &mut list,
+            file("copy", "photo.jpg"),
+            ConflictResolution::KeepBoth,
+            NameMatch::Exact,
+        );
+        assert_eq!(added.unwrap().name, "photo (1).jpg");
+        assert_eq!(list.len(), 2);
+
+        let (added, replaced) = apply(
+            &mut list,
+            file("gone", "photo.jpg"),
+            ConflictResolution::Discard,
+            NameMatch::Exact,
+        );
+        assert!(added.is_none() && replaced.is_none());
+        assert_eq!(list.len(), 2);
+
+        let (_, replaced) = apply(
+            &mut list,
+            file("upper", "PHOTO.JPG"),
+            ConflictResolution::Replace,
+            NameMatch::IgnoreCase,
+        );
+        assert_eq!(replaced.unwrap().id, "new");
+        assert_eq!(list.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_review_times_out_to_keep_both() {
+        let queue = ConflictQueue::default();
+        let conflict = PendingConflict {
+            id: "upload".to_string(),
+            name: "photo.jpg".to_string(),
+            size: 1,
+            room: None,
+        };
+        let resolution = queue.review(conflict, Duration::from_millis(10)).await;
+        assert_eq!(resolution, ConflictResolution::KeepBoth);
+        assert!(queue.pending().is_empty());
+    }
+}
@@ -0,0 +1,579 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path as FsPath;
+
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::client_ip::ClientIp;
+use super::file_server::{add_received_file, shared_path, AppState};
+use super::memory;
+use super::permissions::{self, MODE_HEADER};
+use crate::models::{FileInfo, FileList, NameMatch};
+
+/// First bytes of an encoded delta
+const MAGIC: &[u8; 4] = b"JTD1";
+
+const OP_COPY: u8 = 0;
+const OP_DATA: u8 = 1;
+const OP_END: u8 = 2;
+
+/// Literal runs are split so neither side buffers more than this
+const MAX_LITERAL_LEN: usize = 1024 * 1024;
+
+/// Largest delta accepted in one request
+pub const MAX_DELTA_LEN: usize = 64 * 1024 * 1024;
+
+/// Block size for a file of `len` bytes: about the square root of the
+/// length, as rsync does, so signatures stay small for large files
+pub fn block_size_for(len: u64) -> usize {
+    ((len as f64).sqrt() as usize)
+        .next_multiple_of(4096)
+        .clamp(4096, 1024 * 1024)
+}
+
+/// rsync's rolling checksum of a block, updated in constant time as the
+/// block slides forward by one byte
+#[derive(Debug, Clone, Copy)]
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(block: &[u8]) -> Self {
+        let len = block.len() as u32;
+        let (mut a, mut b) = (0u32, 0u32);
+        for (i, &byte) in block.iter().enumerate() {
+            a = a.wrapping_add(byte as u32);
+            b = b.wrapping_add((len - i as u32).wrapping_mul(byte as u32));
+        }
+        Self { a, b, len }
+    }
+
+    /// Slide the block one byte forward
+    fn roll(&mut self, out: u8, into: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(into as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+
+    fn value(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+fn strong_hash(block: &[u8]) -> String {
+    hex::encode(&Sha256::digest(block)[..16])
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockSum {
+    pub weak: u32,
+    pub strong: String,
+}
+
+/// Checksums of the blocks of a stored file version
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Signature {
+    pub size: u64,
+    pub block_size: usize,
+    pub blocks: Vec<BlockSum>,
+}
+
+impl Signature {
+    pub fn of<R: Read>(mut reader: R, block_size: usize) -> io::Result<Self> {
+        let mut signature = Self {
+            size: 0,
+            block_size,
+            blocks: Vec::new(),
+        };
+        let mut block = vec![0; block_size];
+        loop {
+            let len = read_full(&mut reader, &mut block)?;
+            if len == 0 {
+                break;
+            }
+            signature.size += len as u64;
+            signature.blocks.push(BlockSum {
+                weak: Rolling::new(&block[..len]).value(),
+                strong: strong_hash(&block[..len]),
+            });
+        }
+        Ok(signature)
+    }
+}
+
+/// Read until `buf` is full or the input ends, returning the bytes read
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+/// How much of a new version was found in the old one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeltaStats {
+    pub copied_blocks: usize,
+    pub literal_bytes: u64,
+}
+
+fn write_literal<W: Write>(writer: &mut W, literal: &mut Vec<u8>) -> io::Result<()> {
+    if !literal.is_empty() {
+        writer.write_all(&[OP_DATA])?;
+        writer.write_all(&(literal.len() as u32).to_be_bytes())?;
+        writer.write_all(literal)?;
+        literal.clear();
+    }
+    Ok(())
+}
+
+/// Encode the new version read from `reader` as copies of blocks from the
+/// version described by `signature` plus literal data for everything else.
+/// Only full-size blocks are matched; a shorter last block is sent as is.
+pub fn encode<R: Read, W: Write>(
+    signature: &Signature,
+    mut reader: R,
+    mut writer: W,
+) -> io::Result<DeltaStats> {
+    let block_size = signature.block_size;
+    let full_blocks = (signature.size / block_size as u64) as usize;
+    let mut index: HashMap<u32, Vec<(u32, &str)>> = HashMap::new();
+    for (i, block) in signature.blocks.iter().take(full_blocks).enumerate() {
+        index
+            .entry(block.weak)
+            .or_default()
+            .push((i as u32, &block.strong));
+    }
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&(block_size as u32).to_be_bytes())?;
+
+    let mut stats = DeltaStats::default();
+    let mut hasher = Sha256::new();
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut start = 0;
+    let mut eof = false;
+    let mut rolling: Option<Rolling> = None;
+    let mut literal = Vec::new();
+    let mut read_buf = vec![0; 64 * 1024];
+
+    loop {
+        // Keep a whole block ahead of the window start
+        while !eof && buffer.len() - start < block_size {
+            if start > MAX_LITERAL_LEN {
+                buffer.drain(..start);
+                start = 0;
+            }
+            match reader.read(&mut read_buf) {
+                Ok(0) => eof = true,
+                Ok(n) => {
+                    hasher.update(&read_buf[..n]);
+                    buffer.extend_from_slice(&read_buf[..n]);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if buffer.len() - start < block_size {
+            break;
+        }
+
+        let window = &buffer[start..start + block_size];
+        let weak = *rolling.get_or_insert_with(|| Rolling::new(window));
+        if let Some(candidates) = index.get(&weak.value()) {
+            let strong = strong_hash(window);
+            if let Some(&(block, _)) = candidates.iter().find(|(_, s)| *s == strong) {
+                write_literal(&mut writer, &mut literal)?;
+                writer.write_all(&[OP_COPY])?;
+                writer.write_all(&block.to_be_bytes())?;
+                stats.copied_blocks += 1;
+                start += block_size;
+                rolling = None;
+                continue;
+            }
+        }
+
+        // No match here, the first byte of the window becomes literal data
+        literal.push(buffer[start]);
+        stats.literal_bytes += 1;
+        if literal.len() >= MAX_LITERAL_LEN {
+            write_literal(&mut writer, &mut literal)?;
+        }
+        start += 1;
+        rolling = match rolling {
+            Some(mut sum) if buffer.len() - start >= block_size => {
+                sum.roll(buffer[start - 1], buffer[start + block_size - 1]);
+                Some(sum)
+            }
+            _ => None,
+        };
+    }
+
+    write_literal(&mut writer, &mut literal)?;
+    for chunk in buffer[start..].chunks(MAX_LITERAL_LEN) {
+        literal.extend_from_slice(chunk);
+        stats.literal_bytes += chunk.len() as u64;
+        write_literal(&mut writer, &mut literal)?;
+    }
+    writer.write_all(&[OP_END])?;
+    writer.write_all(&hasher.finalize())?;
+    writer.flush()?;
+    Ok(stats)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Rebuild the new version from the old one and a delta, returning the
+/// number of bytes written. Fails with `InvalidData` when the delta is
+/// malformed or the result does not match the checksum it carries.
+pub fn apply<B: Read + Seek, R: Read, W: Write>(
+    mut base: B,
+    mut delta: R,
+    mut writer: W,
+) -> io::Result<u64> {
+    let mut header = [0; 8];
+    delta.read_exact(&mut header)?;
+    if &header[..4] != MAGIC {
+        return Err(invalid("not a delta"));
+    }
+    let block_size = u32::from_be_bytes(header[4..].try_into().unwrap()) as usize;
+    if block_size == 0 {
+        return Err(invalid("zero block size"));
+    }
+
+    let mut hasher = Sha256::new();
+    let mut written = 0;
+    let mut block = vec![0; block_size];
+    let mut word = [0; 4];
+    loop {
+        let mut op = [0];
+        delta.read_exact(&mut op)?;
+        match op[0] {
+            OP_COPY => {
+                delta.read_exact(&mut word)?;
+                let index = u32::from_be_bytes(word) as u64;
+                base.seek(SeekFrom::Start(index * block_size as u64))?;
+                base.read_exact(&mut block)
+                    .map_err(|_| invalid("copied block is outside the old version"))?;
+                hasher.update(&block);
+                writer.write_all(&block)?;
+                written += block_size as u64;
+            }
+            OP_DATA => {
+                delta.read_exact(&mut word)?;
+                let len = u32::from_be_bytes(word) as usize;
+                if len > MAX_LITERAL_LEN {
+                    return Err(invalid("literal run too long"));
+                }
+                let mut data = vec![0; len];
+                delta.read_exact(&mut data)?;
+                hasher.update(&data);
+                writer.write_all(&data)?;
+                written += len as u64;
+            }
+            OP_END => {
+                let mut expected = [0; 32];
+                delta.read_exact(&mut expected)?;
+                if hasher.finalize()[..] != expected[..] {
+                    return Err(invalid("result does not match the delta checksum"));
+                }
+                writer.flush()?;
+                return Ok(written);
+            }
+            _ => return Err(invalid("unknown delta operation")),
+        }
+    }
+}
+
+fn find_file(state: &AppState, id: &str) -> Result<FileInfo, StatusCode> {
+    let file_info = state
+        .file_list
+        .lock()
+        .unwrap()
+        .get_file_by_id(id)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    // Signatures and deltas would leak a protected file's contents
+    if file_info.protected {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if file_info.shared {
+        let path = shared_path(state, &file_info)?;
+        return Ok(FileInfo { path, ..file_info });
+    }
+    Ok(file_info)
+}
+
+#[axum::debug_handler]
+async fn get_signature(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<Signature>, StatusCode> {
+    let file_info = find_file(&state, &id)?;
+    let signature = tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(&file_info.path)?;
+        let block_size = block_size_for(file.metadata()?.len());
+        Signature::of(io::BufReader::new(file), block_size)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .map_err(|e| {
+        log::error!("Failed to compute block checksums: {}", e);
+        StatusCode::NOT_FOUND
+    })?;
+    Ok(Json(signature))
+}
+
+#[axum::debug_handler]
+async fn upload_delta(
+    Path(id): Path<String>,
+    ClientIp(client_ip): ClientIp,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<FileInfo>, StatusCode> {
+    if state.read_only() {
+        log::warn!("Rejected delta upload to read-only server");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let base = find_file(&state, &id)?;
+    // A new version keeps the permissions of the old one unless the
+    // sender declares its own
+    let declared = match headers.get(MODE_HEADER) {
+        Some(mode) => Some(
+            mode.to_str()
+                .ok()
+                .and_then(permissions::parse_mode)
+                .ok_or(StatusCode::BAD_REQUEST)?,
+        ),
+        None => base.mode,
+    };
+
+    let new_id = uuid::Uuid::new_v4().to_string();
+    let path = state.scratch_dir.join(format!("{}_file", new_id));
+    let result = {
+        let base_path = base.path.clone();
+        let path = path.clone();
+        tokio::task::spawn_blocking(move || {
+            let base = std::fs::File::open(base_path)?;
+            let out = std::fs::File::create(&path)?;
+            apply(io::BufReader::new(base), &body[..], io::BufWriter::new(out))
+        })
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    };
+    let size = match result {
+        Ok(size) => size,
+        Err(e) => {
+            let _ = std::fs::remove_file(&path);
+            log::warn!("Rejected delta for '{}': {}", base.name, e);
+            return Err(match e.kind() {
+                io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+                    StatusCode::BAD_REQUEST
+                }
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            });
+        }
+    };
+    log::info!(
+        "Received new version of '{}' as a delta of {} bytes",
+        base.name,
+        size
+    );
+
+    let file_info = FileInfo {
+        id: new_id,
+        mime_type: base.mime_type,
+        device: client_ip,
+        mode: declared,
+        ..FileInfo::new(base.name, path, size)
+    };
+    add_received_file(&state, &state.file_list, &state.temp_dir, None, file_info).await
+}
+
+/// Routes of the delta upload API on the main share
+pub fn api_router() -> Router<AppState> {
+    Router::new()
+        .route("/files/:id/signature", get(get_signature))
+        .route(
+            "/files/:id/delta",
+            post(upload_delta)
+                .layer(DefaultBodyLimit::max(MAX_DELTA_LEN))
+                .layer(middleware::from_fn_with_state(
+                    MAX_DELTA_LEN,
+                    memory::hold_body,
+                )),
+        )
+}
+
+/// Outcome of sending a new version as a delta
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpdateReport {
+    pub size: u64,
+    pub sent_bytes: u64,
+    pub stats: DeltaStats,
+}
+
+/// Send a new version of a file the instance at `base_url` already has
+/// under the same name, uploading only the blocks that changed. Returns
+/// `None` when there is no earlier version or the delta would be too large.
+pub async fn update(base_url: &str, path: &FsPath) -> anyhow::Result<Option<UpdateReport>> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| anyhow::anyhow!("Invalid file path: {:?}", path))?;
+    let api = format!("{}/api/v1/files", base_url.trim_end_matches('/'));
+    let client = reqwest::Client::new();
+
+    let files: FileList = client
+        .get(&api)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let Some(previous) = files
+        .iter()
+        .filter(|file| NameMatch::Exact.same(&file.name, &name))
+        .max_by_key(|file| file.added_at)
+    else {
+        return Ok(None);
+    };
+
+    let signature: Signature = client
+        .get(format!("{}/{}/signature", api, previous.id))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let mut delta = Vec::new();
+    let file = std::fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    let stats = encode(&signature, io::BufReader::new(file), &mut delta)?;
+    if delta.len() > MAX_DELTA_LEN {
+        return Ok(None);
+    }
+
+    let sent_bytes = delta.len() as u64;
+    let mut request = client.post(format!("{}/{}/delta", api, previous.id));
+    if let Some(mode) = permissions::mode_of(path) {
+        request = request.header(MODE_HEADER, format!("{:o}", mode));
+    }
+    request.body(delta).send().await?.error_for_status()?;
+    Ok(Some(UpdateReport {
+        size,
+        sent_bytes,
+        stats,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Deterministic pseudo-random bytes
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn round_trip(old: &[u8], new: &[u8], block_size: usize) -> (Vec<u8>, DeltaStats) {
+        let signature = Signature::of(old, block_size).unwrap();
+        let mut delta = Vec::new();
+        let stats = encode(&signature, new, &mut delta).unwrap();
+        let mut rebuilt = Vec::new();
+        let written = apply(Cursor::new(old), &delta[..], &mut rebuilt).unwrap();
+        assert_eq!(written, new.len() as u64);
+        (rebuilt, stats)
+    }
+
+    #[test]
+    fn test_rolling_matches_fresh_checksum() {
+        let data = noise(100, 1);
+        let mut rolling = Rolling::new(&data[..32]);
+        for start in 1..=68 {
+            rolling.roll(data[start - 1], data[start + 31]);
+            assert_eq!(
+                rolling.value(),
+                Rolling::new(&data[start..start + 32]).value()
+            );
+        }
+    }
+
+    #[test]
+    fn test_delta_sends_only_changes() {
+        let old = noise(300_000, 2);
+        let mut new = old.clone();
+        new.splice(100_000..100_000, b"inserted".iter().copied());
+        new[250_000] ^= 0xff;
+        new.extend_from_slice(b"appended");
+
+        let (rebuilt, stats) = round_trip(&old, &new, 4096);
+        assert_eq!(rebuilt, new);
+        assert!(stats.copied_blocks >= 70, "{:?}", stats);
+        assert!(stats.literal_bytes < 3 * 4096, "{:?}", stats);
+    }
+
+    #[test]
+    fn test_delta_edge_cases() {
+        let old = noise(10_000, 3);
+        assert_eq!(round_trip(&old, &[], 4096).0, Vec::<u8>::new());
+        assert_eq!(round_trip(&[], &old, 4096).0, old);
+        assert_eq!(round_trip(&old, &old[..100], 4096).0, &old[..100]);
+
+        // Unmatched data before and after the last block stays within one run
+        let new = noise(MAX_LITERAL_LEN + 10, 6);
+        assert_eq!(round_trip(&old, &new, 4096).0, new);
+    }
+
+    #[test]
+    fn test_apply_rejects_bad_delta() {
+        let old = noise(10_000, 4);
+        let signature = Signature::of(&old[..], 4096).unwrap();
+        let mut delta = Vec::new();
+        encode(&signature, &old[..], &mut delta).unwrap();
+
+        // A different base file yields a result that fails the checksum
+        let other = noise(10_000, 5);
+        let error = apply(Cursor::new(&other), &delta[..], io::sink()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let error = apply(Cursor::new(&old), &b"nope"[..], io::sink()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_block_size_for() {
+        assert_eq!(block_size_for(0), 4096);
+        assert_eq!(block_size_for(1 << 30), 32 * 1024);
+        assert_eq!(block_size_for(u64::MAX), 1024 * 1024);
+    }
+}
@@ -0,0 +1,275 @@
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use settings::Settings;
+use tokio::sync::watch;
+
+use super::file_server::AppState;
+use super::power;
+use super::transfers;
+use crate::config::{ConfigData, DeviceRuleConfig};
+use crate::integrations::{self, ServerEvent};
+use crate::models::{unix_now, FileInfo, FileList};
+
+/// How often per-device retention rules are applied
+const RETENTION_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Whether `ip` is the address, or inside the `address/prefix` network,
+/// given by `pattern`
+pub fn address_matches(pattern: &str, ip: IpAddr) -> bool {
+    let (address, prefix) = match pattern.trim().split_once('/') {
+        Some((address, prefix)) => match prefix.parse::<u32>() {
+            Ok(prefix) => (address, Some(prefix)),
+            Err(_) => return false,
+        },
+        None => (pattern.trim(), None),
+    };
+    // Clients on a dual-stack listener show up as IPv4-mapped IPv6
+    match (address.parse::<IpAddr>(), ip.to_canonical()) {
+        (Ok(IpAddr::V4(network)), IpAddr::V4(ip)) => {
+            let prefix = prefix.unwrap_or(32);
+            prefix <= 32
+                && u32::from(network).checked_shr(32 - prefix).unwrap_or(0)
+                    == u32::from(ip).checked_shr(32 - prefix).unwrap_or(0)
+        }
+        (Ok(IpAddr::V6(network)), IpAddr::V6(ip)) => {
+            let prefix = prefix.unwrap_or(128);
+            prefix <= 128
+                && u128::from(network).checked_shr(128 - prefix).unwrap_or(0)
+                    == u128::from(ip).checked_shr(128 - prefix).unwrap_or(0)
+        }
+        _ => false,
+    }
+}
+
+/// First rule whose addresses include `ip`
+pub fn rule_for(rules: &[DeviceRuleConfig], ip: IpAddr) -> Option<&DeviceRuleConfig> {
+    rules.iter().find(|rule| {
+        rule.addresses
+            .iter()
+            .any(|pattern| address_matches(pattern, ip))
+    })
+}
+
+fn current_rules() -> Vec<DeviceRuleConfig> {
+    ConfigData::instance()
+        .map(|instance| instance.lock().unwrap().devices.clone())
+        .unwrap_or_default()
+}
+
+/// Bytes `device` may still store, counting what it already stored in
+/// `file_list`, or `None` when its rule sets no quota
+pub fn remaining_quota(
+    rules: &[DeviceRuleConfig],
+    file_list: &FileList,
+    device: IpAddr,
+) -> Option<u64> {
+    let quota_mb = rule_for(rules, device).and_then(|rule| rule.quota_mb)?;
+    let used: u64 = file_list
+        .iter()
+        .filter(|file| file.device == Some(device))
+        .fold(0u64, |used, file| used.saturating_add(file.size));
+    Some(quota_mb.saturating_mul(1024 * 1024).saturating_sub(used))
+}
+
+/// Whether a file of `size` bytes from `device` fits in the quota of its
+/// rule, counting what the device already stored in `file_list`
+pub fn within_quota(
+    rules: &[DeviceRuleConfig],
+    file_list: &FileList,
+    device: IpAddr,
+    size: u64,
+) -> bool {
+    remaining_quota(rules, file_list, device).is_none_or(|remaining| size <= remaining)
+}
+
+/// Bytes `device` may still store on the share of `file_list` under the
+/// current rules
+pub fn quota_left(file_list: &Arc<Mutex<FileList>>, device: IpAddr) -> Option<u64> {
+    remaining_quota(&current_rules(), &file_list.lock().unwrap(), device)
+}
+
+/// Check a finished upload against the quota of the device that sent it
+pub fn check_quota(file_list: &Arc<Mutex<FileList>>, file_info: &FileInfo) -> bool {
+    match file_info.device {
+        Some(device) => within_quota(
+            &current_rules(),
+            &file_list.lock().unwrap(),
+            device,
+            file_info.size,
+        ),
+        None => true,
+    }
+}
+
+/// Remove files whose device rule keeps them for a shorter time than they
+/// have been stored
+fn expire_list(rules: &[DeviceRuleConfig], file_list: &mut FileList, now: u64) -> Vec<FileInfo> {
+    file_list.remove_where(|file| {
+        file.device
+            .and_then(|device| rule_for(rules, device))
+            .and_then(|rule| rule.retention_hours)
+            .is_some_and(|hours| file.added_at.saturating_add(hours.saturating_mul(3600)) <= now)
+    })
+}
+
+/// Apply retention rules to the main share and its rooms, deleting the
+/// expired files
+pub fn expire(state: &AppState) -> usize {
+    let rules = current_rules();
+    if rules.is_empty() {
+        return 0;
+    }
+    let now = unix_now();
+    let mut expired: Vec<(Option<String>, FileInfo)> =
+        expire_list(&rules, &mut state.file_list.lock().unwrap(), now)
+            .into_iter()
+            .map(|file| (None, file))
+            .collect();
+    let rooms: Vec<_> = state.rooms.lock().unwrap().values().cloned().collect();
+    for room in rooms {
+        let files = expire_list(&rules, &mut room.file_list.lock().unwrap(), now);
+        expired.extend(
+            files
+                .into_iter()
+                .map(|file| (Some(room.name.clone()), file)),
+        );
+    }
+
+    for (room, file_info) in &expired {
+        transfers::cancel_file(&file_info.id);
+        if let Err(e) = std::fs::remove_file(&file_info.path) {
+            log::warn!("Failed to remove file {:?}: {}", file_info.path, e);
+        }
+        log::info!(
+            "Removed '{}' after the retention time of its device",
+            file_info.name
+        );
+        integrations::emit(ServerEvent::FileDeleted {
+            id: file_info.id.clone(),
+            name: file_info.name.clone(),
+            room: room.clone(),
+        });
+    }
+    expired.len()
+}
+
+/// Apply retention rules periodically until the server shuts down
+pub async fn run(state: AppState, mut shutdown: watch::Receiver<bool>) {
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(RETENTION_INTERVAL) => {}
+            _ = shutdown.changed() => return,
+        }
+        if !power::wait_for_background_work("retention cleanup", &mut shutdown).await {
+            return;
+        }
+        let state = state.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || expire(&state)).await {
+            log::error!("Retention cleanup failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn guests() -> Vec<DeviceRuleConfig> {
+        vec![DeviceRuleConfig {
+            name: "guests".to_string(),
+            addresses: vec!["192.168.50.0/24".to_string(), "fd00::/8".to_string()],
+            quota_mb: Some(1),
+            retention_hours: Some(24),
+        }]
+    }
+
+    fn upload(id: &str, device: &str, size: u64, added_at: u64) -> FileInfo {
+        FileInfo {
+            id: id.to_string(),
+            added_at,
+            device: Some(device.parse().unwrap()),
+            ..FileInfo::new(id.to_string(), PathBuf::from(id), size)
+        }
+    }
+
+    #[test]
+    fn test_address_matches() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(address_matches("192.168.50.0/24", ip("192.168.50.7")));
+        assert!(address_matches(
+            "192.168.50.0/24",
+            ip("::ffff:192.168.50.7")
+        ));
+        assert!(!address_matches("192.168.50.0/24", ip("192.168.51.7")));
+        assert!(address_matches("10.0.0.5", ip("10.0.0.5")));
+        assert!(address_matches("0.0.0.0/0", ip("8.8.8.8")));
+        assert!(address_matches("fd00::/8", ip("fd12::1")));
+        assert!(!address_matches("fd00::/8", ip("192.168.50.7")));
+        assert!(!address_matches("192.168.50.0/33", ip("192.168.50.7")));
+        assert!(!address_matches("guest-laptop", ip("192.168.50.7")));
+    }
+
+    #[test]
+    fn test_quota_per_device() {
+        let rules = guests();
+        let mut list = FileList::new();
+        list.add_file(upload("a", "192.168.50.7", 600 * 1024, 0));
+        list.add_file(upload("b", "192.168.1.2", 5 * 1024 * 1024, 0));
+
+        let guest = "192.168.50.7".parse().unwrap();
+        assert!(within_quota(&rules, &list, guest, 400 * 1024));
+        assert!(!within_quota(&rules, &list, guest, 500 * 1024));
+        assert_eq!(remaining_quota(&rules, &list, guest), Some(424 * 1024));
+        // Another guest has its own quota, other devices have none
+        assert!(within_quota(
+            &rules,
+            &list,
+            "192.168.50.8".parse().unwrap(),
+            1024 * 1024
+        ));
+        assert!(within_quota(
+            &rules,
+            &list,
+            "192.168.1.2".parse().unwrap(),
+            u64::MAX / 2
+        ));
+        assert_eq!(
+            remaining_quota(&rules, &list, "192.168.1.2".parse().unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_retention() {
+        let rules = guests();
+        let day = 24 * 3600;
+        let mut list = FileList::new();
+        list.add_file(upload("old-guest", "192.168.50.7", 1, 1000));
+        list.add_file(upload("new-guest", "192.168.50.7", 1, 1000 + day));
+        list.add_file(upload("old-laptop", "192.168.1.2", 1, 1000));
+
+        let expired = expire_list(&rules, &mut list, 1000 + day);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, "old-guest");
+        assert_eq!(list.len(), 2);
+    }
+
+    #[test]
+    fn test_huge_limits() {
+        let rules = vec![DeviceRuleConfig {
+            quota_mb: Some(u64::MAX),
+            retention_hours: Some(u64::MAX),
+            ..guests().remove(0)
+        }];
+        let mut list = FileList::new();
+        list.add_file(upload("a", "192.168.50.7", u64::MAX, 1000));
+        list.add_file(upload("b", "192.168.50.7", 1, 1000));
+
+        let guest = "192.168.50.7".parse().unwrap();
+        assert_eq!(remaining_quota(&rules, &list, guest), Some(0));
+        assert!(expire_list(&rules, &mut list, u64::MAX - 1).is_empty());
+    }
+}
@@ -0,0 +1,333 @@
+use std::io::Write;
+use std::net::{Ipv4Addr, TcpListener, UdpSocket};
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+
+use super::firewall::{self, FirewallStatus};
+use super::tls::{grouped, Certificate};
+use crate::config::{ConfigData, ServerConfig};
+
+/// mDNS multicast group used for `.local` service discovery
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+
+/// Config fields left out of exported reports in addition to schema secrets
+pub const SECRET_NAMES: &[&str] = &["secret", "password", "pin", "username"];
+
+/// Directory the application writes its log files to
+pub const LOG_DIR: &str = "logs";
+
+/// Directory diagnostics bundles are exported to
+pub const BUNDLE_DIR: &str = "diagnostics";
+
+/// Number of log lines included in a diagnostics bundle
+const BUNDLE_LOG_LINES: usize = 300;
+
+/// Outcome of a single self-check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    Warning,
+    Failed,
+    Skipped,
+}
+
+impl CheckStatus {
+    fn label(self) -> &'static str {
+        match self {
+            CheckStatus::Ok => "OK",
+            CheckStatus::Warning => "WARNING",
+            CheckStatus::Failed => "FAILED",
+            CheckStatus::Skipped => "SKIPPED",
+        }
+    }
+}
+
+/// Result of one self-check with a short explanation
+#[derive(Debug, Clone)]
+pub struct DiagnosticCheck {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl DiagnosticCheck {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Results of all startup self-checks
+#[derive(Debug, Clone)]
+pub struct DiagnosticsReport {
+    pub version: &'static str,
+    pub checks: Vec<DiagnosticCheck>,
+}
+
+impl DiagnosticsReport {
+    /// Whether any check found a problem that needs attention
+    pub fn has_failures(&self) -> bool {
+        self.checks.iter().any(|c| c.status == CheckStatus::Failed)
+    }
+
+    /// Render the report as human readable text for the UI
+    pub fn summary(&self) -> String {
+        self.checks
+            .iter()
+            .map(|check| {
+                format!(
+                    "{}: {} - {}",
+                    check.name,
+                    check.status.label(),
+                    check.detail
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Check that files can be created in the storage directory
+pub fn check_storage(storage_dir: &Path) -> DiagnosticCheck {
+    const NAME: &str = "Storage";
+    if let Err(e) = std::fs::create_dir_all(storage_dir) {
+        return DiagnosticCheck::new(
+            NAME,
+            CheckStatus::Failed,
+            format!("Cannot create {}: {}", storage_dir.display(), e),
+        );
+    }
+
+    let probe = storage_dir.join(".justrans-write-test");
+    let result = std::fs::File::create(&probe).and_then(|mut file| file.write_all(b"ok"));
+    let _ = std::fs::remove_file(&probe);
+    match result {
+        Ok(()) => DiagnosticCheck::new(
+            NAME,
+            CheckStatus::Ok,
+            format!("{} is writable", storage_dir.display()),
+        ),
+        Err(e) => DiagnosticCheck::new(
+            NAME,
+            CheckStatus::Failed,
+            format!("Cannot write to {}: {}", storage_dir.display(), e),
+        ),
+    }
+}
+
+/// Check that the server port is free, unless our own server holds it
+pub fn check_port(port: u16, server_running: bool) -> DiagnosticCheck {
+    const NAME: &str = "Port";
+    if server_running {
+        return DiagnosticCheck::new(
+            NAME,
+            CheckStatus::Ok,
+            format!("Port {} is in use by the running server", port),
+        );
+    }
+    match TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)) {
+        Ok(_) => DiagnosticCheck::new(NAME, CheckStatus::Ok, format!("Port {} is free", port)),
+        Err(e) => DiagnosticCheck::new(
+            NAME,
+            CheckStatus::Failed,
+            format!("Port {} is not available: {}", port, e),
+        ),
+    }
+}
+
+/// Check whether the OS firewall lets clients reach the server port
+pub fn check_firewall(port: u16) -> DiagnosticCheck {
+    const NAME: &str = "Firewall";
+    let check = firewall::check_port(port);
+    // Only a warning: the check cannot tell whether a blocking rule applies
+    let status = match check.status {
+        FirewallStatus::Allowed => CheckStatus::Ok,
+        FirewallStatus::Blocked | FirewallStatus::Unknown => CheckStatus::Warning,
+    };
+    DiagnosticCheck::new(NAME, status, check.status_message())
+}
+
+/// Check that this machine can join the mDNS multicast group
+pub fn check_mdns() -> DiagnosticCheck {
+    const NAME: &str = "mDNS";
+    let result = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| socket.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED));
+    match result {
+        Ok(()) => DiagnosticCheck::new(NAME, CheckStatus::Ok, "Multicast is available"),
+        Err(e) => DiagnosticCheck::new(
+            NAME,
+            CheckStatus::Warning,
+            format!("Cannot join the mDNS multicast group: {}", e),
+        ),
+    }
+}
+
+/// Whether the configured certificate can be served
+pub fn check_tls(config: &ServerConfig) -> DiagnosticCheck {
+    const NAME: &str = "TLS certificate";
+    let Some(tls) = config.tls() else {
+        return DiagnosticCheck::new(
+            NAME,
+            CheckStatus::Skipped,
+            "HTTPS is not configured, the portal is served over HTTP",
+        );
+    };
+    match Certificate::load(tls, config.http2) {
+        Ok(certificate) => DiagnosticCheck::new(
+            NAME,
+            CheckStatus::Ok,
+            format!("SHA-256 fingerprint {}", grouped(certificate.fingerprint())),
+        ),
+        Err(e) => DiagnosticCheck::new(NAME, CheckStatus::Failed, format!("{:#}", e)),
+    }
+}
+
+/// Run all self-checks for the default server
+pub fn run_self_check(config: &ConfigData, server_running: bool) -> DiagnosticsReport {
+    let port = config.server.port;
+    DiagnosticsReport {
+        version: env!("CARGO_PKG_VERSION"),
+        checks: vec![
+            check_storage(Path::new(&config.storage.storage_dir)),
+            check_port(port, server_running),
+            check_firewall(port),
+            check_mdns(),
+            check_tls(&config.server),
+        ],
+    }
+}
+
+/// Mask IPv4 addresses other than loopback and wildcard in `text`
+pub fn anonymize(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut token = String::new();
+    for c in text.chars().chain(std::iter::once(' ')) {
+        if c.is_ascii_digit() || c == '.' {
+            token.push(c);
+            continue;
+        }
+        result.push_str(&mask_address(&token));
+        token.clear();
+        result.push(c);
+    }
+    result.pop();
+    result
+}
+
+fn mask_address(token: &str) -> String {
+    let trimmed = token.trim_end_matches('.');
+    match trimmed.parse::<Ipv4Addr>() {
+        Ok(ip) if !ip.is_loopback() && !ip.is_unspecified() => {
+            format!("{}.x.x.x{}", ip.octets()[0], &token[trimmed.len()..])
+        }
+        _ => token.to_string(),
+    }
+}
+
+/// Most recently written log file in `dir`
+fn latest_log(dir: &Path) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "log"))
+        .max_by_key(|entry| entry.metadata().and_then(|m| m.modified()).ok())
+        .map(|entry| entry.path())
+}
+
+/// Last `count` lines of a file
+fn tail(path: &Path, count: usize) -> std::io::Result<String> {
+    let contents = std::fs::read_to_string(path)?;
+    let lines: Vec<&str> = contents.lines().collect();
+    Ok(lines[lines.len().saturating_sub(count)..].join("\n"))
+}
+
+/// Write a bundle with the report, the config without secrets and the
+/// recent log to `dir`, with IP addresses masked, and return its path
+pub fn export_bundle(
+    report: &DiagnosticsReport,
+    config: &ConfigData,
+    log_dir: &Path,
+    dir: &Path,
+) -> anyhow::Result<PathBuf> {
+    let config_yaml = serde_yaml::to_string(&settings::schema::redacted(config, SECRET_NAMES)?)?;
+    let log = match latest_log(log_dir) {
+        Some(path) => tail(&path, BUNDLE_LOG_LINES)?,
+        None => "No log file found".to_string(),
+    };
+
+    let bundle = format!(
+        "JusTrans {} diagnostics\nOS: {} ({})\n\n== Self-check ==\n{}\n\n== Config ==\n{}\n== Log ==\n{}\n",
+        report.version,
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        report.summary(),
+        config_yaml,
+        log
+    );
+
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!(
+        "justrans-diagnostics-{}.txt",
+        Local::now().format("%Y%m%d_%H%M%S")
+    ));
+    std::fs::write(&path, anonymize(&bundle))?;
+    log::info!("Wrote diagnostics bundle to {:?}", path);
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_anonymize() {
+        assert_eq!(
+            anonymize("Listening on 192.168.1.20:8080, ping 127.0.0.1."),
+            "Listening on 192.x.x.x:8080, ping 127.0.0.1."
+        );
+        assert_eq!(
+            anonymize("version 0.1.0 at 10.0.0.5."),
+            "version 0.1.0 at 10.x.x.x."
+        );
+    }
+
+    #[test]
+    fn test_export_bundle_hides_secrets() {
+        let dir = tempdir().unwrap();
+        let log_dir = dir.path().join("logs");
+        std::fs::create_dir_all(&log_dir).unwrap();
+        std::fs::write(
+            log_dir.join("justrans.log"),
+            "client 192.168.0.7 connected\n",
+        )
+        .unwrap();
+
+        let mut config = ConfigData::default();
+        config.storage.storage_dir = dir.path().join("uploads").display().to_string();
+        config
+            .integrations
+            .webhooks
+            .push(crate::config::WebhookConfig {
+                url: "https://example.com/hook".to_string(),
+                secret: "hunter2".to_string(),
+                events: Vec::new(),
+            });
+
+        let report = DiagnosticsReport {
+            version: "test",
+            checks: vec![check_storage(Path::new(&config.storage.storage_dir))],
+        };
+        assert!(!report.has_failures());
+
+        let path = export_bundle(&report, &config, &log_dir, dir.path()).unwrap();
+        let bundle = std::fs::read_to_string(path).unwrap();
+        assert!(bundle.contains("Storage: OK"));
+        assert!(bundle.contains("client 192.x.x.x connected"));
+        assert!(!bundle.contains("hunter2"));
+    }
+}
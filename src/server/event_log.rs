@@ -0,0 +1,215 @@
+//! Recent server events for hosts watching the share from the portal
+//! (`GET /api/v1/events`, host role only): connections, received and
+//! deleted files, transfers and errors. They are streamed as server-sent
+//! events, the recent ones first and then each as it happens.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::get;
+use axum::Router;
+use futures_util::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+
+use super::auth::{self, Role};
+use super::file_server::AppState;
+use crate::integrations::ServerEvent;
+use crate::models::unix_now;
+
+/// Events kept for hosts who open the portal later
+pub const EVENT_LOG_SIZE: usize = 200;
+
+/// An event with the time it happened
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggedEvent {
+    /// Unix time of the event
+    #[serde(flatten, with = "crate::models::time::at")]
+    pub at: u64,
+    #[serde(flatten)]
+    pub event: ServerEvent,
+}
+
+/// Recent events of a server and the hosts following them
+#[derive(Clone, Default)]
+pub struct EventLog {
+    recent: Arc<Mutex<VecDeque<LoggedEvent>>>,
+    /// Sends events as they happen while the server runs
+    live: Arc<Mutex<Option<broadcast::Sender<LoggedEvent>>>>,
+}
+
+impl EventLog {
+    /// A log starting with `events` of earlier runs, oldest first
+    pub fn with_history(mut events: Vec<LoggedEvent>) -> Self {
+        events.drain(..events.len().saturating_sub(EVENT_LOG_SIZE));
+        Self {
+            recent: Arc::new(Mutex::new(events.into())),
+            ..Self::default()
+        }
+    }
+
+    /// Add `event`, unless it is progress too frequent to be worth reading
+    pub fn record(&self, event: ServerEvent, now: u64) {
+        if matches!(event, ServerEvent::TransferProgress { .. }) {
+            return;
+        }
+        let event = LoggedEvent { at: now, event };
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == EVENT_LOG_SIZE {
+            recent.pop_front();
+        }
+        recent.push_back(event.clone());
+        if let Some(live) = self.live.lock().unwrap().as_ref() {
+            // No hosts following is not an error
+            let _ = live.send(event);
+        }
+    }
+
+    /// The events so far and, while the server runs, a receiver of the
+    /// ones to come
+    pub fn follow(&self) -> (Vec<LoggedEvent>, Option<broadcast::Receiver<LoggedEvent>>) {
+        let recent = self.recent.lock().unwrap();
+        let live = self
+            .live
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|live| live.subscribe());
+        (recent.iter().cloned().collect(), live)
+    }
+}
+
+/// Record the events from `events` until `shutdown` changes, which also
+/// ends the streams of the hosts following them
+pub async fn run(
+    log: EventLog,
+    mut events: broadcast::Receiver<ServerEvent>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    *log.live.lock().unwrap() = Some(broadcast::channel(64).0);
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => log.record(event, unix_now()),
+                Err(RecvError::Lagged(skipped)) => {
+                    log::debug!("Event log skipped {} events", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = shutdown.changed() => break,
+        }
+    }
+    *log.live.lock().unwrap() = None;
+}
+
+#[axum::debug_handler]
+async fn stream_events(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, StatusCode> {
+    let role = auth::signed_in_user(&state, &headers).map(|user| user.role);
+    if role != Some(Role::Host) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let (recent, live) = state.event_log.follow();
+    let live = stream::unfold(live, |mut live| async move {
+        loop {
+            match live.as_mut()?.recv().await {
+                Ok(event) => return Some((event, live)),
+                // A slow host misses some events rather than the stream
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    let events = stream::iter(recent)
+        .chain(live)
+        .map(|event| Event::default().json_data(event));
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Event log requests, nested under the versioned API prefix
+pub fn api_router() -> Router<AppState> {
+    Router::new().route("/events", get(stream_events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deleted(name: &str) -> ServerEvent {
+        ServerEvent::FileDeleted {
+            id: name.to_string(),
+            name: name.to_string(),
+            room: None,
+        }
+    }
+
+    #[test]
+    fn test_recent_events() {
+        let log = EventLog::default();
+        log.record(ServerEvent::TransferProgress { id: 1, bytes: 10 }, 1000);
+        for i in 0..EVENT_LOG_SIZE + 1 {
+            log.record(deleted(&i.to_string()), 1000 + i as u64);
+        }
+
+        let (recent, live) = log.follow();
+        assert!(live.is_none());
+        assert_eq!(recent.len(), EVENT_LOG_SIZE);
+        assert_eq!(recent[0].at, 1001);
+        assert!(matches!(&recent[0].event, ServerEvent::FileDeleted { name, .. } if name == "1"));
+
+        let json = serde_json::to_value(&recent[0]).unwrap();
+        assert_eq!(json["event"], "file.deleted");
+        assert_eq!(json["at"], 1001);
+        assert_eq!(json["at_utc"], "1970-01-01T00:16:41Z");
+    }
+
+    #[test]
+    fn test_history() {
+        let history = (0..EVENT_LOG_SIZE as u64 + 1)
+            .map(|at| LoggedEvent {
+                at,
+                event: deleted("old"),
+            })
+            .collect();
+        let log = EventLog::with_history(history);
+        log.record(deleted("new"), 5000);
+
+        let (recent, _) = log.follow();
+        assert_eq!(recent.len(), EVENT_LOG_SIZE);
+        assert_eq!(recent[0].at, 2);
+        assert_eq!(recent[EVENT_LOG_SIZE - 1].at, 5000);
+    }
+
+    #[tokio::test]
+    async fn test_follow_while_running() {
+        let log = EventLog::default();
+        let (events, receiver) = broadcast::channel(8);
+        let (shutdown, stopped) = watch::channel(false);
+        let task = tokio::spawn(run(log.clone(), receiver, stopped));
+        while log.live.lock().unwrap().is_none() {
+            tokio::task::yield_now().await;
+        }
+
+        let (recent, live) = log.follow();
+        assert!(recent.is_empty());
+        let mut live = live.unwrap();
+        events.send(deleted("a")).unwrap();
+        assert!(matches!(
+            live.recv().await.unwrap().event,
+            ServerEvent::FileDeleted { .. }
+        ));
+
+        // Stopping the server ends the hosts' streams
+        shutdown.send(true).unwrap();
+        task.await.unwrap();
+        assert!(matches!(live.recv().await, Err(RecvError::Closed)));
+        assert_eq!(log.follow().0.len(), 1);
+    }
+}
@@ -1,15 +1,17 @@
-use std::io::Write;
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use tokio::sync::oneshot;
+use tokio::sync::{mpsc, watch, OwnedMutexGuard};
 
-use axum::extract::Multipart;
+use axum::extract::{Form, Multipart, Query, Request};
+use axum::middleware::{self, Next};
 use axum::response::AppendHeaders;
 use axum::{
     extract::{Path, State},
-    http::{header, StatusCode},
-    response::{Html, IntoResponse, Response},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
@@ -17,43 +19,328 @@ use local_ip_address::local_ip;
 use serde::{Deserialize, Serialize};
 use settings::Settings;
 use tokio::fs::File;
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tower_http::cors::{Any, CorsLayer};
-use tower_http::services::ServeDir;
 use tower_http::trace::TraceLayer;
 
-use crate::config::ConfigData;
-use crate::models::{FileInfo, FileList};
+use super::api;
+use super::archive;
+use super::assemble;
+use super::assets;
+use super::auth::basic::{self, BasicAuth};
+use super::auth::qr_token::{self, QrTokens};
+use super::auth::{self, Auth, Role};
+use super::chunks;
+use super::client_ip::ClientIp;
+use super::conditional::{self, Validators};
+use super::conflicts::{self, ConflictQueue, ConflictResolution, PendingConflict};
+use super::delta;
+use super::devices;
+use super::event_log::{self, EventLog};
+use super::file_types::FileTypes;
+use super::form_upload;
+use super::headers::{add_security_headers, SecurityHeaders};
+use super::http::{self, HttpTuning};
+use super::inhibit::TransferTracker;
+use super::live::{self, LiveUpdates};
+use super::memory;
+use super::metrics;
+use super::migrate;
+use super::network;
+use super::onetime::{self, OneTimeLinks};
+use super::organize;
+use super::pairing::{self, Pairing};
+use super::permissions;
+use super::preflight;
+use super::protected::{self, Unlock};
+use super::quarantine;
+use super::ranges;
+use super::records;
+use super::remote::{self, RemoteCommand};
+use super::rooms::{self, room_storage_dir, PinFailures};
+use super::sanitize::{content_disposition, sanitize_name};
+use super::scratch;
+use super::scrub;
+use super::segments::{self, Segment, SegmentLimits};
+use super::shared_paths;
+use super::snippets;
+use super::speedtest;
+use super::stats::{ServerStats, StatsSnapshot};
+use super::tls::{self, Certificate};
+use super::transfers::{self, Tracked, Transfer, TransferKind};
+use super::upload_ids::{self, UploadIds};
+use super::upload_progress::{self, UploadProgressMap};
+use super::upload_sessions::{self, UploadSessions};
+use crate::backup::snapshots;
+use crate::config::{ConfigData, LinkPolicy, SnapshotConfig, TlsConfig};
+use crate::integrations::{self, ServerEvent};
+use crate::metadata::{self, Change, MetadataStore, StoredFile};
+use crate::models::{
+    names, unix_now, FileInfo, FileList, GroupedFileList, NameMatch, Room, Snippet, StorageUsage,
+};
+
+/// Times a restart tries to get the listening ports back
+const RESTART_ATTEMPTS: u32 = 20;
+
+const RESTART_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Bytes read from disk at a time for a download; its memory use stays at
+/// this however large the file
+pub(crate) const DOWNLOAD_CHUNK_LEN: usize = 64 * 1024;
 
 #[derive(Clone)]
 pub struct AppState {
     pub file_list: Arc<Mutex<FileList>>,
     pub temp_dir: PathBuf,
+    /// Where uploads are written until complete, `storage.temp_dir` or the
+    /// incoming folder of the storage directory
+    pub scratch_dir: PathBuf,
+    /// Whether changes to the share are refused; hosts can turn it on and
+    /// off from the portal until the next start
+    pub read_only: Arc<AtomicBool>,
+    /// PIN required for the main portal, set by a network overlay
+    pub pin: Option<String>,
+    /// Portal sign-in, when a provider is configured
+    pub auth: Option<Auth>,
+    /// Username and password asked for every request, when enabled
+    pub basic_auth: Option<BasicAuth>,
+    /// Rotating access token shown in the QR code, when enabled
+    pub qr_tokens: Option<QrTokens>,
+    pub security_headers: SecurityHeaders,
+    /// Kinds of files accepted for upload
+    pub file_types: FileTypes,
+    /// Which links among files shared in place are served
+    pub shared_links: LinkPolicy,
+    /// Limits segment uploads are checked against
+    pub segment_limits: SegmentLimits,
+    pub rooms: Arc<Mutex<HashMap<String, Room>>>,
+    /// Wrong room PINs of recent clients
+    pub room_pin_failures: PinFailures,
+    pub conflicts: ConflictQueue,
+    pub snippets: Arc<Mutex<Vec<Snippet>>>,
+    /// Whether pages linked in shared text are fetched for a preview
+    pub link_previews: bool,
+    pub onetime_links: OneTimeLinks,
+    /// IDs issued for segment uploads through `/upload`
+    pub upload_ids: UploadIds,
+    /// Progress of segment uploads, for clients following it
+    pub upload_progress: UploadProgressMap,
+    /// Uploads in progress through the v2 upload protocol
+    pub upload_sessions: UploadSessions,
+    /// Uploads and downloads in flight, which keep the computer awake
+    pub transfers: TransferTracker,
+    /// Reverse proxies whose forwarding headers name the real client
+    pub trusted_proxies: Vec<String>,
+    /// Certificate the portal is served with over HTTPS, if any
+    pub certificate: Option<Arc<Certificate>>,
+    /// Connection details handed out in pairing bundles
+    pub pairing: Pairing,
+    /// Addresses the server is reached at
+    pub server_info: Arc<Mutex<ServerInfo>>,
+    /// Uptime, connected devices and traffic
+    pub stats: ServerStats,
+    /// Recent events, for hosts watching from the portal
+    pub event_log: EventLog,
+    /// Clients following the file lists over a WebSocket
+    pub live: LiveUpdates,
+    /// Records of files, devices, events and one-time links
+    pub metadata: Arc<dyn MetadataStore>,
+    /// Commands from hosts on the portal for the app, on the default server
+    pub remote: Option<mpsc::UnboundedSender<RemoteCommand>>,
+}
+
+impl AppState {
+    pub fn read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    /// Current server info, with the counters as of now
+    pub fn server_info(&self) -> ServerInfo {
+        let mut info = self.server_info.lock().unwrap().clone();
+        info.stats = self.stats.snapshot(unix_now(), self.transfers.active());
+        // The buffers are shared by every server of the app
+        info.stats.memory = memory::usage();
+        info
+    }
+
+    /// Scheme of the portal's URLs
+    pub fn scheme(&self) -> &'static str {
+        tls::scheme(self.certificate.is_some())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerInfo {
     pub url: String,
+    /// Every URL the server can be reached at, starting with `url`
+    pub urls: Vec<String>,
     pub ip: String,
     pub port: u16,
     pub running: bool,
+    /// Name of the network overlay applied at start, if any
+    pub network: Option<String>,
+    #[serde(flatten)]
+    pub stats: StatsSnapshot,
+}
+
+/// Listener settings resolved from the config for a single server instance
+struct ServerSettings {
+    port: u16,
+    bind_addresses: Vec<IpAddr>,
+    http: HttpTuning,
+    storage_dir: PathBuf,
+    scratch_dir: PathBuf,
+    upload_chunk_size_mb: u64,
+    delta_uploads: bool,
+    scrub_interval_hours: u64,
+    /// Only the default server takes snapshots of the records
+    snapshots: Option<SnapshotConfig>,
+    read_only: bool,
+    pin: Option<String>,
+    network: Option<String>,
+    tls: Option<TlsConfig>,
+    /// Only the default server serves the certificate trust page
+    trust_page_port: Option<u16>,
+}
+
+impl ServerSettings {
+    /// Resolve settings for the default server or for a named profile. The
+    /// default server picks up the overlay for the current Wi-Fi network.
+    fn resolve(
+        config: &ConfigData,
+        profile: Option<&str>,
+        ssid: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        match profile {
+            None => {
+                let mut settings = Self {
+                    port: config.server.port,
+                    bind_addresses: parse_bind_addresses(&config.server.bind_addresses)?,
+                    http: HttpTuning::from_config(&config.server),
+                    storage_dir: PathBuf::from(&config.storage.storage_dir),
+                    scratch_dir: PathBuf::new(),
+                    upload_chunk_size_mb: config.server.upload_chunk_size_mb,
+                    delta_uploads: config.server.delta_uploads,
+                    scrub_interval_hours: config.storage.scrub_interval_hours,
+                    snapshots: Some(config.storage.snapshots.clone()),
+                    read_only: false,
+                    pin: None,
+                    network: None,
+                    tls: config.server.tls().cloned(),
+                    trust_page_port: config
+                        .server
+                        .tls()
+                        .map(|tls| tls.trust_page_port)
+                        .filter(|port| *port != 0),
+                };
+                if let Some(overlay) = ssid.and_then(|ssid| config.network_overlay(ssid)) {
+                    if let Some(port) = overlay.port {
+                        settings.port = port;
+                    }
+                    if let Some(storage_dir) = &overlay.storage_dir {
+                        settings.storage_dir = PathBuf::from(storage_dir);
+                    }
+                    settings.read_only = overlay.read_only.unwrap_or(false);
+                    settings.pin = overlay.pin.clone().filter(|pin| !pin.is_empty());
+                    settings.network = Some(overlay.name.clone());
+                }
+                settings.scratch_dir =
+                    scratch::dir(&config.storage.temp_dir, &settings.storage_dir);
+                Ok(settings)
+            }
+            Some(name) => {
+                let profile = config
+                    .profile(name)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown server profile: {}", name))?;
+                Ok(Self {
+                    port: profile.port,
+                    bind_addresses: parse_bind_addresses(&config.server.bind_addresses)?,
+                    http: HttpTuning::from_config(&config.server),
+                    storage_dir: PathBuf::from(&profile.storage_dir),
+                    scratch_dir: scratch::dir(
+                        &config.storage.temp_dir,
+                        std::path::Path::new(&profile.storage_dir),
+                    ),
+                    upload_chunk_size_mb: config.server.upload_chunk_size_mb,
+                    delta_uploads: config.server.delta_uploads,
+                    scrub_interval_hours: config.storage.scrub_interval_hours,
+                    snapshots: None,
+                    read_only: profile.read_only,
+                    pin: profile.pin.clone().filter(|pin| !pin.is_empty()),
+                    network: None,
+                    tls: config.server.tls().cloned(),
+                    trust_page_port: None,
+                })
+            }
+        }
+    }
+}
+
+/// Addresses from the config to listen on; all interfaces when none are set
+fn parse_bind_addresses(addresses: &[String]) -> anyhow::Result<Vec<IpAddr>> {
+    if addresses.is_empty() {
+        return Ok(vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED)]);
+    }
+    addresses
+        .iter()
+        .map(|address| {
+            address
+                .trim()
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid bind address: {}", address))
+        })
+        .collect()
+}
+
+/// URLs clients can use for listeners on `addresses`. A wildcard address is
+/// reached through the machine's LAN address.
+fn listener_urls(scheme: &str, addresses: &[IpAddr], port: u16, local_ip: IpAddr) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    for address in addresses {
+        let ip = if address.is_unspecified() {
+            local_ip
+        } else {
+            *address
+        };
+        let url = format!("{}://{}", scheme, SocketAddr::new(ip, port));
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    urls
 }
 
 pub struct FileServer {
+    profile: Option<String>,
     state: AppState,
-    server_info: Arc<Mutex<ServerInfo>>,
-    shutdown_tx: Option<oneshot::Sender<()>>,
+    shutdown_tx: Option<watch::Sender<bool>>,
+    /// Commands from hosts on the portal, until the app takes them
+    remote_commands: Option<mpsc::UnboundedReceiver<RemoteCommand>>,
+    /// Held across each start or stop so they don't interleave
+    lifecycle: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl FileServer {
     pub fn new() -> anyhow::Result<Self> {
+        Self::build(None)
+    }
+
+    /// Create a server for one of the named profiles in the config
+    pub fn for_profile(name: &str) -> anyhow::Result<Self> {
+        Self::build(Some(name.to_string()))
+    }
+
+    fn build(profile: Option<String>) -> anyhow::Result<Self> {
         // Get config from singleton instance
         let instance = ConfigData::instance()?;
         let config = instance.lock().unwrap();
+        let settings = ServerSettings::resolve(&config, profile.as_deref(), None)?;
 
-        // Create temp directory for uploaded files
-        let storage_dir = PathBuf::from(&config.storage.storage_dir);
-        std::fs::create_dir_all(&storage_dir)?;
+        // Create temp directory for uploaded files; uploads cut off by the
+        // last exit can't be resumed
+        std::fs::create_dir_all(&settings.storage_dir)?;
+        scratch::discard_incoming(&settings.storage_dir);
+        std::fs::create_dir_all(&settings.scratch_dir)?;
 
         // Get local IP address
         let ip = match local_ip() {
@@ -62,77 +349,571 @@ impl FileServer {
         };
 
         // Get port from settings
-        let port = config.server.port;
+        let port = settings.port;
 
+        let url = format!(
+            "{}://{}:{}",
+            tls::scheme(config.server.tls().is_some()),
+            ip,
+            port
+        );
         let server_info = ServerInfo {
-            url: format!("http://{}:{}", ip, port),
+            urls: vec![url.clone()],
+            url,
             ip,
             port,
             running: false,
+            network: None,
+            stats: StatsSnapshot::default(),
         };
 
+        let pairing = Pairing::new(pairing::server_name(profile.as_deref()));
+        let metadata = metadata::open(config.storage.metadata, profile.as_deref());
+        let history = metadata
+            .history(event_log::EVENT_LOG_SIZE)
+            .unwrap_or_else(|e| {
+                log::error!("Failed to read the event history: {}", e);
+                Vec::new()
+            });
+        let mut file_list = records::restore_files(metadata.as_ref());
+        match migrate::migrate(&settings.storage_dir, metadata.as_ref(), unix_now()) {
+            Ok(migration) => {
+                if !migration.is_empty() {
+                    log::warn!("Migrated {:?}: {}", settings.storage_dir, migration);
+                }
+                for file in migration.recovered {
+                    file_list.add_file(file);
+                }
+            }
+            Err(e) => log::error!("Failed to migrate {:?}: {}", settings.storage_dir, e),
+        }
+        // Only the default server is the app's to control
+        let (remote, remote_commands) = if profile.is_none() {
+            let (tx, rx) = mpsc::unbounded_channel();
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        };
         Ok(Self {
+            profile,
             state: AppState {
-                file_list: Arc::new(Mutex::new(FileList::new())),
-                temp_dir: storage_dir,
+                file_list: Arc::new(Mutex::new(file_list)),
+                temp_dir: settings.storage_dir,
+                scratch_dir: settings.scratch_dir,
+                read_only: Arc::new(AtomicBool::new(settings.read_only)),
+                pin: settings.pin,
+                auth: Auth::from_config(&config.security),
+                basic_auth: BasicAuth::from_config(config.security.basic_auth.as_ref()),
+                qr_tokens: QrTokens::from_config(config.security.qr_token.as_ref()),
+                security_headers: SecurityHeaders::from_config(&config.security.headers),
+                file_types: FileTypes::from_config(&config.security),
+                shared_links: config.security.shared_links,
+                segment_limits: SegmentLimits::from_config(&config.server),
+                rooms: Arc::new(Mutex::new(HashMap::new())),
+                room_pin_failures: PinFailures::default(),
+                conflicts: ConflictQueue::default(),
+                snippets: Arc::new(Mutex::new(Vec::new())),
+                link_previews: config.server.link_previews,
+                onetime_links: OneTimeLinks::restore(metadata.clone(), unix_now()),
+                upload_ids: UploadIds::default(),
+                upload_progress: UploadProgressMap::default(),
+                upload_sessions: UploadSessions::default(),
+                transfers: TransferTracker::default(),
+                trusted_proxies: config.server.trusted_proxies.clone(),
+                certificate: None,
+                pairing,
+                server_info: Arc::new(Mutex::new(server_info)),
+                stats: ServerStats::default(),
+                event_log: EventLog::with_history(history),
+                live: LiveUpdates::default(),
+                metadata,
+                remote,
             },
-            server_info: Arc::new(Mutex::new(server_info)),
             shutdown_tx: None,
+            remote_commands,
+            lifecycle: Arc::default(),
         })
     }
 
+    /// Address to show in the QR code for `url`, with the current access
+    /// token when QR tokens are enabled
+    pub fn qr_url(&self, url: &str, now: u64) -> String {
+        match &self.state.qr_tokens {
+            Some(qr_tokens) => format!(
+                "{}/?token={}",
+                url.trim_end_matches('/'),
+                qr_tokens.token_at(now)
+            ),
+            None => url.to_string(),
+        }
+    }
+
+    /// Rotation period of the QR token at `now`, which changes whenever the
+    /// QR code has to be drawn again
+    pub fn qr_period(&self, now: u64) -> Option<u64> {
+        self.state
+            .qr_tokens
+            .as_ref()
+            .map(|qr_tokens| qr_tokens.period(now))
+    }
+
     pub fn get_server_info(&self) -> ServerInfo {
-        let info = self.server_info.lock().unwrap();
-        ServerInfo {
-            url: info.url.clone(),
-            ip: info.ip.clone(),
-            port: info.port,
-            running: info.running,
+        self.state.server_info()
+    }
+
+    /// Where the records of this server are kept
+    pub fn metadata(&self) -> Arc<dyn MetadataStore> {
+        self.state.metadata.clone()
+    }
+
+    /// Files currently offered for download on the main share
+    pub fn files(&self) -> Vec<FileInfo> {
+        self.state
+            .file_list
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Offer a file from the local disk for download without copying it
+    pub fn share_local_file(&self, path: &std::path::Path) -> anyhow::Result<FileInfo> {
+        let target = shared_paths::resolve_shared(path, self.state.shared_links)
+            .map_err(|e| anyhow::anyhow!("Not sharing {:?}: {}", path, e))?;
+        let metadata = std::fs::metadata(target)?;
+
+        let name = path
+            .file_name()
+            .map(|n| names::normalize(&n.to_string_lossy()))
+            .ok_or_else(|| anyhow::anyhow!("Invalid file path: {:?}", path))?;
+
+        let file_info = FileInfo {
+            mime_type: mime_guess::from_path(path)
+                .first_or_octet_stream()
+                .to_string(),
+            shared: true,
+            ..FileInfo::new(name, path.to_path_buf(), metadata.len())
+        };
+
+        self.state
+            .file_list
+            .lock()
+            .unwrap()
+            .add_file(file_info.clone());
+        metadata::save(
+            self.state.metadata.as_ref(),
+            &[Change::PutFile(StoredFile {
+                room: None,
+                file: file_info.clone(),
+            })],
+        );
+        log::info!("Sharing local file {:?} as '{}'", path, file_info.name);
+
+        Ok(file_info)
+    }
+
+    /// Create a new room with an optional PIN
+    pub fn create_room(&self, name: &str, pin: Option<String>) -> anyhow::Result<()> {
+        let room = Room::new(name, pin)?;
+        let mut rooms = self.state.rooms.lock().unwrap();
+        if rooms.contains_key(name) {
+            return Err(anyhow::anyhow!("Room '{}' already exists", name));
+        }
+        log::info!("Created room '{}'", name);
+        rooms.insert(name.to_string(), room);
+        Ok(())
+    }
+
+    /// Remove a room and delete the files shared in it
+    pub fn remove_room(&self, name: &str) -> anyhow::Result<()> {
+        let room = self
+            .state
+            .rooms
+            .lock()
+            .unwrap()
+            .remove(name)
+            .ok_or_else(|| anyhow::anyhow!("Room '{}' does not exist", name))?;
+
+        for file_info in room.file_list.lock().unwrap().iter() {
+            transfers::cancel_file(&file_info.id);
+        }
+        let room_dir = room_storage_dir(&self.state.temp_dir, &room.name);
+        if room_dir.exists() {
+            std::fs::remove_dir_all(&room_dir)?;
+        }
+        for file_info in room.file_list.lock().unwrap().iter() {
+            integrations::emit(ServerEvent::FileDeleted {
+                id: file_info.id.clone(),
+                name: file_info.name.clone(),
+                room: Some(room.name.clone()),
+            });
+        }
+        log::info!("Removed room '{}'", name);
+        Ok(())
+    }
+
+    /// All rooms, sorted by name
+    pub fn rooms(&self) -> Vec<Room> {
+        let mut rooms: Vec<Room> = self.state.rooms.lock().unwrap().values().cloned().collect();
+        rooms.sort_by(|a, b| a.name.cmp(&b.name));
+        rooms
+    }
+
+    /// Files of the main share and of every room, with the room they are in
+    pub fn stored_files(&self) -> Vec<(Option<String>, FileInfo)> {
+        let mut files: Vec<(Option<String>, FileInfo)> = self
+            .state
+            .file_list
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|file| (None, file.clone()))
+            .collect();
+        for room in self.rooms() {
+            let room_files = room.file_list.lock().unwrap();
+            files.extend(
+                room_files
+                    .iter()
+                    .map(|f| (Some(room.name.clone()), f.clone())),
+            );
+        }
+        files
+    }
+
+    /// Directory received files of the main share or of `room` are stored in
+    pub fn storage_dir(&self, room: Option<&str>) -> PathBuf {
+        match room {
+            Some(room) => room_storage_dir(&self.state.temp_dir, room),
+            None => self.state.temp_dir.clone(),
+        }
+    }
+
+    /// Add a file restored from a state archive to the main share or a room
+    pub fn add_restored_file(&self, room: Option<&str>, file_info: FileInfo) -> anyhow::Result<()> {
+        let file_list = match room {
+            Some(name) => self
+                .state
+                .rooms
+                .lock()
+                .unwrap()
+                .get(name)
+                .map(|room| room.file_list.clone())
+                .ok_or_else(|| anyhow::anyhow!("Room '{}' does not exist", name))?,
+            None => self.state.file_list.clone(),
+        };
+        log::info!("Restored '{}' from a state archive", file_info.name);
+        file_list.lock().unwrap().add_file(file_info);
+        Ok(())
+    }
+
+    /// Received programs and scripts waiting to be released, with the room
+    /// they are in
+    pub fn quarantined_files(&self) -> Vec<(Option<String>, FileInfo)> {
+        self.stored_files()
+            .into_iter()
+            .filter(|(_, file)| file.quarantined)
+            .collect()
+    }
+
+    /// Move a quarantined file back under its own path, once the user
+    /// confirmed they trust it
+    pub fn release_file(&self, id: &str) -> anyhow::Result<FileInfo> {
+        let file_lists = std::iter::once((None, self.state.file_list.clone())).chain(
+            self.rooms()
+                .into_iter()
+                .map(|room| (Some(room.name), room.file_list)),
+        );
+        for (room, file_list) in file_lists {
+            let mut file_list = file_list.lock().unwrap();
+            let Some(blocked) = file_list
+                .get_file_by_id(id)
+                .filter(|file| file.quarantined)
+                .map(|file| file.path.clone())
+            else {
+                continue;
+            };
+            let path = quarantine::release(&blocked)?;
+            let file_info = file_list
+                .update(id, |file_info| {
+                    file_info.path = path;
+                    file_info.quarantined = false;
+                    file_info.clone()
+                })
+                .expect("released file is listed");
+            // Executable bits wait for the release too
+            if let Some(mode) = file_info.mode {
+                if let Err(e) = permissions::set_mode(&file_info.path, mode) {
+                    log::warn!(
+                        "Failed to set the permissions of {:?}: {}",
+                        file_info.path,
+                        e
+                    );
+                }
+            }
+            metadata::save(
+                self.state.metadata.as_ref(),
+                &[Change::PutFile(StoredFile {
+                    room,
+                    file: file_info.clone(),
+                })],
+            );
+            log::info!("Released '{}' from quarantine", file_info.name);
+            return Ok(file_info);
         }
+        anyhow::bail!("No quarantined file {}", id)
     }
 
-    pub async fn start(&mut self) -> anyhow::Result<()> {
+    /// Uploads waiting for the user to resolve a name conflict
+    pub fn pending_conflicts(&self) -> Vec<PendingConflict> {
+        self.state.conflicts.pending()
+    }
+
+    /// Release a held upload with the user's decision
+    pub fn resolve_conflict(&self, id: &str, resolution: ConflictResolution) -> anyhow::Result<()> {
+        log::info!("Resolving conflict for upload {} with {:?}", id, resolution);
+        self.state.conflicts.resolve(id, resolution)
+    }
+
+    /// Storage used by received files across the main share and all rooms
+    pub fn storage_usage(&self) -> StorageUsage {
+        let mut usage = self.state.file_list.lock().unwrap().usage();
+        for room in self.rooms() {
+            usage.merge(room.file_list.lock().unwrap().usage());
+        }
+        usage
+    }
+
+    /// Free space on the volume holding the storage directory
+    pub fn free_space(&self) -> anyhow::Result<u64> {
+        Ok(fs2::available_space(&self.state.temp_dir)?)
+    }
+
+    /// Delete received files older than `days` days, returning how many
+    /// files and bytes were removed
+    pub fn cleanup_older_than(&self, days: u64) -> (usize, u64) {
+        let (files, removed_bytes) =
+            self.remove_received_before(unix_now().saturating_sub(days * 24 * 60 * 60));
+        log::info!(
+            "Storage cleanup removed {} files older than {} days ({})",
+            files,
+            days,
+            humanize::Locale::default().format_size(removed_bytes)
+        );
+        (files, removed_bytes)
+    }
+
+    /// Delete all received files, returning how many files and bytes were
+    /// removed
+    pub fn clear_files(&self) -> (usize, u64) {
+        // Including the files added this second
+        self.remove_received_before(unix_now() + 1)
+    }
+
+    /// Delete a received file from the main share, e.g. one sent by
+    /// mistake
+    pub fn remove_file(&self, id: &str) -> anyhow::Result<FileInfo> {
+        remove_received(&self.state.file_list, id, |_| true).map_err(|status| match status {
+            StatusCode::FORBIDDEN => {
+                anyhow::anyhow!("Files shared from this computer are not deleted")
+            }
+            _ => anyhow::anyhow!("No received file {}", id),
+        })
+    }
+
+    /// Delete received files added before the Unix time `cutoff`, and
+    /// chunks kept since then
+    fn remove_received_before(&self, cutoff: u64) -> (usize, u64) {
+        let mut expired: Vec<(Option<String>, FileInfo)> = self
+            .state
+            .file_list
+            .lock()
+            .unwrap()
+            .remove_older_than(cutoff)
+            .into_iter()
+            .map(|f| (None, f))
+            .collect();
+        for room in self.rooms() {
+            let room_files = room.file_list.lock().unwrap().remove_older_than(cutoff);
+            expired.extend(room_files.into_iter().map(|f| (Some(room.name.clone()), f)));
+        }
+
+        let mut removed_bytes = 0;
+        for (room, file_info) in &expired {
+            transfers::cancel_file(&file_info.id);
+            if let Err(e) = std::fs::remove_file(&file_info.path) {
+                log::warn!("Failed to remove file {:?}: {}", file_info.path, e);
+                continue;
+            }
+            removed_bytes += file_info.size;
+            integrations::emit(ServerEvent::FileDeleted {
+                id: file_info.id.clone(),
+                name: file_info.name.clone(),
+                room: room.clone(),
+            });
+        }
+
+        // Chunks kept for deduplicated re-sends expire like the files
+        let chunk_cutoff = std::time::UNIX_EPOCH + std::time::Duration::from_secs(cutoff);
+        match chunks::open_store(&self.state.temp_dir)
+            .and_then(|store| store.remove_older_than(chunk_cutoff))
+        {
+            Ok((_, bytes)) => removed_bytes += bytes,
+            Err(e) => log::warn!("Failed to clean up chunk store: {}", e),
+        }
+        (expired.len(), removed_bytes)
+    }
+
+    /// Refuse or accept changes to the share until the next start
+    pub fn set_read_only(&self, read_only: bool) {
+        self.state.read_only.store(read_only, Ordering::Relaxed);
+    }
+
+    /// Commands hosts send from the portal, for the app to carry out with
+    /// `remote::run`; only the default server has them, once
+    pub fn take_remote_commands(&mut self) -> Option<mpsc::UnboundedReceiver<RemoteCommand>> {
+        self.remote_commands.take()
+    }
+
+    /// Wait for any other start or stop of `server` to finish
+    async fn transition(server: &Mutex<Self>) -> OwnedMutexGuard<()> {
+        let lifecycle = server.lock().unwrap().lifecycle.clone();
+        lifecycle.lock_owned().await
+    }
+
+    /// Start or stop `server`, returning whether anything changed
+    pub async fn set_running(server: &Mutex<Self>, running: bool) -> anyhow::Result<bool> {
+        let _transition = Self::transition(server).await;
+        let was_running = server.lock().unwrap().get_server_info().running;
+        if was_running == running {
+            return Ok(false);
+        }
+        if running {
+            server.lock().unwrap().listen()?;
+        } else {
+            Self::remove_files(server).await?;
+        }
+        Ok(true)
+    }
+
+    /// Start serving `server` on the current runtime. Its lock is only
+    /// held while setting up, never across an await.
+    pub async fn start(server: &Mutex<Self>) -> anyhow::Result<()> {
+        let _transition = Self::transition(server).await;
+        server.lock().unwrap().listen()
+    }
+
+    /// Bind the listeners and spawn the server's tasks
+    fn listen(&mut self) -> anyhow::Result<()> {
         if self.shutdown_tx.is_some() {
             return Ok(());
         }
 
-        // Get fresh config from singleton instance
-        let instance = ConfigData::instance()?;
-        let config = instance.lock().unwrap();
+        // Only the default server follows the current network
+        let ssid = if self.profile.is_none() {
+            network::current_ssid()
+        } else {
+            None
+        };
+
+        // Get fresh config from singleton instance, releasing the lock right away
+        let settings = {
+            let instance = ConfigData::instance()?;
+            let config = instance.lock().unwrap();
+            // Sign-in changes apply on restart; signed-in browsers must sign in again
+            self.state.auth = Auth::from_config(&config.security);
+            self.state.basic_auth = BasicAuth::from_config(config.security.basic_auth.as_ref());
+            self.state.qr_tokens = QrTokens::from_config(config.security.qr_token.as_ref());
+            self.state.security_headers = SecurityHeaders::from_config(&config.security.headers);
+            self.state.file_types = FileTypes::from_config(&config.security);
+            self.state.shared_links = config.security.shared_links;
+            self.state.segment_limits = SegmentLimits::from_config(&config.server);
+            self.state.trusted_proxies = config.server.trusted_proxies.clone();
+            memory::set_limit_mb(config.server.transfer_memory_mb);
+            ServerSettings::resolve(&config, self.profile.as_deref(), ssid.as_deref())?
+        };
+        if let Some(name) = &settings.network {
+            log::info!(
+                "Applying network overlay '{}' for Wi-Fi network {:?}",
+                name,
+                ssid.unwrap_or_default()
+            );
+        }
 
         // Update storage directory if it changed
-        let new_storage_dir = PathBuf::from(&config.storage.storage_dir);
-        std::fs::create_dir_all(&new_storage_dir)?;
-        self.state.temp_dir = new_storage_dir;
+        std::fs::create_dir_all(&settings.storage_dir)?;
+        std::fs::create_dir_all(&settings.scratch_dir)?;
+        self.state.temp_dir = settings.storage_dir;
+        self.state.scratch_dir = settings.scratch_dir;
+        self.state
+            .read_only
+            .store(settings.read_only, Ordering::Relaxed);
+        self.state.pin = settings.pin;
+
+        // A certificate that can't be read keeps the server from starting,
+        // rather than serving the portal unencrypted
+        let certificate = settings
+            .tls
+            .as_ref()
+            .map(|tls| Certificate::load(tls, settings.http.http2).map(Arc::new))
+            .transpose()?;
+        self.state.certificate = certificate.clone();
 
         // Get local IP address
-        let ip = match local_ip() {
-            Ok(ip) => ip.to_string(),
-            Err(_) => "127.0.0.1".to_string(),
-        };
+        let local_ip = local_ip().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
 
         // Get current port from settings (not cached)
-        let port = config.server.port;
-        let upload_chunk_size_mb = config.server.upload_chunk_size_mb;
-
-        // Release the config lock before continuing
-        drop(config);
+        let port = settings.port;
+        let upload_chunk_size_mb = settings.upload_chunk_size_mb;
+
+        // Bind before spawning so port conflicts between profiles are reported
+        let mut listeners = Vec::new();
+        for address in &settings.bind_addresses {
+            let addr = SocketAddr::new(*address, port);
+            let listener = http::listen(addr)
+                .map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", addr, e))?;
+            listeners.push((addr, listener));
+        }
+        let mut trust_listeners = Vec::new();
+        if let (Some(_), Some(trust_port)) = (&certificate, settings.trust_page_port) {
+            for address in &settings.bind_addresses {
+                let addr = SocketAddr::new(*address, trust_port);
+                let listener = http::listen(addr)
+                    .map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", addr, e))?;
+                trust_listeners.push(listener);
+            }
+        }
+        let urls = listener_urls(
+            self.state.scheme(),
+            &settings.bind_addresses,
+            port,
+            local_ip,
+        );
+        let url = urls[0].clone();
+        self.state.pairing.urls = urls.clone();
+        self.state.pairing.pin = self.state.pin.clone();
+        self.state.pairing.certificate_fingerprint = certificate
+            .as_ref()
+            .map(|certificate| certificate.fingerprint().to_string());
 
         let app_state = self.state.clone();
-        let server_info = self.server_info.clone();
 
         // Update server info with fresh values
         {
-            let mut info = server_info.lock().unwrap();
-            info.url = format!("http://{}:{}", ip, port);
-            info.ip = ip.clone();
+            let mut info = self.state.server_info.lock().unwrap();
+            info.url = url.clone();
+            info.urls = urls;
+            info.ip = settings
+                .bind_addresses
+                .iter()
+                .find(|address| !address.is_unspecified())
+                .unwrap_or(&local_ip)
+                .to_string();
             info.port = port;
             info.running = true;
+            info.network = settings.network;
         }
 
-        // Create static file service
-        let static_files_service = ServeDir::new("assets/web");
-
         // Create CORS layer
         let cors = CorsLayer::new()
             .allow_origin(Any)
@@ -140,81 +921,288 @@ impl FileServer {
             .allow_headers(Any);
 
         // Build router with fresh config values
-        let app = Router::new()
-            .route("/", get(serve_index))
-            .route("/api/files", get(get_files))
-            .route("/api/files/:id", get(download_file))
-            .route("/api/config", get(get_config))
+        let upload_body_limit = (upload_chunk_size_mb + 1) as usize * 1024 * 1024;
+        let mut portal_routes = Router::new()
+            .route("/files", get(get_files))
+            .route("/files/:id", get(download_file).delete(delete_file))
+            .route("/files/:id/unlock", post(download_protected_file))
+            .route("/files/:id/qrcode.png", get(file_qr_code))
+            .route("/info", get(get_info))
+            .merge(archive::api_router())
             .route(
-                "/api/upload",
-                post(upload_file).layer(axum::extract::DefaultBodyLimit::max(
-                    (upload_chunk_size_mb + 1) as usize * 1024 * 1024,
-                )),
+                "/upload",
+                post(upload_file)
+                    .layer(axum::extract::DefaultBodyLimit::max(upload_body_limit))
+                    .layer(middleware::from_fn_with_state(
+                        upload_body_limit,
+                        memory::hold_body,
+                    )),
             )
-            .nest_service("/static", static_files_service)
+            .merge(upload_ids::api_router())
+            .merge(upload_progress::api_router())
+            .merge(form_upload::api_router(
+                self.state.segment_limits.max_file_size(),
+            ))
+            .merge(onetime::api_router())
+            .merge(snippets::api_router())
+            .merge(chunks::api_router())
+            .merge(pairing::api_router())
+            .merge(ranges::api_router())
+            .merge(event_log::api_router())
+            .merge(records::api_router())
+            .merge(remote::api_router())
+            .merge(preflight::api_router());
+        if settings.delta_uploads {
+            portal_routes = portal_routes.merge(delta::api_router());
+        }
+        let portal_routes = portal_routes.route_layer(middleware::from_fn_with_state(
+            self.state.clone(),
+            require_pin,
+        ));
+        let api_routes = portal_routes
+            .merge(rooms::api_router(upload_body_limit))
+            .merge(upload_progress::room_api_router())
+            .route_layer(middleware::from_fn_with_state(
+                self.state.clone(),
+                auth::require_login,
+            ))
+            .route("/config", get(get_config))
+            .route("/ping", get(ping))
+            .merge(speedtest::api_router());
+        let live_routes = live::router()
+            .route_layer(middleware::from_fn_with_state(
+                self.state.clone(),
+                require_pin,
+            ))
+            .merge(live::room_router())
+            .route_layer(middleware::from_fn_with_state(
+                self.state.clone(),
+                auth::require_login,
+            ));
+        let upload_routes = upload_sessions::api_router()
+            .route_layer(middleware::from_fn_with_state(
+                self.state.clone(),
+                require_pin,
+            ))
+            .route_layer(middleware::from_fn_with_state(
+                self.state.clone(),
+                auth::require_login,
+            ));
+
+        let app = Router::new()
+            .route("/", get(assets::serve_index))
+            .merge(rooms::router())
+            .merge(onetime::router())
+            .merge(form_upload::router())
+            .merge(auth::router())
+            .merge(metrics::router())
+            .merge(live_routes)
+            .merge(api::versioned(api_routes, upload_routes))
+            .route("/static/:name", get(assets::static_asset))
+            .route("/manifest.webmanifest", get(assets::manifest))
+            .route("/sw.js", get(assets::service_worker))
+            .layer(middleware::from_fn_with_state(
+                self.state.clone(),
+                transfers::track_uploads,
+            ))
+            .layer(middleware::from_fn_with_state(
+                self.state.clone(),
+                qr_token::require_qr_token,
+            ))
+            .layer(middleware::from_fn_with_state(
+                self.state.clone(),
+                basic::require_basic_auth,
+            ))
+            .layer(middleware::from_fn_with_state(
+                self.state.clone(),
+                add_security_headers,
+            ))
             .layer(TraceLayer::new_for_http())
             .layer(cors)
             .with_state(app_state);
-
-        // Get server address with current port
-        let addr = SocketAddr::new("0.0.0.0".parse()?, port);
+        #[cfg(feature = "chaos")]
+        let app = {
+            if super::chaos::enabled() {
+                log::warn!("Chaos mode: uploads will fail at random");
+            }
+            app.layer(middleware::from_fn(super::chaos::inject))
+        };
 
         log::info!(
-            "Starting server on {} with storage dir: {:?}",
-            addr,
+            "Starting server{} on {} with storage dir: {:?}",
+            self.profile
+                .as_ref()
+                .map(|name| format!(" for profile '{}'", name))
+                .unwrap_or_default(),
+            listeners
+                .iter()
+                .map(|(addr, _)| addr.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
             self.state.temp_dir
         );
 
-        // Create shutdown channel
-        let (tx, rx) = oneshot::channel::<()>();
+        // Create shutdown channel, shared by all listeners
+        let (tx, rx) = watch::channel(false);
         self.shutdown_tx = Some(tx);
 
-        // Start server
-        tokio::spawn(async move {
-            let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-            let server = axum::serve(listener, app);
-
-            let server = server.with_graceful_shutdown(async {
-                rx.await.ok();
-            });
-
-            if let Err(err) = server.await {
-                log::error!("Server error: {}", err);
-                let mut info = server_info.lock().unwrap();
-                info.running = false;
+        self.state.stats.started(unix_now());
+        // Subscribed before the start is announced, so the log begins with it
+        let events = integrations::subscribe();
+        let recorded = integrations::subscribe();
+        let changes = integrations::subscribe();
+        integrations::emit(ServerEvent::ServerStarted { url });
+
+        // Start one server per listener
+        let acceptor = certificate
+            .as_ref()
+            .map(|certificate| certificate.acceptor());
+        for (_, listener) in listeners {
+            tokio::spawn(http::serve(
+                listener,
+                app.clone(),
+                settings.http,
+                acceptor.clone(),
+                self.state.stats.clone(),
+                rx.clone(),
+            ));
+        }
+        if let (Some(certificate), Some(trust_port)) = (certificate, settings.trust_page_port) {
+            log::info!("Serving the certificate trust page on port {}", trust_port);
+            let trust_page = tls::trust_router(certificate, port);
+            for listener in trust_listeners {
+                tokio::spawn(http::serve(
+                    listener,
+                    trust_page.clone(),
+                    settings.http,
+                    None,
+                    // Its visitors aren't counted as devices of the portal
+                    ServerStats::default(),
+                    rx.clone(),
+                ));
             }
-        });
+        }
+        tokio::spawn(devices::run(self.state.clone(), rx.clone()));
+        tokio::spawn(event_log::run(
+            self.state.event_log.clone(),
+            events,
+            rx.clone(),
+        ));
+        tokio::spawn(records::run(self.state.clone(), recorded, rx.clone()));
+        tokio::spawn(live::run(self.state.live.clone(), changes, rx.clone()));
+        tokio::spawn(upload_sessions::run(
+            self.state.upload_sessions.clone(),
+            rx.clone(),
+        ));
+        if settings.scrub_interval_hours > 0 {
+            let interval = std::time::Duration::from_secs(settings.scrub_interval_hours * 3600);
+            tokio::spawn(scrub::run(self.state.clone(), interval, rx.clone()));
+        }
+        if let Some(config) = settings
+            .snapshots
+            .filter(|config| config.interval_hours > 0)
+        {
+            tokio::spawn(snapshots::run(
+                self.state.metadata.clone(),
+                config,
+                rx.clone(),
+            ));
+        }
 
         Ok(())
     }
 
-    pub async fn stop(&mut self) -> anyhow::Result<()> {
-        if let Some(tx) = self.shutdown_tx.take() {
-            let _ = tx.send(());
+    /// Start over with the current settings, keeping the stored files.
+    /// Does nothing while the server is stopped.
+    pub async fn restart(server: &Mutex<Self>) -> anyhow::Result<()> {
+        let _transition = Self::transition(server).await;
+        let stopped = server.lock().unwrap().shut_down();
+        if !stopped {
+            return Ok(());
+        }
+        Self::listen_again(server).await
+    }
+
+    /// Stop listening, keeping the stored files until `resume`
+    pub fn pause(&mut self) {
+        self.shut_down();
+    }
+
+    /// Start again after `pause` or a restart
+    pub async fn resume(server: &Mutex<Self>) -> anyhow::Result<()> {
+        let _transition = Self::transition(server).await;
+        Self::listen_again(server).await
+    }
 
-            // Update server info
-            let mut info = self.server_info.lock().unwrap();
-            info.running = false;
+    /// Listen again, retrying while the old listeners close once their
+    /// tasks see the shutdown
+    async fn listen_again(server: &Mutex<Self>) -> anyhow::Result<()> {
+        let mut attempts = 1;
+        loop {
+            let started = server.lock().unwrap().listen();
+            match started {
+                Err(e) if attempts < RESTART_ATTEMPTS => {
+                    log::debug!("Restart attempt {} failed: {}", attempts, e);
+                    attempts += 1;
+                    tokio::time::sleep(RESTART_RETRY_DELAY).await;
+                }
+                result => return result,
+            }
         }
+    }
 
-        // Clean up uploaded files
-        log::info!("Cleaning up uploaded files...");
+    /// Close the listeners, returning whether the server was running
+    fn shut_down(&mut self) -> bool {
+        let Some(tx) = self.shutdown_tx.take() else {
+            return false;
+        };
+        let _ = tx.send(true);
 
-        // Get the list of files to clean up
-        let files_to_remove = {
-            let file_list = self.state.file_list.lock().unwrap();
-            file_list.files.clone()
+        // Update server info
+        self.state.stats.stopped();
+        let mut info = self.state.server_info.lock().unwrap();
+        info.running = false;
+        integrations::emit(ServerEvent::ServerStopped {
+            url: info.url.clone(),
+        });
+        true
+    }
+
+    /// Stop `server` and remove the received files. The files are taken
+    /// off the lists under its lock and removed without holding it.
+    pub async fn stop(server: &Mutex<Self>) -> anyhow::Result<()> {
+        let _transition = Self::transition(server).await;
+        Self::remove_files(server).await
+    }
+
+    /// Close the listeners and remove the received files
+    async fn remove_files(server: &Mutex<Self>) -> anyhow::Result<()> {
+        let (files_to_remove, storage_dir, rooms) = {
+            let mut server = server.lock().unwrap();
+            let rooms: Vec<_> = server.rooms().into_iter().map(|room| room.name).collect();
+            let files = server.forget_files();
+            (files, server.state.temp_dir.clone(), rooms)
         };
 
         // Remove each uploaded file
         let mut removed_count = 0;
         let mut failed_count = 0;
 
-        for file_info in &files_to_remove {
-            match std::fs::remove_file(&file_info.path) {
+        for (room, file_info) in &files_to_remove {
+            // Files shared from the desktop belong to the user, and
+            // organized photos are kept as an offload archive
+            if file_info.shared || file_info.folder.is_some() {
+                continue;
+            }
+            match tokio::fs::remove_file(&file_info.path).await {
                 Ok(_) => {
                     log::debug!("Removed file: {:?}", file_info.path);
                     removed_count += 1;
+                    integrations::emit(ServerEvent::FileDeleted {
+                        id: file_info.id.clone(),
+                        name: file_info.name.clone(),
+                        room: room.clone(),
+                    });
                 }
                 Err(e) => {
                     log::warn!("Failed to remove file {:?}: {}", file_info.path, e);
@@ -223,17 +1211,16 @@ impl FileServer {
             }
         }
 
-        // Clear the file list
-        {
-            let mut file_list = self.state.file_list.lock().unwrap();
-            file_list.clear();
+        for room in &rooms {
+            let _ = tokio::fs::remove_dir(room_storage_dir(&storage_dir, room)).await;
         }
+        let _ = tokio::fs::remove_dir(storage_dir.join("rooms")).await;
 
         // Try to remove the storage directory if it's empty or only contains our files
-        if let Err(e) = std::fs::remove_dir(&self.state.temp_dir) {
+        if let Err(e) = tokio::fs::remove_dir(&storage_dir).await {
             log::debug!("Storage directory not empty or failed to remove: {} (this is normal if directory contains other files)", e);
         } else {
-            log::debug!("Removed empty storage directory: {:?}", self.state.temp_dir);
+            log::debug!("Removed empty storage directory: {:?}", storage_dir);
         }
 
         if files_to_remove.is_empty() {
@@ -248,48 +1235,313 @@ impl FileServer {
 
         Ok(())
     }
+
+    /// Close the listeners and empty the file lists, returning the files
+    /// that were listed with their rooms
+    fn forget_files(&mut self) -> Vec<(Option<String>, FileInfo)> {
+        self.shut_down();
+
+        // Clean up uploaded files
+        log::info!("Cleaning up uploaded files...");
+
+        // Get the list of files to clean up, including files shared in rooms
+        let rooms = self.rooms();
+        let mut files_to_remove: Vec<(Option<String>, FileInfo)> = {
+            let file_list = self.state.file_list.lock().unwrap();
+            file_list.iter().map(|f| (None, f.clone())).collect()
+        };
+        for room in &rooms {
+            let room_files = room.file_list.lock().unwrap();
+            files_to_remove.extend(
+                room_files
+                    .iter()
+                    .map(|f| (Some(room.name.clone()), f.clone())),
+            );
+        }
+
+        // Downloads in flight end with the server rather than being left to
+        // run against files that are about to be removed
+        for (_, file_info) in &files_to_remove {
+            transfers::cancel_file(&file_info.id);
+        }
+        // The lists are emptied, kept files included, so none is listed
+        // again on the next start
+        let forgotten: Vec<_> = files_to_remove
+            .iter()
+            .map(|(_, file_info)| Change::RemoveFile(file_info.id.clone()))
+            .collect();
+        metadata::save(self.state.metadata.as_ref(), &forgotten);
+
+        // Clear the file lists
+        {
+            let mut file_list = self.state.file_list.lock().unwrap();
+            file_list.clear();
+        }
+        self.state.snippets.lock().unwrap().clear();
+        self.state.onetime_links.clear();
+        self.state.upload_ids.clear();
+        self.state.upload_progress.clear();
+        self.state.upload_sessions.clear();
+        scratch::discard_incoming(&self.state.temp_dir);
+        for room in &rooms {
+            room.file_list.lock().unwrap().clear();
+        }
+        files_to_remove
+    }
+}
+
+/// `scheme://host:port` as the client addressed us, for links handed to
+/// other devices
+pub(crate) fn request_base_url(headers: &HeaderMap, scheme: &str) -> Option<String> {
+    headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .map(|host| format!("{}://{}", scheme, host))
+}
+
+/// Credentials a client supplies when the main portal requires a PIN
+#[derive(Debug, Deserialize)]
+struct PortalAccess {
+    pin: Option<String>,
+}
+
+/// Reject main portal requests without the PIN set by a network overlay
+async fn require_pin(
+    State(state): State<AppState>,
+    Query(access): Query<PortalAccess>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    if let Some(expected) = &state.pin {
+        if access.pin.as_deref() != Some(expected.as_str()) {
+            log::warn!("Rejected portal request with wrong PIN");
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+/// Percent-encode a value for use in a URL query string
+fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Search of a file list, e.g. `?q=cafe`
+#[derive(Debug, Deserialize)]
+pub(crate) struct FileSearch {
+    q: Option<String>,
+}
+
+impl FileSearch {
+    /// The files of `file_list` whose names contain the query, all of them
+    /// without one
+    pub(crate) fn apply(&self, file_list: &FileList) -> GroupedFileList {
+        match self.q.as_deref().filter(|q| !q.trim().is_empty()) {
+            Some(query) => GroupedFileList::from(&file_list.search(query)),
+            None => GroupedFileList::from(file_list),
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn get_files(
+    Query(search): Query<FileSearch>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Response {
+    let file_list = search.apply(&state.file_list.lock().unwrap());
+    conditional::json(&headers, &file_list)
 }
 
 #[axum::debug_handler]
-async fn serve_index() -> Html<&'static str> {
-    Html(include_str!("../../assets/web/index.html"))
+async fn ping() -> &'static str {
+    "pong"
 }
 
+/// Addresses, uptime, connected devices and traffic of the server
 #[axum::debug_handler]
-async fn get_files(State(state): State<AppState>) -> Json<FileList> {
-    let file_list = state.file_list.lock().unwrap().clone();
-    Json(file_list)
+async fn get_info(State(state): State<AppState>) -> Json<ServerInfo> {
+    Json(state.server_info())
 }
 
 #[derive(Serialize)]
 struct ConfigResponse {
     upload_chunk_size_mb: u64,
+    read_only: bool,
+    login_required: bool,
+    user: Option<auth::SignedInUser>,
 }
 
 #[axum::debug_handler]
-async fn get_config() -> Json<ConfigResponse> {
+async fn get_config(headers: HeaderMap, State(state): State<AppState>) -> Json<ConfigResponse> {
     let instance = ConfigData::instance().unwrap();
     let config = instance.lock().unwrap();
     Json(ConfigResponse {
         upload_chunk_size_mb: config.server.upload_chunk_size_mb,
+        read_only: state.read_only(),
+        login_required: state.auth.is_some(),
+        user: auth::signed_in_user(&state, &headers),
     })
 }
 
+/// QR code encoding the direct download URL of a file
+#[axum::debug_handler]
+async fn file_qr_code(
+    Path(id): Path<String>,
+    Query(access): Query<PortalAccess>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Response, StatusCode> {
+    if state
+        .file_list
+        .lock()
+        .unwrap()
+        .get_file_by_id(&id)
+        .is_none()
+    {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let base_url = request_base_url(&headers, state.scheme()).ok_or(StatusCode::BAD_REQUEST)?;
+    let mut url = format!("{}/api/v{}/files/{}", base_url, api::CURRENT_VERSION, id);
+    // The scanning device has no session, so it needs the PIN in the link
+    if let Some(pin) = access.pin.filter(|_| state.pin.is_some()) {
+        url.push_str(&format!("?pin={}", encode_query_value(&pin)));
+    }
+    let png = qrcode::generate_qr_code_png(&url).map_err(|e| {
+        log::error!("Failed to generate QR code for file {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response())
+}
+
 #[axum::debug_handler]
 async fn download_file(
     Path(id): Path<String>,
+    headers: HeaderMap,
     State(state): State<AppState>,
+) -> Result<Response, StatusCode> {
+    send_file(&state, &state.file_list, &id, &headers, None).await
+}
+
+/// Download of a password-protected file, with the password posted in a form
+#[axum::debug_handler]
+async fn download_protected_file(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Form(unlock): Form<Unlock>,
+) -> Result<Response, StatusCode> {
+    send_file(
+        &state,
+        &state.file_list,
+        &id,
+        &headers,
+        Some(unlock.password),
+    )
+    .await
+}
+
+/// Delete a received file sent by mistake. Hosts may delete any of them,
+/// everyone else only the files their own device sent.
+#[axum::debug_handler]
+async fn delete_file(
+    Path(id): Path<String>,
+    ClientIp(client_ip): ClientIp,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> StatusCode {
+    if state.read_only() {
+        log::warn!("Rejected deletion on read-only server");
+        return StatusCode::FORBIDDEN;
+    }
+    let host = auth::signed_in_user(&state, &headers).is_some_and(|user| user.role == Role::Host);
+    let sender = |file: &FileInfo| host || (file.device.is_some() && file.device == client_ip);
+    match remove_received(&state.file_list, &id, sender) {
+        Ok(file_info) => {
+            log::info!("Deleted '{}' from the portal", file_info.name);
+            StatusCode::NO_CONTENT
+        }
+        Err(status) => status,
+    }
+}
+
+/// Take the received file `id` off the main share's `file_list` and delete
+/// it from the storage directory, if `allowed` says so for it. Files shared
+/// from the desktop are never deleted this way.
+fn remove_received(
+    file_list: &Mutex<FileList>,
+    id: &str,
+    allowed: impl FnOnce(&FileInfo) -> bool,
+) -> Result<FileInfo, StatusCode> {
+    let file_info = {
+        let mut file_list = file_list.lock().unwrap();
+        let file_info = file_list.get_file_by_id(id).ok_or(StatusCode::NOT_FOUND)?;
+        if file_info.shared || !allowed(file_info) {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        file_list.remove(id).ok_or(StatusCode::NOT_FOUND)?
+    };
+
+    transfers::cancel_file(&file_info.id);
+    match std::fs::remove_file(&file_info.path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => log::warn!("Failed to remove file {:?}: {}", file_info.path, e),
+    }
+    integrations::emit(ServerEvent::FileDeleted {
+        id: file_info.id.clone(),
+        name: file_info.name.clone(),
+        room: None,
+    });
+    Ok(file_info)
+}
+
+/// The regular file a file shared in place leads to now
+pub(crate) fn shared_path(state: &AppState, file_info: &FileInfo) -> Result<PathBuf, StatusCode> {
+    shared_paths::resolve_shared(&file_info.path, state.shared_links).map_err(|e| {
+        if e.is_not_found() {
+            return StatusCode::NOT_FOUND;
+        }
+        log::warn!("Refused to serve shared {:?}: {}", file_info.path, e);
+        StatusCode::FORBIDDEN
+    })
+}
+
+/// Respond with the contents of a file from the given list. Protected
+/// files are only sent with their `password`.
+pub(crate) async fn send_file(
+    state: &AppState,
+    file_list: &Arc<Mutex<FileList>>,
+    id: &str,
+    request_headers: &HeaderMap,
+    password: Option<String>,
 ) -> Result<Response, StatusCode> {
     // Get file info from the list
     let file_info = {
-        let file_list = state.file_list.lock().unwrap();
-        match file_list.get_file_by_id(&id) {
+        let file_list = file_list.lock().unwrap();
+        match file_list.get_file_by_id(id) {
             Some(info) => info.clone(),
             None => return Err(StatusCode::NOT_FOUND),
         }
     };
+    protected::unlock(&file_info, password).await?;
 
-    let path = file_info.path.clone();
+    // Shared files are checked again, they may have been swapped since
+    let path = if file_info.shared {
+        shared_path(state, &file_info)?
+    } else {
+        file_info.path.clone()
+    };
 
     // Open the file
     let mut file = match File::open(&path).await {
@@ -297,27 +1549,109 @@ async fn download_file(
         Err(_) => return Err(StatusCode::NOT_FOUND),
     };
 
-    // Read the file content
-    let mut contents = Vec::new();
-    if file.read_to_end(&mut contents).await.is_err() {
+    // Skip the transfer when the client's cached copy is current
+    let metadata = file
+        .metadata()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let validators = Validators::for_file(&metadata);
+    if validators.matches(request_headers) {
+        return Ok(validators.not_modified());
+    }
+
+    // Clients downloading in parallel ask for one part per connection
+    let len = metadata.len();
+    let range = match ranges::requested(request_headers, &validators, len) {
+        Ok(range) => range,
+        Err(ranges::Unsatisfiable) => return Ok(ranges::not_satisfiable(len)),
+    };
+
+    // Stream the file, or the requested part of it, so seeking in a long
+    // video doesn't read all of it into memory first
+    let part = range.clone().unwrap_or(0..len);
+    if part.start > 0
+        && file
+            .seek(std::io::SeekFrom::Start(part.start))
+            .await
+            .is_err()
+    {
         return Err(StatusCode::INTERNAL_SERVER_ERROR);
     }
+    let size = part.end - part.start;
 
     // Create response with appropriate headers
+    let name = file_info.name.clone();
     let headers = AppendHeaders([
         (header::CONTENT_TYPE, file_info.mime_type),
         (
             header::CONTENT_DISPOSITION,
-            format!("attachment; filename=\"{}\"", file_info.name),
+            content_disposition(&file_info.name),
         ),
+        (header::CONTENT_LENGTH, size.to_string()),
     ]);
 
-    Ok((headers, contents).into_response())
+    // The download counts as a transfer until the body has been sent
+    let transfer = state.transfers.start_if_large(size).map(|guard| {
+        Transfer::start_for_file(
+            TransferKind::Download,
+            Some(id.to_string()),
+            Some(name),
+            None,
+            Some(size),
+            Some(guard),
+        )
+    });
+    let body = Tracked::new(file.take(size), transfer).counting(state.stats.bytes_out());
+    let buffer = memory::reserve(DOWNLOAD_CHUNK_LEN as u64).await;
+    let body = axum::body::Body::from_stream(buffer.holding(
+        tokio_util::io::ReaderStream::with_capacity(body, DOWNLOAD_CHUNK_LEN),
+    ));
+    let mut response = (headers, body).into_response();
+    validators.apply(&mut response);
+    response
+        .headers_mut()
+        .insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Some(range) = range {
+        *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+        if let Ok(value) = HeaderValue::from_str(&ranges::content_range(&range, len)) {
+            response.headers_mut().insert(header::CONTENT_RANGE, value);
+        }
+    }
+    Ok(response)
 }
 
 #[axum::debug_handler]
 async fn upload_file(
+    ClientIp(client_ip): ClientIp,
     State(state): State<AppState>,
+    multipart: Multipart,
+) -> Result<Json<FileInfo>, StatusCode> {
+    if state.read_only() {
+        log::warn!("Rejected upload to read-only server");
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    receive_upload(
+        &state,
+        &state.file_list,
+        &state.temp_dir,
+        None,
+        client_ip,
+        multipart,
+    )
+    .await
+}
+
+/// Store one uploaded segment under `storage_dir`, adding the file to
+/// `file_list` once the final segment has been combined. A file whose name
+/// is already taken is held in the state's conflicts until the user reviews
+/// it, and kinds of files not in its file types are refused.
+pub(crate) async fn receive_upload(
+    state: &AppState,
+    file_list: &Arc<Mutex<FileList>>,
+    storage_dir: &std::path::Path,
+    room: Option<&str>,
+    device: Option<IpAddr>,
     mut multipart: Multipart,
 ) -> Result<Json<FileInfo>, StatusCode> {
     log::debug!("Starting file upload processing");
@@ -326,8 +1660,11 @@ async fn upload_file(
     let mut file_name = None;
     let mut segment_index = None;
     let mut total_segments = None;
-    let mut file_id = None;
+    let mut file_id: Option<String> = None;
+    let mut file_size = None;
     let mut file_data: Option<Vec<u8>> = None;
+    let mut crc32 = None;
+    let mut password = None;
 
     // Log all received form fields for debugging
     log::debug!("Processing multipart form data");
@@ -341,8 +1678,15 @@ async fn upload_file(
 
         match field_name.as_str() {
             "file" => {
-                let original_filename = field.file_name().unwrap_or("unknown").to_string();
+                let original_filename = sanitize_name(field.file_name().unwrap_or_default());
                 log::debug!("Found file field with filename: {}", original_filename);
+                if !state.file_types.accepts(&original_filename) {
+                    log::warn!(
+                        "Rejected '{}': kind of file not accepted",
+                        original_filename
+                    );
+                    return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+                }
                 file_name = Some(original_filename);
 
                 // Read data in smaller chunks for better memory management
@@ -353,6 +1697,12 @@ async fn upload_file(
                 log::debug!("Reading file data chunks");
                 while let Ok(Some(chunk)) = field.chunk().await {
                     bytes_read += chunk.len();
+                    // Known when the client sent them before the file
+                    if let (Some(file_id), Some(index)) = (&file_id, segment_index) {
+                        state
+                            .upload_progress
+                            .arriving(file_id, index, bytes_read as u64);
+                    }
                     log::debug!(
                         "Read chunk: {} bytes (total: {} bytes)",
                         chunk.len(),
@@ -398,6 +1748,15 @@ async fn upload_file(
                     log::error!("Could not read file_id field as text");
                 }
             }
+            "file_size" => match field.text().await.map(|data| data.parse::<u64>()) {
+                Ok(Ok(size)) => file_size = Some(size),
+                _ => {
+                    log::error!("Could not read file_size field as a number");
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+            },
+            segments::CRC32_FIELD => crc32 = field.text().await.ok(),
+            protected::PASSWORD_FIELD => password = field.text().await.ok(),
             _ => log::warn!("Unexpected field name: {}", field_name),
         }
     }
@@ -424,13 +1783,33 @@ async fn upload_file(
                 return Err(StatusCode::BAD_REQUEST);
             }
         };
+    #[cfg(feature = "chaos")]
+    let file_data = {
+        let mut file_data = file_data;
+        file_data.truncate(super::chaos::truncated_len(file_data.len()));
+        file_data
+    };
+    // The segments are stored in a directory named after the ID
+    if !state.upload_ids.accepts(&file_id, unix_now()) {
+        log::warn!("Rejected segment upload with an invalid ID {:?}", file_id);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    state.upload_progress.begin(&file_id, unix_now());
+    let temp_dir = state.scratch_dir.join(&file_id);
+    let received = segments::stored_size(&temp_dir, segment_index);
+    let segment = Segment {
+        index: segment_index,
+        total: total_segments,
+        size: file_data.len() as u64,
+        file_size,
+    };
+    state.segment_limits.check(&segment, received)?;
+    if let Some(crc32) = &crc32 {
+        segments::check_crc32(&file_data, crc32)?;
+    }
 
     // Create the temporary directory for segments
-    log::debug!(
-        "Creating temp directory for file segments: {:?}",
-        state.temp_dir.join(&file_id)
-    );
-    let temp_dir = state.temp_dir.join(&file_id);
+    log::debug!("Creating temp directory for file segments: {:?}", temp_dir);
     std::fs::create_dir_all(&temp_dir).map_err(|e| {
         log::error!(
             "Failed to create temp directory: {:?}, error: {}",
@@ -451,6 +1830,9 @@ async fn upload_file(
         );
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
+    state
+        .upload_progress
+        .stored(&file_id, &file_name, &segment, unix_now());
 
     log::debug!(
         "Received segment {} of {} for file '{}' (ID: {}), size: {} bytes",
@@ -481,55 +1863,41 @@ async fn upload_file(
             log::error!("Missing segments: {:?}", missing_segments);
             return Err(StatusCode::BAD_REQUEST);
         }
+        // Reported failed if any of the following gives up
+        let finishing = state.upload_progress.assembling(&file_id);
 
-        // Combine all segments into the final file
-        let final_path = state.temp_dir.join(format!("{}_file", file_id));
-        log::debug!("Creating final file: {:?}", final_path);
-        let mut final_file = std::fs::File::create(&final_path).map_err(|e| {
-            log::error!(
-                "Failed to create final file: {:?}, error: {}",
-                final_path,
-                e
+        // Every segment is in, so they have to add up to the declared size
+        let received = received + file_data.len() as u64;
+        if file_size.is_some_and(|file_size| file_size != received) {
+            log::warn!(
+                "Discarded '{}': {} bytes received, {:?} declared",
+                file_name,
+                received,
+                file_size
             );
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-
-        let mut total_size: u64 = 0;
+            let _ = std::fs::remove_dir_all(&temp_dir);
+            return Err(StatusCode::BAD_REQUEST);
+        }
 
-        // Combine all segments
-        for i in 0..total_segments {
-            let segment_path = temp_dir.join(format!("segment_{}", i));
-            log::debug!("Reading segment {}: {:?}", i, segment_path);
-
-            let segment_data = std::fs::read(&segment_path).map_err(|e| {
-                log::error!(
-                    "Failed to read segment file: {:?}, error: {}",
-                    segment_path,
-                    e
-                );
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
-
-            total_size += segment_data.len() as u64;
-            log::debug!("Read segment {} ({} bytes)", i, segment_data.len());
-
-            log::debug!("Writing segment {} to final file", i);
-            final_file.write_all(&segment_data).map_err(|e| {
-                log::error!(
-                    "Failed to write to final file: {:?}, error: {}",
-                    final_path,
-                    e
-                );
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
-        }
-
-        // Flush and close file
-        final_file.flush().map_err(|e| {
-            log::error!("Failed to flush final file: {:?}, error: {}", final_path, e);
+        // Combine all segments into the final file, hashing them on the way
+        let final_path = state.scratch_dir.join(format!("{}_file", file_id));
+        log::debug!("Creating final file: {:?}", final_path);
+        let parts: Vec<PathBuf> = (0..total_segments)
+            .map(|i| temp_dir.join(format!("segment_{}", i)))
+            .collect();
+        let target = final_path.clone();
+        let assembled = tokio::task::spawn_blocking(move || {
+            std::fs::File::create(&target)
+                .and_then(|file| assemble::join(&parts, std::io::BufWriter::new(file)))
+        })
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| {
+            log::error!("Failed to combine segments into {:?}: {}", final_path, e);
+            let _ = std::fs::remove_file(&final_path);
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
-        drop(final_file);
+        let total_size = assembled.size;
 
         // Clean up temporary directory
         log::debug!("Cleaning up temporary directory: {:?}", temp_dir);
@@ -550,31 +1918,19 @@ async fn upload_file(
             total_size
         );
 
-        // Create file info
+        state.upload_ids.finish(&file_id);
+        let password_hash = protected::hash(password).await?;
         let file_info = FileInfo {
             id: file_id,
-            name: file_name,
-            path: final_path,
-            size: total_size,
-            mime_type: "application/octet-stream".to_string(),
+            sha256: Some(assembled.sha256),
+            protected: password_hash.is_some(),
+            password_hash,
+            device,
+            ..FileInfo::new(file_name, final_path, total_size)
         };
-
-        // Add file to the list
-        {
-            let mut file_list = state.file_list.lock().unwrap();
-            file_list.add_file(file_info.clone());
-            log::debug!(
-                "Web upload: Added file '{}' to server file list. Total files: {}",
-                file_info.name,
-                file_list.files.len()
-            );
-        }
-
-        log::info!(
-            "Successfully completed upload process for file: {}",
-            file_info.name
-        );
-        Ok(Json(file_info))
+        let added = add_received_file(state, file_list, storage_dir, room, file_info).await;
+        finishing.end(added.is_ok());
+        added
     } else {
         // Return a response indicating segment was received
         log::debug!(
@@ -584,10 +1940,272 @@ async fn upload_file(
         );
         Ok(Json(FileInfo {
             id: file_id,
-            name: format!("segment_{} of {}", segment_index + 1, total_segments),
-            path: segment_path,
-            size: file_data.len() as u64,
-            mime_type: "application/octet-stream".to_string(),
+            ..FileInfo::new(
+                format!("segment_{} of {}", segment_index + 1, total_segments),
+                segment_path,
+                file_data.len() as u64,
+            )
         }))
     }
 }
+
+/// Offer a fully received file for download: move it out of the scratch
+/// directory, sort it into a date folder if enabled, set its permissions,
+/// let the user review a name conflict and announce it
+pub(crate) async fn add_received_file(
+    state: &AppState,
+    file_list: &Arc<Mutex<FileList>>,
+    storage_dir: &std::path::Path,
+    room: Option<&str>,
+    mut file_info: FileInfo,
+) -> Result<Json<FileInfo>, StatusCode> {
+    let written = file_info.path.clone();
+    let target = storage_dir.to_path_buf();
+    file_info.path = tokio::task::spawn_blocking(move || scratch::move_into(&written, &target))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| {
+            log::error!("Failed to move {:?} into storage: {}", file_info.path, e);
+            let _ = std::fs::remove_file(&file_info.path);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    // Sort photos and videos into date folders if enabled
+    let organize_media = ConfigData::instance()
+        .map(|instance| instance.lock().unwrap().storage.organize_media_by_date)
+        .unwrap_or(false);
+    if organize_media && organize::is_media(&file_info.name) {
+        match organize::organize(storage_dir, &file_info.path, &file_info.name) {
+            Ok(path) => {
+                file_info.folder = path
+                    .parent()
+                    .and_then(|dir| dir.strip_prefix(storage_dir).ok())
+                    .map(|dir| dir.to_string_lossy().replace('\\', "/"));
+                log::info!("Organized '{}' into {:?}", file_info.name, path);
+                file_info.path = path;
+            }
+            Err(e) => log::warn!("Failed to organize '{}' by date: {}", file_info.name, e),
+        }
+    }
+    let final_path = file_info.path.clone();
+
+    // Devices with a quota must stay within it
+    if !devices::check_quota(file_list, &file_info) {
+        log::warn!(
+            "Rejected '{}' from {:?}: device quota exceeded",
+            file_info.name,
+            file_info.device
+        );
+        let _ = std::fs::remove_file(&final_path);
+        return Err(StatusCode::INSUFFICIENT_STORAGE);
+    }
+
+    // Programs and scripts wait under a `.blocked` path until released
+    let final_path = if quarantine::is_executable(&file_info.name, &final_path) {
+        let blocked = quarantine::quarantine(&final_path).map_err(|e| {
+            log::error!("Failed to quarantine {:?}: {}", final_path, e);
+            let _ = std::fs::remove_file(&final_path);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        log::warn!("Quarantined '{}' as {:?}", file_info.name, blocked);
+        file_info.path = blocked.clone();
+        file_info.quarantined = true;
+        blocked
+    } else {
+        final_path
+    };
+    let storage_permissions = ConfigData::instance()
+        .map(|instance| instance.lock().unwrap().storage.permissions.clone())
+        .unwrap_or_default();
+    file_info.mode = permissions::apply(
+        &storage_permissions,
+        &final_path,
+        file_info.mode,
+        file_info.quarantined,
+    );
+    // Files hashed while they were written keep that checksum
+    if file_info.sha256.is_none() {
+        file_info.sha256 = scrub::checksum(final_path.clone()).await;
+    }
+
+    // Hold the upload for review if the name is already taken
+    let matching = ConfigData::instance()
+        .map(|instance| {
+            NameMatch::from_config(instance.lock().unwrap().storage.case_insensitive_names)
+        })
+        .unwrap_or_default();
+    let name_taken = file_list
+        .lock()
+        .unwrap()
+        .get_file_by_name(&file_info.name, matching)
+        .is_some();
+    let resolution = if name_taken {
+        log::info!(
+            "File '{}' already exists, waiting for the user to review",
+            file_info.name
+        );
+        integrations::emit(ServerEvent::FileConflict {
+            id: file_info.id.clone(),
+            name: file_info.name.clone(),
+            room: room.map(str::to_string),
+        });
+        let timeout = {
+            let instance = ConfigData::instance().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            let config = instance.lock().unwrap();
+            std::time::Duration::from_secs(config.server.conflict_timeout_secs)
+        };
+        let conflict = PendingConflict {
+            id: file_info.id.clone(),
+            name: file_info.name.clone(),
+            size: file_info.size,
+            room: room.map(str::to_string),
+        };
+        Some(state.conflicts.review(conflict, timeout).await)
+    } else {
+        None
+    };
+
+    // List the file and record it in one step, so every listed file is
+    // recorded and, after a crash, every recorded one is on disk
+    let listed = {
+        let mut file_list = file_list.lock().unwrap();
+        let listed = records::list_received(
+            &mut file_list,
+            state.metadata.as_ref(),
+            room,
+            unix_now(),
+            |file_list| match resolution {
+                Some(resolution) => conflicts::apply(file_list, file_info, resolution, matching),
+                None => {
+                    file_list.add_file(file_info.clone());
+                    (Some(file_info), None)
+                }
+            },
+        );
+        log::debug!(
+            "Web upload: Updated server file list. Total files: {}",
+            file_list.len()
+        );
+        listed
+    };
+    let (added, replaced) = listed.map_err(|e| {
+        log::error!("Failed to record {:?}, not listing it: {}", final_path, e);
+        let _ = std::fs::remove_file(&final_path);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if let Some(replaced) = replaced {
+        transfers::cancel_file(&replaced.id);
+        if !replaced.shared {
+            if let Err(e) = std::fs::remove_file(&replaced.path) {
+                log::warn!("Failed to remove replaced file {:?}: {}", replaced.path, e);
+            }
+        }
+        integrations::emit(ServerEvent::FileDeleted {
+            id: replaced.id,
+            name: replaced.name,
+            room: room.map(str::to_string),
+        });
+    }
+
+    let Some(file_info) = added else {
+        log::info!("Discarded incoming duplicate {:?}", final_path);
+        if let Err(e) = std::fs::remove_file(&final_path) {
+            log::warn!("Failed to remove discarded file {:?}: {}", final_path, e);
+        }
+        return Err(StatusCode::CONFLICT);
+    };
+
+    log::info!(
+        "Successfully completed upload process for file: {}",
+        file_info.name
+    );
+    integrations::emit(records::received(&file_info, room));
+    if file_info.quarantined {
+        integrations::emit(ServerEvent::FileQuarantined {
+            id: file_info.id.clone(),
+            name: file_info.name.clone(),
+            room: room.map(str::to_string),
+        });
+    }
+    Ok(Json(file_info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listener_urls() {
+        let addresses = parse_bind_addresses(&[
+            "0.0.0.0".to_string(),
+            "100.64.0.5".to_string(),
+            "192.168.1.20".to_string(),
+            "::1".to_string(),
+        ])
+        .unwrap();
+        let urls = listener_urls("http", &addresses, 8080, "192.168.1.20".parse().unwrap());
+        assert_eq!(
+            urls,
+            vec![
+                "http://192.168.1.20:8080",
+                "http://100.64.0.5:8080",
+                "http://[::1]:8080",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_bind_addresses() {
+        assert_eq!(
+            parse_bind_addresses(&[]).unwrap(),
+            vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED)]
+        );
+        assert!(parse_bind_addresses(&["tailscale".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_remove_received() {
+        let dir = tempfile::tempdir().unwrap();
+        let phone: IpAddr = "192.168.1.20".parse().unwrap();
+        let file = |id: &str, shared: bool| {
+            let path = dir.path().join(id);
+            std::fs::write(&path, id).unwrap();
+            FileInfo {
+                id: id.to_string(),
+                mime_type: "text/plain".to_string(),
+                shared,
+                added_at: 1000,
+                device: Some(phone).filter(|_| !shared),
+                ..FileInfo::new(id.to_string(), path, id.len() as u64)
+            }
+        };
+        let file_list = Mutex::new(FileList::new());
+        file_list
+            .lock()
+            .unwrap()
+            .add_file(file("mistake.txt", false));
+        file_list.lock().unwrap().add_file(file("notes.txt", true));
+        let from_phone = |file: &FileInfo| file.device == Some(phone);
+
+        assert_eq!(
+            remove_received(&file_list, "missing.txt", from_phone),
+            Err(StatusCode::NOT_FOUND)
+        );
+        // Only its sender may delete a file, and shared files stay
+        assert_eq!(
+            remove_received(&file_list, "mistake.txt", |_| false),
+            Err(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            remove_received(&file_list, "notes.txt", |_| true),
+            Err(StatusCode::FORBIDDEN)
+        );
+        assert!(dir.path().join("notes.txt").exists());
+
+        let removed = remove_received(&file_list, "mistake.txt", from_phone).unwrap();
+        assert_eq!(removed.name, "mistake.txt");
+        assert!(!removed.path.exists());
+        assert_eq!(file_list.lock().unwrap().len(), 1);
+    }
+}
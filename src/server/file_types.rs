@@ -0,0 +1,123 @@
+//! Which kinds of files the server accepts. Hosts who only want photos or
+//! documents list them in `security.allowed_extensions`, and kinds that are
+//! never welcome, such as executables, in `security.blocked_extensions`.
+//! Entries are extensions (`jpg`, `.tar.gz`) or MIME classes (`image/*`,
+//! `application/pdf`), matched against the uploaded file's name.
+
+use crate::config::SecurityConfig;
+
+/// One entry of a list of file kinds, such as an allow or block list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    /// Lowercase, with a leading dot
+    Extension(String),
+    /// Lowercase MIME type, or `type/*` for a whole class
+    Mime(String),
+}
+
+impl Rule {
+    pub fn parse(entry: &str) -> Option<Self> {
+        let entry = entry.trim().to_lowercase();
+        if entry.is_empty() || entry == "." {
+            None
+        } else if entry.contains('/') {
+            Some(Rule::Mime(entry))
+        } else if entry.starts_with('.') {
+            Some(Rule::Extension(entry))
+        } else {
+            Some(Rule::Extension(format!(".{}", entry)))
+        }
+    }
+
+    /// Whether a file called `name` is of this kind
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Rule::Extension(extension) => name.to_lowercase().ends_with(extension.as_str()),
+            Rule::Mime(pattern) => {
+                mime_guess::from_path(name)
+                    .iter()
+                    .any(|mime| match pattern.strip_suffix("/*") {
+                        Some(class) => mime.type_().as_str() == class,
+                        None => mime.essence_str() == pattern,
+                    })
+            }
+        }
+    }
+}
+
+/// Allow and block lists for uploaded files
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileTypes {
+    allowed: Vec<Rule>,
+    blocked: Vec<Rule>,
+}
+
+impl FileTypes {
+    pub fn from_config(config: &SecurityConfig) -> Self {
+        Self {
+            allowed: config
+                .allowed_extensions
+                .iter()
+                .filter_map(|entry| Rule::parse(entry))
+                .collect(),
+            blocked: config
+                .blocked_extensions
+                .iter()
+                .filter_map(|entry| Rule::parse(entry))
+                .collect(),
+        }
+    }
+
+    /// Whether a file called `name` may be uploaded. Blocked kinds are
+    /// refused even if also allowed; with an allow list, only its kinds
+    /// are accepted.
+    pub fn accepts(&self, name: &str) -> bool {
+        if self.blocked.iter().any(|rule| rule.matches(name)) {
+            return false;
+        }
+        self.allowed.is_empty() || self.allowed.iter().any(|rule| rule.matches(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lists(allowed: &[&str], blocked: &[&str]) -> FileTypes {
+        FileTypes::from_config(&SecurityConfig {
+            allowed_extensions: allowed.iter().map(|entry| entry.to_string()).collect(),
+            blocked_extensions: blocked.iter().map(|entry| entry.to_string()).collect(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_accepts_everything_by_default() {
+        let file_types = FileTypes::default();
+        assert!(file_types.accepts("setup.exe"));
+        assert!(file_types.accepts("README"));
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let file_types = lists(&["image/*", "pdf", ".tar.gz"], &[]);
+        assert!(file_types.accepts("holiday.JPG"));
+        assert!(file_types.accepts("scan.pdf"));
+        assert!(file_types.accepts("backup.tar.gz"));
+        assert!(!file_types.accepts("backup.tar"));
+        assert!(!file_types.accepts("setup.exe"));
+        assert!(!file_types.accepts("README"));
+    }
+
+    #[test]
+    fn test_block_list_wins() {
+        let file_types = lists(&["image/*"], &["image/svg+xml", "EXE", " "]);
+        assert!(file_types.accepts("photo.png"));
+        assert!(!file_types.accepts("drawing.svg"));
+        assert!(!file_types.accepts("photo.png.exe"));
+
+        let file_types = lists(&[], &["exe", "application/x-msdownload"]);
+        assert!(!file_types.accepts("Setup.EXE"));
+        assert!(file_types.accepts("notes.txt"));
+    }
+}
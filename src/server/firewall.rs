@@ -0,0 +1,267 @@
+use std::process::Command;
+
+use super::network::run;
+
+/// Name used for firewall rules created by JusTrans
+const RULE_NAME: &str = "JusTrans";
+
+/// Whether the OS firewall is likely to let inbound connections through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirewallStatus {
+    Allowed,
+    Blocked,
+    Unknown,
+}
+
+/// Result of a scripted firewall check
+#[derive(Debug, Clone)]
+pub struct FirewallCheck {
+    pub status: FirewallStatus,
+    pub backend: &'static str,
+    pub detail: String,
+    pub suggested_command: Option<String>,
+}
+
+impl FirewallCheck {
+    fn unknown(detail: impl Into<String>) -> Self {
+        Self {
+            status: FirewallStatus::Unknown,
+            backend: "none",
+            detail: detail.into(),
+            suggested_command: None,
+        }
+    }
+
+    /// Short message suitable for the status bar
+    pub fn status_message(&self) -> String {
+        match self.status {
+            FirewallStatus::Allowed => {
+                format!("Firewall ({}) allows the server port", self.backend)
+            }
+            FirewallStatus::Blocked => format!(
+                "Firewall ({}) may block the server port - add a rule",
+                self.backend
+            ),
+            FirewallStatus::Unknown => "Firewall status unknown".to_string(),
+        }
+    }
+}
+
+/// Interpret the output of `ufw status`
+pub fn parse_ufw_status(output: &str, port: u16) -> FirewallStatus {
+    if !output.contains("Status: active") {
+        return FirewallStatus::Allowed;
+    }
+    let port_token = port.to_string();
+    let allowed = output.lines().any(|line| {
+        let mut parts = line.split_whitespace();
+        let target = parts.next().unwrap_or("");
+        let target_port = target.split('/').next().unwrap_or("");
+        target_port == port_token && line.contains("ALLOW")
+    });
+    if allowed {
+        FirewallStatus::Allowed
+    } else {
+        FirewallStatus::Blocked
+    }
+}
+
+/// Interpret the output of `firewall-cmd --list-ports`
+pub fn parse_firewalld_ports(output: &str, port: u16) -> FirewallStatus {
+    let expected = format!("{}/tcp", port);
+    if output.split_whitespace().any(|p| p == expected) {
+        FirewallStatus::Allowed
+    } else {
+        FirewallStatus::Blocked
+    }
+}
+
+/// Interpret the output of `netsh advfirewall firewall show rule name=JusTrans`
+pub fn parse_netsh_rule(output: &str, port: u16) -> FirewallStatus {
+    if output.contains("No rules match") {
+        return FirewallStatus::Blocked;
+    }
+    let port_token = port.to_string();
+    let has_port = output.lines().any(|line| {
+        line.trim_start().starts_with("LocalPort")
+            && line
+                .split(':')
+                .nth(1)
+                .map(|ports| ports.split(',').any(|p| p.trim() == port_token))
+                .unwrap_or(false)
+    });
+    if has_port {
+        FirewallStatus::Allowed
+    } else {
+        FirewallStatus::Blocked
+    }
+}
+
+fn check_linux(port: u16) -> FirewallCheck {
+    if let Some(output) = run("ufw", &["status"]) {
+        if output.contains("Status:") {
+            let status = parse_ufw_status(&output, port);
+            return FirewallCheck {
+                status,
+                backend: "ufw",
+                detail: output.trim().to_string(),
+                suggested_command: Some(format!("sudo ufw allow {}/tcp", port)),
+            };
+        }
+    }
+
+    if let Some(state) = run("firewall-cmd", &["--state"]) {
+        if state.trim() == "running" {
+            let ports = run("firewall-cmd", &["--list-ports"]).unwrap_or_default();
+            return FirewallCheck {
+                status: parse_firewalld_ports(&ports, port),
+                backend: "firewalld",
+                detail: format!("Open ports: {}", ports.trim()),
+                suggested_command: Some(format!(
+                    "sudo firewall-cmd --permanent --add-port={}/tcp && sudo firewall-cmd --reload",
+                    port
+                )),
+            };
+        }
+    }
+
+    FirewallCheck::unknown("No supported firewall frontend (ufw, firewalld) detected")
+}
+
+fn check_windows(port: u16) -> FirewallCheck {
+    let rule_arg = format!("name={}", RULE_NAME);
+    // netsh fails when no rule has the name
+    let output = run(
+        "netsh",
+        &["advfirewall", "firewall", "show", "rule", &rule_arg],
+    );
+    FirewallCheck {
+        status: output
+            .as_deref()
+            .map_or(FirewallStatus::Blocked, |output| parse_netsh_rule(output, port)),
+        backend: "Windows Defender Firewall",
+        detail: output.map_or_else(
+            || format!("No {} rule found", RULE_NAME),
+            |output| output.trim().to_string(),
+        ),
+        suggested_command: Some(format!(
+            "netsh advfirewall firewall add rule name=\"{}\" dir=in action=allow protocol=TCP localport={}",
+            RULE_NAME, port
+        )),
+    }
+}
+
+fn check_macos() -> FirewallCheck {
+    let socketfilterfw = "/usr/libexec/ApplicationFirewall/socketfilterfw";
+    match run(socketfilterfw, &["--getglobalstate"]) {
+        Some(output) => {
+            let enabled = output.contains("enabled") || output.contains("State = 1");
+            let exe = std::env::current_exe()
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|_| "justrans".to_string());
+            FirewallCheck {
+                status: if enabled {
+                    FirewallStatus::Blocked
+                } else {
+                    FirewallStatus::Allowed
+                },
+                backend: "Application Firewall",
+                detail: output.trim().to_string(),
+                suggested_command: Some(format!(
+                    "sudo {0} --add \"{1}\" && sudo {0} --unblockapp \"{1}\"",
+                    socketfilterfw, exe
+                )),
+            }
+        }
+        None => FirewallCheck::unknown("Could not query the application firewall"),
+    }
+}
+
+/// Check whether the OS firewall is likely to block inbound TCP on `port`
+pub fn check_port(port: u16) -> FirewallCheck {
+    if cfg!(target_os = "windows") {
+        check_windows(port)
+    } else if cfg!(target_os = "macos") {
+        check_macos()
+    } else {
+        check_linux(port)
+    }
+}
+
+/// Try to create the suggested firewall rule.
+///
+/// This needs elevated privileges: Linux asks through `pkexec`, macOS through
+/// an administrator prompt, and on Windows JusTrans must already run elevated.
+pub fn add_rule(check: &FirewallCheck) -> anyhow::Result<()> {
+    let command = check
+        .suggested_command
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("No firewall rule command available"))?;
+
+    let status = if cfg!(target_os = "windows") {
+        Command::new("cmd").args(["/C", command]).status()?
+    } else if cfg!(target_os = "macos") {
+        let script = format!(
+            "do shell script \"{}\" with administrator privileges",
+            command.replace("sudo ", "").replace('"', "\\\"")
+        );
+        Command::new("osascript").args(["-e", &script]).status()?
+    } else {
+        Command::new("pkexec")
+            .args(["sh", "-c", &command.replace("sudo ", "")])
+            .status()?
+    };
+
+    if status.success() {
+        log::info!("Firewall rule added with: {}", command);
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "Command exited with {}. Run it manually: {}",
+            status,
+            command
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ufw_status() {
+        assert_eq!(
+            parse_ufw_status("Status: inactive\n", 8080),
+            FirewallStatus::Allowed
+        );
+
+        let active = "Status: active\n\nTo                         Action      From\n--                         ------      ----\n22/tcp                     ALLOW       Anywhere\n";
+        assert_eq!(parse_ufw_status(active, 8080), FirewallStatus::Blocked);
+
+        let allowed = format!(
+            "{}8080/tcp                   ALLOW       Anywhere\n",
+            active
+        );
+        assert_eq!(parse_ufw_status(&allowed, 8080), FirewallStatus::Allowed);
+    }
+
+    #[test]
+    fn test_parse_netsh_rule() {
+        assert_eq!(
+            parse_netsh_rule("No rules match the specified criteria.", 8080),
+            FirewallStatus::Blocked
+        );
+        let rule = "Rule Name:        JusTrans\nLocalPort:        8080\nAction:           Allow\n";
+        assert_eq!(parse_netsh_rule(rule, 8080), FirewallStatus::Allowed);
+        assert_eq!(parse_netsh_rule(rule, 9000), FirewallStatus::Blocked);
+    }
+
+    #[test]
+    fn test_parse_firewalld_ports() {
+        assert_eq!(
+            parse_firewalld_ports("8080/tcp 22/tcp", 8080),
+            FirewallStatus::Allowed
+        );
+        assert_eq!(parse_firewalld_ports("", 8080), FirewallStatus::Blocked);
+    }
+}
@@ -0,0 +1,283 @@
+//! Uploads from a plain HTML form, for text browsers and old devices that
+//! don't run the portal's JavaScript. Files arrive whole in one request,
+//! streamed to disk as they come, and the browser is sent on to a simple
+//! confirmation page.
+
+use std::path::Path;
+
+use axum::extract::multipart::Field;
+use axum::extract::{DefaultBodyLimit, Multipart, Query, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+
+use super::client_ip::ClientIp;
+use super::file_server::{add_received_file, AppState};
+use super::protected;
+use super::sanitize::{escape_html, sanitize_name};
+use crate::models::FileInfo;
+
+/// Page the browser is sent to after an upload
+const DONE_PATH: &str = "/uploaded";
+
+/// Room in a form's body for its other fields and the part headers
+const FORM_OVERHEAD: u64 = 1024 * 1024;
+
+/// A minimal page with a link back to the portal
+fn page(title: &str, message: &str) -> Html<String> {
+    Html(format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"UTF-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">\n\
+         <title>JusTrans - {title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n\
+         <p>{message}</p>\n<p><a href=\"/\">Back to JusTrans</a></p>\n</body>\n</html>\n",
+        title = escape_html(title),
+        message = escape_html(message),
+    ))
+}
+
+fn failed(status: StatusCode, message: &str) -> Response {
+    (status, page("Upload failed", message)).into_response()
+}
+
+/// Write the file in `field` to `path` as it arrives, returning its size.
+/// A body going over the limit is answered with `413`.
+async fn write_file(field: &mut Field<'_>, path: &Path) -> Result<u64, StatusCode> {
+    let mut file = tokio::fs::File::create(path).await.map_err(|e| {
+        log::error!("Failed to create {:?}: {}", path, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let mut size = 0;
+    loop {
+        let chunk = match field.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                log::warn!("Form upload ended early: {}", e);
+                return Err(e.status());
+            }
+        };
+        size += chunk.len() as u64;
+        file.write_all(&chunk).await.map_err(|e| {
+            log::error!("Failed to write {:?}: {}", path, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    }
+    file.flush().await.map_err(|e| {
+        log::error!("Failed to write {:?}: {}", path, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(size)
+}
+
+#[axum::debug_handler]
+async fn form_upload(
+    ClientIp(client_ip): ClientIp,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Response {
+    if state.read_only() {
+        log::warn!("Rejected form upload to read-only server");
+        return failed(StatusCode::FORBIDDEN, "This share does not accept files.");
+    }
+    if let Err(e) = tokio::fs::create_dir_all(&state.scratch_dir).await {
+        log::error!("Failed to create {:?}: {}", state.scratch_dir, e);
+        return failed(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "The file could not be saved.",
+        );
+    }
+
+    let mut received = 0;
+    // The form puts the password before the files it protects
+    let mut password_hash = None;
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                log::warn!("Form upload ended early: {}", e);
+                return failed(
+                    e.status(),
+                    "The upload was interrupted or is larger than this share accepts.",
+                );
+            }
+        };
+        if field.name() == Some(protected::PASSWORD_FIELD) {
+            password_hash = match protected::hash(field.text().await.ok()).await {
+                Ok(hash) => hash,
+                Err(status) => return failed(status, "The password could not be set."),
+            };
+            continue;
+        }
+        // A file input left empty still sends a part, without a name
+        let Some(name) = field
+            .file_name()
+            .filter(|name| !name.is_empty())
+            .map(sanitize_name)
+        else {
+            continue;
+        };
+        if !state.file_types.accepts(&name) {
+            log::warn!("Rejected '{}': kind of file not accepted", name);
+            return failed(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                &format!("'{}' is not a kind of file this share accepts.", name),
+            );
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let path = state.scratch_dir.join(format!("{}_file", id));
+        let size = match write_file(&mut field, &path).await {
+            Ok(size) => size,
+            Err(status) => {
+                log::warn!("Failed to receive '{}' from a form", name);
+                let _ = tokio::fs::remove_file(&path).await;
+                let message = if status == StatusCode::PAYLOAD_TOO_LARGE {
+                    "The upload is larger than this share accepts."
+                } else {
+                    "The upload was interrupted or could not be saved."
+                };
+                return failed(status, message);
+            }
+        };
+
+        let file_info = FileInfo {
+            id,
+            protected: password_hash.is_some(),
+            password_hash: password_hash.clone(),
+            device: client_ip,
+            ..FileInfo::new(name, path, size)
+        };
+        if let Err(status) =
+            add_received_file(&state, &state.file_list, &state.temp_dir, None, file_info).await
+        {
+            return failed(status, "The file was not accepted by this share.");
+        }
+        received += 1;
+    }
+
+    log::info!("Received {} file(s) from a form upload", received);
+    Redirect::to(&format!("{}?count={}", DONE_PATH, received)).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct Done {
+    #[serde(default)]
+    count: usize,
+}
+
+#[axum::debug_handler]
+async fn uploaded(Query(done): Query<Done>) -> Html<String> {
+    match done.count {
+        0 => page("No files received", "No file was selected."),
+        1 => page("File received", "1 file was received."),
+        count => page("Files received", &format!("{} files were received.", count)),
+    }
+}
+
+/// The confirmation page, outside the API
+pub fn router() -> Router<AppState> {
+    Router::new().route(DONE_PATH, get(uploaded))
+}
+
+/// Form upload target, nested under the versioned API prefix. A form
+/// can't split files into chunks, so its body may be as large as
+/// `max_file_size`, the largest file the segment upload takes; files are
+/// written to disk as they arrive and quotas apply once they are complete
+pub fn api_router(max_file_size: u64) -> Router<AppState> {
+    let body_limit = max_file_size.saturating_add(FORM_OVERHEAD);
+    Router::new().route(
+        "/form-upload",
+        post(form_upload).layer(DefaultBodyLimit::max(
+            usize::try_from(body_limit).unwrap_or(usize::MAX),
+        )),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_confirmation_page() {
+        let Html(html) = uploaded(Query(Done { count: 2 })).await;
+        assert!(html.contains("2 files were received."));
+        assert!(html.contains("<a href=\"/\">"));
+
+        let Html(html) = page("Upload failed", "<script>");
+        assert!(html.contains("&lt;script&gt;"));
+    }
+
+    /// Serve a route writing the first file of a form to `path`, taking
+    /// bodies up to 4 KB
+    async fn serve_form(path: &Path) -> std::net::SocketAddr {
+        let target = path.to_path_buf();
+        let app = Router::new().route(
+            "/",
+            post(move |mut multipart: Multipart| async move {
+                let mut field = match multipart.next_field().await {
+                    Ok(Some(field)) => field,
+                    Ok(None) => return StatusCode::BAD_REQUEST,
+                    Err(e) => return e.status(),
+                };
+                match write_file(&mut field, &target).await {
+                    Ok(_) => StatusCode::OK,
+                    Err(status) => status,
+                }
+            })
+            .layer(DefaultBodyLimit::max(4096)),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        addr
+    }
+
+    /// Send a form with one file of `size` bytes in chunks, as a client
+    /// that doesn't say the length up front, returning the status line
+    async fn post_chunked(addr: std::net::SocketAddr, size: usize) -> String {
+        use tokio::io::AsyncReadExt;
+
+        let mut form =
+            b"--x\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.bin\"\r\n\r\n"
+                .to_vec();
+        form.extend(vec![b'a'; size]);
+        form.extend(b"\r\n--x--\r\n");
+        let mut request = b"POST / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+            Content-Type: multipart/form-data; boundary=x\r\n\
+            Transfer-Encoding: chunked\r\n\r\n"
+            .to_vec();
+        for chunk in form.chunks(1024) {
+            request.extend(format!("{:x}\r\n", chunk.len()).as_bytes());
+            request.extend(chunk);
+            request.extend(b"\r\n");
+        }
+        request.extend(b"0\r\n\r\n");
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        // The server may answer and close before it read everything
+        let _ = stream.write_all(&request).await;
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response).await;
+        let response = String::from_utf8_lossy(&response);
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_body_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.bin");
+        let addr = serve_form(&path).await;
+        assert_eq!(post_chunked(addr, 1000).await, "HTTP/1.1 200 OK");
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 1000);
+
+        // Found out while the file is being written
+        assert_eq!(
+            post_chunked(addr, 10_000).await,
+            "HTTP/1.1 413 Payload Too Large"
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Security headers on every response. The portal shows file names and text
+//! snippets from anyone on the network, so browsers are told to only run the
+//! portal's own scripts, not to guess content types of downloads and not to
+//! let other sites frame the portal.
+
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+
+use super::file_server::AppState;
+use crate::config::SecurityHeadersConfig;
+
+/// Headers added to responses that don't set them themselves
+#[derive(Debug, Clone, Default)]
+pub struct SecurityHeaders {
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl SecurityHeaders {
+    pub fn from_config(config: &SecurityHeadersConfig) -> Self {
+        let policy =
+            content_security_policy(&config.content_security_policy, &config.frame_ancestors);
+        let mut headers = vec![(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        )];
+        for (name, value) in [
+            (header::CONTENT_SECURITY_POLICY, policy.as_str()),
+            (header::REFERRER_POLICY, config.referrer_policy.trim()),
+        ] {
+            if value.is_empty() {
+                continue;
+            }
+            match HeaderValue::from_str(value) {
+                Ok(value) => headers.push((name, value)),
+                Err(_) => log::error!("Invalid {} in security.headers: {:?}", name, value),
+            }
+        }
+        Self { headers }
+    }
+}
+
+/// The configured policy with `frame_ancestors` added, unless the policy
+/// already restricts framing itself
+fn content_security_policy(policy: &str, frame_ancestors: &str) -> String {
+    let policy = policy.trim().trim_end_matches(';').trim();
+    let frame_ancestors = frame_ancestors.trim();
+    let has_frame_ancestors = policy.split(';').any(|directive| {
+        directive
+            .split_whitespace()
+            .next()
+            .is_some_and(|name| name.eq_ignore_ascii_case("frame-ancestors"))
+    });
+    if frame_ancestors.is_empty() || has_frame_ancestors {
+        policy.to_string()
+    } else if policy.is_empty() {
+        format!("frame-ancestors {}", frame_ancestors)
+    } else {
+        format!("{}; frame-ancestors {}", policy, frame_ancestors)
+    }
+}
+
+/// Add the security headers to every response
+pub async fn add_security_headers(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    for (name, value) in &state.security_headers.headers {
+        if !headers.contains_key(name) {
+            headers.insert(name.clone(), value.clone());
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_security_policy() {
+        assert_eq!(
+            content_security_policy("default-src 'self';", "'none'"),
+            "default-src 'self'; frame-ancestors 'none'"
+        );
+        assert_eq!(
+            content_security_policy(
+                "default-src 'self'; frame-ancestors https://ha.local",
+                "'none'"
+            ),
+            "default-src 'self'; frame-ancestors https://ha.local"
+        );
+        assert_eq!(
+            content_security_policy("", "'self'"),
+            "frame-ancestors 'self'"
+        );
+        assert_eq!(
+            content_security_policy("default-src *", ""),
+            "default-src *"
+        );
+    }
+
+    #[test]
+    fn test_default_headers() {
+        let headers = SecurityHeaders::from_config(&SecurityHeadersConfig::default()).headers;
+        let value = |name: HeaderName| {
+            headers
+                .iter()
+                .find(|(header, _)| *header == name)
+                .map(|(_, value)| value.to_str().unwrap().to_string())
+        };
+        assert_eq!(value(header::X_CONTENT_TYPE_OPTIONS).unwrap(), "nosniff");
+        assert_eq!(value(header::REFERRER_POLICY).unwrap(), "no-referrer");
+        let policy = value(header::CONTENT_SECURITY_POLICY).unwrap();
+        assert!(policy.contains("script-src 'self';"));
+        assert!(policy.ends_with("; frame-ancestors 'none'"));
+
+        let without_referrer = SecurityHeadersConfig {
+            referrer_policy: String::new(),
+            ..SecurityHeadersConfig::default()
+        };
+        assert_eq!(
+            SecurityHeaders::from_config(&without_referrer)
+                .headers
+                .len(),
+            2
+        );
+    }
+}
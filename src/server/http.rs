@@ -0,0 +1,338 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::extract::ConnectInfo;
+use axum::Router;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::sync::watch;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tower_http::add_extension::AddExtension;
+
+use super::stats::ServerStats;
+use crate::config::ServerConfig;
+use crate::integrations::{self, ServerEvent};
+
+/// Time an HTTP/2 client gets to answer a keep-alive ping
+const PING_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Time a client gets to complete the TLS handshake, or to send its first
+/// bytes when HTTP/2 is turned off
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections waiting to be accepted before new ones are refused
+const BACKLOG: u32 = 1024;
+
+/// First bytes an HTTP/2 client with prior knowledge sends
+const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Listen on `addr` without waiting, so a server can be started from
+/// synchronous code running on the runtime
+pub fn listen(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    // A restarted server gets its port back while old connections linger
+    #[cfg(unix)]
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    socket.listen(BACKLOG)
+}
+
+/// Connection handling of the portal listeners
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HttpTuning {
+    /// Accept HTTP/2 next to HTTP/1.1
+    pub http2: bool,
+    /// How long idle connections stay open, `None` closes them after each request
+    pub keep_alive: Option<Duration>,
+    /// Requests in flight per HTTP/2 connection
+    pub max_concurrent_streams: u32,
+}
+
+impl HttpTuning {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        Self {
+            http2: config.http2,
+            keep_alive: (config.keep_alive_secs > 0)
+                .then(|| Duration::from_secs(config.keep_alive_secs)),
+            max_concurrent_streams: config.max_concurrent_streams,
+        }
+    }
+
+    fn builder(&self) -> auto::Builder<TokioExecutor> {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .keep_alive(self.keep_alive.is_some())
+            // Also closes keep-alive connections that stay idle this long
+            .header_read_timeout(self.keep_alive);
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .max_concurrent_streams(self.max_concurrent_streams)
+            .keep_alive_interval(self.keep_alive)
+            .keep_alive_timeout(PING_TIMEOUT);
+        if self.http2 {
+            builder
+        } else {
+            builder.http1_only()
+        }
+    }
+}
+
+/// Whether a plain connection opens with the HTTP/2 preface. The auto
+/// builder detects HTTP/2 by it even when told to speak HTTP/1 only. The
+/// bytes are only peeked, so they are still there for the connection.
+async fn opens_h2(stream: &TcpStream) -> bool {
+    let mut start = [0; 4];
+    match stream.peek(&mut start).await {
+        // No HTTP/1 method starts like the preface
+        Ok(read) => read == start.len() && H2_PREFACE.starts_with(&start),
+        Err(_) => false,
+    }
+}
+
+impl Default for HttpTuning {
+    fn default() -> Self {
+        Self::from_config(&ServerConfig::default())
+    }
+}
+
+/// Serve `app` on `listener` until `shutdown` changes, then wait for open
+/// connections to finish their requests. Connections are encrypted with
+/// `tls` if given; without it HTTP/2 is spoken with clients that start with
+/// the HTTP/2 preface (h2c).
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    tuning: HttpTuning,
+    tls: Option<TlsAcceptor>,
+    stats: ServerStats,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let builder = tuning.builder();
+    let http2 = tuning.http2;
+    let graceful = GracefulShutdown::new();
+    // Reported once, not for every failed accept
+    let mut failing = false;
+
+    loop {
+        let (stream, remote) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // Usually out of file descriptors, give connections time to close
+                    log::warn!("Failed to accept connection: {}", e);
+                    if !failing {
+                        failing = true;
+                        integrations::emit(ServerEvent::ServerError {
+                            message: format!("Failed to accept connections: {}", e),
+                        });
+                    }
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            _ = shutdown.changed() => break,
+        };
+        failing = false;
+        // Small responses should not wait for more data to fill a packet
+        let _ = stream.set_nodelay(true);
+
+        // Handlers see the client address through `ConnectInfo`
+        let service = TowerToHyperService::new(AddExtension::new(app.clone(), ConnectInfo(remote)));
+        let builder = builder.clone();
+        // Taken before the handshake, so stopping waits for it or cuts it short
+        let watcher = graceful.watcher();
+        let tls = tls.clone();
+        let mut stopping = shutdown.clone();
+        let open = stats.connect(remote.ip());
+        // Reported when a device arrives, not for each of its connections
+        if open.is_first() {
+            integrations::emit(ServerEvent::ClientConnected {
+                address: remote.ip().to_canonical().to_string(),
+            });
+        }
+        // The handshake runs on the connection's task, not holding up others
+        tokio::spawn(async move {
+            let result = match tls {
+                Some(tls) => {
+                    let handshake = tokio::select! {
+                        handshake = timeout(HANDSHAKE_TIMEOUT, tls.accept(stream)) => handshake,
+                        _ = stopping.changed() => return,
+                    };
+                    match handshake {
+                        Ok(Ok(stream)) => {
+                            let connection = builder
+                                .serve_connection_with_upgrades(TokioIo::new(stream), service);
+                            watcher.watch(connection.into_owned()).await
+                        }
+                        Ok(Err(e)) => {
+                            log::debug!("TLS handshake with {} failed: {}", remote, e);
+                            return;
+                        }
+                        Err(_) => {
+                            log::debug!("TLS handshake with {} timed out", remote);
+                            return;
+                        }
+                    }
+                }
+                None => {
+                    if !http2 {
+                        let opened = tokio::select! {
+                            opened = timeout(HANDSHAKE_TIMEOUT, opens_h2(&stream)) => opened,
+                            _ = stopping.changed() => return,
+                        };
+                        match opened {
+                            Ok(false) => {}
+                            Ok(true) => {
+                                log::debug!("Refused HTTP/2 from {} as it is turned off", remote);
+                                return;
+                            }
+                            Err(_) => {
+                                log::debug!("{} sent nothing in time", remote);
+                                return;
+                            }
+                        }
+                    }
+                    let connection =
+                        builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+                    watcher.watch(connection.into_owned()).await
+                }
+            };
+            if let Err(e) = result {
+                log::debug!("Connection from {} ended with error: {}", remote, e);
+            }
+            drop(open);
+        });
+    }
+
+    drop(listener);
+    graceful.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use std::time::Instant;
+
+    /// Number of small files fetched by the benchmark
+    const FILES: usize = 300;
+
+    async fn start(tuning: HttpTuning) -> (SocketAddr, watch::Sender<bool>) {
+        let app = Router::new().route("/files/:id", get(|| async { vec![b'x'; 4 * 1024] }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = watch::channel(false);
+        tokio::spawn(serve(
+            listener,
+            app,
+            tuning,
+            None,
+            ServerStats::default(),
+            rx,
+        ));
+        (addr, tx)
+    }
+
+    async fn fetch_sequential(client: &reqwest::Client, addr: SocketAddr) -> Duration {
+        let started = Instant::now();
+        for id in 0..FILES {
+            let body = client
+                .get(format!("http://{}/files/{}", addr, id))
+                .send()
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap();
+            assert_eq!(body.len(), 4 * 1024);
+        }
+        started.elapsed()
+    }
+
+    async fn fetch_concurrent(client: &reqwest::Client, addr: SocketAddr) -> Duration {
+        let started = Instant::now();
+        let mut requests = tokio::task::JoinSet::new();
+        for id in 0..FILES {
+            let request = client.get(format!("http://{}/files/{}", addr, id)).send();
+            requests.spawn(async move { request.await.unwrap().bytes().await.unwrap().len() });
+        }
+        while let Some(len) = requests.join_next().await {
+            assert_eq!(len.unwrap(), 4 * 1024);
+        }
+        started.elapsed()
+    }
+
+    #[tokio::test]
+    async fn test_http2_prior_knowledge() {
+        let h2_client = reqwest::Client::builder()
+            .http2_prior_knowledge()
+            .build()
+            .unwrap();
+
+        let (addr, _shutdown) = start(HttpTuning::default()).await;
+        let response = h2_client
+            .get(format!("http://{}/files/1", addr))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.version(), reqwest::Version::HTTP_2);
+
+        let (addr, _shutdown) = start(HttpTuning {
+            http2: false,
+            ..HttpTuning::default()
+        })
+        .await;
+        assert!(h2_client
+            .get(format!("http://{}/files/1", addr))
+            .send()
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_accepting() {
+        let (addr, shutdown) = start(HttpTuning::default()).await;
+        shutdown.send(true).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    }
+
+    /// Many small files: a new connection per request against keep-alive
+    /// and HTTP/2. Run with `cargo test --release -- --ignored bench_`.
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn bench_small_files() {
+        let (addr, _shutdown) = start(HttpTuning::default()).await;
+
+        let no_reuse = reqwest::Client::builder()
+            .pool_max_idle_per_host(0)
+            .build()
+            .unwrap();
+        let keep_alive = reqwest::Client::new();
+        let http2 = reqwest::Client::builder()
+            .http2_prior_knowledge()
+            .build()
+            .unwrap();
+
+        let per_request = fetch_sequential(&no_reuse, addr).await;
+        let reused = fetch_sequential(&keep_alive, addr).await;
+        let multiplexed = fetch_concurrent(&http2, addr).await;
+        println!(
+            "{} files of 4 KB: connection per request {:?}, keep-alive {:?}, HTTP/2 {:?}",
+            FILES, per_request, reused, multiplexed
+        );
+        assert!(reused < per_request);
+        assert!(multiplexed < per_request);
+    }
+}
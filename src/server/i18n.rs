@@ -0,0 +1,253 @@
+//! Languages of the portal. Catalogs map the English text of the page and
+//! of `portal.js` to its translation; English itself needs none. The page
+//! is served in the language of the `justrans_lang` cookie set by the
+//! portal's language switcher, else the first one of `Accept-Language`
+//! there is a catalog for, else English. Its static text is translated on
+//! the server, so it reads right before the script runs or without it, and
+//! the catalog is embedded for the script's own messages.
+
+use std::collections::BTreeMap;
+
+use axum::http::{header, HeaderMap};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use super::auth;
+
+/// Cookie holding the language picked on the portal
+pub const LANGUAGE_COOKIE: &str = "justrans_lang";
+
+/// Language of the text in the page and the script
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+/// Placeholder in the page the catalog is written over
+const CATALOG_PLACEHOLDER: &str = "I18N_CATALOG";
+
+/// Attributes holding text to translate
+const TEXT_ATTRIBUTES: &[&str] = &["placeholder", "aria-label", "alt"];
+
+/// Catalogs compiled into the binary, by language code
+const EMBEDDED: &[(&str, &str)] = &[
+    ("de", include_str!("../../assets/web/i18n/de.json")),
+    ("es", include_str!("../../assets/web/i18n/es.json")),
+    ("fr", include_str!("../../assets/web/i18n/fr.json")),
+];
+
+#[derive(Debug, Deserialize)]
+struct Catalog {
+    /// Name of the language in itself, for the switcher
+    name: String,
+    messages: BTreeMap<String, String>,
+}
+
+/// Catalogs by language code, English first
+static CATALOGS: Lazy<Vec<(&'static str, Catalog)>> = Lazy::new(|| {
+    let mut catalogs = vec![(
+        DEFAULT_LANGUAGE,
+        Catalog {
+            name: "English".to_string(),
+            messages: BTreeMap::new(),
+        },
+    )];
+    for &(code, json) in EMBEDDED {
+        match serde_json::from_str(json) {
+            Ok(catalog) => catalogs.push((code, catalog)),
+            Err(e) => log::error!("Failed to read the {} catalog: {}", code, e),
+        }
+    }
+    catalogs
+});
+
+/// What the script gets to translate its messages and show the switcher
+#[derive(Serialize)]
+struct ScriptCatalog<'a> {
+    lang: &'a str,
+    languages: Vec<(&'a str, &'a str)>,
+    messages: &'a BTreeMap<String, String>,
+}
+
+/// Codes of the languages the portal is available in, English first
+pub fn languages() -> impl Iterator<Item = &'static str> {
+    CATALOGS.iter().map(|(code, _)| *code)
+}
+
+/// The supported language `tag` is in, e.g. `de` for `de-AT`
+fn supported(tag: &str) -> Option<&'static str> {
+    let primary = tag.trim().split(['-', '_']).next()?.to_ascii_lowercase();
+    languages().find(|code| *code == primary)
+}
+
+/// The first supported language of an `Accept-Language` value, by weight
+fn from_accept_language(value: &str) -> Option<&'static str> {
+    let mut ranges: Vec<(&str, f32)> = value
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let tag = parts.next()?.trim();
+            let q = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            (!tag.is_empty() && q > 0.0).then_some((tag, q))
+        })
+        .collect();
+    // Stable, so ranges of equal weight keep their order
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranges.into_iter().find_map(|(tag, _)| supported(tag))
+}
+
+/// Language to serve the portal in for a request with `headers`
+pub fn negotiate(headers: &HeaderMap) -> &'static str {
+    if let Some(language) = auth::cookie(headers, LANGUAGE_COOKIE).and_then(|tag| supported(&tag)) {
+        return language;
+    }
+    headers
+        .get_all(header::ACCEPT_LANGUAGE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .find_map(from_accept_language)
+        .unwrap_or(DEFAULT_LANGUAGE)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The portal page `html` in `language`, with the catalog for its script
+pub fn localize(html: &str, language: &str) -> String {
+    let (code, catalog) = CATALOGS
+        .iter()
+        .find(|(code, _)| *code == language)
+        .unwrap_or(&CATALOGS[0]);
+
+    let mut html = html.replace("<html lang=\"en\">", &format!("<html lang=\"{}\">", code));
+    for (english, translated) in &catalog.messages {
+        let translated = escape_html(translated);
+        html = html.replace(&format!(">{}<", english), &format!(">{}<", translated));
+        for attribute in TEXT_ATTRIBUTES {
+            html = html.replace(
+                &format!("{}=\"{}\"", attribute, english),
+                &format!("{}=\"{}\"", attribute, translated),
+            );
+        }
+    }
+
+    let script = ScriptCatalog {
+        lang: code,
+        languages: CATALOGS
+            .iter()
+            .map(|(code, catalog)| (*code, catalog.name.as_str()))
+            .collect(),
+        messages: &catalog.messages,
+    };
+    // Nothing in the catalog may end the script element it is written into
+    let json = serde_json::to_string(&script)
+        .unwrap_or_default()
+        .replace('<', "\\u003c");
+    html.replace(CATALOG_PLACEHOLDER, &json)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    const INDEX: &str = include_str!("../../assets/web/index.html");
+    const SCRIPT: &str = include_str!("../../assets/web/portal.js");
+
+    fn placeholders(text: &str) -> Vec<&str> {
+        let mut names: Vec<&str> = text
+            .split('{')
+            .skip(1)
+            .filter_map(|part| part.split_once('}').map(|(name, _)| name))
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    #[test]
+    fn test_catalogs() {
+        assert_eq!(CATALOGS.len(), EMBEDDED.len() + 1);
+        let first = &CATALOGS[1].1.messages;
+        for (code, catalog) in CATALOGS.iter().skip(1) {
+            // Every catalog translates the same text, all of it still used
+            assert!(
+                catalog.messages.keys().eq(first.keys()),
+                "{} has other messages",
+                code
+            );
+            for (english, translated) in &catalog.messages {
+                assert!(
+                    INDEX.contains(&format!(">{}<", english))
+                        || INDEX.contains(&format!("=\"{}\"", english))
+                        || SCRIPT.contains(&format!("t('{}'", english))
+                        || SCRIPT.contains(&format!("t(\"{}\"", english)),
+                    "{:?} is not in the portal",
+                    english
+                );
+                assert_eq!(placeholders(english), placeholders(translated));
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_accept_language() {
+        assert_eq!(from_accept_language("de-DE,de;q=0.9,en;q=0.8"), Some("de"));
+        assert_eq!(from_accept_language("nl-NL, fr-BE;q=0.7"), Some("fr"));
+        assert_eq!(from_accept_language("en;q=0.5, es_MX"), Some("es"));
+        assert_eq!(from_accept_language("de;q=0, it"), None);
+        assert_eq!(from_accept_language("*"), None);
+        assert_eq!(from_accept_language(""), None);
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(negotiate(&headers), DEFAULT_LANGUAGE);
+
+        headers.insert(
+            header::ACCEPT_LANGUAGE,
+            HeaderValue::from_static("fr-FR,fr;q=0.9"),
+        );
+        assert_eq!(negotiate(&headers), "fr");
+
+        // The language picked on the portal wins, unless there is no such
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_static("justrans_session=abc; justrans_lang=es"),
+        );
+        assert_eq!(negotiate(&headers), "es");
+        headers.insert(header::COOKIE, HeaderValue::from_static("justrans_lang=xx"));
+        assert_eq!(negotiate(&headers), "fr");
+    }
+
+    #[test]
+    fn test_localize() {
+        let html = localize(INDEX, "de");
+        assert!(html.contains("<html lang=\"de\">"));
+        assert!(
+            html.contains("<button id=\"selectFileBtn\" class=\"btn\">Dateien auswählen</button>")
+        );
+        assert!(html.contains("placeholder=\"Dateien suchen\" aria-label=\"Dateien suchen\""));
+        assert!(!html.contains(CATALOG_PLACEHOLDER));
+
+        let start = html.find("type=\"application/json\">").unwrap() + 24;
+        let end = start + html[start..].find("</script>").unwrap();
+        let catalog: serde_json::Value = serde_json::from_str(&html[start..end]).unwrap();
+        assert_eq!(catalog["lang"], "de");
+        assert_eq!(
+            catalog["languages"][0],
+            serde_json::json!(["en", "English"])
+        );
+        assert_eq!(catalog["messages"]["Sign out"], "Abmelden");
+
+        // English is the page as written, with the switcher's languages
+        let html = localize(INDEX, DEFAULT_LANGUAGE);
+        assert!(html.contains("<h1>JusTrans File Exchange</h1>"));
+        assert!(html.contains("\"languages\":[[\"en\",\"English\"],[\"de\",\"Deutsch\"]"));
+        assert!(html.contains("\"messages\":{}"));
+    }
+}
@@ -0,0 +1,221 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use settings::Settings;
+
+use crate::config::ConfigData;
+
+/// Time without transfers before the computer may sleep again; bridges the
+/// gaps between the segments of an upload
+const RELEASE_DELAY: Duration = Duration::from_secs(30);
+
+/// Smallest upload or download that counts as a transfer
+const MIN_TRANSFER_BYTES: u64 = 1024 * 1024;
+
+/// Asks the OS not to suspend for as long as it is held
+pub struct Inhibition {
+    #[cfg(not(windows))]
+    child: std::process::Child,
+    #[cfg(windows)]
+    _release: std::sync::mpsc::Sender<()>,
+}
+
+impl Inhibition {
+    /// Block sleep through logind on Linux and with `caffeinate` on macOS.
+    /// The helper exits with this process, so a crash cannot keep the
+    /// computer awake.
+    #[cfg(not(windows))]
+    pub fn acquire(reason: &str) -> anyhow::Result<Self> {
+        use std::process::{Command, Stdio};
+
+        let pid = std::process::id().to_string();
+        let mut command = if cfg!(target_os = "macos") {
+            let mut command = Command::new("caffeinate");
+            command.args(["-i", "-w", &pid]);
+            command
+        } else {
+            let mut command = Command::new("systemd-inhibit");
+            command.args([
+                "--what=sleep:idle",
+                "--who=JusTrans",
+                &format!("--why={}", reason),
+                "--mode=block",
+                "tail",
+                &format!("--pid={}", pid),
+                "-f",
+                "/dev/null",
+            ]);
+            command
+        };
+        let child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        Ok(Self { child })
+    }
+
+    /// Set the system-required execution state. The state belongs to the
+    /// thread that sets it, so a thread holds it until released.
+    #[cfg(windows)]
+    pub fn acquire(_reason: &str) -> anyhow::Result<Self> {
+        use windows_sys::Win32::System::Power::{
+            SetThreadExecutionState, ES_CONTINUOUS, ES_SYSTEM_REQUIRED,
+        };
+
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        std::thread::Builder::new()
+            .name("sleep-inhibitor".to_string())
+            .spawn(move || {
+                // SAFETY: only changes the execution state of this thread
+                unsafe { SetThreadExecutionState(ES_CONTINUOUS | ES_SYSTEM_REQUIRED) };
+                let _ = released.recv();
+                unsafe { SetThreadExecutionState(ES_CONTINUOUS) };
+            })?;
+        Ok(Self { _release: release })
+    }
+}
+
+#[cfg(not(windows))]
+impl Drop for Inhibition {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn enabled() -> bool {
+    ConfigData::instance()
+        .map(|instance| {
+            instance
+                .lock()
+                .unwrap()
+                .power
+                .prevent_sleep_during_transfers
+        })
+        .unwrap_or(false)
+}
+
+/// Keep the computer awake for a transfer started on the command line, if
+/// enabled in the config
+pub fn keep_awake(reason: &str) -> Option<Inhibition> {
+    if !enabled() {
+        return None;
+    }
+    Inhibition::acquire(reason)
+        .map_err(|e| log::warn!("Failed to prevent sleep: {}", e))
+        .ok()
+}
+
+#[derive(Default)]
+struct TrackerState {
+    active: usize,
+    /// Bumped whenever a transfer starts, so a delayed release can tell
+    /// whether another transfer came and went in the meantime
+    generation: u64,
+    inhibition: Option<Inhibition>,
+    /// Inhibiting failed before and is not tried again
+    unavailable: bool,
+}
+
+/// Counts the uploads and downloads in flight and keeps the computer awake
+/// while there are any
+#[derive(Clone, Default)]
+pub struct TransferTracker {
+    state: Arc<Mutex<TrackerState>>,
+}
+
+impl TransferTracker {
+    /// Register a transfer that lasts until the guard is dropped
+    pub fn start(&self) -> TransferGuard {
+        let mut state = self.state.lock().unwrap();
+        state.active += 1;
+        state.generation += 1;
+        if state.inhibition.is_none() && !state.unavailable && enabled() {
+            match Inhibition::acquire("Transfers in progress") {
+                Ok(inhibition) => {
+                    log::info!("Keeping the computer awake during transfers");
+                    state.inhibition = Some(inhibition);
+                }
+                Err(e) => {
+                    log::warn!("Failed to prevent sleep during transfers: {}", e);
+                    state.unavailable = true;
+                }
+            }
+        }
+        TransferGuard {
+            tracker: self.clone(),
+        }
+    }
+
+    /// Number of transfers in flight
+    pub fn active(&self) -> usize {
+        self.state.lock().unwrap().active
+    }
+
+    /// Register a transfer of `size` bytes if it is large enough to matter
+    pub fn start_if_large(&self, size: u64) -> Option<TransferGuard> {
+        (size >= MIN_TRANSFER_BYTES).then(|| self.start())
+    }
+
+    fn release_if_idle(&self, generation: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.active == 0 && state.generation == generation && state.inhibition.is_some() {
+            log::info!("Transfers finished, the computer may sleep again");
+            state.inhibition = None;
+        }
+    }
+}
+
+/// One transfer in flight
+pub struct TransferGuard {
+    tracker: TransferTracker,
+}
+
+impl Drop for TransferGuard {
+    fn drop(&mut self) {
+        let generation = {
+            let mut state = self.tracker.state.lock().unwrap();
+            state.active -= 1;
+            if state.active > 0 || state.inhibition.is_none() {
+                return;
+            }
+            state.generation
+        };
+        let tracker = self.tracker.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    tokio::time::sleep(RELEASE_DELAY).await;
+                    tracker.release_if_idle(generation);
+                });
+            }
+            Err(_) => tracker.release_if_idle(generation),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_transfers() {
+        let tracker = TransferTracker::default();
+        // Don't ask the OS for anything while testing
+        tracker.state.lock().unwrap().unavailable = true;
+
+        let first = tracker.start();
+        let second = tracker.start_if_large(MIN_TRANSFER_BYTES).unwrap();
+        assert!(tracker.start_if_large(1024).is_none());
+        assert_eq!(tracker.state.lock().unwrap().active, 2);
+        drop(first);
+        assert_eq!(tracker.state.lock().unwrap().active, 1);
+        drop(second);
+
+        let state = tracker.state.lock().unwrap();
+        assert_eq!(state.active, 0);
+        assert_eq!(state.generation, 2);
+        assert!(state.inhibition.is_none());
+    }
+}
@@ -0,0 +1,475 @@
+//! Previews of links shared as text. With `server.link_previews` on, a
+//! snippet that is nothing but an http(s) URL gets the title and icon of the
+//! page, which the portal shows as a card. Pages are only fetched from public
+//! addresses: the host is resolved and checked before connecting, the
+//! connection goes to the address checked, and every redirect is checked the
+//! same, so a snippet can't make the server reach into the LAN or itself.
+//! Fetching gives up after a few seconds and reads only the start of a page.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use reqwest::header;
+use reqwest::redirect::Policy;
+use reqwest::Url;
+
+use crate::models::LinkPreview;
+
+/// Time a preview may take, redirects and icon included
+const TIMEOUT: Duration = Duration::from_secs(4);
+
+/// Bytes of a page read for its head
+const MAX_PAGE_LEN: usize = 256 * 1024;
+
+/// Largest icon embedded in a preview
+const MAX_ICON_LEN: usize = 32 * 1024;
+
+const MAX_REDIRECTS: usize = 3;
+
+/// Titles are cut to this many characters
+const MAX_TITLE_LEN: usize = 200;
+
+/// The URL `content` consists of, if the snippet is a web link
+pub fn link(content: &str) -> Option<Url> {
+    let content = content.trim();
+    if content.contains(char::is_whitespace) {
+        return None;
+    }
+    Url::parse(content)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https") && url.host_str().is_some())
+}
+
+/// Whether `ip` is on the public internet rather than the LAN, the computer
+/// itself or a range with a special purpose
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                || a >= 240
+                // Carrier-grade NAT and benchmarking
+                || (a == 100 && b & 0xc0 == 64)
+                || (a == 198 && b & 0xfe == 18))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let [a, b, ..] = ip.segments();
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local and link-local
+                || a & 0xfe00 == 0xfc00
+                || a & 0xffc0 == 0xfe80
+                // Documentation, and ranges carrying IPv4 addresses: NAT64,
+                // 6to4 and Teredo
+                || (a == 0x2001 && b == 0x0db8)
+                || (a == 0x0064 && b == 0xff9b)
+                || a == 0x2002
+                || (a == 0x2001 && b == 0))
+        }
+    }
+}
+
+/// Resolve the host of `url`, returning the address to connect to if all
+/// its addresses are public, so a name can't point at the LAN some of the
+/// time
+async fn public_address(url: &Url) -> Option<SocketAddr> {
+    let host = url.host_str()?;
+    let port = url.port_or_known_default()?;
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    let addresses: Vec<SocketAddr> = match literal.parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => tokio::net::lookup_host((host, port)).await.ok()?.collect(),
+    };
+    if addresses.is_empty() || !addresses.iter().all(|address| is_public(address.ip())) {
+        log::debug!("Not previewing {}: not a public address", host);
+        return None;
+    }
+    addresses.first().copied()
+}
+
+/// A response read so far
+struct Fetched {
+    /// Where the content came from after redirects
+    url: Url,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+/// GET `url` from a public address, following a few redirects checked the
+/// same, and read at most `limit` bytes of it
+async fn fetch(mut url: Url, limit: usize) -> Option<Fetched> {
+    for _ in 0..=MAX_REDIRECTS {
+        let address = public_address(&url).await?;
+        let client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .resolve(url.host_str()?, address)
+            .user_agent(concat!("JusTrans/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .ok()?;
+        let mut response = client.get(url.clone()).send().await.ok()?;
+        if response.status().is_redirection() {
+            let location = response.headers().get(header::LOCATION)?.to_str().ok()?;
+            url = url
+                .join(location)
+                .ok()
+                .filter(|url| matches!(url.scheme(), "http" | "https"))?;
+            continue;
+        }
+        if !response.status().is_success() {
+            return None;
+        }
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.ok()? {
+            body.extend_from_slice(&chunk[..chunk.len().min(limit - body.len())]);
+            if body.len() == limit {
+                break;
+            }
+        }
+        return Some(Fetched {
+            url,
+            content_type,
+            body,
+        });
+    }
+    None
+}
+
+/// The title and icon of the page at `url`, if it is a public HTML page
+/// with a title answering in time
+pub async fn preview(url: Url) -> Option<LinkPreview> {
+    let preview = tokio::time::timeout(TIMEOUT, fetch_preview(url.clone())).await;
+    if preview.is_err() {
+        log::debug!("Preview of {} timed out", url);
+    }
+    preview.ok().flatten()
+}
+
+async fn fetch_preview(url: Url) -> Option<LinkPreview> {
+    let page = fetch(url, MAX_PAGE_LEN).await?;
+    if !page.content_type.starts_with("text/html")
+        && !page.content_type.starts_with("application/xhtml+xml")
+    {
+        return None;
+    }
+    let head = Head::parse(&String::from_utf8_lossy(&page.body));
+    let title = head
+        .og_title
+        .or(head.title)
+        .filter(|title| !title.is_empty())?;
+    let icon = match head
+        .icon
+        .as_deref()
+        .map_or_else(|| page.url.join("/favicon.ico"), |href| page.url.join(href))
+    {
+        Ok(icon) => fetch_icon(icon).await,
+        Err(_) => None,
+    };
+    Some(LinkPreview { title, icon })
+}
+
+/// The icon at `url` as a `data:` URL. SVG is left out, as it can carry
+/// scripts.
+async fn fetch_icon(url: Url) -> Option<String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+    let icon = fetch(url, MAX_ICON_LEN + 1).await?;
+    let content_type = icon
+        .content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim();
+    let is_image = content_type.starts_with("image/") && !content_type.contains("svg");
+    if !is_image || icon.body.is_empty() || icon.body.len() > MAX_ICON_LEN {
+        return None;
+    }
+    Some(format!(
+        "data:{};base64,{}",
+        content_type,
+        STANDARD.encode(&icon.body)
+    ))
+}
+
+/// What a preview takes from the head of a page
+#[derive(Debug, Default, PartialEq)]
+struct Head {
+    title: Option<String>,
+    /// `og:title`, which sites write for cards like this
+    og_title: Option<String>,
+    /// `href` of the first icon link
+    icon: Option<String>,
+}
+
+impl Head {
+    /// Read the tags of `page` up to its body
+    fn parse(page: &str) -> Self {
+        // Same byte offsets as the page, for finding tags in any case
+        let lower = page.to_ascii_lowercase();
+        let mut head = Head::default();
+        let mut at = 0;
+        while let Some(start) = lower[at..].find('<') {
+            let start = at + start + 1;
+            let Some(end) = lower[start..].find('>') else {
+                break;
+            };
+            let end = start + end;
+            at = end + 1;
+            let tag = &page[start..end];
+            let (name, rest) = tag
+                .split_once(|c: char| c.is_ascii_whitespace())
+                .unwrap_or((tag, ""));
+            match name.to_ascii_lowercase().as_str() {
+                "title" if head.title.is_none() => {
+                    let close = lower[at..].find("</title").map_or(lower.len(), |i| at + i);
+                    head.title = Some(text(&page[at..close]));
+                    at = close;
+                }
+                "meta" => {
+                    let attributes = attributes(rest);
+                    let is_og_title = ["property", "name"]
+                        .iter()
+                        .any(|key| value(&attributes, key) == Some("og:title"));
+                    if is_og_title && head.og_title.is_none() {
+                        head.og_title = value(&attributes, "content").map(text);
+                    }
+                }
+                "link" => {
+                    let attributes = attributes(rest);
+                    let is_icon = value(&attributes, "rel").is_some_and(|rel| {
+                        rel.split_whitespace()
+                            .any(|rel| rel.eq_ignore_ascii_case("icon"))
+                    });
+                    if is_icon && head.icon.is_none() {
+                        head.icon = value(&attributes, "href").map(str::to_string);
+                    }
+                }
+                "/head" | "body" => break,
+                _ => {}
+            }
+        }
+        head
+    }
+}
+
+/// Attributes of a tag, with lowercase names and decoded values
+fn attributes(mut rest: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if rest.is_empty() {
+            return attributes;
+        }
+        let name_end = rest
+            .find(|c: char| c.is_ascii_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+        let mut value = "";
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let quoted = &after[1..];
+                    let end = quoted.find(quote).unwrap_or(quoted.len());
+                    value = &quoted[..end];
+                    rest = quoted.get(end + 1..).unwrap_or_default();
+                }
+                _ => {
+                    let end = after
+                        .find(|c: char| c.is_ascii_whitespace())
+                        .unwrap_or(after.len());
+                    value = &after[..end];
+                    rest = &after[end..];
+                }
+            }
+        }
+        attributes.push((name, decode_entities(value)));
+    }
+}
+
+fn value<'a>(attributes: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Text as shown: entities decoded, whitespace collapsed and cut to
+/// `MAX_TITLE_LEN` characters
+fn text(raw: &str) -> String {
+    let decoded = decode_entities(raw);
+    let text = decoded.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= MAX_TITLE_LEN {
+        return text;
+    }
+    let mut cut: String = text.chars().take(MAX_TITLE_LEN - 1).collect();
+    cut.push('…');
+    cut
+}
+
+/// Decode the character references found in titles: the common named ones
+/// and numeric ones. Others are kept as written.
+fn decode_entities(raw: &str) -> String {
+    let mut decoded = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .map(|end| &rest[1..end + 1]);
+        let character = reference.and_then(|reference| match reference {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ => reference
+                .strip_prefix("#x")
+                .or_else(|| reference.strip_prefix("#X"))
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| reference.strip_prefix('#').map(|dec| dec.parse::<u32>()))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        });
+        match (reference, character) {
+            (Some(reference), Some(character)) => {
+                decoded.push(character);
+                rest = &rest[reference.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link() {
+        assert_eq!(
+            link("  https://example.com/a?b=c\n").map(String::from),
+            Some("https://example.com/a?b=c".to_string())
+        );
+        assert!(link("see https://example.com").is_none());
+        assert!(link("ftp://example.com/file").is_none());
+        assert!(link("javascript:alert(1)").is_none());
+        assert!(link("file:///etc/passwd").is_none());
+        assert!(link("just some text").is_none());
+    }
+
+    #[test]
+    fn test_is_public() {
+        for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.20",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:192.168.1.1",
+            "64:ff9b::a00:1",
+            "2002:c0a8:101::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_parse_head() {
+        let page = r#"<!DOCTYPE html>
+            <html><HEAD>
+            <meta charset="utf-8">
+            <TITLE>
+              Fish &amp; Chips
+            </TITLE>
+            <link rel="stylesheet" href="/style.css">
+            <link rel='shortcut icon' href='/static/icon.png'>
+            <meta property="og:title" content="Fish &#38; Chips &#x2014; Menu" />
+            </head><body><title>Not this</title></body></html>"#;
+        assert_eq!(
+            Head::parse(page),
+            Head {
+                title: Some("Fish & Chips".to_string()),
+                og_title: Some("Fish & Chips — Menu".to_string()),
+                icon: Some("/static/icon.png".to_string()),
+            }
+        );
+        assert_eq!(Head::parse("no tags at all"), Head::default());
+        assert_eq!(
+            Head::parse("<title>Unclosed").title.as_deref(),
+            Some("Unclosed")
+        );
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(
+            decode_entities("a &lt;b&gt; &unknown; & c"),
+            "a <b> &unknown; & c"
+        );
+        assert_eq!(decode_entities("&#128512;&#xZZ;"), "😀&#xZZ;");
+        assert_eq!(text("  two\n  lines "), "two lines");
+        let long = text(&"é".repeat(MAX_TITLE_LEN + 5));
+        assert_eq!(long.chars().count(), MAX_TITLE_LEN);
+        assert!(long.ends_with('…'));
+    }
+
+    #[tokio::test]
+    async fn test_no_preview_from_local_addresses() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let accepted = tokio::spawn(async move {
+            tokio::time::timeout(Duration::from_millis(500), listener.accept())
+                .await
+                .is_ok()
+        });
+
+        for url in [
+            format!("http://{}/", address),
+            format!("http://localhost:{}/", address.port()),
+            format!("http://[::1]:{}/", address.port()),
+        ] {
+            assert!(preview(link(&url).unwrap()).await.is_none(), "{}", url);
+        }
+        // Nothing even connected
+        assert!(!accepted.await.unwrap());
+    }
+}
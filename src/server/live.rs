@@ -0,0 +1,279 @@
+//! Live updates of the file lists over a WebSocket, so the portal and other
+//! clients, e.g. a desktop app on another computer, needn't poll the list:
+//! `GET /ws` follows the main share and `GET /r/<room>/ws` a room. Each
+//! message is a JSON object, `{"event": "file.added", ...}` or
+//! `{"event": "file.removed", ...}` as files come and go, and
+//! `{"event": "server.stopping"}` before the socket closes with the server.
+//! A client that falls behind is disconnected; it should load the list again
+//! when it reconnects.
+
+use std::sync::{Arc, Mutex};
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Router;
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+
+use super::file_server::AppState;
+use super::rooms::{self, RoomAccess};
+use crate::integrations::ServerEvent;
+
+/// Changes a client may fall behind by before it is disconnected
+const BACKLOG: usize = 64;
+
+/// A change to a file list, as sent to the clients
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "event")]
+pub enum LiveEvent {
+    #[serde(rename = "file.added")]
+    FileAdded {
+        id: String,
+        name: String,
+        size: u64,
+        room: Option<String>,
+    },
+    #[serde(rename = "file.removed")]
+    FileRemoved {
+        id: String,
+        name: String,
+        room: Option<String>,
+    },
+    #[serde(rename = "server.stopping")]
+    ServerStopping,
+}
+
+impl LiveEvent {
+    /// The change `event` makes to a file list, if any
+    fn from_server_event(event: ServerEvent) -> Option<Self> {
+        match event {
+            ServerEvent::FileReceived {
+                id,
+                name,
+                size,
+                room,
+            } => Some(LiveEvent::FileAdded {
+                id,
+                name,
+                size,
+                room,
+            }),
+            ServerEvent::FileDeleted { id, name, room } => {
+                Some(LiveEvent::FileRemoved { id, name, room })
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the clients of `room`, or of the main share for `None`, are
+    /// told about it
+    fn concerns(&self, room: Option<&str>) -> bool {
+        match self {
+            LiveEvent::FileAdded { room: of, .. } | LiveEvent::FileRemoved { room: of, .. } => {
+                of.as_deref() == room
+            }
+            LiveEvent::ServerStopping => true,
+        }
+    }
+}
+
+/// The clients following the file lists of a server
+#[derive(Clone, Default)]
+pub struct LiveUpdates {
+    /// Sends changes while the server runs
+    live: Arc<Mutex<Option<broadcast::Sender<LiveEvent>>>>,
+}
+
+impl LiveUpdates {
+    /// A receiver of the changes to come, while the server runs
+    pub fn subscribe(&self) -> Option<broadcast::Receiver<LiveEvent>> {
+        let live = self.live.lock().unwrap();
+        live.as_ref().map(|live| live.subscribe())
+    }
+
+    fn send(&self, event: LiveEvent) {
+        if let Some(live) = self.live.lock().unwrap().as_ref() {
+            // No clients connected is not an error
+            let _ = live.send(event);
+        }
+    }
+}
+
+/// Pass the file changes among `events` on until `shutdown` changes, then
+/// tell the clients the server is stopping and close their sockets
+pub async fn run(
+    updates: LiveUpdates,
+    mut events: broadcast::Receiver<ServerEvent>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    *updates.live.lock().unwrap() = Some(broadcast::channel(BACKLOG).0);
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    if let Some(event) = LiveEvent::from_server_event(event) {
+                        updates.send(event);
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    log::debug!("Live updates skipped {} events", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = shutdown.changed() => break,
+        }
+    }
+    updates.send(LiveEvent::ServerStopping);
+    *updates.live.lock().unwrap() = None;
+}
+
+/// Send the changes concerning `room` over `socket` until either side ends
+async fn push(
+    mut socket: WebSocket,
+    mut updates: broadcast::Receiver<LiveEvent>,
+    room: Option<String>,
+) {
+    loop {
+        tokio::select! {
+            update = updates.recv() => match update {
+                Ok(update) if update.concerns(room.as_deref()) => {
+                    let Ok(text) = serde_json::to_string(&update) else {
+                        continue;
+                    };
+                    if socket.send(Message::Text(text)).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                // Changes missed can't be told; the client loads the list
+                // again when it reconnects
+                Err(RecvError::Lagged(_)) | Err(RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                // Clients have nothing to say; pings are answered by the
+                // socket itself
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+fn follow(
+    socket: WebSocketUpgrade,
+    state: &AppState,
+    room: Option<String>,
+) -> Result<Response, StatusCode> {
+    let updates = state
+        .live
+        .subscribe()
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    Ok(socket.on_upgrade(move |socket| push(socket, updates, room)))
+}
+
+#[axum::debug_handler]
+async fn follow_share(
+    socket: WebSocketUpgrade,
+    State(state): State<AppState>,
+) -> Result<Response, StatusCode> {
+    follow(socket, &state, None)
+}
+
+#[axum::debug_handler]
+async fn follow_room(
+    socket: WebSocketUpgrade,
+    Path(room): Path<String>,
+    access: RoomAccess,
+    State(state): State<AppState>,
+) -> Result<Response, StatusCode> {
+    let room = rooms::find_room(&state, &room, &access)?;
+    follow(socket, &state, Some(room.name))
+}
+
+/// The socket of the main share, behind its PIN like the file list
+pub fn router() -> Router<AppState> {
+    Router::new().route("/ws", get(follow_share))
+}
+
+/// The sockets of the rooms, each checking the room's own PIN
+pub fn room_router() -> Router<AppState> {
+    Router::new().route("/r/:room/ws", get(follow_room))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn received(name: &str, room: Option<&str>) -> ServerEvent {
+        ServerEvent::FileReceived {
+            id: name.to_string(),
+            name: name.to_string(),
+            size: 4,
+            room: room.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_live_events() {
+        let added = LiveEvent::from_server_event(received("a.txt", None)).unwrap();
+        assert!(added.concerns(None));
+        assert!(!added.concerns(Some("team")));
+        let json = serde_json::to_value(&added).unwrap();
+        assert_eq!(json["event"], "file.added");
+        assert_eq!(json["name"], "a.txt");
+
+        let removed = LiveEvent::from_server_event(ServerEvent::FileDeleted {
+            id: "b".to_string(),
+            name: "b.txt".to_string(),
+            room: Some("team".to_string()),
+        })
+        .unwrap();
+        assert!(removed.concerns(Some("team")));
+        assert!(!removed.concerns(None));
+
+        assert!(LiveEvent::ServerStopping.concerns(Some("team")));
+        assert_eq!(
+            serde_json::to_value(LiveEvent::ServerStopping).unwrap(),
+            serde_json::json!({ "event": "server.stopping" })
+        );
+        assert!(LiveEvent::from_server_event(ServerEvent::ServerError {
+            message: "oops".to_string()
+        })
+        .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_updates_while_running() {
+        let updates = LiveUpdates::default();
+        assert!(updates.subscribe().is_none());
+        let (events, receiver) = broadcast::channel(8);
+        let (shutdown, stopped) = watch::channel(false);
+        let task = tokio::spawn(run(updates.clone(), receiver, stopped));
+        while updates.subscribe().is_none() {
+            tokio::task::yield_now().await;
+        }
+
+        let mut live = updates.subscribe().unwrap();
+        events.send(received("a.txt", Some("team"))).unwrap();
+        events
+            .send(ServerEvent::ClientConnected {
+                address: "192.168.1.5".to_string(),
+            })
+            .unwrap();
+        assert!(matches!(
+            live.recv().await.unwrap(),
+            LiveEvent::FileAdded { room: Some(room), .. } if room == "team"
+        ));
+
+        shutdown.send(true).unwrap();
+        task.await.unwrap();
+        assert_eq!(live.recv().await.unwrap(), LiveEvent::ServerStopping);
+        assert!(matches!(live.recv().await, Err(RecvError::Closed)));
+        assert!(updates.subscribe().is_none());
+    }
+}
@@ -0,0 +1,282 @@
+//! Memory held in transfer buffers: request bodies read whole before they
+//! are stored (upload segments and parts, pushed chunks, deltas and speed
+//! tests) and the read buffers of downloads. Every buffer is reserved
+//! against one limit for the whole app, `server.transfer_memory_mb`; a
+//! transfer that would go over it waits until others let go of theirs, so
+//! many parallel uploads slow down rather than run the computer out of
+//! memory. A buffer larger than the limit on its own gets through once
+//! nothing else is held. What is held now, the most ever held and how often
+//! transfers had to wait are reported with the server's stats and on
+//! `/metrics`, so a change that buffers whole files again shows up there.
+
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+use axum::extract::{Request, State};
+use axum::http::{header, Method};
+use axum::middleware::Next;
+use axum::response::Response;
+use futures_util::Stream;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+/// Limit until the settings are read
+const DEFAULT_LIMIT: u64 = 256 * 1024 * 1024;
+
+/// Buffers of every server of the app
+static MEMORY: Lazy<TransferMemory> = Lazy::new(|| TransferMemory::new(DEFAULT_LIMIT));
+
+/// Buffer memory as reported in the stats
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryUsage {
+    /// Bytes held by transfers now
+    pub buffered_bytes: u64,
+    /// The most bytes held at once since the app started
+    pub peak_bytes: u64,
+    pub limit_bytes: u64,
+    /// Transfers that had to wait for memory
+    pub waits: u64,
+}
+
+/// Reservations against the limit
+struct TransferMemory {
+    held: Mutex<u64>,
+    peak: AtomicU64,
+    limit: AtomicU64,
+    waits: AtomicU64,
+    released: Notify,
+}
+
+impl TransferMemory {
+    fn new(limit: u64) -> Self {
+        Self {
+            held: Mutex::new(0),
+            peak: AtomicU64::new(0),
+            limit: AtomicU64::new(limit),
+            waits: AtomicU64::new(0),
+            released: Notify::new(),
+        }
+    }
+
+    /// Take `bytes` if they fit, or if nothing else is held
+    fn try_take(&self, bytes: u64) -> bool {
+        let mut held = self.held.lock().unwrap();
+        if *held > 0 && *held + bytes > self.limit.load(Ordering::Relaxed) {
+            return false;
+        }
+        *held += bytes;
+        self.peak.fetch_max(*held, Ordering::Relaxed);
+        true
+    }
+
+    /// Wait until `bytes` fit and take them
+    async fn reserve(&'static self, bytes: u64) -> Reservation {
+        let mut waited = false;
+        loop {
+            // Listen before checking, so a release in between isn't missed
+            let mut released = pin!(self.released.notified());
+            released.as_mut().enable();
+            if self.try_take(bytes) {
+                return Reservation {
+                    memory: self,
+                    bytes,
+                };
+            }
+            if !waited {
+                waited = true;
+                self.waits.fetch_add(1, Ordering::Relaxed);
+            }
+            released.await;
+        }
+    }
+
+    fn release(&self, bytes: u64) {
+        *self.held.lock().unwrap() -= bytes;
+        self.released.notify_waiters();
+    }
+
+    fn usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            buffered_bytes: *self.held.lock().unwrap(),
+            peak_bytes: self.peak.load(Ordering::Relaxed),
+            limit_bytes: self.limit.load(Ordering::Relaxed),
+            waits: self.waits.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Memory held for a transfer until dropped
+pub struct Reservation {
+    memory: &'static TransferMemory,
+    bytes: u64,
+}
+
+impl Reservation {
+    /// Keep holding the memory until `stream` is dropped, e.g. with a
+    /// response body
+    pub fn holding<S>(self, stream: S) -> Held<S> {
+        Held {
+            inner: stream,
+            _reservation: self,
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.memory.release(self.bytes);
+    }
+}
+
+/// A stream holding buffer memory for as long as it lives
+pub struct Held<S> {
+    inner: S,
+    _reservation: Reservation,
+}
+
+impl<S: Stream + Unpin> Stream for Held<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+/// Set the limit from `server.transfer_memory_mb`; transfers holding more
+/// keep it, new ones wait until they fit
+pub fn set_limit_mb(mb: u64) {
+    MEMORY
+        .limit
+        .store(mb.max(1) * 1024 * 1024, Ordering::Relaxed);
+    MEMORY.released.notify_waiters();
+}
+
+/// Wait until `bytes` of buffer memory are free and hold them
+pub async fn reserve(bytes: u64) -> Reservation {
+    MEMORY.reserve(bytes).await
+}
+
+pub fn usage() -> MemoryUsage {
+    MEMORY.usage()
+}
+
+/// Memory a request body read whole will take, at most `limit`. Bodies of
+/// unknown length, e.g. chunked ones, are taken to be that large; reads and
+/// deletions have none.
+fn body_len(request: &Request, limit: usize) -> u64 {
+    let limit = limit as u64;
+    match request.headers().get(header::CONTENT_LENGTH) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map_or(limit, |len| len.min(limit)),
+        None if matches!(
+            *request.method(),
+            Method::GET | Method::HEAD | Method::DELETE
+        ) =>
+        {
+            0
+        }
+        None => limit,
+    }
+}
+
+/// Middleware for routes that read their body whole, at most `limit`
+/// bytes: the request waits for the memory before its body is read and
+/// holds it until it is answered
+pub async fn hold_body(State(limit): State<usize>, request: Request, next: Next) -> Response {
+    let _reservation = MEMORY.reserve(body_len(&request, limit)).await;
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::body::Body;
+
+    use super::*;
+
+    fn memory(limit: u64) -> &'static TransferMemory {
+        Box::leak(Box::new(TransferMemory::new(limit)))
+    }
+
+    #[tokio::test]
+    async fn test_reserve_waits_for_memory() {
+        let memory = memory(100);
+        let first = memory.reserve(60).await;
+        let second = memory.reserve(40).await;
+        assert_eq!(memory.usage().buffered_bytes, 100);
+
+        // Over the limit, the third waits until enough is released
+        let third = tokio::spawn(memory.reserve(50));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!third.is_finished());
+        drop(second);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!third.is_finished());
+        drop(first);
+        let third = tokio::time::timeout(Duration::from_secs(1), third)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            memory.usage(),
+            MemoryUsage {
+                buffered_bytes: 50,
+                peak_bytes: 100,
+                limit_bytes: 100,
+                waits: 1,
+            }
+        );
+        drop(third);
+        assert_eq!(memory.usage().buffered_bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_alone() {
+        let memory = memory(100);
+        // A buffer over the limit gets through when nothing else is held
+        let large = memory.reserve(500).await;
+        assert!(!memory.try_take(1));
+        drop(large);
+        assert!(memory.try_take(1));
+        assert_eq!(memory.usage().peak_bytes, 500);
+    }
+
+    #[tokio::test]
+    async fn test_held_stream() {
+        let memory = memory(100);
+        let stream = memory
+            .reserve(64)
+            .await
+            .holding(futures_util::stream::iter([1, 2, 3]));
+        assert_eq!(memory.usage().buffered_bytes, 64);
+        let items: Vec<_> = futures_util::StreamExt::collect(stream).await;
+        assert_eq!(items, [1, 2, 3]);
+        assert_eq!(memory.usage().buffered_bytes, 0);
+    }
+
+    #[test]
+    fn test_body_len() {
+        let request = |method: Method, len: Option<&str>| {
+            let mut builder = Request::builder().method(method).uri("/upload/abc");
+            if let Some(len) = len {
+                builder = builder.header(header::CONTENT_LENGTH, len);
+            }
+            builder.body(Body::empty()).unwrap()
+        };
+        assert_eq!(body_len(&request(Method::PUT, Some("1000")), 4096), 1000);
+        assert_eq!(body_len(&request(Method::PUT, Some("10000")), 4096), 4096);
+        assert_eq!(body_len(&request(Method::PUT, Some("lots")), 4096), 4096);
+        // Chunked bodies may be as large as allowed, reads have none
+        assert_eq!(body_len(&request(Method::POST, None), 4096), 4096);
+        assert_eq!(body_len(&request(Method::GET, None), 4096), 0);
+        assert_eq!(body_len(&request(Method::DELETE, None), 4096), 0);
+    }
+}
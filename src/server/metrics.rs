@@ -0,0 +1,131 @@
+//! `GET /metrics`: the server's stats and the transfer buffer memory in the
+//! Prometheus text format, for scraping into a dashboard or alerting when
+//! the buffers grow.
+
+use std::fmt::Write;
+
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+
+use super::file_server::AppState;
+use super::stats::StatsSnapshot;
+
+/// The metrics of `stats` in the text exposition format
+fn render(stats: &StatsSnapshot) -> String {
+    let memory = &stats.memory;
+    let metrics: [(&str, &str, &str, u64); 9] = [
+        (
+            "justrans_uptime_seconds",
+            "gauge",
+            "Seconds since the server was started",
+            stats.uptime_secs,
+        ),
+        (
+            "justrans_connected_devices",
+            "gauge",
+            "Devices with a connection open",
+            stats.devices as u64,
+        ),
+        (
+            "justrans_active_transfers",
+            "gauge",
+            "Uploads and downloads in flight",
+            stats.active_transfers as u64,
+        ),
+        (
+            "justrans_received_bytes_total",
+            "counter",
+            "Bytes received since the server was started",
+            stats.bytes_in,
+        ),
+        (
+            "justrans_sent_bytes_total",
+            "counter",
+            "Bytes sent since the server was started",
+            stats.bytes_out,
+        ),
+        (
+            "justrans_transfer_memory_bytes",
+            "gauge",
+            "Bytes held in transfer buffers",
+            memory.buffered_bytes,
+        ),
+        (
+            "justrans_transfer_memory_peak_bytes",
+            "gauge",
+            "Most bytes held in transfer buffers at once",
+            memory.peak_bytes,
+        ),
+        (
+            "justrans_transfer_memory_limit_bytes",
+            "gauge",
+            "Bytes transfer buffers may hold before transfers wait",
+            memory.limit_bytes,
+        ),
+        (
+            "justrans_transfer_memory_waits_total",
+            "counter",
+            "Transfers that waited for buffer memory",
+            memory.waits,
+        ),
+    ];
+    let mut text = String::new();
+    for (name, kind, help, value) in metrics {
+        let _ = writeln!(text, "# HELP {} {}", name, help);
+        let _ = writeln!(text, "# TYPE {} {}", name, kind);
+        let _ = writeln!(text, "{} {}", name, value);
+    }
+    text
+}
+
+#[axum::debug_handler]
+async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render(&state.server_info().stats),
+    )
+}
+
+/// The metrics route, at the root like other Prometheus exporters
+pub fn router() -> Router<AppState> {
+    Router::new().route("/metrics", get(metrics))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::memory::MemoryUsage;
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let stats = StatsSnapshot {
+            started_at: Some(1000),
+            uptime_secs: 90,
+            devices: 2,
+            active_transfers: 1,
+            bytes_in: 300,
+            bytes_out: 700,
+            memory: MemoryUsage {
+                buffered_bytes: 65536,
+                peak_bytes: 6_291_456,
+                limit_bytes: 268_435_456,
+                waits: 3,
+            },
+        };
+        let text = render(&stats);
+        assert!(text.starts_with(
+            "# HELP justrans_uptime_seconds Seconds since the server was started\n\
+             # TYPE justrans_uptime_seconds gauge\n\
+             justrans_uptime_seconds 90\n"
+        ));
+        assert!(text.contains("\njustrans_received_bytes_total 300\n"));
+        assert!(text.contains("\njustrans_transfer_memory_bytes 65536\n"));
+        assert!(text.contains("\njustrans_transfer_memory_peak_bytes 6291456\n"));
+        assert!(text.contains("\njustrans_transfer_memory_waits_total 3\n"));
+        // Every metric has its help, type and one sample
+        assert_eq!(text.lines().count(), 9 * 3);
+    }
+}
@@ -0,0 +1,407 @@
+//! Recovery of a storage directory left in the old layout, where received
+//! files are `{id}_file` blobs with nothing on disk recording their names:
+//! the state of every run before the metadata store, and of any run that
+//! crashed with the store kept in memory. Blobs no record points to are
+//! given a name from their contents, moved into `recovered/` and listed
+//! again, quarantined: the lost record may have carried a password or a
+//! room, so nothing is offered until the user releases it. Files found
+//! there later are listed under their names. What can't be recovered is
+//! reported and left in place for the user to look at.
+//!
+//! Runs when a server is built, before anything is received, and from
+//! `justrans migrate`.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use super::organize;
+use super::quarantine::{self, BLOCKED_SUFFIX};
+use crate::metadata::{self, Change, MetadataStore, StoredFile};
+use crate::models::FileInfo;
+
+/// Folder of the storage directory recovered files are moved to
+pub const RECOVERED_DIR: &str = "recovered";
+
+/// Suffix of received blobs in the old layout
+const BLOB_SUFFIX: &str = "_file";
+
+/// Extensions of common formats by their first bytes: offset, magic, extension
+const SIGNATURES: &[(usize, &[u8], &str)] = &[
+    (0, b"\xff\xd8\xff", "jpg"),
+    (0, b"\x89PNG\r\n\x1a\n", "png"),
+    (0, b"GIF8", "gif"),
+    (8, b"WEBP", "webp"),
+    (8, b"WAVE", "wav"),
+    (8, b"AVI ", "avi"),
+    (4, b"ftypheic", "heic"),
+    (4, b"ftypqt", "mov"),
+    (4, b"ftyp", "mp4"),
+    (0, b"\x1a\x45\xdf\xa3", "mkv"),
+    (0, b"ID3", "mp3"),
+    (0, b"OggS", "ogg"),
+    (0, b"fLaC", "flac"),
+    (0, b"%PDF", "pdf"),
+    (0, b"PK\x03\x04", "zip"),
+    (0, b"\x1f\x8b", "gz"),
+    (0, b"7z\xbc\xaf\x27\x1c", "7z"),
+    (0, b"Rar!", "rar"),
+    (0, b"MZ", "exe"),
+];
+
+/// Something in the storage directory that couldn't be recovered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leftover {
+    pub path: PathBuf,
+    pub reason: &'static str,
+}
+
+/// What a migration recovered and what it left behind
+#[derive(Debug, Default)]
+pub struct Migration {
+    pub recovered: Vec<FileInfo>,
+    pub leftovers: Vec<Leftover>,
+}
+
+impl Migration {
+    pub fn is_empty(&self) -> bool {
+        self.recovered.is_empty() && self.leftovers.is_empty()
+    }
+}
+
+impl fmt::Display for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Recovered {} file(s)", self.recovered.len())?;
+        for file in &self.recovered {
+            write!(f, "\n  {} ({} bytes)", file.path.display(), file.size)?;
+        }
+        if !self.leftovers.is_empty() {
+            write!(
+                f,
+                "\nLeft {} item(s) that could not be recovered",
+                self.leftovers.len()
+            )?;
+            for leftover in &self.leftovers {
+                write!(f, "\n  {}: {}", leftover.path.display(), leftover.reason)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Extension of a file by its first bytes: a known format, `txt` for text,
+/// or none
+fn guess_extension(path: &Path) -> Option<&'static str> {
+    let mut head = [0; 512];
+    let read = std::fs::File::open(path)
+        .and_then(|mut file| file.read(&mut head))
+        .ok()?;
+    let head = &head[..read];
+    let known = SIGNATURES
+        .iter()
+        .find(|(offset, magic, _)| head.get(*offset..*offset + magic.len()) == Some(*magic));
+    if let Some((_, _, extension)) = known {
+        return Some(extension);
+    }
+    // A multi-byte character may be cut off at the end of the head
+    let text = match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    (read > 0 && text && !head.contains(&0)).then_some("txt")
+}
+
+/// The ID of a blob in the old layout, quarantined or not
+fn blob_id(name: &str) -> Option<&str> {
+    let name = name.strip_suffix(BLOCKED_SUFFIX).unwrap_or(name);
+    let id = name.strip_suffix(BLOB_SUFFIX)?;
+    (!id.is_empty()).then_some(id)
+}
+
+/// A record for the recovered file at `path`
+fn recovered_file(id: String, name: String, path: PathBuf, size: u64, now: u64) -> FileInfo {
+    let added_at = std::fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(now, |age| age.as_secs());
+    let quarantined = path.to_string_lossy().ends_with(BLOCKED_SUFFIX);
+    FileInfo {
+        id,
+        mime_type: mime_guess::from_path(&name)
+            .first_or_octet_stream()
+            .to_string(),
+        added_at,
+        // Kept when the server stops, as organized photos are
+        folder: Some(RECOVERED_DIR.to_string()),
+        quarantined,
+        ..FileInfo::new(name, path, size)
+    }
+}
+
+/// Give the blob at `path` a name and move it into `recovered`,
+/// quarantined
+fn recover_blob(path: &Path, id: &str, recovered: &Path) -> std::io::Result<PathBuf> {
+    let name = match guess_extension(path) {
+        Some(extension) => format!("recovered_{}.{}", id, extension),
+        None => format!("recovered_{}", id),
+    };
+    std::fs::create_dir_all(recovered)?;
+    let target = organize::free_path(recovered, &name);
+    std::fs::rename(path, &target)?;
+    quarantine::quarantine(&target)
+}
+
+/// Recover the files in `storage_dir` that `store` has no record of,
+/// recording and returning them with the leftovers
+pub fn migrate(
+    storage_dir: &Path,
+    store: &dyn MetadataStore,
+    now: u64,
+) -> anyhow::Result<Migration> {
+    let known: HashSet<PathBuf> = store
+        .files()?
+        .into_iter()
+        .map(|stored| stored.file.path)
+        .collect();
+    let recovered_dir = storage_dir.join(RECOVERED_DIR);
+    let mut migration = Migration::default();
+
+    // Blobs in the old layout, and unfinished segment uploads
+    let entries = match std::fs::read_dir(storage_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(migration),
+        Err(e) => return Err(e.into()),
+    };
+    let mut entries: Vec<PathBuf> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .collect();
+    entries.sort();
+    for path in entries {
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        if path.is_dir() {
+            let segments = std::fs::read_dir(&path).is_ok_and(|mut entries| {
+                entries.any(|entry| {
+                    entry.is_ok_and(|entry| {
+                        entry.file_name().to_string_lossy().starts_with("segment_")
+                    })
+                })
+            });
+            if segments {
+                migration.leftovers.push(Leftover {
+                    path,
+                    reason: "segments of an unfinished upload",
+                });
+            }
+            continue;
+        }
+        let Some(id) = blob_id(&name) else {
+            continue;
+        };
+        if known.contains(&path) {
+            continue;
+        }
+        let size = path.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        if size == 0 {
+            migration.leftovers.push(Leftover {
+                path,
+                reason: "empty, the upload never got any data",
+            });
+            continue;
+        }
+        match recover_blob(&path, id, &recovered_dir) {
+            Ok(target) => {
+                let name = target
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .trim_end_matches(BLOCKED_SUFFIX)
+                    .to_string();
+                let file = recovered_file(id.to_string(), name, target, size, now);
+                migration.recovered.push(file);
+            }
+            Err(e) => {
+                log::warn!("Failed to recover {:?}: {}", path, e);
+                migration.leftovers.push(Leftover {
+                    path,
+                    reason: "could not be moved",
+                });
+            }
+        }
+    }
+
+    // Files recovered before, e.g. by `justrans migrate` while records
+    // were only kept in memory
+    if let Ok(entries) = std::fs::read_dir(&recovered_dir) {
+        let mut entries: Vec<PathBuf> = entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .collect();
+        entries.sort();
+        for path in entries {
+            if !path.is_file()
+                || known.contains(&path)
+                || migration.recovered.iter().any(|file| file.path == path)
+            {
+                continue;
+            }
+            let name = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .trim_end_matches(BLOCKED_SUFFIX)
+                .to_string();
+            let size = path.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            let id = uuid::Uuid::new_v4().to_string();
+            migration
+                .recovered
+                .push(recovered_file(id, name, path, size, now));
+        }
+    }
+
+    // Room files don't outlive the app, so nothing can list them again
+    if let Ok(rooms) = std::fs::read_dir(storage_dir.join("rooms")) {
+        for room in rooms.filter_map(|entry| entry.ok()) {
+            if let Ok(files) = std::fs::read_dir(room.path()) {
+                for file in files.filter_map(|entry| entry.ok()) {
+                    migration.leftovers.push(Leftover {
+                        path: file.path(),
+                        reason: "shared in a room that no longer exists",
+                    });
+                }
+            }
+        }
+    }
+
+    let changes: Vec<_> = migration
+        .recovered
+        .iter()
+        .map(|file| {
+            Change::PutFile(StoredFile {
+                room: None,
+                file: file.clone(),
+            })
+        })
+        .collect();
+    metadata::save(store, &changes);
+    Ok(migration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::MemoryStore;
+
+    #[test]
+    fn test_guess_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let cases: [(&[u8], Option<&str>); 6] = [
+            (b"\xff\xd8\xff\xe0\x00\x10JFIF", Some("jpg")),
+            (b"\x00\x00\x00\x18ftypmp42", Some("mp4")),
+            (b"RIFF\x10\x00\x00\x00WEBPVP8 ", Some("webp")),
+            ("Grüße\n".as_bytes(), Some("txt")),
+            (b"\x00\x01\x02\x03", None),
+            (b"", None),
+        ];
+        for (i, (contents, extension)) in cases.into_iter().enumerate() {
+            let path = dir.path().join(i.to_string());
+            std::fs::write(&path, contents).unwrap();
+            assert_eq!(guess_extension(&path), extension, "{:?}", contents);
+        }
+    }
+
+    #[test]
+    fn test_blob_id() {
+        assert_eq!(blob_id("1234_file"), Some("1234"));
+        assert_eq!(blob_id("1234_file.blocked"), Some("1234"));
+        assert_eq!(blob_id("_file"), None);
+        assert_eq!(blob_id("photo.jpg"), None);
+    }
+
+    #[test]
+    fn test_migrate() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = dir.path();
+        std::fs::write(storage.join("a_file"), b"%PDF-1.7").unwrap();
+        std::fs::write(storage.join("b_file.blocked"), b"#!/bin/sh\necho hi\n").unwrap();
+        std::fs::write(storage.join("c_file"), b"").unwrap();
+        std::fs::write(storage.join("notes.txt"), b"not ours").unwrap();
+        std::fs::create_dir(storage.join("d")).unwrap();
+        std::fs::write(storage.join("d/segment_0"), b"part").unwrap();
+        std::fs::create_dir_all(storage.join("rooms/party")).unwrap();
+        std::fs::write(storage.join("rooms/party/e_file"), b"room").unwrap();
+
+        // Files with a record are left alone
+        let store = MemoryStore::default();
+        let kept = recovered_file(
+            "k".to_string(),
+            "kept.txt".to_string(),
+            storage.join("k_file"),
+            4,
+            1000,
+        );
+        std::fs::write(&kept.path, b"kept").unwrap();
+        metadata::save(
+            &store,
+            &[Change::PutFile(StoredFile {
+                room: None,
+                file: kept,
+            })],
+        );
+
+        let migration = migrate(storage, &store, 1000).unwrap();
+        let recovered: Vec<_> = migration
+            .recovered
+            .iter()
+            .map(|file| (file.id.as_str(), file.name.as_str(), file.quarantined))
+            .collect();
+        assert_eq!(
+            recovered,
+            [
+                ("a", "recovered_a.pdf", true),
+                ("b", "recovered_b.txt", true),
+            ]
+        );
+        assert!(storage.join("recovered/recovered_a.pdf.blocked").is_file());
+        assert!(storage.join("recovered/recovered_b.txt.blocked").is_file());
+        assert!(storage.join("k_file").is_file());
+
+        let leftovers: Vec<_> = migration
+            .leftovers
+            .iter()
+            .map(|leftover| leftover.path.strip_prefix(storage).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            leftovers,
+            [
+                PathBuf::from("c_file"),
+                PathBuf::from("d"),
+                PathBuf::from("rooms/party/e_file"),
+            ]
+        );
+        assert_eq!(store.files().unwrap().len(), 3);
+
+        // A second run finds nothing new
+        let migration = migrate(storage, &store, 1000).unwrap();
+        assert!(migration.recovered.is_empty());
+
+        // Records kept in memory are gone on the next start, but the
+        // recovered files are listed again under their names
+        std::fs::remove_file(storage.join("k_file")).unwrap();
+        quarantine::release(&storage.join("recovered/recovered_a.pdf.blocked")).unwrap();
+        let migration = migrate(storage, &MemoryStore::default(), 1000).unwrap();
+        let names: Vec<_> = migration
+            .recovered
+            .iter()
+            .map(|file| (file.name.as_str(), file.quarantined))
+            .collect();
+        assert_eq!(
+            names,
+            [("recovered_a.pdf", false), ("recovered_b.txt", true)]
+        );
+    }
+}
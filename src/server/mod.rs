@@ -1,3 +1,58 @@
+pub mod api;
+pub mod archive;
+pub mod assemble;
+pub mod assets;
+pub mod auth;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod chunks;
+pub mod client_ip;
+pub mod conditional;
+pub mod conflicts;
+pub mod delta;
+pub mod devices;
+pub mod diagnostics;
+pub mod event_log;
 pub mod file_server;
+pub mod file_types;
+pub mod firewall;
+pub mod form_upload;
+pub mod headers;
+pub mod http;
+pub mod i18n;
+pub mod inhibit;
+pub mod link_preview;
+pub mod live;
+pub mod memory;
+pub mod metrics;
+pub mod migrate;
+pub mod network;
+pub mod onetime;
+pub mod organize;
+pub mod pairing;
+pub mod permissions;
+pub mod power;
+pub mod preflight;
+pub mod protected;
+pub mod quarantine;
+pub mod ranges;
+pub mod reachability;
+pub mod records;
+pub mod remote;
+pub mod rooms;
+pub mod sanitize;
+pub mod schedule;
+pub mod scratch;
+pub mod scrub;
+pub mod segments;
+pub mod shared_paths;
+pub mod snippets;
+pub mod speedtest;
+pub mod stats;
+pub mod tls;
+pub mod transfers;
+pub mod upload_ids;
+pub mod upload_progress;
+pub mod upload_sessions;
 
 pub use file_server::FileServer;
@@ -0,0 +1,106 @@
+use std::process::Command;
+
+/// Run a command and capture stdout, returning None if it could not be executed
+/// or failed
+pub fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Interpret the output of `nmcli -t -f active,ssid dev wifi`
+pub fn parse_nmcli(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix("yes:"))
+        .map(|ssid| ssid.replace("\\:", ":"))
+        .filter(|ssid| !ssid.is_empty())
+}
+
+/// Interpret the output of `netsh wlan show interfaces`
+pub fn parse_netsh(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "SSID")
+            .then(|| value.trim().to_string())
+            .filter(|ssid| !ssid.is_empty())
+    })
+}
+
+/// Interpret the output of `networksetup -getairportnetwork <device>`
+pub fn parse_networksetup(output: &str) -> Option<String> {
+    output
+        .trim()
+        .strip_prefix("Current Wi-Fi Network:")
+        .map(|ssid| ssid.trim().to_string())
+        .filter(|ssid| !ssid.is_empty())
+}
+
+fn ssid_linux() -> Option<String> {
+    run("nmcli", &["-t", "-f", "active,ssid", "dev", "wifi"])
+        .and_then(|output| parse_nmcli(&output))
+        .or_else(|| {
+            run("iwgetid", &["-r"])
+                .map(|output| output.trim().to_string())
+                .filter(|ssid| !ssid.is_empty())
+        })
+}
+
+fn ssid_windows() -> Option<String> {
+    run("netsh", &["wlan", "show", "interfaces"]).and_then(|output| parse_netsh(&output))
+}
+
+fn ssid_macos() -> Option<String> {
+    ["en0", "en1"].iter().find_map(|device| {
+        run("networksetup", &["-getairportnetwork", device])
+            .and_then(|output| parse_networksetup(&output))
+    })
+}
+
+/// SSID of the Wi-Fi network this machine is connected to, if any
+pub fn current_ssid() -> Option<String> {
+    let ssid = if cfg!(target_os = "windows") {
+        ssid_windows()
+    } else if cfg!(target_os = "macos") {
+        ssid_macos()
+    } else {
+        ssid_linux()
+    };
+    log::debug!("Detected Wi-Fi network: {:?}", ssid);
+    ssid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nmcli() {
+        let output = "no:Neighbours\nyes:Home\\:5G\nno:\n";
+        assert_eq!(parse_nmcli(output), Some("Home:5G".to_string()));
+        assert_eq!(parse_nmcli("no:Home\n"), None);
+    }
+
+    #[test]
+    fn test_parse_netsh() {
+        let output = "    Name                   : Wi-Fi\n    \
+                      SSID                   : Office Net\n    \
+                      BSSID                  : 00:11:22:33:44:55\n";
+        assert_eq!(parse_netsh(output), Some("Office Net".to_string()));
+        assert_eq!(parse_netsh("    State : disconnected\n"), None);
+    }
+
+    #[test]
+    fn test_parse_networksetup() {
+        assert_eq!(
+            parse_networksetup("Current Wi-Fi Network: Home\n"),
+            Some("Home".to_string())
+        );
+        assert_eq!(
+            parse_networksetup("You are not associated with an AirPort network.\n"),
+            None
+        );
+    }
+}
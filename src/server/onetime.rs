@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Serialize;
+
+use super::file_server::{request_base_url, send_file, AppState};
+use crate::metadata::{self, Change, MetadataStore, ShareRecord};
+use crate::models::unix_now;
+
+/// How long a one-time link stays valid if it is never used
+pub const LINK_TTL_SECS: u64 = 60 * 60;
+
+/// A single-use download link for one file on the main share
+#[derive(Debug, Clone)]
+pub struct OneTimeLink {
+    pub file_id: String,
+    pub expires_at: u64,
+}
+
+/// Outstanding one-time links, keyed by token, kept in a metadata store
+#[derive(Clone)]
+pub struct OneTimeLinks {
+    links: Arc<Mutex<HashMap<String, OneTimeLink>>>,
+    store: Arc<dyn MetadataStore>,
+}
+
+impl OneTimeLinks {
+    /// The links in `store` still valid at `now`; expired ones are
+    /// forgotten
+    pub fn restore(store: Arc<dyn MetadataStore>, now: u64) -> Self {
+        let shares = store.shares().unwrap_or_else(|e| {
+            log::error!("Failed to read one-time links: {}", e);
+            Vec::new()
+        });
+        let (valid, expired): (Vec<_>, Vec<_>) =
+            shares.into_iter().partition(|share| share.expires_at > now);
+        let expired: Vec<_> = expired
+            .into_iter()
+            .map(|share| Change::RemoveShare(share.token))
+            .collect();
+        metadata::save(store.as_ref(), &expired);
+
+        let links = valid
+            .into_iter()
+            .map(|share| {
+                let link = OneTimeLink {
+                    file_id: share.file_id,
+                    expires_at: share.expires_at,
+                };
+                (share.token, link)
+            })
+            .collect();
+        Self {
+            links: Arc::new(Mutex::new(links)),
+            store,
+        }
+    }
+
+    /// Create a link for a file and return its token
+    pub fn create(&self, file_id: &str, now: u64) -> (String, OneTimeLink) {
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        let link = OneTimeLink {
+            file_id: file_id.to_string(),
+            expires_at: now + LINK_TTL_SECS,
+        };
+
+        let mut links = self.links.lock().unwrap();
+        let mut changes = Vec::new();
+        links.retain(|token, link| {
+            let valid = link.expires_at > now;
+            if !valid {
+                changes.push(Change::RemoveShare(token.clone()));
+            }
+            valid
+        });
+        links.insert(token.clone(), link.clone());
+        changes.push(Change::PutShare(ShareRecord {
+            token: token.clone(),
+            file_id: link.file_id.clone(),
+            expires_at: link.expires_at,
+        }));
+        metadata::save(self.store.as_ref(), &changes);
+        (token, link)
+    }
+
+    /// Use up a link; a token can only ever be redeemed once
+    pub fn redeem(&self, token: &str, now: u64) -> Option<OneTimeLink> {
+        let link = self.links.lock().unwrap().remove(token)?;
+        metadata::save(
+            self.store.as_ref(),
+            &[Change::RemoveShare(token.to_string())],
+        );
+        Some(link).filter(|link| link.expires_at > now)
+    }
+
+    pub fn clear(&self) {
+        let forgotten: Vec<_> = self
+            .links
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(token, _)| Change::RemoveShare(token))
+            .collect();
+        metadata::save(self.store.as_ref(), &forgotten);
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct OneTimeLinkResponse {
+    url: String,
+    expires_at: u64,
+}
+
+#[axum::debug_handler]
+async fn create_link(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Json<OneTimeLinkResponse>, StatusCode> {
+    let protected = state
+        .file_list
+        .lock()
+        .unwrap()
+        .get_file_by_id(&id)
+        .ok_or(StatusCode::NOT_FOUND)?
+        .protected;
+    // Redeeming a link skips the password, so protected files get none
+    if protected {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let (token, link) = state.onetime_links.create(&id, unix_now());
+    let path = format!("/d/{}", token);
+    let url = match request_base_url(&headers, state.scheme()) {
+        Some(base_url) => format!("{}{}", base_url, path),
+        None => path,
+    };
+    log::info!("Created one-time link for file {}", id);
+
+    Ok(Json(OneTimeLinkResponse {
+        url,
+        expires_at: link.expires_at,
+    }))
+}
+
+#[axum::debug_handler]
+async fn redeem_link(
+    Path(token): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Response, StatusCode> {
+    let link = state
+        .onetime_links
+        .redeem(&token, unix_now())
+        .ok_or(StatusCode::GONE)?;
+    log::info!("One-time link used for file {}", link.file_id);
+    // Used only once, so there is no cached copy to revalidate
+    send_file(
+        &state,
+        &state.file_list,
+        &link.file_id,
+        &HeaderMap::new(),
+        None,
+    )
+    .await
+}
+
+/// Route serving one-time downloads, outside the portal and its API
+pub fn router() -> Router<AppState> {
+    Router::new().route("/d/:token", get(redeem_link))
+}
+
+/// One-time link creation, nested under the versioned API prefix
+pub fn api_router() -> Router<AppState> {
+    Router::new().route("/files/:id/onetime", post(create_link))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::MemoryStore;
+
+    fn links() -> OneTimeLinks {
+        OneTimeLinks::restore(Arc::new(MemoryStore::default()), 0)
+    }
+
+    #[test]
+    fn test_link_is_single_use() {
+        let links = links();
+        let (token, _) = links.create("file", 1000);
+
+        assert_eq!(links.redeem(&token, 1001).unwrap().file_id, "file");
+        assert!(links.redeem(&token, 1002).is_none());
+    }
+
+    #[test]
+    fn test_link_expires() {
+        let links = links();
+        let (token, link) = links.create("file", 1000);
+
+        assert!(links.redeem(&token, link.expires_at).is_none());
+    }
+
+    #[test]
+    fn test_links_are_restored() {
+        let store: Arc<dyn MetadataStore> = Arc::new(MemoryStore::default());
+        let links = OneTimeLinks::restore(store.clone(), 0);
+        let (kept, _) = links.create("kept", 10_000);
+        let (used, _) = links.create("used", 10_000);
+        links.create("expired", 10_000 - LINK_TTL_SECS);
+        links.redeem(&used, 10_001);
+
+        let restored = OneTimeLinks::restore(store.clone(), 10_001);
+        assert_eq!(store.shares().unwrap().len(), 1);
+        assert!(restored.redeem(&used, 10_002).is_none());
+        assert_eq!(restored.redeem(&kept, 10_002).unwrap().file_id, "kept");
+        assert!(store.shares().unwrap().is_empty());
+    }
+}
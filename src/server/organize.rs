@@ -0,0 +1,106 @@
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Datelike, Local};
+
+/// Whether a received file is a photo or video that should be sorted by date
+pub fn is_media(name: &str) -> bool {
+    let mime = mime_guess::from_path(name).first_or_octet_stream();
+    mime.type_() == mime_guess::mime::IMAGE || mime.type_() == mime_guess::mime::VIDEO
+}
+
+/// Year and month a photo was taken, from its EXIF data
+pub fn capture_date(path: &Path) -> Option<(u16, u8)> {
+    let file = std::fs::File::open(path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?;
+    let field = exif
+        .get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)
+        .or_else(|| exif.get_field(exif::Tag::DateTime, exif::In::PRIMARY))?;
+    match &field.value {
+        exif::Value::Ascii(values) => {
+            let date = exif::DateTime::from_ascii(values.first()?).ok()?;
+            (1..=12)
+                .contains(&date.month)
+                .then_some((date.year, date.month))
+        }
+        _ => None,
+    }
+}
+
+/// `YYYY/MM` folder for a file, using the capture date if known and
+/// `received` otherwise
+pub fn date_folder(path: &Path, received: DateTime<Local>) -> PathBuf {
+    let (year, month) =
+        capture_date(path).unwrap_or((received.year() as u16, received.month() as u8));
+    PathBuf::from(format!("{:04}", year)).join(format!("{:02}", month))
+}
+
+/// Move a received file into its date folder under `storage_dir`, keeping
+/// its original name, and return the new path
+pub fn organize(storage_dir: &Path, path: &Path, name: &str) -> std::io::Result<PathBuf> {
+    let folder = storage_dir.join(date_folder(path, Local::now()));
+    std::fs::create_dir_all(&folder)?;
+
+    // Never let a client-supplied name escape the folder
+    let name = Path::new(name)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "file".to_string());
+    let target = free_path(&folder, &name);
+    std::fs::rename(path, &target)?;
+    Ok(target)
+}
+
+/// First path in `folder` not taken on disk, numbering copies as `name (n).ext`
+pub fn free_path(folder: &Path, name: &str) -> PathBuf {
+    let candidate = folder.join(name);
+    if !candidate.exists() {
+        return candidate;
+    }
+
+    let path = Path::new(name);
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| folder.join(format!("{} ({}){}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_is_media() {
+        assert!(is_media("IMG_0001.JPG"));
+        assert!(is_media("clip.mp4"));
+        assert!(!is_media("report.pdf"));
+        assert!(!is_media("notes"));
+    }
+
+    #[test]
+    fn test_organize_falls_back_to_received_date() {
+        let dir = tempdir().unwrap();
+        let upload = dir.path().join("abc_file");
+        std::fs::write(&upload, b"not really a video").unwrap();
+
+        let received = Local.with_ymd_and_hms(2024, 3, 9, 12, 0, 0).unwrap();
+        assert_eq!(date_folder(&upload, received), PathBuf::from("2024/03"));
+
+        let target = organize(dir.path(), &upload, "../clip.mp4").unwrap();
+        assert!(target.starts_with(dir.path()));
+        assert_eq!(target.file_name().unwrap(), "clip.mp4");
+        assert!(target.exists() && !upload.exists());
+    }
+}
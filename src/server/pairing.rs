@@ -0,0 +1,181 @@
+//! Pairing bundles for companion clients. A mobile or CLI client imports the
+//! bundle to learn where the instance can be reached and which credentials
+//! to present, instead of the user typing in addresses. Bundles are signed
+//! with the instance's identity, whose fingerprint the client pins.
+
+use axum::extract::State;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+
+use super::api;
+use super::file_server::AppState;
+use crate::identity::{self, Identity};
+use crate::models::unix_now;
+
+/// Format version of the bundle, raised on incompatible changes
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// How long the pairing token in a bundle stays valid
+pub const PAIRING_TTL_SECS: u64 = 10 * 60;
+
+/// What a client needs to reach this instance
+#[derive(Clone)]
+pub struct Pairing {
+    /// Name the instance is listed under in the client
+    pub server_name: String,
+    /// Addresses the server is listening on
+    pub urls: Vec<String>,
+    /// PIN the portal requires, if any
+    pub pin: Option<String>,
+    /// SHA-256 of the certificate the portal is served with over HTTPS
+    pub certificate_fingerprint: Option<String>,
+}
+
+impl Pairing {
+    pub fn new(server_name: String) -> Self {
+        Self {
+            server_name,
+            urls: Vec::new(),
+            pin: None,
+            certificate_fingerprint: None,
+        }
+    }
+
+    /// A bundle with a fresh pairing token, valid from `now` and signed by
+    /// `identity`
+    pub fn bundle(&self, identity: &Identity, now: u64) -> SignedBundle {
+        let bundle = PairingBundle {
+            version: BUNDLE_VERSION,
+            api_version: api::CURRENT_VERSION,
+            server_name: self.server_name.clone(),
+            urls: self.urls.clone(),
+            certificate_fingerprint: self.certificate_fingerprint.clone(),
+            identity_key: identity.public_key(),
+            identity_fingerprint: identity.fingerprint(),
+            pin: self.pin.clone(),
+            pairing_token: uuid::Uuid::new_v4().simple().to_string(),
+            issued_at: now,
+            expires_at: now + PAIRING_TTL_SECS,
+        };
+        let signature = identity.sign(bundle.canonical().as_bytes());
+        SignedBundle { bundle, signature }
+    }
+}
+
+/// Connection details handed to a companion client
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PairingBundle {
+    pub version: u32,
+    pub api_version: u32,
+    pub server_name: String,
+    pub urls: Vec<String>,
+    /// SHA-256 of the TLS certificate, hex encoded, when HTTPS is served
+    pub certificate_fingerprint: Option<String>,
+    /// Ed25519 public key of the instance, hex encoded
+    pub identity_key: String,
+    /// SHA-256 of `identity_key`, which the client pins
+    pub identity_fingerprint: String,
+    pub pin: Option<String>,
+    pub pairing_token: String,
+    pub issued_at: u64,
+    pub expires_at: u64,
+}
+
+impl PairingBundle {
+    /// The exact text the signature covers
+    fn canonical(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// A bundle with the hex encoded Ed25519 signature of its JSON by
+/// `identity_key`, which also vouches for the pairing token in it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedBundle {
+    pub bundle: PairingBundle,
+    pub signature: String,
+}
+
+/// Name shown for the instance: the profile name, or the computer's name
+pub fn server_name(profile: Option<&str>) -> String {
+    if let Some(profile) = profile {
+        return profile.to_string();
+    }
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "JusTrans".to_string())
+}
+
+#[axum::debug_handler]
+async fn get_bundle(State(state): State<AppState>) -> Json<SignedBundle> {
+    log::info!("Issued a pairing bundle");
+    Json(state.pairing.bundle(identity::get(), unix_now()))
+}
+
+/// Pairing bundle download, nested under the versioned API prefix
+pub fn api_router() -> Router<AppState> {
+    Router::new().route("/pairing-bundle", get(get_bundle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    fn verifies(signed: &SignedBundle) -> bool {
+        let key: [u8; 32] = hex::decode(&signed.bundle.identity_key)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let signature: [u8; 64] = hex::decode(&signed.signature).unwrap().try_into().unwrap();
+        VerifyingKey::from_bytes(&key)
+            .unwrap()
+            .verify(
+                signed.bundle.canonical().as_bytes(),
+                &Signature::from_bytes(&signature),
+            )
+            .is_ok()
+    }
+
+    #[test]
+    fn test_bundle_is_signed() {
+        let identity = Identity::generate();
+        let mut pairing = Pairing::new("office".to_string());
+        pairing.urls = vec!["http://192.168.1.5:8080".to_string()];
+
+        let signed = pairing.bundle(&identity, 1000);
+        assert_eq!(signed.bundle.server_name, "office");
+        assert_eq!(signed.bundle.expires_at, 1000 + PAIRING_TTL_SECS);
+        assert_eq!(signed.bundle.identity_fingerprint, identity.fingerprint());
+        assert!(verifies(&signed));
+
+        // Every bundle gets its own token, and a changed bundle no longer
+        // matches its signature
+        let other = pairing.bundle(&identity, 1000);
+        assert_ne!(other.bundle.pairing_token, signed.bundle.pairing_token);
+        let mut tampered = signed.clone();
+        tampered.bundle.urls = vec!["http://203.0.113.9:8080".to_string()];
+        assert!(!verifies(&tampered));
+    }
+
+    #[test]
+    fn test_bundle_json() {
+        let signed = Pairing::new("office".to_string()).bundle(&Identity::generate(), 1000);
+        let json = serde_json::to_value(&signed).unwrap();
+        assert_eq!(json["bundle"]["version"], BUNDLE_VERSION);
+        assert!(json["bundle"]["certificate_fingerprint"].is_null());
+        assert_eq!(json["signature"].as_str().unwrap().len(), 128);
+
+        // Served over HTTPS
+        let mut pairing = Pairing::new("office".to_string());
+        pairing.certificate_fingerprint = Some("ab".repeat(32));
+        let signed = pairing.bundle(&Identity::generate(), 1000);
+        let json = serde_json::to_value(&signed).unwrap();
+        assert_eq!(json["bundle"]["certificate_fingerprint"], "ab".repeat(32));
+    }
+}
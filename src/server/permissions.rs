@@ -0,0 +1,150 @@
+//! Permission bits of received files on Unix. A stored file gets the mode
+//! set in `storage.permissions`, or keeps what the umask gave it, without
+//! executable bits unless its sender declared them and they are kept.
+//! Quarantined files get those bits only once released.
+
+use std::path::Path;
+
+use crate::config::{ExecutableBits, PermissionsConfig};
+
+/// Executable bits of the owner, the group and others
+const EXECUTABLE: u32 = 0o111;
+
+/// Header with the octal permission bits of the file in a request that
+/// carries only its contents
+pub const MODE_HEADER: &str = "x-file-mode";
+
+/// Parse octal permission bits such as "755" or "0640"
+pub fn parse_mode(mode: &str) -> Option<u32> {
+    u32::from_str_radix(mode.trim(), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+}
+
+/// Bits a received file should be stored with, given those it has now and
+/// those its sender declared. Executable bits are only kept for whoever
+/// may read the file.
+pub fn stored_mode(config: &PermissionsConfig, current: u32, declared: Option<u32>) -> u32 {
+    let base = parse_mode(&config.mode).unwrap_or(current & 0o777) & !EXECUTABLE;
+    let executable = match (config.executable, declared) {
+        (ExecutableBits::Keep, Some(declared)) => declared & (base & 0o444) >> 2,
+        _ => 0,
+    };
+    base | executable
+}
+
+/// Permission bits of the file at `path`, to declare when sending it;
+/// `None` off Unix
+pub fn mode_of(path: &Path) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::metadata(path)
+            .ok()
+            .map(|metadata| metadata.permissions().mode() & 0o777)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Give the received file at `path` the bits it should be stored with and
+/// return them; a quarantined file gets them without the executable bits.
+/// `None` off Unix or when they couldn't be set.
+pub fn apply(
+    config: &PermissionsConfig,
+    path: &Path,
+    declared: Option<u32>,
+    quarantined: bool,
+) -> Option<u32> {
+    if !config.mode.is_empty() && parse_mode(&config.mode).is_none() {
+        log::warn!("Ignoring invalid storage permissions '{}'", config.mode);
+    }
+    let mode = stored_mode(config, mode_of(path)?, declared);
+    let on_disk = if quarantined {
+        mode & !EXECUTABLE
+    } else {
+        mode
+    };
+    match set_mode(path, on_disk) {
+        Ok(()) => Some(mode),
+        Err(e) => {
+            log::warn!("Failed to set the permissions of {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Set the permission bits of the file at `path`; does nothing off Unix
+pub fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, mode);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(mode: &str, executable: ExecutableBits) -> PermissionsConfig {
+        PermissionsConfig {
+            mode: mode.to_string(),
+            executable,
+        }
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("755"), Some(0o755));
+        assert_eq!(parse_mode("0640"), Some(0o640));
+        assert_eq!(parse_mode("1777"), None);
+        assert_eq!(parse_mode("rwx"), None);
+        assert_eq!(parse_mode(""), None);
+    }
+
+    #[test]
+    fn test_stored_mode() {
+        let strip = config("", ExecutableBits::Strip);
+        assert_eq!(stored_mode(&strip, 0o644, Some(0o755)), 0o644);
+        assert_eq!(stored_mode(&strip, 0o755, None), 0o644);
+
+        let keep = config("", ExecutableBits::Keep);
+        assert_eq!(stored_mode(&keep, 0o644, Some(0o755)), 0o755);
+        assert_eq!(stored_mode(&keep, 0o644, Some(0o700)), 0o744);
+        assert_eq!(stored_mode(&keep, 0o644, None), 0o644);
+
+        // Only those who may read the file may run it
+        let private = config("600", ExecutableBits::Keep);
+        assert_eq!(stored_mode(&private, 0o644, Some(0o755)), 0o700);
+        let invalid = config("99", ExecutableBits::Strip);
+        assert_eq!(stored_mode(&invalid, 0o640, Some(0o755)), 0o640);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_apply() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("build.sh");
+        std::fs::write(&path, "#!/bin/sh\n").unwrap();
+        set_mode(&path, 0o644).unwrap();
+
+        let keep = config("", ExecutableBits::Keep);
+        assert_eq!(apply(&keep, &path, Some(0o755), true), Some(0o755));
+        assert_eq!(mode_of(&path), Some(0o644));
+        assert_eq!(apply(&keep, &path, Some(0o755), false), Some(0o755));
+        assert_eq!(mode_of(&path), Some(0o755));
+
+        let normalized = config("640", ExecutableBits::Strip);
+        assert_eq!(apply(&normalized, &path, Some(0o755), false), Some(0o640));
+        assert_eq!(mode_of(&path), Some(0o640));
+    }
+}
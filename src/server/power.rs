@@ -0,0 +1,163 @@
+use std::path::Path;
+use std::time::Duration;
+
+use settings::Settings;
+use tokio::sync::watch;
+
+use super::network::run;
+use crate::config::ConfigData;
+
+/// How often postponed background work checks whether mains power is back
+const RECHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Interpret the output of `pmset -g batt`
+pub fn parse_pmset(output: &str) -> Option<bool> {
+    let first_line = output.lines().next()?;
+    if first_line.contains("'Battery Power'") {
+        Some(true)
+    } else if first_line.contains("'AC Power'") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Interpret the `BatteryStatus` of `Win32_Battery`: 1 means discharging,
+/// no output means there is no battery
+pub fn parse_battery_status(output: &str) -> Option<bool> {
+    let status = output
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())?;
+    Some(status == "1")
+}
+
+/// Whether a battery in a sysfs `power_supply` directory is discharging
+fn on_battery_sysfs(dir: &Path) -> Option<bool> {
+    let read = |supply: &Path, name: &str| {
+        std::fs::read_to_string(supply.join(name))
+            .map(|value| value.trim().to_string())
+            .unwrap_or_default()
+    };
+    let mut has_battery = false;
+    for entry in std::fs::read_dir(dir).ok()?.flatten() {
+        let supply = entry.path();
+        if read(&supply, "type") == "Battery" {
+            has_battery = true;
+            if read(&supply, "status") == "Discharging" {
+                return Some(true);
+            }
+        }
+    }
+    has_battery.then_some(false)
+}
+
+/// Whether this machine is running on battery; `None` on desktops and when
+/// the power source cannot be read
+pub fn on_battery() -> Option<bool> {
+    let on_battery = if cfg!(target_os = "windows") {
+        run(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-Command",
+                "(Get-CimInstance Win32_Battery).BatteryStatus",
+            ],
+        )
+        .and_then(|output| parse_battery_status(&output))
+    } else if cfg!(target_os = "macos") {
+        run("pmset", &["-g", "batt"]).and_then(|output| parse_pmset(&output))
+    } else {
+        on_battery_sysfs(Path::new("/sys/class/power_supply"))
+    };
+    log::debug!("Running on battery: {:?}", on_battery);
+    on_battery
+}
+
+/// Whether background work should wait for mains power
+fn defer_background_tasks() -> bool {
+    let enabled = ConfigData::instance()
+        .map(|instance| instance.lock().unwrap().power.defer_background_tasks)
+        .unwrap_or(false);
+    enabled && on_battery() == Some(true)
+}
+
+/// Wait until background work such as scrubbing may run, which on battery
+/// is when mains power is back. Returns `false` if the server shuts down
+/// in the meantime.
+pub async fn wait_for_background_work(task: &str, shutdown: &mut watch::Receiver<bool>) -> bool {
+    let mut postponed = false;
+    while tokio::task::spawn_blocking(defer_background_tasks)
+        .await
+        .unwrap_or(false)
+    {
+        if !postponed {
+            log::info!("Running on battery, postponing {}", task);
+            postponed = true;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(RECHECK_INTERVAL) => {}
+            _ = shutdown.changed() => return false,
+        }
+    }
+    true
+}
+
+/// Warning to show before transferring `size` bytes on battery, if the
+/// transfer is large enough to drain it noticeably
+pub fn large_transfer_warning(size: u64) -> Option<String> {
+    let threshold_mb = ConfigData::instance()
+        .map(|instance| instance.lock().unwrap().power.large_transfer_warning_mb)
+        .unwrap_or(0);
+    if threshold_mb == 0 || size < threshold_mb * 1024 * 1024 || on_battery() != Some(true) {
+        return None;
+    }
+    Some(format!(
+        "Warning: running on battery, transferring {} may drain it. Plug in to be safe.",
+        humanize::size(size)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pmset() {
+        let battery = "Now drawing from 'Battery Power'\n -InternalBattery-0 (id=1234)\t81%; discharging; 4:12 remaining present: true\n";
+        assert_eq!(parse_pmset(battery), Some(true));
+        assert_eq!(
+            parse_pmset("Now drawing from 'AC Power'\n -InternalBattery-0 (id=1234)\t100%; charged; 0:00 remaining present: true\n"),
+            Some(false)
+        );
+        assert_eq!(parse_pmset(""), None);
+    }
+
+    #[test]
+    fn test_parse_battery_status() {
+        assert_eq!(parse_battery_status("1\r\n"), Some(true));
+        assert_eq!(parse_battery_status("\r\n2\r\n"), Some(false));
+        assert_eq!(parse_battery_status(""), None);
+    }
+
+    #[test]
+    fn test_on_battery_sysfs() {
+        let dir = tempfile::tempdir().unwrap();
+        let supply = |name: &str, kind: &str, status: Option<&str>| {
+            let path = dir.path().join(name);
+            std::fs::create_dir(&path).unwrap();
+            std::fs::write(path.join("type"), format!("{}\n", kind)).unwrap();
+            if let Some(status) = status {
+                std::fs::write(path.join("status"), format!("{}\n", status)).unwrap();
+            }
+        };
+
+        assert_eq!(on_battery_sysfs(dir.path()), None);
+        supply("AC", "Mains", None);
+        assert_eq!(on_battery_sysfs(dir.path()), None);
+        supply("BAT0", "Battery", Some("Charging"));
+        assert_eq!(on_battery_sysfs(dir.path()), Some(false));
+        supply("BAT1", "Battery", Some("Discharging"));
+        assert_eq!(on_battery_sysfs(dir.path()), Some(true));
+    }
+}
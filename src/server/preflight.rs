@@ -0,0 +1,228 @@
+//! Upload preflight (`POST /api/v1/preflight`): a client declares the files
+//! it is about to send and learns which of them the share would take, the
+//! limits and free space they are checked against, and roughly how long
+//! sending them takes at the speed of recent uploads. The portal uses it to
+//! warn before starting an upload that would fail or take long.
+
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+
+use super::client_ip::ClientIp;
+use super::devices;
+use super::file_server::AppState;
+use super::sanitize::sanitize_name;
+
+/// A file the client is about to upload
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DeclaredFile {
+    pub name: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PreflightRequest {
+    pub files: Vec<DeclaredFile>,
+}
+
+/// Why the share would turn a file away
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    ReadOnly,
+    /// The file types rules don't accept the file's kind
+    KindNotAccepted,
+    /// More than the segment upload takes in one file
+    TooLarge,
+    /// Over the quota of the sending device
+    OverQuota,
+    /// More than the storage directory has room for
+    NoSpace,
+}
+
+/// What uploads are checked against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Limits {
+    pub read_only: bool,
+    /// Largest file the segment upload takes, in bytes
+    pub max_file_size: u64,
+    pub chunk_size: u64,
+    /// Bytes the device may still store, when its rule sets a quota
+    pub quota_remaining: Option<u64>,
+    /// Free bytes in the storage directory, when it could be read
+    pub free_space: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileCheck {
+    pub name: String,
+    pub size: u64,
+    /// Why the file would be turned away, if it would
+    pub problem: Option<Problem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PreflightResponse {
+    pub files: Vec<FileCheck>,
+    pub limits: Limits,
+    /// Bytes of the files that would be taken
+    pub total_size: u64,
+    /// Bytes per second of recent uploads, once one was measured
+    pub throughput: Option<u64>,
+    /// Rough seconds to send the files that would be taken, when the
+    /// throughput is known
+    pub eta_secs: Option<u64>,
+}
+
+/// Check `files` in order against `limits`. Quota and free space are used
+/// up by the files before, as if they were sent one after the other.
+pub fn check_files(
+    files: &[DeclaredFile],
+    limits: &Limits,
+    accepts: impl Fn(&str) -> bool,
+) -> Vec<FileCheck> {
+    let mut taken = 0u64;
+    files
+        .iter()
+        .map(|file| {
+            let after = taken.saturating_add(file.size);
+            let problem = if limits.read_only {
+                Some(Problem::ReadOnly)
+            } else if !accepts(&sanitize_name(&file.name)) {
+                Some(Problem::KindNotAccepted)
+            } else if file.size > limits.max_file_size {
+                Some(Problem::TooLarge)
+            } else if limits.quota_remaining.is_some_and(|quota| after > quota) {
+                Some(Problem::OverQuota)
+            } else if limits.free_space.is_some_and(|free| after > free) {
+                Some(Problem::NoSpace)
+            } else {
+                taken = after;
+                None
+            };
+            FileCheck {
+                name: file.name.clone(),
+                size: file.size,
+                problem,
+            }
+        })
+        .collect()
+}
+
+/// Seconds to send `bytes` at `throughput` bytes per second, rounded up
+pub fn eta_secs(bytes: u64, throughput: f64) -> Option<u64> {
+    (throughput > 0.0).then(|| (bytes as f64 / throughput).ceil() as u64)
+}
+
+#[axum::debug_handler]
+async fn preflight(
+    ClientIp(client_ip): ClientIp,
+    State(state): State<AppState>,
+    Json(request): Json<PreflightRequest>,
+) -> Json<PreflightResponse> {
+    let limits = Limits {
+        read_only: state.read_only(),
+        max_file_size: state.segment_limits.max_file_size(),
+        chunk_size: state.segment_limits.chunk_size,
+        quota_remaining: client_ip.and_then(|device| devices::quota_left(&state.file_list, device)),
+        free_space: fs2::available_space(&state.temp_dir).ok(),
+    };
+    let files = check_files(&request.files, &limits, |name| {
+        state.file_types.accepts(name)
+    });
+    let total_size = files
+        .iter()
+        .filter(|file| file.problem.is_none())
+        .map(|file| file.size)
+        .sum();
+    let throughput = state.stats.upload_speed();
+    Json(PreflightResponse {
+        files,
+        limits,
+        total_size,
+        throughput: throughput.map(|speed| speed as u64),
+        eta_secs: throughput.and_then(|speed| eta_secs(total_size, speed)),
+    })
+}
+
+/// Preflight requests, nested under the versioned API prefix
+pub fn api_router() -> Router<AppState> {
+    Router::new().route("/preflight", post(preflight))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits {
+        read_only: false,
+        max_file_size: 1000,
+        chunk_size: 100,
+        quota_remaining: None,
+        free_space: Some(1500),
+    };
+
+    fn file(name: &str, size: u64) -> DeclaredFile {
+        DeclaredFile {
+            name: name.to_string(),
+            size,
+        }
+    }
+
+    fn problems(files: &[DeclaredFile], limits: &Limits) -> Vec<Option<Problem>> {
+        check_files(files, limits, |name| !name.ends_with(".exe"))
+            .into_iter()
+            .map(|file| file.problem)
+            .collect()
+    }
+
+    #[test]
+    fn test_check_files() {
+        let files = [
+            file("video.mp4", 900),
+            file("setup.exe", 10),
+            file("huge.iso", 1001),
+            file("photo.jpg", 700),
+            file("notes.txt", 600),
+        ];
+        // The photo doesn't fit next to the video, the notes still do
+        assert_eq!(
+            problems(&files, &LIMITS),
+            vec![
+                None,
+                Some(Problem::KindNotAccepted),
+                Some(Problem::TooLarge),
+                Some(Problem::NoSpace),
+                None,
+            ]
+        );
+
+        let guest = Limits {
+            quota_remaining: Some(800),
+            ..LIMITS
+        };
+        assert_eq!(
+            problems(&files[3..], &guest),
+            vec![None, Some(Problem::OverQuota)]
+        );
+
+        let read_only = Limits {
+            read_only: true,
+            ..LIMITS
+        };
+        assert_eq!(
+            problems(&files[..1], &read_only),
+            vec![Some(Problem::ReadOnly)]
+        );
+    }
+
+    #[test]
+    fn test_eta() {
+        let gigabytes = 8 * 1024 * 1024 * 1024;
+        assert_eq!(eta_secs(gigabytes, 12.5 * 1024.0 * 1024.0), Some(656));
+        assert_eq!(eta_secs(10, 1000.0), Some(1));
+        assert_eq!(eta_secs(0, 1000.0), Some(0));
+        assert_eq!(eta_secs(10, 0.0), None);
+    }
+}
@@ -0,0 +1,96 @@
+//! Uploads protected by a password the sender chose, for dropping a
+//! confidential file on a host whose file list other guests can see. Only
+//! an argon2 hash of the password is kept, and downloads send the password
+//! in a form post rather than in the URL.
+
+use axum::http::StatusCode;
+use serde::Deserialize;
+
+use super::auth::basic::{hash_password, verify_password};
+use crate::models::FileInfo;
+
+/// Multipart field carrying the password of an upload
+pub const PASSWORD_FIELD: &str = "password";
+
+/// Form posted to download a protected file
+#[derive(Debug, Deserialize)]
+pub struct Unlock {
+    pub password: String,
+}
+
+/// Hash of the password the sender set, if any, for `FileInfo::password_hash`
+pub async fn hash(password: Option<String>) -> Result<Option<String>, StatusCode> {
+    let Some(password) = password.filter(|password| !password.is_empty()) else {
+        return Ok(None);
+    };
+    tokio::task::spawn_blocking(move || hash_password(&password))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(Some)
+        .map_err(|e| {
+            log::error!("{}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Whether `password` opens `file_info`; unprotected files need none
+pub async fn unlock(file_info: &FileInfo, password: Option<String>) -> Result<(), StatusCode> {
+    if !file_info.protected {
+        return Ok(());
+    }
+    // A protected file whose hash was lost opens for nobody
+    let (Some(hash), Some(password)) = (file_info.password_hash.clone(), password) else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    let valid = tokio::task::spawn_blocking(move || verify_password(&password, &hash))
+        .await
+        .unwrap_or(false);
+    if !valid {
+        log::warn!(
+            "Rejected download of '{}' with a wrong password",
+            file_info.name
+        );
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_info(password_hash: Option<String>) -> FileInfo {
+        FileInfo {
+            id: "1".to_string(),
+            mime_type: "application/pdf".to_string(),
+            added_at: 0,
+            protected: password_hash.is_some(),
+            password_hash,
+            ..FileInfo::new("payslip.pdf".to_string(), "1_file".into(), 10)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hash_and_unlock() {
+        assert_eq!(hash(Some(String::new())).await, Ok(None));
+        let open = file_info(hash(None).await.unwrap());
+        assert!(!open.protected);
+        assert!(unlock(&open, None).await.is_ok());
+
+        let protected = file_info(hash(Some("s3cret".to_string())).await.unwrap());
+        assert!(protected.protected);
+        assert!(!serde_json::to_string(&protected)
+            .unwrap()
+            .contains("argon2"));
+
+        assert_eq!(
+            unlock(&protected, None).await,
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            unlock(&protected, Some("guess".to_string())).await,
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert!(unlock(&protected, Some("s3cret".to_string())).await.is_ok());
+    }
+}
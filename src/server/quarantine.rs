@@ -0,0 +1,128 @@
+//! Quarantine for received programs and scripts. Whatever a sender calls
+//! it, a file that is executable by its extension or its first bytes is
+//! stored with a `.blocked` suffix, so a double-click on it doesn't run
+//! it, until the user releases it.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Suffix of quarantined files on disk
+pub const BLOCKED_SUFFIX: &str = ".blocked";
+
+/// Extensions of programs, installers and scripts, lowercase
+const EXTENSIONS: &[&str] = &[
+    "apk", "app", "appimage", "bat", "cmd", "com", "cpl", "deb", "dll", "exe", "gadget", "hta",
+    "jar", "js", "jse", "lnk", "msc", "msi", "msp", "pif", "ps1", "psm1", "reg", "rpm", "run",
+    "scr", "sh", "vb", "vbe", "vbs", "ws", "wsf", "wsh",
+];
+
+/// Starts of executable formats: Windows PE, ELF, Mach-O and universal
+/// binaries, and scripts with an interpreter line
+const MAGIC: &[&[u8]] = &[
+    b"MZ",
+    b"\x7fELF",
+    b"\xfe\xed\xfa\xce",
+    b"\xfe\xed\xfa\xcf",
+    b"\xce\xfa\xed\xfe",
+    b"\xcf\xfa\xed\xfe",
+    b"\xca\xfe\xba\xbe",
+    b"#!",
+];
+
+/// Whether a name has an executable's extension
+fn has_executable_extension(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Whether `head`, the start of a file, is an executable format
+fn has_executable_magic(head: &[u8]) -> bool {
+    MAGIC.iter().any(|magic| head.starts_with(magic))
+}
+
+/// Whether the received file `name`, stored at `path`, is a program or
+/// script
+pub fn is_executable(name: &str, path: &Path) -> bool {
+    if has_executable_extension(name) {
+        return true;
+    }
+    let mut head = [0; 4];
+    let read = std::fs::File::open(path)
+        .and_then(|mut file| file.read(&mut head))
+        .unwrap_or(0);
+    has_executable_magic(&head[..read])
+}
+
+/// Move a received file to its quarantined path and return that path
+pub fn quarantine(path: &Path) -> std::io::Result<PathBuf> {
+    let mut blocked = path.as_os_str().to_owned();
+    blocked.push(BLOCKED_SUFFIX);
+    let blocked = PathBuf::from(blocked);
+    std::fs::rename(path, &blocked)?;
+    Ok(blocked)
+}
+
+/// Move a quarantined file back to its own path and return that path
+pub fn release(path: &Path) -> std::io::Result<PathBuf> {
+    let released = path
+        .to_str()
+        .and_then(|path| path.strip_suffix(BLOCKED_SUFFIX))
+        .map(PathBuf::from)
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{:?} is not quarantined", path),
+            )
+        })?;
+    if released.exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{:?} already exists", released),
+        ));
+    }
+    std::fs::rename(path, &released)?;
+    Ok(released)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_executable() {
+        let dir = tempfile::tempdir().unwrap();
+        let program = dir.path().join("a_file");
+        std::fs::write(&program, b"MZ\x90\x00rest of the header").unwrap();
+        let script = dir.path().join("b_file");
+        std::fs::write(&script, b"#!/bin/sh\necho hi\n").unwrap();
+        let photo = dir.path().join("c_file");
+        std::fs::write(&photo, b"\xff\xd8\xff\xe0JFIF").unwrap();
+
+        // By content, whatever the name says
+        assert!(is_executable("holiday.jpg", &program));
+        assert!(is_executable("notes.txt", &script));
+        // By name, whatever the content
+        assert!(is_executable("Setup.EXE", &photo));
+        assert!(is_executable("install.ps1", &photo));
+        assert!(!is_executable("holiday.jpg", &photo));
+        assert!(!is_executable("empty.txt", &dir.path().join("missing")));
+    }
+
+    #[test]
+    fn test_quarantine_and_release() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("1234_file");
+        std::fs::write(&path, b"MZ").unwrap();
+
+        let blocked = quarantine(&path).unwrap();
+        assert_eq!(blocked, dir.path().join("1234_file.blocked"));
+        assert!(!path.exists());
+        assert!(blocked.exists());
+
+        assert_eq!(release(&blocked).unwrap(), path);
+        assert!(path.exists());
+        assert!(release(&path).is_err());
+    }
+}
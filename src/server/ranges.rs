@@ -0,0 +1,293 @@
+use std::ops::Range;
+use std::path::Path as FsPath;
+
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use settings::Settings;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+use super::conditional::{parse_http_date, Validators};
+use super::file_server::AppState;
+use super::power;
+use crate::config::ConfigData;
+
+/// Parts are never smaller than this, so small files use fewer connections
+const MIN_PART_SIZE: u64 = 1024 * 1024;
+
+/// Part boundaries are aligned to this for efficient disk reads
+const PART_ALIGN: u64 = 64 * 1024;
+
+/// The requested range is outside the file
+#[derive(Debug, PartialEq, Eq)]
+pub struct Unsatisfiable;
+
+/// Byte range of a file of `len` bytes from a `Range` header value. Only a
+/// single range is supported; `None` means the whole file is sent.
+pub fn parse_range(value: &str, len: u64) -> Result<Option<Range<u64>>, Unsatisfiable> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return Ok(None);
+    };
+    let range = match (start.trim(), end.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return Err(Unsatisfiable),
+            Ok(suffix) => len.saturating_sub(suffix)..len,
+            Err(_) => return Ok(None),
+        },
+        (start, "") => match start.parse::<u64>() {
+            Ok(start) => start..len,
+            Err(_) => return Ok(None),
+        },
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => start..(end + 1).min(len),
+            _ => return Ok(None),
+        },
+    };
+    if range.start >= len {
+        return Err(Unsatisfiable);
+    }
+    Ok(Some(range))
+}
+
+/// Range requested for a file version, honouring `If-Range`: a client
+/// resuming with validators of an older version gets the whole file
+pub fn requested(
+    headers: &HeaderMap,
+    validators: &Validators,
+    len: u64,
+) -> Result<Option<Range<u64>>, Unsatisfiable> {
+    let Some(range) = headers.get(header::RANGE).and_then(|v| v.to_str().ok()) else {
+        return Ok(None);
+    };
+    if let Some(if_range) = headers.get(header::IF_RANGE).and_then(|v| v.to_str().ok()) {
+        let current = if if_range.trim().starts_with('"') {
+            if_range.trim() == validators.etag
+        } else {
+            matches!(parse_http_date(if_range), Some(date) if Some(date) == validators.last_modified)
+        };
+        if !current {
+            return Ok(None);
+        }
+    }
+    parse_range(range, len)
+}
+
+/// `416 Range Not Satisfiable` for a file of `len` bytes
+pub fn not_satisfiable(len: u64) -> Response {
+    let mut response = StatusCode::RANGE_NOT_SATISFIABLE.into_response();
+    if let Ok(value) = HeaderValue::from_str(&format!("bytes */{}", len)) {
+        response.headers_mut().insert(header::CONTENT_RANGE, value);
+    }
+    response
+}
+
+/// `Content-Range` value of a partial response
+pub fn content_range(range: &Range<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, len)
+}
+
+/// How a client should split a download over several connections
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartPlan {
+    pub name: String,
+    pub size: u64,
+    pub part_size: u64,
+    /// Byte ranges, end exclusive
+    pub parts: Vec<(u64, u64)>,
+}
+
+impl PartPlan {
+    /// Split `size` bytes over at most `connections` parts of at least
+    /// [`MIN_PART_SIZE`], aligned to [`PART_ALIGN`]
+    pub fn new(name: String, size: u64, connections: u32) -> Self {
+        let part_size = size
+            .div_ceil(connections.max(1) as u64)
+            .max(MIN_PART_SIZE)
+            .next_multiple_of(PART_ALIGN);
+        let parts = (0..size)
+            .step_by(part_size as usize)
+            .map(|start| (start, (start + part_size).min(size)))
+            .collect();
+        Self {
+            name,
+            size,
+            part_size,
+            parts,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PartsQuery {
+    connections: Option<u32>,
+}
+
+#[axum::debug_handler]
+async fn get_parts(
+    Path(id): Path<String>,
+    Query(query): Query<PartsQuery>,
+    State(state): State<AppState>,
+) -> Result<Json<PartPlan>, StatusCode> {
+    let file_info = state
+        .file_list
+        .lock()
+        .unwrap()
+        .get_file_by_id(&id)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let size = tokio::fs::metadata(&file_info.path)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?
+        .len();
+
+    let max_connections = ConfigData::instance()
+        .map(|config| config.lock().unwrap().server.max_download_connections)
+        .unwrap_or(1);
+    let connections = query
+        .connections
+        .unwrap_or(max_connections)
+        .clamp(1, max_connections.max(1));
+    Ok(Json(PartPlan::new(file_info.name, size, connections)))
+}
+
+/// Routes of the download coordination API on the main share
+pub fn api_router() -> Router<AppState> {
+    Router::new().route("/files/:id/parts", get(get_parts))
+}
+
+/// Download the file at `url` (a `/api/v1/files/<id>` URL) over up to
+/// `connections` ranged requests in parallel, saving it in `dir`. Returns
+/// the path written and the number of parts used.
+pub async fn download(
+    url: &str,
+    connections: u32,
+    dir: &FsPath,
+) -> anyhow::Result<(std::path::PathBuf, usize)> {
+    let url = url.trim_end_matches('/');
+    let client = reqwest::Client::new();
+    let plan: PartPlan = client
+        .get(format!("{}/parts", url))
+        .query(&[("connections", connections)])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    if let Some(warning) = power::large_transfer_warning(plan.size) {
+        eprintln!("{}", warning);
+    }
+
+    let name = FsPath::new(&plan.name)
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Invalid file name: {}", plan.name))?;
+    let path = dir.join(name);
+    tokio::fs::File::create(&path)
+        .await?
+        .set_len(plan.size)
+        .await?;
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for (start, end) in plan.parts.iter().copied() {
+        let client = client.clone();
+        let url = url.to_string();
+        let path = path.clone();
+        tasks.spawn(async move {
+            let mut response = client
+                .get(&url)
+                .header(header::RANGE, format!("bytes={}-{}", start, end - 1))
+                .send()
+                .await?
+                .error_for_status()?;
+            if response.status() != StatusCode::PARTIAL_CONTENT {
+                anyhow::bail!("Server ignored the range request");
+            }
+            let mut file = tokio::fs::OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .await?;
+            file.seek(std::io::SeekFrom::Start(start)).await?;
+            let mut received = 0;
+            while let Some(chunk) = response.chunk().await? {
+                received += chunk.len() as u64;
+                file.write_all(&chunk).await?;
+            }
+            file.flush().await?;
+            if received != end - start {
+                anyhow::bail!("Part {}-{} is incomplete", start, end);
+            }
+            Ok(())
+        });
+    }
+    while let Some(result) = tasks.join_next().await {
+        if let Err(e) = result? {
+            tasks.abort_all();
+            let _ = tokio::fs::remove_file(&path).await;
+            return Err(e);
+        }
+    }
+    Ok((path, plan.parts.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Ok(Some(0..100)));
+        assert_eq!(parse_range("bytes=900-", 1000), Ok(Some(900..1000)));
+        assert_eq!(parse_range("bytes=-100", 1000), Ok(Some(900..1000)));
+        assert_eq!(parse_range("bytes=990-2000", 1000), Ok(Some(990..1000)));
+        assert_eq!(parse_range("bytes=1000-", 1000), Err(Unsatisfiable));
+        // Unsupported or malformed ranges fall back to the whole file
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=5-1", 1000), Ok(None));
+        assert_eq!(parse_range("lines=1-2", 1000), Ok(None));
+    }
+
+    #[test]
+    fn test_if_range() {
+        let validators = Validators {
+            etag: "\"v2\"".to_string(),
+            last_modified: Some(784111777),
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_static("bytes=10-"));
+        assert_eq!(requested(&headers, &validators, 100), Ok(Some(10..100)));
+
+        headers.insert(header::IF_RANGE, HeaderValue::from_static("\"v1\""));
+        assert_eq!(requested(&headers, &validators, 100), Ok(None));
+        headers.insert(
+            header::IF_RANGE,
+            HeaderValue::from_static("Sun, 06 Nov 1994 08:49:37 GMT"),
+        );
+        assert_eq!(requested(&headers, &validators, 100), Ok(Some(10..100)));
+    }
+
+    #[test]
+    fn test_part_plan() {
+        let plan = PartPlan::new("disk.img".to_string(), 100 * 1024 * 1024, 4);
+        assert_eq!(plan.part_size, 25 * 1024 * 1024);
+        assert_eq!(plan.parts.len(), 4);
+        assert_eq!(plan.parts[3], (75 * 1024 * 1024, 100 * 1024 * 1024));
+
+        // Small files are not split below the minimum part size
+        let plan = PartPlan::new("a.txt".to_string(), 1500 * 1024, 8);
+        assert_eq!(
+            plan.parts,
+            vec![(0, 1024 * 1024), (1024 * 1024, 1500 * 1024)]
+        );
+
+        assert!(PartPlan::new("empty".to_string(), 0, 4).parts.is_empty());
+    }
+}
@@ -0,0 +1,219 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use local_ip_address::list_afinet_netifas;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::time::timeout;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Interface name fragments that usually belong to VPN or tunnel adapters
+const VPN_INTERFACE_HINTS: &[&str] = &["tun", "tap", "wg", "utun", "ppp", "ipsec", "zt"];
+
+/// Result of probing the server from a single local source address
+#[derive(Debug, Clone)]
+pub struct ProbeResult {
+    pub interface: String,
+    pub source: IpAddr,
+    pub reachable: bool,
+    pub error: Option<String>,
+}
+
+/// Outcome of a full connection self-test
+#[derive(Debug, Clone)]
+pub struct ConnectionReport {
+    pub target: SocketAddr,
+    pub loopback_ok: bool,
+    pub target_ok: bool,
+    pub probes: Vec<ProbeResult>,
+    pub hints: Vec<String>,
+}
+
+impl ConnectionReport {
+    /// Render the report as human readable text for the UI
+    pub fn summary(&self) -> String {
+        let mut lines = Vec::new();
+        lines.push(format!(
+            "Loopback (127.0.0.1:{}): {}",
+            self.target.port(),
+            if self.loopback_ok { "OK" } else { "FAILED" }
+        ));
+        lines.push(format!(
+            "Advertised address ({}): {}",
+            self.target,
+            if self.target_ok { "OK" } else { "FAILED" }
+        ));
+        for probe in &self.probes {
+            lines.push(format!(
+                "From {} ({}): {}",
+                probe.interface,
+                probe.source,
+                match (&probe.reachable, &probe.error) {
+                    (true, _) => "OK".to_string(),
+                    (false, Some(e)) => format!("FAILED - {}", e),
+                    (false, None) => "FAILED".to_string(),
+                }
+            ));
+        }
+        if !self.hints.is_empty() {
+            lines.push(String::new());
+            lines.push("Hints:".to_string());
+            for hint in &self.hints {
+                lines.push(format!("• {}", hint));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+/// Check whether an interface name looks like a VPN or tunnel adapter
+pub fn is_vpn_interface(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.contains("vpn")
+        || VPN_INTERFACE_HINTS
+            .iter()
+            .any(|hint| name.starts_with(hint))
+}
+
+/// Issue a minimal HTTP request against the ping endpoint
+async fn http_ping(stream: &mut TcpStream, target: SocketAddr) -> anyhow::Result<()> {
+    let request = format!(
+        "GET /api/v1/ping HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        target
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut buffer = [0u8; 64];
+    let read = stream.read(&mut buffer).await?;
+    let status_line = String::from_utf8_lossy(&buffer[..read]);
+    if status_line.starts_with("HTTP/1.1 200") || status_line.starts_with("HTTP/1.0 200") {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "unexpected response: {}",
+            status_line.lines().next().unwrap_or("")
+        ))
+    }
+}
+
+/// Connect to the target, optionally binding to a specific local source address.
+/// An HTTPS server won't answer a plain request, so there the connection is enough.
+async fn probe(target: SocketAddr, source: Option<IpAddr>, https: bool) -> anyhow::Result<()> {
+    let socket = match target {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    if let Some(source) = source {
+        socket.bind(SocketAddr::new(source, 0))?;
+    }
+
+    let mut stream = timeout(PROBE_TIMEOUT, socket.connect(target))
+        .await
+        .map_err(|_| anyhow::anyhow!("connection timed out"))??;
+    if https {
+        return Ok(());
+    }
+
+    timeout(PROBE_TIMEOUT, http_ping(&mut stream, target))
+        .await
+        .map_err(|_| anyhow::anyhow!("no HTTP response"))?
+}
+
+/// Probe the running server from loopback, the advertised address and every
+/// other local IPv4 interface, then derive troubleshooting hints.
+pub async fn run_connection_test(
+    ip: &str,
+    port: u16,
+    https: bool,
+) -> anyhow::Result<ConnectionReport> {
+    let target_ip: IpAddr = ip.parse()?;
+    let target = SocketAddr::new(target_ip, port);
+
+    let loopback_ok = probe(SocketAddr::new("127.0.0.1".parse()?, port), None, https)
+        .await
+        .is_ok();
+    let target_result = probe(target, None, https).await;
+    let target_ok = target_result.is_ok();
+
+    let interfaces = list_afinet_netifas().unwrap_or_default();
+    let selected_interface = interfaces
+        .iter()
+        .find(|(_, addr)| *addr == target_ip)
+        .map(|(name, _)| name.clone());
+
+    let mut probes = Vec::new();
+    for (name, addr) in &interfaces {
+        if *addr == target_ip || addr.is_loopback() || !addr.is_ipv4() {
+            continue;
+        }
+        let result = probe(target, Some(*addr), https).await;
+        probes.push(ProbeResult {
+            interface: name.clone(),
+            source: *addr,
+            reachable: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+        });
+    }
+
+    let mut hints = Vec::new();
+    if !loopback_ok {
+        hints.push(
+            "The server does not answer locally. Make sure it is started and the port is not used by another program."
+                .to_string(),
+        );
+    }
+    if loopback_ok && !target_ok {
+        hints.push(format!(
+            "The server answers on 127.0.0.1 but not on {}. A firewall is most likely blocking port {}.",
+            target_ip, port
+        ));
+    }
+    match &selected_interface {
+        Some(name) if is_vpn_interface(name) => hints.push(format!(
+            "The advertised address belongs to '{}', which looks like a VPN adapter. Disconnect the VPN or pick your Wi-Fi/Ethernet address.",
+            name
+        )),
+        None if !target_ip.is_loopback() => hints.push(format!(
+            "{} is not assigned to any local interface. Your network may have changed; restart the server.",
+            target_ip
+        )),
+        _ => {}
+    }
+    if target_ip.is_loopback() {
+        hints.push(
+            "The server is only advertised on a loopback address, which other devices cannot reach. Connect to a network first."
+                .to_string(),
+        );
+    }
+    if target_ok {
+        hints.push(
+            "The server is reachable from this machine. If your phone still cannot connect, make sure it is on the same Wi-Fi network and that the router does not enable AP/client isolation (common on guest networks)."
+                .to_string(),
+        );
+    }
+
+    Ok(ConnectionReport {
+        target,
+        loopback_ok,
+        target_ok,
+        probes,
+        hints,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vpn_interface_detection() {
+        assert!(is_vpn_interface("tun0"));
+        assert!(is_vpn_interface("wg0"));
+        assert!(is_vpn_interface("utun3"));
+        assert!(is_vpn_interface("NordVPN"));
+        assert!(!is_vpn_interface("eth0"));
+        assert!(!is_vpn_interface("wlan0"));
+        assert!(!is_vpn_interface("en0"));
+    }
+}
@@ -0,0 +1,344 @@
+//! Keeping the records of a server in its metadata store (see
+//! `crate::metadata`): files as they are received (written as they are
+//! listed, so the two never disagree after a crash), flagged and deleted,
+//! the devices that connect and send files, and the events of the event
+//! log. Files of the main share are listed again when the app starts after
+//! a crash, and hosts can list the devices seen so far
+//! (`GET /api/v1/devices`, host role only).
+
+use std::net::IpAddr;
+
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::get;
+use axum::{Json, Router};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+
+use super::auth::{self, Role};
+use super::event_log::LoggedEvent;
+use super::file_server::AppState;
+use crate::integrations::ServerEvent;
+use crate::metadata::{self, Change, DeviceRecord, MetadataStore, StoredFile};
+use crate::models::{unix_now, FileInfo, FileList};
+
+/// The files of the main share in `store` that are still on disk. Files
+/// that are gone, and those of rooms, which don't outlive the app, are
+/// forgotten.
+pub fn restore_files(store: &dyn MetadataStore) -> FileList {
+    let mut file_list = FileList::new();
+    let stored = match store.files() {
+        Ok(stored) => stored,
+        Err(e) => {
+            log::error!("Failed to read stored files: {}", e);
+            return file_list;
+        }
+    };
+    let mut forgotten = Vec::new();
+    for StoredFile { room, file } in stored {
+        if room.is_none() && file.path.is_file() {
+            file_list.add_file(file);
+        } else {
+            forgotten.push(Change::RemoveFile(file.id));
+        }
+    }
+    if !file_list.is_empty() {
+        log::info!("Listing {} file(s) kept from the last run", file_list.len());
+    }
+    metadata::save(store, &forgotten);
+    file_list
+}
+
+/// Changes recording `event` at `now`, with `find` looking up the listed
+/// file of a room, or of the main share, by ID
+pub fn changes_for(
+    event: &ServerEvent,
+    now: u64,
+    find: impl Fn(Option<&str>, &str) -> Option<FileInfo>,
+) -> Vec<Change> {
+    let mut changes = Vec::new();
+    match event {
+        // Too frequent to be worth keeping, as in the event log
+        ServerEvent::TransferProgress { .. } => return changes,
+        ServerEvent::FileReceived { id, room, .. } => {
+            if let Some(file) = find(room.as_deref(), id) {
+                if let Some(address) = file.device {
+                    changes.push(Change::DeviceSent {
+                        address,
+                        at: now,
+                        bytes: file.size,
+                    });
+                }
+                changes.push(Change::PutFile(StoredFile {
+                    room: room.clone(),
+                    file,
+                }));
+            }
+        }
+        ServerEvent::FileCorrupted { id, room, .. }
+        | ServerEvent::FileQuarantined { id, room, .. } => {
+            if let Some(file) = find(room.as_deref(), id) {
+                changes.push(Change::PutFile(StoredFile {
+                    room: room.clone(),
+                    file,
+                }));
+            }
+        }
+        ServerEvent::FileDeleted { id, .. } => changes.push(Change::RemoveFile(id.clone())),
+        ServerEvent::ClientConnected { address } => {
+            if let Ok(address) = address.parse::<IpAddr>() {
+                changes.push(Change::DeviceSeen { address, at: now });
+            }
+        }
+        _ => {}
+    }
+    changes.push(Change::Event(LoggedEvent {
+        at: now,
+        event: event.clone(),
+    }));
+    changes
+}
+
+/// The event announcing `file` was received in `room`
+pub fn received(file: &FileInfo, room: Option<&str>) -> ServerEvent {
+    ServerEvent::FileReceived {
+        id: file.id.clone(),
+        name: file.name.clone(),
+        size: file.size,
+        room: room.map(str::to_string),
+    }
+}
+
+/// List a received file with `add`, which returns the file it listed, if
+/// any, and the one it replaced, and record it in `store` with its event
+/// at `now`. When the record can't be written `file_list` is put back as
+/// it was.
+pub fn list_received(
+    file_list: &mut FileList,
+    store: &dyn MetadataStore,
+    room: Option<&str>,
+    now: u64,
+    add: impl FnOnce(&mut FileList) -> (Option<FileInfo>, Option<FileInfo>),
+) -> anyhow::Result<(Option<FileInfo>, Option<FileInfo>)> {
+    let (added, replaced) = add(file_list);
+    let Some(file) = &added else {
+        return Ok((added, replaced));
+    };
+    let changes = changes_for(&received(file, room), now, |_, _| Some(file.clone()));
+    if let Err(e) = store.apply(&changes) {
+        file_list.remove(&file.id);
+        if let Some(replaced) = replaced {
+            file_list.add_file(replaced);
+        }
+        return Err(e);
+    }
+    Ok((added, replaced))
+}
+
+/// A copy of the listed file with `id`, in `room` or on the main share
+fn find_file(state: &AppState, room: Option<&str>, id: &str) -> Option<FileInfo> {
+    let file_list = match room {
+        Some(room) => state.rooms.lock().unwrap().get(room)?.file_list.clone(),
+        None => state.file_list.clone(),
+    };
+    let file_list = file_list.lock().unwrap();
+    file_list.get_file_by_id(id).cloned()
+}
+
+/// Record the events from `events` in the store of `state` until
+/// `shutdown` changes
+pub async fn run(
+    state: AppState,
+    mut events: broadcast::Receiver<ServerEvent>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                // Recorded in the same step that listed the file
+                Ok(ServerEvent::FileReceived { .. }) => {}
+                Ok(event) => {
+                    let changes = changes_for(&event, unix_now(), |room, id| {
+                        find_file(&state, room, id)
+                    });
+                    metadata::save(state.metadata.as_ref(), &changes);
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    log::warn!("Metadata records missed {} events", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = shutdown.changed() => break,
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn list_devices(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<DeviceRecord>>, StatusCode> {
+    let role = auth::signed_in_user(&state, &headers).map(|user| user.role);
+    if role != Some(Role::Host) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    state.metadata.devices().map(Json).map_err(|e| {
+        log::error!("Failed to read the devices seen: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+/// Device records, nested under the versioned API prefix
+pub fn api_router() -> Router<AppState> {
+    Router::new().route("/devices", get(list_devices))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::metadata::MemoryStore;
+
+    fn file(id: &str, path: PathBuf) -> FileInfo {
+        FileInfo {
+            id: id.to_string(),
+            mime_type: "image/jpeg".to_string(),
+            added_at: 1000,
+            device: Some("192.168.1.20".parse().unwrap()),
+            ..FileInfo::new(format!("{}.jpg", id), path, 2048)
+        }
+    }
+
+    #[test]
+    fn test_changes_for_events() {
+        let photo = file("photo", PathBuf::from("photo.jpg"));
+        let find =
+            |room: Option<&str>, id: &str| (room.is_none() && id == "photo").then(|| photo.clone());
+
+        let received = ServerEvent::FileReceived {
+            id: "photo".to_string(),
+            name: "photo.jpg".to_string(),
+            size: 2048,
+            room: None,
+        };
+        let changes = changes_for(&received, 1000, find);
+        assert!(matches!(
+            changes.as_slice(),
+            [
+                Change::DeviceSent { bytes: 2048, .. },
+                Change::PutFile(StoredFile { room: None, .. }),
+                Change::Event(LoggedEvent { at: 1000, .. }),
+            ]
+        ));
+
+        // A file no longer listed is only logged
+        let corrupted_in_room = ServerEvent::FileCorrupted {
+            id: "photo".to_string(),
+            name: "photo.jpg".to_string(),
+            room: Some("party".to_string()),
+        };
+        assert!(matches!(
+            changes_for(&corrupted_in_room, 1000, find).as_slice(),
+            [Change::Event(_)]
+        ));
+
+        let connected = ServerEvent::ClientConnected {
+            address: "192.168.1.30".to_string(),
+        };
+        assert!(matches!(
+            changes_for(&connected, 1000, find).as_slice(),
+            [Change::DeviceSeen { at: 1000, .. }, Change::Event(_)]
+        ));
+        let progress = ServerEvent::TransferProgress { id: 1, bytes: 10 };
+        assert!(changes_for(&progress, 1000, find).is_empty());
+    }
+
+    /// A store whose disk is full
+    struct FullStore;
+
+    impl MetadataStore for FullStore {
+        fn apply(&self, _changes: &[Change]) -> anyhow::Result<()> {
+            anyhow::bail!("database or disk is full")
+        }
+        fn files(&self) -> anyhow::Result<Vec<StoredFile>> {
+            Ok(Vec::new())
+        }
+        fn devices(&self) -> anyhow::Result<Vec<DeviceRecord>> {
+            Ok(Vec::new())
+        }
+        fn history(&self, _limit: usize) -> anyhow::Result<Vec<LoggedEvent>> {
+            Ok(Vec::new())
+        }
+        fn shares(&self) -> anyhow::Result<Vec<metadata::ShareRecord>> {
+            Ok(Vec::new())
+        }
+        fn snapshot(&self, _path: &std::path::Path) -> anyhow::Result<bool> {
+            Ok(false)
+        }
+        fn restore(&self, _path: &std::path::Path) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_list_received() {
+        let store = MemoryStore::default();
+        let mut file_list = FileList::new();
+        let photo = file("photo", PathBuf::from("photo.jpg"));
+        let (added, replaced) = list_received(&mut file_list, &store, None, 1000, |list| {
+            list.add_file(photo.clone());
+            (Some(photo.clone()), None)
+        })
+        .unwrap();
+        assert_eq!(added.unwrap().id, "photo");
+        assert!(replaced.is_none());
+        // Listed and recorded together, with the event
+        assert_eq!(store.files().unwrap()[0].file.id, "photo");
+        assert_eq!(store.history(10).unwrap().len(), 1);
+
+        // A record that can't be written leaves the list as it was, with
+        // the file it would have replaced
+        let newer = file("newer", PathBuf::from("newer.jpg"));
+        let result = list_received(&mut file_list, &FullStore, None, 1000, |list| {
+            let replaced = list.remove("photo");
+            list.add_file(newer.clone());
+            (Some(newer.clone()), replaced)
+        });
+        assert!(result.is_err());
+        assert_eq!(file_list.len(), 1);
+        assert!(file_list.get_file_by_id("photo").is_some());
+    }
+
+    #[test]
+    fn test_restore_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let kept = dir.path().join("kept.jpg");
+        std::fs::write(&kept, "jpeg").unwrap();
+        let store = MemoryStore::default();
+        metadata::save(
+            &store,
+            &[
+                Change::PutFile(StoredFile {
+                    room: None,
+                    file: file("kept", kept),
+                }),
+                Change::PutFile(StoredFile {
+                    room: None,
+                    file: file("gone", dir.path().join("gone.jpg")),
+                }),
+                Change::PutFile(StoredFile {
+                    room: Some("party".to_string()),
+                    file: file("room", dir.path().join("kept.jpg")),
+                }),
+            ],
+        );
+
+        let file_list = restore_files(&store);
+        assert_eq!(file_list.len(), 1);
+        assert!(file_list.get_file_by_id("kept").is_some());
+        let stored = store.files().unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].file.id, "kept");
+    }
+}
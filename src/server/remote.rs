@@ -0,0 +1,158 @@
+//! Remote control of the desktop app from the portal, so the host can
+//! manage the share from their phone when away from the desk (host role
+//! only, default server only): `POST /api/v1/control/pause`, `/stop` and
+//! `/clear`, and `PUT /api/v1/control/read-only` with `{"read_only": true}`.
+//! Commands are handed to the app, which owns the server, and answered
+//! with `202 Accepted` before they run. Pausing and stopping take the
+//! portal down with the server, so only the desktop app can start it
+//! again; read-only mode lasts until the server next starts.
+
+use std::sync::{Arc, Mutex};
+
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::{post, put};
+use axum::{Json, Router};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use super::auth::{self, Role};
+use super::file_server::{AppState, FileServer};
+
+/// What a host asked for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoteAction {
+    /// Stop listening, keeping the received files
+    Pause,
+    /// Stop the server, removing the received files as the desktop app does
+    Stop,
+    /// Remove the received files, leaving files shared from the desktop
+    ClearFiles,
+    ReadOnly(bool),
+}
+
+impl RemoteAction {
+    /// Carry out the action on `file_server`, saying what was done
+    async fn apply(self, file_server: &Arc<Mutex<FileServer>>) -> anyhow::Result<String> {
+        match self {
+            RemoteAction::Pause => {
+                file_server.lock().unwrap().pause();
+                Ok("Server paused".to_string())
+            }
+            RemoteAction::Stop => {
+                FileServer::stop(file_server).await?;
+                Ok("Server stopped".to_string())
+            }
+            RemoteAction::ClearFiles => {
+                // Removing the files blocks
+                let server = file_server.clone();
+                let (files, bytes) =
+                    tokio::task::spawn_blocking(move || server.lock().unwrap().clear_files())
+                        .await?;
+                Ok(format!(
+                    "Cleared {} file(s) ({})",
+                    files,
+                    humanize::Locale::default().format_size(bytes)
+                ))
+            }
+            RemoteAction::ReadOnly(read_only) => {
+                file_server.lock().unwrap().set_read_only(read_only);
+                Ok(if read_only {
+                    "Read-only mode turned on".to_string()
+                } else {
+                    "Read-only mode turned off".to_string()
+                })
+            }
+        }
+    }
+}
+
+/// A command from a host on the portal
+#[derive(Debug, Clone)]
+pub struct RemoteCommand {
+    pub action: RemoteAction,
+    /// Name of the host who sent it
+    pub by: String,
+}
+
+/// Callback invoked with a status message when a command ran
+pub type RemoteNotifier = Box<dyn Fn(String) + Send + Sync>;
+
+/// Carry out the commands from `commands` on `file_server` until the
+/// server is dropped
+pub async fn run(
+    file_server: Arc<Mutex<FileServer>>,
+    mut commands: mpsc::UnboundedReceiver<RemoteCommand>,
+    notify: RemoteNotifier,
+) {
+    while let Some(command) = commands.recv().await {
+        let action = command.action;
+        let message = match action.apply(&file_server).await {
+            Ok(done) => format!("{} from the portal by {}", done, command.by),
+            Err(e) => format!(
+                "{:?} from the portal by {} failed: {}",
+                action, command.by, e
+            ),
+        };
+        log::info!("{}", message);
+        notify(message);
+    }
+}
+
+/// Hand `action` to the app if a host asked for it
+fn send(state: &AppState, headers: &HeaderMap, action: RemoteAction) -> StatusCode {
+    let Some(user) = auth::signed_in_user(state, headers).filter(|user| user.role == Role::Host)
+    else {
+        return StatusCode::FORBIDDEN;
+    };
+    let Some(remote) = &state.remote else {
+        return StatusCode::NOT_FOUND;
+    };
+    let command = RemoteCommand {
+        action,
+        by: user.name,
+    };
+    match remote.send(command) {
+        Ok(()) => StatusCode::ACCEPTED,
+        // The app isn't taking commands, e.g. while it shuts down
+        Err(_) => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+#[axum::debug_handler]
+async fn pause(headers: HeaderMap, State(state): State<AppState>) -> StatusCode {
+    send(&state, &headers, RemoteAction::Pause)
+}
+
+#[axum::debug_handler]
+async fn stop(headers: HeaderMap, State(state): State<AppState>) -> StatusCode {
+    send(&state, &headers, RemoteAction::Stop)
+}
+
+#[axum::debug_handler]
+async fn clear_files(headers: HeaderMap, State(state): State<AppState>) -> StatusCode {
+    send(&state, &headers, RemoteAction::ClearFiles)
+}
+
+#[derive(Debug, Deserialize)]
+struct ReadOnlyRequest {
+    read_only: bool,
+}
+
+#[axum::debug_handler]
+async fn set_read_only(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Json(request): Json<ReadOnlyRequest>,
+) -> StatusCode {
+    send(&state, &headers, RemoteAction::ReadOnly(request.read_only))
+}
+
+/// Remote control routes, nested under the versioned API prefix
+pub fn api_router() -> Router<AppState> {
+    Router::new()
+        .route("/control/pause", post(pause))
+        .route("/control/stop", post(stop))
+        .route("/control/clear", post(clear_files))
+        .route("/control/read-only", put(set_read_only))
+}
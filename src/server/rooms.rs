@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use axum::extract::{DefaultBodyLimit, Form, FromRequestParts, Multipart, Path, Query, State};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware;
+use axum::response::Response;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use percent_encoding::percent_decode_str;
+
+use super::assets;
+use super::auth;
+use super::client_ip::{self, ClientIp};
+use super::conditional;
+use super::file_server::{receive_upload, send_file, AppState, FileSearch};
+use super::memory;
+use super::protected::Unlock;
+use super::upload_ids::InitResponse;
+use crate::models::{unix_now, FileInfo, Room};
+
+/// Header API clients send a room's PIN in
+pub const PIN_HEADER: &str = "x-room-pin";
+
+/// Cookie the portal keeps a room's PIN in, followed by the room name
+pub const PIN_COOKIE_PREFIX: &str = "justrans_room_";
+
+/// Wrong PINs a client may try before it is locked out
+const MAX_PIN_FAILURES: usize = 5;
+
+/// How long wrong PINs count against a client
+const PIN_LOCKOUT: Duration = Duration::from_secs(60);
+
+/// Credentials a client supplies when accessing a room. The PIN comes in a
+/// header or cookie rather than the query string, which ends up in logs and
+/// browser history.
+pub struct RoomAccess {
+    headers: HeaderMap,
+    client: Option<IpAddr>,
+}
+
+impl RoomAccess {
+    /// PIN supplied for `room`, from the header or else the room's cookie,
+    /// which the portal percent-encodes
+    fn pin(&self, room: &str) -> Option<String> {
+        if let Some(pin) = self.headers.get(PIN_HEADER) {
+            return pin.to_str().ok().map(str::to_string);
+        }
+        let cookie = auth::cookie(&self.headers, &format!("{}{}", PIN_COOKIE_PREFIX, room))?;
+        percent_decode_str(&cookie)
+            .decode_utf8()
+            .ok()
+            .map(|pin| pin.into_owned())
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for RoomAccess {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(RoomAccess {
+            headers: parts.headers.clone(),
+            client: client_ip::of_parts(&parts.extensions, &parts.headers, &state.trusted_proxies),
+        })
+    }
+}
+
+/// Recent wrong room PINs of each client, to stop it from guessing
+#[derive(Clone, Default)]
+pub struct PinFailures(Arc<Mutex<HashMap<Option<IpAddr>, Vec<Instant>>>>);
+
+impl PinFailures {
+    /// Whether `client` got too many PINs wrong lately to try again
+    fn locked_out(&self, client: Option<IpAddr>, now: Instant) -> bool {
+        let mut failures = self.0.lock().unwrap();
+        failures.retain(|_, times| {
+            times.retain(|time| now.duration_since(*time) < PIN_LOCKOUT);
+            !times.is_empty()
+        });
+        failures
+            .get(&client)
+            .is_some_and(|times| times.len() >= MAX_PIN_FAILURES)
+    }
+
+    fn record(&self, client: Option<IpAddr>, now: Instant) {
+        self.0.lock().unwrap().entry(client).or_default().push(now);
+    }
+}
+
+/// Directory that holds the uploads of a single room
+pub fn room_storage_dir(storage_dir: &std::path::Path, room: &str) -> std::path::PathBuf {
+    storage_dir.join("rooms").join(room)
+}
+
+/// Look up a room and verify the supplied PIN. Clients that got too many
+/// PINs wrong are turned away for a while.
+pub(crate) fn find_room(
+    state: &AppState,
+    name: &str,
+    access: &RoomAccess,
+) -> Result<Room, StatusCode> {
+    let room = state
+        .rooms
+        .lock()
+        .unwrap()
+        .get(name)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if room.pin.is_none() {
+        return Ok(room);
+    }
+
+    let now = Instant::now();
+    if state.room_pin_failures.locked_out(access.client, now) {
+        log::warn!(
+            "Rejected access to room '{}' after too many wrong PINs",
+            name
+        );
+        return Err(StatusCode::TOO_MANY_REQUESTS);
+    }
+    if !room.check_pin(access.pin(name).as_deref()) {
+        state.room_pin_failures.record(access.client, now);
+        log::warn!("Rejected access to room '{}' with wrong PIN", name);
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(room)
+}
+
+#[axum::debug_handler]
+async fn serve_room_index(
+    Path(room): Path<String>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Response, StatusCode> {
+    if !state.rooms.lock().unwrap().contains_key(&room) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(assets::serve_index(headers).await)
+}
+
+#[axum::debug_handler]
+async fn get_room_files(
+    Path(room): Path<String>,
+    access: RoomAccess,
+    Query(search): Query<FileSearch>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Response, StatusCode> {
+    let room = find_room(&state, &room, &access)?;
+    let file_list = search.apply(&room.file_list.lock().unwrap());
+    Ok(conditional::json(&headers, &file_list))
+}
+
+#[axum::debug_handler]
+async fn download_room_file(
+    Path((room, id)): Path<(String, String)>,
+    access: RoomAccess,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Response, StatusCode> {
+    let room = find_room(&state, &room, &access)?;
+    send_file(&state, &room.file_list, &id, &headers, None).await
+}
+
+#[axum::debug_handler]
+async fn download_protected_room_file(
+    Path((room, id)): Path<(String, String)>,
+    access: RoomAccess,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Form(unlock): Form<Unlock>,
+) -> Result<Response, StatusCode> {
+    let room = find_room(&state, &room, &access)?;
+    send_file(
+        &state,
+        &room.file_list,
+        &id,
+        &headers,
+        Some(unlock.password),
+    )
+    .await
+}
+
+#[axum::debug_handler]
+async fn init_room_upload(
+    Path(room): Path<String>,
+    access: RoomAccess,
+    State(state): State<AppState>,
+) -> Result<Json<InitResponse>, StatusCode> {
+    find_room(&state, &room, &access)?;
+    Ok(Json(InitResponse {
+        file_id: state.upload_ids.issue(unix_now()),
+    }))
+}
+
+#[axum::debug_handler]
+async fn upload_room_file(
+    Path(room): Path<String>,
+    access: RoomAccess,
+    ClientIp(client_ip): ClientIp,
+    State(state): State<AppState>,
+    multipart: Multipart,
+) -> Result<Json<FileInfo>, StatusCode> {
+    if state.read_only() {
+        log::warn!("Rejected upload to read-only server");
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let room = find_room(&state, &room, &access)?;
+    let storage_dir = room_storage_dir(&state.temp_dir, &room.name);
+    receive_upload(
+        &state,
+        &room.file_list,
+        &storage_dir,
+        Some(&room.name),
+        client_ip,
+        multipart,
+    )
+    .await
+}
+
+/// Route serving the per-room portal page
+pub fn router() -> Router<AppState> {
+    Router::new().route("/r/:room", get(serve_room_index))
+}
+
+/// Per-room API routes, nested under the versioned API prefix
+pub fn api_router(upload_body_limit: usize) -> Router<AppState> {
+    Router::new()
+        .route("/rooms/:room/files", get(get_room_files))
+        .route("/rooms/:room/files/:id", get(download_room_file))
+        .route(
+            "/rooms/:room/files/:id/unlock",
+            post(download_protected_room_file),
+        )
+        .route("/rooms/:room/upload/init", post(init_room_upload))
+        .route(
+            "/rooms/:room/upload",
+            post(upload_room_file)
+                .layer(DefaultBodyLimit::max(upload_body_limit))
+                .layer(middleware::from_fn_with_state(
+                    upload_body_limit,
+                    memory::hold_body,
+                )),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{header, HeaderValue};
+
+    #[test]
+    fn test_pin_sources() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_static("justrans_lang=de; justrans_room_team=12%2034"),
+        );
+        let access = RoomAccess {
+            headers,
+            client: None,
+        };
+        assert_eq!(access.pin("team").as_deref(), Some("12 34"));
+        assert_eq!(access.pin("other"), None);
+
+        let mut headers = access.headers.clone();
+        headers.insert(PIN_HEADER, HeaderValue::from_static("5678"));
+        let access = RoomAccess { headers, ..access };
+        assert_eq!(access.pin("team").as_deref(), Some("5678"));
+    }
+
+    #[test]
+    fn test_pin_lockout() {
+        let failures = PinFailures::default();
+        let guesser = Some("192.168.1.9".parse().unwrap());
+        let start = Instant::now();
+        for _ in 0..MAX_PIN_FAILURES {
+            assert!(!failures.locked_out(guesser, start));
+            failures.record(guesser, start);
+        }
+        assert!(failures.locked_out(guesser, start));
+        // Other clients can still try, and the guesser once the wait is over
+        assert!(!failures.locked_out(Some("192.168.1.10".parse().unwrap()), start));
+        assert!(!failures.locked_out(guesser, start + PIN_LOCKOUT));
+    }
+}
@@ -0,0 +1,157 @@
+//! File names and text from other devices are shown in the portal and the
+//! app, and sent back in response headers. They are treated as hostile:
+//! names are cleaned when they arrive and everything is escaped on the way
+//! out.
+
+use std::path::Path;
+
+use crate::models::names;
+
+/// Name used when nothing displayable is left of an uploaded name
+const FALLBACK_NAME: &str = "unknown";
+
+/// Escape text for use in HTML element content and attributes
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Characters that change how the rest of a name is displayed, such as the
+/// right-to-left override that makes `evil\u{202E}gpj.exe` look like
+/// `evilexe.jpg`
+fn is_format_char(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2069}' | '\u{FEFF}'
+    )
+}
+
+/// A file name from another device, reduced to its last path component,
+/// stripped of control and formatting characters and composed (NFC)
+pub fn sanitize_name(name: &str) -> String {
+    // Windows clients send backslash separated paths
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = name
+        .chars()
+        .filter(|c| !c.is_control() && !is_format_char(*c))
+        .collect();
+    let cleaned = cleaned.trim();
+    if cleaned.is_empty() || cleaned == "." || cleaned == ".." {
+        return FALLBACK_NAME.to_string();
+    }
+    Path::new(cleaned)
+        .file_name()
+        .map(|name| names::normalize(&name.to_string_lossy()))
+        .unwrap_or_else(|| FALLBACK_NAME.to_string())
+}
+
+/// `Content-Disposition` value for downloading a file under `name`, with a
+/// plain ASCII fallback and the full name encoded as RFC 5987 `filename*`
+pub fn content_disposition(name: &str) -> String {
+    let name = sanitize_name(name);
+    let fallback: String = name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii() && !c.is_ascii_control() => c,
+            _ => '_',
+        })
+        .collect();
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback, encoded
+    )
+}
+
+/// Links such as `javascript:` could run script in the portal. Browsers
+/// ignore tabs and newlines inside a scheme, so those are removed before
+/// checking it.
+pub fn is_safe_url(url: &str) -> bool {
+    let scheme: String = url
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .take_while(|c| *c != ':')
+        .collect::<String>()
+        .to_ascii_lowercase();
+    let has_scheme = url.contains(':');
+    !(has_scheme && matches!(scheme.as_str(), "javascript" | "vbscript" | "data"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html("<img src=x onerror=\"alert('1')\">"),
+            "&lt;img src=x onerror=&quot;alert(&#39;1&#39;)&quot;&gt;"
+        );
+    }
+
+    #[test]
+    fn test_sanitize_name() {
+        assert_eq!(sanitize_name("report.pdf"), "report.pdf");
+        assert_eq!(sanitize_name("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_name("C:\\Users\\me\\photo.jpg"), "photo.jpg");
+        assert_eq!(sanitize_name("evil\u{202E}gpj.exe"), "evilgpj.exe");
+        assert_eq!(sanitize_name("line\nbreak\u{0}.txt"), "linebreak.txt");
+        assert_eq!(sanitize_name(".."), "unknown");
+        assert_eq!(sanitize_name("  \u{200B} "), "unknown");
+        assert_eq!(sanitize_name("Cafe\u{301}.txt"), "Café.txt");
+        // Markup stays in the name, it is escaped wherever it is shown
+        assert_eq!(
+            sanitize_name("<img src=x onerror=alert(1)>.png"),
+            "<img src=x onerror=alert(1)>.png"
+        );
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(
+            content_disposition("report.pdf"),
+            "attachment; filename=\"report.pdf\"; filename*=UTF-8''report.pdf"
+        );
+        // A quote cannot end the value and smuggle in another parameter
+        assert_eq!(
+            content_disposition("a\"; filename=evil.exe"),
+            "attachment; filename=\"a_; filename=evil.exe\"; \
+             filename*=UTF-8''a%22%3B%20filename%3Devil.exe"
+        );
+        assert_eq!(
+            content_disposition("Grüße.txt"),
+            "attachment; filename=\"Gr__e.txt\"; filename*=UTF-8''Gr%C3%BC%C3%9Fe.txt"
+        );
+        assert!(
+            axum::http::HeaderValue::from_str(&content_disposition("a\r\nSet-Cookie: x")).is_ok()
+        );
+    }
+
+    #[test]
+    fn test_is_safe_url() {
+        assert!(is_safe_url("https://example.com/a:b"));
+        assert!(is_safe_url("notes/data:1"));
+        assert!(!is_safe_url("javascript:alert(1)"));
+        assert!(!is_safe_url(" JavaScript:alert(1)"));
+        assert!(!is_safe_url("java\tscript:alert(1)"));
+        assert!(!is_safe_url("data:text/html,<script>alert(1)</script>"));
+    }
+}
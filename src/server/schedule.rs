@@ -0,0 +1,230 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{Datelike, Local, NaiveTime, Weekday};
+use settings::Settings;
+
+use super::FileServer;
+use crate::config::{ConfigData, ScheduleConfig};
+
+/// How often the scheduler looks at the clock
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// Callback invoked with a status message when the scheduler starts or
+/// stops the server
+pub type ScheduleNotifier = Box<dyn Fn(String) + Send + Sync>;
+
+/// Availability window parsed from the config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    /// Days the window opens, Monday first
+    days: [bool; 7],
+    start: NaiveTime,
+    stop: NaiveTime,
+}
+
+fn parse_day(name: &str) -> anyhow::Result<usize> {
+    let name = name.trim().to_lowercase();
+    DAY_NAMES
+        .iter()
+        .position(|day| name.starts_with(day))
+        .ok_or_else(|| anyhow::anyhow!("Unknown day '{}', use mon, tue, ..., sun", name))
+}
+
+/// Days from a list of names and ranges such as "mon-fri,sun"
+fn parse_days(days: &str) -> anyhow::Result<[bool; 7]> {
+    if days.trim().is_empty() {
+        return Ok([true; 7]);
+    }
+    let mut parsed = [false; 7];
+    for part in days.split(',') {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse_day(first)?, parse_day(last)?);
+                // Ranges may wrap around the week, e.g. "fri-mon"
+                let mut day = first;
+                loop {
+                    parsed[day] = true;
+                    if day == last {
+                        break;
+                    }
+                    day = (day + 1) % 7;
+                }
+            }
+            None => parsed[parse_day(part)?] = true,
+        }
+    }
+    Ok(parsed)
+}
+
+fn parse_time(time: &str) -> anyhow::Result<NaiveTime> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M")
+        .map_err(|_| anyhow::anyhow!("Invalid time '{}', use HH:MM", time))
+}
+
+impl Schedule {
+    pub fn parse(config: &ScheduleConfig) -> anyhow::Result<Self> {
+        let schedule = Self {
+            days: parse_days(&config.days)?,
+            start: parse_time(&config.start)?,
+            stop: parse_time(&config.stop)?,
+        };
+        if schedule.start == schedule.stop {
+            return Err(anyhow::anyhow!(
+                "Start and stop time of the schedule are the same"
+            ));
+        }
+        Ok(schedule)
+    }
+
+    /// Whether the server should be running at `time` on `weekday`. A window
+    /// that runs past midnight belongs to the day it starts on.
+    pub fn is_open(&self, weekday: Weekday, time: NaiveTime) -> bool {
+        let today = weekday.num_days_from_monday() as usize;
+        let yesterday = (today + 6) % 7;
+        if self.start < self.stop {
+            self.days[today] && self.start <= time && time < self.stop
+        } else {
+            (self.days[today] && self.start <= time) || (self.days[yesterday] && time < self.stop)
+        }
+    }
+
+    /// Short description for the main window, e.g. "mon, tue, fri 09:00-18:00"
+    pub fn describe(&self) -> String {
+        let days = if self.days.iter().all(|&open| open) {
+            "daily".to_string()
+        } else {
+            DAY_NAMES
+                .iter()
+                .zip(self.days)
+                .filter(|(_, open)| *open)
+                .map(|(day, _)| *day)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        format!(
+            "{} {}-{}",
+            days,
+            self.start.format("%H:%M"),
+            self.stop.format("%H:%M")
+        )
+    }
+}
+
+/// The schedule from the config, or `None` if scheduling is turned off
+pub fn configured(config: &ConfigData) -> anyhow::Result<Option<Schedule>> {
+    if !config.schedule.enabled {
+        return Ok(None);
+    }
+    Schedule::parse(&config.schedule).map(Some)
+}
+
+/// Start or stop the server, returning whether anything changed
+async fn apply(file_server: Arc<Mutex<FileServer>>, open: bool) -> anyhow::Result<bool> {
+    FileServer::set_running(&file_server, open).await
+}
+
+/// Start and stop the server when its window opens and closes. The server is
+/// only touched when the window changes, so it can still be started or
+/// stopped by hand in between.
+pub async fn run(file_server: Arc<Mutex<FileServer>>, notify: ScheduleNotifier) {
+    let mut last_open: Option<bool> = None;
+    let mut last_error = String::new();
+    loop {
+        let schedule =
+            ConfigData::instance().and_then(|instance| configured(&instance.lock().unwrap()));
+        match schedule {
+            Ok(Some(schedule)) => {
+                let now = Local::now();
+                let open = schedule.is_open(now.weekday(), now.time());
+                if last_open != Some(open) {
+                    last_open = Some(open);
+                    let message = match apply(file_server.clone(), open).await {
+                        Ok(false) => None,
+                        Ok(true) if open => Some(format!(
+                            "Server started by schedule ({})",
+                            schedule.describe()
+                        )),
+                        Ok(true) => Some("Server stopped by schedule".to_string()),
+                        Err(e) => Some(format!("Scheduled server change failed: {}", e)),
+                    };
+                    if let Some(message) = message {
+                        log::info!("{}", message);
+                        notify(message);
+                    }
+                }
+            }
+            Ok(None) => last_open = None,
+            Err(e) => {
+                // Only complain once about the same mistake
+                if e.to_string() != last_error {
+                    log::warn!("Ignoring the server schedule: {}", e);
+                    last_error = e.to_string();
+                }
+                last_open = None;
+            }
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(days: &str, start: &str, stop: &str) -> anyhow::Result<Schedule> {
+        Schedule::parse(&ScheduleConfig {
+            enabled: true,
+            days: days.to_string(),
+            start: start.to_string(),
+            stop: stop.to_string(),
+        })
+    }
+
+    fn at(time: &str) -> NaiveTime {
+        parse_time(time).unwrap()
+    }
+
+    #[test]
+    fn test_office_hours() {
+        let office = schedule("mon-fri", "09:00", "18:00").unwrap();
+        assert!(office.is_open(Weekday::Mon, at("09:00")));
+        assert!(office.is_open(Weekday::Fri, at("17:59")));
+        assert!(!office.is_open(Weekday::Fri, at("18:00")));
+        assert!(!office.is_open(Weekday::Tue, at("08:30")));
+        assert!(!office.is_open(Weekday::Sat, at("12:00")));
+        assert_eq!(office.describe(), "mon, tue, wed, thu, fri 09:00-18:00");
+    }
+
+    #[test]
+    fn test_overnight_window() {
+        let night = schedule("fri,sat", "22:00", "06:00").unwrap();
+        assert!(night.is_open(Weekday::Fri, at("23:00")));
+        assert!(night.is_open(Weekday::Sat, at("05:00")));
+        assert!(night.is_open(Weekday::Sun, at("05:00")));
+        assert!(!night.is_open(Weekday::Fri, at("05:00")));
+        assert!(!night.is_open(Weekday::Sun, at("23:00")));
+    }
+
+    #[test]
+    fn test_parse_days() {
+        assert_eq!(parse_days("").unwrap(), [true; 7]);
+        assert_eq!(
+            parse_days("fri-mon").unwrap(),
+            [true, false, false, false, true, true, true]
+        );
+        assert_eq!(
+            parse_days("Tuesday, thu").unwrap(),
+            [false, true, false, true, false, false, false]
+        );
+        assert!(parse_days("mon-funday").is_err());
+        assert!(schedule("", "9am", "18:00").is_err());
+        assert!(schedule("", "09:00", "09:00").is_err());
+        assert_eq!(
+            schedule("", "00:00", "23:59").unwrap().describe(),
+            "daily 00:00-23:59"
+        );
+    }
+}
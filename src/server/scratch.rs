@@ -0,0 +1,139 @@
+//! Scratch space for uploads in progress. Segments, session parts and
+//! files being assembled are written to `storage.temp_dir` when it is set,
+//! e.g. on a faster disk, or else to `.incoming` in the storage directory,
+//! and only moved into the storage directory once complete and flushed to
+//! disk. From the same disk that move is a single rename, so nothing half
+//! written is ever there to be listed, served or picked up by `migrate`
+//! after a crash.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Folder of the storage directory uploads are written to when no
+/// `storage.temp_dir` is set
+pub const INCOMING_DIR: &str = ".incoming";
+
+/// Where uploads are written until complete: `temp_dir`, or the incoming
+/// folder of `storage_dir` when it is empty
+pub fn dir(temp_dir: &str, storage_dir: &Path) -> PathBuf {
+    match temp_dir.trim() {
+        "" => storage_dir.join(INCOMING_DIR),
+        temp_dir => PathBuf::from(temp_dir),
+    }
+}
+
+/// Remove the incoming folder of `storage_dir` with the uploads left in
+/// it, which can't be resumed once their server stopped. A separate
+/// `storage.temp_dir` may be shared by other servers and is left alone.
+pub fn discard_incoming(storage_dir: &Path) {
+    let incoming = storage_dir.join(INCOMING_DIR);
+    match std::fs::remove_dir_all(&incoming) {
+        Ok(()) => log::debug!("Removed unfinished uploads in {:?}", incoming),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => log::warn!(
+            "Failed to remove unfinished uploads in {:?}: {}",
+            incoming,
+            e
+        ),
+    }
+}
+
+/// Flush `path` to disk, so a crash can't leave it listed but empty
+fn sync(path: &Path) -> io::Result<()> {
+    // Windows only flushes files opened for writing
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .sync_all()
+}
+
+/// Flush the entries of `dir`, so a rename into it survives a crash; not
+/// possible on every platform, e.g. Windows, where renames are durable
+/// already
+fn sync_dir(dir: &Path) {
+    let _ = std::fs::File::open(dir).and_then(|dir| dir.sync_all());
+}
+
+/// Move the finished upload at `path` into `storage_dir` under the same
+/// name once it is on disk, returning where it ended up. A file already
+/// there stays put. Across disks it is copied under a hidden name first, so
+/// the storage directory only ever holds the whole file.
+pub fn move_into(path: &Path, storage_dir: &Path) -> io::Result<PathBuf> {
+    sync(path)?;
+    if path.parent() == Some(storage_dir) {
+        return Ok(path.to_path_buf());
+    }
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+    let target = storage_dir.join(name);
+    std::fs::create_dir_all(storage_dir)?;
+    if std::fs::rename(path, &target).is_ok() {
+        sync_dir(storage_dir);
+        return Ok(target);
+    }
+
+    let copying = storage_dir.join(format!(".{}.partial", name.to_string_lossy()));
+    let copied = std::fs::copy(path, &copying)
+        .and_then(|_| sync(&copying))
+        .and_then(|_| std::fs::rename(&copying, &target));
+    if let Err(e) = copied {
+        let _ = std::fs::remove_file(&copying);
+        return Err(e);
+    }
+    sync_dir(storage_dir);
+    let _ = std::fs::remove_file(path);
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_dir() {
+        let storage = Path::new("uploads");
+        assert_eq!(dir("", storage), storage.join(".incoming"));
+        assert_eq!(dir("  ", storage), storage.join(".incoming"));
+        assert_eq!(dir("/fast/tmp", storage), Path::new("/fast/tmp"));
+    }
+
+    #[test]
+    fn test_move_into() {
+        let scratch = tempdir().unwrap();
+        let storage = tempdir().unwrap();
+        let storage_dir = storage.path().join("rooms").join("team");
+
+        let path = scratch.path().join("abc_file");
+        std::fs::write(&path, b"finished").unwrap();
+        let moved = move_into(&path, &storage_dir).unwrap();
+        assert_eq!(moved, storage_dir.join("abc_file"));
+        assert_eq!(std::fs::read(&moved).unwrap(), b"finished");
+        assert!(!path.exists());
+        // Nothing is left under the name used while copying
+        assert_eq!(std::fs::read_dir(&storage_dir).unwrap().count(), 1);
+
+        // Files written in the storage directory stay where they are
+        assert_eq!(move_into(&moved, &storage_dir).unwrap(), moved);
+        assert!(moved.exists());
+
+        assert!(move_into(&scratch.path().join("missing_file"), &storage_dir).is_err());
+    }
+
+    #[test]
+    fn test_discard_incoming() {
+        let storage = tempdir().unwrap();
+        let incoming = dir("", storage.path());
+        std::fs::create_dir_all(incoming.join("abc")).unwrap();
+        std::fs::write(incoming.join("abc").join("segment_0"), b"part").unwrap();
+        std::fs::write(storage.path().join("kept_file"), b"kept").unwrap();
+
+        discard_incoming(storage.path());
+        assert!(!incoming.exists());
+        assert!(storage.path().join("kept_file").exists());
+        // Nothing to remove the next time
+        discard_incoming(storage.path());
+    }
+}
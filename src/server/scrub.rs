@@ -0,0 +1,167 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+use tokio::sync::watch;
+
+use super::file_server::AppState;
+use super::power;
+use crate::integrations::{self, ServerEvent};
+use crate::models::FileList;
+
+/// Hex SHA-256 of a file's contents
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 256 * 1024];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => break,
+            n => hasher.update(&buffer[..n]),
+        }
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Checksum of a newly received file, recorded so later scrubs can tell
+/// whether the stored copy changed
+pub async fn checksum(path: PathBuf) -> Option<String> {
+    let result = tokio::task::spawn_blocking(move || {
+        hash_file(&path).map_err(|e| log::warn!("Failed to checksum {:?}: {}", path, e))
+    })
+    .await;
+    result.ok().and_then(Result::ok)
+}
+
+/// Outcome of one pass over the stored files
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ScrubReport {
+    pub checked: usize,
+    pub corrupted: usize,
+}
+
+/// Re-hash the received files of one list and flag those that no longer
+/// match their recorded checksum. Files shared from the desktop may be
+/// edited in place and are not checked.
+fn scrub_list(file_list: &Arc<Mutex<FileList>>, room: Option<&str>, report: &mut ScrubReport) {
+    let files: Vec<_> = file_list
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|file| !file.shared && !file.corrupted && file.sha256.is_some())
+        .cloned()
+        .collect();
+
+    for file in files {
+        report.checked += 1;
+        // Hash outside the lock, large files take a while
+        let intact = match hash_file(&file.path) {
+            Ok(hash) => Some(&hash) == file.sha256.as_ref(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => false,
+            Err(e) => {
+                log::warn!("Failed to scrub {:?}: {}", file.path, e);
+                continue;
+            }
+        };
+        if intact {
+            continue;
+        }
+
+        log::warn!(
+            "Stored copy of '{}' no longer matches its checksum",
+            file.name
+        );
+        report.corrupted += 1;
+        file_list
+            .lock()
+            .unwrap()
+            .update(&file.id, |entry| entry.corrupted = true);
+        integrations::emit(ServerEvent::FileCorrupted {
+            id: file.id,
+            name: file.name,
+            room: room.map(str::to_string),
+        });
+    }
+}
+
+/// Check every received file of the main share and its rooms
+pub fn scrub(state: &AppState) -> ScrubReport {
+    let mut report = ScrubReport::default();
+    scrub_list(&state.file_list, None, &mut report);
+    let rooms: Vec<_> = state.rooms.lock().unwrap().values().cloned().collect();
+    for room in rooms {
+        scrub_list(&room.file_list, Some(&room.name), &mut report);
+    }
+    report
+}
+
+/// Scrub the stored files every `interval` until the server shuts down
+pub async fn run(state: AppState, interval: Duration, mut shutdown: watch::Receiver<bool>) {
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown.changed() => return,
+        }
+        if !power::wait_for_background_work("the integrity scrub", &mut shutdown).await {
+            return;
+        }
+        let state = state.clone();
+        match tokio::task::spawn_blocking(move || scrub(&state)).await {
+            Ok(report) => log::info!(
+                "Integrity scrub checked {} files, {} corrupted",
+                report.checked,
+                report.corrupted
+            ),
+            Err(e) => log::error!("Integrity scrub failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FileInfo;
+
+    fn received(dir: &Path, name: &str, contents: &[u8]) -> FileInfo {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        FileInfo {
+            id: name.to_string(),
+            added_at: 0,
+            sha256: Some(hash_file(&path).unwrap()),
+            ..FileInfo::new(name.to_string(), path, contents.len() as u64)
+        }
+    }
+
+    #[test]
+    fn test_scrub_flags_changed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut list = FileList::new();
+        list.add_file(received(dir.path(), "intact.bin", b"hello"));
+        list.add_file(received(dir.path(), "rotten.bin", b"hello"));
+        list.add_file(received(dir.path(), "gone.bin", b"hello"));
+        std::fs::write(dir.path().join("rotten.bin"), b"hellO").unwrap();
+        std::fs::remove_file(dir.path().join("gone.bin")).unwrap();
+        let list = Arc::new(Mutex::new(list));
+
+        let mut report = ScrubReport::default();
+        scrub_list(&list, None, &mut report);
+        assert_eq!(
+            report,
+            ScrubReport {
+                checked: 3,
+                corrupted: 2
+            }
+        );
+
+        let list = list.lock().unwrap();
+        let flagged: Vec<&str> = list
+            .iter()
+            .filter(|file| file.corrupted)
+            .map(|file| file.name.as_str())
+            .collect();
+        assert_eq!(flagged, vec!["rotten.bin", "gone.bin"]);
+    }
+}
@@ -0,0 +1,231 @@
+//! Sanity checks for segment uploads (`/api/v1/upload`). Each segment says
+//! where it belongs and, from current clients, how large the whole file is,
+//! so an upload that doesn't add up is refused at the segment that breaks
+//! it rather than assembled into garbage at the end. A segment may also
+//! carry its CRC32, and one damaged on the way is refused with
+//! `422 Unprocessable Entity` so the client sends just that one again.
+
+use std::path::Path;
+
+use axum::http::StatusCode;
+
+use crate::config::ServerConfig;
+
+/// Form field of the segment upload, and query parameter of the v2 part
+/// upload, with the CRC32 of the data as 8 hex digits
+pub const CRC32_FIELD: &str = "segment_crc32";
+
+/// Limits segment uploads are checked against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentLimits {
+    /// Most segments one file may be split into
+    pub max_segments: usize,
+    /// Chunk size clients are told to use, in bytes
+    pub chunk_size: u64,
+}
+
+impl SegmentLimits {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        Self {
+            max_segments: config.max_upload_segments as usize,
+            chunk_size: config.upload_chunk_size_mb * 1024 * 1024,
+        }
+    }
+
+    /// Largest file the segment upload takes: the most segments, each a
+    /// whole chunk
+    pub fn max_file_size(&self) -> u64 {
+        self.max_segments as u64 * self.chunk_size
+    }
+
+    /// Check one segment. `received` is the size of the file's other
+    /// segments already stored.
+    pub fn check(&self, segment: &Segment, received: u64) -> Result<(), StatusCode> {
+        if segment.total == 0 || segment.index >= segment.total {
+            log::warn!(
+                "Rejected segment {} of {}: out of range",
+                segment.index,
+                segment.total
+            );
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        if segment.total > self.max_segments {
+            log::warn!(
+                "Rejected upload in {} segments, more than the {} allowed",
+                segment.total,
+                self.max_segments
+            );
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        if segment.size > self.chunk_size {
+            log::warn!(
+                "Rejected segment of {} bytes, larger than the {} byte chunk size",
+                segment.size,
+                self.chunk_size
+            );
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        let Some(file_size) = segment.file_size else {
+            return Ok(());
+        };
+        // Every segment holds at least one byte and at most a chunk
+        let total = segment.total as u64;
+        if total > file_size || total.saturating_mul(self.chunk_size) < file_size {
+            log::warn!(
+                "Rejected upload of {} bytes in {} segments",
+                file_size,
+                segment.total
+            );
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        if received + segment.size > file_size {
+            log::warn!(
+                "Rejected segment {}: {} bytes received of a {} byte file",
+                segment.index,
+                received + segment.size,
+                file_size
+            );
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        Ok(())
+    }
+}
+
+/// Check `data` against the CRC32 a client sent with it
+pub fn check_crc32(data: &[u8], crc32: &str) -> Result<(), StatusCode> {
+    let Ok(expected) = u32::from_str_radix(crc32.trim(), 16) else {
+        log::warn!("Rejected segment with a malformed CRC32 {:?}", crc32);
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    let actual = crc32fast::hash(data);
+    if actual != expected {
+        log::warn!(
+            "Rejected segment of {} bytes damaged on the way: CRC32 {:08x}, sent {:08x}",
+            data.len(),
+            actual,
+            expected
+        );
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    Ok(())
+}
+
+/// Bytes of the segments stored in `dir`, other than segment `except`
+pub fn stored_size(dir: &Path, except: usize) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix("segment_"))
+                .and_then(|index| index.parse::<usize>().ok())
+                .is_some_and(|index| index != except)
+        })
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// What a segment request says about itself and its file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub index: usize,
+    pub total: usize,
+    /// Bytes in this segment
+    pub size: u64,
+    /// Declared size of the whole file; clients before it was added don't
+    /// send one
+    pub file_size: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: SegmentLimits = SegmentLimits {
+        max_segments: 4,
+        chunk_size: 100,
+    };
+
+    fn segment(index: usize, total: usize, size: u64, file_size: Option<u64>) -> Segment {
+        Segment {
+            index,
+            total,
+            size,
+            file_size,
+        }
+    }
+
+    #[test]
+    fn test_segment_bounds() {
+        assert_eq!(LIMITS.check(&segment(0, 1, 100, None), 0), Ok(()));
+        assert_eq!(
+            LIMITS.check(&segment(0, 0, 10, None), 0),
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            LIMITS.check(&segment(2, 2, 10, None), 0),
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            LIMITS.check(&segment(0, 5, 10, None), 0),
+            Err(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+        assert_eq!(
+            LIMITS.check(&segment(0, 2, 101, None), 0),
+            Err(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+    }
+
+    #[test]
+    fn test_check_crc32() {
+        // The check value of CRC-32/ISO-HDLC
+        assert_eq!(check_crc32(b"123456789", "cbf43926"), Ok(()));
+        assert_eq!(check_crc32(b"123456789", "CBF43926"), Ok(()));
+        assert_eq!(check_crc32(b"", "00000000"), Ok(()));
+        assert_eq!(
+            check_crc32(b"123456788", "cbf43926"),
+            Err(StatusCode::UNPROCESSABLE_ENTITY)
+        );
+        assert_eq!(
+            check_crc32(b"123456789", "not hex"),
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn test_stored_size() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(stored_size(&dir.path().join("missing"), 0), 0);
+        std::fs::write(dir.path().join("segment_0"), [0; 100]).unwrap();
+        std::fs::write(dir.path().join("segment_1"), [0; 100]).unwrap();
+        std::fs::write(dir.path().join("notes"), [0; 7]).unwrap();
+        assert_eq!(stored_size(dir.path(), 2), 200);
+        // A segment sent again replaces the stored one
+        assert_eq!(stored_size(dir.path(), 1), 100);
+    }
+
+    #[test]
+    fn test_declared_size() {
+        assert_eq!(LIMITS.check(&segment(2, 3, 50, Some(250)), 200), Ok(()));
+        // Three segments can't hold 350 bytes, nor can 2 bytes fill three
+        assert_eq!(
+            LIMITS.check(&segment(0, 3, 100, Some(350)), 0),
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            LIMITS.check(&segment(0, 3, 1, Some(2)), 0),
+            Err(StatusCode::BAD_REQUEST)
+        );
+        // More bytes arrive than the file has
+        assert_eq!(
+            LIMITS.check(&segment(2, 3, 100, Some(250)), 200),
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Checks on files shared in place from the desktop. The folder a shared
+//! file sits in may hold links crafted by someone else, e.g. `report.pdf`
+//! pointing at `~/.ssh/id_ed25519`, and a path that was fine when shared
+//! may since have been swapped for a link, a FIFO or a device. Shared paths
+//! are checked when shared and again before each read: links are served
+//! as `security.shared_links` says and anything but a regular file is
+//! refused, so a FIFO can't hang a download and a device can't be read.
+
+use std::fmt;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::config::LinkPolicy;
+
+/// Why a shared path is not served
+#[derive(Debug)]
+pub enum Refused {
+    Io(io::Error),
+    /// The path could lead out of its folder, e.g. through `..`
+    NotInFolder,
+    /// A link, with links denied
+    Link(PathBuf),
+    /// A link to somewhere outside the folder
+    Escapes(PathBuf),
+    /// A FIFO, socket, device or folder rather than a regular file
+    NotAFile(&'static str),
+}
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refused::Io(e) => write!(f, "{}", e),
+            Refused::NotInFolder => write!(f, "not a plain path inside its folder"),
+            Refused::Link(target) => write!(f, "a link to {:?}, and links are not shared", target),
+            Refused::Escapes(target) => write!(f, "a link out of its folder, to {:?}", target),
+            Refused::NotAFile(kind) => write!(f, "a {}, not a regular file", kind),
+        }
+    }
+}
+
+impl std::error::Error for Refused {}
+
+impl From<io::Error> for Refused {
+    fn from(e: io::Error) -> Self {
+        Refused::Io(e)
+    }
+}
+
+impl Refused {
+    /// Whether the path is simply gone rather than refused
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Refused::Io(e) if e.kind() == io::ErrorKind::NotFound)
+    }
+}
+
+/// What kind of file other than a regular one `file_type` is
+fn kind(file_type: &std::fs::FileType) -> &'static str {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_fifo() {
+            return "FIFO";
+        }
+        if file_type.is_socket() {
+            return "socket";
+        }
+        if file_type.is_block_device() || file_type.is_char_device() {
+            return "device";
+        }
+    }
+    if file_type.is_dir() {
+        "folder"
+    } else {
+        "special file"
+    }
+}
+
+/// The regular file that `relative`, a path inside the folder `root`,
+/// leads to under `policy`
+pub fn resolve(root: &Path, relative: &Path, policy: LinkPolicy) -> Result<PathBuf, Refused> {
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(Refused::NotInFolder);
+    }
+    let root = root.canonicalize()?;
+    let direct = root.join(relative);
+    // Resolving links never opens the file, so a FIFO can't block here
+    let target = direct.canonicalize()?;
+    if target != direct {
+        match policy {
+            LinkPolicy::Deny => return Err(Refused::Link(target)),
+            LinkPolicy::Within if !target.starts_with(&root) => {
+                return Err(Refused::Escapes(target))
+            }
+            _ => {}
+        }
+    }
+    let file_type = std::fs::metadata(&target)?.file_type();
+    if !file_type.is_file() {
+        return Err(Refused::NotAFile(kind(&file_type)));
+    }
+    Ok(target)
+}
+
+/// The regular file the shared `path` leads to under `policy`, with the
+/// folder it is in as the one links must stay within
+pub fn resolve_shared(path: &Path, policy: LinkPolicy) -> Result<PathBuf, Refused> {
+    match (path.parent(), path.file_name()) {
+        (Some(folder), Some(name)) => resolve(folder, Path::new(name), policy),
+        _ => Err(Refused::NotInFolder),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_regular_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("docs")).unwrap();
+        std::fs::write(dir.path().join("docs/notes.txt"), "notes").unwrap();
+        let root = dir.path().canonicalize().unwrap();
+
+        for policy in [LinkPolicy::Follow, LinkPolicy::Within, LinkPolicy::Deny] {
+            assert_eq!(
+                resolve(dir.path(), Path::new("docs/notes.txt"), policy).unwrap(),
+                root.join("docs/notes.txt")
+            );
+        }
+        assert!(matches!(
+            resolve(
+                dir.path(),
+                Path::new("docs/../docs/notes.txt"),
+                LinkPolicy::Follow
+            ),
+            Err(Refused::NotInFolder)
+        ));
+        assert!(matches!(
+            resolve(dir.path(), Path::new("docs"), LinkPolicy::Follow),
+            Err(Refused::NotAFile("folder"))
+        ));
+        assert!(
+            resolve(dir.path(), Path::new("missing.txt"), LinkPolicy::Follow)
+                .unwrap_err()
+                .is_not_found()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_links() {
+        use std::os::unix::fs::symlink;
+
+        let outside = tempfile::tempdir().unwrap();
+        let secret = outside.path().join("id_ed25519");
+        std::fs::write(&secret, "key").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "notes").unwrap();
+        symlink(dir.path().join("notes.txt"), dir.path().join("inside.txt")).unwrap();
+        symlink(&secret, dir.path().join("report.pdf")).unwrap();
+        symlink(outside.path(), dir.path().join("linked")).unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let secret = secret.canonicalize().unwrap();
+
+        let inside = Path::new("inside.txt");
+        let escaping = Path::new("report.pdf");
+        let through_folder = Path::new("linked/id_ed25519");
+        assert_eq!(
+            resolve(dir.path(), inside, LinkPolicy::Within).unwrap(),
+            root.join("notes.txt")
+        );
+        assert!(matches!(
+            resolve(dir.path(), escaping, LinkPolicy::Within),
+            Err(Refused::Escapes(target)) if target == secret
+        ));
+        assert!(matches!(
+            resolve(dir.path(), through_folder, LinkPolicy::Within),
+            Err(Refused::Escapes(_))
+        ));
+        assert!(matches!(
+            resolve(dir.path(), inside, LinkPolicy::Deny),
+            Err(Refused::Link(_))
+        ));
+        assert_eq!(
+            resolve(dir.path(), escaping, LinkPolicy::Follow).unwrap(),
+            secret
+        );
+        assert_eq!(
+            resolve_shared(&dir.path().join("report.pdf"), LinkPolicy::Follow).unwrap(),
+            secret
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_special_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let fifo = dir.path().join("pipe");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert!(matches!(
+            resolve_shared(&fifo, LinkPolicy::Follow),
+            Err(Refused::NotAFile("FIFO"))
+        ));
+        assert!(matches!(
+            resolve(Path::new("/dev"), Path::new("null"), LinkPolicy::Follow),
+            Err(Refused::NotAFile("device"))
+        ));
+    }
+}
@@ -0,0 +1,274 @@
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{Json, Router};
+use once_cell::sync::Lazy;
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Options, Parser, Tag};
+use serde::Deserialize;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::highlighted_html_for_string;
+use syntect::parsing::SyntaxSet;
+
+use super::file_server::AppState;
+use super::link_preview;
+use super::sanitize::{escape_html, is_safe_url};
+use crate::models::{unix_now, Snippet};
+
+/// Snippets kept on the portal; the oldest is dropped beyond this
+pub const MAX_SNIPPETS: usize = 50;
+
+/// Largest snippet accepted, in bytes
+pub const MAX_SNIPPET_LEN: usize = 256 * 1024;
+
+static SYNTAXES: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
+static THEME: Lazy<Theme> = Lazy::new(|| {
+    ThemeSet::load_defaults()
+        .themes
+        .remove("InspiredGitHub")
+        .unwrap_or_default()
+});
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct NewSnippet {
+    content: String,
+    #[serde(default)]
+    format: String,
+}
+
+impl NewSnippet {
+    /// The snippet a request shares: JSON with its format, or the text
+    /// itself as a `text/plain` body, e.g. a clipboard piped into curl
+    fn from_request(headers: &HeaderMap, body: &[u8]) -> Result<Self, StatusCode> {
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        if content_type.starts_with("text/plain") {
+            let content = std::str::from_utf8(body).map_err(|_| StatusCode::BAD_REQUEST)?;
+            return Ok(Self {
+                content: content.to_string(),
+                format: String::new(),
+            });
+        }
+        if !content_type.starts_with("application/json") {
+            return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+        serde_json::from_slice(body).map_err(|_| StatusCode::BAD_REQUEST)
+    }
+}
+
+/// Highlight code in the given language, falling back to plain text
+fn highlight(code: &str, language: &str) -> String {
+    SYNTAXES
+        .find_syntax_by_token(language)
+        .and_then(|syntax| highlighted_html_for_string(code, &SYNTAXES, syntax, &THEME).ok())
+        .unwrap_or_else(|| format!("<pre>{}</pre>", escape_html(code)))
+}
+
+/// Render Markdown with raw HTML shown as text and fenced code highlighted
+fn render_markdown(content: &str) -> String {
+    let mut events = Vec::new();
+    let mut code_block: Option<(String, String)> = None;
+
+    for event in Parser::new_ext(content, Options::all()) {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(language) => language.to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code_block = Some((language, String::new()));
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                if let Some((language, code)) = code_block.take() {
+                    events.push(Event::Html(highlight(&code, &language).into()));
+                }
+            }
+            Event::Text(text) if code_block.is_some() => {
+                if let Some((_, code)) = code_block.as_mut() {
+                    code.push_str(&text);
+                }
+            }
+            Event::Html(raw) => events.push(Event::Text(raw)),
+            Event::Start(Tag::Link(kind, url, title)) if !is_safe_url(&url) => {
+                events.push(Event::Start(Tag::Link(kind, CowStr::from("#"), title)))
+            }
+            Event::Start(Tag::Image(kind, url, title)) if !is_safe_url(&url) => {
+                events.push(Event::Start(Tag::Image(kind, CowStr::from("#"), title)))
+            }
+            event => events.push(event),
+        }
+    }
+
+    let mut rendered = String::new();
+    html::push_html(&mut rendered, events.into_iter());
+    rendered
+}
+
+/// Render a snippet to HTML for the portal
+pub fn render(content: &str, format: &str) -> String {
+    match format {
+        "" | "text" => format!("<pre>{}</pre>", escape_html(content)),
+        "markdown" => render_markdown(content),
+        language => highlight(content, language),
+    }
+}
+
+#[axum::debug_handler]
+async fn get_snippets(State(state): State<AppState>) -> Json<Vec<Snippet>> {
+    Json(state.snippets.lock().unwrap().clone())
+}
+
+#[axum::debug_handler]
+async fn share_snippet(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Snippet>, StatusCode> {
+    if state.read_only() {
+        log::warn!("Rejected text share on read-only server");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let new = NewSnippet::from_request(&headers, &body)?;
+    if new.content.trim().is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if new.content.len() > MAX_SNIPPET_LEN {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let preview = match link_preview::link(&new.content) {
+        Some(url) if state.link_previews => link_preview::preview(url).await,
+        _ => None,
+    };
+    let format = new.format.trim().to_ascii_lowercase();
+    let snippet = Snippet {
+        id: uuid::Uuid::new_v4().to_string(),
+        html: render(&new.content, &format),
+        content: new.content,
+        format,
+        created_at: unix_now(),
+        preview,
+    };
+
+    let mut snippets = state.snippets.lock().unwrap();
+    snippets.push(snippet.clone());
+    if snippets.len() > MAX_SNIPPETS {
+        snippets.remove(0);
+    }
+    log::info!("Shared {} snippet {}", snippet.format, snippet.id);
+    Ok(Json(snippet))
+}
+
+/// The text shared last, to fetch on another device, e.g. into its
+/// clipboard
+#[axum::debug_handler]
+async fn get_latest_snippet(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let snippets = state.snippets.lock().unwrap();
+    let snippet = snippets.last().ok_or(StatusCode::NOT_FOUND)?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        snippet.content.clone(),
+    ))
+}
+
+#[axum::debug_handler]
+async fn get_raw_snippet(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let snippets = state.snippets.lock().unwrap();
+    let snippet = snippets
+        .iter()
+        .find(|s| s.id == id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        snippet.content.clone(),
+    ))
+}
+
+/// Text sharing endpoints, nested under the versioned API prefix
+pub fn api_router() -> Router<AppState> {
+    Router::new()
+        .route("/texts", get(get_snippets).post(share_snippet))
+        .route("/texts/latest/raw", get(get_latest_snippet))
+        .route("/texts/:id/raw", get(get_raw_snippet))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_text_is_escaped() {
+        assert_eq!(
+            render("<b>rm -rf</b> & exit", "text"),
+            "<pre>&lt;b&gt;rm -rf&lt;/b&gt; &amp; exit</pre>"
+        );
+    }
+
+    #[test]
+    fn test_markdown_drops_raw_html_and_script_links() {
+        let html = render(
+            "# Title\n\n<script>alert(1)</script>\n\n[x](javascript:alert(1))",
+            "markdown",
+        );
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("javascript:"));
+    }
+
+    #[test]
+    fn test_new_snippet_from_request() {
+        let headers = |content_type: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+            headers
+        };
+        assert_eq!(
+            NewSnippet::from_request(
+                &headers("application/json"),
+                br#"{"content":"fn main() {}","format":"rust"}"#
+            ),
+            Ok(NewSnippet {
+                content: "fn main() {}".to_string(),
+                format: "rust".to_string(),
+            })
+        );
+        // A clipboard sent as it is
+        assert_eq!(
+            NewSnippet::from_request(
+                &headers("text/plain; charset=utf-8"),
+                "https://example.com/ü".as_bytes()
+            ),
+            Ok(NewSnippet {
+                content: "https://example.com/ü".to_string(),
+                format: String::new(),
+            })
+        );
+        assert_eq!(
+            NewSnippet::from_request(&headers("text/plain"), b"\xff\xfe"),
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            NewSnippet::from_request(&headers("application/json"), b"{}"),
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            NewSnippet::from_request(&HeaderMap::new(), b"text"),
+            Err(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        );
+    }
+
+    #[test]
+    fn test_code_is_highlighted() {
+        let html = render("fn main() {}\n", "rust");
+        assert!(html.contains("<span"));
+        assert!(!render("<x>", "no-such-language").contains("<x>"));
+    }
+}
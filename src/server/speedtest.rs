@@ -0,0 +1,87 @@
+use axum::body::{Body, Bytes};
+use axum::extract::{DefaultBodyLimit, Query};
+use axum::http::header;
+use axum::middleware;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
+use tokio_util::io::ReaderStream;
+
+use super::file_server::AppState;
+use super::memory;
+
+/// Upper bound for a single speed test transfer
+pub const MAX_TEST_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Transfer size used when the client doesn't ask for one
+const DEFAULT_TEST_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct DownloadQuery {
+    bytes: Option<u64>,
+}
+
+/// Reply to an upload test; the client times the request itself
+#[derive(Debug, Serialize)]
+pub struct UploadResult {
+    bytes: u64,
+}
+
+/// Clamp the requested transfer size to the allowed range
+pub fn test_size(requested: Option<u64>) -> u64 {
+    requested
+        .unwrap_or(DEFAULT_TEST_BYTES)
+        .clamp(1, MAX_TEST_BYTES)
+}
+
+/// Stream `bytes` of filler data without buffering it
+async fn download(Query(query): Query<DownloadQuery>) -> Response {
+    let bytes = test_size(query.bytes);
+    let body = Body::from_stream(ReaderStream::new(tokio::io::repeat(0xA5).take(bytes)));
+    (
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (header::CONTENT_LENGTH, bytes.to_string()),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// Discard an uploaded body and report how much arrived
+async fn upload(body: Bytes) -> Json<UploadResult> {
+    Json(UploadResult {
+        bytes: body.len() as u64,
+    })
+}
+
+/// Throughput test endpoints, nested under the versioned API prefix
+pub fn api_router() -> Router<AppState> {
+    Router::new()
+        .route("/speedtest/download", get(download))
+        .route(
+            "/speedtest/upload",
+            post(upload)
+                .layer(DefaultBodyLimit::max(MAX_TEST_BYTES as usize))
+                .layer(middleware::from_fn_with_state(
+                    MAX_TEST_BYTES as usize,
+                    memory::hold_body,
+                )),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size_is_clamped() {
+        assert_eq!(test_size(None), DEFAULT_TEST_BYTES);
+        assert_eq!(test_size(Some(0)), 1);
+        assert_eq!(test_size(Some(1024)), 1024);
+        assert_eq!(test_size(Some(u64::MAX)), MAX_TEST_BYTES);
+    }
+}
@@ -0,0 +1,206 @@
+//! Live counters of a running server: since when it runs, which devices have
+//! a connection open and how many bytes were received and sent. They are
+//! reported in `ServerInfo`, so both `/api/v1/info` and the desktop header
+//! show them. The speed of recent uploads is kept too, for the transfer time
+//! estimates of `/api/v1/preflight`.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::memory::MemoryUsage;
+use super::transfers::SPEED_SMOOTHING;
+
+/// Counters of one server, shared by its listeners and handlers
+#[derive(Clone, Default)]
+pub struct ServerStats {
+    /// Unix time the server was started, 0 while it is stopped
+    started_at: Arc<AtomicU64>,
+    /// Open connections per client address
+    connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    bytes_in: Arc<AtomicU64>,
+    bytes_out: Arc<AtomicU64>,
+    /// Bytes per second of recent uploads, smoothed
+    upload_speed: Arc<Mutex<Option<f64>>>,
+}
+
+impl ServerStats {
+    /// Start counting afresh for a server started at `now`
+    pub fn started(&self, now: u64) {
+        self.started_at.store(now, Ordering::Relaxed);
+        self.bytes_in.store(0, Ordering::Relaxed);
+        self.bytes_out.store(0, Ordering::Relaxed);
+    }
+
+    pub fn stopped(&self) {
+        self.started_at.store(0, Ordering::Relaxed);
+    }
+
+    /// Register a connection from `ip` that stays open until the guard is
+    /// dropped
+    pub fn connect(&self, ip: IpAddr) -> Connection {
+        // Clients on a dual-stack listener show up as IPv4-mapped IPv6
+        let ip = ip.to_canonical();
+        let mut connections = self.connections.lock().unwrap();
+        let count = connections.entry(ip).or_default();
+        *count += 1;
+        Connection {
+            connections: self.connections.clone(),
+            ip,
+            first: *count == 1,
+        }
+    }
+
+    /// Counter of the bytes received in request bodies
+    pub fn bytes_in(&self) -> Arc<AtomicU64> {
+        self.bytes_in.clone()
+    }
+
+    /// Take the speed of an upload of `bytes` that took `elapsed` into
+    /// account. Speeds outlast restarts, as the network usually stays.
+    pub fn record_upload(&self, bytes: u64, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        if bytes == 0 || secs <= 0.0 {
+            return;
+        }
+        let speed = bytes as f64 / secs;
+        let mut upload_speed = self.upload_speed.lock().unwrap();
+        *upload_speed = Some(match *upload_speed {
+            Some(previous) => previous + SPEED_SMOOTHING * (speed - previous),
+            None => speed,
+        });
+    }
+
+    /// Bytes per second of recent uploads, once one was measured
+    pub fn upload_speed(&self) -> Option<f64> {
+        *self.upload_speed.lock().unwrap()
+    }
+
+    /// Counter of the bytes sent in downloads
+    pub fn bytes_out(&self) -> Arc<AtomicU64> {
+        self.bytes_out.clone()
+    }
+
+    /// The counters at `now`, with the `active_transfers` the server has
+    pub fn snapshot(&self, now: u64, active_transfers: usize) -> StatsSnapshot {
+        let started_at = match self.started_at.load(Ordering::Relaxed) {
+            0 => None,
+            started_at => Some(started_at),
+        };
+        StatsSnapshot {
+            started_at,
+            uptime_secs: started_at.map_or(0, |started_at| now.saturating_sub(started_at)),
+            devices: self.connections.lock().unwrap().len(),
+            active_transfers,
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            memory: MemoryUsage::default(),
+        }
+    }
+}
+
+/// One open connection
+pub struct Connection {
+    connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    ip: IpAddr,
+    first: bool,
+}
+
+impl Connection {
+    /// Whether the device had no other connection open, i.e. just arrived
+    pub fn is_first(&self) -> bool {
+        self.first
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(count) = connections.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// What the counters of a server said when its info was taken
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatsSnapshot {
+    /// Unix time the server was started, `None` while it is stopped
+    pub started_at: Option<u64>,
+    pub uptime_secs: u64,
+    /// Devices with a connection open
+    pub devices: usize,
+    /// Uploads and downloads in flight
+    pub active_transfers: usize,
+    /// Bytes received since the server was started
+    pub bytes_in: u64,
+    /// Bytes sent since the server was started
+    pub bytes_out: u64,
+    /// Transfer buffers of the whole app, filled in by the server
+    #[serde(default)]
+    pub memory: MemoryUsage,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connected_devices() {
+        let stats = ServerStats::default();
+        let laptop = stats.connect("192.168.1.20".parse().unwrap());
+        let mapped = stats.connect("::ffff:192.168.1.20".parse().unwrap());
+        let phone = stats.connect("192.168.1.21".parse().unwrap());
+        assert_eq!(stats.snapshot(0, 0).devices, 2);
+        assert!(laptop.is_first() && !mapped.is_first() && phone.is_first());
+
+        drop(laptop);
+        assert_eq!(stats.snapshot(0, 0).devices, 2);
+        drop(mapped);
+        assert_eq!(stats.snapshot(0, 0).devices, 1);
+        drop(phone);
+        assert_eq!(stats.snapshot(0, 0).devices, 0);
+        assert!(stats.connect("192.168.1.21".parse().unwrap()).is_first());
+    }
+
+    #[test]
+    fn test_uptime_and_bytes() {
+        let stats = ServerStats::default();
+        assert_eq!(stats.snapshot(1000, 0), StatsSnapshot::default());
+
+        stats.started(1000);
+        stats.bytes_in().fetch_add(300, Ordering::Relaxed);
+        stats.bytes_out().fetch_add(700, Ordering::Relaxed);
+        let snapshot = stats.snapshot(1090, 2);
+        assert_eq!(snapshot.started_at, Some(1000));
+        assert_eq!(snapshot.uptime_secs, 90);
+        assert_eq!(snapshot.active_transfers, 2);
+        assert_eq!((snapshot.bytes_in, snapshot.bytes_out), (300, 700));
+
+        // A restart counts from zero again
+        stats.stopped();
+        assert_eq!(stats.snapshot(2000, 0).started_at, None);
+        stats.started(2000);
+        assert_eq!(stats.snapshot(2000, 0).bytes_in, 0);
+    }
+
+    #[test]
+    fn test_upload_speed() {
+        let stats = ServerStats::default();
+        assert_eq!(stats.upload_speed(), None);
+        stats.record_upload(1000, Duration::ZERO);
+        assert_eq!(stats.upload_speed(), None);
+
+        stats.record_upload(2000, Duration::from_secs(2));
+        assert_eq!(stats.upload_speed(), Some(1000.0));
+        stats.record_upload(4000, Duration::from_secs(2));
+        assert_eq!(stats.upload_speed(), Some(1300.0));
+    }
+}
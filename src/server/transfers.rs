@@ -0,0 +1,745 @@
+//! Transfers in flight on this instance: uploads, downloads, chunks pushed by
+//! other instances and delta syncs. Each one is announced with `transfer.*`
+//! events, which the Transfers panel follows, and can be paused, resumed
+//! or cancelled by id. A paused transfer holds its bytes, keeping the
+//! connection open until it is resumed.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::body::{Body, Bytes};
+use axum::extract::{Request, State};
+use axum::http::{header, Method};
+use axum::middleware::Next;
+use axum::response::Response;
+use futures_util::Stream;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+
+use super::client_ip;
+use super::file_server::AppState;
+use super::inhibit::TransferGuard;
+use crate::integrations::{self, ServerEvent};
+
+/// Time between two progress events of a transfer
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Weight of the latest measurement in the displayed speed
+pub const SPEED_SMOOTHING: f64 = 0.3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferKind {
+    /// A file sent to this instance
+    Upload,
+    /// A file served by this instance
+    Download,
+    /// Chunks pushed by another instance
+    Push,
+    /// A delta update of a file this instance already has
+    Sync,
+}
+
+impl TransferKind {
+    /// What the transfer is doing, for display
+    pub fn verb(&self) -> &'static str {
+        match self {
+            TransferKind::Upload => "Receiving",
+            TransferKind::Download => "Sending",
+            TransferKind::Push => "Receiving chunks",
+            TransferKind::Sync => "Syncing",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferOutcome {
+    Completed,
+    Cancelled,
+    /// The connection ended before everything was transferred
+    Failed,
+}
+
+struct ActiveTransfer {
+    token: CancellationToken,
+    /// Id of the stored file being sent
+    file: Option<String>,
+    pause: Pause,
+}
+
+/// Hold on transfers, shared by the parts of an upload session so that the
+/// session and its parts in flight pause and resume together
+#[derive(Debug, Clone, Default)]
+pub struct Pause {
+    /// While paused, a token cancelled once resumed
+    resumed: Arc<Mutex<Option<CancellationToken>>>,
+}
+
+impl Pause {
+    pub fn is_paused(&self) -> bool {
+        self.resumed.lock().unwrap().is_some()
+    }
+
+    /// Hold the transfers, returning whether they were running
+    pub fn pause(&self) -> bool {
+        {
+            let mut resumed = self.resumed.lock().unwrap();
+            if resumed.is_some() {
+                return false;
+            }
+            *resumed = Some(CancellationToken::new());
+        }
+        self.announce(true);
+        true
+    }
+
+    /// Let the transfers go on, returning whether they were paused
+    pub fn resume(&self) -> bool {
+        let Some(resumed) = self.resumed.lock().unwrap().take() else {
+            return false;
+        };
+        resumed.cancel();
+        self.announce(false);
+        true
+    }
+
+    /// While paused, resolves once resumed
+    fn resumed(&self) -> Option<WaitForCancellationFutureOwned> {
+        self.resumed
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|resumed| resumed.clone().cancelled_owned())
+    }
+
+    fn is(&self, other: &Pause) -> bool {
+        Arc::ptr_eq(&self.resumed, &other.resumed)
+    }
+
+    /// Tell the Transfers panel about the transfers held by this
+    fn announce(&self, paused: bool) {
+        let ids: Vec<u64> = ACTIVE
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, transfer)| transfer.pause.is(self))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ids {
+            integrations::emit(if paused {
+                ServerEvent::TransferPaused { id }
+            } else {
+                ServerEvent::TransferResumed { id }
+            });
+        }
+    }
+}
+
+/// The transfers in flight
+static ACTIVE: Lazy<Mutex<HashMap<u64, ActiveTransfer>>> = Lazy::new(Default::default);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Cancel transfer `id`, returning whether it was still in flight
+pub fn cancel(id: u64) -> bool {
+    match ACTIVE.lock().unwrap().get(&id) {
+        Some(transfer) => {
+            log::info!("Cancelling transfer {}", id);
+            transfer.token.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Hold transfer `id`, returning whether it was in flight and running
+pub fn pause(id: u64) -> bool {
+    let pause = ACTIVE.lock().unwrap().get(&id).map(|t| t.pause.clone());
+    if !pause.is_some_and(|pause| pause.pause()) {
+        return false;
+    }
+    log::info!("Paused transfer {}", id);
+    true
+}
+
+/// Let transfer `id` go on, returning whether it was in flight and paused
+pub fn resume(id: u64) -> bool {
+    let pause = ACTIVE.lock().unwrap().get(&id).map(|t| t.pause.clone());
+    if !pause.is_some_and(|pause| pause.resume()) {
+        return false;
+    }
+    log::info!("Resumed transfer {}", id);
+    true
+}
+
+/// Cancel the downloads of stored file `id`, e.g. because it is being
+/// deleted, returning how many were in flight
+pub fn cancel_file(id: &str) -> usize {
+    let active = ACTIVE.lock().unwrap();
+    let mut cancelled = 0;
+    for transfer in active.values() {
+        if transfer.file.as_deref() == Some(id) {
+            transfer.token.cancel();
+            cancelled += 1;
+        }
+    }
+    if cancelled > 0 {
+        log::info!("Cancelled {} download(s) of removed file {}", cancelled, id);
+    }
+    cancelled
+}
+
+fn is_active(id: u64) -> bool {
+    ACTIVE.lock().unwrap().contains_key(&id)
+}
+
+/// One transfer in flight, announced when started and finished when dropped
+pub struct Transfer {
+    id: u64,
+    size: Option<u64>,
+    bytes: u64,
+    reported: Instant,
+    token: CancellationToken,
+    pause: Pause,
+    _guard: Option<TransferGuard>,
+}
+
+impl Transfer {
+    pub fn start(
+        kind: TransferKind,
+        name: Option<String>,
+        peer: Option<String>,
+        size: Option<u64>,
+        guard: Option<TransferGuard>,
+    ) -> Self {
+        Self::start_for_file(kind, None, name, peer, size, guard)
+    }
+
+    /// Start sending stored file `file`, so the transfer is cancelled when
+    /// the file is removed
+    pub fn start_for_file(
+        kind: TransferKind,
+        file: Option<String>,
+        name: Option<String>,
+        peer: Option<String>,
+        size: Option<u64>,
+        guard: Option<TransferGuard>,
+    ) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let token = CancellationToken::new();
+        let pause = Pause::default();
+        ACTIVE.lock().unwrap().insert(
+            id,
+            ActiveTransfer {
+                token: token.clone(),
+                file,
+                pause: pause.clone(),
+            },
+        );
+        integrations::emit(ServerEvent::TransferStarted {
+            id,
+            kind,
+            name,
+            peer,
+            size,
+        });
+        Self {
+            id,
+            size,
+            bytes: 0,
+            reported: Instant::now(),
+            token,
+            pause,
+            _guard: guard,
+        }
+    }
+
+    /// Pause and resume together with `pause`, that of the upload session
+    /// the transfer sends a part of
+    pub fn paused_with(mut self, pause: Pause) -> Self {
+        if let Some(transfer) = ACTIVE.lock().unwrap().get_mut(&self.id) {
+            transfer.pause = pause.clone();
+        }
+        if pause.is_paused() {
+            integrations::emit(ServerEvent::TransferPaused { id: self.id });
+        }
+        self.pause = pause;
+        self
+    }
+
+    /// Count `bytes` more as transferred, reporting progress now and then
+    pub fn record(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+        if self.reported.elapsed() >= PROGRESS_INTERVAL {
+            self.reported = Instant::now();
+            integrations::emit(ServerEvent::TransferProgress {
+                id: self.id,
+                bytes: self.bytes,
+            });
+        }
+    }
+
+    /// Resolves once the transfer is cancelled
+    pub fn cancelled(&self) -> WaitForCancellationFutureOwned {
+        self.token.clone().cancelled_owned()
+    }
+
+    fn outcome(&self) -> TransferOutcome {
+        if self.token.is_cancelled() {
+            TransferOutcome::Cancelled
+        } else if self.size.is_some_and(|size| self.bytes < size) {
+            TransferOutcome::Failed
+        } else {
+            TransferOutcome::Completed
+        }
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        ACTIVE.lock().unwrap().remove(&self.id);
+        integrations::emit(ServerEvent::TransferFinished {
+            id: self.id,
+            bytes: self.bytes,
+            outcome: self.outcome(),
+        });
+    }
+}
+
+fn cancelled_error() -> std::io::Error {
+    std::io::Error::other("Transfer cancelled")
+}
+
+/// A request or response body that counts its bytes towards a transfer,
+/// holds them while the transfer is paused and fails once it is cancelled
+pub struct Tracked<R> {
+    inner: R,
+    transfer: Option<Transfer>,
+    cancelled: Option<Pin<Box<WaitForCancellationFutureOwned>>>,
+    /// Resolves once the paused transfer is resumed
+    resumed: Option<Pin<Box<WaitForCancellationFutureOwned>>>,
+    /// Traffic counter of the server, which sees small bodies too
+    counter: Option<Arc<AtomicU64>>,
+}
+
+impl<R> Tracked<R> {
+    pub fn new(inner: R, transfer: Option<Transfer>) -> Self {
+        let cancelled = transfer
+            .as_ref()
+            .map(|transfer| Box::pin(transfer.cancelled()));
+        Self {
+            inner,
+            transfer,
+            cancelled,
+            resumed: None,
+            counter: None,
+        }
+    }
+
+    /// Also add the bytes to `counter`
+    pub fn counting(mut self, counter: Arc<AtomicU64>) -> Self {
+        self.counter = Some(counter);
+        self
+    }
+
+    fn poll_cancelled(&mut self, cx: &mut Context<'_>) -> bool {
+        self.cancelled
+            .as_mut()
+            .is_some_and(|cancelled| cancelled.as_mut().poll(cx).is_ready())
+    }
+
+    /// Whether the transfer is paused, waking the task once it is resumed
+    fn poll_paused(&mut self, cx: &mut Context<'_>) -> bool {
+        loop {
+            if self.resumed.is_none() {
+                self.resumed = self
+                    .transfer
+                    .as_ref()
+                    .and_then(|transfer| transfer.pause.resumed())
+                    .map(Box::pin);
+            }
+            let Some(resumed) = self.resumed.as_mut() else {
+                return false;
+            };
+            if resumed.as_mut().poll(cx).is_pending() {
+                return true;
+            }
+            // Resumed, unless paused again since
+            self.resumed = None;
+        }
+    }
+
+    fn record(&mut self, bytes: usize) {
+        if let Some(transfer) = &mut self.transfer {
+            transfer.record(bytes);
+        }
+        if let Some(counter) = &self.counter {
+            counter.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<R: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for Tracked<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if self.poll_cancelled(cx) {
+            return Poll::Ready(Err(cancelled_error()));
+        }
+        if self.poll_paused(cx) {
+            return Poll::Pending;
+        }
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            let read = buf.filled().len() - filled;
+            self.record(read);
+        }
+        result
+    }
+}
+
+impl<S> Stream for Tracked<S>
+where
+    S: Stream<Item = Result<Bytes, axum::Error>> + Unpin,
+{
+    type Item = Result<Bytes, axum::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.poll_cancelled(cx) {
+            return Poll::Ready(Some(Err(axum::Error::new(cancelled_error()))));
+        }
+        if self.poll_paused(cx) {
+            return Poll::Pending;
+        }
+        let result = Pin::new(&mut self.inner).poll_next(cx);
+        if let Poll::Ready(Some(Ok(data))) = &result {
+            let read = data.len();
+            self.record(read);
+        }
+        result
+    }
+}
+
+/// Kind of transfer a request with a body makes
+fn request_kind(method: &Method, path: &str) -> TransferKind {
+    if method == Method::PUT && path.contains("/chunks/") {
+        TransferKind::Push
+    } else if path.ends_with("/delta") {
+        TransferKind::Sync
+    } else {
+        TransferKind::Upload
+    }
+}
+
+/// Id of the v2 upload session a request sends a part of
+fn upload_session<'a>(method: &Method, path: &'a str) -> Option<&'a str> {
+    let (_, id) = path.split_once("/api/v2/upload/")?;
+    (method == Method::PUT && !id.is_empty() && !id.contains('/')).then_some(id)
+}
+
+/// Count the bytes of every request body towards the server's traffic and
+/// track requests with a large body, i.e. uploads, pushed chunks and delta
+/// syncs, as transfers whose speed is kept when they succeed
+pub async fn track_uploads(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    let size = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(0);
+    let transfer = state.transfers.start_if_large(size).map(|guard| {
+        let kind = request_kind(request.method(), request.uri().path());
+        let peer = client_ip::of_parts(
+            request.extensions(),
+            request.headers(),
+            &state.trusted_proxies,
+        )
+        .map(|ip| ip.to_string());
+        let transfer = Transfer::start(kind, None, peer, Some(size), Some(guard));
+        let path = request.uri().path();
+        match upload_session(request.method(), path)
+            .and_then(|id| state.upload_sessions.pause_of(id))
+        {
+            Some(pause) => transfer.paused_with(pause),
+            None => transfer,
+        }
+    });
+    let measured = transfer.is_some();
+    let request = request.map(|body| {
+        Body::from_stream(
+            Tracked::new(body.into_data_stream(), transfer).counting(state.stats.bytes_in()),
+        )
+    });
+    let response = next.run(request).await;
+    if measured && response.status().is_success() {
+        state.stats.record_upload(size, started.elapsed());
+    }
+    response
+}
+
+/// A transfer as seen through its events
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferStatus {
+    pub id: u64,
+    pub kind: TransferKind,
+    pub name: Option<String>,
+    pub peer: Option<String>,
+    pub size: Option<u64>,
+    pub bytes: u64,
+    /// Bytes per second
+    pub speed: f64,
+    pub paused: bool,
+    updated: Instant,
+}
+
+impl TransferStatus {
+    /// Share of the transfer done, 0 when the size is unknown
+    pub fn fraction(&self) -> f32 {
+        match self.size {
+            Some(size) if size > 0 => (self.bytes as f64 / size as f64).min(1.0) as f32,
+            _ => 0.0,
+        }
+    }
+
+    /// Name to show for the transfer
+    pub fn title(&self) -> String {
+        match (&self.name, &self.peer) {
+            (Some(name), _) => name.clone(),
+            (None, Some(peer)) => format!("{} from {}", self.kind.verb(), peer),
+            (None, None) => self.kind.verb().to_string(),
+        }
+    }
+}
+
+/// The transfers in flight, kept current from `transfer.*` events
+#[derive(Debug, Default)]
+pub struct TransferList {
+    transfers: Vec<TransferStatus>,
+}
+
+impl TransferList {
+    pub fn apply(&mut self, event: &ServerEvent, now: Instant) {
+        match event {
+            ServerEvent::TransferStarted {
+                id,
+                kind,
+                name,
+                peer,
+                size,
+            } => self.transfers.push(TransferStatus {
+                id: *id,
+                kind: *kind,
+                name: name.clone(),
+                peer: peer.clone(),
+                size: *size,
+                bytes: 0,
+                speed: 0.0,
+                paused: false,
+                updated: now,
+            }),
+            ServerEvent::TransferProgress { id, bytes } => {
+                let Some(transfer) = self.transfers.iter_mut().find(|t| t.id == *id) else {
+                    return;
+                };
+                let elapsed = now.duration_since(transfer.updated).as_secs_f64();
+                if elapsed > 0.0 {
+                    let speed = bytes.saturating_sub(transfer.bytes) as f64 / elapsed;
+                    transfer.speed = if transfer.speed == 0.0 {
+                        speed
+                    } else {
+                        transfer.speed + SPEED_SMOOTHING * (speed - transfer.speed)
+                    };
+                }
+                transfer.bytes = *bytes;
+                transfer.updated = now;
+            }
+            ServerEvent::TransferPaused { id } | ServerEvent::TransferResumed { id } => {
+                let Some(transfer) = self.transfers.iter_mut().find(|t| t.id == *id) else {
+                    return;
+                };
+                // The time on hold doesn't count towards the speed
+                transfer.paused = matches!(event, ServerEvent::TransferPaused { .. });
+                transfer.speed = 0.0;
+                transfer.updated = now;
+            }
+            ServerEvent::TransferFinished { id, .. } => {
+                self.transfers.retain(|transfer| transfer.id != *id);
+            }
+            _ => {}
+        }
+    }
+
+    /// Forget transfers whose end was missed, e.g. after falling behind on
+    /// events
+    pub fn prune(&mut self) {
+        self.transfers.retain(|transfer| is_active(transfer.id));
+    }
+
+    pub fn list(&self) -> &[TransferStatus] {
+        &self.transfers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_transfer() {
+        let mut transfer = Transfer::start(TransferKind::Download, None, None, Some(10), None);
+        let id = transfer.id;
+        transfer.record(4);
+        assert_eq!(transfer.outcome(), TransferOutcome::Failed);
+        transfer.record(6);
+        assert_eq!(transfer.outcome(), TransferOutcome::Completed);
+
+        assert!(cancel(id));
+        assert_eq!(transfer.outcome(), TransferOutcome::Cancelled);
+        drop(transfer);
+        assert!(!cancel(id));
+    }
+
+    #[test]
+    fn test_cancel_file() {
+        let file = Transfer::start_for_file(
+            TransferKind::Download,
+            Some("f-1".to_string()),
+            None,
+            None,
+            None,
+            None,
+        );
+        let other = Transfer::start(TransferKind::Download, None, None, None, None);
+        assert_eq!(cancel_file("f-1"), 1);
+        assert_eq!(file.outcome(), TransferOutcome::Cancelled);
+        assert_eq!(other.outcome(), TransferOutcome::Completed);
+        drop(file);
+        assert_eq!(cancel_file("f-1"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_pause_transfer() {
+        use tokio::io::AsyncReadExt;
+
+        let transfer = Transfer::start(TransferKind::Download, None, None, Some(5), None);
+        let id = transfer.id;
+        assert!(pause(id));
+        assert!(!pause(id));
+
+        // Nothing is read while paused
+        let mut body = Tracked::new(std::io::Cursor::new(b"hello".to_vec()), Some(transfer));
+        let mut read = Vec::new();
+        let held = tokio::time::timeout(Duration::from_millis(50), body.read_to_end(&mut read));
+        assert!(held.await.is_err());
+        assert!(read.is_empty());
+
+        assert!(resume(id));
+        assert!(!resume(id));
+        body.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, b"hello");
+        drop(body);
+        assert!(!pause(id));
+    }
+
+    #[test]
+    fn test_shared_pause() {
+        let session = Pause::default();
+        let part = Transfer::start(TransferKind::Upload, None, None, None, None)
+            .paused_with(session.clone());
+        assert!(pause(part.id));
+        assert!(session.is_paused());
+        assert!(session.resume());
+        assert!(!part.pause.is_paused());
+    }
+
+    #[test]
+    fn test_request_kind() {
+        assert_eq!(
+            request_kind(&Method::PUT, "/api/v1/chunks/abc"),
+            TransferKind::Push
+        );
+        assert_eq!(
+            request_kind(&Method::POST, "/api/v1/files/abc/delta"),
+            TransferKind::Sync
+        );
+        assert_eq!(
+            request_kind(&Method::POST, "/api/v1/upload"),
+            TransferKind::Upload
+        );
+        assert_eq!(
+            upload_session(&Method::PUT, "/api/v2/upload/abc"),
+            Some("abc")
+        );
+        assert_eq!(
+            upload_session(&Method::POST, "/api/v2/upload/abc/commit"),
+            None
+        );
+        assert_eq!(upload_session(&Method::POST, "/api/v2/upload/"), None);
+    }
+
+    #[test]
+    fn test_transfer_list() {
+        let start = Instant::now();
+        let mut list = TransferList::default();
+        list.apply(
+            &ServerEvent::TransferStarted {
+                id: 7,
+                kind: TransferKind::Upload,
+                name: None,
+                peer: Some("10.0.0.3".to_string()),
+                size: Some(4000),
+            },
+            start,
+        );
+        list.apply(
+            &ServerEvent::TransferProgress { id: 7, bytes: 1000 },
+            start + Duration::from_secs(1),
+        );
+        list.apply(
+            &ServerEvent::TransferProgress { id: 7, bytes: 3000 },
+            start + Duration::from_secs(2),
+        );
+
+        let transfer = &list.list()[0];
+        assert_eq!(transfer.title(), "Receiving from 10.0.0.3");
+        assert_eq!(transfer.fraction(), 0.75);
+        assert_eq!(transfer.speed, 1300.0);
+
+        list.apply(
+            &ServerEvent::TransferPaused { id: 7 },
+            start + Duration::from_secs(2),
+        );
+        assert!(list.list()[0].paused);
+        assert_eq!(list.list()[0].speed, 0.0);
+        list.apply(
+            &ServerEvent::TransferResumed { id: 7 },
+            start + Duration::from_secs(3),
+        );
+        assert!(!list.list()[0].paused);
+
+        list.apply(
+            &ServerEvent::TransferFinished {
+                id: 7,
+                bytes: 4000,
+                outcome: TransferOutcome::Completed,
+            },
+            start + Duration::from_secs(3),
+        );
+        assert!(list.list().is_empty());
+    }
+}
@@ -0,0 +1,109 @@
+//! IDs for segment uploads (`/api/v1/upload`). Clients ask for one with
+//! `POST /api/v1/upload/init` before the first segment instead of making it
+//! up, so two clients can't pick the same ID and an ID can't smuggle a path
+//! into the storage directory, where the segments are kept under it.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::Serialize;
+
+use super::file_server::AppState;
+use crate::models::unix_now;
+
+/// How long an issued ID stays valid after its last segment
+pub const UPLOAD_ID_TTL_SECS: u64 = 60 * 60;
+
+/// IDs issued for segment uploads in progress, with their expiry
+#[derive(Clone, Default)]
+pub struct UploadIds {
+    ids: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl UploadIds {
+    /// Issue a new upload ID
+    pub fn issue(&self, now: u64) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        let mut ids = self.ids.lock().unwrap();
+        ids.retain(|_, expires_at| *expires_at > now);
+        ids.insert(id.clone(), now + UPLOAD_ID_TTL_SECS);
+        id
+    }
+
+    /// Whether a segment may be stored under `id`, keeping an issued ID
+    /// alive. Clients from before `upload/init` still pick their own IDs,
+    /// which are accepted as long as they are UUIDs.
+    pub fn accepts(&self, id: &str, now: u64) -> bool {
+        if let Some(expires_at) = self.ids.lock().unwrap().get_mut(id) {
+            if *expires_at > now {
+                *expires_at = now + UPLOAD_ID_TTL_SECS;
+                return true;
+            }
+        }
+        match uuid::Uuid::parse_str(id) {
+            Ok(uuid) if uuid.hyphenated().to_string() == id => {
+                log::debug!("Segment upload with a client-chosen ID {}", id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Forget the ID of a finished upload
+    pub fn finish(&self, id: &str) {
+        self.ids.lock().unwrap().remove(id);
+    }
+
+    pub fn clear(&self) {
+        self.ids.lock().unwrap().clear();
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct InitResponse {
+    pub file_id: String,
+}
+
+#[axum::debug_handler]
+async fn init_upload(State(state): State<AppState>) -> Json<InitResponse> {
+    Json(InitResponse {
+        file_id: state.upload_ids.issue(unix_now()),
+    })
+}
+
+/// Upload ID requests, nested under the versioned API prefix
+pub fn api_router() -> Router<AppState> {
+    Router::new().route("/upload/init", post(init_upload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issued_ids() {
+        let ids = UploadIds::default();
+        let id = ids.issue(1000);
+        assert_ne!(ids.issue(1000), id);
+
+        // Each segment extends the expiry
+        assert!(ids.accepts(&id, 1000 + UPLOAD_ID_TTL_SECS - 1));
+        assert!(ids.accepts(&id, 1000 + 2 * UPLOAD_ID_TTL_SECS - 2));
+        ids.finish(&id);
+        // Issued IDs are UUIDs, so the compatibility path still takes it
+        assert!(ids.accepts(&id, 1000));
+    }
+
+    #[test]
+    fn test_client_ids() {
+        let ids = UploadIds::default();
+        assert!(ids.accepts("0f8fad5b-d9cb-469f-a165-70867728950e", 1000));
+        assert!(!ids.accepts("../../.config/autostart", 1000));
+        assert!(!ids.accepts("0F8FAD5B-D9CB-469F-A165-70867728950E", 1000));
+        assert!(!ids.accepts("0f8fad5bd9cb469fa16570867728950e", 1000));
+        assert!(!ids.accepts("", 1000));
+    }
+}
@@ -0,0 +1,346 @@
+//! Progress of segment uploads (`/api/v1/upload`) as server-sent events, so
+//! a progress bar can show the bytes the server has rather than count the
+//! segments sent: `GET /api/v1/upload/<file_id>/progress`, or
+//! `/api/v1/rooms/<room>/upload/<file_id>/progress` for a room. Each event
+//! is the upload's state, the first one right away; the stream ends once the
+//! upload completed or failed. Clients that send the metadata fields before
+//! the file see progress while each segment arrives, others as segments are
+//! stored.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::get;
+use axum::Router;
+use futures_util::{stream, Stream, StreamExt};
+use serde::Serialize;
+use tokio::sync::watch;
+
+use super::file_server::AppState;
+use super::rooms::{self, RoomAccess};
+use super::segments::Segment;
+use super::upload_ids::UPLOAD_ID_TTL_SECS;
+use crate::models::unix_now;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadStage {
+    Receiving,
+    /// Every segment is in and the file is being put together and checked
+    Assembling,
+    Completed,
+    Failed,
+}
+
+/// State of an upload as sent to the client
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UploadProgress {
+    pub file_id: String,
+    /// Name of the file, once its first segment is stored
+    pub name: Option<String>,
+    /// Size of the whole file, when the client declared it
+    pub size: Option<u64>,
+    /// Bytes received, counting the segment arriving
+    pub received: u64,
+    pub segments_received: usize,
+    /// 0 until the first segment is stored
+    pub total_segments: usize,
+    pub stage: UploadStage,
+}
+
+/// An upload followed
+struct Followed {
+    /// Sends the progress to the clients; replaced once the upload ended,
+    /// which ends their streams
+    progress: watch::Sender<UploadProgress>,
+    /// Sizes of the segments stored, by index
+    stored: BTreeMap<usize, u64>,
+    ended: bool,
+    updated_at: u64,
+}
+
+impl Followed {
+    /// Bytes of the segments stored, other than segment `except`
+    fn stored_bytes(&self, except: usize) -> u64 {
+        self.stored
+            .iter()
+            .filter(|(index, _)| **index != except)
+            .map(|(_, size)| size)
+            .sum()
+    }
+}
+
+/// Segment uploads followed, by file ID
+#[derive(Clone, Default)]
+pub struct UploadProgressMap {
+    uploads: Arc<Mutex<HashMap<String, Followed>>>,
+}
+
+impl UploadProgressMap {
+    /// Start following upload `file_id`, if it isn't already, and drop the
+    /// uploads that have been idle for longer than their ID lives
+    pub fn begin(&self, file_id: &str, now: u64) {
+        let mut uploads = self.uploads.lock().unwrap();
+        uploads.retain(|_, upload| upload.updated_at + UPLOAD_ID_TTL_SECS > now);
+        uploads.entry(file_id.to_string()).or_insert_with(|| {
+            let progress = UploadProgress {
+                file_id: file_id.to_string(),
+                name: None,
+                size: None,
+                received: 0,
+                segments_received: 0,
+                total_segments: 0,
+                stage: UploadStage::Receiving,
+            };
+            Followed {
+                progress: watch::channel(progress).0,
+                stored: BTreeMap::new(),
+                ended: false,
+                updated_at: now,
+            }
+        });
+    }
+
+    /// Change upload `file_id`, if it is followed and still running
+    fn update(&self, file_id: &str, change: impl FnOnce(&mut Followed)) {
+        let mut uploads = self.uploads.lock().unwrap();
+        if let Some(upload) = uploads.get_mut(file_id).filter(|upload| !upload.ended) {
+            change(upload);
+        }
+    }
+
+    /// `bytes` of segment `index` have arrived so far
+    pub fn arriving(&self, file_id: &str, index: usize, bytes: u64) {
+        self.update(file_id, |upload| {
+            let received = upload.stored_bytes(index) + bytes;
+            upload.progress.send_if_modified(|progress| {
+                std::mem::replace(&mut progress.received, received) != received
+            });
+        });
+    }
+
+    /// `segment` of file `name` was stored. A segment sent again counts
+    /// once.
+    pub fn stored(&self, file_id: &str, name: &str, segment: &Segment, now: u64) {
+        self.update(file_id, |upload| {
+            upload.stored.insert(segment.index, segment.size);
+            upload.updated_at = now;
+            let received = upload.stored_bytes(usize::MAX);
+            let segments_received = upload.stored.len();
+            upload.progress.send_modify(|progress| {
+                progress.name = Some(name.to_string());
+                progress.size = segment.file_size;
+                progress.received = received;
+                progress.segments_received = segments_received;
+                progress.total_segments = segment.total;
+            });
+        });
+    }
+
+    /// The last segment is in; the upload ends when the returned guard is
+    /// dropped, as failed unless told otherwise
+    pub fn assembling(&self, file_id: &str) -> Finishing {
+        self.update(file_id, |upload| {
+            upload
+                .progress
+                .send_modify(|progress| progress.stage = UploadStage::Assembling);
+        });
+        Finishing {
+            uploads: self.clone(),
+            file_id: file_id.to_string(),
+            stage: UploadStage::Failed,
+        }
+    }
+
+    fn end(&self, file_id: &str, stage: UploadStage) {
+        self.update(file_id, |upload| {
+            upload
+                .progress
+                .send_modify(|progress| progress.stage = stage);
+            upload.ended = true;
+            // Dropping the sender ends the streams after this last state
+            let last = upload.progress.borrow().clone();
+            upload.progress = watch::channel(last).0;
+        });
+    }
+
+    /// Follow upload `file_id`. An upload that ended gives its last state
+    /// only.
+    fn follow(&self, file_id: &str, now: u64) -> watch::Receiver<UploadProgress> {
+        self.begin(file_id, now);
+        let uploads = self.uploads.lock().unwrap();
+        let upload = &uploads[file_id];
+        if upload.ended {
+            watch::channel(upload.progress.borrow().clone()).1
+        } else {
+            upload.progress.subscribe()
+        }
+    }
+
+    /// Stop following all uploads, when the server stops
+    pub fn clear(&self) {
+        self.uploads.lock().unwrap().clear();
+    }
+}
+
+/// An upload being put together, ended as failed unless it is told it
+/// completed
+pub struct Finishing {
+    uploads: UploadProgressMap,
+    file_id: String,
+    stage: UploadStage,
+}
+
+impl Finishing {
+    pub fn end(mut self, completed: bool) {
+        if completed {
+            self.stage = UploadStage::Completed;
+        }
+    }
+}
+
+impl Drop for Finishing {
+    fn drop(&mut self) {
+        self.uploads.end(&self.file_id, self.stage);
+    }
+}
+
+/// The states of an upload as they change, starting with the current one
+fn changes(progress: watch::Receiver<UploadProgress>) -> impl Stream<Item = UploadProgress> {
+    stream::unfold((progress, true), |(mut progress, first)| async move {
+        if !first && progress.changed().await.is_err() {
+            return None;
+        }
+        let current = progress.borrow_and_update().clone();
+        Some((current, (progress, false)))
+    })
+}
+
+fn follow(
+    state: &AppState,
+    file_id: &str,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, StatusCode> {
+    let now = unix_now();
+    if !state.upload_ids.accepts(file_id, now) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let events = changes(state.upload_progress.follow(file_id, now))
+        .map(|progress| Event::default().json_data(progress));
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[axum::debug_handler]
+async fn stream_progress(
+    Path(file_id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, StatusCode> {
+    follow(&state, &file_id)
+}
+
+#[axum::debug_handler]
+async fn stream_room_progress(
+    Path((room, file_id)): Path<(String, String)>,
+    access: RoomAccess,
+    State(state): State<AppState>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, StatusCode> {
+    rooms::find_room(&state, &room, &access)?;
+    follow(&state, &file_id)
+}
+
+/// Progress of the main share's uploads, nested under the versioned API
+/// prefix behind its PIN
+pub fn api_router() -> Router<AppState> {
+    Router::new().route("/upload/:file_id/progress", get(stream_progress))
+}
+
+/// Progress of the rooms' uploads, each checking the room's own PIN
+pub fn room_api_router() -> Router<AppState> {
+    Router::new().route(
+        "/rooms/:room/upload/:file_id/progress",
+        get(stream_room_progress),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(index: usize, total: usize, file_size: Option<u64>, size: u64) -> Segment {
+        Segment {
+            index,
+            total,
+            size,
+            file_size,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload_progress() {
+        let uploads = UploadProgressMap::default();
+        uploads.begin("abc", 1000);
+        let mut progress = uploads.follow("abc", 1000);
+        assert_eq!(progress.borrow_and_update().stage, UploadStage::Receiving);
+
+        uploads.arriving("abc", 0, 40);
+        assert_eq!(progress.borrow_and_update().received, 40);
+        uploads.stored("abc", "a.bin", &segment(0, 3, Some(250), 100), 1001);
+        uploads.stored("abc", "a.bin", &segment(1, 3, Some(250), 100), 1002);
+        // A segment sent again counts once
+        uploads.arriving("abc", 1, 30);
+        assert_eq!(progress.borrow_and_update().received, 130);
+        uploads.stored("abc", "a.bin", &segment(1, 3, Some(250), 100), 1003);
+        uploads.stored("abc", "a.bin", &segment(2, 3, Some(250), 50), 1004);
+        assert_eq!(
+            *progress.borrow_and_update(),
+            UploadProgress {
+                file_id: "abc".to_string(),
+                name: Some("a.bin".to_string()),
+                size: Some(250),
+                received: 250,
+                segments_received: 3,
+                total_segments: 3,
+                stage: UploadStage::Receiving,
+            }
+        );
+
+        let finishing = uploads.assembling("abc");
+        assert_eq!(progress.borrow_and_update().stage, UploadStage::Assembling);
+        finishing.end(true);
+        let states: Vec<_> = changes(progress).collect().await;
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].stage, UploadStage::Completed);
+
+        // Following it later gives the last state, and nothing changes it
+        uploads.arriving("abc", 0, 10);
+        let states: Vec<_> = changes(uploads.follow("abc", 1005)).collect().await;
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].stage, UploadStage::Completed);
+        assert_eq!(states[0].received, 250);
+    }
+
+    #[tokio::test]
+    async fn test_failed_and_idle_uploads() {
+        let uploads = UploadProgressMap::default();
+        uploads.begin("abc", 1000);
+        let progress = uploads.follow("abc", 1000);
+        uploads.stored("abc", "a.bin", &segment(0, 1, None, 10), 1001);
+        {
+            let _finishing = uploads.assembling("abc");
+            // e.g. the file didn't match its declared size
+        }
+        let states: Vec<_> = changes(progress).collect().await;
+        assert_eq!(states.last().unwrap().stage, UploadStage::Failed);
+
+        // Uploads nobody finished are dropped with their ID, ending the
+        // streams following them
+        uploads.begin("idle", 2000);
+        let progress = uploads.follow("idle", 2000);
+        uploads.begin("other", 2000 + UPLOAD_ID_TTL_SECS);
+        let states: Vec<_> = changes(progress).collect().await;
+        assert_eq!(states.len(), 1);
+        assert!(!uploads.uploads.lock().unwrap().contains_key("idle"));
+    }
+}
@@ -0,0 +1,909 @@
+//! Upload protocol v2, served under `/api/v2/upload`. The client declares
+//! the file and its size up front, sends parts addressed by their byte
+//! offset in any order, and commits with the SHA-256 of the whole file.
+//! Sessions have an explicit lifecycle: they can be queried to resume after
+//! a dropped connection, paused and resumed, aborted, and expire when left
+//! idle. A client following a session's events is warned before it expires,
+//! so it can resume in time. The segment upload of v1 stays for existing
+//! clients.
+
+use std::collections::HashMap;
+use std::io::{Seek, SeekFrom, Write};
+use std::net::IpAddr;
+use std::ops::Range;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, FromRequest, Path, Query, Request, State};
+use axum::http::StatusCode;
+use axum::middleware;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures_util::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+
+use super::assemble::RunningHash;
+use super::client_ip::ClientIp;
+use super::file_server::{add_received_file, AppState};
+use super::memory;
+use super::protected;
+use super::sanitize::sanitize_name;
+use super::segments;
+use super::transfers::Pause;
+use crate::models::{unix_now, FileInfo};
+
+/// Directory under the storage dir holding the files of open sessions
+const SESSION_DIR: &str = ".uploads";
+
+/// Sessions that received nothing for this long are dropped
+pub const SESSION_TTL_SECS: u64 = 60 * 60;
+
+/// How long before a session expires its client is warned
+pub const EXPIRY_WARNING_SECS: u64 = 5 * 60;
+
+/// How often sessions are checked for expiry
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Part size suggested to clients
+const PART_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Largest part accepted in one request
+const MAX_PART_LEN: usize = 64 * 1024 * 1024;
+
+/// Byte ranges of a file received so far, sorted and merged
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Received {
+    ranges: Vec<Range<u64>>,
+}
+
+impl Received {
+    pub fn add(&mut self, range: Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
+        self.ranges.push(range);
+        self.ranges.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<u64>> = Vec::with_capacity(self.ranges.len());
+        for range in self.ranges.drain(..) {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        self.ranges = merged;
+    }
+
+    /// Number of bytes received from the start of the file on, up to the
+    /// first gap
+    pub fn contiguous(&self) -> u64 {
+        match self.ranges.first() {
+            Some(range) if range.start == 0 => range.end,
+            _ => 0,
+        }
+    }
+
+    /// Number of distinct bytes received
+    pub fn bytes(&self) -> u64 {
+        self.ranges
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum()
+    }
+
+    /// Ranges of a file of `size` bytes that are still missing
+    pub fn missing(&self, size: u64) -> Vec<Range<u64>> {
+        let mut missing = Vec::new();
+        let mut next = 0;
+        for range in &self.ranges {
+            if range.start > next {
+                missing.push(next..range.start);
+            }
+            next = range.end;
+        }
+        if next < size {
+            missing.push(next..size);
+        }
+        missing
+    }
+}
+
+/// An upload in progress
+#[derive(Debug, Clone)]
+struct Session {
+    name: String,
+    size: u64,
+    /// File the parts are written into, at their offsets
+    path: PathBuf,
+    received: Received,
+    device: Option<IpAddr>,
+    /// Hash of the password downloads will need, if the sender set one
+    password_hash: Option<String>,
+    expires_at: u64,
+    /// Held by the host or the client; parts in flight wait and new ones are
+    /// turned away with 425 Too Early
+    pause: Pause,
+    /// Checksum of the parts received so far, kept up as they arrive
+    hash: Arc<Mutex<RunningHash>>,
+    /// Whether the client was warned of the expiry; until the next activity
+    warned: bool,
+    /// Notices for the client following the session, closed when it ends
+    notices: broadcast::Sender<SessionNotice>,
+}
+
+/// State of a session as reported to the client
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SessionStatus {
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+    /// Bytes received so far
+    pub received: u64,
+    /// Byte ranges still to send, as `{ "start", "end" }` with `end` excluded
+    pub missing: Vec<Range<u64>>,
+    pub part_size: u64,
+    #[serde(flatten, with = "crate::models::time::expires_at")]
+    pub expires_at: u64,
+    #[serde(default)]
+    pub paused: bool,
+}
+
+/// Told to the client following a session, as `{ "event": ... }`
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "event")]
+pub enum SessionNotice {
+    /// The session expires soon unless a part arrives or it is paused or
+    /// resumed, with its state for resuming
+    #[serde(rename = "session.expiring")]
+    Expiring(SessionStatus),
+    /// The session expired and the parts received were discarded
+    #[serde(rename = "session.expired")]
+    Expired { id: String },
+}
+
+impl Session {
+    fn status(&self, id: &str) -> SessionStatus {
+        SessionStatus {
+            id: id.to_string(),
+            name: self.name.clone(),
+            size: self.size,
+            received: self.received.bytes(),
+            missing: self.received.missing(self.size),
+            part_size: PART_SIZE,
+            expires_at: self.expires_at,
+            paused: self.pause.is_paused(),
+        }
+    }
+
+    /// Bytes still to come
+    fn remaining(&self) -> u64 {
+        self.size.saturating_sub(self.received.bytes())
+    }
+
+    fn remove_file(&self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            log::warn!("Failed to remove upload {:?}: {}", self.path, e);
+        }
+    }
+
+    /// Remove the file of an aborted session, letting go of the parts still
+    /// held so their requests end
+    fn end(&self) {
+        self.pause.resume();
+        self.remove_file();
+    }
+}
+
+/// Open upload sessions, keyed by id
+#[derive(Clone, Default)]
+pub struct UploadSessions {
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+}
+
+impl UploadSessions {
+    /// Start a session for a file of `size` bytes, stored under
+    /// `scratch_dir` until it is committed. The file and what the open
+    /// sessions still expect must fit in the free space, or it is turned
+    /// away with 507.
+    fn create(
+        &self,
+        scratch_dir: &FsPath,
+        name: &str,
+        size: u64,
+        device: Option<IpAddr>,
+        password_hash: Option<String>,
+        now: u64,
+    ) -> Result<SessionStatus, StatusCode> {
+        self.expire(now);
+
+        let failed = |e: std::io::Error| {
+            log::error!("Failed to start upload of '{}': {}", name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        };
+        let dir = scratch_dir.join(SESSION_DIR);
+        std::fs::create_dir_all(&dir).map_err(failed)?;
+        // Held until the session is in, so sessions started at once can't
+        // count on the same free space
+        let mut sessions = self.sessions.lock().unwrap();
+        let expected: u64 = sessions.values().map(Session::remaining).sum();
+        let free = fs2::available_space(&dir).map_err(failed)?;
+        if size.saturating_add(expected) > free {
+            log::warn!(
+                "Rejected upload of '{}' ({} bytes): {} bytes free, {} expected by open sessions",
+                name,
+                size,
+                free,
+                expected
+            );
+            return Err(StatusCode::INSUFFICIENT_STORAGE);
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let path = dir.join(&id);
+        std::fs::File::create(&path)
+            .and_then(|file| file.set_len(size))
+            .map_err(failed)?;
+
+        let session = Session {
+            name: sanitize_name(name),
+            size,
+            path,
+            received: Received::default(),
+            device,
+            password_hash,
+            expires_at: now + SESSION_TTL_SECS,
+            pause: Pause::default(),
+            hash: Default::default(),
+            warned: false,
+            notices: broadcast::channel(4).0,
+        };
+        let status = session.status(&id);
+        sessions.insert(id, session);
+        Ok(status)
+    }
+
+    /// Drop the sessions that have been idle for too long
+    fn expire(&self, now: u64) {
+        self.sessions.lock().unwrap().retain(|id, session| {
+            let alive = session.expires_at > now;
+            if !alive {
+                log::info!("Upload session {} for '{}' expired", id, session.name);
+                // Nobody following is not an error
+                let _ = session
+                    .notices
+                    .send(SessionNotice::Expired { id: id.clone() });
+                session.remove_file();
+            }
+            alive
+        });
+    }
+
+    /// Warn the clients of sessions about to expire, once until they are
+    /// active again, and drop the expired ones
+    fn sweep(&self, now: u64) {
+        for (id, session) in self.sessions.lock().unwrap().iter_mut() {
+            if !session.warned && expires_soon(session, now) {
+                session.warned = true;
+                log::debug!("Upload session {} expires soon", id);
+                let _ = session
+                    .notices
+                    .send(SessionNotice::Expiring(session.status(id)));
+            }
+        }
+        self.expire(now);
+    }
+
+    /// Follow the notices of session `id`: a warning right away if it
+    /// expires soon, then those to come until the session ends
+    fn follow(
+        &self,
+        id: &str,
+        now: u64,
+    ) -> Option<(Option<SessionNotice>, broadcast::Receiver<SessionNotice>)> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get(id)
+            .filter(|session| session.expires_at > now)?;
+        let warning =
+            expires_soon(session, now).then(|| SessionNotice::Expiring(session.status(id)));
+        Some((warning, session.notices.subscribe()))
+    }
+
+    fn status(&self, id: &str, now: u64) -> Option<SessionStatus> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|session| session.expires_at > now)
+            .map(|session| session.status(id))
+    }
+
+    /// Write `data` at `offset` into the session's file
+    fn write_part(
+        &self,
+        id: &str,
+        offset: u64,
+        data: &[u8],
+        now: u64,
+    ) -> Result<SessionStatus, StatusCode> {
+        let path = {
+            let sessions = self.sessions.lock().unwrap();
+            let session = sessions
+                .get(id)
+                .filter(|session| session.expires_at > now)
+                .ok_or(StatusCode::NOT_FOUND)?;
+            if session.pause.is_paused() {
+                return Err(StatusCode::TOO_EARLY);
+            }
+            offset
+                .checked_add(data.len() as u64)
+                .filter(|end| *end <= session.size)
+                .ok_or(StatusCode::RANGE_NOT_SATISFIABLE)?;
+            session.path.clone()
+        };
+
+        // Parts are written without holding the lock, so sessions don't
+        // wait for each other
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .and_then(|mut file| {
+                file.seek(SeekFrom::Start(offset))?;
+                file.write_all(data)
+            })
+            .map_err(|e| {
+                log::error!("Failed to write part of {:?}: {}", path, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+
+        let (status, hash, contiguous) = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let session = sessions.get_mut(id).ok_or(StatusCode::NOT_FOUND)?;
+            session.received.add(offset..offset + data.len() as u64);
+            session.expires_at = now + SESSION_TTL_SECS;
+            session.warned = false;
+            let contiguous = session.received.contiguous();
+            (session.status(id), session.hash.clone(), contiguous)
+        };
+        // Hashed while the part is at hand, so the commit needn't read the
+        // whole file again
+        hash.lock().unwrap().update(&path, offset, data, contiguous);
+        Ok(status)
+    }
+
+    /// End a session whose file is complete, handing it over for the
+    /// checksum. An incomplete session stays open.
+    fn finish(&self, id: &str, now: u64) -> Result<Session, StatusCode> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get(id)
+            .filter(|session| session.expires_at > now)
+            .ok_or(StatusCode::NOT_FOUND)?;
+        if session.pause.is_paused() {
+            return Err(StatusCode::TOO_EARLY);
+        }
+        if session.received.bytes() < session.size {
+            return Err(StatusCode::CONFLICT);
+        }
+        sessions.remove(id).ok_or(StatusCode::NOT_FOUND)
+    }
+
+    /// Hold or let go on the session and its parts in flight. Either counts
+    /// as activity, so a paused session expires like an idle one.
+    fn set_paused(&self, id: &str, paused: bool, now: u64) -> Result<SessionStatus, StatusCode> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(id)
+            .filter(|session| session.expires_at > now)
+            .ok_or(StatusCode::NOT_FOUND)?;
+        if paused {
+            session.pause.pause();
+        } else {
+            session.pause.resume();
+        }
+        session.expires_at = now + SESSION_TTL_SECS;
+        session.warned = false;
+        Ok(session.status(id))
+    }
+
+    /// The hold of session `id`, for the transfers of its parts
+    pub fn pause_of(&self, id: &str) -> Option<Pause> {
+        let sessions = self.sessions.lock().unwrap();
+        sessions.get(id).map(|session| session.pause.clone())
+    }
+
+    fn abort(&self, id: &str) -> bool {
+        let session = self.sessions.lock().unwrap().remove(id);
+        if let Some(session) = &session {
+            session.end();
+        }
+        session.is_some()
+    }
+
+    /// Abort all sessions, when the server stops
+    pub fn clear(&self) {
+        for (_, session) in self.sessions.lock().unwrap().drain() {
+            session.end();
+        }
+    }
+}
+
+fn expires_soon(session: &Session, now: u64) -> bool {
+    session.expires_at > now && session.expires_at <= now + EXPIRY_WARNING_SECS
+}
+
+/// Warn of and drop expiring sessions until `shutdown` changes
+pub async fn run(sessions: UploadSessions, mut shutdown: watch::Receiver<bool>) {
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(SWEEP_INTERVAL) => {}
+            _ = shutdown.changed() => return,
+        }
+        sessions.sweep(unix_now());
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateRequest {
+    pub name: String,
+    pub size: u64,
+    /// Password downloads of the file will need
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PartQuery {
+    offset: u64,
+    /// CRC32 of the part, checked before it is written
+    #[serde(rename = "segment_crc32")]
+    crc32: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommitRequest {
+    /// Hex SHA-256 of the whole file
+    pub sha256: String,
+}
+
+#[axum::debug_handler]
+async fn create_session(
+    ClientIp(client_ip): ClientIp,
+    State(state): State<AppState>,
+    Json(request): Json<CreateRequest>,
+) -> Result<(StatusCode, Json<SessionStatus>), StatusCode> {
+    if state.read_only() {
+        log::warn!("Rejected upload session on read-only server");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if !state.file_types.accepts(&sanitize_name(&request.name)) {
+        log::warn!("Rejected '{}': kind of file not accepted", request.name);
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+    // The largest file the segment upload takes is the limit here too
+    let max_size = state.segment_limits.max_file_size();
+    if request.size > max_size {
+        log::warn!(
+            "Rejected '{}' of {} bytes, larger than the {} bytes accepted",
+            request.name,
+            request.size,
+            max_size
+        );
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    let password_hash = protected::hash(request.password).await?;
+    let status = state.upload_sessions.create(
+        &state.scratch_dir,
+        &request.name,
+        request.size,
+        client_ip,
+        password_hash,
+        unix_now(),
+    )?;
+    log::info!(
+        "Started upload session {} for '{}' ({} bytes)",
+        status.id,
+        status.name,
+        status.size
+    );
+    Ok((StatusCode::CREATED, Json(status)))
+}
+
+#[axum::debug_handler]
+async fn get_session(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<SessionStatus>, StatusCode> {
+    state
+        .upload_sessions
+        .status(&id, unix_now())
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+#[axum::debug_handler]
+async fn put_part(
+    Path(id): Path<String>,
+    Query(query): Query<PartQuery>,
+    State(state): State<AppState>,
+    request: Request,
+) -> Result<Json<SessionStatus>, StatusCode> {
+    // Turned away before reading the part, which would wait for the resume
+    if state
+        .upload_sessions
+        .pause_of(&id)
+        .is_some_and(|pause| pause.is_paused())
+    {
+        return Err(StatusCode::TOO_EARLY);
+    }
+    let body = Bytes::from_request(request, &state)
+        .await
+        .map_err(|rejection| rejection.status())?;
+    #[cfg(feature = "chaos")]
+    let body = body.slice(..super::chaos::truncated_len(body.len()));
+    if let Some(crc32) = &query.crc32 {
+        segments::check_crc32(&body, crc32)?;
+    }
+    let sessions = state.upload_sessions.clone();
+    tokio::task::spawn_blocking(move || sessions.write_part(&id, query.offset, &body, unix_now()))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(Json)
+}
+
+#[axum::debug_handler]
+async fn commit_session(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Json(request): Json<CommitRequest>,
+) -> Result<Json<FileInfo>, StatusCode> {
+    let session = state.upload_sessions.finish(&id, unix_now())?;
+
+    let (hash, path) = (session.hash.clone(), session.path.clone());
+    let sha256 = tokio::task::spawn_blocking(move || {
+        let hash = hash.lock().unwrap().clone();
+        hash.finish(&path)
+            .map_err(|e| log::warn!("Failed to checksum {:?}: {}", path, e))
+            .ok()
+    })
+    .await
+    .ok()
+    .flatten();
+    if !sha256
+        .as_deref()
+        .is_some_and(|sha256| sha256.eq_ignore_ascii_case(request.sha256.trim()))
+    {
+        log::warn!(
+            "Upload of '{}' does not match its checksum, discarded",
+            session.name
+        );
+        session.remove_file();
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let path = state.scratch_dir.join(format!("{}_file", id));
+    if let Err(e) = std::fs::rename(&session.path, &path) {
+        log::error!("Failed to store upload {:?}: {}", path, e);
+        session.remove_file();
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    log::info!("Committed upload session {} for '{}'", id, session.name);
+
+    let file_info = FileInfo {
+        id,
+        sha256,
+        protected: session.password_hash.is_some(),
+        password_hash: session.password_hash,
+        device: session.device,
+        ..FileInfo::new(session.name, path, session.size)
+    };
+    add_received_file(&state, &state.file_list, &state.temp_dir, None, file_info).await
+}
+
+#[axum::debug_handler]
+async fn pause_session(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<SessionStatus>, StatusCode> {
+    let status = state.upload_sessions.set_paused(&id, true, unix_now())?;
+    log::info!("Paused upload session {}", id);
+    Ok(Json(status))
+}
+
+#[axum::debug_handler]
+async fn resume_session(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<SessionStatus>, StatusCode> {
+    let status = state.upload_sessions.set_paused(&id, false, unix_now())?;
+    log::info!("Resumed upload session {}", id);
+    Ok(Json(status))
+}
+
+/// Notices of a session as server-sent events, for its client to resume
+/// before the session expires. The session id is all it takes, as for
+/// the session's other requests; the stream ends with the session.
+#[axum::debug_handler]
+async fn session_events(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, StatusCode> {
+    let (warning, notices) = state
+        .upload_sessions
+        .follow(&id, unix_now())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let live = stream::unfold(notices, |mut notices| async move {
+        loop {
+            match notices.recv().await {
+                Ok(notice) => return Some((notice, notices)),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    let events = stream::iter(warning)
+        .chain(live)
+        .map(|notice| Event::default().json_data(notice));
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[axum::debug_handler]
+async fn abort_session(Path(id): Path<String>, State(state): State<AppState>) -> StatusCode {
+    if state.upload_sessions.abort(&id) {
+        log::info!("Aborted upload session {}", id);
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// Routes of the v2 upload protocol, nested under `/api/v2`
+pub fn api_router() -> Router<AppState> {
+    Router::new()
+        .route("/upload", post(create_session))
+        .route(
+            "/upload/:id",
+            get(get_session)
+                .put(put_part)
+                .delete(abort_session)
+                .layer(DefaultBodyLimit::max(MAX_PART_LEN))
+                .layer(middleware::from_fn_with_state(
+                    MAX_PART_LEN,
+                    memory::hold_body,
+                )),
+        )
+        .route("/upload/:id/commit", post(commit_session))
+        .route("/upload/:id/pause", post(pause_session))
+        .route("/upload/:id/resume", post(resume_session))
+        .route("/upload/:id/events", get(session_events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::Digest;
+
+    #[test]
+    fn test_received_ranges() {
+        let mut received = Received::default();
+        received.add(10..20);
+        received.add(30..40);
+        assert_eq!(received.bytes(), 20);
+        assert_eq!(received.missing(50), vec![0..10, 20..30, 40..50]);
+
+        // Parts sent again or overlapping count once
+        received.add(15..35);
+        received.add(10..20);
+        assert_eq!(received.bytes(), 30);
+        assert_eq!(received.missing(40), vec![0..10]);
+
+        assert_eq!(received.contiguous(), 0);
+        received.add(0..10);
+        assert_eq!(received.contiguous(), 40);
+        assert!(received.missing(40).is_empty());
+        assert_eq!(Received::default().missing(5), vec![0..5]);
+    }
+
+    #[test]
+    fn test_session_lifecycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let sessions = UploadSessions::default();
+        let status = sessions
+            .create(dir.path(), "../notes.txt", 11, None, None, 1000)
+            .unwrap();
+        assert_eq!(status.name, "notes.txt");
+        assert_eq!(status.missing, vec![0..11]);
+
+        // Parts arrive in any order
+        sessions.write_part(&status.id, 6, b"world", 1001).unwrap();
+        assert_eq!(
+            sessions.finish(&status.id, 1002).unwrap_err(),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            sessions.write_part(&status.id, 8, b"overflow", 1002),
+            Err(StatusCode::RANGE_NOT_SATISFIABLE)
+        );
+        let done = sessions.write_part(&status.id, 0, b"hello ", 1003).unwrap();
+        assert_eq!(done.received, 11);
+
+        let session = sessions.finish(&status.id, 1004).unwrap();
+        assert_eq!(std::fs::read(&session.path).unwrap(), b"hello world");
+        let hash = session.hash.lock().unwrap().clone();
+        assert_eq!(
+            hash.finish(&session.path).unwrap(),
+            hex::encode(sha2::Sha256::digest(b"hello world"))
+        );
+        assert!(sessions.status(&status.id, 1004).is_none());
+    }
+
+    #[test]
+    fn test_sessions_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let sessions = UploadSessions::default();
+        let old = sessions
+            .create(dir.path(), "a.bin", 4, None, None, 1000)
+            .unwrap();
+        sessions.write_part(&old.id, 0, b"ab", 2000).unwrap();
+        assert!(sessions.status(&old.id, 2000 + SESSION_TTL_SECS).is_none());
+
+        // Starting another session cleans up the idle one
+        let path = dir.path().join(SESSION_DIR).join(&old.id);
+        assert!(path.exists());
+        sessions
+            .create(dir.path(), "b.bin", 4, None, None, 2000 + SESSION_TTL_SECS)
+            .unwrap();
+        assert!(!path.exists());
+
+        let aborted = sessions
+            .create(dir.path(), "c.bin", 4, None, None, 3000)
+            .unwrap();
+        assert!(sessions.abort(&aborted.id));
+        assert!(!sessions.abort(&aborted.id));
+    }
+
+    #[test]
+    fn test_session_needs_free_space() {
+        let dir = tempfile::tempdir().unwrap();
+        let sessions = UploadSessions::default();
+        let status = sessions
+            .create(dir.path(), "huge.bin", u64::MAX / 2, None, None, 1000)
+            .unwrap_err();
+        assert_eq!(status, StatusCode::INSUFFICIENT_STORAGE);
+        // Nothing was allocated for it
+        let uploads = dir.path().join(SESSION_DIR);
+        assert_eq!(std::fs::read_dir(&uploads).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_pause_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let sessions = UploadSessions::default();
+        let status = sessions
+            .create(dir.path(), "a.bin", 4, None, None, 1000)
+            .unwrap();
+        sessions.write_part(&status.id, 0, b"ab", 1001).unwrap();
+
+        // Parts are turned away until resumed
+        let paused = sessions.set_paused(&status.id, true, 1002).unwrap();
+        assert!(paused.paused);
+        assert_eq!(paused.expires_at, 1002 + SESSION_TTL_SECS);
+        assert!(sessions.pause_of(&status.id).unwrap().is_paused());
+        assert_eq!(
+            sessions.write_part(&status.id, 2, b"cd", 1003),
+            Err(StatusCode::TOO_EARLY)
+        );
+        assert_eq!(
+            sessions.finish(&status.id, 1003).unwrap_err(),
+            StatusCode::TOO_EARLY
+        );
+
+        assert!(!sessions.set_paused(&status.id, false, 1004).unwrap().paused);
+        let done = sessions.write_part(&status.id, 2, b"cd", 1005).unwrap();
+        assert_eq!(done.received, 4);
+        assert_eq!(
+            sessions.set_paused("missing", true, 1005),
+            Err(StatusCode::NOT_FOUND)
+        );
+
+        // Aborting lets go of parts still held
+        let pause = sessions.pause_of(&status.id).unwrap();
+        sessions.set_paused(&status.id, true, 1006).unwrap();
+        assert!(sessions.abort(&status.id));
+        assert!(!pause.is_paused());
+    }
+
+    #[test]
+    fn test_expiry_notices() {
+        let dir = tempfile::tempdir().unwrap();
+        let sessions = UploadSessions::default();
+        let status = sessions
+            .create(dir.path(), "a.bin", 4, None, None, 1000)
+            .unwrap();
+        let expires_at = 1000 + SESSION_TTL_SECS;
+        let (warning, mut notices) = sessions.follow(&status.id, 1000).unwrap();
+        assert!(warning.is_none());
+
+        // Warned once when the expiry comes near
+        let soon = expires_at - EXPIRY_WARNING_SECS;
+        sessions.sweep(soon - 1);
+        assert!(notices.try_recv().is_err());
+        sessions.sweep(soon);
+        sessions.sweep(soon + 1);
+        match notices.try_recv().unwrap() {
+            SessionNotice::Expiring(warned) => assert_eq!(warned.expires_at, expires_at),
+            notice => panic!("unexpected {:?}", notice),
+        }
+        assert!(notices.try_recv().is_err());
+        // A client following only now is warned right away
+        assert!(sessions.follow(&status.id, soon + 1).unwrap().0.is_some());
+
+        // Activity puts the expiry off and warns again when it comes near
+        sessions.write_part(&status.id, 0, b"ab", soon + 2).unwrap();
+        let expires_at = soon + 2 + SESSION_TTL_SECS;
+        sessions.sweep(expires_at - EXPIRY_WARNING_SECS);
+        assert!(matches!(notices.try_recv(), Ok(SessionNotice::Expiring(_))));
+
+        sessions.sweep(expires_at);
+        assert_eq!(
+            notices.try_recv().unwrap(),
+            SessionNotice::Expired {
+                id: status.id.clone()
+            }
+        );
+        assert!(matches!(
+            notices.try_recv(),
+            Err(broadcast::error::TryRecvError::Closed)
+        ));
+        assert!(sessions.follow(&status.id, expires_at).is_none());
+        assert!(!dir.path().join(SESSION_DIR).join(&status.id).exists());
+
+        let json = serde_json::to_value(SessionNotice::Expired { id: "abc".into() }).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "event": "session.expired", "id": "abc" })
+        );
+    }
+
+    /// Parts cut short on the way are reported missing, and a client that
+    /// resumes from that ends up with the file it sent
+    #[cfg(feature = "chaos")]
+    #[test]
+    fn test_resume_through_chaos() {
+        let chaos = crate::server::chaos::Chaos::new(99);
+        let data: Vec<u8> = (0..64 * 100).map(|i| (i % 251) as u8).collect();
+        let dir = tempfile::tempdir().unwrap();
+        let sessions = UploadSessions::default();
+        let status = sessions
+            .create(dir.path(), "a.bin", data.len() as u64, None, None, 1000)
+            .unwrap();
+
+        let mut missing = status.missing;
+        let mut rounds = 0;
+        while !missing.is_empty() {
+            rounds += 1;
+            for range in missing {
+                for start in range.clone().step_by(100) {
+                    let end = (start + 100).min(range.end) as usize;
+                    let part = &data[start as usize..end];
+                    let part = &part[..chaos.truncate(part.len())];
+                    sessions.write_part(&status.id, start, part, 1001).unwrap();
+                }
+            }
+            missing = sessions.status(&status.id, 1001).unwrap().missing;
+        }
+        assert!(rounds > 1, "nothing was cut short");
+
+        let session = sessions.finish(&status.id, 1002).unwrap();
+        assert_eq!(std::fs::read(&session.path).unwrap(), data);
+        let hash = session.hash.lock().unwrap().clone();
+        assert_eq!(
+            hash.finish(&session.path).unwrap(),
+            hex::encode(sha2::Sha256::digest(&data))
+        );
+    }
+}
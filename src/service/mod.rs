@@ -0,0 +1,292 @@
+//! Headless mode (`justrans --service`): the server without the desktop app,
+//! for machines nobody sits at. Under systemd it reports its state through
+//! `sd_notify` (see `assets/linux/justrans.service`), logs to the journal
+//! and reads its settings again on `SIGHUP`. On Windows it runs under the
+//! Service Control Manager, see [`windows`].
+
+#[cfg(windows)]
+pub mod windows;
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use log::{error, info};
+use settings::Settings;
+use tokio::runtime::{Handle, Runtime};
+use tokio::sync::mpsc;
+
+use crate::cli;
+use crate::config::ConfigData;
+use crate::integrations;
+use crate::server::FileServer;
+use crate::server::{remote, schedule};
+
+/// Name the service is registered under
+pub const SERVICE_NAME: &str = "JusTrans";
+
+/// What the service manager, or a signal, asks of the service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+// Only the Windows service manager pauses and resumes
+#[cfg_attr(not(windows), allow(dead_code))]
+pub enum Control {
+    /// Read the settings file again
+    Reload,
+    /// Stop listening, keeping the stored files
+    Pause,
+    Resume,
+    Stop,
+}
+
+/// State reported to the service manager
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum State {
+    /// Serving or waiting for the schedule, as described
+    Ready(String),
+    Reloading,
+    Paused,
+    Stopping,
+    Failed(String),
+}
+
+/// Run the server until the service is stopped
+pub fn run(portable_dir: Option<PathBuf>) -> anyhow::Result<()> {
+    if logger::journal_attached() {
+        logger::init_journal(log::Level::Info)?;
+    } else {
+        logger::init(&logger::timestamped_log_path()?, log::Level::Info)?;
+    }
+    info!("Starting JusTrans v{} as a service", crate::VERSION);
+    if let Some(dir) = &portable_dir {
+        info!("Portable mode, keeping all data in {:?}", dir);
+    }
+
+    // Started by the Service Control Manager, which runs the service
+    #[cfg(windows)]
+    if windows::dispatch()? {
+        return Ok(());
+    }
+
+    let (sender, controls) = mpsc::unbounded_channel();
+    run_service(controls, Some(sender))
+}
+
+/// Serve on a runtime of its own until told to stop. Signals are turned
+/// into controls when a `signals` sender is given.
+fn run_service(
+    controls: mpsc::UnboundedReceiver<Control>,
+    signals: Option<mpsc::UnboundedSender<Control>>,
+) -> anyhow::Result<()> {
+    let runtime = Runtime::new()?;
+    if let Some(signals) = signals {
+        runtime.spawn(forward_signals(signals));
+    }
+    let result = runtime.block_on(serve(controls));
+    if let Err(e) = &result {
+        error!("Service failed: {}", e);
+        report(State::Failed(e.to_string()));
+    }
+    result
+}
+
+async fn serve(mut controls: mpsc::UnboundedReceiver<Control>) -> anyhow::Result<()> {
+    integrations::init(Handle::current());
+    let file_server = Arc::new(Mutex::new(FileServer::new()?));
+    integrations::serve_control_api(&Handle::current(), file_server.clone())?;
+
+    // A configured schedule decides when the server runs, checking the
+    // settings in use each time
+    let scheduled = schedule::configured(&ConfigData::instance()?.lock().unwrap())?.is_some();
+    if !scheduled {
+        FileServer::set_running(&file_server, true).await?;
+    }
+    tokio::spawn(schedule::run(
+        file_server.clone(),
+        Box::new(|message| info!("{}", message)),
+    ));
+    // Commands from hosts on the portal; `run` logs them already
+    let commands = file_server.lock().unwrap().take_remote_commands();
+    if let Some(commands) = commands {
+        tokio::spawn(remote::run(file_server.clone(), commands, Box::new(|_| {})));
+    }
+    report(State::Ready(describe(&file_server)));
+
+    while let Some(control) = controls.recv().await {
+        match control {
+            Control::Reload => reload(&file_server).await,
+            Control::Pause => {
+                file_server.lock().unwrap().pause();
+                info!("Service paused");
+                report(State::Paused);
+            }
+            Control::Resume => {
+                FileServer::resume(&file_server).await?;
+                info!("Service resumed");
+                report(State::Ready(describe(&file_server)));
+            }
+            Control::Stop => break,
+        }
+    }
+
+    info!("Stopping the service");
+    report(State::Stopping);
+    FileServer::stop(&file_server).await
+}
+
+/// What the service is doing, for `systemctl status`
+fn describe(file_server: &Mutex<FileServer>) -> String {
+    let server_info = file_server.lock().unwrap().get_server_info();
+    if server_info.running {
+        format!("Serving at {}", server_info.urls.join(", "))
+    } else {
+        "Waiting for the scheduled opening".to_string()
+    }
+}
+
+/// Reload the settings on `SIGHUP` and stop on `SIGTERM` or `SIGINT`
+#[cfg(unix)]
+async fn forward_signals(controls: mpsc::UnboundedSender<Control>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let signals = (
+        signal(SignalKind::hangup()),
+        signal(SignalKind::terminate()),
+        signal(SignalKind::interrupt()),
+    );
+    let (Ok(mut hangup), Ok(mut terminate), Ok(mut interrupt)) = signals else {
+        error!("Failed to listen for signals, stopping");
+        let _ = controls.send(Control::Stop);
+        return;
+    };
+    loop {
+        let control = tokio::select! {
+            _ = hangup.recv() => Control::Reload,
+            _ = terminate.recv() => Control::Stop,
+            _ = interrupt.recv() => Control::Stop,
+        };
+        if controls.send(control).is_err() || control == Control::Stop {
+            return;
+        }
+    }
+}
+
+/// Stop on Ctrl+C
+#[cfg(not(unix))]
+async fn forward_signals(controls: mpsc::UnboundedSender<Control>) {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        error!("Failed to listen for Ctrl+C: {}", e);
+    }
+    let _ = controls.send(Control::Stop);
+}
+
+/// Read the settings file again and restart the server with it
+async fn reload(file_server: &Arc<Mutex<FileServer>>) {
+    info!("Reloading the settings from {:?}", settings::path());
+    report(State::Reloading);
+
+    let description = match apply_settings(file_server.clone()).await {
+        Ok(()) => {
+            info!("Settings reloaded");
+            describe(file_server)
+        }
+        Err(e) => {
+            error!("Failed to reload the settings: {}", e);
+            format!("Failed to reload the settings: {}", e)
+        }
+    };
+    report(State::Ready(description));
+}
+
+/// Replace the settings in use with the settings file and restart the
+/// server, keeping the stored files. A file that doesn't parse or names a
+/// bad schedule leaves the settings in use alone.
+async fn apply_settings(file_server: Arc<Mutex<FileServer>>) -> anyhow::Result<()> {
+    let mut config = ConfigData::load(&settings::path())?;
+    cli::override_settings(&mut config)?;
+    schedule::configured(&config)?;
+    *ConfigData::instance()?.lock().unwrap() = config;
+    FileServer::restart(&file_server).await
+}
+
+/// Tell the service manager about the service's state
+fn report(state: State) {
+    #[cfg(unix)]
+    notify(&match state {
+        State::Ready(description) => format!("READY=1\nSTATUS={}", description),
+        State::Reloading => "RELOADING=1\nSTATUS=Reloading the settings".to_string(),
+        State::Paused => "STATUS=Paused".to_string(),
+        State::Stopping => "STOPPING=1".to_string(),
+        State::Failed(message) => format!("STATUS=Failed: {}", message),
+    });
+    #[cfg(windows)]
+    windows::report(&state);
+}
+
+/// Send `state` to systemd, when started by it as a notify service
+#[cfg(unix)]
+fn notify(state: &str) {
+    if let Some(socket) = std::env::var_os("NOTIFY_SOCKET") {
+        if let Err(e) = send_notification(std::path::Path::new(&socket), state) {
+            log::warn!("Failed to notify the service manager: {}", e);
+        }
+    }
+}
+
+/// Send `state` to the `sd_notify` socket at `socket`, which starts with `@`
+/// for an abstract socket
+#[cfg(unix)]
+fn send_notification(socket: &std::path::Path, state: &str) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let sender = UnixDatagram::unbound()?;
+    #[cfg(target_os = "linux")]
+    if let Some(name) = socket.as_os_str().as_bytes().strip_prefix(b"@") {
+        use std::os::linux::net::SocketAddrExt;
+        let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        sender.send_to_addr(state.as_bytes(), &address)?;
+        return Ok(());
+    }
+    sender.send_to(state.as_bytes(), socket)?;
+    Ok(())
+}
+
+/// Register the service with the Service Control Manager
+#[cfg(not(windows))]
+pub fn install() -> anyhow::Result<()> {
+    Err(anyhow::anyhow!(
+        "Services are installed this way on Windows only; on Linux, start from \
+         assets/linux/justrans.service"
+    ))
+}
+
+#[cfg(windows)]
+pub use windows::install;
+
+/// Remove the service from the Service Control Manager
+#[cfg(not(windows))]
+pub fn uninstall() -> anyhow::Result<()> {
+    install()
+}
+
+#[cfg(windows)]
+pub use windows::uninstall;
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn test_send_notification() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        send_notification(&path, "READY=1\nSTATUS=Serving").unwrap();
+        let mut buffer = [0; 64];
+        let read = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..read], b"READY=1\nSTATUS=Serving");
+
+        assert!(send_notification(&dir.path().join("missing"), "READY=1").is_err());
+    }
+}
@@ -0,0 +1,215 @@
+//! The headless server as a Windows service. `justrans install-service`
+//! registers it with the Service Control Manager, which then starts
+//! `justrans --dir <dir> --service` at boot. Stop, pause, continue and
+//! parameter changes (`sc control JusTrans paramchange`, which reloads the
+//! settings) arrive as [`Control`]s.
+
+use std::ffi::{c_void, OsStr};
+use std::os::windows::ffi::OsStrExt;
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+
+use log::{error, info};
+use tokio::sync::mpsc;
+use windows_sys::core::PWSTR;
+use windows_sys::Win32::Foundation::{
+    ERROR_CALL_NOT_IMPLEMENTED, ERROR_FAILED_SERVICE_CONTROLLER_CONNECT,
+    ERROR_SERVICE_SPECIFIC_ERROR, NO_ERROR,
+};
+use windows_sys::Win32::System::Services::{
+    RegisterServiceCtrlHandlerExW, SetServiceStatus, StartServiceCtrlDispatcherW,
+    SERVICE_ACCEPT_PARAMCHANGE, SERVICE_ACCEPT_PAUSE_CONTINUE, SERVICE_ACCEPT_SHUTDOWN,
+    SERVICE_ACCEPT_STOP, SERVICE_CONTROL_CONTINUE, SERVICE_CONTROL_INTERROGATE,
+    SERVICE_CONTROL_PARAMCHANGE, SERVICE_CONTROL_PAUSE, SERVICE_CONTROL_SHUTDOWN,
+    SERVICE_CONTROL_STOP, SERVICE_PAUSED, SERVICE_RUNNING, SERVICE_START_PENDING, SERVICE_STATUS,
+    SERVICE_STATUS_CURRENT_STATE, SERVICE_STATUS_HANDLE, SERVICE_STOPPED, SERVICE_STOP_PENDING,
+    SERVICE_TABLE_ENTRYW, SERVICE_WIN32_OWN_PROCESS,
+};
+
+use super::{run_service, Control, State, SERVICE_NAME};
+use crate::cli;
+
+/// How long the Service Control Manager waits between pending reports
+const WAIT_HINT_MS: u32 = 10_000;
+
+/// Where controls from the Service Control Manager go
+static CONTROLS: OnceLock<mpsc::UnboundedSender<Control>> = OnceLock::new();
+
+/// Handle to report the service's status through, with the checkpoint of
+/// the last pending report
+static STATUS: Mutex<Option<(SERVICE_STATUS_HANDLE, u32)>> = Mutex::new(None);
+
+/// Run as the service when started by the Service Control Manager,
+/// returning once it stopped. Returns `false` when started any other way,
+/// e.g. from a console.
+pub fn dispatch() -> anyhow::Result<bool> {
+    let mut name = wide(SERVICE_NAME);
+    let table = [
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: name.as_mut_ptr(),
+            lpServiceProc: Some(service_main),
+        },
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: std::ptr::null_mut(),
+            lpServiceProc: None,
+        },
+    ];
+    // Blocks until the service stopped
+    if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } != 0 {
+        return Ok(true);
+    }
+    let e = std::io::Error::last_os_error();
+    if e.raw_os_error() == Some(ERROR_FAILED_SERVICE_CONTROLLER_CONNECT as i32) {
+        return Ok(false);
+    }
+    Err(e.into())
+}
+
+unsafe extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+    let name = wide(SERVICE_NAME);
+    let handle =
+        RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(handle_control), std::ptr::null());
+    if handle == 0 {
+        error!(
+            "Failed to register the service control handler: {}",
+            std::io::Error::last_os_error()
+        );
+        return;
+    }
+    *STATUS.lock().unwrap() = Some((handle, 0));
+    set_status(SERVICE_START_PENDING, NO_ERROR);
+
+    let (sender, controls) = mpsc::unbounded_channel();
+    let _ = CONTROLS.set(sender);
+    match run_service(controls, None) {
+        Ok(()) => set_status(SERVICE_STOPPED, NO_ERROR),
+        Err(_) => set_status(SERVICE_STOPPED, ERROR_SERVICE_SPECIFIC_ERROR),
+    }
+}
+
+unsafe extern "system" fn handle_control(
+    control: u32,
+    _event_type: u32,
+    _event_data: *mut c_void,
+    _context: *mut c_void,
+) -> u32 {
+    let control = match control {
+        SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => Control::Stop,
+        SERVICE_CONTROL_PAUSE => Control::Pause,
+        SERVICE_CONTROL_CONTINUE => Control::Resume,
+        SERVICE_CONTROL_PARAMCHANGE => Control::Reload,
+        SERVICE_CONTROL_INTERROGATE => return NO_ERROR,
+        _ => return ERROR_CALL_NOT_IMPLEMENTED,
+    };
+    if let Some(controls) = CONTROLS.get() {
+        let _ = controls.send(control);
+    }
+    NO_ERROR
+}
+
+/// Tell the Service Control Manager about the service's state
+pub fn report(state: &State) {
+    match state {
+        State::Ready(description) => {
+            info!("{}", description);
+            set_status(SERVICE_RUNNING, NO_ERROR);
+        }
+        // Settings are reloaded while running, so the state stays as it is
+        State::Reloading => {}
+        State::Paused => set_status(SERVICE_PAUSED, NO_ERROR),
+        State::Stopping => set_status(SERVICE_STOP_PENDING, NO_ERROR),
+        // Reported as stopped with an error once the service returns
+        State::Failed(_) => {}
+    }
+}
+
+fn set_status(current_state: SERVICE_STATUS_CURRENT_STATE, exit_code: u32) {
+    let mut status = STATUS.lock().unwrap();
+    // Not running under the Service Control Manager
+    let Some((handle, checkpoint)) = status.as_mut() else {
+        return;
+    };
+    let pending = matches!(current_state, SERVICE_START_PENDING | SERVICE_STOP_PENDING);
+    *checkpoint = if pending { *checkpoint + 1 } else { 0 };
+    let controls_accepted = match current_state {
+        SERVICE_RUNNING | SERVICE_PAUSED => {
+            SERVICE_ACCEPT_STOP
+                | SERVICE_ACCEPT_SHUTDOWN
+                | SERVICE_ACCEPT_PAUSE_CONTINUE
+                | SERVICE_ACCEPT_PARAMCHANGE
+        }
+        _ => 0,
+    };
+    let service_status = SERVICE_STATUS {
+        dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+        dwCurrentState: current_state,
+        dwControlsAccepted: controls_accepted,
+        dwWin32ExitCode: exit_code,
+        dwServiceSpecificExitCode: u32::from(exit_code == ERROR_SERVICE_SPECIFIC_ERROR),
+        dwCheckPoint: *checkpoint,
+        dwWaitHint: if pending { WAIT_HINT_MS } else { 0 },
+    };
+    if unsafe { SetServiceStatus(*handle, &service_status) } == 0 {
+        error!(
+            "Failed to report the service status: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+/// Register the service to start at boot with the settings, data
+/// directory and storage override in use now
+pub fn install() -> anyhow::Result<()> {
+    let dir = std::env::current_dir()?;
+    let mut command_line = format!(
+        "\"{}\" --dir \"{}\" --config \"{}\"",
+        std::env::current_exe()?.display(),
+        dir.display(),
+        dir.join(settings::path()).display()
+    );
+    if let Some(storage) = cli::storage_override() {
+        command_line.push_str(&format!(" --storage \"{}\"", storage));
+    }
+    command_line.push_str(" --service");
+
+    sc(&[
+        "create",
+        SERVICE_NAME,
+        "binPath=",
+        &command_line,
+        "start=",
+        "auto",
+        "DisplayName=",
+        SERVICE_NAME,
+    ])?;
+    sc(&[
+        "description",
+        SERVICE_NAME,
+        "Shares files with devices on the local network",
+    ])
+}
+
+/// Stop the service and remove it from the Service Control Manager
+pub fn uninstall() -> anyhow::Result<()> {
+    // Fails when the service isn't running, which is fine
+    let _ = sc(&["stop", SERVICE_NAME]);
+    sc(&["delete", SERVICE_NAME])
+}
+
+/// Run `sc.exe`, failing with its output when it does
+fn sc(args: &[&str]) -> anyhow::Result<()> {
+    let output = Command::new("sc.exe").args(args).output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "sc {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stdout).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// `s` as a NUL-terminated UTF-16 string
+fn wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
@@ -0,0 +1,215 @@
+//! `.justrans` bundles: a small JSON manifest listing share links, for
+//! handing someone a batch of files in one go. Opening a bundle queues every
+//! file it lists for download.
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "title": "Holiday photos",
+//!   "server": "http://192.168.1.5:8080",
+//!   "links": [
+//!     "justrans://share?host=192.168.1.5&file=3f6c...",
+//!     "http://192.168.1.5:8080/api/v1/files/9a1e...",
+//!     "b2c4..."
+//!   ]
+//! }
+//! ```
+//!
+//! Links are `justrans://share` links naming a file, plain download URLs, or
+//! bare one-time tokens redeemed on `server`.
+
+use std::path::{Path, PathBuf};
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use super::link::Link;
+use crate::downloads::{self, DownloadQueue};
+
+/// File extension registered for bundles
+pub const EXTENSION: &str = "justrans";
+
+/// MIME type registered for bundles
+pub const MIME_TYPE: &str = "application/x-justrans";
+
+/// Bumped when the bundle format changes incompatibly
+const FORMAT_VERSION: u32 = 1;
+
+fn default_version() -> u32 {
+    FORMAT_VERSION
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bundle {
+    #[serde(default = "default_version")]
+    pub version: u32,
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Address of the server bare one-time tokens belong to
+    #[serde(default)]
+    pub server: Option<String>,
+    pub links: Vec<String>,
+}
+
+/// Path of the bundle a command line argument names, if it names one. File
+/// managers may pass it as a `file://` URL.
+pub fn bundle_path(arg: &str) -> Option<PathBuf> {
+    let path = if arg.to_lowercase().starts_with("file:") {
+        Url::parse(arg).ok()?.to_file_path().ok()?
+    } else {
+        PathBuf::from(arg)
+    };
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case(EXTENSION))
+        .then_some(path)
+}
+
+impl Bundle {
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let bundle: Bundle = serde_json::from_slice(data)
+            .map_err(|e| anyhow::anyhow!("Not a JusTrans bundle: {}", e))?;
+        if bundle.version > FORMAT_VERSION {
+            return Err(anyhow::anyhow!(
+                "The bundle was made by a newer JusTrans and cannot be opened"
+            ));
+        }
+        Ok(bundle)
+    }
+
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    /// Name to show for the bundle
+    pub fn title(&self, path: &Path) -> String {
+        self.title.clone().unwrap_or_else(|| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        })
+    }
+
+    /// Download URL of one entry of `links`, with the PIN of the room the
+    /// file is in
+    pub fn resolve(&self, link: &str) -> anyhow::Result<(Url, Option<String>)> {
+        let link = link.trim();
+        if Link::is_link(link) {
+            return Link::parse(link)?
+                .download()
+                .unwrap_or_else(|| Err(anyhow::anyhow!("'{}' does not point at a file", link)));
+        }
+        if link.starts_with("http://") || link.starts_with("https://") {
+            return Ok((Url::parse(link)?, None));
+        }
+
+        // Anything else is a one-time token
+        if link.is_empty()
+            || !link
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(anyhow::anyhow!("Invalid link '{}'", link));
+        }
+        let server = self
+            .server
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("Token '{}' needs a server in the bundle", link))?;
+        Ok((Url::parse(server)?.join(&format!("/d/{}", link))?, None))
+    }
+}
+
+/// Queue the files of the bundle at `path` for download, returning a status
+/// message
+pub fn open_bundle(path: &Path, downloads: &DownloadQueue) -> anyhow::Result<String> {
+    let bundle = Bundle::read(path)?;
+    let dir = downloads::destination();
+    let mut queued = 0;
+    let mut invalid = 0;
+    for link in &bundle.links {
+        match bundle.resolve(link) {
+            Ok((url, room_pin)) => {
+                downloads.add_from_room(url, room_pin, dir.clone());
+                queued += 1;
+            }
+            Err(e) => {
+                log::warn!("Skipped bundle entry: {}", e);
+                invalid += 1;
+            }
+        }
+    }
+    log::info!("Queued {} download(s) from bundle {:?}", queued, path);
+
+    let mut message = format!(
+        "Queued {} download(s) from '{}'",
+        queued,
+        bundle.title(path)
+    );
+    if invalid > 0 {
+        message.push_str(&format!("; skipped {} invalid link(s)", invalid));
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_links() {
+        let bundle = Bundle::parse(
+            br#"{"server": "http://10.0.0.2:8080", "links": [
+                "justrans://share?host=10.0.0.2&room=team&pin=1234&file=abc",
+                "http://10.0.0.2:8080/api/v1/files/def",
+                "tok-123",
+                "justrans://share?host=10.0.0.2&room=team",
+                "../etc/passwd"
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(bundle.version, FORMAT_VERSION);
+
+        let urls: Vec<_> = bundle
+            .links
+            .iter()
+            .map(|link| {
+                bundle
+                    .resolve(link)
+                    .ok()
+                    .map(|(url, pin)| (String::from(url), pin))
+            })
+            .collect();
+        assert_eq!(
+            urls,
+            vec![
+                Some((
+                    "http://10.0.0.2:8080/api/v1/rooms/team/files/abc".to_string(),
+                    Some("1234".to_string())
+                )),
+                Some(("http://10.0.0.2:8080/api/v1/files/def".to_string(), None)),
+                Some(("http://10.0.0.2:8080/d/tok-123".to_string(), None)),
+                None,
+                None,
+            ]
+        );
+
+        let serverless = Bundle::parse(br#"{"links": ["tok-123"]}"#).unwrap();
+        assert!(serverless.resolve("tok-123").is_err());
+        assert!(Bundle::parse(br#"{"version": 2, "links": []}"#).is_err());
+    }
+
+    #[test]
+    fn test_bundle_path() {
+        assert_eq!(
+            bundle_path("photos.justrans"),
+            Some(PathBuf::from("photos.justrans"))
+        );
+        assert!(bundle_path("Photos.JUSTRANS").is_some());
+        #[cfg(unix)]
+        assert_eq!(
+            bundle_path("file:///home/me/My%20Photos.justrans"),
+            Some(PathBuf::from("/home/me/My Photos.justrans"))
+        );
+        assert!(bundle_path("photos.zip").is_none());
+        assert!(bundle_path("justrans://pair?host=a").is_none());
+    }
+}
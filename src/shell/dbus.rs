@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use zbus::{connection, fdo, interface};
+
+use crate::server::FileServer;
+
+/// Well-known bus name of the running JusTrans instance
+pub const BUS_NAME: &str = "org.justrans.JusTrans";
+
+/// Object path of the share service
+pub const OBJECT_PATH: &str = "/org/justrans/JusTrans";
+
+/// Callback invoked with a status message after files were shared
+pub type ShareNotifier = Box<dyn Fn(String) + Send + Sync>;
+
+/// D-Bus interface used by file-manager actions to add files to the share
+struct ShareService {
+    file_server: Arc<Mutex<FileServer>>,
+    notify: ShareNotifier,
+}
+
+#[interface(name = "org.justrans.JusTrans1")]
+impl ShareService {
+    /// Share the given absolute paths, returning how many were added
+    fn share_files(&self, paths: Vec<String>) -> fdo::Result<u32> {
+        let file_server = self.file_server.lock().unwrap();
+        let mut shared = 0;
+        let mut errors = Vec::new();
+
+        for path in paths {
+            match file_server.share_local_file(&PathBuf::from(&path)) {
+                Ok(_) => shared += 1,
+                Err(e) => {
+                    log::warn!("D-Bus share of {} failed: {}", path, e);
+                    errors.push(format!("{}: {}", path, e));
+                }
+            }
+        }
+
+        if shared == 0 && !errors.is_empty() {
+            return Err(fdo::Error::InvalidArgs(errors.join("; ")));
+        }
+
+        (self.notify)(format!("Shared {} file(s) from the file manager", shared));
+        Ok(shared)
+    }
+}
+
+/// Register the share service on the session bus and keep it alive
+pub async fn serve(
+    file_server: Arc<Mutex<FileServer>>,
+    notify: ShareNotifier,
+) -> anyhow::Result<()> {
+    let connection = connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(
+            OBJECT_PATH,
+            ShareService {
+                file_server,
+                notify,
+            },
+        )?
+        .build()
+        .await?;
+
+    log::info!("Registered D-Bus service {} at {}", BUS_NAME, OBJECT_PATH);
+
+    // The service lives as long as the connection does
+    tokio::spawn(async move {
+        let _connection = connection;
+        std::future::pending::<()>().await;
+    });
+
+    Ok(())
+}
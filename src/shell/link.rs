@@ -0,0 +1,230 @@
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::Url;
+
+use crate::downloads::peers;
+
+/// URL scheme registered for JusTrans links
+pub const SCHEME: &str = "justrans";
+
+/// Action encoded in a `justrans://` link
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Link {
+    /// `justrans://pair?host=...&port=...&token=...[&id=...]`, where `id` is
+    /// the fingerprint of the instance's identity
+    Pair {
+        host: String,
+        port: u16,
+        token: Option<String>,
+        id: Option<String>,
+    },
+    /// `justrans://share?host=...&port=...[&room=...][&pin=...][&file=...]`
+    Share {
+        host: String,
+        port: u16,
+        room: Option<String>,
+        pin: Option<String>,
+        file: Option<String>,
+    },
+}
+
+impl Link {
+    /// Check whether a command line argument looks like a JusTrans link
+    pub fn is_link(arg: &str) -> bool {
+        arg.to_lowercase().starts_with(&format!("{}:", SCHEME))
+    }
+
+    pub fn parse(link: &str) -> anyhow::Result<Self> {
+        let url = Url::parse(link)?;
+        if url.scheme() != SCHEME {
+            return Err(anyhow::anyhow!("Not a {}:// link", SCHEME));
+        }
+
+        let query = |key: &str| {
+            url.query_pairs()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.into_owned())
+                .filter(|v| !v.is_empty())
+        };
+        let host = query("host").ok_or_else(|| anyhow::anyhow!("Link has no host"))?;
+        let port = match query("port") {
+            Some(port) => port
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid port '{}'", port))?,
+            None => 8080,
+        };
+
+        match url.host_str() {
+            Some("pair") => Ok(Link::Pair {
+                host,
+                port,
+                token: query("token"),
+                id: query("id"),
+            }),
+            Some("share") => Ok(Link::Share {
+                host,
+                port,
+                room: query("room"),
+                pin: query("pin"),
+                file: query("file"),
+            }),
+            Some(other) => Err(anyhow::anyhow!("Unknown link action '{}'", other)),
+            None => Err(anyhow::anyhow!("Link has no action")),
+        }
+    }
+
+    /// Web address of the server the link points at. A room's PIN goes in
+    /// the fragment, which the portal reads and the browser never sends.
+    /// Browsers can't send it to the API either, so a file in a room with
+    /// a PIN is found on the room's page.
+    pub fn web_url(&self) -> anyhow::Result<Url> {
+        let mut url = self.server_url()?;
+        match self {
+            Link::Pair { token, .. } => {
+                if let Some(token) = token {
+                    url.query_pairs_mut().append_pair("token", token);
+                }
+            }
+            Link::Share {
+                room: Some(room),
+                pin: Some(pin),
+                ..
+            } => {
+                url.set_path(&format!("/r/{}", room));
+                url.set_fragment(Some(&format!(
+                    "pin={}",
+                    utf8_percent_encode(pin, NON_ALPHANUMERIC)
+                )));
+            }
+            Link::Share { room, file, .. } => url.set_path(&share_path(room, file)),
+        }
+        Ok(url)
+    }
+
+    /// URL the file of a share link is downloaded from, with the PIN of its
+    /// room to send along
+    pub fn download(&self) -> Option<anyhow::Result<(Url, Option<String>)>> {
+        let Link::Share {
+            room,
+            pin,
+            file: file @ Some(_),
+            ..
+        } = self
+        else {
+            return None;
+        };
+        Some(self.server_url().map(|mut url| {
+            url.set_path(&share_path(room, file));
+            (url, room.as_ref().and(pin.clone()))
+        }))
+    }
+
+    /// Root URL of the server the link points at
+    fn server_url(&self) -> anyhow::Result<Url> {
+        let (host, port) = match self {
+            Link::Pair { host, port, .. } | Link::Share { host, port, .. } => (host, port),
+        };
+        let host = if host.contains(':') && !host.starts_with('[') {
+            format!("[{}]", host)
+        } else {
+            host.clone()
+        };
+        Ok(Url::parse(&format!("http://{}:{}/", host, port))?)
+    }
+
+    /// Short message suitable for the status bar
+    pub fn description(&self) -> String {
+        match self {
+            Link::Pair { host, port, .. } => format!("Pairing with {}:{}", host, port),
+            Link::Share {
+                host, port, room, ..
+            } => match room {
+                Some(room) => format!("Opening room '{}' on {}:{}", room, host, port),
+                None => format!("Opening share on {}:{}", host, port),
+            },
+        }
+    }
+}
+
+/// Path of the page or file a share link points at
+fn share_path(room: &Option<String>, file: &Option<String>) -> String {
+    match (room, file) {
+        (Some(room), Some(file)) => format!("/api/v1/rooms/{}/files/{}", room, file),
+        (Some(room), None) => format!("/r/{}", room),
+        (None, Some(file)) => format!("/api/v1/files/{}", file),
+        (None, None) => "/".to_string(),
+    }
+}
+
+/// Open a `justrans://` link in the default browser, returning a status
+/// message. Paired instances are remembered for the Transfers panel.
+pub fn open_link(link: &str) -> anyhow::Result<String> {
+    let link = Link::parse(link)?;
+    if let Err(e) = peers::remember(&link) {
+        log::warn!("Failed to remember paired instance: {}", e);
+    }
+    open::that(link.web_url()?.as_str())?;
+    Ok(link.description())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pair_link() {
+        let link = Link::parse("justrans://pair?host=192.168.1.5&port=9000&token=abc").unwrap();
+        assert_eq!(
+            link,
+            Link::Pair {
+                host: "192.168.1.5".to_string(),
+                port: 9000,
+                token: Some("abc".to_string()),
+                id: None,
+            }
+        );
+        assert_eq!(
+            link.web_url().unwrap().as_str(),
+            "http://192.168.1.5:9000/?token=abc"
+        );
+    }
+
+    #[test]
+    fn test_share_link_urls() {
+        let room = Link::parse("justrans://share?host=10.0.0.2&room=team&pin=1234").unwrap();
+        assert_eq!(
+            room.web_url().unwrap().as_str(),
+            "http://10.0.0.2:8080/r/team#pin=1234"
+        );
+        let spaced =
+            Link::parse("justrans://share?host=10.0.0.2&room=team&pin=12+34&file=abc").unwrap();
+        assert_eq!(
+            spaced.web_url().unwrap().as_str(),
+            "http://10.0.0.2:8080/r/team#pin=12%2034"
+        );
+        let (url, pin) = spaced.download().unwrap().unwrap();
+        assert_eq!(
+            (url.as_str(), pin.as_deref()),
+            (
+                "http://10.0.0.2:8080/api/v1/rooms/team/files/abc",
+                Some("12 34")
+            )
+        );
+        assert!(room.download().is_none());
+
+        let file = Link::parse("justrans://share?host=10.0.0.2&port=8080&file=abc-123").unwrap();
+        assert_eq!(
+            file.web_url().unwrap().as_str(),
+            "http://10.0.0.2:8080/api/v1/files/abc-123"
+        );
+    }
+
+    #[test]
+    fn test_invalid_links() {
+        assert!(Link::parse("https://pair?host=a").is_err());
+        assert!(Link::parse("justrans://pair").is_err());
+        assert!(Link::parse("justrans://unknown?host=a").is_err());
+        assert!(Link::parse("justrans://pair?host=a&port=x").is_err());
+        assert!(Link::is_link("JusTrans://pair?host=a"));
+        assert!(!Link::is_link("file.txt"));
+    }
+}
@@ -0,0 +1,5 @@
+pub mod bundle;
+#[cfg(target_os = "linux")]
+pub mod dbus;
+pub mod link;
+pub mod register;
@@ -0,0 +1,222 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::bundle;
+use super::link::SCHEME;
+
+/// Name shown in the OS "Send To" / Services menus
+const MENU_NAME: &str = "Send to JusTrans";
+
+fn home_dir() -> anyhow::Result<PathBuf> {
+    let var = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow::anyhow!("{} is not set", var))
+}
+
+fn write_file(path: &Path, contents: &str, executable: bool) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, contents)?;
+
+    #[cfg(unix)]
+    if executable {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+    }
+    #[cfg(not(unix))]
+    let _ = executable;
+
+    log::info!("Installed shell integration: {:?}", path);
+    Ok(())
+}
+
+/// Windows Explorer: a command file in the user's SendTo folder
+fn register_windows(exe: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let appdata = std::env::var_os("APPDATA")
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow::anyhow!("APPDATA is not set"))?;
+    let path = appdata
+        .join("Microsoft")
+        .join("Windows")
+        .join("SendTo")
+        .join(format!("{}.cmd", MENU_NAME));
+    write_file(
+        &path,
+        &format!("@echo off\r\nstart \"\" \"{}\" share %*\r\n", exe),
+        false,
+    )?;
+    Ok(vec![path])
+}
+
+/// Write registry values with `reg add`
+fn reg_add(entries: &[(&str, &[&str])]) -> anyhow::Result<()> {
+    for &(key, args) in entries {
+        let status = Command::new("reg")
+            .arg("add")
+            .arg(key)
+            .args(args)
+            .arg("/f")
+            .status()?;
+        if !status.success() {
+            return Err(anyhow::anyhow!("reg add {} exited with {}", key, status));
+        }
+    }
+    Ok(())
+}
+
+/// Windows: `justrans://` handler under HKCU\Software\Classes
+fn register_windows_scheme(exe: &str) -> anyhow::Result<PathBuf> {
+    let key = format!(r"HKCU\Software\Classes\{}", SCHEME);
+    let command = format!("\"{}\" \"%1\"", exe);
+    reg_add(&[
+        (&key, &["/ve", "/d", "URL:JusTrans Link"]),
+        (&key, &["/v", "URL Protocol", "/d", ""]),
+        (
+            &format!(r"{}\shell\open\command", key),
+            &["/ve", "/d", &command],
+        ),
+    ])?;
+    log::info!("Registered {}:// handler at {}", SCHEME, key);
+    Ok(PathBuf::from(key))
+}
+
+/// Windows: open `.justrans` bundles with this executable
+fn register_windows_bundle(exe: &str) -> anyhow::Result<PathBuf> {
+    let class = r"HKCU\Software\Classes\JusTrans.Bundle";
+    let extension = format!(r"HKCU\Software\Classes\.{}", bundle::EXTENSION);
+    let command = format!("\"{}\" \"%1\"", exe);
+    reg_add(&[
+        (&extension, &["/ve", "/d", "JusTrans.Bundle"]),
+        (&extension, &["/v", "Content Type", "/d", bundle::MIME_TYPE]),
+        (class, &["/ve", "/d", "JusTrans Download Bundle"]),
+        (
+            &format!(r"{}\shell\open\command", class),
+            &["/ve", "/d", &command],
+        ),
+    ])?;
+    log::info!("Registered .{} files at {}", bundle::EXTENSION, extension);
+    Ok(PathBuf::from(extension))
+}
+
+/// macOS: an Automator Quick Action listed under Services
+fn register_macos(exe: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let bundle = home_dir()?
+        .join("Library")
+        .join("Services")
+        .join(format!("{}.workflow", MENU_NAME));
+    let contents = bundle.join("Contents");
+
+    let info = include_str!("../../assets/macos/Info.plist").replace("{{MENU_NAME}}", MENU_NAME);
+    let command = format!("\"{}\" share \"$@\"", exe)
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    let workflow =
+        include_str!("../../assets/macos/document.wflow").replace("{{COMMAND}}", &command);
+
+    write_file(&contents.join("Info.plist"), &info, false)?;
+    write_file(&contents.join("document.wflow"), &workflow, false)?;
+    Ok(vec![bundle])
+}
+
+/// Linux: Dolphin service menu and Nautilus script
+fn register_linux(exe: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let data_dir = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .map(Ok)
+        .unwrap_or_else(|| home_dir().map(|h| h.join(".local").join("share")))?;
+
+    let service_menu = data_dir
+        .join("kio")
+        .join("servicemenus")
+        .join("justrans-servicemenu.desktop");
+    write_file(
+        &service_menu,
+        &format!(
+            "[Desktop Entry]\nType=Service\nMimeType=application/octet-stream;\nX-KDE-ServiceTypes=KonqPopupMenu/Plugin\nActions=sendToJusTrans;\n\n[Desktop Action sendToJusTrans]\nName={}\nIcon=justrans\nExec=\"{}\" share %F\n",
+            MENU_NAME, exe
+        ),
+        true,
+    )?;
+
+    let nautilus_script = data_dir.join("nautilus").join("scripts").join(MENU_NAME);
+    write_file(
+        &nautilus_script,
+        &format!("#!/bin/sh\nexec \"{}\" share \"$@\"\n", exe),
+        true,
+    )?;
+
+    Ok(vec![service_menu, nautilus_script])
+}
+
+/// Run a helper whose failure only degrades the integration
+fn run_optional(program: &str, args: &[&str]) {
+    match Command::new(program).args(args).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => log::warn!("{} exited with {}", program, status),
+        Err(e) => log::warn!("Could not run {}: {}", program, e),
+    }
+}
+
+/// Linux: desktop entry claiming `x-scheme-handler/justrans` and `.justrans`
+/// bundles, whose MIME type is declared through shared-mime-info
+fn register_linux_scheme(exe: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let data_dir = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .map(Ok)
+        .unwrap_or_else(|| home_dir().map(|h| h.join(".local").join("share")))?;
+
+    let mime_dir = data_dir.join("mime");
+    let mime_info = mime_dir.join("packages").join("justrans.xml");
+    write_file(
+        &mime_info,
+        &format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<mime-info xmlns=\"http://www.freedesktop.org/standards/shared-mime-info\">\n  <mime-type type=\"{}\">\n    <comment>JusTrans download bundle</comment>\n    <sub-class-of type=\"application/json\"/>\n    <glob pattern=\"*.{}\"/>\n  </mime-type>\n</mime-info>\n",
+            bundle::MIME_TYPE,
+            bundle::EXTENSION
+        ),
+        false,
+    )?;
+    run_optional("update-mime-database", &[&mime_dir.display().to_string()]);
+
+    let entry = "justrans-link.desktop";
+    let path = data_dir.join("applications").join(entry);
+    let scheme_mime = format!("x-scheme-handler/{}", SCHEME);
+    write_file(
+        &path,
+        &format!(
+            "[Desktop Entry]\nType=Application\nName=JusTrans\nIcon=justrans\nExec=\"{}\" %u\nTerminal=false\nNoDisplay=true\nMimeType={};{};\n",
+            exe, scheme_mime, bundle::MIME_TYPE
+        ),
+        false,
+    )?;
+    run_optional(
+        "xdg-mime",
+        &["default", entry, &scheme_mime, bundle::MIME_TYPE],
+    );
+    Ok(vec![path, mime_info])
+}
+
+/// Install the OS-native "Send To" shims, the `justrans://` handler and the
+/// `.justrans` file association pointing at this executable.
+///
+/// On macOS the URL scheme and the bundle type are declared by the app
+/// bundle instead (see `assets/macos/url-types.plist` and
+/// `assets/macos/document-types.plist`).
+pub fn register() -> anyhow::Result<Vec<PathBuf>> {
+    let exe = std::env::current_exe()?.display().to_string();
+    if cfg!(target_os = "windows") {
+        let mut installed = register_windows(&exe)?;
+        installed.push(register_windows_scheme(&exe)?);
+        installed.push(register_windows_bundle(&exe)?);
+        Ok(installed)
+    } else if cfg!(target_os = "macos") {
+        register_macos(&exe)
+    } else {
+        let mut installed = register_linux(&exe)?;
+        installed.extend(register_linux_scheme(&exe)?);
+        Ok(installed)
+    }
+}
@@ -0,0 +1,203 @@
+//! Status bar messages. Notable server events are turned into status lines
+//! here, so the bar reports them the same way whichever server or callback
+//! they come from.
+
+use crate::integrations::ServerEvent;
+use crate::server::file_server::ServerInfo;
+use crate::server::stats::StatsSnapshot;
+
+/// How a status line is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// A line for the status bar
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Status {
+    pub fn info(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Info,
+            message: message.into(),
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+}
+
+/// Follows the server events for the status bar
+#[derive(Debug, Default)]
+pub struct StatusFeed {
+    /// Whether a client connected since the server started
+    client_seen: bool,
+}
+
+impl StatusFeed {
+    /// Status line for `event`, if it is worth one. `server_info` is the
+    /// default server's, whose network overlay is named when it starts.
+    pub fn update(&mut self, event: &ServerEvent, server_info: &ServerInfo) -> Option<Status> {
+        match event {
+            ServerEvent::ServerStarted { url } => {
+                self.client_seen = false;
+                let network = server_info
+                    .network
+                    .as_ref()
+                    .filter(|_| server_info.url == *url);
+                Some(Status::info(match network {
+                    Some(network) => format!(
+                        "Server running at {} with '{}' network settings - QR code ready",
+                        url, network
+                    ),
+                    None => format!("Server running at {} - QR code ready", url),
+                }))
+            }
+            ServerEvent::ServerStopped { url } => {
+                Some(Status::info(format!("Server at {} stopped", url)))
+            }
+            ServerEvent::ServerError { message } => Some(Status::error(message.clone())),
+            ServerEvent::ClientConnected { address } if !self.client_seen => {
+                self.client_seen = true;
+                Some(Status::info(format!(
+                    "First device connected from {}",
+                    address
+                )))
+            }
+            ServerEvent::FileCorrupted { name, .. } => Some(Status::warning(format!(
+                "'{}' no longer matches its checksum",
+                name
+            ))),
+            ServerEvent::FileQuarantined { name, .. } => Some(Status::warning(format!(
+                "'{}' is a program or script and was quarantined",
+                name
+            ))),
+            _ => None,
+        }
+    }
+}
+
+/// Counters of a running server for the header, e.g. "Up 2 h 5 min · 1
+/// device · 3.4 MB in · 120.0 MB out"
+pub fn server_stats(stats: &StatsSnapshot) -> String {
+    let mut parts = vec![format!("Up {}", humanize::duration(stats.uptime_secs))];
+    parts.push(match stats.devices {
+        1 => "1 device".to_string(),
+        devices => format!("{} devices", devices),
+    });
+    if stats.active_transfers > 0 {
+        parts.push(match stats.active_transfers {
+            1 => "1 transfer".to_string(),
+            transfers => format!("{} transfers", transfers),
+        });
+    }
+    parts.push(format!("{} in", humanize::size(stats.bytes_in)));
+    parts.push(format!("{} out", humanize::size(stats.bytes_out)));
+    if stats.memory.peak_bytes > 0 {
+        parts.push(format!(
+            "{} buffered (peak {})",
+            humanize::size(stats.memory.buffered_bytes),
+            humanize::size(stats.memory.peak_bytes)
+        ));
+    }
+    parts.join(" · ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::memory::MemoryUsage;
+
+    fn server_info(network: Option<&str>) -> ServerInfo {
+        ServerInfo {
+            url: "http://192.168.1.10:8080".to_string(),
+            urls: vec!["http://192.168.1.10:8080".to_string()],
+            ip: "192.168.1.10".to_string(),
+            port: 8080,
+            running: true,
+            network: network.map(str::to_string),
+            stats: StatsSnapshot::default(),
+        }
+    }
+
+    #[test]
+    fn test_status_feed() {
+        let mut feed = StatusFeed::default();
+        let info = server_info(Some("office"));
+        let started = ServerEvent::ServerStarted {
+            url: info.url.clone(),
+        };
+        let status = feed.update(&started, &info).unwrap();
+        assert_eq!(status.severity, Severity::Info);
+        assert!(status.message.contains("'office' network"));
+
+        // Only the first client is worth a note
+        let connected = ServerEvent::ClientConnected {
+            address: "192.168.1.20".to_string(),
+        };
+        assert_eq!(
+            feed.update(&connected, &info),
+            Some(Status::info("First device connected from 192.168.1.20"))
+        );
+        assert_eq!(feed.update(&connected, &info), None);
+        feed.update(&started, &info);
+        assert!(feed.update(&connected, &info).is_some());
+
+        let error = ServerEvent::ServerError {
+            message: "Failed to accept connections".to_string(),
+        };
+        assert_eq!(
+            feed.update(&error, &info).map(|status| status.severity),
+            Some(Severity::Error)
+        );
+        let progress = ServerEvent::TransferProgress { id: 1, bytes: 10 };
+        assert_eq!(feed.update(&progress, &info), None);
+    }
+
+    #[test]
+    fn test_server_stats() {
+        let mut stats = StatsSnapshot {
+            started_at: Some(1000),
+            uptime_secs: 125,
+            devices: 1,
+            active_transfers: 0,
+            bytes_in: 300,
+            bytes_out: 0,
+            memory: MemoryUsage::default(),
+        };
+        assert_eq!(
+            server_stats(&stats),
+            "Up 2 min · 1 device · 300 B in · 0 B out"
+        );
+        stats.devices = 3;
+        stats.active_transfers = 2;
+        assert_eq!(
+            server_stats(&stats),
+            "Up 2 min · 3 devices · 2 transfers · 300 B in · 0 B out"
+        );
+        stats.memory.buffered_bytes = 64 * 1024;
+        stats.memory.peak_bytes = 6 * 1024 * 1024;
+        assert!(server_stats(&stats).ends_with(&format!(
+            "0 B out · {} buffered (peak {})",
+            humanize::size(64 * 1024),
+            humanize::size(6 * 1024 * 1024)
+        )));
+    }
+}
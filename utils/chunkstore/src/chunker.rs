@@ -0,0 +1,187 @@
+use std::io::{self, Read};
+
+/// Random values for each byte, mixed into the rolling hash
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    // splitmix64, so the table is the same in every build
+    let mut table = [0; 256];
+    let mut state: u64 = 0x4a75_7354_7261_6e73;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Splits data into chunks at content-defined boundaries using a gear
+/// rolling hash over the last 64 bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunker {
+    min_size: usize,
+    max_size: usize,
+    mask: u64,
+}
+
+impl Default for Chunker {
+    /// About 1 MB chunks, suited to large files such as VM images
+    fn default() -> Self {
+        Self::new(256 * 1024, 1024 * 1024, 4 * 1024 * 1024)
+    }
+}
+
+impl Chunker {
+    /// Chunks of `avg_size` on average, never smaller than `min_size`
+    /// (except at the end) or larger than `max_size`
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> Self {
+        assert!(0 < min_size && min_size <= avg_size && avg_size <= max_size);
+        // A boundary is where the top bits of the hash are all zero
+        let bits = avg_size.next_power_of_two().trailing_zeros();
+        Self {
+            min_size,
+            max_size,
+            mask: !0u64 << (64 - bits),
+        }
+    }
+
+    /// Length of the first chunk of `data`. Data shorter than the maximum
+    /// chunk size is taken to be the end of the input.
+    pub fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+        let end = data.len().min(self.max_size);
+        let mut hash: u64 = 0;
+        for (i, &byte) in data[..end].iter().enumerate().skip(self.min_size) {
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+            if hash & self.mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+
+    /// Iterate over the chunks of `reader`
+    pub fn chunks<R: Read>(&self, reader: R) -> Chunks<R> {
+        Chunks {
+            chunker: *self,
+            reader,
+            buffer: Vec::with_capacity(self.max_size),
+            eof: false,
+        }
+    }
+}
+
+/// Chunks read from a reader, see [`Chunker::chunks`]
+pub struct Chunks<R> {
+    chunker: Chunker,
+    reader: R,
+    buffer: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> Chunks<R> {
+    /// Read until a full maximum-size chunk is buffered or the input ends
+    fn fill(&mut self) -> io::Result<()> {
+        let mut block = [0; 64 * 1024];
+        while !self.eof && self.buffer.len() < self.chunker.max_size {
+            let wanted = (self.chunker.max_size - self.buffer.len()).min(block.len());
+            match self.reader.read(&mut block[..wanted]) {
+                Ok(0) => self.eof = true,
+                Ok(n) => self.buffer.extend_from_slice(&block[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for Chunks<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.fill() {
+            return Some(Err(e));
+        }
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let len = self.chunker.cut(&self.buffer);
+        let rest = self.buffer.split_off(len);
+        Some(Ok(std::mem::replace(&mut self.buffer, rest)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChunkHash, Manifest};
+
+    /// Deterministic pseudo-random bytes
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn small_chunker() -> Chunker {
+        Chunker::new(2 * 1024, 8 * 1024, 32 * 1024)
+    }
+
+    #[test]
+    fn test_chunks_cover_input() {
+        let data = noise(500 * 1024, 1);
+        let chunks: Vec<Vec<u8>> = small_chunker()
+            .chunks(&data[..])
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(chunks.concat(), data);
+        assert!(chunks.len() > 20);
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() >= 2 * 1024 && chunk.len() <= 32 * 1024);
+        }
+    }
+
+    #[test]
+    fn test_edit_changes_few_chunks() {
+        let original = noise(1024 * 1024, 2);
+        let mut edited = original.clone();
+        // Insert a few bytes in the middle, shifting everything after them
+        edited.splice(400_000..400_000, b"inserted".iter().copied());
+
+        let chunker = small_chunker();
+        let before = Manifest::from_reader(&chunker, &original[..]).unwrap();
+        let after = Manifest::from_reader(&chunker, &edited[..]).unwrap();
+        assert_eq!(after.size, original.len() as u64 + 8);
+
+        let changed: Vec<&ChunkHash> = after
+            .chunks
+            .iter()
+            .filter(|hash| !before.chunks.contains(hash))
+            .collect();
+        assert!(
+            changed.len() <= 2,
+            "{} of {} chunks changed",
+            changed.len(),
+            after.chunks.len()
+        );
+    }
+
+    #[test]
+    fn test_empty_input() {
+        assert_eq!(small_chunker().chunks(&[][..]).count(), 0);
+    }
+}
@@ -0,0 +1,79 @@
+//! Content-addressable storage of file chunks.
+//!
+//! Files are cut into chunks at content-defined boundaries by a rolling
+//! hash, so inserting or changing a few bytes only changes the chunks
+//! around the edit. Chunks are stored by their SHA-256, which lets a sender
+//! skip every chunk the receiver already has from an earlier version.
+
+mod chunker;
+mod store;
+
+use std::fmt;
+use std::str::FromStr;
+
+use sha2::{Digest, Sha256};
+
+pub use chunker::{Chunker, Chunks};
+pub use store::ChunkStore;
+
+/// SHA-256 of a chunk's contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChunkHash([u8; 32]);
+
+impl ChunkHash {
+    pub fn of(data: &[u8]) -> Self {
+        Self(Sha256::digest(data).into())
+    }
+}
+
+impl fmt::Display for ChunkHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl FromStr for ChunkHash {
+    type Err = hex::FromHexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut hash = [0; 32];
+        hex::decode_to_slice(s, &mut hash)?;
+        Ok(Self(hash))
+    }
+}
+
+/// The chunks a file is made of, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub chunks: Vec<ChunkHash>,
+    pub size: u64,
+}
+
+impl Manifest {
+    /// Chunk a file without storing it, e.g. on the sending side
+    pub fn from_reader<R: std::io::Read>(chunker: &Chunker, reader: R) -> std::io::Result<Self> {
+        let mut manifest = Self::default();
+        for chunk in chunker.chunks(reader) {
+            let chunk = chunk?;
+            manifest.size += chunk.len() as u64;
+            manifest.chunks.push(ChunkHash::of(&chunk));
+        }
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_hash_round_trip() {
+        let hash = ChunkHash::of(b"abc");
+        assert_eq!(
+            hash.to_string(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(hash.to_string().parse::<ChunkHash>().unwrap(), hash);
+        assert!("xyz".parse::<ChunkHash>().is_err());
+    }
+}
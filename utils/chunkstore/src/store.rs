@@ -0,0 +1,194 @@
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use crate::{ChunkHash, Chunker, Manifest};
+
+/// Distinguishes temporary files of concurrent writers
+static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+/// Chunks kept on disk under `<dir>/<first two hex digits>/<hash>`
+#[derive(Debug, Clone)]
+pub struct ChunkStore {
+    dir: PathBuf,
+}
+
+impl ChunkStore {
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, hash: &ChunkHash) -> PathBuf {
+        let name = hash.to_string();
+        self.dir.join(&name[..2]).join(name)
+    }
+
+    pub fn contains(&self, hash: &ChunkHash) -> bool {
+        self.path(hash).is_file()
+    }
+
+    /// The hashes in `hashes` that are not stored yet, without duplicates
+    pub fn missing(&self, hashes: &[ChunkHash]) -> Vec<ChunkHash> {
+        let mut missing: Vec<ChunkHash> = Vec::new();
+        for hash in hashes {
+            if !missing.contains(hash) && !self.contains(hash) {
+                missing.push(*hash);
+            }
+        }
+        missing
+    }
+
+    /// Store a chunk and return its hash; storing it again is a no-op
+    pub fn put(&self, data: &[u8]) -> io::Result<ChunkHash> {
+        let hash = ChunkHash::of(data);
+        let path = self.path(&hash);
+        if path.is_file() {
+            return Ok(hash);
+        }
+
+        // Write next to the target and rename, so readers never see half a chunk
+        let dir = path.parent().expect("chunk path has a parent");
+        std::fs::create_dir_all(dir)?;
+        let temp = dir.join(format!(
+            ".{}-{}.tmp",
+            std::process::id(),
+            NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+        ));
+        let result = std::fs::write(&temp, data).and_then(|_| std::fs::rename(&temp, &path));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp);
+        }
+        result.map(|_| hash)
+    }
+
+    /// Store a chunk received from elsewhere, checking it matches `expected`
+    pub fn put_verified(&self, expected: &ChunkHash, data: &[u8]) -> io::Result<()> {
+        if ChunkHash::of(data) != *expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("chunk does not match hash {}", expected),
+            ));
+        }
+        self.put(data).map(|_| ())
+    }
+
+    pub fn get(&self, hash: &ChunkHash) -> io::Result<Vec<u8>> {
+        std::fs::read(self.path(hash))
+    }
+
+    /// Chunk a file and store all of its chunks
+    pub fn ingest<R: Read>(&self, chunker: &Chunker, reader: R) -> io::Result<Manifest> {
+        let mut manifest = Manifest::default();
+        for chunk in chunker.chunks(reader) {
+            let chunk = chunk?;
+            manifest.size += chunk.len() as u64;
+            manifest.chunks.push(self.put(&chunk)?);
+        }
+        Ok(manifest)
+    }
+
+    /// Write the chunks of `manifest` to `writer` in order, returning the
+    /// number of bytes written. Fails if a chunk is missing.
+    pub fn assemble<W: Write>(&self, manifest: &Manifest, mut writer: W) -> io::Result<u64> {
+        let mut written = 0;
+        for hash in &manifest.chunks {
+            let chunk = self.get(hash).map_err(|e| {
+                io::Error::new(e.kind(), format!("chunk {} is not available: {}", hash, e))
+            })?;
+            writer.write_all(&chunk)?;
+            written += chunk.len() as u64;
+        }
+        writer.flush()?;
+        Ok(written)
+    }
+
+    /// Delete chunks last written before `cutoff`, returning how many
+    /// chunks and bytes were removed
+    pub fn remove_older_than(&self, cutoff: SystemTime) -> io::Result<(usize, u64)> {
+        let mut removed = (0, 0);
+        for prefix in std::fs::read_dir(&self.dir)? {
+            let prefix = prefix?.path();
+            if !prefix.is_dir() {
+                continue;
+            }
+            for entry in std::fs::read_dir(&prefix)? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                if metadata.modified()? < cutoff {
+                    std::fs::remove_file(entry.path())?;
+                    removed.0 += 1;
+                    removed.1 += metadata.len();
+                }
+            }
+            let _ = std::fs::remove_dir(&prefix);
+        }
+        Ok(removed)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_put_and_assemble() {
+        let dir = tempdir().unwrap();
+        let store = ChunkStore::open(dir.path().join("chunks")).unwrap();
+        let chunker = Chunker::new(1024, 4096, 16 * 1024);
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+
+        let manifest = store.ingest(&chunker, &data[..]).unwrap();
+        assert!(store.missing(&manifest.chunks).is_empty());
+
+        let mut assembled = Vec::new();
+        assert_eq!(
+            store.assemble(&manifest, &mut assembled).unwrap(),
+            data.len() as u64
+        );
+        assert_eq!(assembled, data);
+    }
+
+    #[test]
+    fn test_put_verified_rejects_mismatch() {
+        let dir = tempdir().unwrap();
+        let store = ChunkStore::open(dir.path()).unwrap();
+        let hash = ChunkHash::of(b"hello");
+
+        assert_eq!(store.missing(&[hash, hash]), vec![hash]);
+        assert!(store.put_verified(&hash, b"hellO").is_err());
+        store.put_verified(&hash, b"hello").unwrap();
+        assert!(store.contains(&hash));
+        assert_eq!(store.get(&hash).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_assemble_missing_chunk() {
+        let dir = tempdir().unwrap();
+        let store = ChunkStore::open(dir.path()).unwrap();
+        let manifest = Manifest {
+            chunks: vec![ChunkHash::of(b"gone")],
+            size: 4,
+        };
+        assert!(store.assemble(&manifest, io::sink()).is_err());
+    }
+
+    #[test]
+    fn test_remove_older_than() {
+        let dir = tempdir().unwrap();
+        let store = ChunkStore::open(dir.path()).unwrap();
+        let hash = store.put(b"old chunk").unwrap();
+
+        let future = SystemTime::now() + Duration::from_secs(60);
+        assert_eq!(store.remove_older_than(future).unwrap(), (1, 9));
+        assert!(!store.contains(&hash));
+    }
+}
@@ -0,0 +1,149 @@
+use once_cell::sync::Lazy;
+
+/// Units for byte counts, in steps of 1024
+const SIZE_UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+
+/// Languages that write a comma as the decimal separator
+const COMMA_LANGUAGES: &[&str] = &[
+    "bg", "ca", "cs", "da", "de", "el", "es", "et", "fi", "fr", "hr", "hu", "id", "it", "lt", "lv",
+    "nb", "nl", "nn", "no", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr", "uk", "vi",
+];
+
+static CURRENT: Lazy<Locale> = Lazy::new(|| {
+    sys_locale::get_locale()
+        .map(|tag| Locale::from_tag(&tag))
+        .unwrap_or_default()
+});
+
+/// Number formatting conventions of a locale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale {
+    pub decimal_separator: char,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self {
+            decimal_separator: '.',
+        }
+    }
+}
+
+impl Locale {
+    /// Conventions for a BCP 47 or POSIX locale tag such as `de-DE` or `fr_FR.UTF-8`
+    pub fn from_tag(tag: &str) -> Self {
+        let language = tag
+            .split(['-', '_', '.'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if COMMA_LANGUAGES.contains(&language.as_str()) {
+            Self {
+                decimal_separator: ',',
+            }
+        } else {
+            Self::default()
+        }
+    }
+
+    /// Locale of the user running the application
+    pub fn current() -> Self {
+        *CURRENT
+    }
+
+    /// Format a byte count, e.g. "1.5 MB" or "1,5 MB"
+    pub fn format_size(&self, bytes: u64) -> String {
+        let mut size = bytes as f64;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < SIZE_UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        if unit == 0 {
+            return format!("{} {}", bytes, SIZE_UNITS[0]);
+        }
+
+        let number = format!("{:.1}", size).replace('.', &self.decimal_separator.to_string());
+        format!("{} {}", number, SIZE_UNITS[unit])
+    }
+}
+
+/// Format a byte count in the user's locale
+pub fn size(bytes: u64) -> String {
+    Locale::current().format_size(bytes)
+}
+
+/// Describe how long ago something happened, e.g. "3 min ago"
+pub fn relative_time(seconds_ago: u64) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+
+    match seconds_ago {
+        s if s < MINUTE => "just now".to_string(),
+        s if s < HOUR => format!("{} min ago", s / MINUTE),
+        s if s < DAY => format!("{} h ago", s / HOUR),
+        s if s < 2 * DAY => "yesterday".to_string(),
+        s => format!("{} days ago", s / DAY),
+    }
+}
+
+/// Describe a length of time, e.g. "45 s" or "2 h 5 min"
+pub fn duration(seconds: u64) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+
+    match seconds {
+        s if s < MINUTE => format!("{} s", s),
+        s if s < HOUR => format!("{} min", s / MINUTE),
+        s if s < DAY => format!("{} h {} min", s / HOUR, s % HOUR / MINUTE),
+        s => format!("{} d {} h", s / DAY, s % DAY / HOUR),
+    }
+}
+
+/// Describe a Unix timestamp relative to `now`; future times count as now
+pub fn since(timestamp: u64, now: u64) -> String {
+    relative_time(now.saturating_sub(timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locale_from_tag() {
+        assert_eq!(Locale::from_tag("de-DE").decimal_separator, ',');
+        assert_eq!(Locale::from_tag("fr_FR.UTF-8").decimal_separator, ',');
+        assert_eq!(Locale::from_tag("en-US").decimal_separator, '.');
+        assert_eq!(Locale::from_tag("").decimal_separator, '.');
+    }
+
+    #[test]
+    fn test_format_size() {
+        let english = Locale::from_tag("en");
+        let german = Locale::from_tag("de");
+        assert_eq!(english.format_size(512), "512 B");
+        assert_eq!(english.format_size(1536), "1.5 KB");
+        assert_eq!(german.format_size(1536), "1,5 KB");
+        assert_eq!(german.format_size(5 * 1024 * 1024 * 1024), "5,0 GB");
+    }
+
+    #[test]
+    fn test_relative_time() {
+        assert_eq!(relative_time(5), "just now");
+        assert_eq!(relative_time(3 * 60 + 10), "3 min ago");
+        assert_eq!(relative_time(2 * 60 * 60), "2 h ago");
+        assert_eq!(relative_time(30 * 60 * 60), "yesterday");
+        assert_eq!(relative_time(5 * 24 * 60 * 60), "5 days ago");
+        assert_eq!(since(2000, 1000), "just now");
+    }
+
+    #[test]
+    fn test_duration() {
+        assert_eq!(duration(45), "45 s");
+        assert_eq!(duration(3 * 60 + 10), "3 min");
+        assert_eq!(duration(2 * 60 * 60 + 5 * 60), "2 h 5 min");
+        assert_eq!(duration(3 * 24 * 60 * 60 + 4 * 60 * 60 + 59), "3 d 4 h");
+    }
+}
@@ -100,6 +100,58 @@ pub fn init(log_file_path: &Path, level: Level) -> Result<()> {
     Ok(())
 }
 
+/// Logger for running under systemd: every line goes to stderr with its
+/// syslog priority in front (`<3>` for errors and so on), which journald
+/// turns into the entry's priority. Journald adds its own timestamps.
+pub struct JournalLogger {
+    level: Level,
+}
+
+/// Syslog priority of a log level as understood by journald
+fn priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// One journal line of `message`. Lines after the first would lose their
+/// priority, so a message spanning several is folded into one.
+fn journal_line(level: Level, message: &str) -> String {
+    format!("<{}>{}\n", priority(level), message.replace('\n', " | "))
+}
+
+impl log::Log for JournalLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let line = journal_line(record.level(), &record.args().to_string());
+            let _ = std::io::stderr().lock().write_all(line.as_bytes());
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// Initialize the logger to write to the systemd journal
+pub fn init_journal(level: Level) -> Result<()> {
+    log::set_boxed_logger(Box::new(JournalLogger { level }))
+        .map(|()| log::set_max_level(level.to_level_filter()))
+        .map_err(|e| anyhow::anyhow!("Failed to set logger: {}", e))
+}
+
+/// Whether stderr is connected to the systemd journal
+pub fn journal_attached() -> bool {
+    std::env::var_os("JOURNAL_STREAM").is_some()
+}
+
 /// A convenience function to initialize the logger with default settings
 /// Logs to "./logs/justrans.log" at INFO level
 pub fn init_default() -> Result<()> {
@@ -124,6 +176,13 @@ mod tests {
     use log::{debug, error, info, warn};
     use std::io::Read;
 
+    #[test]
+    fn test_journal_line() {
+        assert_eq!(journal_line(Level::Error, "Failed"), "<3>Failed\n");
+        assert_eq!(journal_line(Level::Info, "Started"), "<6>Started\n");
+        assert_eq!(journal_line(Level::Warn, "Two\nlines"), "<4>Two | lines\n");
+    }
+
     #[test]
     fn test_logger_creates_file() {
         let temp_dir = tempfile::tempdir().unwrap();
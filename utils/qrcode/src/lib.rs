@@ -1,5 +1,7 @@
 use anyhow::Result;
-use image::{DynamicImage, ImageBuffer, Luma};
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageBuffer, ImageOutputFormat, Luma};
 use qrcode::QrCode;
 
 pub fn generate_qr_code_for_url(data: &str) -> Result<DynamicImage> {
@@ -14,12 +16,15 @@ pub fn generate_qr_code_for_url(data: &str) -> Result<DynamicImage> {
         .build();
 
     // Convert to DynamicImage
-    let image_buffer = ImageBuffer::from_raw(
-        image.width() as u32,
-        image.height() as u32,
-        image.into_raw(),
-    )
-    .ok_or_else(|| anyhow::anyhow!("Failed to create image buffer"))?;
+    let image_buffer = ImageBuffer::from_raw(image.width(), image.height(), image.into_raw())
+        .ok_or_else(|| anyhow::anyhow!("Failed to create image buffer"))?;
 
     Ok(DynamicImage::ImageLuma8(image_buffer))
 }
+
+/// Encode a QR code for `data` as PNG bytes
+pub fn generate_qr_code_png(data: &str) -> Result<Vec<u8>> {
+    let mut png = Vec::new();
+    generate_qr_code_for_url(data)?.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+    Ok(png)
+}
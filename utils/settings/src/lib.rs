@@ -1,10 +1,29 @@
+pub mod schema;
+
 use anyhow::{Context, Result};
 use serde::{de::DeserializeOwned, Serialize};
 use std::fs;
 use std::io::Write;
-use std::sync::Mutex;
+use std::sync::{Mutex, OnceLock};
 use std::{path::PathBuf, sync::Arc};
 
+/// Settings file used unless another one is given at launch
+pub const DEFAULT_PATH: &str = "config/settings.yaml";
+
+static PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Use `path` as the settings file instead of [`DEFAULT_PATH`]. Fails once
+/// the settings file is in use, so call it before the first `instance()`.
+pub fn set_path(path: PathBuf) -> Result<()> {
+    PATH.set(path)
+        .map_err(|_| anyhow::anyhow!("The settings file is already in use"))
+}
+
+/// The settings file in use
+pub fn path() -> PathBuf {
+    PATH.get_or_init(|| PathBuf::from(DEFAULT_PATH)).clone()
+}
+
 /// Trait for managing application settings
 pub trait Settings: Serialize {
     type Config: DeserializeOwned + Serialize + Default + Clone + 'static;
@@ -49,5 +68,275 @@ pub trait Settings: Serialize {
     }
 }
 
+pub use schema::{SettingField, SettingKind, SettingsSchema};
+
 #[cfg(feature = "settings_derive")]
-pub use settings_derive::Settings;
+pub use settings_derive::{Settings, SettingsSchema};
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::OnceLock;
+
+    use proptest::prelude::*;
+    use serde::Deserialize;
+    use serde_yaml::Value;
+
+    use super::*;
+    use crate::schema::{get_value, redacted, set_value, SettingField, SettingsSchema};
+
+    /// Settings holding any YAML document
+    #[derive(Serialize)]
+    #[serde(transparent)]
+    struct Document(Value);
+
+    impl Settings for Document {
+        type Config = Value;
+
+        fn instance() -> Result<Arc<Mutex<Self>>> {
+            static INSTANCE: OnceLock<Arc<Mutex<Document>>> = OnceLock::new();
+            Ok(INSTANCE
+                .get_or_init(|| Arc::new(Mutex::new(Document(Value::Null))))
+                .clone())
+        }
+    }
+
+    impl SettingsSchema for Document {
+        fn schema() -> Vec<SettingField> {
+            Vec::new()
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Server {
+        #[serde(default = "default_port")]
+        port: u16,
+        #[serde(default)]
+        read_only: bool,
+        #[serde(default)]
+        pin: Option<String>,
+    }
+
+    fn default_port() -> u16 {
+        8080
+    }
+
+    impl Default for Server {
+        fn default() -> Self {
+            Server {
+                port: default_port(),
+                read_only: false,
+                pin: None,
+            }
+        }
+    }
+
+    /// Settings shaped like the app's: sections of defaulted fields, lists
+    /// and maps
+    #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        #[serde(default)]
+        server: Server,
+        #[serde(default)]
+        theme: String,
+        #[serde(default)]
+        peers: Vec<String>,
+        #[serde(default)]
+        labels: BTreeMap<String, String>,
+    }
+
+    impl Settings for Config {
+        type Config = Config;
+
+        fn instance() -> Result<Arc<Mutex<Self>>> {
+            static INSTANCE: OnceLock<Arc<Mutex<Config>>> = OnceLock::new();
+            Ok(INSTANCE
+                .get_or_init(|| Arc::new(Mutex::new(Config::default())))
+                .clone())
+        }
+    }
+
+    /// Text as people type it into a settings file, including what YAML
+    /// reads as something else unless it is quoted
+    fn text() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "\\PC{0,12}",
+            Just("yes".to_string()),
+            Just("~".to_string()),
+            Just("0x1F".to_string()),
+            Just("- a: b # c".to_string()),
+            Just(" padded ".to_string()),
+            Just("two\nlines\n".to_string()),
+        ]
+    }
+
+    fn mapping(entries: BTreeMap<String, Value>) -> Value {
+        Value::Mapping(
+            entries
+                .into_iter()
+                .map(|(key, value)| (Value::String(key), value))
+                .collect(),
+        )
+    }
+
+    /// Any nested YAML document without floats, which no setting holds
+    fn value() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::Bool),
+            any::<i64>().prop_map(|n| Value::Number(n.into())),
+            any::<u64>().prop_map(|n| Value::Number(n.into())),
+            text().prop_map(Value::String),
+        ];
+        leaf.prop_recursive(4, 48, 6, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..6).prop_map(Value::Sequence),
+                prop::collection::btree_map(text(), inner, 0..6).prop_map(mapping),
+            ]
+        })
+    }
+
+    /// A settings file: a mapping of anything
+    fn document() -> impl Strategy<Value = Value> {
+        prop::collection::btree_map(text(), value(), 1..6).prop_map(mapping)
+    }
+
+    fn config() -> impl Strategy<Value = Config> {
+        (
+            any::<u16>(),
+            any::<bool>(),
+            prop::option::of(text()),
+            text(),
+            prop::collection::vec(text(), 0..4),
+            prop::collection::btree_map(text(), text(), 0..4),
+        )
+            .prop_map(|(port, read_only, pin, theme, peers, labels)| Config {
+                server: Server {
+                    port,
+                    read_only,
+                    pin,
+                },
+                theme,
+                peers,
+                labels,
+            })
+    }
+
+    /// Paths to the entries of mappings in `value`, outermost first
+    fn mapping_paths(value: &Value, prefix: &[String], paths: &mut Vec<Vec<String>>) {
+        if let Value::Mapping(mapping) = value {
+            for (key, value) in mapping {
+                if let Some(key) = key.as_str() {
+                    let mut path = prefix.to_vec();
+                    path.push(key.to_string());
+                    paths.push(path.clone());
+                    mapping_paths(value, &path, paths);
+                }
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_document_round_trip(document in value()) {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("settings.yaml");
+            Document(document.clone()).save(&path).unwrap();
+            prop_assert_eq!(Document::load(&path).unwrap(), document);
+        }
+
+        #[test]
+        fn test_config_round_trip(config in config()) {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("nested").join("settings.yaml");
+            config.save(&path).unwrap();
+            prop_assert_eq!(Config::load(&path).unwrap(), config);
+        }
+
+        /// Settings left out of the file take their defaults, and those in
+        /// it are kept
+        #[test]
+        fn test_defaults_merge(
+            config in config(),
+            dropped in prop::collection::vec(any::<prop::sample::Index>(), 0..4),
+        ) {
+            let mut root = serde_yaml::to_value(&config).unwrap();
+            let mut paths = Vec::new();
+            mapping_paths(&root, &[], &mut paths);
+            // Only the fields of the structs; the labels map is data
+            paths.retain(|path| path[0] != "labels" || path.len() == 1);
+            for index in &dropped {
+                let path = index.get(&paths);
+                let (last, parents) = path.split_last().unwrap();
+                let mut value = Some(&mut root);
+                for part in parents {
+                    value = value.and_then(|value| value.get_mut(part.as_str()));
+                }
+                if let Some(Value::Mapping(mapping)) = value {
+                    mapping.remove(last.as_str());
+                }
+            }
+
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("settings.yaml");
+            std::fs::write(&path, serde_yaml::to_string(&root).unwrap()).unwrap();
+            let loaded = Config::load(&path).unwrap();
+
+            let defaults = serde_yaml::to_value(Config::default()).unwrap();
+            let loaded_value = serde_yaml::to_value(&loaded).unwrap();
+            let original = serde_yaml::to_value(&config).unwrap();
+            for path in &paths {
+                let lookup = |value: &Value| {
+                    path.iter()
+                        .try_fold(value.clone(), |value, part| value.get(part.as_str()).cloned())
+                };
+                // Sections are checked field by field
+                if path.len() == 1 && path[0] == "server" {
+                    continue;
+                }
+                let expected = if lookup(&root).is_some() {
+                    lookup(&original)
+                } else {
+                    // Dropped, or inside a section that was
+                    lookup(&defaults)
+                };
+                if let Some(expected) = expected {
+                    prop_assert_eq!(lookup(&loaded_value), Some(expected), "at {:?}", path);
+                }
+            }
+        }
+
+        /// A value set at any key reads back, and other keys are untouched
+        #[test]
+        fn test_set_value(
+            document in document(),
+            index in any::<prop::sample::Index>(),
+            new in any::<i64>(),
+        ) {
+            let mut paths = Vec::new();
+            mapping_paths(&document, &[], &mut paths);
+            let target = index.get(&paths);
+            // Keys with dots in them can't be addressed
+            prop_assume!(target.iter().all(|part| !part.contains('.')));
+            let key = target.join(".");
+
+            let mut edited = document.clone();
+            set_value(&mut edited, &key, Value::Number(new.into())).unwrap();
+            prop_assert_eq!(get_value(&edited, &key), Some(new.to_string()));
+            for path in paths.iter().filter(|path| !path.starts_with(target)) {
+                let other = path.join(".");
+                prop_assert_eq!(get_value(&edited, &other), get_value(&document, &other));
+            }
+
+            // Below a number there is nothing to set
+            let missing = format!("{}.missing", key);
+            prop_assert!(set_value(&mut edited, &missing, Value::Null).is_err());
+        }
+
+        /// Redacting for a report keeps everything that isn't a secret
+        #[test]
+        fn test_redacted_keeps_data(document in value()) {
+            prop_assert_eq!(redacted(&Document(document.clone()), &[]).unwrap(), document);
+        }
+    }
+}
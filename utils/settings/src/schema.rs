@@ -0,0 +1,379 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_yaml::Value;
+
+/// What kind of value a setting holds, used to pick an editor and validate input
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingKind {
+    /// Whole number within an optional inclusive range
+    Integer { min: Option<i64>, max: Option<i64> },
+    /// Free text
+    Text,
+    /// One of a fixed set of values
+    Choice(Vec<String>),
+    /// On/off switch
+    Toggle,
+}
+
+/// Description of a single user-editable setting
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettingField {
+    /// Dotted path of the field in the config, e.g. `server.port`
+    pub key: String,
+    pub label: String,
+    pub help: String,
+    /// Heading the setting is grouped under
+    pub section: String,
+    pub kind: SettingKind,
+    /// Hide the value in the UI and leave it out of exported reports
+    pub secret: bool,
+    /// Reject empty text
+    pub required: bool,
+}
+
+impl SettingField {
+    /// Place a field of a nested struct under its parent's key and section
+    pub fn nested(mut self, parent: &str, section: &str) -> Self {
+        self.key = format!("{}.{}", parent, self.key);
+        if self.section.is_empty() {
+            self.section = section.to_string();
+        }
+        self
+    }
+
+    /// Validate user input and convert it to the value stored in the config
+    pub fn parse(&self, input: &str) -> std::result::Result<Value, String> {
+        match &self.kind {
+            SettingKind::Integer { min, max } => {
+                let number: i64 = input
+                    .trim()
+                    .parse()
+                    .map_err(|_| "Enter a whole number".to_string())?;
+                let too_small = min.map(|min| number < min).unwrap_or(false);
+                let too_large = max.map(|max| number > max).unwrap_or(false);
+                if too_small || too_large {
+                    return Err(match (min, max) {
+                        (Some(min), Some(max)) => format!("Must be between {} and {}", min, max),
+                        (Some(min), None) => format!("Must be at least {}", min),
+                        (None, Some(max)) => format!("Must be at most {}", max),
+                        (None, None) => unreachable!(),
+                    });
+                }
+                Ok(Value::Number(number.into()))
+            }
+            SettingKind::Text => {
+                if self.required && input.trim().is_empty() {
+                    return Err("Must not be empty".to_string());
+                }
+                Ok(Value::String(input.to_string()))
+            }
+            SettingKind::Choice(choices) => {
+                if !choices.iter().any(|choice| choice == input) {
+                    return Err(format!("Must be one of: {}", choices.join(", ")));
+                }
+                Ok(Value::String(input.to_string()))
+            }
+            SettingKind::Toggle => match input {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                _ => Err("Must be true or false".to_string()),
+            },
+        }
+    }
+}
+
+/// Types that describe their user-editable settings
+pub trait SettingsSchema {
+    fn schema() -> Vec<SettingField>;
+}
+
+/// Current value of the setting at `key`, formatted for display
+pub fn get_value<T: Serialize>(config: &T, key: &str) -> Option<String> {
+    let mut value = serde_yaml::to_value(config).ok()?;
+    for part in key.split('.') {
+        value = value.get(part)?.clone();
+    }
+    match value {
+        Value::String(s) => Some(s),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Replace the setting at `key` with a value returned by [`SettingField::parse`]
+pub fn set_value<T: Serialize + DeserializeOwned>(
+    config: &mut T,
+    key: &str,
+    new: Value,
+) -> Result<()> {
+    let mut root = serde_yaml::to_value(&*config).context("Failed to serialize settings")?;
+
+    let mut value = &mut root;
+    for part in key.split('.') {
+        value = value
+            .get_mut(part)
+            .ok_or_else(|| anyhow!("Unknown setting: {}", key))?;
+    }
+    *value = new;
+
+    *config = serde_yaml::from_value(root).context(format!("Invalid value for {}", key))?;
+    Ok(())
+}
+
+/// Override settings from environment variables named `prefix` followed by
+/// the setting's key in capitals, with `__` between its parts, e.g.
+/// `JUSTRANS_SERVER__PORT` for `server.port`. Values are read as YAML, so
+/// lists and whole sections can be given too, and as text when that doesn't
+/// fit the setting. A section is applied before the settings in it, so those
+/// win over its values. Variables that name no setting are skipped with a
+/// warning. Returns the keys that were set.
+pub fn apply_env<T: Serialize + DeserializeOwned>(
+    config: &mut T,
+    prefix: &str,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<Vec<String>> {
+    let mut overrides: Vec<(Vec<String>, String, String)> = vars
+        .into_iter()
+        .filter_map(|(name, raw)| {
+            let parts = name
+                .strip_prefix(prefix)?
+                .split("__")
+                .map(str::to_lowercase)
+                .collect();
+            Some((parts, name, raw))
+        })
+        .collect();
+    // The environment comes in no particular order
+    overrides.sort();
+
+    let mut applied = Vec::new();
+    for (parts, name, raw) in overrides {
+        let key = parts.join(".");
+        let root = serde_yaml::to_value(&*config).context("Failed to serialize settings")?;
+        let Some((last, parents)) = parts.split_last().filter(|(last, parents)| {
+            !last.is_empty() && matches!(lookup(&root, parents), Some(Value::Mapping(_)))
+        }) else {
+            log::warn!("Ignoring {}: there is no setting {}", name, key);
+            continue;
+        };
+        let known = lookup(&root, &parts).is_some();
+
+        // As YAML first, so `8080` is a number and `true` a switch
+        let typed = serde_yaml::from_str(&raw).unwrap_or_else(|_| Value::String(raw.clone()));
+        let mut edited = None;
+        for new in [typed, Value::String(raw.clone())] {
+            let mut root = root.clone();
+            if let Some(Value::Mapping(mapping)) = lookup_mut(&mut root, parents) {
+                mapping.insert(Value::String(last.clone()), new);
+            }
+            if let Ok(parsed) = serde_yaml::from_value(root) {
+                edited = Some(parsed);
+                break;
+            }
+        }
+        let edited = edited.ok_or_else(|| anyhow!("Invalid value for {} in {}", key, name))?;
+
+        // Settings that don't exist are dropped when the config is read
+        let read_back = serde_yaml::to_value(&edited).context("Failed to serialize settings")?;
+        if !known && lookup(&read_back, &parts).is_none() {
+            log::warn!("Ignoring {}: there is no setting {}", name, key);
+            continue;
+        }
+        *config = edited;
+        applied.push(key);
+    }
+    Ok(applied)
+}
+
+fn lookup<'a>(value: &'a Value, parts: &[String]) -> Option<&'a Value> {
+    parts
+        .iter()
+        .try_fold(value, |value, part| value.get(part.as_str()))
+}
+
+fn lookup_mut<'a>(value: &'a mut Value, parts: &[String]) -> Option<&'a mut Value> {
+    parts
+        .iter()
+        .try_fold(value, |value, part| value.get_mut(part.as_str()))
+}
+
+/// Placeholder written in place of secret values
+pub const REDACTED: &str = "<redacted>";
+
+/// The config as YAML with secret settings, and any field called one of
+/// `secret_names`, replaced by [`REDACTED`] so it can be shared in reports
+pub fn redacted<T: Serialize + SettingsSchema>(config: &T, secret_names: &[&str]) -> Result<Value> {
+    let mut root = serde_yaml::to_value(config).context("Failed to serialize settings")?;
+
+    for field in T::schema().iter().filter(|field| field.secret) {
+        let mut value = Some(&mut root);
+        for part in field.key.split('.') {
+            value = value.and_then(|value| value.get_mut(part));
+        }
+        if let Some(value) = value {
+            *value = Value::String(REDACTED.to_string());
+        }
+    }
+
+    redact_named(&mut root, secret_names);
+    Ok(root)
+}
+
+fn redact_named(value: &mut Value, secret_names: &[&str]) {
+    match value {
+        Value::Mapping(mapping) => {
+            for (key, value) in mapping.iter_mut() {
+                let is_secret = key
+                    .as_str()
+                    .map(|key| secret_names.contains(&key))
+                    .unwrap_or(false);
+                let is_set = !matches!(value, Value::Null)
+                    && value.as_str().map(|s| !s.is_empty()).unwrap_or(true);
+                if is_secret && is_set {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_named(value, secret_names);
+                }
+            }
+        }
+        Value::Sequence(items) => {
+            for item in items {
+                redact_named(item, secret_names);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Inner {
+        port: u16,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Outer {
+        inner: Inner,
+    }
+
+    impl SettingsSchema for Outer {
+        fn schema() -> Vec<SettingField> {
+            vec![SettingField {
+                secret: true,
+                ..port_field()
+            }]
+        }
+    }
+
+    fn port_field() -> SettingField {
+        SettingField {
+            key: "port".to_string(),
+            label: "Port".to_string(),
+            help: String::new(),
+            section: String::new(),
+            kind: SettingKind::Integer {
+                min: Some(1024),
+                max: Some(65535),
+            },
+            secret: false,
+            required: false,
+        }
+        .nested("inner", "Inner")
+    }
+
+    #[test]
+    fn test_parse_validates_range() {
+        let field = port_field();
+        assert_eq!(field.key, "inner.port");
+        assert_eq!(field.parse("8080"), Ok(Value::Number(8080i64.into())));
+        assert!(field.parse("80").is_err());
+        assert!(field.parse("eighty").is_err());
+    }
+
+    #[test]
+    fn test_get_and_set_value() {
+        let mut config = Outer {
+            inner: Inner { port: 8080 },
+        };
+        assert_eq!(get_value(&config, "inner.port"), Some("8080".to_string()));
+
+        set_value(&mut config, "inner.port", Value::Number(9000i64.into())).unwrap();
+        assert_eq!(config.inner.port, 9000);
+        assert!(set_value(&mut config, "inner.missing", Value::Bool(true)).is_err());
+    }
+
+    #[test]
+    fn test_redacted() {
+        let config = Outer {
+            inner: Inner { port: 8080 },
+        };
+        let value = redacted(&config, &[]).unwrap();
+        assert_eq!(value["inner"]["port"], Value::String(REDACTED.to_string()));
+
+        let mut value: Value = serde_yaml::from_str(
+            "hooks:\n  - url: x\n    secret: abc\n  - url: y\n    secret: ''\n",
+        )
+        .unwrap();
+        redact_named(&mut value, &["secret"]);
+        assert_eq!(
+            value["hooks"][0]["secret"],
+            Value::String(REDACTED.to_string())
+        );
+        assert_eq!(value["hooks"][0]["url"], Value::String("x".to_string()));
+        assert_eq!(value["hooks"][1]["secret"], Value::String(String::new()));
+    }
+
+    #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct Deployment {
+        #[serde(default)]
+        name: String,
+        #[serde(default)]
+        inner: Option<Inner>,
+        #[serde(default)]
+        tags: Vec<String>,
+    }
+
+    fn vars(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_apply_env() {
+        let mut config = Deployment::default();
+        let applied = apply_env(
+            &mut config,
+            "APP_",
+            vars(&[
+                ("HOME", "/root"),
+                ("APP_NAME", "8080"),
+                // Applied after the section, in whichever order they come
+                ("APP_INNER__PORT", "9001"),
+                ("APP_INNER", "{port: 9000}"),
+                ("APP_TAGS", "[a, b]"),
+                ("APP_MISSING", "1"),
+                ("APP_TAGS__FIRST", "a"),
+                ("APP_", "1"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(applied, vec!["inner", "inner.port", "name", "tags"]);
+        assert_eq!(
+            config,
+            Deployment {
+                name: "8080".to_string(),
+                inner: Some(Inner { port: 9001 }),
+                tags: vec!["a".to_string(), "b".to_string()],
+            }
+        );
+
+        assert!(apply_env(&mut config, "APP_", vars(&[("APP_INNER__PORT", "eighty")])).is_err());
+        assert_eq!(config.inner, Some(Inner { port: 9001 }));
+    }
+}
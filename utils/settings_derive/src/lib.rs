@@ -1,6 +1,6 @@
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, DeriveInput};
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Fields, Lit, Meta, NestedMeta, Type};
 
 #[proc_macro_derive(Settings)]
 pub fn derive_settings(input: TokenStream) -> TokenStream {
@@ -19,8 +19,7 @@ pub fn derive_settings(input: TokenStream) -> TokenStream {
                 static INSTANCE: once_cell::sync::OnceCell<std::sync::Arc<std::sync::Mutex<#name>>> = once_cell::sync::OnceCell::new();
 
                 Ok(INSTANCE.get_or_try_init(|| -> anyhow::Result<std::sync::Arc<std::sync::Mutex<#name>>> {
-                    let default_path = std::path::PathBuf::from("config/settings.yaml");
-                    Ok(std::sync::Arc::new(std::sync::Mutex::new(Self::load(&default_path)?)))
+                    Ok(std::sync::Arc::new(std::sync::Mutex::new(Self::load(&settings::path())?)))
                 })?.clone())
             }
         }
@@ -28,3 +27,222 @@ pub fn derive_settings(input: TokenStream) -> TokenStream {
 
     TokenStream::from(expanded)
 }
+
+/// Options collected from the `#[setting(...)]` attributes of one field
+#[derive(Default)]
+struct SettingAttrs {
+    label: Option<String>,
+    help: Option<String>,
+    section: Option<String>,
+    min: Option<i64>,
+    max: Option<i64>,
+    choices: Vec<String>,
+    secret: bool,
+    required: bool,
+}
+
+fn lit_str(lit: &Lit) -> syn::Result<String> {
+    match lit {
+        Lit::Str(s) => Ok(s.value()),
+        _ => Err(syn::Error::new_spanned(lit, "expected a string")),
+    }
+}
+
+fn lit_int(lit: &Lit) -> syn::Result<i64> {
+    match lit {
+        Lit::Int(i) => i.base10_parse(),
+        _ => Err(syn::Error::new_spanned(lit, "expected an integer")),
+    }
+}
+
+/// Parse `#[setting(...)]` attributes; `None` if the field has none
+fn setting_attrs(attrs: &[Attribute]) -> syn::Result<Option<SettingAttrs>> {
+    let mut found = None;
+    for attr in attrs.iter().filter(|a| a.path.is_ident("setting")) {
+        let settings = found.get_or_insert_with(SettingAttrs::default);
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            Meta::Path(_) => continue,
+            meta => return Err(syn::Error::new_spanned(meta, "expected #[setting(...)]")),
+        };
+
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(nv)) => {
+                    let name = nv
+                        .path
+                        .get_ident()
+                        .map(|i| i.to_string())
+                        .unwrap_or_default();
+                    match name.as_str() {
+                        "label" => settings.label = Some(lit_str(&nv.lit)?),
+                        "help" => settings.help = Some(lit_str(&nv.lit)?),
+                        "section" => settings.section = Some(lit_str(&nv.lit)?),
+                        "min" => settings.min = Some(lit_int(&nv.lit)?),
+                        "max" => settings.max = Some(lit_int(&nv.lit)?),
+                        _ => {
+                            return Err(syn::Error::new_spanned(nv.path, "unknown setting option"))
+                        }
+                    }
+                }
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("secret") => {
+                    settings.secret = true
+                }
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("required") => {
+                    settings.required = true
+                }
+                NestedMeta::Meta(Meta::List(choices)) if choices.path.is_ident("choices") => {
+                    for choice in choices.nested {
+                        match choice {
+                            NestedMeta::Lit(lit) => settings.choices.push(lit_str(&lit)?),
+                            other => {
+                                return Err(syn::Error::new_spanned(other, "expected a string"))
+                            }
+                        }
+                    }
+                }
+                other => return Err(syn::Error::new_spanned(other, "unknown setting option")),
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Doc comment of a field, used as help text when none is given
+fn doc_comment(attrs: &[Attribute]) -> String {
+    attrs
+        .iter()
+        .filter(|a| a.path.is_ident("doc"))
+        .filter_map(|a| match a.parse_meta() {
+            Ok(Meta::NameValue(nv)) => lit_str(&nv.lit).ok(),
+            _ => None,
+        })
+        .map(|line| line.trim().to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// "upload_chunk_size_mb" -> "Upload chunk size mb"
+fn default_label(ident: &str) -> String {
+    let text = ident.replace('_', " ");
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => text,
+    }
+}
+
+fn field_schema(field: &syn::Field, attrs: SettingAttrs) -> syn::Result<proc_macro2::TokenStream> {
+    let ident = field.ident.as_ref().expect("named field");
+    let key = ident.to_string();
+    let ty = &field.ty;
+
+    // Struct fields with a section contribute their own settings
+    if let Some(section) = &attrs.section {
+        return Ok(quote! {
+            fields.extend(
+                <#ty as settings::SettingsSchema>::schema()
+                    .into_iter()
+                    .map(|field| field.nested(#key, #section)),
+            );
+        });
+    }
+
+    let type_name = match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .map(|s| s.ident.to_string())
+            .unwrap_or_default(),
+        _ => String::new(),
+    };
+    let kind = match type_name.as_str() {
+        "u8" | "u16" | "u32" | "u64" | "usize" | "i8" | "i16" | "i32" | "i64" | "isize" => {
+            let min = match attrs.min {
+                Some(min) => quote! { Some(#min) },
+                None => quote! { None },
+            };
+            let max = match attrs.max {
+                Some(max) => quote! { Some(#max) },
+                None => quote! { None },
+            };
+            quote! { settings::SettingKind::Integer { min: #min, max: #max } }
+        }
+        "bool" => quote! { settings::SettingKind::Toggle },
+        "String" if !attrs.choices.is_empty() => {
+            let choices = &attrs.choices;
+            quote! { settings::SettingKind::Choice(vec![#(#choices.to_string()),*]) }
+        }
+        "String" => quote! { settings::SettingKind::Text },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                ty,
+                "#[setting] supports integers, bool and String, or a struct with `section`",
+            ))
+        }
+    };
+
+    let label = attrs.label.unwrap_or_else(|| default_label(&key));
+    let help = attrs.help.unwrap_or_else(|| doc_comment(&field.attrs));
+    let secret = attrs.secret;
+    let required = attrs.required;
+    Ok(quote! {
+        fields.push(settings::SettingField {
+            key: #key.to_string(),
+            label: #label.to_string(),
+            help: #help.to_string(),
+            section: String::new(),
+            kind: #kind,
+            secret: #secret,
+            required: #required,
+        });
+    })
+}
+
+/// Describe the fields marked with `#[setting(...)]` so a settings UI can be
+/// generated from the struct. Fields without the attribute are not editable.
+#[proc_macro_derive(SettingsSchema, attributes(setting))]
+pub fn derive_settings_schema(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = input.ident;
+
+    let fields = match input.data {
+        Data::Struct(data) => match data.fields {
+            Fields::Named(fields) => fields.named,
+            _ => {
+                return syn::Error::new_spanned(name, "SettingsSchema needs named fields")
+                    .to_compile_error()
+                    .into()
+            }
+        },
+        _ => {
+            return syn::Error::new_spanned(name, "SettingsSchema can only be derived for structs")
+                .to_compile_error()
+                .into()
+        }
+    };
+
+    let mut pushes = Vec::new();
+    for field in fields.iter() {
+        let result = setting_attrs(&field.attrs)
+            .and_then(|attrs| attrs.map(|attrs| field_schema(field, attrs)).transpose());
+        match result {
+            Ok(Some(push)) => pushes.push(push),
+            Ok(None) => {}
+            Err(e) => return e.to_compile_error().into(),
+        }
+    }
+
+    let expanded = quote! {
+        impl settings::SettingsSchema for #name {
+            fn schema() -> Vec<settings::SettingField> {
+                let mut fields = Vec::new();
+                #(#pushes)*
+                fields
+            }
+        }
+    };
+
+    TokenStream::from(expanded)
+}
@@ -65,3 +65,51 @@ fn test_settings_derive() {
         assert_eq!(default_config.bind_address, "0.0.0.0");
     }
 }
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, settings_derive::SettingsSchema)]
+struct Network {
+    /// Port to listen on
+    #[setting(label = "Port", min = 1024, max = 65535)]
+    pub port: u16,
+
+    #[setting(choices("light", "dark"))]
+    pub theme: String,
+
+    #[setting(secret)]
+    pub api_token: String,
+
+    pub internal: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, settings_derive::SettingsSchema)]
+struct AppConfig {
+    #[setting(section = "Network")]
+    pub network: Network,
+}
+
+#[test]
+fn test_settings_schema_derive() {
+    use settings::{SettingKind, SettingsSchema};
+
+    let schema = AppConfig::schema();
+    assert_eq!(schema.len(), 3);
+
+    assert_eq!(schema[0].key, "network.port");
+    assert_eq!(schema[0].label, "Port");
+    assert_eq!(schema[0].help, "Port to listen on");
+    assert_eq!(schema[0].section, "Network");
+    assert_eq!(
+        schema[0].kind,
+        SettingKind::Integer {
+            min: Some(1024),
+            max: Some(65535)
+        }
+    );
+
+    assert_eq!(
+        schema[1].kind,
+        SettingKind::Choice(vec!["light".to_string(), "dark".to_string()])
+    );
+    assert_eq!(schema[2].label, "Api token");
+    assert!(schema[2].secret);
+}